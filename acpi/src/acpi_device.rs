@@ -10,7 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use address_space::GuestAddress;
 use log::error;
@@ -57,14 +57,25 @@ impl AcpiPMTimer {
         data.copy_from_slice(&((counter & 0xFFFF_FFFF) as u32).to_le_bytes());
         true
     }
+
+    /// Nanoseconds elapsed since the timer started counting.
+    pub fn elapsed_nanos(&self) -> u64 {
+        self.start.elapsed().as_nanos() as u64
+    }
+
+    /// Restart the timer as if it had been counting for `nanos` nanoseconds.
+    pub fn set_elapsed_nanos(&mut self, nanos: u64) {
+        let now = Instant::now();
+        self.start = now.checked_sub(Duration::from_nanos(nanos)).unwrap_or(now);
+    }
 }
 
 #[derive(Default)]
 pub struct AcpiPmEvent {
-    // PM1 Status Registers, location: PM1a_EVT_BLK.
-    status: u16,
-    // PM1Enable Registers, location: PM1a_EVT_BLK + PM1_EVT_LEN / 2.
-    enable: u16,
+    /// PM1 Status Registers, location: PM1a_EVT_BLK.
+    pub status: u16,
+    /// PM1Enable Registers, location: PM1a_EVT_BLK + PM1_EVT_LEN / 2.
+    pub enable: u16,
 }
 
 impl AcpiPmEvent {
@@ -113,7 +124,8 @@ impl AcpiPmEvent {
 
 #[derive(Default)]
 pub struct AcpiPmCtrl {
-    control: u16,
+    /// PM1 Control Registers, location: PM1a_CNT_BLK.
+    pub control: u16,
 }
 
 impl AcpiPmCtrl {
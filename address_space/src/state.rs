@@ -11,19 +11,20 @@
 // See the Mulan PSL v2 for more details.
 
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::mem::size_of;
 use std::sync::Arc;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use migration::{
-    error::MigrationError, DeviceStateDesc, FieldDesc, MemBlock, MigrationHook, StateTransfer,
+    error::MigrationError, postcopy::RamBlock, DeviceStateDesc, FieldDesc, MemBlock, MigrationHook,
+    StateTransfer,
 };
 use migration_derive::{ByteCode, Desc};
 use util::byte_code::ByteCode;
 use util::unix::host_page_size;
 
-use crate::{AddressSpace, FileBackend, GuestAddress, HostMemMapping, Region};
+use crate::{AddressSpace, FileBackend, GuestAddress, HostMemMapping, Region, RegionType};
 
 const MIGRATION_HEADER_LENGTH: usize = 4096;
 
@@ -60,6 +61,11 @@ impl StateTransfer for AddressSpace {
         let mut offset = memory_offset() as u64;
 
         for region in self.root().subregions().iter() {
+            // Rom devices are backed by their image files and device memory is owned
+            // by the device itself, so only guest ram is saved into the snapshot.
+            if region.region_type() != RegionType::Ram {
+                continue;
+            }
             if let Some(start_addr) = region.start_addr() {
                 state.ram_region_state[state.nr_ram_region as usize] = RamRegionState {
                     base_address: start_addr.0,
@@ -88,6 +94,9 @@ impl MigrationHook for AddressSpace {
         fd.write_all(&padding_buffer)?;
 
         for region in self.root().subregions().iter() {
+            if region.region_type() != RegionType::Ram {
+                continue;
+            }
             if let Some(base_addr) = region.start_addr() {
                 region
                     .read(fd, base_addr, 0, region.size())
@@ -103,11 +112,44 @@ impl MigrationHook for AddressSpace {
             AddressSpaceState::from_bytes(&state[0..size_of::<AddressSpaceState>()])
                 .ok_or_else(|| anyhow!(MigrationError::FromBytesError("MEMORY")))?;
         let memfile_arc = Arc::new(memory.unwrap().try_clone().unwrap());
+        let file_len = memfile_arc.metadata()?.len();
 
         for ram_state in address_space_state.ram_region_state
             [0..address_space_state.nr_ram_region as usize]
             .iter()
         {
+            // Accessing guest memory mapped beyond the end of file raises SIGBUS.
+            if ram_state.offset + ram_state.size > file_len {
+                bail!(
+                    "Memory snapshot file is truncated, ram 0x{:x} ends at 0x{:x} of file length 0x{:x}",
+                    ram_state.base_address,
+                    ram_state.offset + ram_state.size,
+                    file_len
+                );
+            }
+
+            // Ram regions created by devices during realization (e.g. bios shadow ram)
+            // already exist, fill them with the saved content instead of remapping.
+            if let Some(region) = self.root().subregions().iter().find(|r| {
+                r.region_type() == RegionType::Ram
+                    && r.start_addr() == Some(GuestAddress(ram_state.base_address))
+                    && r.size() == ram_state.size
+            }) {
+                let mut memfile = memory.unwrap().try_clone()?;
+                memfile.seek(SeekFrom::Start(ram_state.offset))?;
+                region
+                    .write(
+                        &mut memfile,
+                        GuestAddress(ram_state.base_address),
+                        0,
+                        ram_state.size,
+                    )
+                    .map_err(|e| anyhow!(MigrationError::RestoreVmMemoryErr(e.to_string())))?;
+                continue;
+            }
+
+            // Map the file privately, pages are loaded on first access and shared in
+            // page cache between VMs restored from the same snapshot until written.
             let file_backend = FileBackend {
                 file: memfile_arc.clone(),
                 offset: ram_state.offset,
@@ -149,4 +191,80 @@ impl MigrationHook for AddressSpace {
 
         Ok(())
     }
+
+    fn postcopy_ram(&self) -> Result<Vec<RamBlock>> {
+        let mut ram = Vec::new();
+        for region in self.root().subregions().iter() {
+            if region.region_type() != RegionType::Ram {
+                continue;
+            }
+            if let (Some(start_addr), Some(host_addr)) =
+                (region.start_addr(), region.get_host_address())
+            {
+                // Missing pages of shared memory can't be discarded by madvise.
+                if region.get_file_backend().is_some() {
+                    bail!(
+                        "Post-copy only supports anonymous private memory, ram 0x{:x} is backed by file",
+                        start_addr.0
+                    );
+                }
+                ram.push(RamBlock {
+                    gpa: start_addr.0,
+                    hva: host_addr,
+                    len: region.size(),
+                });
+            }
+        }
+
+        Ok(ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vmm_sys_util::tempfile::TempFile;
+
+    use super::*;
+
+    fn create_address_space(ram_size: u64) -> Arc<AddressSpace> {
+        let root = Region::init_container_region(1 << 36);
+        let space = AddressSpace::new(root.clone()).unwrap();
+        if ram_size != 0 {
+            let ram = Arc::new(
+                HostMemMapping::new(GuestAddress(0), None, ram_size, None, false, false, false)
+                    .unwrap(),
+            );
+            root.add_subregion(Region::init_ram_region(ram), 0).unwrap();
+        }
+        space
+    }
+
+    #[test]
+    fn test_restore_memory_from_file() {
+        let page_size = host_page_size();
+        let src = create_address_space(4 * page_size);
+        let data = vec![0x5a_u8; page_size as usize];
+        src.write(&mut data.as_slice(), GuestAddress(page_size), page_size)
+            .unwrap();
+
+        // Migration header is followed by the memory state.
+        let mut file = TempFile::new().unwrap().into_file();
+        file.write_all(&[0_u8; MIGRATION_HEADER_LENGTH]).unwrap();
+        src.save_memory(&mut file).unwrap();
+        let state = src.get_state_vec().unwrap();
+
+        let dst = create_address_space(0);
+        dst.restore_memory(Some(&file), &state).unwrap();
+        let mut buf = Vec::new();
+        dst.read(&mut buf, GuestAddress(page_size), page_size)
+            .unwrap();
+        assert_eq!(buf, data);
+
+        // Restoring from truncated file fails without mapping any ram.
+        let len = file.metadata().unwrap().len();
+        file.set_len(len - page_size).unwrap();
+        let dst = create_address_space(0);
+        assert!(dst.restore_memory(Some(&file), &state).is_err());
+        assert!(dst.root().subregions().is_empty());
+    }
 }
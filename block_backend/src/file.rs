@@ -0,0 +1,177 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::fs::File;
+use std::io::{Error, Seek, SeekFrom};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+
+use util::aio::{raw_datasync, raw_read, raw_write};
+use util::num_ops::{round_down, round_up};
+use util::unix::host_page_size;
+
+/// Aligned buffer used to access files opened with O_DIRECT.
+struct AlignedBuf {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl AlignedBuf {
+    fn new(len: usize) -> Result<Self> {
+        // SAFETY: the memory is freed in drop.
+        let ptr = unsafe { libc::memalign(host_page_size() as usize, len) };
+        if ptr.is_null() {
+            bail!("Failed to alloc aligned buffer of {} bytes", len);
+        }
+        Ok(AlignedBuf { ptr, len })
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        // SAFETY: ptr is valid and owned by us.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr as *mut u8, self.len) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        // SAFETY: the memory is allocated by us and will not be used anymore.
+        unsafe { libc::free(self.ptr) };
+    }
+}
+
+/// Synchronous access to a block backend file, used for format metadata. The
+/// alignment requirement of O_DIRECT is handled internally.
+#[derive(Clone)]
+pub struct FileDriver {
+    pub file: Arc<File>,
+    /// The align requirement of request(offset/len).
+    pub req_align: u32,
+    /// The align requirement of buffer(iova_base).
+    pub buf_align: u32,
+}
+
+impl FileDriver {
+    pub fn new(file: Arc<File>, req_align: u32, buf_align: u32) -> Self {
+        FileDriver {
+            file,
+            req_align,
+            buf_align,
+        }
+    }
+
+    pub fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+
+    fn misaligned(&self, buf: &[u8], offset: u64) -> bool {
+        let req_align = self.req_align as u64;
+        offset & (req_align - 1) != 0
+            || buf.len() as u64 & (req_align - 1) != 0
+            || buf.as_ptr() as u64 & (self.buf_align as u64 - 1) != 0
+    }
+
+    fn aligned_range(&self, offset: u64, len: u64) -> Result<(u64, u64)> {
+        let align = self.req_align as u64;
+        let start = round_down(offset, align)
+            .with_context(|| format!("Failed to round down offset {}", offset))?;
+        let end = round_up(offset + len, align)
+            .with_context(|| format!("Failed to round up offset {}", offset + len))?;
+        Ok((start, end))
+    }
+
+    fn read_raw(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+        let ret = raw_read(
+            self.as_raw_fd(),
+            buf.as_mut_ptr() as u64,
+            buf.len(),
+            offset as usize,
+        );
+        if ret < 0 {
+            return Err(Error::from_raw_os_error(-ret as i32)).with_context(|| {
+                format!("Failed to read {} bytes at offset {}", buf.len(), offset)
+            });
+        }
+        // Reading beyond the end of file gets zero.
+        if (ret as usize) < buf.len() {
+            buf[ret as usize..].fill(0);
+        }
+        Ok(())
+    }
+
+    fn write_raw(&self, buf: &[u8], offset: u64) -> Result<()> {
+        let ret = raw_write(
+            self.as_raw_fd(),
+            buf.as_ptr() as u64,
+            buf.len(),
+            offset as usize,
+        );
+        // A short write means there is no space left on the host.
+        let errno = match ret {
+            ret if ret < 0 => -ret as i32,
+            ret if ret as usize != buf.len() => libc::ENOSPC,
+            _ => return Ok(()),
+        };
+        Err(Error::from_raw_os_error(errno))
+            .with_context(|| format!("Failed to write {} bytes at offset {}", buf.len(), offset))
+    }
+
+    /// Read `buf.len()` bytes from `offset` of the file.
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+        if !self.misaligned(buf, offset) {
+            return self.read_raw(buf, offset);
+        }
+        let (start, end) = self.aligned_range(offset, buf.len() as u64)?;
+        let mut bounce = AlignedBuf::new((end - start) as usize)?;
+        self.read_raw(bounce.as_mut_slice(), start)?;
+        let head = (offset - start) as usize;
+        buf.copy_from_slice(&bounce.as_mut_slice()[head..head + buf.len()]);
+        Ok(())
+    }
+
+    /// Write `buf` to `offset` of the file.
+    pub fn write_at(&self, buf: &[u8], offset: u64) -> Result<()> {
+        if !self.misaligned(buf, offset) {
+            return self.write_raw(buf, offset);
+        }
+        let (start, end) = self.aligned_range(offset, buf.len() as u64)?;
+        let mut bounce = AlignedBuf::new((end - start) as usize)?;
+        // Read-modify-write if the data does not cover the aligned range.
+        if start != offset || end != offset + buf.len() as u64 {
+            self.read_raw(bounce.as_mut_slice(), start)?;
+        }
+        let head = (offset - start) as usize;
+        bounce.as_mut_slice()[head..head + buf.len()].copy_from_slice(buf);
+        self.write_raw(bounce.as_mut_slice(), start)
+    }
+
+    /// Fill `len` bytes from `offset` of the file with zero.
+    pub fn write_zeroes(&self, offset: u64, len: u64) -> Result<()> {
+        self.write_at(&vec![0_u8; len as usize], offset)
+    }
+
+    pub fn datasync(&self) -> Result<()> {
+        let ret = raw_datasync(self.as_raw_fd());
+        if ret < 0 {
+            return Err(Error::from_raw_os_error(-ret as i32))
+                .with_context(|| "Failed to sync the block backend file");
+        }
+        Ok(())
+    }
+
+    pub fn disk_size(&self) -> Result<u64> {
+        let mut file = self.file.as_ref();
+        file.seek(SeekFrom::End(0))
+            .with_context(|| "Failed to seek the end of block backend file")
+    }
+}
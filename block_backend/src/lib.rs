@@ -0,0 +1,257 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! # Block Backend
+//!
+//! Format drivers of block devices, sitting between the emulated disks
+//! (virtio-blk, scsi-disk) and the asynchronous IO of `util::aio`.
+//!
+//! A driver maps the guest IO to the host IO on the image file. Data is still
+//! transferred by the aio context of the device, metadata of the image format
+//! is handled synchronously inside the driver, so are the writes to newly
+//! allocated clusters which must be done before the metadata refers to them,
+//! and the reads from the backing file.
+
+pub mod file;
+pub mod qcow2;
+pub mod raw;
+pub mod stats;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context, Result};
+use log::error;
+use once_cell::sync::Lazy;
+
+use machine_manager::config::DiskFormat;
+use util::aio::{Aio, AioCb, CombineRequest, Iovec, OpCode};
+
+use crate::file::FileDriver;
+use crate::qcow2::Qcow2Driver;
+use crate::raw::RawDriver;
+
+/// Information of an internal snapshot.
+#[derive(Clone, Debug, Default)]
+pub struct SnapshotInfo {
+    pub id: String,
+    pub name: String,
+    pub vm_state_size: u64,
+    pub date_sec: u32,
+    pub date_nsec: u32,
+    pub vm_clock_nsec: u64,
+}
+
+/// Operations of the block format driver.
+pub trait BlockDriverOps: Send {
+    /// Get the virtual size of the disk in bytes.
+    fn disk_size(&mut self) -> Result<u64>;
+
+    /// Map the guest read of `iov` from `offset` to host IOs. Parts which do not
+    /// need host IO (e.g. unallocated clusters) are filled in place.
+    fn read_vectored(&mut self, iov: &[Iovec], offset: u64) -> Result<Vec<CombineRequest>>;
+
+    /// Map the guest write of `iov` from `offset` to host IOs, allocating space if needed.
+    fn write_vectored(&mut self, iov: &[Iovec], offset: u64) -> Result<Vec<CombineRequest>>;
+
+    /// Map the guest discard of `nbytes` from `offset` to host discards.
+    fn discard(&mut self, offset: u64, nbytes: u64) -> Result<Vec<CombineRequest>>;
+
+    /// Map the guest write zeroes of `nbytes` from `offset` to host IOs, the space
+    /// may be deallocated if `unmap` is true.
+    fn write_zeroes(
+        &mut self,
+        offset: u64,
+        nbytes: u64,
+        unmap: bool,
+    ) -> Result<Vec<CombineRequest>>;
+
+    /// Read `buf.len()` bytes from guest `offset` synchronously.
+    fn read_at(&mut self, buf: &mut [u8], offset: u64) -> Result<()>;
+
+    /// Create an internal snapshot named `name`.
+    fn create_snapshot(&mut self, _name: String, _vm_clock_nsec: u64) -> Result<()> {
+        bail!("Internal snapshot is not supported by this format");
+    }
+
+    /// Delete the internal snapshot named `name`, the deleted one is returned.
+    fn delete_snapshot(&mut self, _name: String) -> Result<SnapshotInfo> {
+        bail!("Internal snapshot is not supported by this format");
+    }
+
+    /// Revert the disk to the internal snapshot named `name`.
+    fn apply_snapshot(&mut self, _name: String) -> Result<()> {
+        bail!("Internal snapshot is not supported by this format");
+    }
+
+    /// List all internal snapshots.
+    fn list_snapshots(&self) -> Vec<SnapshotInfo> {
+        Vec::new()
+    }
+}
+
+pub type BlockBackend = Arc<Mutex<dyn BlockDriverOps>>;
+
+/// Properties used to open a block backend.
+#[derive(Clone, Debug)]
+pub struct BlockProperty {
+    pub format: DiskFormat,
+    pub read_only: bool,
+    pub direct: bool,
+    /// The align requirement of request(offset/len).
+    pub req_align: u32,
+    /// The align requirement of buffer(iova_base).
+    pub buf_align: u32,
+    /// Path of the image file, used to find the relative backing file.
+    pub path: String,
+}
+
+/// Create the block backend of `file` in format `prop.format`.
+pub fn create_block_backend(file: Arc<File>, prop: BlockProperty) -> Result<BlockBackend> {
+    let driver = FileDriver::new(file, prop.req_align, prop.buf_align);
+    let backend: BlockBackend = match prop.format {
+        DiskFormat::Raw => Arc::new(Mutex::new(RawDriver::new(driver))),
+        DiskFormat::Qcow2 => Arc::new(Mutex::new(
+            Qcow2Driver::new(driver, &prop, 0)
+                .with_context(|| format!("Failed to open qcow2 image {}", prop.path))?,
+        )),
+    };
+    Ok(backend)
+}
+
+static BLOCK_BACKENDS: Lazy<Mutex<HashMap<String, BlockBackend>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Register the block backend of device `id`, so that it can be found by QMP commands.
+pub fn register_block_backend(id: &str, backend: BlockBackend) {
+    BLOCK_BACKENDS
+        .lock()
+        .unwrap()
+        .insert(id.to_string(), backend);
+}
+
+pub fn unregister_block_backend(id: &str) {
+    BLOCK_BACKENDS.lock().unwrap().remove(id);
+}
+
+pub fn get_block_backend(id: &str) -> Option<BlockBackend> {
+    BLOCK_BACKENDS.lock().unwrap().get(id).cloned()
+}
+
+/// Map the guest read/write/discard/write zeroes of `aiocb` by the format driver,
+/// and submit the host IOs. The request is submitted as is if there is no format driver.
+pub fn submit_rw_request<T: Clone + 'static>(
+    aio: &mut Aio<T>,
+    backend: &Option<BlockBackend>,
+    aiocb: AioCb<T>,
+) -> Result<()> {
+    let backend = match backend {
+        Some(backend) => backend,
+        None => return aio.submit_request(aiocb),
+    };
+    let mut locked_backend = backend.lock().unwrap();
+    let offset = aiocb.offset as u64;
+    let reqs = match aiocb.opcode {
+        OpCode::Preadv => locked_backend.read_vectored(&aiocb.iovec, offset),
+        OpCode::Pwritev => locked_backend.write_vectored(&aiocb.iovec, offset),
+        OpCode::Discard => locked_backend.discard(offset, aiocb.nbytes),
+        OpCode::WriteZeroes => locked_backend.write_zeroes(offset, aiocb.nbytes, false),
+        OpCode::WriteZeroesUnmap => locked_backend.write_zeroes(offset, aiocb.nbytes, true),
+        _ => {
+            drop(locked_backend);
+            return aio.submit_request(aiocb);
+        }
+    };
+    drop(locked_backend);
+    match reqs {
+        Ok(reqs) => aio.submit_combine_requests(aiocb, reqs),
+        Err(e) => {
+            error!("Failed to map block request: {:?}", e);
+            aio.complete_request(aiocb, -(error_errno(&e) as i64))
+        }
+    }
+}
+
+/// Get the errno of the first host IO error in the chain of `err`, EIO if there is none.
+pub fn error_errno(err: &anyhow::Error) -> i32 {
+    err.chain()
+        .find_map(|e| e.downcast_ref::<std::io::Error>())
+        .and_then(|e| e.raw_os_error())
+        .unwrap_or(libc::EIO)
+}
+
+/// Get the iovecs which cover `len` bytes from `offset` of `iov`.
+pub fn iov_slice(iov: &[Iovec], mut offset: u64, mut len: u64) -> Vec<Iovec> {
+    let mut res = Vec::new();
+    for iov in iov {
+        if len == 0 {
+            break;
+        }
+        if offset >= iov.iov_len {
+            offset -= iov.iov_len;
+            continue;
+        }
+        let size = std::cmp::min(iov.iov_len - offset, len);
+        res.push(Iovec {
+            iov_base: iov.iov_base + offset,
+            iov_len: size,
+        });
+        len -= size;
+        offset = 0;
+    }
+    res
+}
+
+/// Get the total length of `iov`.
+pub fn iov_len(iov: &[Iovec]) -> u64 {
+    iov.iter().map(|iov| iov.iov_len).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iov_slice() {
+        let iov = vec![
+            Iovec {
+                iov_base: 0x1000,
+                iov_len: 0x200,
+            },
+            Iovec {
+                iov_base: 0x4000,
+                iov_len: 0x400,
+            },
+        ];
+        assert_eq!(iov_len(&iov), 0x600);
+
+        let slice = iov_slice(&iov, 0x100, 0x200);
+        assert_eq!(slice.len(), 2);
+        assert_eq!((slice[0].iov_base, slice[0].iov_len), (0x1100, 0x100));
+        assert_eq!((slice[1].iov_base, slice[1].iov_len), (0x4000, 0x100));
+
+        let slice = iov_slice(&iov, 0x300, 0x1000);
+        assert_eq!(slice.len(), 1);
+        assert_eq!((slice[0].iov_base, slice[0].iov_len), (0x4100, 0x300));
+    }
+
+    #[test]
+    fn test_error_errno() {
+        let err = Err::<(), _>(std::io::Error::from_raw_os_error(libc::ENOSPC))
+            .with_context(|| "Failed to write")
+            .with_context(|| "Failed to allocate cluster")
+            .unwrap_err();
+        assert_eq!(error_errno(&err), libc::ENOSPC);
+        assert_eq!(error_errno(&anyhow::anyhow!("Invalid offset")), libc::EIO);
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::collections::HashMap;
+
+use byteorder::{BigEndian, ByteOrder};
+
+/// One cluster sized metadata table (L2 table or refcount block) held in memory.
+/// Data is kept in on-disk format, so that it can be written back directly.
+pub struct CacheTable {
+    /// Host offset of the table in the image file.
+    pub addr: u64,
+    data: Vec<u8>,
+    /// Size of each entry in bytes.
+    entry_size: usize,
+    /// Last access, used to evict the least recently used table.
+    lru_count: u64,
+}
+
+impl CacheTable {
+    pub fn new(addr: u64, data: Vec<u8>, entry_size: usize) -> Self {
+        CacheTable {
+            addr,
+            data,
+            entry_size,
+            lru_count: 0,
+        }
+    }
+
+    pub fn entries(&self) -> usize {
+        self.data.len() / self.entry_size
+    }
+
+    pub fn get_entry(&self, idx: usize) -> u64 {
+        let start = idx * self.entry_size;
+        let bytes = &self.data[start..start + self.entry_size];
+        match self.entry_size {
+            1 => bytes[0] as u64,
+            2 => BigEndian::read_u16(bytes) as u64,
+            4 => BigEndian::read_u32(bytes) as u64,
+            _ => BigEndian::read_u64(bytes),
+        }
+    }
+
+    pub fn set_entry(&mut self, idx: usize, value: u64) {
+        let start = idx * self.entry_size;
+        let bytes = &mut self.data[start..start + self.entry_size];
+        match self.entry_size {
+            1 => bytes[0] = value as u8,
+            2 => BigEndian::write_u16(bytes, value as u16),
+            4 => BigEndian::write_u32(bytes, value as u32),
+            _ => BigEndian::write_u64(bytes, value),
+        }
+    }
+
+    /// Get the on-disk bytes of entry `idx` and its offset in the table.
+    pub fn entry_bytes(&self, idx: usize) -> (&[u8], u64) {
+        let start = idx * self.entry_size;
+        (&self.data[start..start + self.entry_size], start as u64)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// Write-through cache of metadata tables, evicted by LRU.
+pub struct Qcow2Cache {
+    max_size: usize,
+    tables: HashMap<u64, CacheTable>,
+    lru_count: u64,
+}
+
+impl Qcow2Cache {
+    pub fn new(max_size: usize) -> Self {
+        Qcow2Cache {
+            max_size: std::cmp::max(max_size, 1),
+            tables: HashMap::with_capacity(max_size),
+            lru_count: 0,
+        }
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        self.tables.contains_key(&addr)
+    }
+
+    pub fn get(&mut self, addr: u64) -> Option<&mut CacheTable> {
+        self.lru_count += 1;
+        let lru_count = self.lru_count;
+        self.tables.get_mut(&addr).map(|table| {
+            table.lru_count = lru_count;
+            table
+        })
+    }
+
+    pub fn insert(&mut self, mut table: CacheTable) {
+        if self.tables.len() >= self.max_size && !self.tables.contains_key(&table.addr) {
+            if let Some(victim) = self
+                .tables
+                .values()
+                .min_by_key(|t| t.lru_count)
+                .map(|t| t.addr)
+            {
+                self.tables.remove(&victim);
+            }
+        }
+        self.lru_count += 1;
+        table.lru_count = self.lru_count;
+        self.tables.insert(table.addr, table);
+    }
+
+    pub fn remove(&mut self, addr: u64) {
+        self.tables.remove(&addr);
+    }
+
+    pub fn clear(&mut self) {
+        self.tables.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_lru() {
+        let mut cache = Qcow2Cache::new(2);
+        cache.insert(CacheTable::new(0x1000, vec![0; 16], 8));
+        cache.insert(CacheTable::new(0x2000, vec![0; 16], 2));
+        cache.get(0x1000).unwrap().set_entry(1, 0xdead_beef);
+        cache.get(0x2000).unwrap().set_entry(7, 0xabcd);
+        assert_eq!(cache.get(0x1000).unwrap().get_entry(1), 0xdead_beef);
+        assert_eq!(cache.get(0x2000).unwrap().entries(), 8);
+        assert_eq!(
+            cache.get(0x2000).unwrap().entry_bytes(7),
+            (&[0xab, 0xcd][..], 14)
+        );
+
+        // 0x1000 is the least recently used one.
+        cache.insert(CacheTable::new(0x3000, vec![0; 16], 8));
+        assert!(!cache.contains(0x1000));
+        assert!(cache.contains(0x2000));
+        assert!(cache.contains(0x3000));
+    }
+}
@@ -0,0 +1,248 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{bail, Result};
+use byteorder::{BigEndian, ByteOrder};
+
+pub const QCOW_MAGIC: u32 = 0x5146_49fb;
+/// Length of the header of qcow2 version 2.
+pub const QCOW_HEADER_V2_LENGTH: usize = 72;
+/// Length of the header of qcow2 version 3, without optional fields.
+pub const QCOW_HEADER_V3_LENGTH: usize = 104;
+pub const MIN_CLUSTER_BITS: u32 = 9;
+pub const MAX_CLUSTER_BITS: u32 = 21;
+/// The header extension which indicates the format of the backing file.
+pub const HEADER_EXT_BACKING_FORMAT: u32 = 0xe279_2aca;
+pub const HEADER_EXT_END: u32 = 0;
+/// Max length of the backing file name.
+pub const MAX_BACKING_FILE_NAME: u32 = 1023;
+
+/// Incompatible feature bits.
+pub const INCOMPAT_DIRTY: u64 = 1 << 0;
+pub const INCOMPAT_CORRUPT: u64 = 1 << 1;
+pub const INCOMPAT_DATA_FILE: u64 = 1 << 2;
+pub const INCOMPAT_COMPRESSION: u64 = 1 << 3;
+pub const INCOMPAT_EXTL2: u64 = 1 << 4;
+
+/// Header of qcow2 image, all fields are stored in big endian on disk.
+#[derive(Clone, Debug, Default)]
+pub struct QcowHeader {
+    pub magic: u32,
+    pub version: u32,
+    pub backing_file_offset: u64,
+    pub backing_file_size: u32,
+    pub cluster_bits: u32,
+    pub size: u64,
+    pub crypt_method: u32,
+    pub l1_size: u32,
+    pub l1_table_offset: u64,
+    pub refcount_table_offset: u64,
+    pub refcount_table_clusters: u32,
+    pub nb_snapshots: u32,
+    pub snapshots_offset: u64,
+    // Fields below are only valid in version 3.
+    pub incompatible_features: u64,
+    pub compatible_features: u64,
+    pub autoclear_features: u64,
+    pub refcount_order: u32,
+    pub header_length: u32,
+}
+
+impl QcowHeader {
+    pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        if buf.len() < QCOW_HEADER_V2_LENGTH {
+            bail!("Invalid header length {}", buf.len());
+        }
+        let mut header = QcowHeader {
+            magic: BigEndian::read_u32(&buf[0..4]),
+            version: BigEndian::read_u32(&buf[4..8]),
+            backing_file_offset: BigEndian::read_u64(&buf[8..16]),
+            backing_file_size: BigEndian::read_u32(&buf[16..20]),
+            cluster_bits: BigEndian::read_u32(&buf[20..24]),
+            size: BigEndian::read_u64(&buf[24..32]),
+            crypt_method: BigEndian::read_u32(&buf[32..36]),
+            l1_size: BigEndian::read_u32(&buf[36..40]),
+            l1_table_offset: BigEndian::read_u64(&buf[40..48]),
+            refcount_table_offset: BigEndian::read_u64(&buf[48..56]),
+            refcount_table_clusters: BigEndian::read_u32(&buf[56..60]),
+            nb_snapshots: BigEndian::read_u32(&buf[60..64]),
+            snapshots_offset: BigEndian::read_u64(&buf[64..72]),
+            // Default values of version 2.
+            refcount_order: 4,
+            header_length: QCOW_HEADER_V2_LENGTH as u32,
+            ..Default::default()
+        };
+        if header.magic != QCOW_MAGIC {
+            bail!("Invalid qcow2 magic {:#x}", header.magic);
+        }
+        match header.version {
+            2 => {}
+            3 => {
+                if buf.len() < QCOW_HEADER_V3_LENGTH {
+                    bail!("Invalid header length {} for version 3", buf.len());
+                }
+                header.incompatible_features = BigEndian::read_u64(&buf[72..80]);
+                header.compatible_features = BigEndian::read_u64(&buf[80..88]);
+                header.autoclear_features = BigEndian::read_u64(&buf[88..96]);
+                header.refcount_order = BigEndian::read_u32(&buf[96..100]);
+                header.header_length = BigEndian::read_u32(&buf[100..104]);
+            }
+            v => bail!("Unsupported qcow2 version {}", v),
+        }
+        Ok(header)
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let len = if self.version == 2 {
+            QCOW_HEADER_V2_LENGTH
+        } else {
+            QCOW_HEADER_V3_LENGTH
+        };
+        let mut buf = vec![0_u8; len];
+        BigEndian::write_u32(&mut buf[0..4], self.magic);
+        BigEndian::write_u32(&mut buf[4..8], self.version);
+        BigEndian::write_u64(&mut buf[8..16], self.backing_file_offset);
+        BigEndian::write_u32(&mut buf[16..20], self.backing_file_size);
+        BigEndian::write_u32(&mut buf[20..24], self.cluster_bits);
+        BigEndian::write_u64(&mut buf[24..32], self.size);
+        BigEndian::write_u32(&mut buf[32..36], self.crypt_method);
+        BigEndian::write_u32(&mut buf[36..40], self.l1_size);
+        BigEndian::write_u64(&mut buf[40..48], self.l1_table_offset);
+        BigEndian::write_u64(&mut buf[48..56], self.refcount_table_offset);
+        BigEndian::write_u32(&mut buf[56..60], self.refcount_table_clusters);
+        BigEndian::write_u32(&mut buf[60..64], self.nb_snapshots);
+        BigEndian::write_u64(&mut buf[64..72], self.snapshots_offset);
+        if self.version == 3 {
+            BigEndian::write_u64(&mut buf[72..80], self.incompatible_features);
+            BigEndian::write_u64(&mut buf[80..88], self.compatible_features);
+            BigEndian::write_u64(&mut buf[88..96], self.autoclear_features);
+            BigEndian::write_u32(&mut buf[96..100], self.refcount_order);
+            BigEndian::write_u32(&mut buf[100..104], self.header_length);
+        }
+        buf
+    }
+
+    pub fn cluster_size(&self) -> u64 {
+        1 << self.cluster_bits
+    }
+
+    /// Check whether this header can be handled by the driver.
+    pub fn check(&self) -> Result<()> {
+        if !(MIN_CLUSTER_BITS..=MAX_CLUSTER_BITS).contains(&self.cluster_bits) {
+            bail!("Invalid cluster bits {}", self.cluster_bits);
+        }
+        if self.crypt_method != 0 {
+            bail!("Encrypted qcow2 image is not supported");
+        }
+        if self.incompatible_features & INCOMPAT_DIRTY != 0 {
+            bail!("Qcow2 image is dirty, repair it with \"qemu-img check -r all\" first");
+        }
+        if self.incompatible_features & INCOMPAT_CORRUPT != 0 {
+            bail!("Qcow2 image is corrupt");
+        }
+        let unsupported = self.incompatible_features & !INCOMPAT_DIRTY & !INCOMPAT_CORRUPT;
+        if unsupported & INCOMPAT_DATA_FILE != 0 {
+            bail!("Qcow2 image with external data file is not supported");
+        }
+        if unsupported & INCOMPAT_COMPRESSION != 0 {
+            bail!("Qcow2 image with compression type field is not supported");
+        }
+        if unsupported & INCOMPAT_EXTL2 != 0 {
+            bail!("Qcow2 image with extended L2 entries is not supported");
+        }
+        if unsupported != 0 {
+            bail!("Unknown incompatible features {:#x}", unsupported);
+        }
+        if !(3..=6).contains(&self.refcount_order) {
+            bail!("Unsupported refcount order {}", self.refcount_order);
+        }
+        if (self.header_length as usize) < QCOW_HEADER_V2_LENGTH
+            || self.header_length as u64 > self.cluster_size()
+        {
+            bail!("Invalid header length {}", self.header_length);
+        }
+        let cluster_size = self.cluster_size();
+        for (name, offset) in [
+            ("L1 table", self.l1_table_offset),
+            ("refcount table", self.refcount_table_offset),
+            ("snapshot table", self.snapshots_offset),
+        ] {
+            if offset & (cluster_size - 1) != 0 {
+                bail!("Offset {:#x} of {} is not cluster aligned", offset, name);
+            }
+        }
+        if self.refcount_table_clusters == 0 {
+            bail!("Qcow2 image has no refcount table");
+        }
+        if self.backing_file_offset != 0 && self.backing_file_size > MAX_BACKING_FILE_NAME {
+            bail!("Backing file name is too long");
+        }
+        // Guest offset must be representable, and L1 table must be able to cover the size.
+        let l2_bits = self.cluster_bits - 3;
+        let l1_coverage = 1_u64 << (self.cluster_bits + l2_bits);
+        let min_l1_size = (self.size + l1_coverage - 1) / l1_coverage;
+        if (self.l1_size as u64) < min_l1_size {
+            bail!(
+                "L1 table size {} is too small for disk size {}",
+                self.l1_size,
+                self.size
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_serialize() {
+        let header = QcowHeader {
+            magic: QCOW_MAGIC,
+            version: 3,
+            cluster_bits: 16,
+            size: 1 << 30,
+            l1_size: 2,
+            l1_table_offset: 0x30000,
+            refcount_table_offset: 0x10000,
+            refcount_table_clusters: 1,
+            refcount_order: 4,
+            header_length: QCOW_HEADER_V3_LENGTH as u32,
+            ..Default::default()
+        };
+        let buf = header.to_vec();
+        assert_eq!(buf.len(), QCOW_HEADER_V3_LENGTH);
+        assert_eq!(&buf[0..4], &[0x51, 0x46, 0x49, 0xfb]);
+        let parsed = QcowHeader::from_bytes(&buf).unwrap();
+        assert_eq!(parsed.size, 1 << 30);
+        assert_eq!(parsed.l1_table_offset, 0x30000);
+        assert!(parsed.check().is_ok());
+
+        // L1 table can not cover the disk size.
+        let mut bad = parsed.clone();
+        bad.size = 1 << 40;
+        assert!(bad.check().is_err());
+        // Encrypted image.
+        let mut bad = parsed.clone();
+        bad.crypt_method = 1;
+        assert!(bad.check().is_err());
+        // Unknown incompatible feature.
+        let mut bad = parsed;
+        bad.incompatible_features = 1 << 10;
+        assert!(bad.check().is_err());
+
+        let mut buf = header.to_vec();
+        buf[3] = 0;
+        assert!(QcowHeader::from_bytes(&buf).is_err());
+    }
+}
@@ -0,0 +1,1199 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+pub mod cache;
+pub mod header;
+pub mod refcount;
+pub mod snapshot;
+
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use byteorder::{BigEndian, ByteOrder};
+use log::info;
+
+use self::cache::{CacheTable, Qcow2Cache};
+use self::header::{
+    QcowHeader, HEADER_EXT_BACKING_FORMAT, HEADER_EXT_END, QCOW_HEADER_V3_LENGTH, QCOW_MAGIC,
+};
+use self::refcount::RefCount;
+use self::snapshot::{parse_snapshot_table, snapshot_table_to_bytes, QcowSnapshot, MAX_SNAPSHOTS};
+use crate::file::FileDriver;
+use crate::raw::RawDriver;
+use crate::{iov_len, iov_slice, BlockDriverOps, BlockProperty, SnapshotInfo};
+use machine_manager::config::DiskFormat;
+use util::aio::{iov_from_buf_direct, iov_to_buf_direct, CombineRequest, Iovec};
+
+/// Mask of the L2 table offset in L1 table entry.
+const L1_OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+/// Mask of the host cluster offset in L2 table entry.
+const L2_OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+/// The refcount of the table or cluster is exactly one.
+const QCOW2_OFLAG_COPIED: u64 = 1 << 63;
+const QCOW2_OFLAG_COMPRESSED: u64 = 1 << 62;
+/// The cluster reads as all zeros.
+const QCOW2_OFLAG_ZERO: u64 = 1 << 0;
+/// Offset of `nb_snapshots` field in the qcow2 header.
+const HEADER_NB_SNAPSHOTS_OFFSET: u64 = 60;
+/// Max number of L2 tables cached.
+const L2_CACHE_SIZE: usize = 16;
+/// Max depth of the backing chain.
+const MAX_BACKING_DEPTH: u32 = 16;
+
+/// State of a guest cluster.
+#[derive(Debug, PartialEq, Eq)]
+enum ClusterState {
+    Unallocated,
+    Zero,
+    Normal(u64),
+    Compressed,
+}
+
+impl ClusterState {
+    fn from_l2_entry(entry: u64) -> Self {
+        if entry & QCOW2_OFLAG_COMPRESSED != 0 {
+            return ClusterState::Compressed;
+        }
+        if entry & QCOW2_OFLAG_ZERO != 0 {
+            return ClusterState::Zero;
+        }
+        match entry & L2_OFFSET_MASK {
+            0 => ClusterState::Unallocated,
+            host => ClusterState::Normal(host),
+        }
+    }
+}
+
+/// Driver of qcow2 image, see docs/interop/qcow2.txt of QEMU for the format.
+pub struct Qcow2Driver {
+    file: FileDriver,
+    header: QcowHeader,
+    l1_table: Vec<u64>,
+    l2_cache: Qcow2Cache,
+    refcount: RefCount,
+    snapshots: Vec<QcowSnapshot>,
+    /// Size of the snapshot table in bytes.
+    snapshots_size: u64,
+    backing: Option<Box<dyn BlockDriverOps>>,
+    backing_size: u64,
+    read_only: bool,
+}
+
+impl Qcow2Driver {
+    pub fn new(file: FileDriver, prop: &BlockProperty, depth: u32) -> Result<Self> {
+        let mut buf = vec![0_u8; QCOW_HEADER_V3_LENGTH];
+        file.read_at(&mut buf, 0)
+            .with_context(|| "Failed to read qcow2 header")?;
+        let header = QcowHeader::from_bytes(&buf)?;
+        header.check()?;
+
+        let mut buf = vec![0_u8; header.l1_size as usize * 8];
+        file.read_at(&mut buf, header.l1_table_offset)
+            .with_context(|| "Failed to read L1 table")?;
+        let l1_table = buf.chunks(8).map(BigEndian::read_u64).collect();
+        let refcount = RefCount::new(file.clone(), &header)?;
+
+        let mut driver = Qcow2Driver {
+            file,
+            header,
+            l1_table,
+            l2_cache: Qcow2Cache::new(L2_CACHE_SIZE),
+            refcount,
+            snapshots: Vec::new(),
+            snapshots_size: 0,
+            backing: None,
+            backing_size: 0,
+            read_only: prop.read_only,
+        };
+        driver.load_snapshot_table()?;
+        driver.open_backing(prop, depth)?;
+        Ok(driver)
+    }
+
+    fn cluster_bits(&self) -> u32 {
+        self.header.cluster_bits
+    }
+
+    fn cluster_size(&self) -> u64 {
+        self.header.cluster_size()
+    }
+
+    fn l1_index(&self, guest_offset: u64) -> usize {
+        (guest_offset >> (2 * self.cluster_bits() - 3)) as usize
+    }
+
+    fn l2_index(&self, guest_offset: u64) -> usize {
+        ((guest_offset >> self.cluster_bits()) & ((1 << (self.cluster_bits() - 3)) - 1)) as usize
+    }
+
+    fn load_snapshot_table(&mut self) -> Result<()> {
+        if self.header.nb_snapshots == 0 {
+            return Ok(());
+        }
+        if self.header.nb_snapshots as usize > MAX_SNAPSHOTS {
+            bail!("Too many snapshots {}", self.header.nb_snapshots);
+        }
+        // Entries are of variable length, walk through them to get the table size.
+        let mut size = 0;
+        let mut entry = [0_u8; 40];
+        for _ in 0..self.header.nb_snapshots {
+            self.file
+                .read_at(&mut entry, self.header.snapshots_offset + size)
+                .with_context(|| "Failed to read snapshot table")?;
+            let len = 40
+                + BigEndian::read_u32(&entry[36..40]) as u64
+                + BigEndian::read_u16(&entry[12..14]) as u64
+                + BigEndian::read_u16(&entry[14..16]) as u64;
+            size += (len + 7) & !7;
+        }
+        let mut buf = vec![0_u8; size as usize];
+        self.file
+            .read_at(&mut buf, self.header.snapshots_offset)
+            .with_context(|| "Failed to read snapshot table")?;
+        self.snapshots = parse_snapshot_table(&buf, self.header.nb_snapshots, self.header.size)?;
+        self.snapshots_size = size;
+        Ok(())
+    }
+
+    /// Get the format of the backing file from the header extensions.
+    fn backing_format(&self) -> Result<Option<DiskFormat>> {
+        let mut buf = vec![0_u8; self.cluster_size() as usize];
+        self.file.read_at(&mut buf, 0)?;
+        let end = if self.header.backing_file_offset != 0 {
+            std::cmp::min(self.header.backing_file_offset as usize, buf.len())
+        } else {
+            buf.len()
+        };
+        let mut pos = self.header.header_length as usize;
+        while pos + 8 <= end {
+            let ext_type = BigEndian::read_u32(&buf[pos..pos + 4]);
+            let ext_len = BigEndian::read_u32(&buf[pos + 4..pos + 8]) as usize;
+            if ext_type == HEADER_EXT_END || pos + 8 + ext_len > end {
+                break;
+            }
+            if ext_type == HEADER_EXT_BACKING_FORMAT {
+                let name = String::from_utf8_lossy(&buf[pos + 8..pos + 8 + ext_len]);
+                let format = DiskFormat::from_str(&name)
+                    .map_err(|_| anyhow::anyhow!("Unsupported backing format {}", name))?;
+                return Ok(Some(format));
+            }
+            pos += 8 + ((ext_len + 7) & !7);
+        }
+        Ok(None)
+    }
+
+    fn open_backing(&mut self, prop: &BlockProperty, depth: u32) -> Result<()> {
+        if self.header.backing_file_offset == 0 {
+            return Ok(());
+        }
+        if depth >= MAX_BACKING_DEPTH {
+            bail!("Backing chain is too deep");
+        }
+        let mut buf = vec![0_u8; self.header.backing_file_size as usize];
+        self.file
+            .read_at(&mut buf, self.header.backing_file_offset)
+            .with_context(|| "Failed to read backing file name")?;
+        let name = String::from_utf8_lossy(&buf).to_string();
+        // Relative path is based on the directory of the image.
+        let path = match Path::new(&prop.path).parent() {
+            Some(dir) if !name.starts_with('/') => dir.join(&name),
+            _ => Path::new(&name).to_path_buf(),
+        };
+        let backing_file = OpenOptions::new()
+            .read(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open backing file {:?}", path))?;
+        let backing_file = FileDriver::new(Arc::new(backing_file), 1, 1);
+
+        let format = match self.backing_format()? {
+            Some(format) => format,
+            None => {
+                let mut magic = [0_u8; 4];
+                backing_file.read_at(&mut magic, 0)?;
+                if BigEndian::read_u32(&magic) == QCOW_MAGIC {
+                    DiskFormat::Qcow2
+                } else {
+                    DiskFormat::Raw
+                }
+            }
+        };
+        let backing_prop = BlockProperty {
+            format,
+            read_only: true,
+            direct: false,
+            req_align: 1,
+            buf_align: 1,
+            path: path.to_string_lossy().to_string(),
+        };
+        let mut backing: Box<dyn BlockDriverOps> = match format {
+            DiskFormat::Raw => Box::new(RawDriver::new(backing_file)),
+            DiskFormat::Qcow2 => Box::new(
+                Qcow2Driver::new(backing_file, &backing_prop, depth + 1)
+                    .with_context(|| format!("Failed to open backing file {:?}", path))?,
+            ),
+        };
+        self.backing_size = backing.disk_size()?;
+        self.backing = Some(backing);
+        info!("Open backing file {:?} of format {:?}", path, format);
+        Ok(())
+    }
+
+    fn load_l2(&mut self, l2_offset: u64) -> Result<&mut CacheTable> {
+        if !self.l2_cache.contains(l2_offset) {
+            let mut buf = vec![0_u8; self.cluster_size() as usize];
+            self.file
+                .read_at(&mut buf, l2_offset)
+                .with_context(|| format!("Failed to read L2 table {:#x}", l2_offset))?;
+            self.l2_cache.insert(CacheTable::new(l2_offset, buf, 8));
+        }
+        Ok(self.l2_cache.get(l2_offset).unwrap())
+    }
+
+    fn get_cluster_state(&mut self, guest_offset: u64) -> Result<ClusterState> {
+        let l1_index = self.l1_index(guest_offset);
+        let l2_offset = self
+            .l1_table
+            .get(l1_index)
+            .map_or(0, |entry| entry & L1_OFFSET_MASK);
+        if l2_offset == 0 {
+            return Ok(ClusterState::Unallocated);
+        }
+        let l2_index = self.l2_index(guest_offset);
+        let entry = self.load_l2(l2_offset)?.get_entry(l2_index);
+        Ok(ClusterState::from_l2_entry(entry))
+    }
+
+    /// Read guest data from the backing file, zero beyond the end of it.
+    fn read_backing(&mut self, buf: &mut [u8], guest_offset: u64) -> Result<()> {
+        buf.fill(0);
+        if let Some(backing) = self.backing.as_mut() {
+            if guest_offset < self.backing_size {
+                let len = std::cmp::min(buf.len() as u64, self.backing_size - guest_offset);
+                backing.read_at(&mut buf[..len as usize], guest_offset)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_l1_entry(&mut self, l1_index: usize) -> Result<()> {
+        let mut buf = [0_u8; 8];
+        BigEndian::write_u64(&mut buf, self.l1_table[l1_index]);
+        self.file
+            .write_at(&buf, self.header.l1_table_offset + l1_index as u64 * 8)
+            .with_context(|| "Failed to update L1 table")
+    }
+
+    fn write_l1_table(&mut self) -> Result<()> {
+        let buf = l1_table_to_bytes(&self.l1_table);
+        self.file
+            .write_at(&buf, self.header.l1_table_offset)
+            .with_context(|| "Failed to write L1 table")
+    }
+
+    fn set_l2_entry(&mut self, l2_offset: u64, l2_index: usize, entry: u64) -> Result<()> {
+        let table = self.load_l2(l2_offset)?;
+        table.set_entry(l2_index, entry);
+        let (bytes, offset) = table.entry_bytes(l2_index);
+        let bytes = bytes.to_vec();
+        self.file
+            .write_at(&bytes, l2_offset + offset)
+            .with_context(|| "Failed to update L2 table")
+    }
+
+    /// Get the L2 table of `guest_offset` which can be written, it is allocated or
+    /// copied if needed.
+    fn get_l2_table_for_write(&mut self, guest_offset: u64) -> Result<u64> {
+        let l1_index = self.l1_index(guest_offset);
+        if l1_index >= self.l1_table.len() {
+            bail!("Guest offset {:#x} is beyond the disk", guest_offset);
+        }
+        let entry = self.l1_table[l1_index];
+        let old_offset = entry & L1_OFFSET_MASK;
+        if old_offset != 0 {
+            if entry & QCOW2_OFLAG_COPIED != 0 {
+                return Ok(old_offset);
+            }
+            if self.refcount.get_refcount(old_offset)? == 1 {
+                self.l1_table[l1_index] = old_offset | QCOW2_OFLAG_COPIED;
+                self.write_l1_entry(l1_index)?;
+                return Ok(old_offset);
+            }
+        }
+
+        // The table is shared with snapshots or does not exist, make a new one.
+        let cluster_size = self.cluster_size();
+        let new_offset = self.refcount.alloc_clusters(cluster_size)?;
+        let data = if old_offset == 0 {
+            vec![0_u8; cluster_size as usize]
+        } else {
+            self.load_l2(old_offset)?.as_bytes().to_vec()
+        };
+        self.file
+            .write_at(&data, new_offset)
+            .with_context(|| "Failed to write new L2 table")?;
+        self.l2_cache.insert(CacheTable::new(new_offset, data, 8));
+        self.l1_table[l1_index] = new_offset | QCOW2_OFLAG_COPIED;
+        self.write_l1_entry(l1_index)?;
+        if old_offset != 0 {
+            self.refcount.free_clusters(old_offset, cluster_size)?;
+        }
+        Ok(new_offset)
+    }
+
+    /// Get the host cluster of the guest cluster which contains `guest_offset`, if it
+    /// can be written in place. None if it needs a new host cluster.
+    fn get_cluster_for_write(&mut self, guest_offset: u64) -> Result<Option<u64>> {
+        let l2_offset = self.get_l2_table_for_write(guest_offset)?;
+        let l2_index = self.l2_index(guest_offset);
+        let entry = self.load_l2(l2_offset)?.get_entry(l2_index);
+        match ClusterState::from_l2_entry(entry) {
+            ClusterState::Compressed => bail!("Writing to compressed cluster is not supported"),
+            ClusterState::Normal(host) => {
+                if entry & QCOW2_OFLAG_COPIED != 0 {
+                    return Ok(Some(host));
+                }
+                if self.refcount.get_refcount(host)? == 1 {
+                    self.set_l2_entry(l2_offset, l2_index, host | QCOW2_OFLAG_COPIED)?;
+                    return Ok(Some(host));
+                }
+                Ok(None)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Write `data` from `offset_in_cluster` of the guest cluster which contains
+    /// `guest_offset` to a new host cluster, the rest of it is copied from the old
+    /// host cluster or the backing file.
+    ///
+    /// The data is written before the L2 table refers to the new cluster, and the
+    /// old cluster is released at last, so the guest cluster never refers to a host
+    /// cluster without its data even if the host crashes in the middle.
+    fn write_new_cluster(
+        &mut self,
+        guest_offset: u64,
+        offset_in_cluster: u64,
+        data: &[u8],
+    ) -> Result<()> {
+        let l2_offset = self.get_l2_table_for_write(guest_offset)?;
+        let l2_index = self.l2_index(guest_offset);
+        let entry = self.load_l2(l2_offset)?.get_entry(l2_index);
+        let old_host = entry & L2_OFFSET_MASK;
+        let cluster_size = self.cluster_size();
+        let mut buf = vec![0_u8; cluster_size as usize];
+        if data.len() as u64 != cluster_size {
+            match ClusterState::from_l2_entry(entry) {
+                ClusterState::Normal(host) => self.file.read_at(&mut buf, host)?,
+                ClusterState::Unallocated => {
+                    self.read_backing(&mut buf, guest_offset - offset_in_cluster)?
+                }
+                ClusterState::Compressed => {
+                    bail!("Writing to compressed cluster is not supported")
+                }
+                ClusterState::Zero => {}
+            }
+        }
+        let start = offset_in_cluster as usize;
+        buf[start..start + data.len()].copy_from_slice(data);
+
+        let new_host = self.refcount.alloc_clusters(cluster_size)?;
+        self.file
+            .write_at(&buf, new_host)
+            .with_context(|| "Failed to write new cluster")?;
+        self.set_l2_entry(l2_offset, l2_index, new_host | QCOW2_OFLAG_COPIED)?;
+        if old_host != 0 {
+            self.refcount.free_clusters(old_host, cluster_size)?;
+        }
+        Ok(())
+    }
+
+    /// Set the L2 entry of the guest cluster which contains `guest_offset` to `new_entry`,
+    /// the host cluster it refers to is released.
+    fn reset_cluster(&mut self, guest_offset: u64, new_entry: u64) -> Result<()> {
+        match self.get_cluster_state(guest_offset)? {
+            ClusterState::Compressed => bail!("Discarding compressed cluster is not supported"),
+            ClusterState::Unallocated if new_entry == 0 => return Ok(()),
+            _ => {}
+        }
+        let l2_offset = self.get_l2_table_for_write(guest_offset)?;
+        let l2_index = self.l2_index(guest_offset);
+        let entry = self.load_l2(l2_offset)?.get_entry(l2_index);
+        if entry == new_entry {
+            return Ok(());
+        }
+        self.set_l2_entry(l2_offset, l2_index, new_entry)?;
+        let host = entry & L2_OFFSET_MASK;
+        if host != 0 && host != new_entry & L2_OFFSET_MASK {
+            self.refcount.free_clusters(host, self.cluster_size())?;
+        }
+        Ok(())
+    }
+
+    /// Make the guest cluster which contains `guest_offset` read as zeroes by the zero flag.
+    /// The host cluster is kept if it is not shared and `unmap` is false.
+    fn zero_cluster(&mut self, guest_offset: u64, unmap: bool) -> Result<()> {
+        let l2_offset = self
+            .l1_table
+            .get(self.l1_index(guest_offset))
+            .map_or(0, |entry| entry & L1_OFFSET_MASK);
+        if !unmap && l2_offset != 0 {
+            let l2_index = self.l2_index(guest_offset);
+            let entry = self.load_l2(l2_offset)?.get_entry(l2_index);
+            if let ClusterState::Normal(host) = ClusterState::from_l2_entry(entry) {
+                if entry & QCOW2_OFLAG_COPIED != 0 {
+                    return self
+                        .reset_cluster(guest_offset, host | QCOW2_OFLAG_COPIED | QCOW2_OFLAG_ZERO);
+                }
+            }
+        }
+        self.reset_cluster(guest_offset, QCOW2_OFLAG_ZERO)
+    }
+
+    /// Add `addend` to the refcount of all L2 tables and data clusters referenced by `l1_table`.
+    fn update_tree_refcount(&mut self, l1_table: &[u64], addend: i64) -> Result<()> {
+        for l1_entry in l1_table {
+            let l2_offset = l1_entry & L1_OFFSET_MASK;
+            if l2_offset == 0 {
+                continue;
+            }
+            let table = self.load_l2(l2_offset)?;
+            let entries: Vec<u64> = (0..table.entries()).map(|i| table.get_entry(i)).collect();
+            for entry in entries {
+                if entry & QCOW2_OFLAG_COMPRESSED != 0 {
+                    bail!("Snapshot of image with compressed clusters is not supported");
+                }
+                let host = entry & L2_OFFSET_MASK;
+                if host != 0 {
+                    self.refcount.update_refcount(host, 1, addend)?;
+                }
+            }
+            self.refcount.update_refcount(l2_offset, 1, addend)?;
+            if self.refcount.get_refcount(l2_offset)? == 0 {
+                self.l2_cache.remove(l2_offset);
+            }
+        }
+        Ok(())
+    }
+
+    /// Make the COPIED flag of the active L1 table and L2 tables match the refcounts.
+    fn fix_copied_flags(&mut self) -> Result<()> {
+        for l1_index in 0..self.l1_table.len() {
+            let l2_offset = self.l1_table[l1_index] & L1_OFFSET_MASK;
+            if l2_offset == 0 {
+                continue;
+            }
+            if self.refcount.get_refcount(l2_offset)? != 1 {
+                self.l1_table[l1_index] = l2_offset;
+                continue;
+            }
+            self.l1_table[l1_index] = l2_offset | QCOW2_OFLAG_COPIED;
+            let entries = self.load_l2(l2_offset)?.entries();
+            let mut changed = false;
+            for l2_index in 0..entries {
+                let entry = self.load_l2(l2_offset)?.get_entry(l2_index);
+                let host = entry & L2_OFFSET_MASK;
+                if host == 0 || entry & QCOW2_OFLAG_COMPRESSED != 0 {
+                    continue;
+                }
+                let new_entry = if self.refcount.get_refcount(host)? == 1 {
+                    entry | QCOW2_OFLAG_COPIED
+                } else {
+                    entry & !QCOW2_OFLAG_COPIED
+                };
+                if new_entry != entry {
+                    self.load_l2(l2_offset)?.set_entry(l2_index, new_entry);
+                    changed = true;
+                }
+            }
+            if changed {
+                self.write_l2_table(l2_offset)?;
+            }
+        }
+        self.write_l1_table()
+    }
+
+    fn write_l2_table(&mut self, l2_offset: u64) -> Result<()> {
+        let buf = self.load_l2(l2_offset)?.as_bytes().to_vec();
+        self.file
+            .write_at(&buf, l2_offset)
+            .with_context(|| "Failed to write L2 table")
+    }
+
+    /// Clear the COPIED flag of the whole active tree, as it is going to be shared.
+    fn clear_copied_flags(&mut self) -> Result<()> {
+        for l1_index in 0..self.l1_table.len() {
+            let l2_offset = self.l1_table[l1_index] & L1_OFFSET_MASK;
+            self.l1_table[l1_index] &= !QCOW2_OFLAG_COPIED;
+            if l2_offset == 0 {
+                continue;
+            }
+            let table = self.load_l2(l2_offset)?;
+            let mut changed = false;
+            for l2_index in 0..table.entries() {
+                let entry = table.get_entry(l2_index);
+                if entry & QCOW2_OFLAG_COPIED != 0 {
+                    table.set_entry(l2_index, entry & !QCOW2_OFLAG_COPIED);
+                    changed = true;
+                }
+            }
+            if changed {
+                self.write_l2_table(l2_offset)?;
+            }
+        }
+        self.write_l1_table()
+    }
+
+    fn read_snapshot_l1(&self, snapshot: &QcowSnapshot) -> Result<Vec<u64>> {
+        let mut buf = vec![0_u8; snapshot.l1_size as usize * 8];
+        self.file
+            .read_at(&mut buf, snapshot.l1_table_offset)
+            .with_context(|| format!("Failed to read L1 table of snapshot {}", snapshot.name))?;
+        Ok(buf.chunks(8).map(BigEndian::read_u64).collect())
+    }
+
+    /// Write the snapshot table to new clusters, and switch the header to it.
+    fn write_snapshot_table(&mut self) -> Result<()> {
+        let buf = snapshot_table_to_bytes(&self.snapshots);
+        let new_offset = if buf.is_empty() {
+            0
+        } else {
+            let offset = self.refcount.alloc_clusters(buf.len() as u64)?;
+            self.file
+                .write_at(&buf, offset)
+                .with_context(|| "Failed to write snapshot table")?;
+            offset
+        };
+        let mut header_buf = [0_u8; 12];
+        BigEndian::write_u32(&mut header_buf[0..4], self.snapshots.len() as u32);
+        BigEndian::write_u64(&mut header_buf[4..12], new_offset);
+        self.file
+            .write_at(&header_buf, HEADER_NB_SNAPSHOTS_OFFSET)
+            .with_context(|| "Failed to update snapshot table in header")?;
+
+        let old_offset = self.header.snapshots_offset;
+        let old_size = self.snapshots_size;
+        self.header.nb_snapshots = self.snapshots.len() as u32;
+        self.header.snapshots_offset = new_offset;
+        self.snapshots_size = buf.len() as u64;
+        if old_offset != 0 && old_size != 0 {
+            self.refcount.free_clusters(old_offset, old_size)?;
+        }
+        Ok(())
+    }
+
+    fn find_snapshot(&self, name: &str) -> Option<usize> {
+        self.snapshots.iter().position(|s| s.name == name)
+    }
+}
+
+fn l1_table_to_bytes(l1_table: &[u64]) -> Vec<u8> {
+    let mut buf = vec![0_u8; l1_table.len() * 8];
+    for (i, entry) in l1_table.iter().enumerate() {
+        BigEndian::write_u64(&mut buf[i * 8..(i + 1) * 8], *entry);
+    }
+    buf
+}
+
+/// Append a host IO, merge it into the last one if they are continuous.
+fn push_request(reqs: &mut Vec<CombineRequest>, iov: Vec<Iovec>, offset: u64, len: u64) {
+    if let Some(last) = reqs.last_mut() {
+        if last.offset + last.nbytes == offset {
+            last.iov.extend(iov);
+            last.nbytes += len;
+            return;
+        }
+    }
+    reqs.push(CombineRequest::new(iov, offset, len));
+}
+
+impl BlockDriverOps for Qcow2Driver {
+    fn disk_size(&mut self) -> Result<u64> {
+        Ok(self.header.size)
+    }
+
+    fn read_vectored(&mut self, iov: &[Iovec], offset: u64) -> Result<Vec<CombineRequest>> {
+        let total = iov_len(iov);
+        let cluster_size = self.cluster_size();
+        let mut reqs = Vec::new();
+        let mut pos = 0;
+        while pos < total {
+            let guest_offset = offset + pos;
+            let offset_in_cluster = guest_offset & (cluster_size - 1);
+            let len = std::cmp::min(cluster_size - offset_in_cluster, total - pos);
+            let sub_iov = iov_slice(iov, pos, len);
+            match self.get_cluster_state(guest_offset)? {
+                ClusterState::Normal(host) => {
+                    push_request(&mut reqs, sub_iov, host + offset_in_cluster, len)
+                }
+                ClusterState::Zero => {
+                    iov_from_buf_direct(&sub_iov, &vec![0_u8; len as usize])?;
+                }
+                ClusterState::Unallocated => {
+                    // The backing file is read synchronously in the IO thread, the aio
+                    // context of the request only does IO on the image file itself.
+                    let mut buf = vec![0_u8; len as usize];
+                    self.read_backing(&mut buf, guest_offset)?;
+                    iov_from_buf_direct(&sub_iov, &buf)?;
+                }
+                ClusterState::Compressed => bail!("Compressed cluster is not supported"),
+            }
+            pos += len;
+        }
+        Ok(reqs)
+    }
+
+    fn write_vectored(&mut self, iov: &[Iovec], offset: u64) -> Result<Vec<CombineRequest>> {
+        if self.read_only {
+            bail!("Can not write to read-only qcow2 image");
+        }
+        let total = iov_len(iov);
+        let cluster_size = self.cluster_size();
+        let mut reqs = Vec::new();
+        let mut pos = 0;
+        while pos < total {
+            let guest_offset = offset + pos;
+            let offset_in_cluster = guest_offset & (cluster_size - 1);
+            let len = std::cmp::min(cluster_size - offset_in_cluster, total - pos);
+            let sub_iov = iov_slice(iov, pos, len);
+            match self.get_cluster_for_write(guest_offset)? {
+                Some(host) => push_request(&mut reqs, sub_iov, host + offset_in_cluster, len),
+                None => {
+                    // Writing to a new cluster is done synchronously, as the L2 table
+                    // can only be updated after the data is written.
+                    let mut buf = vec![0_u8; len as usize];
+                    iov_to_buf_direct(&sub_iov, &mut buf)?;
+                    self.write_new_cluster(guest_offset, offset_in_cluster, &buf)?;
+                }
+            }
+            pos += len;
+        }
+        Ok(reqs)
+    }
+
+    fn discard(&mut self, offset: u64, nbytes: u64) -> Result<Vec<CombineRequest>> {
+        if self.read_only {
+            bail!("Can not discard read-only qcow2 image");
+        }
+        // The discarded clusters must not expose the data of the backing file, which needs
+        // the zero flag of qcow2 version 3.
+        let zero = self.backing.is_some();
+        if zero && self.header.version < 3 {
+            return Ok(Vec::new());
+        }
+        let new_entry = if zero { QCOW2_OFLAG_ZERO } else { 0 };
+        let cluster_size = self.cluster_size();
+        // Discard is a hint, the partial clusters are just ignored.
+        let mut pos = (offset + cluster_size - 1) & !(cluster_size - 1);
+        while pos + cluster_size <= offset + nbytes {
+            self.reset_cluster(pos, new_entry)?;
+            pos += cluster_size;
+        }
+        Ok(Vec::new())
+    }
+
+    fn write_zeroes(
+        &mut self,
+        offset: u64,
+        nbytes: u64,
+        unmap: bool,
+    ) -> Result<Vec<CombineRequest>> {
+        if self.read_only {
+            bail!("Can not write to read-only qcow2 image");
+        }
+        let cluster_size = self.cluster_size();
+        let mut pos = 0;
+        while pos < nbytes {
+            let guest_offset = offset + pos;
+            let offset_in_cluster = guest_offset & (cluster_size - 1);
+            let len = std::cmp::min(cluster_size - offset_in_cluster, nbytes - pos);
+            if len == cluster_size && self.header.version >= 3 {
+                self.zero_cluster(guest_offset, unmap)?;
+            } else {
+                match self.get_cluster_for_write(guest_offset)? {
+                    Some(host) => self.file.write_zeroes(host + offset_in_cluster, len)?,
+                    None => self.write_new_cluster(
+                        guest_offset,
+                        offset_in_cluster,
+                        &vec![0_u8; len as usize],
+                    )?,
+                }
+            }
+            pos += len;
+        }
+        Ok(Vec::new())
+    }
+
+    fn read_at(&mut self, buf: &mut [u8], offset: u64) -> Result<()> {
+        let cluster_size = self.cluster_size();
+        let mut pos = 0;
+        while pos < buf.len() {
+            let guest_offset = offset + pos as u64;
+            let offset_in_cluster = guest_offset & (cluster_size - 1);
+            let len = std::cmp::min((cluster_size - offset_in_cluster) as usize, buf.len() - pos);
+            let chunk = &mut buf[pos..pos + len];
+            match self.get_cluster_state(guest_offset)? {
+                ClusterState::Normal(host) => self.file.read_at(chunk, host + offset_in_cluster)?,
+                ClusterState::Zero => chunk.fill(0),
+                ClusterState::Unallocated => self.read_backing(chunk, guest_offset)?,
+                ClusterState::Compressed => bail!("Compressed cluster is not supported"),
+            }
+            pos += len;
+        }
+        Ok(())
+    }
+
+    fn create_snapshot(&mut self, name: String, vm_clock_nsec: u64) -> Result<()> {
+        if self.read_only {
+            bail!("Can not create snapshot for read-only qcow2 image");
+        }
+        if self.find_snapshot(&name).is_some() {
+            bail!("Snapshot {} already exists", name);
+        }
+        if self.snapshots.len() >= MAX_SNAPSHOTS {
+            bail!("Too many snapshots");
+        }
+        let id = self
+            .snapshots
+            .iter()
+            .filter_map(|s| s.id.parse::<u64>().ok())
+            .max()
+            .unwrap_or(0)
+            + 1;
+
+        // The active tree is shared with the snapshot from now on.
+        let l1_table = self.l1_table.clone();
+        self.update_tree_refcount(&l1_table, 1)?;
+        self.clear_copied_flags()?;
+        let buf = l1_table_to_bytes(&self.l1_table);
+        let l1_table_offset = self.refcount.alloc_clusters(buf.len() as u64)?;
+        self.file
+            .write_at(&buf, l1_table_offset)
+            .with_context(|| "Failed to write L1 table of snapshot")?;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.snapshots.push(QcowSnapshot {
+            l1_table_offset,
+            l1_size: self.l1_table.len() as u32,
+            id: id.to_string(),
+            name,
+            date_sec: now.as_secs() as u32,
+            date_nsec: now.subsec_nanos(),
+            vm_clock_nsec,
+            vm_state_size: 0,
+            disk_size: self.header.size,
+            extra_data: Vec::new(),
+        });
+        self.write_snapshot_table()?;
+        self.file.datasync()
+    }
+
+    fn delete_snapshot(&mut self, name: String) -> Result<SnapshotInfo> {
+        if self.read_only {
+            bail!("Can not delete snapshot for read-only qcow2 image");
+        }
+        let index = self
+            .find_snapshot(&name)
+            .with_context(|| format!("Snapshot {} is not found", name))?;
+        let snapshot = self.snapshots.remove(index);
+        self.write_snapshot_table()?;
+
+        let l1_table = self.read_snapshot_l1(&snapshot)?;
+        self.update_tree_refcount(&l1_table, -1)?;
+        self.refcount
+            .free_clusters(snapshot.l1_table_offset, snapshot.l1_size as u64 * 8)?;
+        self.fix_copied_flags()?;
+        self.file.datasync()?;
+        Ok(SnapshotInfo::from(&snapshot))
+    }
+
+    fn apply_snapshot(&mut self, name: String) -> Result<()> {
+        if self.read_only {
+            bail!("Can not apply snapshot for read-only qcow2 image");
+        }
+        let index = self
+            .find_snapshot(&name)
+            .with_context(|| format!("Snapshot {} is not found", name))?;
+        let snapshot = self.snapshots[index].clone();
+        if snapshot.l1_size as usize > self.l1_table.len() {
+            bail!("Snapshot {} is larger than the disk", name);
+        }
+        let mut l1_table = self.read_snapshot_l1(&snapshot)?;
+        // Take the reference of the snapshot before dropping the active tree.
+        self.update_tree_refcount(&l1_table, 1)?;
+        l1_table.resize(self.l1_table.len(), 0);
+        let old_table = std::mem::replace(&mut self.l1_table, l1_table);
+        self.write_l1_table()?;
+        self.update_tree_refcount(&old_table, -1)?;
+        self.fix_copied_flags()?;
+        self.file.datasync()
+    }
+
+    fn list_snapshots(&self) -> Vec<SnapshotInfo> {
+        self.snapshots.iter().map(SnapshotInfo::from).collect()
+    }
+}
+
+impl From<&QcowSnapshot> for SnapshotInfo {
+    fn from(snapshot: &QcowSnapshot) -> Self {
+        SnapshotInfo {
+            id: snapshot.id.clone(),
+            name: snapshot.name.clone(),
+            vm_state_size: snapshot.vm_state_size as u64,
+            date_sec: snapshot.date_sec,
+            date_nsec: snapshot.date_nsec,
+            vm_clock_nsec: snapshot.vm_clock_nsec,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::os::unix::fs::FileExt;
+
+    use vmm_sys_util::tempfile::TempFile;
+
+    use super::header::QCOW_HEADER_V3_LENGTH;
+    use super::*;
+
+    const CLUSTER_BITS: u32 = 16;
+    const CLUSTER_SIZE: u64 = 1 << CLUSTER_BITS;
+
+    /// Create a qcow2 image: header, refcount table, refcount block and L1 table
+    /// are in the first four clusters.
+    fn create_image(path: &str, size: u64, backing: Option<&str>) {
+        let header = QcowHeader {
+            magic: QCOW_MAGIC,
+            version: 3,
+            backing_file_offset: if backing.is_some() { 112 } else { 0 },
+            backing_file_size: backing.map_or(0, |b| b.len() as u32),
+            cluster_bits: CLUSTER_BITS,
+            size,
+            l1_size: 2,
+            l1_table_offset: 3 * CLUSTER_SIZE,
+            refcount_table_offset: CLUSTER_SIZE,
+            refcount_table_clusters: 1,
+            refcount_order: 4,
+            header_length: QCOW_HEADER_V3_LENGTH as u32,
+            ..Default::default()
+        };
+        let file = File::create(path).unwrap();
+        file.write_all_at(&header.to_vec(), 0).unwrap();
+        if let Some(backing) = backing {
+            file.write_all_at(backing.as_bytes(), 112).unwrap();
+        }
+        let mut entry = [0_u8; 8];
+        BigEndian::write_u64(&mut entry, 2 * CLUSTER_SIZE);
+        file.write_all_at(&entry, CLUSTER_SIZE).unwrap();
+        file.write_all_at(&[0, 1, 0, 1, 0, 1, 0, 1], 2 * CLUSTER_SIZE)
+            .unwrap();
+        file.set_len(4 * CLUSTER_SIZE).unwrap();
+    }
+
+    fn open_image(path: &str) -> Qcow2Driver {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        let prop = BlockProperty {
+            format: DiskFormat::Qcow2,
+            read_only: false,
+            direct: false,
+            req_align: 1,
+            buf_align: 1,
+            path: path.to_string(),
+        };
+        Qcow2Driver::new(FileDriver::new(Arc::new(file), 1, 1), &prop, 0).unwrap()
+    }
+
+    fn buf_iov(buf: &[u8]) -> Vec<Iovec> {
+        vec![Iovec {
+            iov_base: buf.as_ptr() as u64,
+            iov_len: buf.len() as u64,
+        }]
+    }
+
+    /// Do the host IOs synchronously, as the aio context does.
+    fn exec_requests(driver: &Qcow2Driver, reqs: Vec<CombineRequest>, write: bool) {
+        for req in reqs {
+            let mut offset = req.offset;
+            for iov in req.iov {
+                // SAFETY: iov points to the buffers of the test.
+                let buf = unsafe {
+                    std::slice::from_raw_parts_mut(iov.iov_base as *mut u8, iov.iov_len as usize)
+                };
+                if write {
+                    driver.file.file.write_all_at(buf, offset).unwrap();
+                } else {
+                    driver.file.file.read_exact_at(buf, offset).unwrap();
+                }
+                offset += iov.iov_len;
+            }
+        }
+    }
+
+    fn write(driver: &mut Qcow2Driver, buf: &[u8], offset: u64) {
+        let reqs = driver.write_vectored(&buf_iov(buf), offset).unwrap();
+        exec_requests(driver, reqs, true);
+    }
+
+    fn read(driver: &mut Qcow2Driver, len: usize, offset: u64) -> Vec<u8> {
+        let buf = vec![0xff_u8; len];
+        let reqs = driver.read_vectored(&buf_iov(&buf), offset).unwrap();
+        exec_requests(driver, reqs, false);
+        buf
+    }
+
+    #[test]
+    fn test_qcow2_read_write() {
+        let image = TempFile::new().unwrap();
+        let path = image.as_path().to_str().unwrap();
+        create_image(path, 1 << 30, None);
+        let mut driver = open_image(path);
+        assert_eq!(driver.disk_size().unwrap(), 1 << 30);
+
+        // Unallocated clusters read as zero.
+        assert_eq!(read(&mut driver, 4096, 0x10000), vec![0_u8; 4096]);
+
+        // Write crossing the cluster boundary.
+        let data = vec![0x5a_u8; 8192];
+        write(&mut driver, &data, CLUSTER_SIZE - 4096);
+        assert_eq!(read(&mut driver, 8192, CLUSTER_SIZE - 4096), data);
+        assert_eq!(read(&mut driver, 4096, 0), vec![0_u8; 4096]);
+        let mut buf = vec![0_u8; 8192];
+        driver.read_at(&mut buf, CLUSTER_SIZE - 4096).unwrap();
+        assert_eq!(buf, data);
+
+        // Overwrite in place.
+        let host = match driver.get_cluster_state(CLUSTER_SIZE).unwrap() {
+            ClusterState::Normal(host) => host,
+            state => panic!("Unexpected cluster state {:?}", state),
+        };
+        write(&mut driver, &[0xa5_u8; 512], CLUSTER_SIZE + 512);
+        assert_eq!(
+            driver.get_cluster_state(CLUSTER_SIZE).unwrap(),
+            ClusterState::Normal(host)
+        );
+        assert_eq!(driver.refcount.get_refcount(host).unwrap(), 1);
+
+        // Data is persistent after reopen.
+        drop(driver);
+        let mut driver = open_image(path);
+        assert_eq!(
+            read(&mut driver, 512, CLUSTER_SIZE + 512),
+            vec![0xa5_u8; 512]
+        );
+        assert_eq!(read(&mut driver, 512, CLUSTER_SIZE), vec![0x5a_u8; 512]);
+        assert_eq!(
+            read(&mut driver, 4096, CLUSTER_SIZE - 4096),
+            vec![0x5a_u8; 4096]
+        );
+    }
+
+    #[test]
+    fn test_qcow2_backing_file() {
+        let backing = TempFile::new().unwrap();
+        let backing_path = backing.as_path().to_str().unwrap();
+        let backing_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(backing_path)
+            .unwrap();
+        backing_file
+            .write_all_at(&vec![0x11_u8; 2 * CLUSTER_SIZE as usize], 0)
+            .unwrap();
+        let image = TempFile::new().unwrap();
+        let path = image.as_path().to_str().unwrap();
+        create_image(path, 1 << 30, Some(backing_path));
+        let mut driver = open_image(path);
+        assert_eq!(driver.backing_size, 2 * CLUSTER_SIZE);
+
+        assert_eq!(read(&mut driver, 1024, 512), vec![0x11_u8; 1024]);
+        // Beyond the end of the backing file.
+        assert_eq!(read(&mut driver, 1024, 2 * CLUSTER_SIZE), vec![0_u8; 1024]);
+
+        // The rest of the cluster is copied from the backing file.
+        write(&mut driver, &[0x22_u8; 512], 1024);
+        let data = read(&mut driver, CLUSTER_SIZE as usize, 0);
+        assert_eq!(data[..1024], vec![0x11_u8; 1024]);
+        assert_eq!(data[1024..1536], vec![0x22_u8; 512]);
+        assert_eq!(data[1536..], vec![0x11_u8; CLUSTER_SIZE as usize - 1536]);
+        // The backing file is not changed.
+        let mut buf = vec![0_u8; 512];
+        backing_file.read_exact_at(&mut buf, 1024).unwrap();
+        assert_eq!(buf, vec![0x11_u8; 512]);
+    }
+
+    #[test]
+    fn test_qcow2_discard_write_zeroes() {
+        let image = TempFile::new().unwrap();
+        let path = image.as_path().to_str().unwrap();
+        create_image(path, 1 << 30, None);
+        let mut driver = open_image(path);
+        write(&mut driver, &vec![0x44_u8; 3 * CLUSTER_SIZE as usize], 0);
+        let host = match driver.get_cluster_state(CLUSTER_SIZE).unwrap() {
+            ClusterState::Normal(host) => host,
+            state => panic!("Unexpected cluster state {:?}", state),
+        };
+
+        // Only the whole cluster is discarded.
+        let reqs = driver.discard(512, 2 * CLUSTER_SIZE).unwrap();
+        assert!(reqs.is_empty());
+        assert_eq!(
+            driver.get_cluster_state(CLUSTER_SIZE).unwrap(),
+            ClusterState::Unallocated
+        );
+        assert_eq!(driver.refcount.get_refcount(host).unwrap(), 0);
+        assert_eq!(read(&mut driver, 512, 0), vec![0x44_u8; 512]);
+        assert_eq!(read(&mut driver, 512, 2 * CLUSTER_SIZE), vec![0x44_u8; 512]);
+
+        // The whole cluster keeps its host cluster without unmap.
+        let host = match driver.get_cluster_state(0).unwrap() {
+            ClusterState::Normal(host) => host,
+            state => panic!("Unexpected cluster state {:?}", state),
+        };
+        driver
+            .write_zeroes(0, 2 * CLUSTER_SIZE + 512, false)
+            .unwrap();
+        assert_eq!(driver.get_cluster_state(0).unwrap(), ClusterState::Zero);
+        assert_eq!(driver.refcount.get_refcount(host).unwrap(), 1);
+        assert_eq!(read(&mut driver, 4096, 0), vec![0_u8; 4096]);
+        assert_eq!(
+            driver.get_cluster_state(CLUSTER_SIZE).unwrap(),
+            ClusterState::Zero
+        );
+        // The partial cluster is filled with zero.
+        let data = read(&mut driver, 1024, 2 * CLUSTER_SIZE);
+        assert_eq!(data[..512], vec![0_u8; 512]);
+        assert_eq!(data[512..], vec![0x44_u8; 512]);
+
+        // The host cluster is released with unmap.
+        driver.write_zeroes(0, CLUSTER_SIZE, true).unwrap();
+        assert_eq!(driver.refcount.get_refcount(host).unwrap(), 0);
+        assert_eq!(read(&mut driver, 4096, 0), vec![0_u8; 4096]);
+
+        // The zero cluster is writable again.
+        write(&mut driver, &[0x55_u8; 512], 512);
+        let data = read(&mut driver, 1024, 0);
+        assert_eq!(data[..512], vec![0_u8; 512]);
+        assert_eq!(data[512..], vec![0x55_u8; 512]);
+    }
+
+    #[test]
+    fn test_qcow2_new_cluster_written_before_l2() {
+        let image = TempFile::new().unwrap();
+        let path = image.as_path().to_str().unwrap();
+        create_image(path, 1 << 30, None);
+        let mut driver = open_image(path);
+
+        // Allocating write is done inside the driver, no host IO is left to the aio.
+        let data = vec![0x66_u8; 512];
+        let reqs = driver.write_vectored(&buf_iov(&data), 1024).unwrap();
+        assert!(reqs.is_empty());
+        let host = match driver.get_cluster_state(0).unwrap() {
+            ClusterState::Normal(host) => host,
+            state => panic!("Unexpected cluster state {:?}", state),
+        };
+        let mut buf = vec![0xff_u8; CLUSTER_SIZE as usize];
+        driver.file.file.read_exact_at(&mut buf, host).unwrap();
+        assert_eq!(buf[..1024], vec![0_u8; 1024]);
+        assert_eq!(buf[1024..1536], data);
+        assert_eq!(buf[1536..], vec![0_u8; CLUSTER_SIZE as usize - 1536]);
+
+        // Write in place is left to the aio.
+        let reqs = driver.write_vectored(&buf_iov(&data), 4096).unwrap();
+        assert_eq!(reqs.len(), 1);
+        assert_eq!(reqs[0].offset, host + 4096);
+
+        // Copy on write has the new cluster filled before the old one is released.
+        driver.create_snapshot("snap1".to_string(), 0).unwrap();
+        let reqs = driver.write_vectored(&buf_iov(&[0x77_u8; 512]), 0).unwrap();
+        assert!(reqs.is_empty());
+        let new_host = match driver.get_cluster_state(0).unwrap() {
+            ClusterState::Normal(host) => host,
+            state => panic!("Unexpected cluster state {:?}", state),
+        };
+        assert_ne!(host, new_host);
+        assert_eq!(driver.refcount.get_refcount(host).unwrap(), 1);
+        driver.file.file.read_exact_at(&mut buf, new_host).unwrap();
+        assert_eq!(buf[..512], vec![0x77_u8; 512]);
+        assert_eq!(buf[512..1024], vec![0_u8; 512]);
+        assert_eq!(buf[1024..1536], data);
+    }
+
+    #[test]
+    fn test_qcow2_internal_snapshot() {
+        let image = TempFile::new().unwrap();
+        let path = image.as_path().to_str().unwrap();
+        create_image(path, 1 << 30, None);
+        let mut driver = open_image(path);
+        write(&mut driver, &[0x33_u8; 4096], 0);
+        let host = match driver.get_cluster_state(0).unwrap() {
+            ClusterState::Normal(host) => host,
+            state => panic!("Unexpected cluster state {:?}", state),
+        };
+
+        driver.create_snapshot("snap1".to_string(), 0).unwrap();
+        assert!(driver.create_snapshot("snap1".to_string(), 0).is_err());
+        assert_eq!(driver.refcount.get_refcount(host).unwrap(), 2);
+
+        // Copy on write.
+        write(&mut driver, &[0x44_u8; 512], 512);
+        let new_host = match driver.get_cluster_state(0).unwrap() {
+            ClusterState::Normal(host) => host,
+            state => panic!("Unexpected cluster state {:?}", state),
+        };
+        assert_ne!(host, new_host);
+        assert_eq!(driver.refcount.get_refcount(host).unwrap(), 1);
+        assert_eq!(driver.refcount.get_refcount(new_host).unwrap(), 1);
+        let data = read(&mut driver, 4096, 0);
+        assert_eq!(data[..512], [0x33_u8; 512]);
+        assert_eq!(data[512..1024], [0x44_u8; 512]);
+
+        // Snapshot table is persistent.
+        drop(driver);
+        let mut driver = open_image(path);
+        let snapshots = driver.list_snapshots();
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].name, "snap1");
+        assert_eq!(snapshots[0].id, "1");
+
+        driver.apply_snapshot("snap1".to_string()).unwrap();
+        assert_eq!(read(&mut driver, 4096, 0), vec![0x33_u8; 4096]);
+        assert_eq!(driver.refcount.get_refcount(new_host).unwrap(), 0);
+        assert_eq!(driver.refcount.get_refcount(host).unwrap(), 2);
+
+        let deleted = driver.delete_snapshot("snap1".to_string()).unwrap();
+        assert_eq!(deleted.name, "snap1");
+        assert!(driver.list_snapshots().is_empty());
+        assert!(driver.delete_snapshot("snap1".to_string()).is_err());
+        assert_eq!(driver.refcount.get_refcount(host).unwrap(), 1);
+        assert_eq!(driver.header.nb_snapshots, 0);
+
+        // Written in place again, as the cluster is not shared anymore.
+        write(&mut driver, &[0x55_u8; 512], 0);
+        assert_eq!(
+            driver.get_cluster_state(0).unwrap(),
+            ClusterState::Normal(host)
+        );
+        let data = read(&mut driver, 1024, 0);
+        assert_eq!(data[..512], [0x55_u8; 512]);
+        assert_eq!(data[512..], [0x33_u8; 512]);
+    }
+}
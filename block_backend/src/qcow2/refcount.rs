@@ -0,0 +1,291 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{bail, Context, Result};
+use byteorder::{BigEndian, ByteOrder};
+
+use super::cache::{CacheTable, Qcow2Cache};
+use super::header::QcowHeader;
+use crate::file::FileDriver;
+
+/// Offset of `refcount_table_offset` field in the qcow2 header.
+const HEADER_REFCOUNT_TABLE_OFFSET: u64 = 48;
+/// Mask of the refcount block offset in refcount table entry.
+const REFCOUNT_TABLE_OFFSET_MASK: u64 = 0xffff_ffff_ffff_fe00;
+/// Max number of refcount blocks cached.
+const REFCOUNT_CACHE_SIZE: usize = 16;
+
+/// Manages the reference count of every host cluster in the image file.
+pub struct RefCount {
+    file: FileDriver,
+    pub refcount_table: Vec<u64>,
+    pub refcount_table_offset: u64,
+    pub refcount_table_clusters: u32,
+    cluster_bits: u32,
+    cluster_size: u64,
+    /// Size of each refcount entry in bytes.
+    entry_size: usize,
+    /// Number of refcount entries in one refcount block.
+    block_entries: u64,
+    refcount_max: u64,
+    cache: Qcow2Cache,
+    /// All clusters before this index are in use.
+    free_cluster_index: u64,
+}
+
+impl RefCount {
+    pub fn new(file: FileDriver, header: &QcowHeader) -> Result<Self> {
+        let cluster_size = header.cluster_size();
+        let table_len = header.refcount_table_clusters as u64 * cluster_size;
+        let mut buf = vec![0_u8; table_len as usize];
+        file.read_at(&mut buf, header.refcount_table_offset)
+            .with_context(|| "Failed to read refcount table")?;
+        let refcount_table = buf.chunks(8).map(BigEndian::read_u64).collect();
+        let refcount_bits = 1_u32 << header.refcount_order;
+        Ok(RefCount {
+            file,
+            refcount_table,
+            refcount_table_offset: header.refcount_table_offset,
+            refcount_table_clusters: header.refcount_table_clusters,
+            cluster_bits: header.cluster_bits,
+            cluster_size,
+            entry_size: (refcount_bits / 8) as usize,
+            block_entries: cluster_size * 8 / refcount_bits as u64,
+            refcount_max: if refcount_bits == 64 {
+                u64::MAX
+            } else {
+                (1 << refcount_bits) - 1
+            },
+            cache: Qcow2Cache::new(REFCOUNT_CACHE_SIZE),
+            free_cluster_index: 0,
+        })
+    }
+
+    fn block_offset(&self, block_index: u64) -> u64 {
+        self.refcount_table
+            .get(block_index as usize)
+            .map_or(0, |entry| entry & REFCOUNT_TABLE_OFFSET_MASK)
+    }
+
+    fn load_block(&mut self, block_offset: u64) -> Result<&mut CacheTable> {
+        if !self.cache.contains(block_offset) {
+            let mut buf = vec![0_u8; self.cluster_size as usize];
+            self.file
+                .read_at(&mut buf, block_offset)
+                .with_context(|| format!("Failed to read refcount block {:#x}", block_offset))?;
+            self.cache
+                .insert(CacheTable::new(block_offset, buf, self.entry_size));
+        }
+        Ok(self.cache.get(block_offset).unwrap())
+    }
+
+    fn get_refcount_by_index(&mut self, cluster_index: u64) -> Result<u64> {
+        let block_offset = self.block_offset(cluster_index / self.block_entries);
+        if block_offset == 0 {
+            return Ok(0);
+        }
+        let idx = (cluster_index % self.block_entries) as usize;
+        Ok(self.load_block(block_offset)?.get_entry(idx))
+    }
+
+    /// Get the refcount of the cluster which contains host `offset`.
+    pub fn get_refcount(&mut self, offset: u64) -> Result<u64> {
+        self.get_refcount_by_index(offset >> self.cluster_bits)
+    }
+
+    /// Find the first position to put `len` continuous clusters from `start`, which
+    /// does not overlap with `reserved` clusters.
+    fn skip_reserved(start: u64, len: u64, reserved: (u64, u64)) -> u64 {
+        if start < reserved.1 && start + len > reserved.0 {
+            reserved.1
+        } else {
+            start
+        }
+    }
+
+    /// Make sure refcount block `block_index` exists, allocate it if not. The new block is
+    /// placed in the range of clusters it describes, all of which are free. Clusters in
+    /// `reserved` are being allocated by the caller and must be skipped.
+    fn ensure_block(&mut self, block_index: u64, reserved: (u64, u64)) -> Result<u64> {
+        let block_offset = self.block_offset(block_index);
+        if block_offset != 0 {
+            return Ok(block_offset);
+        }
+
+        let first = block_index * self.block_entries;
+        let block_cluster = Self::skip_reserved(first, 1, reserved);
+        let need_grow = block_index as usize >= self.refcount_table.len();
+        let mut new_table = Vec::new();
+        let mut table_cluster = 0;
+        let mut table_clusters = 0;
+        if need_grow {
+            let entries_per_cluster = self.cluster_size / 8;
+            table_clusters = std::cmp::max(
+                (block_index + 1 + entries_per_cluster - 1) / entries_per_cluster,
+                self.refcount_table_clusters as u64 * 2,
+            );
+            table_cluster = Self::skip_reserved(block_cluster + 1, table_clusters, reserved);
+            new_table = self.refcount_table.clone();
+            new_table.resize((table_clusters * entries_per_cluster) as usize, 0);
+        }
+        let last = if need_grow {
+            table_cluster + table_clusters
+        } else {
+            block_cluster + 1
+        };
+        if last > first + self.block_entries {
+            bail!("No space for refcount block {}", block_index);
+        }
+
+        // Write the new block, with the refcount of itself and the new table.
+        let mut block = CacheTable::new(
+            block_cluster << self.cluster_bits,
+            vec![0_u8; self.cluster_size as usize],
+            self.entry_size,
+        );
+        block.set_entry((block_cluster - first) as usize, 1);
+        for i in 0..table_clusters {
+            block.set_entry((table_cluster + i - first) as usize, 1);
+        }
+        self.file
+            .write_at(block.as_bytes(), block.addr)
+            .with_context(|| "Failed to write new refcount block")?;
+        let block_offset = block.addr;
+        self.cache.insert(block);
+
+        if !need_grow {
+            self.refcount_table[block_index as usize] = block_offset;
+            let mut buf = [0_u8; 8];
+            BigEndian::write_u64(&mut buf, block_offset);
+            self.file
+                .write_at(&buf, self.refcount_table_offset + block_index * 8)
+                .with_context(|| "Failed to update refcount table")?;
+            return Ok(block_offset);
+        }
+
+        // Write the grown table, and switch the header to it.
+        new_table[block_index as usize] = block_offset;
+        let mut buf = vec![0_u8; new_table.len() * 8];
+        for (i, entry) in new_table.iter().enumerate() {
+            BigEndian::write_u64(&mut buf[i * 8..(i + 1) * 8], *entry);
+        }
+        let new_table_offset = table_cluster << self.cluster_bits;
+        self.file
+            .write_at(&buf, new_table_offset)
+            .with_context(|| "Failed to write new refcount table")?;
+        let mut header_buf = [0_u8; 12];
+        BigEndian::write_u64(&mut header_buf[0..8], new_table_offset);
+        BigEndian::write_u32(&mut header_buf[8..12], table_clusters as u32);
+        self.file
+            .write_at(&header_buf, HEADER_REFCOUNT_TABLE_OFFSET)
+            .with_context(|| "Failed to update refcount table in header")?;
+
+        let old_offset = self.refcount_table_offset;
+        let old_clusters = self.refcount_table_clusters as u64;
+        self.refcount_table = new_table;
+        self.refcount_table_offset = new_table_offset;
+        self.refcount_table_clusters = table_clusters as u32;
+        self.update_refcount_internal(old_offset, old_clusters, -1, reserved)?;
+        Ok(block_offset)
+    }
+
+    fn update_refcount_internal(
+        &mut self,
+        offset: u64,
+        clusters: u64,
+        addend: i64,
+        reserved: (u64, u64),
+    ) -> Result<()> {
+        let first_index = offset >> self.cluster_bits;
+        for cluster_index in first_index..first_index + clusters {
+            let block_index = cluster_index / self.block_entries;
+            let block_offset = if addend > 0 {
+                self.ensure_block(block_index, reserved)?
+            } else {
+                self.block_offset(block_index)
+            };
+            if block_offset == 0 {
+                bail!(
+                    "Refcount block of cluster {:#x} does not exist",
+                    cluster_index << self.cluster_bits
+                );
+            }
+            let idx = (cluster_index % self.block_entries) as usize;
+            let refcount_max = self.refcount_max;
+            let cluster_offset = cluster_index << self.cluster_bits;
+            let block = self.load_block(block_offset)?;
+            let old = block.get_entry(idx);
+            let new = if addend >= 0 {
+                old.checked_add(addend as u64)
+                    .filter(|&v| v <= refcount_max)
+            } else {
+                old.checked_sub(addend.unsigned_abs())
+            };
+            let new = new.with_context(|| {
+                format!(
+                    "Invalid refcount of cluster {:#x}: {} + {}",
+                    cluster_offset, old, addend
+                )
+            })?;
+            block.set_entry(idx, new);
+            let (bytes, entry_offset) = block.entry_bytes(idx);
+            let bytes = bytes.to_vec();
+            self.file
+                .write_at(&bytes, block_offset + entry_offset)
+                .with_context(|| "Failed to update refcount")?;
+            if new == 0 && cluster_index < self.free_cluster_index {
+                self.free_cluster_index = cluster_index;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add `addend` to the refcount of `clusters` clusters from host `offset`.
+    pub fn update_refcount(&mut self, offset: u64, clusters: u64, addend: i64) -> Result<()> {
+        let first_index = offset >> self.cluster_bits;
+        self.update_refcount_internal(
+            offset,
+            clusters,
+            addend,
+            (first_index, first_index + clusters),
+        )
+    }
+
+    /// Allocate continuous clusters for `size` bytes, and return the host offset.
+    pub fn alloc_clusters(&mut self, size: u64) -> Result<u64> {
+        let clusters = std::cmp::max((size + self.cluster_size - 1) >> self.cluster_bits, 1);
+        let mut start = self.free_cluster_index;
+        let mut idx = 0;
+        while idx < clusters {
+            if self.get_refcount_by_index(start + idx)? != 0 {
+                start += idx + 1;
+                idx = 0;
+            } else {
+                idx += 1;
+            }
+        }
+        // Clusters before `start` are all in use now, unless some are freed below.
+        let free_cluster_index = self.free_cluster_index;
+        let offset = start << self.cluster_bits;
+        self.update_refcount(offset, clusters, 1)?;
+        if self.free_cluster_index == free_cluster_index {
+            self.free_cluster_index = start + clusters;
+        }
+        Ok(offset)
+    }
+
+    /// Drop the reference of clusters which hold `size` bytes from host `offset`.
+    pub fn free_clusters(&mut self, offset: u64, size: u64) -> Result<()> {
+        let clusters = (size + self.cluster_size - 1) >> self.cluster_bits;
+        self.update_refcount(offset, clusters, -1)
+    }
+}
@@ -0,0 +1,182 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{bail, Result};
+use byteorder::{BigEndian, ByteOrder};
+
+/// Length of the fixed part of a snapshot table entry.
+const SNAPSHOT_FIXED_LENGTH: usize = 40;
+/// Length of the extra data which is required by qcow2 version 3.
+const SNAPSHOT_EXTRA_DATA_LENGTH: usize = 16;
+/// Max number of internal snapshots.
+pub const MAX_SNAPSHOTS: usize = 65536;
+
+/// Entry of the internal snapshot table.
+#[derive(Clone, Debug, Default)]
+pub struct QcowSnapshot {
+    pub l1_table_offset: u64,
+    pub l1_size: u32,
+    pub id: String,
+    pub name: String,
+    pub date_sec: u32,
+    pub date_nsec: u32,
+    pub vm_clock_nsec: u64,
+    pub vm_state_size: u32,
+    /// Virtual disk size when the snapshot is taken.
+    pub disk_size: u64,
+    /// Extra data which is not known by us, kept as is.
+    pub extra_data: Vec<u8>,
+}
+
+impl QcowSnapshot {
+    fn entry_len(&self) -> usize {
+        let len = SNAPSHOT_FIXED_LENGTH + self.extra_len() + self.id.len() + self.name.len();
+        (len + 7) & !7
+    }
+
+    fn extra_len(&self) -> usize {
+        std::cmp::max(self.extra_data.len(), SNAPSHOT_EXTRA_DATA_LENGTH)
+    }
+
+    fn to_bytes(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        buf.resize(start + self.entry_len(), 0);
+        let entry = &mut buf[start..];
+        BigEndian::write_u64(&mut entry[0..8], self.l1_table_offset);
+        BigEndian::write_u32(&mut entry[8..12], self.l1_size);
+        BigEndian::write_u16(&mut entry[12..14], self.id.len() as u16);
+        BigEndian::write_u16(&mut entry[14..16], self.name.len() as u16);
+        BigEndian::write_u32(&mut entry[16..20], self.date_sec);
+        BigEndian::write_u32(&mut entry[20..24], self.date_nsec);
+        BigEndian::write_u64(&mut entry[24..32], self.vm_clock_nsec);
+        BigEndian::write_u32(&mut entry[32..36], self.vm_state_size);
+        BigEndian::write_u32(&mut entry[36..40], self.extra_len() as u32);
+
+        let mut pos = SNAPSHOT_FIXED_LENGTH;
+        let extra = &mut entry[pos..pos + self.extra_len()];
+        extra[..self.extra_data.len()].copy_from_slice(&self.extra_data);
+        BigEndian::write_u64(&mut extra[0..8], self.vm_state_size as u64);
+        BigEndian::write_u64(&mut extra[8..16], self.disk_size);
+        pos += self.extra_len();
+        entry[pos..pos + self.id.len()].copy_from_slice(self.id.as_bytes());
+        pos += self.id.len();
+        entry[pos..pos + self.name.len()].copy_from_slice(self.name.as_bytes());
+    }
+
+    fn from_bytes(buf: &[u8], default_disk_size: u64) -> Result<(Self, usize)> {
+        if buf.len() < SNAPSHOT_FIXED_LENGTH {
+            bail!("Snapshot table is truncated");
+        }
+        let id_len = BigEndian::read_u16(&buf[12..14]) as usize;
+        let name_len = BigEndian::read_u16(&buf[14..16]) as usize;
+        let extra_len = BigEndian::read_u32(&buf[36..40]) as usize;
+        let len = SNAPSHOT_FIXED_LENGTH + extra_len + id_len + name_len;
+        if buf.len() < len {
+            bail!("Snapshot table is truncated");
+        }
+        let mut pos = SNAPSHOT_FIXED_LENGTH;
+        let extra_data = buf[pos..pos + extra_len].to_vec();
+        pos += extra_len;
+        let id = String::from_utf8_lossy(&buf[pos..pos + id_len]).to_string();
+        pos += id_len;
+        let name = String::from_utf8_lossy(&buf[pos..pos + name_len]).to_string();
+
+        let disk_size = if extra_len >= SNAPSHOT_EXTRA_DATA_LENGTH {
+            BigEndian::read_u64(&extra_data[8..16])
+        } else {
+            default_disk_size
+        };
+        let snapshot = QcowSnapshot {
+            l1_table_offset: BigEndian::read_u64(&buf[0..8]),
+            l1_size: BigEndian::read_u32(&buf[8..12]),
+            id,
+            name,
+            date_sec: BigEndian::read_u32(&buf[16..20]),
+            date_nsec: BigEndian::read_u32(&buf[20..24]),
+            vm_clock_nsec: BigEndian::read_u64(&buf[24..32]),
+            vm_state_size: BigEndian::read_u32(&buf[32..36]),
+            disk_size,
+            extra_data,
+        };
+        Ok((snapshot, (len + 7) & !7))
+    }
+}
+
+/// Parse `nb_snapshots` entries of the snapshot table.
+pub fn parse_snapshot_table(
+    buf: &[u8],
+    nb_snapshots: u32,
+    disk_size: u64,
+) -> Result<Vec<QcowSnapshot>> {
+    if nb_snapshots as usize > MAX_SNAPSHOTS {
+        bail!("Too many snapshots {}", nb_snapshots);
+    }
+    let mut snapshots = Vec::with_capacity(nb_snapshots as usize);
+    let mut pos = 0;
+    for _ in 0..nb_snapshots {
+        let (snapshot, len) = QcowSnapshot::from_bytes(&buf[pos..], disk_size)?;
+        snapshots.push(snapshot);
+        pos += len;
+    }
+    Ok(snapshots)
+}
+
+/// Serialize the whole snapshot table.
+pub fn snapshot_table_to_bytes(snapshots: &[QcowSnapshot]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for snapshot in snapshots {
+        snapshot.to_bytes(&mut buf);
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_table() {
+        let snapshots = vec![
+            QcowSnapshot {
+                l1_table_offset: 0x50000,
+                l1_size: 2,
+                id: "1".to_string(),
+                name: "base".to_string(),
+                date_sec: 100,
+                disk_size: 1 << 30,
+                ..Default::default()
+            },
+            QcowSnapshot {
+                l1_table_offset: 0x60000,
+                l1_size: 2,
+                id: "2".to_string(),
+                name: "snapshot-with-long-name".to_string(),
+                vm_clock_nsec: 123,
+                disk_size: 1 << 31,
+                ..Default::default()
+            },
+        ];
+        let buf = snapshot_table_to_bytes(&snapshots);
+        assert_eq!(buf.len() % 8, 0);
+        let parsed = parse_snapshot_table(&buf, 2, 0).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].name, "base");
+        assert_eq!(parsed[0].disk_size, 1 << 30);
+        assert_eq!(parsed[1].id, "2");
+        assert_eq!(parsed[1].name, "snapshot-with-long-name");
+        assert_eq!(parsed[1].l1_table_offset, 0x60000);
+        assert_eq!(parsed[1].vm_clock_nsec, 123);
+        assert_eq!(parsed[1].disk_size, 1 << 31);
+
+        assert!(parse_snapshot_table(&buf, 3, 0).is_err());
+    }
+}
@@ -0,0 +1,67 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::Result;
+
+use crate::file::FileDriver;
+use crate::{iov_len, BlockDriverOps};
+use util::aio::{CombineRequest, Iovec};
+
+/// Raw image, guest offset is the same as host offset.
+pub struct RawDriver {
+    file: FileDriver,
+}
+
+impl RawDriver {
+    pub fn new(file: FileDriver) -> Self {
+        RawDriver { file }
+    }
+}
+
+impl BlockDriverOps for RawDriver {
+    fn disk_size(&mut self) -> Result<u64> {
+        self.file.disk_size()
+    }
+
+    fn read_vectored(&mut self, iov: &[Iovec], offset: u64) -> Result<Vec<CombineRequest>> {
+        Ok(vec![CombineRequest::new(
+            iov.to_vec(),
+            offset,
+            iov_len(iov),
+        )])
+    }
+
+    fn write_vectored(&mut self, iov: &[Iovec], offset: u64) -> Result<Vec<CombineRequest>> {
+        Ok(vec![CombineRequest::new(
+            iov.to_vec(),
+            offset,
+            iov_len(iov),
+        )])
+    }
+
+    fn discard(&mut self, offset: u64, nbytes: u64) -> Result<Vec<CombineRequest>> {
+        Ok(vec![CombineRequest::new(Vec::new(), offset, nbytes)])
+    }
+
+    fn write_zeroes(
+        &mut self,
+        offset: u64,
+        nbytes: u64,
+        _unmap: bool,
+    ) -> Result<Vec<CombineRequest>> {
+        Ok(vec![CombineRequest::new(Vec::new(), offset, nbytes)])
+    }
+
+    fn read_at(&mut self, buf: &mut [u8], offset: u64) -> Result<()> {
+        self.file.read_at(buf, offset)
+    }
+}
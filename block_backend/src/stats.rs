@@ -0,0 +1,327 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Accounting of the block requests and the drive registry used by the
+//! `query-block`, `query-blockstats` and `query-named-block-nodes` QMP commands.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use once_cell::sync::Lazy;
+
+use machine_manager::config::DiskFormat;
+use machine_manager::qmp::qmp_schema::{
+    BlockDeviceInfo, BlockDeviceStats, BlockInfo, BlockStats, BlockdevCacheInfo,
+};
+use util::aio::{AioEngine, WriteZeroesState};
+
+/// Type of an accounted block request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockAcctType {
+    Read,
+    Write,
+    Flush,
+    Discard,
+}
+
+/// Counters of one type of block requests.
+#[derive(Debug, Default, Clone, Copy)]
+struct BlockAcctCounter {
+    bytes: u64,
+    ops: u64,
+    failed_ops: u64,
+    total_time_ns: u64,
+    in_flight: u64,
+}
+
+/// IO statistics of a block drive.
+#[derive(Debug, Default, Clone)]
+pub struct BlockAcctStats {
+    counters: [BlockAcctCounter; 4],
+}
+
+impl BlockAcctStats {
+    fn counter(&mut self, acct_type: BlockAcctType) -> &mut BlockAcctCounter {
+        &mut self.counters[acct_type as usize]
+    }
+
+    fn to_qmp(&self) -> BlockDeviceStats {
+        let rd = &self.counters[BlockAcctType::Read as usize];
+        let wr = &self.counters[BlockAcctType::Write as usize];
+        let flush = &self.counters[BlockAcctType::Flush as usize];
+        let unmap = &self.counters[BlockAcctType::Discard as usize];
+        BlockDeviceStats {
+            rd_bytes: rd.bytes,
+            wr_bytes: wr.bytes,
+            unmap_bytes: unmap.bytes,
+            rd_operations: rd.ops,
+            wr_operations: wr.ops,
+            flush_operations: flush.ops,
+            unmap_operations: unmap.ops,
+            rd_total_time_ns: rd.total_time_ns,
+            wr_total_time_ns: wr.total_time_ns,
+            flush_total_time_ns: flush.total_time_ns,
+            unmap_total_time_ns: unmap.total_time_ns,
+            failed_rd_operations: rd.failed_ops,
+            failed_wr_operations: wr.failed_ops,
+            failed_flush_operations: flush.failed_ops,
+            failed_unmap_operations: unmap.failed_ops,
+            rd_in_flight: rd.in_flight,
+            wr_in_flight: wr.in_flight,
+            flush_in_flight: flush.in_flight,
+            unmap_in_flight: unmap.in_flight,
+        }
+    }
+}
+
+pub type BlockStatsRef = Arc<Mutex<BlockAcctStats>>;
+
+/// An in flight block request being accounted, created by `block_acct_start`.
+#[derive(Clone)]
+pub struct BlockAcctCookie {
+    stats: BlockStatsRef,
+    acct_type: BlockAcctType,
+    bytes: u64,
+    /// Number of the guest requests, more than one if the requests are merged.
+    ops: u64,
+    start: Instant,
+}
+
+impl BlockAcctCookie {
+    /// Account the completion of the request.
+    pub fn done(&self, failed: bool) {
+        let elapsed = self.start.elapsed().as_nanos() as u64;
+        let mut locked_stats = self.stats.lock().unwrap();
+        let counter = locked_stats.counter(self.acct_type);
+        counter.in_flight = counter.in_flight.saturating_sub(self.ops);
+        if failed {
+            counter.failed_ops += self.ops;
+        } else {
+            counter.ops += self.ops;
+            counter.bytes += self.bytes;
+            counter.total_time_ns += elapsed;
+        }
+    }
+
+    /// Drop the accounting of the request which is not completed, such as the
+    /// request kept to be submitted again.
+    pub fn cancel(&self) {
+        let mut locked_stats = self.stats.lock().unwrap();
+        let counter = locked_stats.counter(self.acct_type);
+        counter.in_flight = counter.in_flight.saturating_sub(self.ops);
+    }
+}
+
+/// Start accounting `ops` guest requests of `bytes` in total.
+pub fn block_acct_start(
+    stats: &BlockStatsRef,
+    acct_type: BlockAcctType,
+    bytes: u64,
+    ops: u64,
+) -> BlockAcctCookie {
+    stats.lock().unwrap().counter(acct_type).in_flight += ops;
+    BlockAcctCookie {
+        stats: stats.clone(),
+        acct_type,
+        bytes,
+        ops,
+        start: Instant::now(),
+    }
+}
+
+/// Properties of a block drive reported by QMP.
+#[derive(Debug, Clone)]
+pub struct BlockDriveInfo {
+    /// Id of the device which the drive is attached to.
+    pub id: String,
+    pub file: String,
+    pub format: DiskFormat,
+    pub read_only: bool,
+    pub direct: bool,
+    pub aio: AioEngine,
+    pub iops: Option<u64>,
+    pub write_zeroes: WriteZeroesState,
+}
+
+impl BlockDriveInfo {
+    fn to_qmp(&self) -> BlockDeviceInfo {
+        BlockDeviceInfo {
+            node_name: self.id.clone(),
+            file: self.file.clone(),
+            ro: self.read_only,
+            drv: match self.format {
+                DiskFormat::Raw => "raw",
+                DiskFormat::Qcow2 => "qcow2",
+            }
+            .to_string(),
+            encrypted: false,
+            detect_zeroes: match self.write_zeroes {
+                WriteZeroesState::Off => "off",
+                WriteZeroesState::On => "on",
+                WriteZeroesState::Unmap => "unmap",
+            }
+            .to_string(),
+            aio: match self.aio {
+                AioEngine::Off => "off",
+                AioEngine::Native => "native",
+                AioEngine::IoUring => "io_uring",
+            }
+            .to_string(),
+            iops: self.iops.unwrap_or(0),
+            cache: BlockdevCacheInfo {
+                writeback: true,
+                direct: self.direct,
+                no_flush: false,
+            },
+        }
+    }
+}
+
+static BLOCK_DRIVES: Lazy<Mutex<BTreeMap<String, (BlockDriveInfo, BlockStatsRef)>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// Register the drive of device `info.id` with its statistics.
+pub fn register_block_drive(info: BlockDriveInfo, stats: BlockStatsRef) {
+    BLOCK_DRIVES
+        .lock()
+        .unwrap()
+        .insert(info.id.clone(), (info, stats));
+}
+
+pub fn unregister_block_drive(id: &str) {
+    BLOCK_DRIVES.lock().unwrap().remove(id);
+}
+
+/// Get the information of all registered drives, in order of the device id.
+pub fn query_block() -> Vec<BlockInfo> {
+    BLOCK_DRIVES
+        .lock()
+        .unwrap()
+        .values()
+        .map(|(info, _)| BlockInfo {
+            device: info.id.clone(),
+            qdev: info.id.clone(),
+            block_type: "unknown".to_string(),
+            removable: false,
+            locked: false,
+            inserted: Some(info.to_qmp()),
+        })
+        .collect()
+}
+
+/// Get the IO statistics of all registered drives, in order of the device id.
+pub fn query_blockstats() -> Vec<BlockStats> {
+    BLOCK_DRIVES
+        .lock()
+        .unwrap()
+        .values()
+        .map(|(info, stats)| BlockStats {
+            device: info.id.clone(),
+            qdev: info.id.clone(),
+            node_name: info.id.clone(),
+            stats: stats.lock().unwrap().to_qmp(),
+        })
+        .collect()
+}
+
+/// Get the information of all registered drive nodes, in order of the node name.
+pub fn query_named_block_nodes() -> Vec<BlockDeviceInfo> {
+    BLOCK_DRIVES
+        .lock()
+        .unwrap()
+        .values()
+        .map(|(info, _)| info.to_qmp())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_acct() {
+        let stats: BlockStatsRef = Arc::new(Mutex::new(BlockAcctStats::default()));
+        let read = block_acct_start(&stats, BlockAcctType::Read, 4096, 2);
+        let write = block_acct_start(&stats, BlockAcctType::Write, 512, 1);
+        let flush = block_acct_start(&stats, BlockAcctType::Flush, 0, 1);
+        let qmp_stats = stats.lock().unwrap().to_qmp();
+        assert_eq!(qmp_stats.rd_in_flight, 2);
+        assert_eq!(qmp_stats.wr_in_flight, 1);
+        assert_eq!(qmp_stats.flush_in_flight, 1);
+        assert_eq!(qmp_stats.rd_operations, 0);
+
+        read.done(false);
+        write.done(true);
+        flush.done(false);
+        let qmp_stats = stats.lock().unwrap().to_qmp();
+        assert_eq!(qmp_stats.rd_in_flight, 0);
+        assert_eq!(qmp_stats.wr_in_flight, 0);
+        assert_eq!(qmp_stats.flush_in_flight, 0);
+        assert_eq!(qmp_stats.rd_operations, 2);
+        assert_eq!(qmp_stats.rd_bytes, 4096);
+        assert_eq!(qmp_stats.wr_operations, 0);
+        assert_eq!(qmp_stats.wr_bytes, 0);
+        assert_eq!(qmp_stats.failed_wr_operations, 1);
+        assert_eq!(qmp_stats.flush_operations, 1);
+
+        // The canceled request is neither done nor failed.
+        block_acct_start(&stats, BlockAcctType::Write, 512, 1).cancel();
+        let qmp_stats = stats.lock().unwrap().to_qmp();
+        assert_eq!(qmp_stats.wr_in_flight, 0);
+        assert_eq!(qmp_stats.wr_operations, 0);
+        assert_eq!(qmp_stats.failed_wr_operations, 1);
+    }
+
+    #[test]
+    fn test_block_drive_registry() {
+        let info = BlockDriveInfo {
+            id: "test-drive".to_string(),
+            file: "/path/to/disk.img".to_string(),
+            format: DiskFormat::Qcow2,
+            read_only: true,
+            direct: false,
+            aio: AioEngine::Off,
+            iops: Some(100),
+            write_zeroes: WriteZeroesState::Unmap,
+        };
+        let stats: BlockStatsRef = Arc::new(Mutex::new(BlockAcctStats::default()));
+        register_block_drive(info, stats.clone());
+        block_acct_start(&stats, BlockAcctType::Discard, 1024, 1).done(false);
+
+        let block = query_block();
+        let block = block.iter().find(|b| b.device == "test-drive").unwrap();
+        let inserted = block.inserted.as_ref().unwrap();
+        assert_eq!(inserted.node_name, "test-drive");
+        assert_eq!(inserted.file, "/path/to/disk.img");
+        assert!(inserted.ro);
+        assert_eq!(inserted.drv, "qcow2");
+        assert_eq!(inserted.aio, "off");
+        assert_eq!(inserted.detect_zeroes, "unmap");
+        assert_eq!(inserted.iops, 100);
+        assert!(!inserted.cache.direct);
+
+        let blockstats = query_blockstats();
+        let blockstats = blockstats
+            .iter()
+            .find(|s| s.node_name == "test-drive")
+            .unwrap();
+        assert_eq!(blockstats.stats.unmap_operations, 1);
+        assert_eq!(blockstats.stats.unmap_bytes, 1024);
+        assert!(query_named_block_nodes()
+            .iter()
+            .any(|n| n.node_name == "test-drive"));
+
+        unregister_block_drive("test-drive");
+        assert!(!query_block().iter().any(|b| b.device == "test-drive"));
+    }
+}
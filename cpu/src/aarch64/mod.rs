@@ -352,4 +352,9 @@ impl StateTransfer for CPU {
     }
 }
 
-impl MigrationHook for CPU {}
+impl MigrationHook for CPU {
+    fn set_throttle(&self, percentage: u8) -> Result<()> {
+        self.set_throttle_percentage(percentage);
+        Ok(())
+    }
+}
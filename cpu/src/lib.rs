@@ -63,10 +63,10 @@ pub use x86_64::X86CPUState as ArchCPU;
 pub use x86_64::X86CPUTopology as CPUTopology;
 
 use std::cell::RefCell;
-use std::sync::atomic::{fence, AtomicBool, Ordering};
+use std::sync::atomic::{fence, AtomicBool, AtomicU8, Ordering};
 use std::sync::{Arc, Barrier, Condvar, Mutex, Weak};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use kvm_ioctls::{VcpuExit, VcpuFd};
 use libc::{c_int, c_void, siginfo_t};
@@ -90,6 +90,10 @@ const VCPU_TASK_SIGNAL: i32 = 35;
 const VCPU_RESET_SIGNAL: i32 = 35;
 #[cfg(target_env = "musl")]
 const VCPU_RESET_SIGNAL: i32 = 36;
+#[cfg(not(target_env = "musl"))]
+const VCPU_THROTTLE_SIGNAL: i32 = 36;
+#[cfg(target_env = "musl")]
+const VCPU_THROTTLE_SIGNAL: i32 = 37;
 
 /// Watch `0x3ff` IO port to record the magic value trapped from guest kernel.
 #[cfg(all(target_arch = "x86_64", feature = "boot_time"))]
@@ -103,6 +107,8 @@ const MAGIC_VALUE_SIGNAL_GUEST_BOOT_START: u8 = 0x01;
 /// The boot complete value can be verified before init guest userspace.
 #[cfg(feature = "boot_time")]
 const MAGIC_VALUE_SIGNAL_GUEST_BOOT_COMPLETE: u8 = 0x02;
+/// Time slice of vCPU running before it is throttled.
+const CPU_THROTTLE_TIMESLICE: Duration = Duration::from_millis(10);
 
 /// State for `CPU` lifecycle.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -191,6 +197,10 @@ pub struct CPU {
     boot_state: Arc<Mutex<ArchCPU>>,
     /// Sync the pause state of vCPU in kvm and userspace.
     pause_signal: Arc<AtomicBool>,
+    /// Percentage of time this vCPU sleeps, used to converge live migration.
+    throttle_percentage: Arc<AtomicU8>,
+    /// Whether the thread kicking this vCPU out of kvm to be throttled is running.
+    throttle_kicker: Arc<AtomicBool>,
 }
 
 impl CPU {
@@ -219,6 +229,67 @@ impl CPU {
             caps: CPUCaps::init_capabilities(),
             boot_state: Arc::new(Mutex::new(ArchCPU::default())),
             pause_signal: Arc::new(AtomicBool::new(false)),
+            throttle_percentage: Arc::new(AtomicU8::new(0)),
+            throttle_kicker: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Throttle this `CPU` to sleep `percentage` of time, 0 means no throttle.
+    ///
+    /// A vCPU only gets throttled when it exits from kvm, so a thread is started
+    /// to kick it out of kvm every time slice until the throttle is removed.
+    pub fn set_throttle_percentage(&self, percentage: u8) {
+        self.throttle_percentage
+            .store(percentage.min(99), Ordering::SeqCst);
+        if percentage == 0
+            || self
+                .throttle_kicker
+                .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+                .is_err()
+        {
+            return;
+        }
+
+        let task = self.task.clone();
+        let throttle_percentage = self.throttle_percentage.clone();
+        let throttle_kicker = self.throttle_kicker.clone();
+        if let Err(e) = thread::Builder::new()
+            .name(format!("CPU {} throttle", self.id))
+            .spawn(move || Self::kick_throttled(task, throttle_percentage, throttle_kicker))
+        {
+            error!(
+                "Failed to start throttle thread of vcpu{}: {:?}",
+                self.id, e
+            );
+            self.throttle_kicker.store(false, Ordering::SeqCst);
+        }
+    }
+
+    /// Kick the vCPU thread out of kvm every time slice while it is throttled.
+    fn kick_throttled(
+        task: Arc<Mutex<Option<thread::JoinHandle<()>>>>,
+        throttle_percentage: Arc<AtomicU8>,
+        throttle_kicker: Arc<AtomicBool>,
+    ) {
+        loop {
+            thread::sleep(CPU_THROTTLE_TIMESLICE);
+            if throttle_percentage.load(Ordering::SeqCst) == 0 {
+                throttle_kicker.store(false, Ordering::SeqCst);
+                // Keep kicking if it is throttled again before the flag is cleared.
+                if throttle_percentage.load(Ordering::SeqCst) == 0
+                    || throttle_kicker
+                        .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+                        .is_err()
+                {
+                    return;
+                }
+            }
+
+            if let Some(thread) = task.lock().unwrap().as_ref() {
+                if let Err(e) = thread.kill(VCPU_THROTTLE_SIGNAL) {
+                    warn!("Failed to kick vcpu to be throttled: {:?}", e);
+                }
+            }
         }
     }
 
@@ -625,6 +696,12 @@ impl CPUThreadWorker {
                         fence(Ordering::Release)
                     });
                 }
+                VCPU_THROTTLE_SIGNAL => {
+                    // Only exit from kvm, the vcpu sleeps in `throttle_vcpu`.
+                    let _ = CPUThreadWorker::run_on_local_thread_vcpu(|vcpu| {
+                        vcpu.fd().set_kvm_immediate_exit(1);
+                    });
+                }
                 VCPU_RESET_SIGNAL => {
                     let _ = CPUThreadWorker::run_on_local_thread_vcpu(|vcpu| {
                         if let Err(e) = vcpu.arch_cpu.lock().unwrap().reset_vcpu(
@@ -644,6 +721,8 @@ impl CPUThreadWorker {
             .with_context(|| "Failed to register VCPU_TASK_SIGNAL signal.")?;
         register_signal_handler(VCPU_RESET_SIGNAL, handle_signal)
             .with_context(|| "Failed to register VCPU_TASK_SIGNAL signal.")?;
+        register_signal_handler(VCPU_THROTTLE_SIGNAL, handle_signal)
+            .with_context(|| "Failed to register VCPU_THROTTLE_SIGNAL signal.")?;
 
         Ok(())
     }
@@ -678,6 +757,26 @@ impl CPUThreadWorker {
         }
     }
 
+    /// Sleep for a while if the vcpu has run a whole time slice and it is
+    /// throttled. The sleep time is in proportion to the time it has run.
+    ///
+    /// # Arguments
+    ///
+    /// * `run_start` - The time this vcpu starts running after last sleep.
+    fn throttle_vcpu(&self, run_start: &mut Instant) {
+        let percentage = self.thread_cpu.throttle_percentage.load(Ordering::SeqCst) as u32;
+        if percentage == 0 {
+            *run_start = Instant::now();
+            return;
+        }
+        if run_start.elapsed() < CPU_THROTTLE_TIMESLICE {
+            return;
+        }
+
+        thread::sleep(run_start.elapsed() * percentage / (100 - percentage));
+        *run_start = Instant::now();
+    }
+
     /// Handle the all events in vcpu thread.
     fn handle(&self, thread_barrier: Arc<Barrier>) -> Result<()> {
         self.init_local_thread_vcpu();
@@ -709,7 +808,9 @@ impl CPUThreadWorker {
         thread_barrier.wait();
 
         info!("vcpu{} start running", self.thread_cpu.id);
+        let mut run_start = Instant::now();
         while let Ok(true) = self.ready_for_running() {
+            self.throttle_vcpu(&mut run_start);
             #[cfg(not(test))]
             {
                 if is_test_enabled() {
@@ -1017,6 +1118,117 @@ mod tests {
         drop(cpu_state);
     }
 
+    #[test]
+    #[serial]
+    #[cfg(target_arch = "x86_64")]
+    fn test_cpu_throttle() {
+        use std::sync::atomic::AtomicU32;
+
+        let kvm_fds = KVMFds::new();
+        if kvm_fds.vm_fd.is_none() {
+            return;
+        }
+        KVM_FDS.store(Arc::new(kvm_fds));
+
+        // The guest spins on `jmp $` at 0x1000 in real mode, so the vcpu never exits
+        // from kvm unless it is kicked.
+        let mem_size = 0x2000_u64;
+        // SAFETY: The anonymous mapping is checked and only used as the guest memory.
+        let host_addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                mem_size as usize,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_ANONYMOUS | libc::MAP_PRIVATE,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(host_addr, libc::MAP_FAILED);
+        // SAFETY: 0x1000 is inside the mapping above.
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                [0xeb_u8, 0xfe].as_ptr(),
+                host_addr.add(0x1000) as *mut u8,
+                2,
+            )
+        };
+        let region = kvm_bindings::kvm_userspace_memory_region {
+            slot: 0,
+            flags: 0,
+            guest_phys_addr: 0,
+            memory_size: mem_size,
+            userspace_addr: host_addr as u64,
+        };
+        let kvm_fds = KVM_FDS.load();
+        let vm_fd = kvm_fds.vm_fd.as_ref().unwrap();
+        // SAFETY: The guest memory is mapped above and is never unmapped while the vm exists.
+        unsafe { vm_fd.set_user_memory_region(region).unwrap() };
+
+        let vcpu_fd = vm_fd.create_vcpu(0).unwrap();
+        let mut sregs = vcpu_fd.get_sregs().unwrap();
+        sregs.cs.base = 0;
+        sregs.cs.selector = 0;
+        vcpu_fd.set_sregs(&sregs).unwrap();
+        let mut regs = vcpu_fd.get_regs().unwrap();
+        regs.rip = 0x1000;
+        regs.rflags = 0x2;
+        vcpu_fd.set_regs(&regs).unwrap();
+
+        let vm = Arc::new(Mutex::new(TestVm::new()));
+        let cpu = Arc::new(CPU::new(
+            Arc::new(vcpu_fd),
+            0,
+            Arc::new(Mutex::new(ArchCPU::default())),
+            vm.clone(),
+        ));
+
+        let exits = Arc::new(AtomicU32::new(0));
+        let slept_ms = Arc::new(AtomicU32::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (thread_cpu, thread_exits, thread_slept, thread_stop) =
+            (cpu.clone(), exits.clone(), slept_ms.clone(), stop.clone());
+        let task = thread::spawn(move || {
+            let worker = CPUThreadWorker::new(thread_cpu);
+            worker.init_local_thread_vcpu();
+            CPUThreadWorker::init_signals().unwrap();
+            let mut run_start = Instant::now();
+            while !thread_stop.load(Ordering::SeqCst) {
+                let sleep_start = Instant::now();
+                worker.throttle_vcpu(&mut run_start);
+                thread_slept.fetch_add(sleep_start.elapsed().as_millis() as u32, Ordering::SeqCst);
+                assert!(worker.thread_cpu.kvm_vcpu_exec().unwrap());
+                thread_exits.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        *cpu.task.lock().unwrap() = Some(task);
+
+        // Not throttled, the vcpu keeps running in kvm.
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(exits.load(Ordering::SeqCst), 0);
+
+        // Throttled for half of the time, the vcpu is kicked out of kvm to sleep.
+        cpu.set_throttle_percentage(50);
+        thread::sleep(Duration::from_millis(400));
+        assert!(exits.load(Ordering::SeqCst) > 0);
+        let slept = slept_ms.load(Ordering::SeqCst);
+        assert!((100..=300).contains(&slept), "vcpu slept {} ms", slept);
+
+        // The throttle is removed, the vcpu stops exiting from kvm.
+        cpu.set_throttle_percentage(0);
+        thread::sleep(Duration::from_millis(50));
+        assert!(!cpu.throttle_kicker.load(Ordering::SeqCst));
+        let exits_now = exits.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(exits.load(Ordering::SeqCst), exits_now);
+
+        stop.store(true, Ordering::SeqCst);
+        cpu.kick().unwrap();
+        cpu.task.lock().unwrap().take().unwrap().join().unwrap();
+        // SAFETY: The vm using the guest memory is not run any more.
+        unsafe { libc::munmap(host_addr, mem_size as usize) };
+    }
+
     #[test]
     fn test_cpu_get_topu() {
         let test_nr_cpus: u8 = 16;
@@ -609,7 +609,12 @@ impl StateTransfer for CPU {
     }
 }
 
-impl MigrationHook for CPU {}
+impl MigrationHook for CPU {
+    fn set_throttle(&self, percentage: u8) -> Result<()> {
+        self.set_throttle_percentage(percentage);
+        Ok(())
+    }
+}
 
 #[cfg(test)]
 mod test {
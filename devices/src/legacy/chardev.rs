@@ -11,17 +11,20 @@
 // See the Mulan PSL v2 for more details.
 
 use std::fs::{read_link, File, OpenOptions};
-use std::io::{Stdin, Stdout};
+use std::io::{Stdin, Stdout, Write};
 use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::PathBuf;
 use std::rc::Rc;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, Weak};
 
 use anyhow::{bail, Context, Result};
 use libc::{cfmakeraw, tcgetattr, tcsetattr, termios};
 use log::{error, info};
 use machine_manager::machine::{PathInfo, PTY_PATH};
+use machine_manager::qmp::guest_agent::{
+    guest_agent_receive, guest_agent_registered, register_guest_agent, GuestAgentTransport,
+};
 use machine_manager::{
     config::{ChardevConfig, ChardevType},
     temp_cleaner::TempCleaner,
@@ -133,6 +136,15 @@ impl Chardev {
                 ));
                 self.output = Some(file);
             }
+            ChardevType::GuestAgent => {
+                if guest_agent_registered() {
+                    bail!(
+                        "Chardev {} failed: only one guest agent channel is supported",
+                        self.id
+                    );
+                }
+                self.output = Some(Arc::new(Mutex::new(GuestAgentOutput {})));
+            }
         };
         Ok(())
     }
@@ -274,7 +286,7 @@ fn get_notifier_handler(
                 vec![inner_handler],
             )])
         }),
-        ChardevType::File(_) => Rc::new(move |_, _| None),
+        ChardevType::File(_) | ChardevType::GuestAgent => Rc::new(move |_, _| None),
     }
 }
 
@@ -315,6 +327,16 @@ impl EventNotifierHelper for Chardev {
                 }
             }
             ChardevType::File(_) => (),
+            ChardevType::GuestAgent => {
+                // Requests to guest agent are sent through the receiver of
+                // the device bound to this chardev.
+                let transport = Arc::new(GuestAgentChannel {
+                    chardev: Arc::downgrade(&chardev),
+                });
+                if let Err(e) = register_guest_agent(transport) {
+                    error!("Failed to register guest agent: {:?}", e);
+                }
+            }
         }
         notifiers
     }
@@ -343,3 +365,59 @@ impl CommunicatInInterface for Stdin {}
 impl CommunicatOutInterface for UnixStream {}
 impl CommunicatOutInterface for File {}
 impl CommunicatOutInterface for Stdout {}
+
+/// Output of the guest-agent chardev, which hands the replies of guest agent
+/// to the QMP guest agent proxy.
+struct GuestAgentOutput {}
+
+impl Write for GuestAgentOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        guest_agent_receive(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl CommunicatOutInterface for GuestAgentOutput {}
+
+/// Channel used by the QMP guest agent proxy to send requests to guest agent.
+struct GuestAgentChannel {
+    chardev: Weak<Mutex<Chardev>>,
+}
+
+impl GuestAgentTransport for GuestAgentChannel {
+    fn send(&self, data: &[u8]) -> Result<()> {
+        let chardev = self
+            .chardev
+            .upgrade()
+            .with_context(|| "Guest agent chardev has been removed")?;
+        let locked_chardev = chardev.lock().unwrap();
+        if locked_chardev.deactivated {
+            bail!("Guest agent channel {} is not connected", locked_chardev.id);
+        }
+        let (receive, get_remain_space_size) = match (
+            locked_chardev.receive.clone(),
+            locked_chardev.get_remain_space_size.clone(),
+        ) {
+            (Some(receive), Some(get_remain_space_size)) => (receive, get_remain_space_size),
+            _ => bail!(
+                "Guest agent channel {} is not bound to device",
+                locked_chardev.id
+            ),
+        };
+        drop(locked_chardev);
+
+        if get_remain_space_size() < data.len() {
+            bail!("No enough space in guest agent channel for request");
+        }
+        receive(data);
+        Ok(())
+    }
+
+    fn is_alive(&self) -> bool {
+        self.chardev.strong_count() > 0
+    }
+}
@@ -26,6 +26,11 @@ use anyhow::{anyhow, bail, Context, Result};
 use byteorder::LittleEndian;
 use byteorder::{BigEndian, ByteOrder};
 use log::{error, warn};
+use migration::{
+    snapshot::FWCFG_SNAPSHOT_ID, DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager,
+    StateTransfer,
+};
+use migration_derive::{ByteCode, Desc};
 use sysbus::{SysBus, SysBusDevOps, SysBusDevType, SysRes};
 use util::byte_code::ByteCode;
 use util::num_ops::extract_u64;
@@ -285,6 +290,20 @@ fn write_dma_result(addr_space: &Arc<AddressSpace>, addr: GuestAddress, value: u
     Ok(())
 }
 
+/// Status of FwCfg device, the entries themselves are rebuilt from the
+/// configuration of the destination VM.
+#[repr(C)]
+#[derive(Copy, Clone, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+pub struct FwCfgState {
+    /// DMA guest address.
+    dma_addr: u64,
+    /// The current entry data offset of the entry selected.
+    cur_offset: u32,
+    /// The current entry index selected.
+    cur_entry: u16,
+}
+
 pub struct FwCfgCommon {
     // Firmware file slot count
     file_slots: u16,
@@ -307,6 +326,25 @@ pub struct FwCfgCommon {
 }
 
 impl FwCfgCommon {
+    fn get_state(&self) -> FwCfgState {
+        FwCfgState {
+            dma_addr: self.dma_addr.raw_value(),
+            cur_offset: self.cur_offset,
+            cur_entry: self.cur_entry,
+        }
+    }
+
+    fn set_state(&mut self, state: &[u8]) -> Result<()> {
+        let fwcfg_state = FwCfgState::from_bytes(state)
+            .ok_or_else(|| anyhow!(migration::MigrationError::FromBytesError("FWCFG")))?;
+
+        self.dma_addr = GuestAddress(fwcfg_state.dma_addr);
+        self.cur_offset = fwcfg_state.cur_offset;
+        self.cur_entry = fwcfg_state.cur_entry;
+
+        Ok(())
+    }
+
     fn new(sys_mem: Arc<AddressSpace>) -> Self {
         FwCfgCommon {
             file_slots: FW_CFG_FILE_SLOTS_DFLT,
@@ -855,6 +893,11 @@ impl FwCfgMem {
         sysbus
             .attach_device(&dev, region_base, region_size)
             .with_context(|| "Failed to attach FwCfg device to system bus.")?;
+        MigrationManager::register_device_instance(
+            FwCfgState::descriptor(),
+            dev.clone(),
+            FWCFG_SNAPSHOT_ID,
+        );
         Ok(dev)
     }
 }
@@ -1027,6 +1070,11 @@ impl FwCfgIO {
         sysbus
             .attach_device(&dev, region_base, region_size)
             .with_context(|| "Failed to attach FwCfg device to system bus.")?;
+        MigrationManager::register_device_instance(
+            FwCfgState::descriptor(),
+            dev.clone(),
+            FWCFG_SNAPSHOT_ID,
+        );
         Ok(dev)
     }
 }
@@ -1238,6 +1286,24 @@ pub trait FwCfgOps {
     }
 }
 
+#[cfg(target_arch = "aarch64")]
+impl StateTransfer for FwCfgMem {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        Ok(self.fwcfg.get_state().as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        self.fwcfg.set_state(state)
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&FwCfgState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+impl MigrationHook for FwCfgMem {}
+
 #[cfg(target_arch = "aarch64")]
 impl AmlBuilder for FwCfgMem {
     fn aml_bytes(&self) -> Vec<u8> {
@@ -1258,6 +1324,24 @@ impl AmlBuilder for FwCfgMem {
     }
 }
 
+#[cfg(target_arch = "x86_64")]
+impl StateTransfer for FwCfgIO {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        Ok(self.fwcfg.get_state().as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        self.fwcfg.set_state(state)
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&FwCfgState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl MigrationHook for FwCfgIO {}
+
 #[cfg(target_arch = "x86_64")]
 impl AmlBuilder for FwCfgIO {
     fn aml_bytes(&self) -> Vec<u8> {
@@ -1325,6 +1409,24 @@ mod test {
         sys_space
     }
 
+    #[test]
+    fn test_state_transfer() {
+        let sys_mem = address_space_init();
+        let mut src = FwCfgCommon::new(sys_mem.clone());
+        src.cur_entry = FwCfgEntryType::NbCpus as u16;
+        src.cur_offset = 2;
+        src.dma_addr = GuestAddress(0x1000);
+
+        let state = src.get_state();
+        let mut dst = FwCfgCommon::new(sys_mem);
+        dst.set_state(state.as_bytes()).unwrap();
+        assert_eq!(dst.cur_entry, FwCfgEntryType::NbCpus as u16);
+        assert_eq!(dst.cur_offset, 2);
+        assert_eq!(dst.dma_addr, GuestAddress(0x1000));
+
+        assert!(dst.set_state(&[0_u8; 3]).is_err());
+    }
+
     #[test]
     fn test_entry_functions() {
         let sys_mem = address_space_init();
@@ -19,8 +19,28 @@ use acpi::AmlBuilder;
 use address_space::{FileBackend, GuestAddress, HostMemMapping, Region};
 use anyhow::{anyhow, bail, Context, Result};
 use log::{debug, error, warn};
+use migration::{DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager, StateTransfer};
+use migration_derive::{ByteCode, Desc};
 use sysbus::{SysBus, SysBusDevOps, SysBusDevType, SysRes};
+use util::byte_code::ByteCode;
 use util::num_ops::{deposit_u32, extract_u32, read_data_u32, write_data_u32};
+/// Status of `PFlash` device, the flash content lives in its backend file.
+#[repr(C)]
+#[derive(Copy, Clone, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+pub struct PFlashState {
+    /// If 0, the PFlash is read normally.
+    write_cycle: i32,
+    /// Counter for writing block.
+    counter: u32,
+    /// Command to control PFlash.
+    cmd: u8,
+    /// PFlash status.
+    status: u8,
+    /// ROM region is in read array mode or not.
+    romd: u8,
+}
+
 pub struct PFlash {
     /// Has backend file or not.
     has_backend: bool,
@@ -221,7 +241,13 @@ impl PFlash {
             .root()
             .add_subregion(rom_region, region_base)
             .with_context(|| "Failed to attach PFlash to system bus")?;
-        sysbus.devices.push(dev);
+        sysbus.devices.push(dev.clone());
+
+        MigrationManager::register_device_instance(
+            PFlashState::descriptor(),
+            dev,
+            &format!("pflash@{:#x}", region_base),
+        );
 
         Ok(())
     }
@@ -886,6 +912,46 @@ impl SysBusDevOps for PFlash {
     }
 }
 
+impl StateTransfer for PFlash {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        let state = PFlashState {
+            write_cycle: self.write_cycle,
+            counter: self.counter,
+            cmd: self.cmd,
+            status: self.status,
+            romd: self
+                .rom
+                .as_ref()
+                .and_then(|rom| rom.get_rom_device_romd())
+                .unwrap_or(true) as u8,
+        };
+
+        Ok(state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        let pflash_state = PFlashState::from_bytes(state)
+            .ok_or_else(|| anyhow!(migration::MigrationError::FromBytesError("PFLASH")))?;
+
+        self.write_cycle = pflash_state.write_cycle;
+        self.counter = pflash_state.counter;
+        self.cmd = pflash_state.cmd;
+        self.status = pflash_state.status;
+        if let Some(rom) = self.rom.as_ref() {
+            rom.set_rom_device_romd(pflash_state.romd != 0)
+                .with_context(|| "Failed to restore PFlash read array mode")?;
+        }
+
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&PFlashState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+impl MigrationHook for PFlash {}
+
 impl AmlBuilder for PFlash {
     fn aml_bytes(&self) -> Vec<u8> {
         Vec::new()
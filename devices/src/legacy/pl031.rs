@@ -19,6 +19,8 @@ use address_space::GuestAddress;
 use anyhow::{anyhow, Context, Result};
 use byteorder::{ByteOrder, LittleEndian};
 use log::error;
+use machine_manager::event;
+use machine_manager::qmp::{qmp_schema as schema, QmpChannel};
 use migration::{
     snapshot::PL031_SNAPSHOT_ID, DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager,
     StateTransfer,
@@ -175,6 +177,17 @@ impl SysBusDevOps for PL031 {
                 self.state.lr = value;
                 self.tick_offset = value;
                 self.base_time = Instant::now();
+
+                if QmpChannel::is_connected() {
+                    let host_time = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs();
+                    let rtc_event = schema::RtcChange {
+                        offset: value as i64 - host_time as i64,
+                    };
+                    event!(RtcChange; rtc_event);
+                }
             }
             RTC_IMSC => {
                 self.state.imsr = value & 1;
@@ -18,9 +18,17 @@ use acpi::{
     AmlResTemplate, AmlScopeBuilder,
 };
 use address_space::GuestAddress;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use log::{debug, error, warn};
+use machine_manager::event;
+use machine_manager::qmp::{qmp_schema as schema, QmpChannel};
+use migration::{
+    snapshot::RTC_SNAPSHOT_ID, DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager,
+    StateTransfer,
+};
+use migration_derive::{ByteCode, Desc};
 use sysbus::{SysBus, SysBusDevOps, SysBusDevType, SysRes};
+use util::byte_code::ByteCode;
 use vmm_sys_util::eventfd::EventFd;
 
 use util::time::{mktime64, NANOSECONDS_PER_SECOND};
@@ -96,6 +104,20 @@ fn bcd_to_bin(src: u8) -> u64 {
     (((src >> 4) * 10) + (src & 0x0f)) as u64
 }
 
+#[allow(clippy::upper_case_acronyms)]
+/// Status of `RTC` device.
+#[repr(C)]
+#[derive(Copy, Clone, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+pub struct RTCState {
+    /// Clock value in seconds since 1970-01-01 00:00:00 when saving.
+    clock: i64,
+    /// Static CMOS RAM.
+    cmos_data: [u8; 128],
+    /// Index of Selected register.
+    cur_index: u8,
+}
+
 #[allow(clippy::upper_case_acronyms)]
 /// RTC device.
 pub struct RTC {
@@ -268,6 +290,9 @@ impl RTC {
 
         let dev = Arc::new(Mutex::new(self));
         sysbus.attach_device(&dev, region_base, region_size)?;
+
+        MigrationManager::register_device_instance(RTCState::descriptor(), dev, RTC_SNAPSHOT_ID);
+
         Ok(())
     }
 
@@ -348,6 +373,17 @@ impl RTC {
         self.tick_offset = mktime64(year, mon, day, hour, min, sec);
 
         self.base_time = Instant::now();
+
+        if QmpChannel::is_connected() {
+            let host_time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let rtc_event = schema::RtcChange {
+                offset: self.tick_offset as i64 - host_time as i64,
+            };
+            event!(RtcChange; rtc_event);
+        }
     }
 
     fn update_in_progress(&self) -> bool {
@@ -398,6 +434,38 @@ impl SysBusDevOps for RTC {
     }
 }
 
+impl StateTransfer for RTC {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        let state = RTCState {
+            clock: self.get_current_value(),
+            cmos_data: self.cmos_data,
+            cur_index: self.cur_index,
+        };
+
+        Ok(state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        let rtc_state = RTCState::from_bytes(state)
+            .ok_or_else(|| anyhow!(migration::MigrationError::FromBytesError("RTC")))?;
+
+        self.cmos_data = rtc_state.cmos_data;
+        self.cur_index = rtc_state.cur_index;
+        // The guest clock continues from the saved value rather than jumping
+        // to the host time of the destination.
+        self.tick_offset = rtc_state.clock as u64;
+        self.base_time = Instant::now();
+
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&RTCState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+impl MigrationHook for RTC {}
+
 impl AmlBuilder for RTC {
     fn aml_bytes(&self) -> Vec<u8> {
         let mut acpi_dev = AmlDevice::new("RTC");
@@ -474,6 +542,35 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_rtc_state_transfer() -> Result<()> {
+        let mut src = RTC::new().with_context(|| "Failed to create RTC device")?;
+        // Set rtc time: 2013-11-13 02:04:56
+        cmos_write(&mut src, RTC_CENTURY_BCD, 0x20);
+        cmos_write(&mut src, RTC_YEAR, 0x13);
+        cmos_write(&mut src, RTC_MONTH, 0x11);
+        cmos_write(&mut src, RTC_DAY_OF_MONTH, 0x13);
+        cmos_write(&mut src, RTC_HOURS, 0x02);
+        cmos_write(&mut src, RTC_MINUTES, 0x04);
+        cmos_write(&mut src, RTC_SECONDS, 0x56);
+        // Select a general purpose CMOS byte.
+        cmos_write(&mut src, 0x40, 0x5a);
+
+        let state = src.get_state_vec()?;
+        let mut dst = RTC::new().with_context(|| "Failed to create RTC device")?;
+        dst.set_state_mut(&state)?;
+
+        assert_eq!(dst.cur_index, 0x40);
+        assert_eq!(cmos_read(&mut dst, 0x40), 0x5a);
+        assert!((cmos_read(&mut dst, RTC_SECONDS) - 0x56) <= WIGGLE);
+        assert_eq!(cmos_read(&mut dst, RTC_MINUTES), 0x04);
+        assert_eq!(cmos_read(&mut dst, RTC_DAY_OF_MONTH), 0x13);
+        assert_eq!(cmos_read(&mut dst, RTC_YEAR), 0x13);
+        assert_eq!(cmos_read(&mut dst, RTC_CENTURY_BCD), 0x20);
+
+        Ok(())
+    }
+
     #[test]
     fn test_set_year_1970() -> Result<()> {
         let mut rtc = RTC::new().with_context(|| "Failed to create RTC device")?;
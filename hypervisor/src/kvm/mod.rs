@@ -156,6 +156,21 @@ impl KVMFds {
             .with_context(|| format!("Failed to register irqfd: gsi {}.", gsi))
     }
 
+    /// Register a level-triggered irqfd, `resample_fd` is signaled when the interrupt is
+    /// acknowledged by guest.
+    pub fn register_irqfd_with_resample(
+        &self,
+        fd: &EventFd,
+        resample_fd: &EventFd,
+        gsi: u32,
+    ) -> Result<()> {
+        self.vm_fd
+            .as_ref()
+            .unwrap()
+            .register_irqfd_with_resample(fd, resample_fd, gsi)
+            .with_context(|| format!("Failed to register irqfd with resample: gsi {}.", gsi))
+    }
+
     pub fn unregister_irqfd(&self, fd: &EventFd, gsi: u32) -> Result<()> {
         self.vm_fd
             .as_ref()
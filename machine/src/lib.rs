@@ -19,13 +19,15 @@ mod vm_state;
 pub use crate::error::MachineError;
 use std::collections::{BTreeMap, HashMap};
 use std::fs::{remove_file, File};
+use std::io::{Read, Write};
 use std::net::TcpListener;
 use std::ops::Deref;
 use std::os::unix::net::UnixListener;
 use std::path::Path;
 use std::sync::{Arc, Barrier, Condvar, Mutex, Weak};
+use std::thread;
 
-use log::warn;
+use log::{error, warn};
 use util::file::{lock_file, unlock_file};
 
 pub use micro_vm::LightMachine;
@@ -49,35 +51,38 @@ use machine_manager::config::{
     complete_numa_node, get_multi_function, get_pci_bdf, parse_balloon, parse_blk, parse_demo_dev,
     parse_device_id, parse_fs, parse_net, parse_numa_distance, parse_numa_mem, parse_rng_dev,
     parse_root_port, parse_scsi_controller, parse_scsi_device, parse_vfio,
-    parse_vhost_user_blk_pci, parse_virtconsole, parse_virtio_serial, parse_vsock, BootIndexInfo,
-    DriveFile, Incoming, MachineMemConfig, MigrateMode, NumaConfig, NumaDistance, NumaNode,
-    NumaNodes, PFlashConfig, PciBdf, SerialConfig, VfioConfig, VmConfig, FAST_UNPLUG_ON,
+    parse_vhost_user_blk_pci, parse_virtio_serial, parse_virtserialport, parse_vsock,
+    BootIndexInfo, DriveFile, Incoming, MachineMemConfig, MigrateMode, NumaConfig, NumaDistance,
+    NumaNode, NumaNodes, PFlashConfig, PciBdf, SerialConfig, VfioConfig, VmConfig, FAST_UNPLUG_ON,
     MAX_VIRTIO_QUEUE,
 };
 #[cfg(not(target_env = "musl"))]
-use machine_manager::config::{parse_gpu, parse_usb_keyboard, parse_usb_tablet, parse_xhci};
-use machine_manager::machine::{KvmVmState, MachineInterface};
-use migration::MigrationManager;
+use machine_manager::config::{
+    parse_gpu, parse_usb_keyboard, parse_usb_storage, parse_usb_tablet, parse_xhci,
+};
+use machine_manager::machine::{notify_vm_resumed, KvmVmState, MachineInterface};
+use migration::{MigrationManager, MigrationStatus};
 use pci::{demo_dev::DemoDev, PciBus, PciDevOps, PciHost, RootPort};
 use standard_vm::Result as StdResult;
 pub use standard_vm::StdMachine;
 use sysbus::{SysBus, SysBusDevOps};
 #[cfg(not(target_env = "musl"))]
 use usb::{
-    keyboard::UsbKeyboard, tablet::UsbTablet, usb::UsbDeviceOps, xhci::xhci_pci::XhciPciDevice,
+    keyboard::UsbKeyboard, storage::UsbStorage, tablet::UsbTablet, usb::UsbDeviceOps,
+    xhci::xhci_pci::XhciPciDevice,
 };
 use util::{
     arg_parser,
     seccomp::{BpfRule, SeccompOpt, SyscallFilter},
 };
 use vfio::{VfioDevice, VfioPciDevice};
-#[cfg(not(target_env = "musl"))]
-use virtio::Gpu;
 use virtio::{
-    balloon_allow_list, vhost, Balloon, Block, BlockState, Console, Rng, RngState, ScsiBus,
-    ScsiCntlr, ScsiDisk, VhostKern, VhostUser, VirtioConsoleState, VirtioDevice, VirtioMmioDevice,
-    VirtioMmioState, VirtioNetState, VirtioPciDevice,
+    balloon_allow_list, vhost, Balloon, BalloonState, Block, BlockState, Console, Rng, RngState,
+    ScsiBus, ScsiCntlr, ScsiDisk, VhostKern, VhostUser, VirtioConsoleState, VirtioDevice,
+    VirtioMmioDevice, VirtioMmioState, VirtioNetState, VirtioPciDevice,
 };
+#[cfg(not(target_env = "musl"))]
+use virtio::{Gpu, GpuState};
 use ScsiCntlr::ScsiCntlrMap;
 use ScsiDisk::{SCSI_TYPE_DISK, SCSI_TYPE_ROM};
 
@@ -332,6 +337,11 @@ pub trait MachineOps {
             vm_config.machine_config.mem_config.mem_share,
         )));
         Balloon::object_init(balloon.clone());
+        MigrationManager::register_device_instance(
+            BalloonState::descriptor(),
+            balloon.clone(),
+            &device_cfg.id,
+        );
         if cfg_args.contains("virtio-balloon-device") {
             let device = VirtioMmioDevice::new(sys_mem, balloon);
             self.realize_virtio_mmio_device(device)?;
@@ -351,63 +361,70 @@ pub trait MachineOps {
         Ok(())
     }
 
-    /// Add console device.
+    /// Add virtio-serial device.
     ///
     /// # Arguments
     ///
     /// * `vm_config` - VM configuration.
     /// * `cfg_args` - Device configuration args.
-    fn add_virtio_console(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
-        let device_cfg = parse_virtconsole(vm_config, cfg_args)?;
-        let sys_mem = self.get_sys_mem();
-        let console = Arc::new(Mutex::new(Console::new(device_cfg.clone())));
-        if let Some(serial) = &vm_config.virtio_serial {
-            if serial.pci_bdf.is_none() {
-                let device = VirtioMmioDevice::new(sys_mem, console.clone());
-                MigrationManager::register_device_instance(
-                    VirtioMmioState::descriptor(),
-                    self.realize_virtio_mmio_device(device)
-                        .with_context(|| anyhow!(MachineError::RlzVirtioMmioErr))?,
-                    &device_cfg.id,
-                );
-            } else {
-                let virtio_serial_info = if let Some(serial_info) = &vm_config.virtio_serial {
-                    serial_info
-                } else {
-                    bail!("No virtio-serial-pci device configured for virtconsole");
-                };
-                // Reasonable, because for virtio-serial-pci device, the bdf has been checked.
-                let bdf = virtio_serial_info.pci_bdf.clone().unwrap();
-                let multi_func = virtio_serial_info.multifunction;
-                let (devfn, parent_bus) = self.get_devfn_and_parent_bus(&bdf)?;
-                let sys_mem = self.get_sys_mem().clone();
-                let virtio_pci_device = VirtioPciDevice::new(
-                    device_cfg.id.clone(),
-                    devfn,
-                    sys_mem,
-                    console.clone(),
-                    parent_bus,
-                    multi_func,
-                );
-                virtio_pci_device
-                    .realize()
-                    .with_context(|| "Failed  to add virtio pci console device")?;
-            }
+    fn add_virtio_serial(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
+        parse_virtio_serial(vm_config, cfg_args)?;
+        // Reasonable, because the virtio serial info has been set by the parse function.
+        let serial_cfg = vm_config.virtio_serial.clone().unwrap();
+        let sys_mem = self.get_sys_mem().clone();
+        let serial = Arc::new(Mutex::new(Console::new(&serial_cfg)));
+        if let Some(bdf) = &serial_cfg.pci_bdf {
+            let (devfn, parent_bus) = self.get_devfn_and_parent_bus(bdf)?;
+            let virtio_pci_device = VirtioPciDevice::new(
+                serial_cfg.id.clone(),
+                devfn,
+                sys_mem,
+                serial.clone(),
+                parent_bus,
+                serial_cfg.multifunction,
+            );
+            virtio_pci_device
+                .realize()
+                .with_context(|| "Failed to add virtio pci serial device")?;
         } else {
-            bail!("No virtio-serial-bus specified");
+            let device = VirtioMmioDevice::new(&sys_mem, serial.clone());
+            MigrationManager::register_device_instance(
+                VirtioMmioState::descriptor(),
+                self.realize_virtio_mmio_device(device)
+                    .with_context(|| anyhow!(MachineError::RlzVirtioMmioErr))?,
+                &serial_cfg.id,
+            );
         }
         MigrationManager::register_device_instance(
             VirtioConsoleState::descriptor(),
-            console,
-            &device_cfg.id,
+            serial.clone(),
+            &serial_cfg.id,
         );
+        *self.get_virtio_serial() = Some(serial);
 
         Ok(())
     }
 
-    fn add_virtio_serial(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
-        parse_virtio_serial(vm_config, cfg_args)?;
-        Ok(())
+    /// Add virtconsole or virtserialport to the virtio-serial device.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm_config` - VM configuration.
+    /// * `cfg_args` - Device configuration args.
+    /// * `is_console` - The port is virtconsole or not.
+    fn add_virtio_serial_port(
+        &mut self,
+        vm_config: &mut VmConfig,
+        cfg_args: &str,
+        is_console: bool,
+    ) -> Result<()> {
+        let port_cfg = parse_virtserialport(vm_config, cfg_args, is_console)?;
+        let serial = self
+            .get_virtio_serial()
+            .clone()
+            .with_context(|| "No virtio-serial-bus specified")?;
+        let mut locked_serial = serial.lock().unwrap();
+        locked_serial.add_port(port_cfg)
     }
 
     /// Add virtio-rng device.
@@ -687,6 +704,11 @@ pub trait MachineOps {
             .with_context(|| "Failed to add virtio scsi controller")?;
         self.reset_bus(&device_cfg.id)?;
         device.lock().unwrap().config.boot_prefix = pci_dev.lock().unwrap().get_dev_path();
+        MigrationManager::register_device_instance(
+            ScsiCntlr::ScsiCntlrState::descriptor(),
+            device,
+            &device_cfg.id,
+        );
         Ok(())
     }
 
@@ -865,7 +887,8 @@ pub trait MachineOps {
         let multi_func = get_multi_function(cfg_args)?;
         let device_cfg = parse_gpu(cfg_args)?;
         let device = Arc::new(Mutex::new(Gpu::new(device_cfg.clone())));
-        self.add_virtio_pci_device(&device_cfg.id, &bdf, device, multi_func, false)?;
+        self.add_virtio_pci_device(&device_cfg.id, &bdf, device.clone(), multi_func, false)?;
+        MigrationManager::register_device_instance(GpuState::descriptor(), device, &device_cfg.id);
         Ok(())
     }
 
@@ -1163,6 +1186,35 @@ pub trait MachineOps {
         Ok(())
     }
 
+    /// Add usb storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `cfg_args` - USB Storage Configuration.
+    #[cfg(not(target_env = "musl"))]
+    fn add_usb_storage(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
+        let device_cfg = parse_usb_storage(vm_config, cfg_args)?;
+        let storage = UsbStorage::new(device_cfg, self.get_drive_files())
+            .with_context(|| "Failed to create usb storage device")?;
+        let stg = storage
+            .realize()
+            .with_context(|| "Failed to realize usb storage device")?;
+        let parent_dev_op = self.get_pci_dev_by_name(vm_config, "nec-usb-xhci");
+        if parent_dev_op.is_none() {
+            bail!("Can not find parent device from pci bus");
+        }
+        let parent_dev = parent_dev_op.unwrap();
+        let locked_parent_dev = parent_dev.lock().unwrap();
+        let xhci_pci = locked_parent_dev.as_any().downcast_ref::<XhciPciDevice>();
+        if xhci_pci.is_none() {
+            bail!("PciDevOps can not downcast to XhciPciDevice");
+        }
+        xhci_pci
+            .unwrap()
+            .attach_device(&(stg as Arc<Mutex<dyn UsbDeviceOps>>))?;
+        Ok(())
+    }
+
     /// Add peripheral devices.
     ///
     /// # Arguments
@@ -1231,7 +1283,10 @@ pub trait MachineOps {
                     self.add_virtio_serial(vm_config, cfg_args)?;
                 }
                 "virtconsole" => {
-                    self.add_virtio_console(vm_config, cfg_args)?;
+                    self.add_virtio_serial_port(vm_config, cfg_args, true)?;
+                }
+                "virtserialport" => {
+                    self.add_virtio_serial_port(vm_config, cfg_args, false)?;
                 }
                 "virtio-rng-device" | "virtio-rng-pci" => {
                     self.add_virtio_rng(vm_config, cfg_args)?;
@@ -1258,6 +1313,10 @@ pub trait MachineOps {
                     self.add_usb_tablet(vm_config, cfg_args)?;
                 }
                 #[cfg(not(target_env = "musl"))]
+                "usb-storage" => {
+                    self.add_usb_storage(vm_config, cfg_args)?;
+                }
+                #[cfg(not(target_env = "musl"))]
                 "virtio-gpu-pci" => {
                     self.add_virtio_pci_gpu(cfg_args)?;
                 }
@@ -1265,6 +1324,9 @@ pub trait MachineOps {
                 "ramfb" => {
                     self.add_ramfb()?;
                 }
+                "pvpanic" => {
+                    self.add_pvpanic(cfg_args)?;
+                }
                 "pcie-demo-dev" => {
                     self.add_demo_dev(vm_config, cfg_args)?;
                 }
@@ -1285,6 +1347,10 @@ pub trait MachineOps {
         bail!("ramfb device is not supported!");
     }
 
+    fn add_pvpanic(&mut self, _cfg_args: &str) -> Result<()> {
+        bail!("pvpanic device is not supported!");
+    }
+
     fn add_demo_dev(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
         let bdf = get_pci_bdf(cfg_args)?;
         let (devfn, parent_bus) = self.get_devfn_and_parent_bus(&bdf)?;
@@ -1327,6 +1393,9 @@ pub trait MachineOps {
     /// Get the drive backend files.
     fn get_drive_files(&self) -> Arc<Mutex<HashMap<String, DriveFile>>>;
 
+    /// Get the virtio-serial device, which virtconsole and virtserialport are attached to.
+    fn get_virtio_serial(&mut self) -> &mut Option<Arc<Mutex<Console>>>;
+
     /// Fetch a cloned file from drive backend files.
     fn fetch_drive_file(&self, path: &str) -> Result<File> {
         let files = self.get_drive_files();
@@ -1480,6 +1549,7 @@ pub trait MachineOps {
         }
 
         *vm_state = KvmVmState::Running;
+        notify_vm_resumed();
 
         Ok(())
     }
@@ -1596,29 +1666,28 @@ fn start_incoming_migration(vm: &Arc<Mutex<dyn MachineOps + Send + Sync>>) -> Re
         MigrateMode::Unix => {
             let listener = UnixListener::bind(&path)?;
             let (mut sock, _) = listener.accept()?;
-            remove_file(&path)?;
 
-            MigrationManager::recv_migration(&mut sock)
-                .with_context(|| "Failed to receive migration with unix mode")?;
+            // Extra channels of memory may be connected to the listener.
+            let result = MigrationManager::recv_migration(&mut sock, &listener);
+            remove_file(&path)?;
+            result.with_context(|| "Failed to receive migration with unix mode")?;
             vm.lock()
                 .unwrap()
                 .run(false)
                 .with_context(|| "Failed to start VM.")?;
-            MigrationManager::finish_migration(&mut sock)
-                .with_context(|| "Failed to finish migraton.")?;
+            finish_incoming_migration(sock)?;
         }
         MigrateMode::Tcp => {
             let listener = TcpListener::bind(&path)?;
             let mut sock = listener.accept().map(|(stream, _)| stream)?;
 
-            MigrationManager::recv_migration(&mut sock)
+            MigrationManager::recv_migration(&mut sock, &listener)
                 .with_context(|| "Failed to receive migration with tcp mode")?;
             vm.lock()
                 .unwrap()
                 .run(false)
                 .with_context(|| "Failed to start VM.")?;
-            MigrationManager::finish_migration(&mut sock)
-                .with_context(|| "Failed to finish migraton.")?;
+            finish_incoming_migration(sock)?;
         }
         MigrateMode::Unknown => {
             bail!("Unknown migration mode");
@@ -1635,6 +1704,31 @@ fn start_incoming_migration(vm: &Arc<Mutex<dyn MachineOps + Send + Sync>>) -> Re
     Ok(())
 }
 
+/// Finish the incoming migration. In post-copy, the remaining memory is still
+/// in transit, wait for it in another thread to not block the main loop.
+fn finish_incoming_migration<T>(mut sock: T) -> Result<()>
+where
+    T: Read + Write + Send + 'static,
+{
+    if MigrationManager::status() != MigrationStatus::PostcopyActive {
+        return MigrationManager::finish_migration(&mut sock)
+            .with_context(|| "Failed to finish migraton.");
+    }
+
+    thread::Builder::new()
+        .name("postcopy_finish".to_string())
+        .spawn(move || {
+            if let Err(e) = MigrationManager::finish_migration(&mut sock) {
+                error!("Failed to finish post-copy migration: {:?}", e);
+                let _ = MigrationManager::set_status(MigrationStatus::Failed)
+                    .map_err(|e| error!("{}", e));
+            }
+        })
+        .with_context(|| "Failed to create thread to finish post-copy")?;
+
+    Ok(())
+}
+
 fn coverage_allow_list(syscall_allow_list: &mut Vec<BpfRule>) {
     syscall_allow_list.extend(vec![
         BpfRule::new(libc::SYS_fcntl),
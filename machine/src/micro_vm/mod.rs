@@ -32,7 +32,7 @@ pub mod error;
 pub use error::MicroVmError;
 use machine_manager::event_loop::EventLoop;
 use machine_manager::qmp::qmp_schema::UpdateRegionArgument;
-use util::aio::AioEngine;
+use util::aio::{AioEngine, WriteZeroesState};
 
 mod mem_layout;
 mod syscall;
@@ -44,6 +44,7 @@ use std::fmt;
 use std::fmt::Debug;
 use std::ops::Deref;
 use std::os::unix::io::RawFd;
+use std::str::FromStr;
 use std::sync::{Arc, Condvar, Mutex};
 use std::vec::Vec;
 
@@ -67,9 +68,9 @@ use hypervisor::kvm::KVM_FDS;
 use kvm_bindings::{kvm_pit_config, KVM_PIT_SPEAKER_DUMMY};
 use machine_manager::{
     config::{
-        parse_blk, parse_incoming_uri, parse_net, BlkDevConfig, BootSource, ConfigCheck, DriveFile,
-        Incoming, MigrateMode, NetworkInterfaceConfig, SerialConfig, VmConfig,
-        DEFAULT_VIRTQUEUE_SIZE,
+        parse_blk, parse_block_error_policies, parse_incoming_uri, parse_net, BlkDevConfig,
+        BootSource, ConfigCheck, DiskFormat, DriveFile, Incoming, MigrateMode,
+        NetworkInterfaceConfig, SerialConfig, VmConfig, DEFAULT_VIRTQUEUE_SIZE,
     },
     event,
     machine::{
@@ -90,8 +91,8 @@ use util::{
     loop_context::EventLoopManager, num_ops::str_to_usize, seccomp::BpfRule, set_termi_canon_mode,
 };
 use virtio::{
-    create_tap, qmp_balloon, qmp_query_balloon, Block, BlockState, Net, VhostKern, VirtioDevice,
-    VirtioMmioDevice, VirtioMmioState, VirtioNetState,
+    create_tap, qmp_balloon, qmp_query_balloon, Block, BlockState, Console, Net, VhostKern,
+    VirtioDevice, VirtioMmioDevice, VirtioMmioState, VirtioNetState,
 };
 
 use super::{error::MachineError, MachineOps};
@@ -186,6 +187,8 @@ pub struct LightMachine {
     vm_config: Arc<Mutex<VmConfig>>,
     // Drive backend files.
     drive_files: Arc<Mutex<HashMap<String, DriveFile>>>,
+    // Virtio-serial device.
+    virtio_serial: Option<Arc<Mutex<Console>>>,
 }
 
 impl LightMachine {
@@ -240,6 +243,7 @@ impl LightMachine {
             vm_state,
             vm_config: Arc::new(Mutex::new(vm_config.clone())),
             drive_files: Arc::new(Mutex::new(vm_config.init_drive_files()?)),
+            virtio_serial: None,
         })
     }
 
@@ -708,6 +712,12 @@ impl MachineOps for LightMachine {
         cfg_args: &str,
     ) -> MachineResult<()> {
         let device_cfg = parse_net(vm_config, cfg_args)?;
+        if device_cfg.user_net.is_some() {
+            bail!("User netdev is not supported by microvm");
+        }
+        if device_cfg.socket.is_some() {
+            bail!("Socket netdev is not supported by microvm");
+        }
         if device_cfg.vhost_type.is_some() {
             let net = Arc::new(Mutex::new(VhostKern::Net::new(&device_cfg, &self.sys_mem)));
             let device = VirtioMmioDevice::new(&self.sys_mem, net);
@@ -752,6 +762,10 @@ impl MachineOps for LightMachine {
         self.drive_files.clone()
     }
 
+    fn get_virtio_serial(&mut self) -> &mut Option<Arc<Mutex<Console>>> {
+        &mut self.virtio_serial
+    }
+
     fn realize(vm: &Arc<Mutex<Self>>, vm_config: &mut VmConfig) -> MachineResult<()> {
         let mut locked_vm = vm.lock().unwrap();
 
@@ -1094,6 +1108,21 @@ impl DeviceInterface for LightMachine {
         )
     }
 
+    fn query_block(&self) -> Response {
+        let block_info = block_backend::stats::query_block();
+        Response::create_response(serde_json::to_value(block_info).unwrap(), None)
+    }
+
+    fn query_named_block_nodes(&self) -> Response {
+        let nodes = block_backend::stats::query_named_block_nodes();
+        Response::create_response(serde_json::to_value(nodes).unwrap(), None)
+    }
+
+    fn query_blockstats(&self) -> Response {
+        let stats = block_backend::stats::query_blockstats();
+        Response::create_response(serde_json::to_value(stats).unwrap(), None)
+    }
+
     fn device_add(&mut self, args: Box<qmp_schema::DeviceAddArgument>) -> Response {
         // get slot of bus by addr or lun
         let mut slot = 0;
@@ -1158,6 +1187,56 @@ impl DeviceInterface for LightMachine {
             true
         };
 
+        let format = match args
+            .driver
+            .as_ref()
+            .map_or(Ok(DiskFormat::Raw), |driver| DiskFormat::from_str(driver))
+        {
+            Ok(format) => format,
+            Err(_) => {
+                let err_str = format!("Unsupported block driver {:?}", args.driver);
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(err_str),
+                    None,
+                );
+            }
+        };
+        let discard = match args.discard.as_deref() {
+            None | Some("ignore") => false,
+            Some("unmap") => true,
+            Some(other) => {
+                let err_str = format!("Invalid discard option {:?}", other);
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(err_str),
+                    None,
+                );
+            }
+        };
+        let write_zeroes = match args
+            .detect_zeroes
+            .as_ref()
+            .map_or(Ok(WriteZeroesState::Off), |state| {
+                WriteZeroesState::from_str(state)
+            }) {
+            Ok(state) => state,
+            Err(_) => {
+                let err_str = format!("Invalid detect-zeroes option {:?}", args.detect_zeroes);
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(err_str),
+                    None,
+                );
+            }
+        };
+        let (werror, rerror) =
+            match parse_block_error_policies(args.werror.as_deref(), args.rerror.as_deref()) {
+                Ok(policies) => policies,
+                Err(e) => {
+                    return Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                        None,
+                    );
+                }
+            };
         let config = BlkDevConfig {
             id: args.node_name.clone(),
             path_on_host: args.file.filename.clone(),
@@ -1177,6 +1256,11 @@ impl DeviceInterface for LightMachine {
                 AioEngine::Off
             },
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            format,
+            discard,
+            write_zeroes,
+            werror,
+            rerror,
         };
         if let Err(e) = config.check() {
             error!("{:?}", e);
@@ -1227,6 +1311,8 @@ impl DeviceInterface for LightMachine {
             mq: false,
             socket_path: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            user_net: None,
+            socket: None,
         };
 
         if let Some(fds) = args.fds {
@@ -1327,9 +1413,19 @@ impl DeviceInterface for LightMachine {
 }
 
 impl MigrateInterface for LightMachine {
-    fn migrate(&self, uri: String) -> Response {
+    fn migrate(&self, uri: String, parent: Option<String>, incremental: Option<bool>) -> Response {
         match parse_incoming_uri(&uri) {
-            Ok((MigrateMode::File, path)) => migration::snapshot(path),
+            Ok((MigrateMode::File, path)) => {
+                migration::snapshot(path, parent, incremental.unwrap_or(false))
+            }
+            Ok(_) if parent.is_some() || incremental == Some(true) => {
+                Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(
+                        "Parent and incremental are only supported by snapshot".to_string(),
+                    ),
+                    None,
+                )
+            }
             Ok((MigrateMode::Unix, _)) | Ok((MigrateMode::Tcp, _)) => {
                 Response::create_error_response(
                     qmp_schema::QmpErrorClass::GenericError(
@@ -78,6 +78,7 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_recvmsg),
         BpfRule::new(libc::SYS_sendmsg),
         BpfRule::new(libc::SYS_recvfrom),
+        BpfRule::new(libc::SYS_sendto),
         BpfRule::new(libc::SYS_mremap),
         BpfRule::new(libc::SYS_io_setup),
         BpfRule::new(libc::SYS_brk),
@@ -94,6 +95,7 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_mmap),
         BpfRule::new(libc::SYS_munmap),
         BpfRule::new(libc::SYS_accept4),
+        BpfRule::new(libc::SYS_setsockopt),
         BpfRule::new(libc::SYS_lseek),
         futex_rule(),
         BpfRule::new(libc::SYS_exit),
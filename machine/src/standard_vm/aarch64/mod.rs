@@ -50,8 +50,8 @@ use devices::legacy::{
 use devices::{ICGICConfig, ICGICv3Config, InterruptController, GIC_IRQ_MAX};
 use hypervisor::kvm::KVM_FDS;
 use machine_manager::config::{
-    parse_incoming_uri, BootIndexInfo, BootSource, DriveFile, Incoming, MigrateMode, NumaNode,
-    NumaNodes, PFlashConfig, SerialConfig, VmConfig,
+    get_pci_bdf, parse_incoming_uri, parse_pvpanic, BootIndexInfo, BootSource, DriveFile, Incoming,
+    MigrateMode, NumaNode, NumaNodes, PFlashConfig, SerialConfig, VmConfig,
 };
 use machine_manager::event;
 use machine_manager::machine::{
@@ -60,7 +60,7 @@ use machine_manager::machine::{
 };
 use machine_manager::qmp::{qmp_schema, QmpChannel, Response};
 use migration::{MigrationManager, MigrationStatus};
-use pci::{PciDevOps, PciHost};
+use pci::{pci_swizzle_intx, PciDevOps, PciHost, PvPanicPci, PCI_INTX_GSI_BASE, PCI_INTX_PIN_NUM};
 use pci_host_root::PciHostRoot;
 use sysbus::{SysBus, SysBusDevType, SysRes, IRQ_BASE, IRQ_MAX};
 use syscall::syscall_whitelist;
@@ -75,7 +75,7 @@ use vnc::vnc;
 use super::{AcpiBuilder, Result as StdResult, StdMachineOps};
 use crate::MachineOps;
 use anyhow::{anyhow, bail, Context, Result};
-use virtio::ScsiCntlr::ScsiCntlrMap;
+use virtio::{Console, ScsiCntlr::ScsiCntlrMap};
 
 /// The type of memory layout entry on aarch64
 pub enum LayoutEntryType {
@@ -140,6 +140,8 @@ pub struct StdMachine {
     vm_config: Arc<Mutex<VmConfig>>,
     /// Reset request, handle VM `Reset` event.
     reset_req: Arc<EventFd>,
+    /// Panic request, handle the guest panic reported by pvpanic.
+    panic_req: Arc<EventFd>,
     /// Device Tree Blob.
     dtb_vec: Vec<u8>,
     /// List of guest NUMA nodes information.
@@ -152,6 +154,8 @@ pub struct StdMachine {
     scsi_cntlr_list: ScsiCntlrMap,
     /// Drive backend files.
     drive_files: Arc<Mutex<HashMap<String, DriveFile>>>,
+    /// Virtio-serial device.
+    virtio_serial: Option<Arc<Mutex<Console>>>,
 }
 
 impl StdMachine {
@@ -201,12 +205,18 @@ impl StdMachine {
                     anyhow!(MachineError::InitEventFdErr("reset_req".to_string()))
                 })?,
             ),
+            panic_req: Arc::new(
+                EventFd::new(libc::EFD_NONBLOCK).with_context(|| {
+                    anyhow!(MachineError::InitEventFdErr("panic_req".to_string()))
+                })?,
+            ),
             dtb_vec: Vec::new(),
             numa_nodes: None,
             boot_order_list: Arc::new(Mutex::new(Vec::new())),
             fwcfg_dev: None,
             scsi_cntlr_list: Arc::new(Mutex::new(HashMap::new())),
             drive_files: Arc::new(Mutex::new(vm_config.init_drive_files()?)),
+            virtio_serial: None,
         })
     }
 
@@ -445,6 +455,16 @@ impl MachineOps for StdMachine {
         })
     }
 
+    fn add_pvpanic(&mut self, cfg_args: &str) -> Result<()> {
+        let bdf = get_pci_bdf(cfg_args)?;
+        let (devfn, parent_bus) = self.get_devfn_and_parent_bus(&bdf)?;
+        let id = parse_pvpanic(cfg_args)?;
+        let pvpanic = PvPanicPci::new(id, devfn, parent_bus, self.panic_req.clone());
+        pvpanic
+            .realize()
+            .with_context(|| "Failed to realize pvpanic device")
+    }
+
     fn add_rtc_device(&mut self) -> Result<()> {
         let rtc = PL031::default();
         PL031::realize(
@@ -493,6 +513,10 @@ impl MachineOps for StdMachine {
         self.drive_files.clone()
     }
 
+    fn get_virtio_serial(&mut self) -> &mut Option<Arc<Mutex<Console>>> {
+        &mut self.virtio_serial
+    }
+
     fn realize(vm: &Arc<Mutex<Self>>, vm_config: &mut VmConfig) -> Result<()> {
         use super::error::StandardVmError as StdErrorKind;
 
@@ -503,6 +527,9 @@ impl MachineOps for StdMachine {
         locked_vm
             .register_reset_event(locked_vm.reset_req.clone(), clone_vm)
             .with_context(|| "Fail to register reset event")?;
+        locked_vm
+            .register_panic_event(locked_vm.panic_req.clone(), vm.clone())
+            .with_context(|| "Fail to register panic event")?;
         locked_vm.numa_nodes = locked_vm.add_numa_nodes(vm_config)?;
         locked_vm.init_memory(
             &vm_config.machine_config.mem_config,
@@ -567,9 +594,11 @@ impl MachineOps for StdMachine {
         }
 
         // If it is direct kernel boot mode, the ACPI can not be enabled.
-        if migrate.0 == MigrateMode::Unknown && fwcfg.is_some() {
+        // ACPI tables only live in fw_cfg, so they are rebuilt on incoming side as
+        // well to keep them available to firmware after the guest reboots.
+        if let Some(fwcfg) = fwcfg {
             locked_vm
-                .build_acpi_tables(&fwcfg.unwrap())
+                .build_acpi_tables(&fwcfg)
                 .with_context(|| "Failed to create ACPI tables")?;
         }
 
@@ -1049,9 +1078,19 @@ impl MachineAddressInterface for StdMachine {
 }
 
 impl MigrateInterface for StdMachine {
-    fn migrate(&self, uri: String) -> Response {
+    fn migrate(&self, uri: String, parent: Option<String>, incremental: Option<bool>) -> Response {
         match parse_incoming_uri(&uri) {
-            Ok((MigrateMode::File, path)) => migration::snapshot(path),
+            Ok((MigrateMode::File, path)) => {
+                migration::snapshot(path, parent, incremental.unwrap_or(false))
+            }
+            Ok(_) if parent.is_some() || incremental == Some(true) => {
+                Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(
+                        "Parent and incremental are only supported by snapshot".to_string(),
+                    ),
+                    None,
+                )
+            }
             Ok((MigrateMode::Unix, path)) => migration::migration_unix_mode(path),
             Ok((MigrateMode::Tcp, path)) => migration::migration_tcp_mode(path),
             _ => Response::create_error_response(
@@ -1068,6 +1107,14 @@ impl MigrateInterface for StdMachine {
     fn cancel_migrate(&self) -> Response {
         migration::cancel_migrate()
     }
+
+    fn migrate_set_parameters(&self, args: qmp_schema::MigrateSetParametersArgument) -> Response {
+        migration::migrate_set_parameters(args)
+    }
+
+    fn query_migrate_parameters(&self) -> Response {
+        migration::query_migrate_parameters()
+    }
 }
 
 impl MachineInterface for StdMachine {}
@@ -1156,6 +1203,29 @@ fn generate_pci_host_node(fdt: &mut FdtBuilder) -> util::Result<()> {
         ],
     )?;
 
+    // INTx routing of the slots repeats every 4 slots, so only the low 2 bits of the slot
+    // number and the pin number are matched.
+    fdt.set_property_u32("#interrupt-cells", 1)?;
+    fdt.set_property_array_u32("interrupt-map-mask", &[0x1800, 0, 0, 7])?;
+    let mut interrupt_map = Vec::new();
+    for slot in 0..PCI_INTX_PIN_NUM {
+        for pin in 0..PCI_INTX_PIN_NUM {
+            let gsi = PCI_INTX_GSI_BASE + pci_swizzle_intx((slot << 3) as u8, pin as u8) as u32;
+            interrupt_map.extend_from_slice(&[
+                slot << 11,
+                0,
+                0,
+                pin + 1,
+                device_tree::GIC_PHANDLE,
+                0,
+                0,
+                device_tree::GIC_FDT_IRQ_TYPE_SPI,
+                gsi,
+                device_tree::IRQ_TYPE_LEVEL_HIGH,
+            ]);
+        }
+    }
+    fdt.set_property_array_u32("interrupt-map", &interrupt_map)?;
     fdt.set_property_u32("msi-parent", device_tree::GIC_ITS_PHANDLE)?;
     fdt.end_node(pci_node_dep)?;
     Ok(())
@@ -12,6 +12,9 @@
 
 use std::sync::{Arc, Mutex, Weak};
 
+use anyhow::anyhow;
+use migration::{DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager, StateTransfer};
+use migration_derive::{ByteCode, Desc};
 use pci::{
     config::{
         PciConfig, CLASS_CODE_HOST_BRIDGE, DEVICE_ID, PCI_CONFIG_SPACE_SIZE, PCI_VENDOR_ID_REDHAT,
@@ -19,9 +22,18 @@ use pci::{
     },
     le_write_u16, PciBus, PciDevOps, Result as PciResult,
 };
+use util::byte_code::ByteCode;
 
 const DEVICE_ID_PCIE_HOST: u16 = 0x0008;
 
+/// Status of PciHost root.
+#[repr(C)]
+#[derive(Copy, Clone, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+pub struct PciHostRootState {
+    config_space: [u8; 256],
+}
+
 /// PciHost root (Device 0:Function 0).
 pub struct PciHostRoot {
     /// Pci config space.
@@ -70,11 +82,17 @@ impl PciDevOps for PciHostRoot {
         le_write_u16(&mut self.config.config, REVISION_ID as usize, 0)?;
 
         let parent_bus = self.parent_bus.upgrade().unwrap();
+        let host_root = Arc::new(Mutex::new(self));
         parent_bus
             .lock()
             .unwrap()
             .devices
-            .insert(0, Arc::new(Mutex::new(self)));
+            .insert(0, host_root.clone());
+        MigrationManager::register_device_instance(
+            PciHostRootState::descriptor(),
+            host_root,
+            "pci_host_root",
+        );
         Ok(())
     }
 
@@ -90,3 +108,26 @@ impl PciDevOps for PciHostRoot {
         "PCI Host Root".to_string()
     }
 }
+
+impl StateTransfer for PciHostRoot {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        let mut state = PciHostRootState::default();
+        state.config_space.copy_from_slice(&self.config.config);
+
+        Ok(state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        let host_root_state = PciHostRootState::from_bytes(state)
+            .ok_or_else(|| anyhow!(migration::MigrationError::FromBytesError("PCI_HOST_ROOT")))?;
+        self.config.config = host_root_state.config_space.to_vec();
+
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&PciHostRootState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+impl MigrationHook for PciHostRoot {}
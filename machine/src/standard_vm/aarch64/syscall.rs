@@ -13,6 +13,7 @@
 use hypervisor::kvm::*;
 use util::seccomp::{BpfRule, SeccompCmpOpt};
 use util::tap::{TUNGETFEATURES, TUNSETIFF, TUNSETOFFLOAD, TUNSETVNETHDRSZ};
+use util::userfaultfd::UFFDIO_COPY;
 use vfio::{
     VFIO_CHECK_EXTENSION, VFIO_DEVICE_GET_INFO, VFIO_DEVICE_GET_IRQ_INFO,
     VFIO_DEVICE_GET_REGION_INFO, VFIO_DEVICE_RESET, VFIO_DEVICE_SET_IRQS, VFIO_GET_API_VERSION,
@@ -56,8 +57,8 @@ const KVM_RUN: u32 = 0xae80;
 ///
 /// # Notes
 /// This allowlist limit syscall with:
-/// * aarch64-unknown-gnu: 81 syscalls
-/// * aarch64-unknown-musl: 59 syscalls
+/// * aarch64-unknown-gnu: 84 syscalls
+/// * aarch64-unknown-musl: 62 syscalls
 /// To reduce performance losses, the syscall rules is ordered by frequency.
 pub fn syscall_whitelist() -> Vec<BpfRule> {
     vec![
@@ -76,6 +77,9 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_dup),
         BpfRule::new(libc::SYS_close),
         BpfRule::new(libc::SYS_eventfd2),
+        BpfRule::new(libc::SYS_epoll_create1),
+        BpfRule::new(libc::SYS_timerfd_create),
+        BpfRule::new(libc::SYS_timerfd_settime),
         BpfRule::new(libc::SYS_epoll_ctl),
         #[cfg(target_env = "gnu")]
         BpfRule::new(libc::SYS_ppoll),
@@ -125,8 +129,9 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_msync),
         BpfRule::new(libc::SYS_readlinkat),
         BpfRule::new(libc::SYS_socket),
-        #[cfg(target_env = "gnu")]
+        BpfRule::new(libc::SYS_socketpair),
         BpfRule::new(libc::SYS_bind),
+        BpfRule::new(libc::SYS_listen),
         BpfRule::new(libc::SYS_connect),
         BpfRule::new(libc::SYS_getcwd),
         BpfRule::new(libc::SYS_clone),
@@ -209,6 +214,7 @@ fn ioctl_allow_list() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETIFF() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETOFFLOAD() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETVNETHDRSZ() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, UFFDIO_COPY() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_GSI_ROUTING() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_IRQFD() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, VFIO_DEVICE_SET_IRQS() as u32)
@@ -21,9 +21,10 @@ pub use error::StandardVmError;
 #[cfg(target_arch = "aarch64")]
 pub use aarch64::StdMachine;
 use log::error;
+use machine_manager::event;
 use machine_manager::event_loop::EventLoop;
 use machine_manager::qmp::qmp_schema::UpdateRegionArgument;
-use util::aio::AioEngine;
+use util::aio::{AioEngine, WriteZeroesState};
 use util::loop_context::{read_fd, EventNotifier, NotifierCallback, NotifierOperation};
 use vmm_sys_util::epoll::EventSet;
 use vmm_sys_util::eventfd::EventFd;
@@ -40,6 +41,7 @@ use std::ops::Deref;
 use std::os::unix::io::RawFd;
 use std::os::unix::prelude::AsRawFd;
 use std::rc::Rc;
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 
 use super::Result as MachineResult;
@@ -55,22 +57,24 @@ use address_space::{
 };
 pub use anyhow::Result;
 use anyhow::{bail, Context};
+use block_backend::{get_block_backend, BlockBackend};
 use cpu::{CpuTopology, CPU};
 use devices::legacy::FwCfgOps;
 use machine_manager::config::{
-    get_chardev_config, get_netdev_config, get_pci_df, BlkDevConfig, ChardevType, ConfigCheck,
-    DriveConfig, NetworkInterfaceConfig, NumaNode, NumaNodes, PciBdf, ScsiCntlrConfig, VmConfig,
-    DEFAULT_VIRTQUEUE_SIZE, MAX_VIRTIO_QUEUE,
+    get_chardev_config, get_netdev_config, get_pci_df, parse_block_error_policies, BlkDevConfig,
+    ChardevType, ConfigCheck, DiskFormat, DriveConfig, NetworkInterfaceConfig, NumaNode, NumaNodes,
+    PciBdf, ScsiCntlrConfig, ShutdownAction, VmConfig, DEFAULT_VIRTQUEUE_SIZE, MAX_VIRTIO_QUEUE,
 };
-use machine_manager::machine::{DeviceInterface, KvmVmState};
+use machine_manager::machine::{DeviceInterface, KvmVmState, MachineLifecycle};
 use machine_manager::qmp::{qmp_schema, QmpChannel, Response};
 use migration::MigrationManager;
 use pci::hotplug::{handle_plug, handle_unplug_request};
 use pci::PciBus;
 use util::byte_code::ByteCode;
 use virtio::{
-    qmp_balloon, qmp_query_balloon, Block, BlockState, ScsiBus, ScsiCntlr, VhostKern, VhostUser,
-    VirtioDevice, VirtioNetState, VirtioPciDevice,
+    qmp_balloon, qmp_query_balloon, Block, BlockState, ScsiBus, ScsiCntlr,
+    ScsiDisk::{SCSI_TYPE_DISK, SCSI_TYPE_ROM},
+    VhostKern, VhostUser, VirtioDevice, VirtioNetState, VirtioPciDevice,
 };
 
 #[cfg(target_arch = "aarch64")]
@@ -221,6 +225,35 @@ trait StdMachineOps: AcpiBuilder {
         Ok(())
     }
 
+    /// Register event notifier for panic of standard machine, which is reported by pvpanic.
+    ///
+    /// # Arguments
+    ///
+    /// * `panic_req` - Eventfd of the panic request.
+    /// * `clone_vm` - Reference of the StdMachine.
+    fn register_panic_event(
+        &self,
+        panic_req: Arc<EventFd>,
+        clone_vm: Arc<Mutex<StdMachine>>,
+    ) -> MachineResult<()> {
+        let panic_req_fd = panic_req.as_raw_fd();
+        let panic_req_handler: Rc<NotifierCallback> = Rc::new(move |_, _| {
+            read_fd(panic_req_fd);
+            handle_panic_request(&clone_vm);
+            None
+        });
+        let notifier = EventNotifier::new(
+            NotifierOperation::AddShared,
+            panic_req_fd,
+            None,
+            EventSet::IN,
+            vec![panic_req_handler],
+        );
+        EventLoop::update_event(vec![notifier], None)
+            .with_context(|| "Failed to register event notifier.")?;
+        Ok(())
+    }
+
     #[cfg(target_arch = "x86_64")]
     fn register_acpi_shutdown_event(
         &self,
@@ -248,6 +281,41 @@ trait StdMachineOps: AcpiBuilder {
     }
 }
 
+/// Pause or power off the VM according to the shutdown action, after the guest panicked.
+fn handle_panic_request(vm: &Arc<Mutex<StdMachine>>) {
+    let locked_vm = vm.lock().unwrap();
+    let shutdown_act = locked_vm.get_shutdown_action();
+    if QmpChannel::is_connected() {
+        let action = match shutdown_act {
+            ShutdownAction::ShutdownActionPoweroff => qmp_schema::GuestPanicAction::poweroff,
+            ShutdownAction::ShutdownActionPause => qmp_schema::GuestPanicAction::pause,
+        };
+        let panic_msg = qmp_schema::GuestPanicked { action };
+        event!(GuestPanicked; panic_msg);
+    }
+
+    match shutdown_act {
+        ShutdownAction::ShutdownActionPoweroff => {
+            if !locked_vm.destroy() {
+                error!("Failed to power off VM after guest panic");
+                return;
+            }
+            if QmpChannel::is_connected() {
+                let shutdown_msg = qmp_schema::Shutdown {
+                    guest: true,
+                    reason: "guest-panic".to_string(),
+                };
+                event!(Shutdown; shutdown_msg);
+            }
+        }
+        ShutdownAction::ShutdownActionPause => {
+            if !locked_vm.pause() {
+                error!("Failed to pause VM after guest panic");
+            }
+        }
+    }
+}
+
 /// Trait that helps to build ACPI tables.
 /// Standard machine struct should at least implement `build_dsdt_table`, `build_madt_table`
 /// and `build_mcfg_table` function.
@@ -735,6 +803,31 @@ fn get_device_bdf(bus: Option<String>, addr: Option<String>) -> Result<PciBdf> {
     Ok(pci_bdf)
 }
 
+/// The controller, the bus and the (target, lun) of a scsi device.
+type ScsiDeviceLocation = (
+    Arc<Mutex<ScsiCntlr::ScsiCntlr>>,
+    Arc<Mutex<ScsiBus::ScsiBus>>,
+    (u8, u16),
+);
+
+/// Build the configuration args of the hot plugged pci device in the format of command line.
+fn get_pci_cfg_args(args: &qmp_schema::DeviceAddArgument, with_multifunction: bool) -> String {
+    let mut cfg_args = format!(
+        "{},id={},bus={},addr={}",
+        args.driver,
+        args.id,
+        args.bus.as_deref().unwrap_or("pcie.0"),
+        args.addr.as_deref().unwrap_or("0x0")
+    );
+    if with_multifunction {
+        if let Some(multifunction) = args.multifunction {
+            let switch = if multifunction { "on" } else { "off" };
+            cfg_args = format!("{},multifunction={}", cfg_args, switch);
+        }
+    }
+    cfg_args
+}
+
 impl StdMachine {
     fn plug_virtio_pci_blk(
         &mut self,
@@ -768,6 +861,11 @@ impl StdMachine {
                 socket_path: None,
                 aio: conf.aio,
                 queue_size,
+                format: conf.format,
+                discard: conf.discard,
+                write_zeroes: conf.write_zeroes,
+                werror: conf.werror,
+                rerror: conf.rerror,
             };
             dev.check()?;
             dev
@@ -843,6 +941,11 @@ impl StdMachine {
         };
 
         device.lock().unwrap().config.boot_prefix = pci_dev.lock().unwrap().get_dev_path();
+        MigrationManager::register_device_instance(
+            ScsiCntlr::ScsiCntlrState::descriptor(),
+            device,
+            &dev_cfg.id,
+        );
 
         Ok(())
     }
@@ -949,6 +1052,8 @@ impl StdMachine {
                 mq: conf.queues > 2,
                 socket_path,
                 queue_size,
+                user_net: conf.user_net.clone(),
+                socket: conf.socket.clone(),
             };
             dev.check()?;
             dev
@@ -980,6 +1085,229 @@ impl StdMachine {
         Ok(())
     }
 
+    fn plug_virtio_serial_port(&mut self, args: &qmp_schema::DeviceAddArgument) -> Result<()> {
+        let chardev = if let Some(dev) = &args.chardev {
+            dev
+        } else {
+            bail!("Chardev not set");
+        };
+        let mut cfg_args = format!("{},id={},chardev={}", args.driver, args.id, chardev);
+        if let Some(nr) = args.nr {
+            cfg_args = format!("{},nr={}", cfg_args, nr);
+        }
+        if let Some(name) = &args.name {
+            cfg_args = format!("{},name={}", cfg_args, name);
+        }
+
+        let vm_config = self.get_vm_config();
+        let mut locked_vmconfig = vm_config.lock().unwrap();
+        self.add_virtio_serial_port(
+            &mut locked_vmconfig,
+            &cfg_args,
+            args.driver == "virtconsole",
+        )?;
+        locked_vmconfig
+            .devices
+            .push((args.driver.clone(), cfg_args));
+        Ok(())
+    }
+
+    fn plug_virtio_pci_rng(&mut self, args: &qmp_schema::DeviceAddArgument) -> Result<()> {
+        let rng = if let Some(rng) = &args.rng {
+            rng
+        } else {
+            bail!("Rng object not set");
+        };
+        let mut cfg_args = format!("{},rng={}", get_pci_cfg_args(args, true), rng);
+        if let Some(max_bytes) = args.max_bytes {
+            cfg_args = format!("{},max-bytes={}", cfg_args, max_bytes);
+        }
+        if let Some(period) = args.period {
+            cfg_args = format!("{},period={}", cfg_args, period);
+        }
+
+        let vm_config = self.get_vm_config();
+        let mut locked_vmconfig = vm_config.lock().unwrap();
+        self.add_virtio_rng(&mut locked_vmconfig, &cfg_args)?;
+        locked_vmconfig
+            .devices
+            .push((args.driver.clone(), cfg_args));
+        Ok(())
+    }
+
+    fn plug_virtio_pci_balloon(&mut self, args: &qmp_schema::DeviceAddArgument) -> Result<()> {
+        let mut cfg_args = get_pci_cfg_args(args, true);
+        if let Some(deflate_on_oom) = args.deflate_on_oom {
+            cfg_args = format!("{},deflate-on-oom={}", cfg_args, deflate_on_oom);
+        }
+        if let Some(free_page_reporting) = args.free_page_reporting {
+            cfg_args = format!("{},free-page-reporting={}", cfg_args, free_page_reporting);
+        }
+
+        let vm_config = self.get_vm_config();
+        let mut locked_vmconfig = vm_config.lock().unwrap();
+        self.add_virtio_balloon(&mut locked_vmconfig, &cfg_args)?;
+        locked_vmconfig
+            .devices
+            .push((args.driver.clone(), cfg_args));
+        Ok(())
+    }
+
+    fn plug_virtio_pci_serial(&mut self, args: &qmp_schema::DeviceAddArgument) -> Result<()> {
+        let mut cfg_args = get_pci_cfg_args(args, true);
+        if let Some(max_ports) = args.max_ports {
+            cfg_args = format!("{},max_ports={}", cfg_args, max_ports);
+        }
+
+        let vm_config = self.get_vm_config();
+        let mut locked_vmconfig = vm_config.lock().unwrap();
+        self.add_virtio_serial(&mut locked_vmconfig, &cfg_args)?;
+        locked_vmconfig
+            .devices
+            .push((args.driver.clone(), cfg_args));
+        Ok(())
+    }
+
+    #[cfg(not(target_env = "musl"))]
+    fn plug_virtio_pci_gpu(&mut self, args: &qmp_schema::DeviceAddArgument) -> Result<()> {
+        let mut cfg_args = get_pci_cfg_args(args, false);
+        if let Some(max_outputs) = args.max_outputs {
+            cfg_args = format!("{},max_outputs={}", cfg_args, max_outputs);
+        }
+        if let Some(edid) = args.edid {
+            cfg_args = format!("{},edid={}", cfg_args, edid);
+        }
+        if let Some(xres) = args.xres {
+            cfg_args = format!("{},xres={}", cfg_args, xres);
+        }
+        if let Some(yres) = args.yres {
+            cfg_args = format!("{},yres={}", cfg_args, yres);
+        }
+        if let Some(max_hostmem) = args.max_hostmem {
+            cfg_args = format!("{},max_hostmem={}", cfg_args, max_hostmem);
+        }
+
+        self.add_virtio_pci_gpu(&cfg_args)?;
+        let vm_config = self.get_vm_config();
+        vm_config
+            .lock()
+            .unwrap()
+            .devices
+            .push((args.driver.clone(), cfg_args));
+        Ok(())
+    }
+
+    /// Find the scsi device with the id, return the controller, the bus and the
+    /// (target, lun) of it.
+    fn find_scsi_device(&mut self, id: &str) -> Option<ScsiDeviceLocation> {
+        let cntlr_list = self.get_scsi_cntlr_list()?.lock().unwrap();
+        for cntlr in cntlr_list.values() {
+            let bus = match &cntlr.lock().unwrap().bus {
+                Some(bus) => bus.clone(),
+                None => continue,
+            };
+            let key = bus
+                .lock()
+                .unwrap()
+                .devices
+                .iter()
+                .find(|(_, dev)| dev.lock().unwrap().config.id == id)
+                .map(|(key, _)| *key);
+            if let Some(key) = key {
+                return Some((cntlr.clone(), bus, key));
+            }
+        }
+        None
+    }
+
+    /// Hot plug a scsi-hd or scsi-cd lun to the existing virtio-scsi bus.
+    fn plug_scsi_device(&mut self, args: &qmp_schema::DeviceAddArgument) -> Result<()> {
+        let drive = if let Some(drv) = &args.drive {
+            drv
+        } else {
+            bail!("Drive not set");
+        };
+        let bus = if let Some(bus) = &args.bus {
+            bus
+        } else {
+            bail!("Bus not set");
+        };
+        let mut cfg_args = format!("{},id={},bus={},drive={}", args.driver, args.id, bus, drive);
+        if let Some(target) = args.scsi_id {
+            cfg_args = format!("{},scsi-id={}", cfg_args, target);
+        }
+        if let Some(lun) = args.lun {
+            cfg_args = format!("{},lun={}", cfg_args, lun);
+        }
+        if let Some(serial_num) = &args.serial_num {
+            cfg_args = format!("{},serial={}", cfg_args, serial_num);
+        }
+        if let Some(bootindex) = args.boot_index {
+            cfg_args = format!("{},bootindex={}", cfg_args, bootindex);
+        }
+        let scsi_type = if args.driver == "scsi-hd" {
+            SCSI_TYPE_DISK
+        } else {
+            SCSI_TYPE_ROM
+        };
+
+        let vm_config = self.get_vm_config();
+        let mut locked_vmconfig = vm_config.lock().unwrap();
+        if !locked_vmconfig.drives.contains_key(drive) {
+            bail!("Drive not found");
+        }
+        if let Err(e) = self.add_scsi_device(&mut locked_vmconfig, &cfg_args, scsi_type) {
+            // The device may be attached to the bus before realize fails.
+            if let Some((_, bus, key)) = self.find_scsi_device(&args.id) {
+                bus.lock().unwrap().devices.remove(&key);
+            }
+            return Err(e);
+        }
+        locked_vmconfig
+            .devices
+            .push((args.driver.clone(), cfg_args));
+        drop(locked_vmconfig);
+
+        // Safe to unwrap, because the device has been attached to the bus.
+        let (cntlr, _, (target, lun)) = self.find_scsi_device(&args.id).unwrap();
+        let locked_cntlr = cntlr.lock().unwrap();
+        locked_cntlr.lun_changed(target, lun, ScsiCntlr::VIRTIO_SCSI_EVT_RESET_RESCAN)
+    }
+
+    /// Hot unplug the scsi lun from the virtio-scsi bus, return false if no lun has the id.
+    fn unplug_scsi_device(&mut self, id: &str) -> Result<bool> {
+        let (cntlr, bus, (target, lun)) = match self.find_scsi_device(id) {
+            Some(found) => found,
+            None => return Ok(false),
+        };
+        let device = bus.lock().unwrap().devices.remove(&(target, lun));
+        if let Some(dev) = device {
+            dev.lock().unwrap().unrealize();
+        }
+        self.del_bootindex_devices(id);
+        let vm_config = self.get_vm_config();
+        vm_config.lock().unwrap().del_device_by_id(id.to_string());
+
+        let locked_cntlr = cntlr.lock().unwrap();
+        locked_cntlr.lun_changed(target, lun, ScsiCntlr::VIRTIO_SCSI_EVT_RESET_REMOVED)?;
+        Ok(true)
+    }
+
+    /// Clean up the machine wide configuration of the pci device which is being unplugged.
+    fn del_pci_device_config(&mut self, device_id: &str) {
+        let vm_config = self.get_vm_config();
+        let mut locked_config = vm_config.lock().unwrap();
+        if matches!(&locked_config.virtio_serial, Some(serial) if serial.id == device_id) {
+            locked_config.virtio_serial = None;
+            *self.get_virtio_serial() = None;
+        }
+        let driver = locked_config.get_device_driver(device_id);
+        if matches!(driver, Some(driver) if driver == "virtio-balloon-pci") {
+            locked_config.dev_name.remove("balloon");
+        }
+        locked_config.del_device_by_id(device_id.to_string());
+    }
+
     fn plug_vfio_pci_device(
         &mut self,
         bdf: &PciBdf,
@@ -1017,6 +1345,15 @@ impl StdMachine {
         }
         Ok(())
     }
+
+    /// Get the block backend of `device`, the VM must be paused to keep the
+    /// disk consistent while operating on internal snapshots.
+    fn get_paused_block_backend(&self, device: &str) -> Result<BlockBackend> {
+        if *self.get_vm_state().deref().0.lock().unwrap() != KvmVmState::Paused {
+            bail!("VM must be paused to operate internal snapshots");
+        }
+        get_block_backend(device).with_context(|| format!("Block device {} not found", device))
+    }
 }
 
 impl DeviceInterface for StdMachine {
@@ -1121,6 +1458,21 @@ impl DeviceInterface for StdMachine {
         )
     }
 
+    fn query_block(&self) -> Response {
+        let block_info = block_backend::stats::query_block();
+        Response::create_response(serde_json::to_value(block_info).unwrap(), None)
+    }
+
+    fn query_named_block_nodes(&self) -> Response {
+        let nodes = block_backend::stats::query_named_block_nodes();
+        Response::create_response(serde_json::to_value(nodes).unwrap(), None)
+    }
+
+    fn query_blockstats(&self) -> Response {
+        let stats = block_backend::stats::query_blockstats();
+        Response::create_response(serde_json::to_value(stats).unwrap(), None)
+    }
+
     fn device_add(&mut self, args: Box<qmp_schema::DeviceAddArgument>) -> Response {
         if let Err(e) = self.check_device_id_existed(&args.id) {
             return Response::create_error_response(
@@ -1129,6 +1481,36 @@ impl DeviceInterface for StdMachine {
             );
         }
 
+        // The serial ports are attached to the virtio-serial device rather than pci bus.
+        if args.driver == "virtconsole" || args.driver == "virtserialport" {
+            return match self.plug_virtio_serial_port(args.as_ref()) {
+                Ok(()) => Response::create_empty_response(),
+                Err(e) => {
+                    error!("{:?}", e);
+                    let err_str = format!("Failed to add virtio serial port: {}", e);
+                    Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(err_str),
+                        None,
+                    )
+                }
+            };
+        }
+
+        // The scsi luns are attached to the virtio-scsi bus rather than pci bus.
+        if args.driver == "scsi-hd" || args.driver == "scsi-cd" {
+            return match self.plug_scsi_device(args.as_ref()) {
+                Ok(()) => Response::create_empty_response(),
+                Err(e) => {
+                    error!("{:?}", e);
+                    let err_str = format!("Failed to add scsi device: {}", e);
+                    Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(err_str),
+                        None,
+                    )
+                }
+            };
+        }
+
         // Use args.bus.clone() and args.addr.clone() because args borrowed in the following process.
         let pci_bdf = match get_device_bdf(args.bus.clone(), args.addr.clone()) {
             Ok(bdf) => bdf,
@@ -1190,6 +1572,47 @@ impl DeviceInterface for StdMachine {
                     );
                 }
             }
+            "virtio-rng-pci" => {
+                if let Err(e) = self.plug_virtio_pci_rng(args.as_ref()) {
+                    error!("{:?}", e);
+                    let err_str = format!("Failed to add virtio pci rng: {}", e);
+                    return Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(err_str),
+                        None,
+                    );
+                }
+            }
+            "virtio-balloon-pci" => {
+                if let Err(e) = self.plug_virtio_pci_balloon(args.as_ref()) {
+                    error!("{:?}", e);
+                    let err_str = format!("Failed to add virtio pci balloon: {}", e);
+                    return Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(err_str),
+                        None,
+                    );
+                }
+            }
+            "virtio-serial-pci" => {
+                if let Err(e) = self.plug_virtio_pci_serial(args.as_ref()) {
+                    error!("{:?}", e);
+                    let err_str = format!("Failed to add virtio pci serial: {}", e);
+                    return Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(err_str),
+                        None,
+                    );
+                }
+            }
+            #[cfg(not(target_env = "musl"))]
+            "virtio-gpu-pci" => {
+                if let Err(e) = self.plug_virtio_pci_gpu(args.as_ref()) {
+                    error!("{:?}", e);
+                    let err_str = format!("Failed to add virtio pci gpu: {}", e);
+                    return Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(err_str),
+                        None,
+                    );
+                }
+            }
             _ => {
                 let err_str = format!("Failed to add device: Driver {} is not support", driver);
                 return Response::create_error_response(
@@ -1227,6 +1650,62 @@ impl DeviceInterface for StdMachine {
     }
 
     fn device_del(&mut self, device_id: String) -> Response {
+        if let Some(serial) = self.get_virtio_serial().clone() {
+            let mut locked_serial = serial.lock().unwrap();
+            if locked_serial.has_port(&device_id) {
+                if let Err(e) = locked_serial.remove_port(&device_id) {
+                    return Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                        None,
+                    );
+                }
+                drop(locked_serial);
+                let vm_config = self.get_vm_config();
+                vm_config
+                    .lock()
+                    .unwrap()
+                    .del_device_by_id(device_id.clone());
+                let port_del_event = qmp_schema::DeviceDeleted {
+                    device: Some(device_id.clone()),
+                    path: device_id,
+                };
+                event!(DeviceDeleted; port_del_event);
+                return Response::create_empty_response();
+            }
+            let is_serial = matches!(
+                &self.get_vm_config().lock().unwrap().virtio_serial,
+                Some(serial) if serial.id == device_id
+            );
+            if locked_serial.has_ports() && is_serial {
+                let err_str = format!(
+                    "Failed to remove device: ports of virtio-serial {} should be removed first",
+                    &device_id
+                );
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(err_str),
+                    None,
+                );
+            }
+        }
+
+        match self.unplug_scsi_device(&device_id) {
+            Ok(true) => {
+                let lun_del_event = qmp_schema::DeviceDeleted {
+                    device: Some(device_id.clone()),
+                    path: device_id,
+                };
+                event!(DeviceDeleted; lun_del_event);
+                return Response::create_empty_response();
+            }
+            Ok(false) => (),
+            Err(e) => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                )
+            }
+        }
+
         let pci_host = match self.get_pci_host() {
             Ok(host) => host,
             Err(e) => {
@@ -1245,10 +1724,7 @@ impl DeviceInterface for StdMachine {
                     let dev_id = locked_dev.name();
                     drop(locked_pci_host);
                     self.del_bootindex_devices(&dev_id);
-                    let vm_config = self.get_vm_config();
-                    let mut locked_config = vm_config.lock().unwrap();
-                    locked_config.del_device_by_id(device_id);
-                    drop(locked_config);
+                    self.del_pci_device_config(&device_id);
                     Response::create_empty_response()
                 }
                 Err(e) => Response::create_error_response(
@@ -1269,6 +1745,56 @@ impl DeviceInterface for StdMachine {
         } else {
             true
         };
+        let format = match args
+            .driver
+            .as_ref()
+            .map_or(Ok(DiskFormat::Raw), |driver| DiskFormat::from_str(driver))
+        {
+            Ok(format) => format,
+            Err(_) => {
+                let err_str = format!("Unsupported block driver {:?}", args.driver);
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(err_str),
+                    None,
+                );
+            }
+        };
+        let discard = match args.discard.as_deref() {
+            None | Some("ignore") => false,
+            Some("unmap") => true,
+            Some(other) => {
+                let err_str = format!("Invalid discard option {:?}", other);
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(err_str),
+                    None,
+                );
+            }
+        };
+        let write_zeroes = match args
+            .detect_zeroes
+            .as_ref()
+            .map_or(Ok(WriteZeroesState::Off), |state| {
+                WriteZeroesState::from_str(state)
+            }) {
+            Ok(state) => state,
+            Err(_) => {
+                let err_str = format!("Invalid detect-zeroes option {:?}", args.detect_zeroes);
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(err_str),
+                    None,
+                );
+            }
+        };
+        let (werror, rerror) =
+            match parse_block_error_policies(args.werror.as_deref(), args.rerror.as_deref()) {
+                Ok(policies) => policies,
+                Err(e) => {
+                    return Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                        None,
+                    );
+                }
+            };
         let config = DriveConfig {
             id: args.node_name,
             path_on_host: args.file.filename.clone(),
@@ -1281,6 +1807,11 @@ impl DeviceInterface for StdMachine {
             } else {
                 AioEngine::Off
             },
+            format,
+            discard,
+            write_zeroes,
+            werror,
+            rerror,
         };
 
         if let Err(e) = config.check() {
@@ -1344,6 +1875,57 @@ impl DeviceInterface for StdMachine {
         }
     }
 
+    fn blockdev_snapshot_internal_sync(&self, device: String, name: String) -> Response {
+        let backend = match self.get_paused_block_backend(&device) {
+            Ok(backend) => backend,
+            Err(e) => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                );
+            }
+        };
+        let mut locked_backend = backend.lock().unwrap();
+        match locked_backend.create_snapshot(name, 0) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn blockdev_snapshot_delete_internal_sync(&self, device: String, name: String) -> Response {
+        let backend = match self.get_paused_block_backend(&device) {
+            Ok(backend) => backend,
+            Err(e) => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                );
+            }
+        };
+        let mut locked_backend = backend.lock().unwrap();
+        match locked_backend.delete_snapshot(name) {
+            Ok(info) => {
+                let snapshot = qmp_schema::SnapshotInfo {
+                    id: info.id,
+                    name: info.name,
+                    vm_state_size: info.vm_state_size,
+                    date_sec: info.date_sec,
+                    date_nsec: info.date_nsec,
+                    vm_clock_sec: info.vm_clock_nsec / 1_000_000_000,
+                    vm_clock_nsec: info.vm_clock_nsec % 1_000_000_000,
+                };
+                Response::create_response(serde_json::to_value(&snapshot).unwrap(), None)
+            }
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
     fn chardev_add(&mut self, args: qmp_schema::CharDevAddArgument) -> Response {
         let config = match get_chardev_config(args) {
             Ok(conf) => conf,
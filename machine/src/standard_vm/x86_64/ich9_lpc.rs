@@ -19,8 +19,10 @@ use super::VENDOR_ID_INTEL;
 use crate::standard_vm::Result;
 use acpi::{AcpiPMTimer, AcpiPmCtrl, AcpiPmEvent};
 use address_space::{AddressSpace, GuestAddress, Region, RegionOps};
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use log::error;
+use migration::{DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager, StateTransfer};
+use migration_derive::{ByteCode, Desc};
 use pci::config::CLASS_CODE_ISA_BRIDGE;
 use pci::config::{
     PciConfig, DEVICE_ID, HEADER_TYPE, HEADER_TYPE_BRIDGE, HEADER_TYPE_MULTIFUNC,
@@ -40,6 +42,24 @@ pub const PM_CTRL_OFFSET: u16 = 0x604;
 pub const SLEEP_CTRL_OFFSET: u16 = 0xCE9;
 pub const RST_CTRL_OFFSET: u16 = 0xCF9;
 
+/// Status of LPC bridge of ICH9.
+#[repr(C)]
+#[derive(Copy, Clone, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+pub struct LPCBridgeState {
+    config_space: [u8; 256],
+    /// Nanoseconds elapsed since PM timer started.
+    pm_timer_nanos: u64,
+    /// PM1 status register.
+    pm_evt_status: u16,
+    /// PM1 enable register.
+    pm_evt_enable: u16,
+    /// PM1 control register.
+    pm_ctrl: u16,
+    /// Reset control register.
+    rst_ctrl: u8,
+}
+
 /// LPC bridge of ICH9 (IO controller hub 9), Device 1F : Function 0
 #[allow(clippy::upper_case_acronyms)]
 pub struct LPCBridge {
@@ -266,13 +286,15 @@ impl PciDevOps for LPCBridge {
             .with_context(|| "Fail to init IO region for PM control register")?;
 
         let parent_bus = self.parent_bus.clone();
+        let lpc = Arc::new(Mutex::new(self));
         parent_bus
             .upgrade()
             .unwrap()
             .lock()
             .unwrap()
             .devices
-            .insert(0x1F << 3, Arc::new(Mutex::new(self)));
+            .insert(0x1F << 3, lpc.clone());
+        MigrationManager::register_device_instance(LPCBridgeState::descriptor(), lpc, "ich9_lpc");
         Ok(())
     }
 
@@ -300,3 +322,55 @@ impl PciDevOps for LPCBridge {
         "ICH9 LPC bridge".to_string()
     }
 }
+
+impl StateTransfer for LPCBridge {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        let mut state = LPCBridgeState::default();
+        state.config_space.copy_from_slice(&self.config.config);
+        state.pm_timer_nanos = self.pm_timer.lock().unwrap().elapsed_nanos();
+        let pm_evt = self.pm_evt.lock().unwrap();
+        state.pm_evt_status = pm_evt.status;
+        state.pm_evt_enable = pm_evt.enable;
+        state.pm_ctrl = self.pm_ctrl.lock().unwrap().control;
+        state.rst_ctrl = self.rst_ctrl.load(Ordering::SeqCst);
+
+        Ok(state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        let lpc_state = LPCBridgeState::from_bytes(state)
+            .ok_or_else(|| anyhow!(migration::MigrationError::FromBytesError("ICH9_LPC")))?;
+
+        self.config.config = lpc_state.config_space.to_vec();
+        self.pm_timer
+            .lock()
+            .unwrap()
+            .set_elapsed_nanos(lpc_state.pm_timer_nanos);
+        let mut pm_evt = self.pm_evt.lock().unwrap();
+        pm_evt.status = lpc_state.pm_evt_status;
+        pm_evt.enable = lpc_state.pm_evt_enable;
+        self.pm_ctrl.lock().unwrap().control = lpc_state.pm_ctrl;
+        self.rst_ctrl.store(lpc_state.rst_ctrl, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&LPCBridgeState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+impl MigrationHook for LPCBridge {
+    fn resume(&mut self) -> migration::Result<()> {
+        let mut pm_base_addr = 0_u32;
+        self.config
+            .read(PM_BASE_OFFSET as usize, pm_base_addr.as_mut_bytes());
+        // PM timer region is only mapped once firmware programs PM base.
+        if pm_base_addr != 0 {
+            self.update_pm_base()
+                .with_context(|| "Failed to restore PM timer mapping")?;
+        }
+
+        Ok(())
+    }
+}
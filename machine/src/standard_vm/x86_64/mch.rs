@@ -13,8 +13,10 @@
 use std::sync::{Arc, Mutex, Weak};
 
 use address_space::{Region, RegionOps};
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use log::error;
+use migration::{DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager, StateTransfer};
+use migration_derive::{ByteCode, Desc};
 use pci::{
     config::{
         PciConfig, CLASS_CODE_HOST_BRIDGE, DEVICE_ID, PCI_CONFIG_SPACE_SIZE, SUB_CLASS_CODE,
@@ -23,6 +25,8 @@ use pci::{
     le_read_u64, le_write_u16, ranges_overlap, PciBus, PciDevOps, Result as PciResult,
 };
 
+use util::byte_code::ByteCode;
+
 use super::VENDOR_ID_INTEL;
 
 const DEVICE_ID_INTEL_Q35_MCH: u16 = 0x29c0;
@@ -39,6 +43,14 @@ const PCIEXBAR_64MB_ADDR_MASK: u64 = 1 << 25;
 // Bit 25:3 of PCIEXBAR is reserved.
 const PCIEXBAR_RESERVED_MASK: u64 = 0x3ff_fff8;
 
+/// Status of Memory controller hub.
+#[repr(C)]
+#[derive(Copy, Clone, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+pub struct MchState {
+    config_space: [u8; 256],
+}
+
 /// Memory controller hub (Device 0:Function 0)
 pub struct Mch {
     config: PciConfig,
@@ -98,7 +110,8 @@ impl Mch {
                 .lock()
                 .unwrap()
                 .mem_region
-                .add_subregion(region, base_addr)?;
+                .add_subregion(region.clone(), base_addr)?;
+            self.mmconfig_region = Some(region);
         }
         Ok(())
     }
@@ -139,13 +152,15 @@ impl PciDevOps for Mch {
         )?;
 
         let parent_bus = self.parent_bus.clone();
+        let mch = Arc::new(Mutex::new(self));
         parent_bus
             .upgrade()
             .unwrap()
             .lock()
             .unwrap()
             .devices
-            .insert(0, Arc::new(Mutex::new(self)));
+            .insert(0, mch.clone());
+        MigrationManager::register_device_instance(MchState::descriptor(), mch, "mch");
         Ok(())
     }
 
@@ -171,3 +186,36 @@ impl PciDevOps for Mch {
         "Memory Controller Hub".to_string()
     }
 }
+
+impl StateTransfer for Mch {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        let mut state = MchState::default();
+        state.config_space.copy_from_slice(&self.config.config);
+
+        Ok(state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        let mch_state = MchState::from_bytes(state)
+            .ok_or_else(|| anyhow!(migration::MigrationError::FromBytesError("MCH")))?;
+        self.config.config = mch_state.config_space.to_vec();
+
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&MchState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+impl MigrationHook for Mch {
+    fn resume(&mut self) -> migration::Result<()> {
+        // PCIEXBAR is zero before firmware programs it.
+        if self.check_pciexbar_update(0) {
+            self.update_pciexbar_mapping()
+                .with_context(|| "Failed to restore PCIEXBAR mapping")?;
+        }
+
+        Ok(())
+    }
+}
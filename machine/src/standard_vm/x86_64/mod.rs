@@ -38,8 +38,8 @@ use devices::legacy::{
 use hypervisor::kvm::KVM_FDS;
 use kvm_bindings::{kvm_pit_config, KVM_PIT_SPEAKER_DUMMY};
 use machine_manager::config::{
-    parse_incoming_uri, BootIndexInfo, BootSource, DriveFile, Incoming, MigrateMode, NumaNode,
-    NumaNodes, PFlashConfig, SerialConfig, VmConfig,
+    get_pci_bdf, parse_incoming_uri, parse_pvpanic, BootIndexInfo, BootSource, DriveFile, Incoming,
+    MigrateMode, NumaNode, NumaNodes, PFlashConfig, SerialConfig, ShutdownAction, VmConfig,
 };
 use machine_manager::event;
 use machine_manager::event_loop::EventLoop;
@@ -49,8 +49,8 @@ use machine_manager::machine::{
 };
 use machine_manager::qmp::{qmp_schema, QmpChannel, Response};
 use mch::Mch;
-use migration::{MigrationManager, MigrationStatus};
-use pci::{PciDevOps, PciHost};
+use migration::{snapshot::PCI_HOST_SNAPSHOT_ID, MigrationManager, MigrationStatus};
+use pci::{PciDevOps, PciHost, PciHostState, PvPanicPci};
 use sysbus::{SysBus, IRQ_BASE, IRQ_MAX};
 use syscall::syscall_whitelist;
 use util::{
@@ -62,7 +62,7 @@ use super::error::StandardVmError;
 use super::{AcpiBuilder, StdMachineOps};
 use crate::{vm_state, MachineOps};
 use anyhow::{anyhow, bail, Context, Result};
-use virtio::ScsiCntlr::ScsiCntlrMap;
+use virtio::{Console, ScsiCntlr::ScsiCntlrMap};
 #[cfg(not(target_env = "musl"))]
 use vnc::vnc;
 
@@ -115,6 +115,8 @@ pub struct StdMachine {
     boot_source: Arc<Mutex<BootSource>>,
     /// Reset request, handle VM `Reset` event.
     reset_req: Arc<EventFd>,
+    /// Panic request, handle the guest panic reported by pvpanic.
+    panic_req: Arc<EventFd>,
     /// All configuration information of virtual machine.
     vm_config: Arc<Mutex<VmConfig>>,
     /// List of guest NUMA nodes information.
@@ -127,6 +129,8 @@ pub struct StdMachine {
     scsi_cntlr_list: ScsiCntlrMap,
     /// Drive backend files.
     drive_files: Arc<Mutex<HashMap<String, DriveFile>>>,
+    /// Virtio-serial device.
+    virtio_serial: Option<Arc<Mutex<Console>>>,
 }
 
 impl StdMachine {
@@ -173,12 +177,16 @@ impl StdMachine {
             reset_req: Arc::new(EventFd::new(libc::EFD_NONBLOCK).with_context(|| {
                 anyhow!(MachineError::InitEventFdErr("reset request".to_string()))
             })?),
+            panic_req: Arc::new(EventFd::new(libc::EFD_NONBLOCK).with_context(|| {
+                anyhow!(MachineError::InitEventFdErr("panic request".to_string()))
+            })?),
             vm_config: Arc::new(Mutex::new(vm_config.clone())),
             numa_nodes: None,
             boot_order_list: Arc::new(Mutex::new(Vec::new())),
             fwcfg_dev: None,
             scsi_cntlr_list: Arc::new(Mutex::new(HashMap::new())),
             drive_files: Arc::new(Mutex::new(vm_config.init_drive_files()?)),
+            virtio_serial: None,
         })
     }
 
@@ -295,6 +303,11 @@ impl StdMachineOps for StdMachine {
 
         let mch = Mch::new(root_bus, mmconfig_region, mmconfig_region_ops);
         mch.realize()?;
+        MigrationManager::register_device_instance(
+            PciHostState::descriptor(),
+            self.pci_host.clone(),
+            PCI_HOST_SNAPSHOT_ID,
+        );
         Ok(())
     }
 
@@ -390,6 +403,16 @@ impl MachineOps for StdMachine {
         })
     }
 
+    fn add_pvpanic(&mut self, cfg_args: &str) -> Result<()> {
+        let bdf = get_pci_bdf(cfg_args)?;
+        let (devfn, parent_bus) = self.get_devfn_and_parent_bus(&bdf)?;
+        let id = parse_pvpanic(cfg_args)?;
+        let pvpanic = PvPanicPci::new(id, devfn, parent_bus, self.panic_req.clone());
+        pvpanic
+            .realize()
+            .with_context(|| "Failed to realize pvpanic device")
+    }
+
     fn add_rtc_device(&mut self, mem_size: u64) -> Result<()> {
         let mut rtc = RTC::new().with_context(|| "Failed to create RTC device")?;
         rtc.set_memory(
@@ -420,11 +443,18 @@ impl MachineOps for StdMachine {
         self.drive_files.clone()
     }
 
+    fn get_virtio_serial(&mut self) -> &mut Option<Arc<Mutex<Console>>> {
+        &mut self.virtio_serial
+    }
+
     fn realize(vm: &Arc<Mutex<Self>>, vm_config: &mut VmConfig) -> Result<()> {
         let nr_cpus = vm_config.machine_config.nr_cpus;
         let clone_vm = vm.clone();
         let mut locked_vm = vm.lock().unwrap();
         locked_vm.init_global_config(vm_config)?;
+        locked_vm
+            .register_panic_event(locked_vm.panic_req.clone(), vm.clone())
+            .with_context(|| "Fail to register panic event")?;
         locked_vm.numa_nodes = locked_vm.add_numa_nodes(vm_config)?;
         locked_vm.init_memory(
             &vm_config.machine_config.mem_config,
@@ -466,9 +496,11 @@ impl MachineOps for StdMachine {
             &boot_config,
         )?);
 
-        if migrate.0 == MigrateMode::Unknown && fwcfg.is_some() {
+        // ACPI tables only live in fw_cfg, so they are rebuilt on incoming side as
+        // well to keep them available to firmware after the guest reboots.
+        if let Some(fwcfg) = fwcfg {
             locked_vm
-                .build_acpi_tables(&fwcfg.unwrap())
+                .build_acpi_tables(&fwcfg)
                 .with_context(|| "Failed to create ACPI tables")?;
         }
 
@@ -824,6 +856,14 @@ impl MachineLifecycle for StdMachine {
         true
     }
 
+    fn get_shutdown_action(&self) -> ShutdownAction {
+        self.vm_config
+            .lock()
+            .unwrap()
+            .machine_config
+            .shutdown_action
+    }
+
     fn reset(&mut self) -> bool {
         if self.reset_req.write(1).is_err() {
             error!("X86 standard vm write reset request failed");
@@ -889,9 +929,19 @@ impl MachineAddressInterface for StdMachine {
 }
 
 impl MigrateInterface for StdMachine {
-    fn migrate(&self, uri: String) -> Response {
+    fn migrate(&self, uri: String, parent: Option<String>, incremental: Option<bool>) -> Response {
         match parse_incoming_uri(&uri) {
-            Ok((MigrateMode::File, path)) => migration::snapshot(path),
+            Ok((MigrateMode::File, path)) => {
+                migration::snapshot(path, parent, incremental.unwrap_or(false))
+            }
+            Ok(_) if parent.is_some() || incremental == Some(true) => {
+                Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(
+                        "Parent and incremental are only supported by snapshot".to_string(),
+                    ),
+                    None,
+                )
+            }
             Ok((MigrateMode::Unix, path)) => migration::migration_unix_mode(path),
             Ok((MigrateMode::Tcp, path)) => migration::migration_tcp_mode(path),
             _ => Response::create_error_response(
@@ -908,6 +958,14 @@ impl MigrateInterface for StdMachine {
     fn cancel_migrate(&self) -> Response {
         migration::cancel_migrate()
     }
+
+    fn migrate_set_parameters(&self, args: qmp_schema::MigrateSetParametersArgument) -> Response {
+        migration::migrate_set_parameters(args)
+    }
+
+    fn query_migrate_parameters(&self) -> Response {
+        migration::query_migrate_parameters()
+    }
 }
 
 impl MachineInterface for StdMachine {}
@@ -10,6 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::net::TcpListener;
 use std::os::unix::net::UnixListener;
 
 use anyhow::{bail, Context, Result};
@@ -18,6 +19,7 @@ use util::unix::{limit_permission, parse_unix_uri};
 
 use crate::{
     config::{add_trace_events, ChardevType, CmdParser, MachineType, VmConfig},
+    socket::SocketListener,
     temp_cleaner::TempCleaner,
 };
 
@@ -181,10 +183,11 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
         )
         .arg(
             Arg::with_name("qmp")
+            .multiple(true)
             .long("qmp")
-            .value_name("unix:<socket_path>")
-            .help("set QMP's unix socket path")
-            .takes_value(true)
+            .value_name("unix:<socket_path> or tcp:<host>:<port>[,tls-creds=<tls_id>]")
+            .help("set QMP's unix socket path or tcp address, can be used several times")
+            .takes_values(true)
         )
         .arg(
             Arg::with_name("mod-test")
@@ -217,7 +220,7 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             Arg::with_name("chardev")
             .multiple(true)
             .long("chardev")
-            .value_name("socket,id=<str>,path=<socket_path>")
+            .value_name("socket,id=<str>,path=<socket_path> or guest-agent,id=<str>")
             .help("set char device virtio console for vm")
             .takes_values(true),
         )
@@ -233,8 +236,9 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
                    \n\t\tadd virtio pci net: -device virtio-net-pci,id=<net_id>,netdev=<netdev_id>,bus=<pcie.0>,addr=<0x2>[,multifunction=on|off][,iothread=<iothread1>][,mac=<12:34:56:78:9A:BC>][,mq=on|off]; \
                    \n\t\tadd vhost mmio net: -device virtio-net-device,id=<net_id>,netdev=<netdev_id>[,iothread=<iothread1>][,mac=<12:34:56:78:9A:BC>]; \
                    \n\t\tadd vhost pci net: -device virtio-net-pci,id=<net_id>,netdev=<netdev_id>,bus=<pcie.0>,addr=<0x2>[,multifunction=on|off][,iothread=<iothread1>][,mac=<12:34:56:78:9A:BC>][,mq=on|off]; \
-                   \n\t\tadd virtio mmio console: -device virtio-serial-device[,id=<virtio-serial0>] -device virtconsole,id=console_id,chardev=<virtioconsole1>; \
-                   \n\t\tadd virtio pci console: -device virtio-serial-pci,id=<virtio-serial0>,bus=<pcie.0>,addr=<0x3>[,multifunction=on|off] -device virtconsole,id=<console_id>,chardev=<virtioconsole1>; \
+                   \n\t\tadd virtio mmio console: -device virtio-serial-device[,id=<virtio-serial0>][,max_ports=<3>] -device virtconsole,id=console_id,chardev=<virtioconsole1>[,nr=<0>]; \
+                   \n\t\tadd virtio pci console: -device virtio-serial-pci,id=<virtio-serial0>,bus=<pcie.0>,addr=<0x3>[,multifunction=on|off][,max_ports=<15>] -device virtconsole,id=<console_id>,chardev=<virtioconsole1>[,nr=<0>]; \
+                   \n\t\tadd virtio serial port: -device virtserialport,id=<port_id>,chardev=<chardev_id>[,nr=<1>][,name=<port_name>]; \
                    \n\t\tadd vhost mmio vsock: -device vhost-vsock-device,id=<vsock_id>,guest-cid=<N>; \
                    \n\t\tadd vhost pci vsock: -device vhost-vsock-pci,id=<vsock_id>,guest-cid=<N>,bus=<pcie.0>,addr=<0x3>[,multifunction=on|off]; \
                    \n\t\tadd virtio mmio balloon: -device virtio-balloon-device[,deflate-on-oom=true|false][,free-page-reporting=true|false]; \
@@ -246,6 +250,7 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
                    \n\t\tadd usb controller: -device nec-usb-xhci,id=<xhci>,bus=<pcie.0>,addr=<0xa>; \
                    \n\t\tadd usb keyboard: -device usb-kbd,id=<kbd>; \
                    \n\t\tadd usb tablet-device usb-tablet,id=<tablet>; \
+                   \n\t\tadd usb storage: -device usb-storage,id=<storage>,drive=<drive_id>; \
                    \n\t\tadd scsi controller: -device virtio-scsi-pci,id=<scsi_id>,bus=<pcie.0>,addr=<0x3>[,multifunction=on|off][,iothread=<iothread1>][,num-queues=<N>]; \
                    \n\t\tadd scsi hard disk: -device scsi-hd,scsi-id=<0>,bus=<scsi0.0>,lun=<0>,drive=<drive-scsi0-0-0-0>,id=<scsi0-0-0-0>; \
                    \n\t\tadd vhost user fs: -device vhost-user-fs-pci,id=<device_id>,chardev=<chardev_id>,tag=<mount_tag>")
@@ -312,10 +317,11 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
         )
         .arg(
             Arg::with_name("mon")
+            .multiple(true)
             .long("mon")
             .value_name("chardev=<chardev_id>,id=<mon_id>[,mode=control]")
             .help("-mon is another way to create qmp channel. To use it, the chardev should be specified")
-            .takes_value(true),
+            .takes_values(true),
         )
         .arg(
             Arg::with_name("overcommit")
@@ -514,7 +520,15 @@ pub fn create_vmconfig(args: &ArgMatches) -> Result<VmConfig> {
     Ok(vm_cfg)
 }
 
-/// This function is to parse qmp socket path and type.
+/// Api channel to accept qmp clients, given by `-qmp` or `-mon`.
+pub struct ApiChannel {
+    /// Listener of the channel.
+    pub listener: SocketListener,
+    /// Id of the tls credentials object to encrypt the channel.
+    pub tls_creds: Option<String>,
+}
+
+/// This function is to parse qmp socket paths and types.
 ///
 /// # Arguments
 ///
@@ -523,28 +537,58 @@ pub fn create_vmconfig(args: &ArgMatches) -> Result<VmConfig> {
 /// # Errors
 ///
 /// The value of `qmp` is illegel.
-pub fn check_api_channel(args: &ArgMatches, vm_config: &mut VmConfig) -> Result<Vec<UnixListener>> {
-    let mut sock_paths = Vec::new();
-    if let Some(qmp_config) = args.value_of("qmp") {
+pub fn check_api_channel(args: &ArgMatches, vm_config: &mut VmConfig) -> Result<Vec<ApiChannel>> {
+    let mut channels = Vec::new();
+    for qmp_config in args.values_of("qmp").unwrap_or_default() {
         let mut cmd_parser = CmdParser::new("qmp");
-        cmd_parser.push("").push("server").push("nowait");
+        cmd_parser
+            .push("")
+            .push("server")
+            .push("nowait")
+            .push("tls-creds");
 
         cmd_parser.parse(&qmp_config)?;
-        if let Some(uri) = cmd_parser.get_value::<String>("")? {
-            let api_path =
-                parse_unix_uri(&uri).with_context(|| "Failed to parse qmp socket path")?;
-            sock_paths.push(api_path);
+        let uri = if let Some(uri) = cmd_parser.get_value::<String>("")? {
+            uri
         } else {
             bail!("No uri found for qmp");
-        }
+        };
         if cmd_parser.get_value::<String>("server")?.is_none() {
             bail!("Argument \'server\' is needed for qmp");
         }
         if cmd_parser.get_value::<String>("nowait")?.is_none() {
             bail!("Argument \'nowait\' is needed for qmp");
         }
+        let tls_creds = cmd_parser.get_value::<String>("tls-creds")?;
+        if let Some(id) = &tls_creds {
+            if !uri.starts_with("tcp:") {
+                bail!("Argument \'tls-creds\' is only supported by tcp qmp socket");
+            }
+            if !vm_config.object.tls_object.contains_key(id) {
+                bail!("No tls-creds object found: {}", id);
+            }
+        }
+
+        let listener = if uri.starts_with("tcp:") {
+            let addr = parse_tcp_uri(&uri).with_context(|| "Failed to parse qmp socket address")?;
+            SocketListener::Tcp(
+                TcpListener::bind(&addr)
+                    .with_context(|| format!("Failed to bind socket for address: {}", &addr))?,
+            )
+        } else {
+            let path = parse_unix_uri(&uri).with_context(|| "Failed to parse qmp socket path")?;
+            SocketListener::Unix(
+                bind_socket(path.clone())
+                    .with_context(|| format!("Failed to bind socket for path: {:?}", &path))?,
+            )
+        };
+        channels.push(ApiChannel {
+            listener,
+            tls_creds,
+        });
     }
-    if let Some(mon_config) = args.value_of("mon") {
+
+    for mon_config in args.values_of("mon").unwrap_or_default() {
         let mut cmd_parser = CmdParser::new("monitor");
         cmd_parser.push("id").push("mode").push("chardev");
 
@@ -577,7 +621,14 @@ pub fn check_api_channel(args: &ArgMatches, vm_config: &mut VmConfig) -> Result<
                         path
                     );
                 }
-                sock_paths.push(path);
+                channels.push(ApiChannel {
+                    listener: SocketListener::Unix(
+                        bind_socket(path.clone()).with_context(|| {
+                            format!("Failed to bind socket for path: {:?}", &path)
+                        })?,
+                    ),
+                    tls_creds: None,
+                });
             } else {
                 bail!("Only socket-type of chardev can be used for monitor");
             }
@@ -586,18 +637,25 @@ pub fn check_api_channel(args: &ArgMatches, vm_config: &mut VmConfig) -> Result<
         }
     }
 
-    if sock_paths.is_empty() {
-        bail!("Please use \'-qmp\' or \'-mon\' to give a qmp path for Unix socket");
-    }
-    let mut listeners = Vec::new();
-    for path in sock_paths {
-        listeners.push(
-            bind_socket(path.clone())
-                .with_context(|| format!("Failed to bind socket for path: {:?}", &path))?,
-        )
+    if channels.is_empty() {
+        bail!("Please use \'-qmp\' or \'-mon\' to give a qmp socket");
     }
 
-    Ok(listeners)
+    Ok(channels)
+}
+
+/// Parse tcp uri as `tcp:<host>:<port>` to socket address.
+fn parse_tcp_uri(uri: &str) -> Result<String> {
+    let addr = match uri.strip_prefix("tcp:") {
+        Some(addr) => addr,
+        None => bail!("Invalid tcp uri: {}", uri),
+    };
+    match addr.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+            Ok(addr.to_string())
+        }
+        _ => bail!("Invalid tcp uri: {}", uri),
+    }
 }
 
 fn bind_socket(path: String) -> Result<UnixListener> {
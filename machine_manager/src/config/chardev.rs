@@ -15,11 +15,17 @@ use log::error;
 use serde::{Deserialize, Serialize};
 
 use super::{error::ConfigError, get_pci_bdf, pci_args_check, PciBdf};
-use crate::config::{CmdParser, ConfigCheck, ExBool, VmConfig, MAX_PATH_LENGTH, MAX_STRING_LENGTH};
+use crate::config::{
+    CmdParser, ConfigCheck, ExBool, VmConfig, MAX_PATH_LENGTH, MAX_STRING_LENGTH, MAX_VIRTIO_QUEUE,
+};
 use crate::qmp::qmp_schema;
 
 const MAX_GUEST_CID: u64 = 4_294_967_295;
 const MIN_GUEST_CID: u64 = 3;
+/// Every port owns a pair of queues, and one more pair is taken by the control queues.
+const MAX_SERIAL_PORTS_PCI: u32 = (MAX_VIRTIO_QUEUE / 2 - 1) as u32;
+/// Virtio-mmio devices support 8 queues at most.
+const MAX_SERIAL_PORTS_MMIO: u32 = 3;
 
 /// Charecter device options.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -32,13 +38,45 @@ pub enum ChardevType {
         nowait: bool,
     },
     File(String),
+    /// Channel connected to the qemu guest agent running in the guest, which
+    /// is driven by the guest-* QMP commands instead of an external client.
+    GuestAgent,
 }
 
-/// Config structure for virtio-console.
+/// Config structure for virtio-serial port, both virtconsole and virtserialport.
 #[derive(Debug, Clone)]
-pub struct VirtioConsole {
+pub struct VirtioSerialPort {
     pub id: String,
     pub chardev: ChardevConfig,
+    /// Port number, allocated by the virtio-serial device if not set.
+    pub nr: Option<u32>,
+    /// Name of the port, the guest can find it in /dev/virtio-ports/.
+    pub name: Option<String>,
+    pub is_console: bool,
+}
+
+impl ConfigCheck for VirtioSerialPort {
+    fn check(&self) -> Result<()> {
+        if self.id.len() > MAX_STRING_LENGTH {
+            return Err(anyhow!(ConfigError::StringLengthTooLong(
+                "virtio serial port id".to_string(),
+                MAX_STRING_LENGTH,
+            )));
+        }
+        if let Some(name) = &self.name {
+            if name.len() > MAX_STRING_LENGTH {
+                return Err(anyhow!(ConfigError::StringLengthTooLong(
+                    "virtio serial port name".to_string(),
+                    MAX_STRING_LENGTH,
+                )));
+            }
+        }
+        if self.nr == Some(0) && !self.is_console {
+            bail!("Port number 0 of virtio-serial is reserved for virtconsole");
+        }
+
+        Ok(())
+    }
 }
 
 /// Config structure for character device.
@@ -79,7 +117,7 @@ fn check_chardev_args(cmd_parser: CmdParser) -> Result<()> {
         let server = cmd_parser.get_value::<String>("server")?;
         let nowait = cmd_parser.get_value::<String>("nowait")?;
         match chardev_str {
-            "stdio" | "pty" | "file" => {
+            "stdio" | "pty" | "file" | "guest-agent" => {
                 if server.is_some() {
                     bail!(
                         "Chardev of {}-type does not support \'server\' argument",
@@ -140,6 +178,7 @@ pub fn parse_chardev(cmd_parser: CmdParser) -> Result<ChardevConfig> {
         match backend.as_str() {
             "stdio" => ChardevType::Stdio,
             "pty" => ChardevType::Pty,
+            "guest-agent" => ChardevType::GuestAgent,
             "socket" => {
                 if let Some(path) = path {
                     ChardevType::Socket {
@@ -254,31 +293,78 @@ pub fn get_chardev_socket_path(chardev: &str, vm_config: &mut VmConfig) -> Resul
     }
 }
 
-pub fn parse_virtconsole(vm_config: &mut VmConfig, config_args: &str) -> Result<VirtioConsole> {
-    let mut cmd_parser = CmdParser::new("virtconsole");
-    cmd_parser.push("").push("id").push("chardev");
+/// Parse the virtconsole or virtserialport, the ports are attached to the virtio-serial device.
+///
+/// # Arguments
+///
+/// * `vm_config` - VM configuration.
+/// * `config_args` - Port configuration args.
+/// * `is_console` - The port is virtconsole or not.
+pub fn parse_virtserialport(
+    vm_config: &mut VmConfig,
+    config_args: &str,
+    is_console: bool,
+) -> Result<VirtioSerialPort> {
+    let dev_name = if is_console {
+        "virtconsole"
+    } else {
+        "virtserialport"
+    };
+    let mut cmd_parser = CmdParser::new(dev_name);
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("chardev")
+        .push("nr")
+        .push("name");
     cmd_parser.parse(config_args)?;
 
+    let max_ports = if let Some(serial) = &vm_config.virtio_serial {
+        serial.max_ports
+    } else {
+        bail!("No virtio-serial-bus specified for {}", dev_name);
+    };
+
     let chardev_name = if let Some(chardev) = cmd_parser.get_value::<String>("chardev")? {
         chardev
     } else {
-        return Err(anyhow!(ConfigError::FieldIsMissing(
-            "chardev",
-            "virtconsole"
-        )));
+        return Err(anyhow!(ConfigError::FieldIsMissing("chardev", dev_name)));
     };
 
     let id = if let Some(chardev_id) = cmd_parser.get_value::<String>("id")? {
         chardev_id
     } else {
-        return Err(anyhow!(ConfigError::FieldIsMissing("id", "virtconsole")));
+        return Err(anyhow!(ConfigError::FieldIsMissing("id", dev_name)));
+    };
+
+    let nr = cmd_parser.get_value::<u32>("nr")?;
+    if let Some(nr) = nr {
+        if nr >= max_ports {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "port number".to_string(),
+                0,
+                true,
+                max_ports as u64,
+                false,
+            )));
+        }
+    }
+
+    let mut port = VirtioSerialPort {
+        id,
+        chardev: ChardevConfig {
+            id: String::new(),
+            backend: ChardevType::Stdio,
+        },
+        nr,
+        name: cmd_parser.get_value::<String>("name")?,
+        is_console,
     };
+    port.check()?;
 
     if let Some(char_dev) = vm_config.chardev.remove(&chardev_name) {
-        return Ok(VirtioConsole {
-            id,
-            chardev: char_dev,
-        });
+        port.chardev = char_dev;
+        return Ok(port);
     }
     bail!("Chardev {:?} not found or is in use", &chardev_name);
 }
@@ -445,6 +531,8 @@ pub struct VirtioSerialInfo {
     pub id: String,
     pub pci_bdf: Option<PciBdf>,
     pub multifunction: bool,
+    /// Max number of ports, including the console port 0.
+    pub max_ports: u32,
 }
 
 impl ConfigCheck for VirtioSerialInfo {
@@ -456,6 +544,21 @@ impl ConfigCheck for VirtioSerialInfo {
             )));
         }
 
+        let max_ports = if self.pci_bdf.is_some() {
+            MAX_SERIAL_PORTS_PCI
+        } else {
+            MAX_SERIAL_PORTS_MMIO
+        };
+        if self.max_ports < 1 || self.max_ports > max_ports {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "virtio-serial max_ports".to_string(),
+                1,
+                true,
+                max_ports as u64,
+                true,
+            )));
+        }
+
         Ok(())
     }
 }
@@ -467,7 +570,8 @@ pub fn parse_virtio_serial(vm_config: &mut VmConfig, serial_config: &str) -> Res
         .push("id")
         .push("bus")
         .push("addr")
-        .push("multifunction");
+        .push("multifunction")
+        .push("max_ports");
     cmd_parser.parse(serial_config)?;
     pci_args_check(&cmd_parser)?;
 
@@ -488,12 +592,18 @@ pub fn parse_virtio_serial(vm_config: &mut VmConfig, serial_config: &str) -> Res
                 id,
                 pci_bdf: Some(pci_bdf),
                 multifunction,
+                max_ports: cmd_parser
+                    .get_value::<u32>("max_ports")?
+                    .unwrap_or(MAX_SERIAL_PORTS_PCI),
             }
         } else {
             VirtioSerialInfo {
                 id,
                 pci_bdf: None,
                 multifunction,
+                max_ports: cmd_parser
+                    .get_value::<u32>("max_ports")?
+                    .unwrap_or(MAX_SERIAL_PORTS_MMIO),
             }
         };
         virtio_serial.check()?;
@@ -517,9 +627,10 @@ mod tests {
         assert!(vm_config
             .add_chardev("socket,id=test_console,path=/path/to/socket,server,nowait")
             .is_ok());
-        let virt_console = parse_virtconsole(
+        let virt_console = parse_virtserialport(
             &mut vm_config,
             "virtconsole,chardev=test_console,id=console1",
+            true,
         );
         assert!(virt_console.is_ok());
         let console_cfg = virt_console.unwrap();
@@ -547,9 +658,10 @@ mod tests {
         assert!(vm_config
             .add_chardev("socket,id=test_console,path=/path/to/socket,server,nowait")
             .is_ok());
-        let virt_console = parse_virtconsole(
+        let virt_console = parse_virtserialport(
             &mut vm_config,
             "virtconsole,chardev=test_console1,id=console1",
+            true,
         );
         // test_console1 does not exist.
         assert!(virt_console.is_err());
@@ -565,9 +677,10 @@ mod tests {
         assert!(vm_config
             .add_chardev("socket,id=test_console,path=/path/to/socket,server,nowait")
             .is_ok());
-        let virt_console = parse_virtconsole(
+        let virt_console = parse_virtserialport(
             &mut vm_config,
             "virtconsole,chardev=test_console,id=console1",
+            true,
         );
         assert!(virt_console.is_ok());
         let console_cfg = virt_console.unwrap();
@@ -595,6 +708,67 @@ mod tests {
         .is_ok());
     }
 
+    #[test]
+    fn test_virtserialport_config_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_chardev("socket,id=test_port,path=/path/to/socket,server,nowait")
+            .is_ok());
+        // No virtio-serial device.
+        assert!(parse_virtserialport(
+            &mut vm_config,
+            "virtserialport,chardev=test_port,id=port1",
+            false
+        )
+        .is_err());
+
+        assert!(parse_virtio_serial(
+            &mut vm_config,
+            "virtio-serial-pci,bus=pcie.0,addr=0x1,max_ports=4"
+        )
+        .is_ok());
+        assert_eq!(vm_config.virtio_serial.as_ref().unwrap().max_ports, 4);
+        // Port 0 is reserved for virtconsole.
+        assert!(parse_virtserialport(
+            &mut vm_config,
+            "virtserialport,chardev=test_port,id=port1,nr=0",
+            false
+        )
+        .is_err());
+        assert!(parse_virtserialport(
+            &mut vm_config,
+            "virtserialport,chardev=test_port,id=port1,nr=4",
+            false
+        )
+        .is_err());
+        let port = parse_virtserialport(
+            &mut vm_config,
+            "virtserialport,chardev=test_port,id=port1,nr=3,name=org.qemu.guest_agent.0",
+            false,
+        )
+        .unwrap();
+        assert_eq!(port.id, "port1");
+        assert_eq!(port.nr, Some(3));
+        assert_eq!(port.name, Some("org.qemu.guest_agent.0".to_string()));
+        assert!(!port.is_console);
+        assert_eq!(port.chardev.id, "test_port");
+
+        let mut vm_config = VmConfig::default();
+        assert!(parse_virtio_serial(&mut vm_config, "virtio-serial-device,max_ports=4").is_err());
+        assert!(parse_virtio_serial(
+            &mut vm_config,
+            "virtio-serial-pci,bus=pcie.0,addr=0x1,max_ports=0"
+        )
+        .is_err());
+        assert!(parse_virtio_serial(
+            &mut vm_config,
+            "virtio-serial-pci,bus=pcie.0,addr=0x1,max_ports=16"
+        )
+        .is_err());
+        assert!(parse_virtio_serial(&mut vm_config, "virtio-serial-device").is_ok());
+        assert_eq!(vm_config.virtio_serial.as_ref().unwrap().max_ports, 3);
+    }
+
     #[test]
     fn test_vsock_config_cmdline_parser() {
         let vsock_cfg_op = parse_vsock("vhost-vsock-device,id=test_vsock,guest-cid=3");
@@ -637,5 +811,14 @@ mod tests {
         } else {
             assert!(false);
         }
+
+        assert!(vm_config.add_chardev("guest-agent,id=qga0").is_ok());
+        assert_eq!(
+            vm_config.chardev.get("qga0").unwrap().backend,
+            ChardevType::GuestAgent
+        );
+        assert!(vm_config
+            .add_chardev("guest-agent,id=qga1,server,nowait")
+            .is_err());
     }
 }
@@ -27,6 +27,17 @@ impl VmConfig {
         Ok(())
     }
 
+    /// Get the driver of the device with the id.
+    pub fn get_device_driver(&self, dev_id: &str) -> Option<String> {
+        let rex = format!("id={}(,|$)", dev_id);
+        let re = Regex::new(rex.as_str()).unwrap();
+
+        self.devices
+            .iter()
+            .find(|(_, dev_info)| re.is_match(dev_info.as_str()))
+            .map(|(driver, _)| driver.clone())
+    }
+
     pub fn del_device_by_id(&mut self, dev_id: String) {
         let rex = format!("id={}(,|$)", dev_id);
         let re = Regex::new(rex.as_str()).unwrap();
@@ -70,4 +81,27 @@ mod tests {
         let id = ret.unwrap();
         assert_eq!("", id);
     }
+
+    #[test]
+    fn test_get_device_driver() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_device("virtio-balloon-pci,id=balloon0,bus=pcie.0,addr=0x4")
+            .is_ok());
+        assert!(vm_config
+            .add_device("virtio-rng-pci,id=rng,rng=objrng0,bus=pcie.0,addr=0x5")
+            .is_ok());
+        assert_eq!(
+            vm_config.get_device_driver("balloon0"),
+            Some("virtio-balloon-pci".to_string())
+        );
+        assert_eq!(
+            vm_config.get_device_driver("rng"),
+            Some("virtio-rng-pci".to_string())
+        );
+        assert_eq!(vm_config.get_device_driver("balloon"), None);
+
+        vm_config.del_device_by_id("balloon0".to_string());
+        assert_eq!(vm_config.get_device_driver("balloon0"), None);
+    }
 }
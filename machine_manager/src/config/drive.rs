@@ -13,6 +13,7 @@
 use std::fs::{metadata, File};
 use std::os::linux::fs::MetadataExt;
 use std::path::Path;
+use std::str::FromStr;
 
 use anyhow::{anyhow, bail, Result};
 use log::error;
@@ -24,7 +25,7 @@ use crate::config::{
     MAX_PATH_LENGTH, MAX_STRING_LENGTH, MAX_VIRTIO_QUEUE,
 };
 use crate::qmp::qmp_schema;
-use util::aio::{aio_probe, AioEngine};
+use util::aio::{aio_probe, AioEngine, WriteZeroesState};
 const MAX_SERIAL_NUM: usize = 20;
 const MAX_IOPS: u64 = 1_000_000;
 const MAX_UNIT_ID: usize = 2;
@@ -34,6 +35,91 @@ const MIN_QUEUE_SIZE_BLK: u16 = 2;
 // Max size of each virtqueue for virtio-blk.
 const MAX_QUEUE_SIZE_BLK: u16 = 1024;
 
+/// Format of the drive backend file.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy)]
+pub enum DiskFormat {
+    Raw,
+    Qcow2,
+}
+
+impl FromStr for DiskFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(DiskFormat::Raw),
+            "qcow2" => Ok(DiskFormat::Qcow2),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What to do when a disk I/O request fails, set by the `werror` and `rerror`
+/// options of the drive for write and read requests.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy)]
+pub enum BlockErrorPolicy {
+    /// Pause the VM, the failed request is retried when the VM is resumed.
+    Stop,
+    /// Report the error to the guest.
+    Report,
+    /// Ignore the error and complete the request successfully.
+    Ignore,
+    /// Pause the VM if the host device has no space left, report other errors.
+    Enospc,
+}
+
+impl Default for BlockErrorPolicy {
+    fn default() -> Self {
+        BlockErrorPolicy::Report
+    }
+}
+
+impl FromStr for BlockErrorPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "stop" => Ok(BlockErrorPolicy::Stop),
+            "report" => Ok(BlockErrorPolicy::Report),
+            "ignore" => Ok(BlockErrorPolicy::Ignore),
+            "enospc" => Ok(BlockErrorPolicy::Enospc),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Parse the `werror` and `rerror` options of a drive, `report` is used for the
+/// options not given.
+pub fn parse_block_error_policies(
+    werror: Option<&str>,
+    rerror: Option<&str>,
+) -> Result<(BlockErrorPolicy, BlockErrorPolicy)> {
+    let parse = |name: &str, policy: Option<&str>| {
+        policy.map_or(Ok(BlockErrorPolicy::Report), |policy| {
+            BlockErrorPolicy::from_str(policy).map_err(|_| {
+                anyhow!(ConfigError::InvalidParam(
+                    format!("{}={}", name, policy),
+                    "drive".to_string()
+                ))
+            })
+        })
+    };
+    Ok((parse("werror", werror)?, parse("rerror", rerror)?))
+}
+
+impl BlockErrorPolicy {
+    /// Get the action to take on a request failed with `errno`.
+    pub fn action(&self, errno: i32) -> qmp_schema::BlockErrorAction {
+        match self {
+            BlockErrorPolicy::Stop => qmp_schema::BlockErrorAction::stop,
+            BlockErrorPolicy::Report => qmp_schema::BlockErrorAction::report,
+            BlockErrorPolicy::Ignore => qmp_schema::BlockErrorAction::ignore,
+            BlockErrorPolicy::Enospc if errno == libc::ENOSPC => qmp_schema::BlockErrorAction::stop,
+            BlockErrorPolicy::Enospc => qmp_schema::BlockErrorAction::report,
+        }
+    }
+}
+
 /// Represent a single drive backend file.
 pub struct DriveFile {
     /// The opened file.
@@ -68,6 +154,11 @@ pub struct BlkDevConfig {
     pub socket_path: Option<String>,
     pub aio: AioEngine,
     pub queue_size: u16,
+    pub format: DiskFormat,
+    pub discard: bool,
+    pub write_zeroes: WriteZeroesState,
+    pub werror: BlockErrorPolicy,
+    pub rerror: BlockErrorPolicy,
 }
 
 #[derive(Debug, Clone)]
@@ -93,6 +184,11 @@ impl Default for BlkDevConfig {
             socket_path: None,
             aio: AioEngine::Native,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            format: DiskFormat::Raw,
+            discard: false,
+            write_zeroes: WriteZeroesState::Off,
+            werror: BlockErrorPolicy::Report,
+            rerror: BlockErrorPolicy::Report,
         }
     }
 }
@@ -108,6 +204,11 @@ pub struct DriveConfig {
     pub direct: bool,
     pub iops: Option<u64>,
     pub aio: AioEngine,
+    pub format: DiskFormat,
+    pub discard: bool,
+    pub write_zeroes: WriteZeroesState,
+    pub werror: BlockErrorPolicy,
+    pub rerror: BlockErrorPolicy,
 }
 
 impl Default for DriveConfig {
@@ -119,6 +220,11 @@ impl Default for DriveConfig {
             direct: true,
             iops: None,
             aio: AioEngine::Native,
+            format: DiskFormat::Raw,
+            discard: false,
+            write_zeroes: WriteZeroesState::Off,
+            werror: BlockErrorPolicy::Report,
+            rerror: BlockErrorPolicy::Report,
         }
     }
 }
@@ -271,10 +377,8 @@ impl ConfigCheck for BlkDevConfig {
 fn parse_drive(cmd_parser: CmdParser) -> Result<DriveConfig> {
     let mut drive = DriveConfig::default();
 
-    if let Some(format) = cmd_parser.get_value::<String>("format")? {
-        if format.ne("raw") {
-            bail!("Only \'raw\' type of block is supported");
-        }
+    if let Some(format) = cmd_parser.get_value::<DiskFormat>("format")? {
+        drive.format = format;
     }
 
     if let Some(id) = cmd_parser.get_value::<String>("id")? {
@@ -303,6 +407,27 @@ fn parse_drive(cmd_parser: CmdParser) -> Result<DriveConfig> {
             AioEngine::Off
         }
     });
+    if let Some(discard) = cmd_parser.get_value::<String>("discard")? {
+        drive.discard = match discard.as_str() {
+            "unmap" => true,
+            "ignore" => false,
+            _ => {
+                return Err(anyhow!(ConfigError::InvalidParam(
+                    discard,
+                    "discard".to_string()
+                )))
+            }
+        };
+    }
+    drive.write_zeroes = cmd_parser
+        .get_value::<WriteZeroesState>("detect-zeroes")?
+        .unwrap_or(WriteZeroesState::Off);
+    drive.werror = cmd_parser
+        .get_value::<BlockErrorPolicy>("werror")?
+        .unwrap_or_default();
+    drive.rerror = cmd_parser
+        .get_value::<BlockErrorPolicy>("rerror")?
+        .unwrap_or_default();
     drive.check()?;
     #[cfg(not(test))]
     drive.check_path()?;
@@ -373,6 +498,11 @@ pub fn parse_blk(
         blkdevcfg.direct = drive_arg.direct;
         blkdevcfg.iops = drive_arg.iops;
         blkdevcfg.aio = drive_arg.aio;
+        blkdevcfg.format = drive_arg.format;
+        blkdevcfg.discard = drive_arg.discard;
+        blkdevcfg.write_zeroes = drive_arg.write_zeroes;
+        blkdevcfg.werror = drive_arg.werror;
+        blkdevcfg.rerror = drive_arg.rerror;
     } else {
         bail!("No drive configured matched for blk device");
     }
@@ -505,7 +635,11 @@ impl VmConfig {
             .push("format")
             .push("if")
             .push("throttling.iops-total")
-            .push("aio");
+            .push("aio")
+            .push("discard")
+            .push("detect-zeroes")
+            .push("werror")
+            .push("rerror");
 
         cmd_parser.parse(block_config)?;
         let drive_cfg = parse_drive(cmd_parser)?;
@@ -683,6 +817,119 @@ mod tests {
             None,
         );
         assert!(blk_cfg_res.is_err()); // Can not find drive named "rootfs1".
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,format=qcow2")
+            .is_ok());
+        let blk_cfg_res = parse_blk(
+            &mut vm_config,
+            "virtio-blk-device,drive=rootfs,id=rootfs",
+            None,
+        );
+        assert!(blk_cfg_res.is_ok());
+        assert_eq!(blk_cfg_res.unwrap().format, DiskFormat::Qcow2);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,format=vmdk")
+            .is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,discard=unmap,detect-zeroes=unmap")
+            .is_ok());
+        let blk_cfg_res = parse_blk(
+            &mut vm_config,
+            "virtio-blk-device,drive=rootfs,id=rootfs",
+            None,
+        );
+        assert!(blk_cfg_res.is_ok());
+        let blk_device_config = blk_cfg_res.unwrap();
+        assert!(blk_device_config.discard);
+        assert_eq!(blk_device_config.write_zeroes, WriteZeroesState::Unmap);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,discard=on")
+            .is_err());
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,detect-zeroes=true")
+            .is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,werror=enospc,rerror=ignore")
+            .is_ok());
+        let blk_device_config = parse_blk(
+            &mut vm_config,
+            "virtio-blk-device,drive=rootfs,id=rootfs",
+            None,
+        )
+        .unwrap();
+        assert_eq!(blk_device_config.werror, BlockErrorPolicy::Enospc);
+        assert_eq!(blk_device_config.rerror, BlockErrorPolicy::Ignore);
+        assert_eq!(
+            blk_device_config.werror.action(libc::ENOSPC),
+            qmp_schema::BlockErrorAction::stop
+        );
+        assert_eq!(
+            blk_device_config.werror.action(libc::EIO),
+            qmp_schema::BlockErrorAction::report
+        );
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,werror=pause")
+            .is_err());
+    }
+
+    #[test]
+    fn test_block_error_policy_action() {
+        for errno in [libc::EIO, libc::ENOSPC, libc::EPERM] {
+            assert_eq!(
+                BlockErrorPolicy::Stop.action(errno),
+                qmp_schema::BlockErrorAction::stop
+            );
+            assert_eq!(
+                BlockErrorPolicy::Report.action(errno),
+                qmp_schema::BlockErrorAction::report
+            );
+            assert_eq!(
+                BlockErrorPolicy::Ignore.action(errno),
+                qmp_schema::BlockErrorAction::ignore
+            );
+        }
+        assert_eq!(
+            BlockErrorPolicy::Enospc.action(libc::ENOSPC),
+            qmp_schema::BlockErrorAction::stop
+        );
+        assert_eq!(
+            BlockErrorPolicy::Enospc.action(libc::EIO),
+            qmp_schema::BlockErrorAction::report
+        );
+        assert_eq!(
+            BlockErrorPolicy::Enospc.action(libc::EPERM),
+            qmp_schema::BlockErrorAction::report
+        );
+    }
+
+    #[test]
+    fn test_parse_block_error_policies() {
+        assert_eq!(
+            parse_block_error_policies(None, None).unwrap(),
+            (BlockErrorPolicy::Report, BlockErrorPolicy::Report)
+        );
+        assert_eq!(
+            parse_block_error_policies(Some("stop"), Some("ignore")).unwrap(),
+            (BlockErrorPolicy::Stop, BlockErrorPolicy::Ignore)
+        );
+        assert_eq!(
+            parse_block_error_policies(Some("enospc"), None).unwrap(),
+            (BlockErrorPolicy::Enospc, BlockErrorPolicy::Report)
+        );
+        assert!(parse_block_error_policies(Some("pause"), None).is_err());
+        assert!(parse_block_error_policies(None, Some("")).is_err());
     }
 
     #[test]
@@ -20,9 +20,44 @@ use crate::config::{
     MAX_STRING_LENGTH, MAX_VIRTIO_QUEUE,
 };
 use crate::qmp::{qmp_schema, QmpChannel};
+use util::net_backend::{NetSocketAddr, NetSocketConfig};
+use util::slirp::{HostFwd, SlirpConfig};
 
 const MAC_ADDRESS_LENGTH: usize = 17;
 
+/// Arguments which are only supported by the specific netdev type.
+const NETDEV_TYPE_ARGS: [(&str, &[&str]); 4] = [
+    ("user", &["net", "host", "dns", "dhcpstart", "restrict"]),
+    (
+        "socket",
+        &["listen", "connect", "mcast", "udp", "localaddr"],
+    ),
+    (
+        "stream",
+        &[
+            "server",
+            "addr.type",
+            "addr.host",
+            "addr.port",
+            "addr.path",
+            "reconnect",
+        ],
+    ),
+    (
+        "dgram",
+        &[
+            "local.type",
+            "local.host",
+            "local.port",
+            "local.path",
+            "remote.type",
+            "remote.host",
+            "remote.port",
+            "remote.path",
+        ],
+    ),
+];
+
 /// Max virtqueue size of each virtqueue.
 pub const MAX_QUEUE_SIZE_NET: u16 = 4096;
 
@@ -35,6 +70,10 @@ pub struct NetDevcfg {
     pub ifname: String,
     pub queues: u16,
     pub chardev: Option<String>,
+    /// Config of the userspace network backend.
+    pub user_net: Option<SlirpConfig>,
+    /// Config of the socket network backend.
+    pub socket: Option<NetSocketConfig>,
 }
 
 impl Default for NetDevcfg {
@@ -47,6 +86,8 @@ impl Default for NetDevcfg {
             ifname: "".to_string(),
             queues: 2,
             chardev: None,
+            user_net: None,
+            socket: None,
         }
     }
 }
@@ -104,6 +145,8 @@ pub struct NetworkInterfaceConfig {
     pub socket_path: Option<String>,
     /// All queues of a net device have the same queue size now.
     pub queue_size: u16,
+    pub user_net: Option<SlirpConfig>,
+    pub socket: Option<NetSocketConfig>,
 }
 
 impl Default for NetworkInterfaceConfig {
@@ -120,6 +163,8 @@ impl Default for NetworkInterfaceConfig {
             mq: false,
             socket_path: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            user_net: None,
+            socket: None,
         }
     }
 }
@@ -193,14 +238,186 @@ fn parse_fds(cmd_parser: &CmdParser, name: &str) -> Result<Option<Vec<i32>>> {
     }
 }
 
-fn parse_netdev(cmd_parser: CmdParser) -> Result<NetDevcfg> {
+/// Build the config of userspace network.
+///
+/// # Arguments
+///
+/// * `net` - The virtual network with format `addr[/prefix_len]`.
+/// * `host` - Address of the gateway.
+/// * `dns` - Address of the DNS server.
+/// * `dhcp_start` - The first address assigned by DHCP.
+/// * `hostfwds` - Host forwarding rules with format
+///   `[tcp|udp]:[hostaddr]:hostport-[guestaddr]:guestport`.
+/// * `restrict` - Forbid guest to reach the loopback of host through the gateway.
+fn build_user_net(
+    net: Option<String>,
+    host: Option<String>,
+    dns: Option<String>,
+    dhcp_start: Option<String>,
+    hostfwds: &[String],
+    restrict: Option<bool>,
+) -> Result<SlirpConfig> {
+    let mut config = SlirpConfig::default();
+    if let Some(restrict) = restrict {
+        config.restrict = restrict;
+    }
+    if let Some(net) = net {
+        config.set_net(&net)?;
+    }
+    if let Some(host) = host {
+        config.host = host
+            .parse()
+            .map_err(|_| anyhow!(ConfigError::ConvertValueFailed(host, "host".to_string())))?;
+    }
+    if let Some(dns) = dns {
+        config.dns = dns
+            .parse()
+            .map_err(|_| anyhow!(ConfigError::ConvertValueFailed(dns, "dns".to_string())))?;
+    }
+    if let Some(dhcp_start) = dhcp_start {
+        config.dhcp_start = dhcp_start.parse().map_err(|_| {
+            anyhow!(ConfigError::ConvertValueFailed(
+                dhcp_start,
+                "dhcpstart".to_string()
+            ))
+        })?;
+    }
+    for hostfwd in hostfwds {
+        config.hostfwds.push(hostfwd.parse::<HostFwd>()?);
+    }
+    config.check()?;
+    Ok(config)
+}
+
+/// Parse the inet address with format `[host]:port`, the port can be omitted
+/// if `need_port` is false.
+fn parse_inet_addr(addr: &str, name: &str, need_port: bool) -> Result<NetSocketAddr> {
+    if !need_port && !addr.contains(':') {
+        return Ok(NetSocketAddr::Inet {
+            host: addr.to_string(),
+            port: 0,
+        });
+    }
+    addr.parse::<NetSocketAddr>().map_err(|_| {
+        anyhow!(ConfigError::ConvertValueFailed(
+            addr.to_string(),
+            name.to_string()
+        ))
+    })
+}
+
+/// Build the config of socket netdev.
+///
+/// # Arguments
+///
+/// * `listen` - Listen on `[host]:port` and wait for the connection.
+/// * `connect` - Connect to `host:port`.
+/// * `mcast` - Join the multicast group `maddr:port`.
+/// * `udp` - Send the frames to `host:port` by udp.
+/// * `localaddr` - Local address of `udp`, or the interface address of `mcast`.
+fn build_socket_net(
+    listen: Option<String>,
+    connect: Option<String>,
+    mcast: Option<String>,
+    udp: Option<String>,
+    localaddr: Option<String>,
+) -> Result<NetSocketConfig> {
+    if (listen.is_some() || connect.is_some()) && localaddr.is_some() {
+        bail!("Argument \'localaddr\' is only supported by \'mcast\' and \'udp\'");
+    }
+    let config = match (listen, connect, mcast, udp) {
+        (Some(listen), None, None, None) => NetSocketConfig::Stream {
+            server: true,
+            addr: parse_inet_addr(&listen, "listen", true)?,
+            reconnect: 0,
+        },
+        (None, Some(connect), None, None) => NetSocketConfig::Stream {
+            server: false,
+            addr: parse_inet_addr(&connect, "connect", true)?,
+            reconnect: 0,
+        },
+        (None, None, Some(mcast), None) => {
+            let remote = parse_inet_addr(&mcast, "mcast", true)?;
+            if remote.multicast_group().is_none() {
+                bail!("{} is not an ipv4 multicast address", mcast);
+            }
+            NetSocketConfig::Dgram {
+                local: localaddr
+                    .map(|addr| parse_inet_addr(&addr, "localaddr", false))
+                    .transpose()?,
+                remote: Some(remote),
+            }
+        }
+        (None, None, None, Some(udp)) => NetSocketConfig::Dgram {
+            local: Some(parse_inet_addr(
+                &localaddr
+                    .ok_or_else(|| anyhow!(ConfigError::FieldIsMissing("localaddr", "udp")))?,
+                "localaddr",
+                true,
+            )?),
+            remote: Some(parse_inet_addr(&udp, "udp", true)?),
+        },
+        _ => bail!(
+            "Socket netdev needs exactly one of \'listen\', \'connect\', \'mcast\' and \'udp\'"
+        ),
+    };
+    config.check()?;
+    Ok(config)
+}
+
+/// Parse the socket address with arguments `<prefix>.type`, `<prefix>.host`,
+/// `<prefix>.port` and `<prefix>.path`.
+fn parse_socket_addr(cmd_parser: &CmdParser, prefix: &str) -> Result<Option<NetSocketAddr>> {
+    let addr_type = cmd_parser.get_value::<String>(&format!("{}.type", prefix))?;
+    let host = cmd_parser.get_value::<String>(&format!("{}.host", prefix))?;
+    let port = cmd_parser.get_value::<u16>(&format!("{}.port", prefix))?;
+    let path = cmd_parser.get_value::<String>(&format!("{}.path", prefix))?;
+    match addr_type.as_deref() {
+        Some("inet") => {
+            if path.is_some() {
+                bail!(
+                    "Argument \'{}.path\' is not supported by inet address",
+                    prefix
+                );
+            }
+            let port = port.ok_or_else(|| {
+                anyhow!("Argument \'{}.port\' is missing for inet address", prefix)
+            })?;
+            Ok(Some(NetSocketAddr::Inet {
+                host: host.unwrap_or_default(),
+                port,
+            }))
+        }
+        Some("unix") => {
+            if host.is_some() || port.is_some() {
+                bail!(
+                    "Argument \'{0}.host\' and \'{0}.port\' are not supported by unix address",
+                    prefix
+                );
+            }
+            let path = path.ok_or_else(|| {
+                anyhow!("Argument \'{}.path\' is missing for unix address", prefix)
+            })?;
+            Ok(Some(NetSocketAddr::Unix { path }))
+        }
+        Some(addr_type) => bail!("Unsupported address type {} of {}", addr_type, prefix),
+        None => {
+            if host.is_some() || port.is_some() || path.is_some() {
+                bail!("Argument \'{}.type\' is missing", prefix);
+            }
+            Ok(None)
+        }
+    }
+}
+
+fn parse_netdev(cmd_parser: CmdParser, hostfwds: &[String]) -> Result<NetDevcfg> {
     let mut net = NetDevcfg::default();
     let netdev_type = if let Some(netdev_type) = cmd_parser.get_value::<String>("")? {
         netdev_type
     } else {
         "".to_string()
     };
-    if netdev_type.ne("tap") && netdev_type.ne("vhost-user") {
+    if !["tap", "vhost-user", "user", "socket", "stream", "dgram"].contains(&netdev_type.as_str()) {
         bail!("Unsupported netdev type: {:?}", &netdev_type);
     }
     if let Some(net_id) = cmd_parser.get_value::<String>("id")? {
@@ -270,8 +487,89 @@ fn parse_netdev(cmd_parser: CmdParser) -> Result<NetDevcfg> {
     if net.vhost_fds.is_some() && net.vhost_type.is_none() {
         bail!("Argument \'vhostfd\' is not needed for virtio-net device");
     }
-    if net.tap_fds.is_none() && net.ifname.eq("") && netdev_type.ne("vhost-user") {
-        bail!("Tap device is missing, use \'ifname\' or \'fd\' to configure a tap device");
+
+    for (arg_type, params) in NETDEV_TYPE_ARGS.iter() {
+        if netdev_type.eq(arg_type) {
+            continue;
+        }
+        for param in params.iter() {
+            if cmd_parser.get_value::<String>(param)?.is_some() {
+                bail!(
+                    "Argument \'{}\' is only supported by {} netdev",
+                    param,
+                    arg_type
+                );
+            }
+        }
+    }
+    if netdev_type.ne("user") && !hostfwds.is_empty() {
+        bail!("Argument \'hostfwd\' is only supported by user netdev");
+    }
+
+    match netdev_type.as_str() {
+        "user" | "socket" | "stream" | "dgram" => {
+            if net.tap_fds.is_some() || !net.ifname.is_empty() || net.vhost_type.is_some() {
+                bail!(
+                    "Tap device and vhost are not supported by {} netdev",
+                    netdev_type
+                );
+            }
+            if net.queues != 2 {
+                bail!("{} netdev only supports one queue pair", netdev_type);
+            }
+        }
+        _ => {
+            if net.tap_fds.is_none() && net.ifname.eq("") && netdev_type.ne("vhost-user") {
+                bail!("Tap device is missing, use \'ifname\' or \'fd\' to configure a tap device");
+            }
+        }
+    }
+    match netdev_type.as_str() {
+        "user" => {
+            net.user_net = Some(build_user_net(
+                cmd_parser.get_value::<String>("net")?,
+                cmd_parser.get_value::<String>("host")?,
+                cmd_parser.get_value::<String>("dns")?,
+                cmd_parser.get_value::<String>("dhcpstart")?,
+                hostfwds,
+                cmd_parser
+                    .get_value::<ExBool>("restrict")?
+                    .map(|restrict| restrict.into()),
+            )?);
+        }
+        "socket" => {
+            net.socket = Some(build_socket_net(
+                cmd_parser.get_value::<String>("listen")?,
+                cmd_parser.get_value::<String>("connect")?,
+                cmd_parser.get_value::<String>("mcast")?,
+                cmd_parser.get_value::<String>("udp")?,
+                cmd_parser.get_value::<String>("localaddr")?,
+            )?);
+        }
+        "stream" => {
+            let mut server = false;
+            if let Some(value) = cmd_parser.get_value::<ExBool>("server")? {
+                server = value.inner;
+            }
+            let addr = parse_socket_addr(&cmd_parser, "addr")?
+                .ok_or_else(|| anyhow!(ConfigError::FieldIsMissing("addr.type", "netdev")))?;
+            let socket = NetSocketConfig::Stream {
+                server,
+                addr,
+                reconnect: cmd_parser.get_value::<u64>("reconnect")?.unwrap_or(0),
+            };
+            socket.check()?;
+            net.socket = Some(socket);
+        }
+        "dgram" => {
+            let socket = NetSocketConfig::Dgram {
+                local: parse_socket_addr(&cmd_parser, "local")?,
+                remote: parse_socket_addr(&cmd_parser, "remote")?,
+            };
+            socket.check()?;
+            net.socket = Some(socket);
+        }
+        _ => {}
     }
 
     net.check()?;
@@ -325,6 +623,8 @@ pub fn parse_net(vm_config: &mut VmConfig, net_config: &str) -> Result<NetworkIn
         netdevinterfacecfg.vhost_fds = netcfg.vhost_fds.clone();
         netdevinterfacecfg.vhost_type = netcfg.vhost_type.clone();
         netdevinterfacecfg.queues = netcfg.queues;
+        netdevinterfacecfg.user_net = netcfg.user_net.clone();
+        netdevinterfacecfg.socket = netcfg.socket.clone();
         if let Some(chardev) = &netcfg.chardev {
             netdevinterfacecfg.socket_path = Some(get_chardev_socket_path(chardev, vm_config)?);
         }
@@ -350,6 +650,8 @@ pub fn get_netdev_config(args: Box<qmp_schema::NetDevAddArgument>) -> Result<Net
         ifname: String::new(),
         queues,
         chardev: args.chardev,
+        user_net: None,
+        socket: None,
     };
 
     if let Some(fds) = args.fds {
@@ -411,7 +713,35 @@ pub fn get_netdev_config(args: Box<qmp_schema::NetDevAddArgument>) -> Result<Net
     if config.vhost_fds.is_some() && config.vhost_type.is_none() {
         bail!("Argument \'vhostfd\' is not needed for virtio-net device");
     }
-    if config.tap_fds.is_none() && config.ifname.eq("") && netdev_type.ne("vhost-user") {
+    if netdev_type.eq("user") || netdev_type.eq("socket") {
+        if config.tap_fds.is_some() || !config.ifname.is_empty() || config.vhost_type.is_some() {
+            bail!(
+                "Tap device and vhost are not supported by {} netdev",
+                netdev_type
+            );
+        }
+        if config.queues != 2 {
+            bail!("{} netdev only supports one queue pair", netdev_type);
+        }
+    }
+    if netdev_type.eq("user") {
+        config.user_net = Some(build_user_net(
+            args.net,
+            args.host,
+            args.dns,
+            args.dhcpstart,
+            &args.hostfwd.unwrap_or_default(),
+            args.restrict,
+        )?);
+    } else if netdev_type.eq("socket") {
+        config.socket = Some(build_socket_net(
+            args.listen,
+            args.connect,
+            args.mcast,
+            args.udp,
+            args.localaddr,
+        )?);
+    } else if config.tap_fds.is_none() && config.ifname.eq("") && netdev_type.ne("vhost-user") {
         bail!("Tap device is missing, use \'ifname\' or \'fd\' to configure a tap device");
     }
 
@@ -432,9 +762,24 @@ impl VmConfig {
             .push("vhostfds")
             .push("queues")
             .push("chardev");
+        for (_, params) in NETDEV_TYPE_ARGS.iter() {
+            for param in params.iter() {
+                cmd_parser.push(param);
+            }
+        }
 
-        cmd_parser.parse(netdev_config)?;
-        let drive_cfg = parse_netdev(cmd_parser)?;
+        // Argument `hostfwd` of user netdev can be repeated, which is not supported
+        // by `CmdParser`, so it's parsed separately.
+        let (hostfwds, params): (Vec<&str>, Vec<&str>) = netdev_config
+            .split(',')
+            .partition(|param| param.starts_with("hostfwd="));
+        let hostfwds: Vec<String> = hostfwds
+            .iter()
+            .map(|param| param["hostfwd=".len()..].to_string())
+            .collect();
+
+        cmd_parser.parse(&params.join(","))?;
+        let drive_cfg = parse_netdev(cmd_parser, &hostfwds)?;
         self.add_netdev_with_config(drive_cfg)
     }
 
@@ -533,6 +878,10 @@ fn is_netdev_queues_valid(queues: u16) -> bool {
 
 #[cfg(test)]
 mod tests {
+    use std::net::Ipv4Addr;
+
+    use util::slirp::HostFwdProto;
+
     use crate::config::get_pci_bdf;
 
     use super::*;
@@ -816,6 +1165,17 @@ mod tests {
             script: None,
             queues: None,
             chardev: None,
+            net: None,
+            host: None,
+            dns: None,
+            dhcpstart: None,
+            hostfwd: None,
+            restrict: None,
+            listen: None,
+            connect: None,
+            mcast: None,
+            udp: None,
+            localaddr: None,
         })
     }
 
@@ -899,5 +1259,259 @@ mod tests {
         let net_cfg = net_cfg.unwrap();
         assert_eq!(net_cfg.vhost_type.unwrap(), "vhost-kernel");
         assert_eq!(net_cfg.vhost_fds.unwrap()[0], 12);
+
+        let mut netdev_add = create_netdev_add(String::from("netdev"), None, None, None, None);
+        netdev_add.net_type = Some(String::from("user"));
+        netdev_add.net = Some(String::from("192.168.10.0/24"));
+        netdev_add.hostfwd = Some(vec![String::from("udp::5353-:53")]);
+        let net_cfg = get_netdev_config(netdev_add);
+        assert!(net_cfg.is_ok());
+        let user_net = net_cfg.unwrap().user_net.unwrap();
+        assert_eq!(user_net.host, Ipv4Addr::new(192, 168, 10, 2));
+        assert_eq!(user_net.hostfwds.len(), 1);
+        assert_eq!(user_net.hostfwds[0].proto, HostFwdProto::Udp);
+
+        // User netdev does not support tap device.
+        let mut netdev_add = create_netdev_add(
+            String::from("netdev"),
+            Some(String::from("tap0")),
+            None,
+            None,
+            None,
+        );
+        netdev_add.net_type = Some(String::from("user"));
+        assert!(get_netdev_config(netdev_add).is_err());
+
+        let mut netdev_add = create_netdev_add(String::from("netdev"), None, None, None, None);
+        netdev_add.net_type = Some(String::from("socket"));
+        netdev_add.connect = Some(String::from("127.0.0.1:1234"));
+        let net_cfg = get_netdev_config(netdev_add);
+        assert_eq!(
+            net_cfg.unwrap().socket,
+            Some(NetSocketConfig::Stream {
+                server: false,
+                addr: NetSocketAddr::Inet {
+                    host: String::from("127.0.0.1"),
+                    port: 1234
+                },
+                reconnect: 0,
+            })
+        );
+
+        // Socket netdev needs the address.
+        let mut netdev_add = create_netdev_add(String::from("netdev"), None, None, None, None);
+        netdev_add.net_type = Some(String::from("socket"));
+        assert!(get_netdev_config(netdev_add).is_err());
+    }
+
+    #[test]
+    fn test_user_netdev_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_netdev(
+                "user,id=eth0,hostfwd=tcp::2222-:22,hostfwd=udp:127.0.0.1:5353-10.0.2.20:53"
+            )
+            .is_ok());
+        let net_cfg = parse_net(
+            &mut vm_config,
+            "virtio-net-pci,id=net0,netdev=eth0,bus=pcie.0,addr=0x2.0x0",
+        )
+        .unwrap();
+        assert!(net_cfg.host_dev_name.is_empty());
+        assert!(net_cfg.tap_fds.is_none());
+        let user_net = net_cfg.user_net.unwrap();
+        let config = SlirpConfig {
+            hostfwds: vec![
+                HostFwd {
+                    proto: HostFwdProto::Tcp,
+                    host_addr: Ipv4Addr::UNSPECIFIED,
+                    host_port: 2222,
+                    guest_addr: None,
+                    guest_port: 22,
+                },
+                HostFwd {
+                    proto: HostFwdProto::Udp,
+                    host_addr: Ipv4Addr::LOCALHOST,
+                    host_port: 5353,
+                    guest_addr: Some(Ipv4Addr::new(10, 0, 2, 20)),
+                    guest_port: 53,
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(user_net, config);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_netdev("user,id=eth0,net=172.16.0.0/16,host=172.16.0.1,dhcpstart=172.16.1.1")
+            .is_ok());
+        let user_net = vm_config
+            .netdevs
+            .get("eth0")
+            .unwrap()
+            .user_net
+            .clone()
+            .unwrap();
+        assert_eq!(user_net.net, Ipv4Addr::new(172, 16, 0, 0));
+        assert_eq!(user_net.prefix_len, 16);
+        assert_eq!(user_net.host, Ipv4Addr::new(172, 16, 0, 1));
+        assert_eq!(user_net.dns, Ipv4Addr::new(172, 16, 0, 3));
+        assert_eq!(user_net.dhcp_start, Ipv4Addr::new(172, 16, 1, 1));
+        assert!(user_net.restrict);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_netdev("user,id=eth0,restrict=off").is_ok());
+        assert!(
+            !vm_config.netdevs["eth0"]
+                .user_net
+                .as_ref()
+                .unwrap()
+                .restrict
+        );
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_netdev("user,id=eth0,restrict=1").is_err());
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_netdev("tap,id=eth0,restrict=on").is_err());
+
+        let mut vm_config = VmConfig::default();
+        // Address out of the network.
+        assert!(vm_config.add_netdev("user,id=eth0,host=10.0.3.2").is_err());
+        // Invalid host forwarding rule.
+        assert!(vm_config
+            .add_netdev("user,id=eth0,hostfwd=tcp:2222-22")
+            .is_err());
+        // Tap device is not supported.
+        assert!(vm_config.add_netdev("user,id=eth0,ifname=tap0").is_err());
+        // Multiple queues are not supported.
+        assert!(vm_config.add_netdev("user,id=eth0,queues=2").is_err());
+        // User network arguments for tap.
+        assert!(vm_config
+            .add_netdev("tap,id=eth0,ifname=tap0,hostfwd=tcp::2222-:22")
+            .is_err());
+        assert!(vm_config
+            .add_netdev("tap,id=eth0,ifname=tap0,dns=10.0.2.3")
+            .is_err());
+    }
+
+    #[test]
+    fn test_socket_netdev_cmdline_parser() {
+        let inet = |host: &str, port| NetSocketAddr::Inet {
+            host: host.to_string(),
+            port,
+        };
+        let unix = |path: &str| NetSocketAddr::Unix {
+            path: path.to_string(),
+        };
+        let cases = [
+            (
+                "socket,id=eth0,listen=:1234",
+                NetSocketConfig::Stream {
+                    server: true,
+                    addr: inet("", 1234),
+                    reconnect: 0,
+                },
+            ),
+            (
+                "socket,id=eth0,connect=127.0.0.1:1234",
+                NetSocketConfig::Stream {
+                    server: false,
+                    addr: inet("127.0.0.1", 1234),
+                    reconnect: 0,
+                },
+            ),
+            (
+                "socket,id=eth0,mcast=230.0.0.1:1234,localaddr=192.168.1.1",
+                NetSocketConfig::Dgram {
+                    local: Some(inet("192.168.1.1", 0)),
+                    remote: Some(inet("230.0.0.1", 1234)),
+                },
+            ),
+            (
+                "socket,id=eth0,udp=127.0.0.1:1234,localaddr=127.0.0.1:1235",
+                NetSocketConfig::Dgram {
+                    local: Some(inet("127.0.0.1", 1235)),
+                    remote: Some(inet("127.0.0.1", 1234)),
+                },
+            ),
+            (
+                "stream,id=eth0,server=on,addr.type=unix,addr.path=/tmp/net.sock",
+                NetSocketConfig::Stream {
+                    server: true,
+                    addr: unix("/tmp/net.sock"),
+                    reconnect: 0,
+                },
+            ),
+            (
+                "stream,id=eth0,addr.type=inet,addr.host=127.0.0.1,addr.port=1234",
+                NetSocketConfig::Stream {
+                    server: false,
+                    addr: inet("127.0.0.1", 1234),
+                    reconnect: 0,
+                },
+            ),
+            (
+                "stream,id=eth0,addr.type=unix,addr.path=/tmp/net.sock,reconnect=3",
+                NetSocketConfig::Stream {
+                    server: false,
+                    addr: unix("/tmp/net.sock"),
+                    reconnect: 3,
+                },
+            ),
+            (
+                "dgram,id=eth0,remote.type=inet,remote.host=230.0.0.1,remote.port=1234",
+                NetSocketConfig::Dgram {
+                    local: None,
+                    remote: Some(inet("230.0.0.1", 1234)),
+                },
+            ),
+            (
+                "dgram,id=eth0,local.type=unix,local.path=/tmp/a.sock,remote.type=unix,remote.path=/tmp/b.sock",
+                NetSocketConfig::Dgram {
+                    local: Some(unix("/tmp/a.sock")),
+                    remote: Some(unix("/tmp/b.sock")),
+                },
+            ),
+        ];
+        for (cmdline, config) in cases {
+            let mut vm_config = VmConfig::default();
+            assert!(vm_config.add_netdev(cmdline).is_ok());
+            let net_cfg = parse_net(
+                &mut vm_config,
+                "virtio-net-pci,id=net0,netdev=eth0,bus=pcie.0,addr=0x2.0x0",
+            )
+            .unwrap();
+            assert_eq!(net_cfg.socket, Some(config));
+        }
+
+        let invalid_cases = [
+            // Address is missing or conflicts.
+            "socket,id=eth0",
+            "socket,id=eth0,listen=:1234,connect=127.0.0.1:1234",
+            "socket,id=eth0,listen=1234",
+            // Not a multicast address.
+            "socket,id=eth0,mcast=127.0.0.1:1234",
+            // Local address is missing.
+            "socket,id=eth0,udp=127.0.0.1:1234",
+            "socket,id=eth0,listen=:1234,localaddr=127.0.0.1",
+            "stream,id=eth0,server=on",
+            "stream,id=eth0,addr.type=inet,addr.host=127.0.0.1",
+            "stream,id=eth0,addr.type=unix,addr.port=1234,addr.path=/tmp/net.sock",
+            "stream,id=eth0,addr.type=vsock,addr.port=1234",
+            // Only the client of stream reconnects.
+            "stream,id=eth0,server=on,addr.type=unix,addr.path=/tmp/net.sock,reconnect=3",
+            "stream,id=eth0,addr.type=unix,addr.path=/tmp/net.sock,reconnect=-1",
+            "socket,id=eth0,connect=127.0.0.1:1234,reconnect=3",
+            "dgram,id=eth0,remote.type=inet,remote.host=127.0.0.1,remote.port=1234",
+            // Tap and multiple queues are not supported.
+            "socket,id=eth0,listen=:1234,ifname=tap0",
+            "socket,id=eth0,listen=:1234,queues=2",
+            // Socket arguments for other types.
+            "tap,id=eth0,ifname=tap0,listen=:1234",
+            "socket,id=eth0,listen=:1234,addr.type=inet",
+        ];
+        for cmdline in invalid_cases {
+            let mut vm_config = VmConfig::default();
+            assert!(vm_config.add_netdev(cmdline).is_err(), "{}", cmdline);
+        }
     }
 }
@@ -176,6 +176,24 @@ pub fn parse_root_port(rootport_cfg: &str) -> Result<RootPortConfig> {
     Ok(root_port)
 }
 
+/// Parse the cmdline of pvpanic device, returns its id.
+pub fn parse_pvpanic(pvpanic_cfg: &str) -> Result<String> {
+    let mut cmd_parser = CmdParser::new("pvpanic");
+    cmd_parser.push("").push("id").push("bus").push("addr");
+    cmd_parser.parse(pvpanic_cfg)?;
+
+    let id = cmd_parser
+        .get_value::<String>("id")?
+        .with_context(|| ConfigError::FieldIsMissing("id", "pvpanic"))?;
+    if id.len() > MAX_STRING_LENGTH {
+        return Err(anyhow!(ConfigError::StringLengthTooLong(
+            "pvpanic id".to_string(),
+            MAX_STRING_LENGTH,
+        )));
+    }
+    Ok(id)
+}
+
 pub fn pci_args_check(cmd_parser: &CmdParser) -> Result<()> {
     let device_type = cmd_parser.get_value::<String>("")?;
     let dev_type = device_type.unwrap();
@@ -277,4 +295,14 @@ mod tests {
         )
         .is_err());
     }
+
+    #[test]
+    fn test_parse_pvpanic() {
+        assert_eq!(
+            parse_pvpanic("pvpanic,id=pvpanic0,bus=pcie.0,addr=0x7").unwrap(),
+            "pvpanic0"
+        );
+        assert!(parse_pvpanic("pvpanic,bus=pcie.0,addr=0x7").is_err());
+        assert!(parse_pvpanic("pvpanic,id=pvpanic0,bus=pcie.0,addr=0x7,events=1").is_err());
+    }
 }
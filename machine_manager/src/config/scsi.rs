@@ -14,7 +14,8 @@ use anyhow::{anyhow, bail, Result};
 
 use super::{error::ConfigError, pci_args_check};
 use crate::config::{
-    CmdParser, ConfigCheck, VmConfig, DEFAULT_VIRTQUEUE_SIZE, MAX_STRING_LENGTH, MAX_VIRTIO_QUEUE,
+    BlockErrorPolicy, CmdParser, ConfigCheck, DiskFormat, VmConfig, DEFAULT_VIRTQUEUE_SIZE,
+    MAX_STRING_LENGTH, MAX_VIRTIO_QUEUE,
 };
 use util::aio::AioEngine;
 
@@ -153,7 +154,7 @@ pub fn parse_scsi_controller(
     Ok(cntlr_cfg)
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct ScsiDevConfig {
     /// Scsi Device id.
     pub id: String,
@@ -169,12 +170,18 @@ pub struct ScsiDevConfig {
     pub direct: bool,
     /// Async IO type.
     pub aio_type: AioEngine,
+    /// Format of the image file.
+    pub format: DiskFormat,
     /// Boot order.
     pub boot_index: Option<u8>,
     /// Scsi four level hierarchical address(host, channel, target, lun).
     pub channel: u8,
     pub target: u8,
     pub lun: u16,
+    /// Action on write error.
+    pub werror: BlockErrorPolicy,
+    /// Action on read error.
+    pub rerror: BlockErrorPolicy,
 }
 
 impl Default for ScsiDevConfig {
@@ -187,10 +194,13 @@ impl Default for ScsiDevConfig {
             read_only: false,
             direct: true,
             aio_type: AioEngine::Native,
+            format: DiskFormat::Raw,
             boot_index: None,
             channel: 0,
             target: 0,
             lun: 0,
+            werror: BlockErrorPolicy::Report,
+            rerror: BlockErrorPolicy::Report,
         }
     }
 }
@@ -271,6 +281,9 @@ pub fn parse_scsi_device(vm_config: &mut VmConfig, drive_config: &str) -> Result
         scsi_dev_cfg.read_only = drive_arg.read_only;
         scsi_dev_cfg.direct = drive_arg.direct;
         scsi_dev_cfg.aio_type = drive_arg.aio;
+        scsi_dev_cfg.format = drive_arg.format;
+        scsi_dev_cfg.werror = drive_arg.werror;
+        scsi_dev_cfg.rerror = drive_arg.rerror;
     }
 
     Ok(scsi_dev_cfg)
@@ -13,7 +13,7 @@
 use super::error::ConfigError;
 use anyhow::{anyhow, bail, Result};
 
-use crate::config::{CmdParser, ConfigCheck, MAX_STRING_LENGTH};
+use crate::config::{CmdParser, ConfigCheck, ScsiDevConfig, VmConfig, MAX_STRING_LENGTH};
 
 /// XHCI contoller configuration.
 #[derive(Debug)]
@@ -155,6 +155,68 @@ pub fn parse_usb_tablet(conf: &str) -> Result<UsbTabletConfig> {
     Ok(dev)
 }
 
+#[derive(Clone, Debug)]
+pub struct UsbStorageConfig {
+    /// USB storage device id.
+    pub id: String,
+    /// Configuration of the scsi disk which emulates the scsi commands.
+    pub scsi_cfg: ScsiDevConfig,
+}
+
+impl UsbStorageConfig {
+    fn new() -> Self {
+        UsbStorageConfig {
+            id: String::new(),
+            scsi_cfg: ScsiDevConfig::default(),
+        }
+    }
+}
+
+impl ConfigCheck for UsbStorageConfig {
+    fn check(&self) -> Result<()> {
+        check_id(&self.id)?;
+        if self.scsi_cfg.path_on_host.is_empty() {
+            bail!("No drive configured matched for usb storage device");
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_usb_storage(vm_config: &mut VmConfig, conf: &str) -> Result<UsbStorageConfig> {
+    let mut cmd_parser = CmdParser::new("usb-storage");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("port")
+        .push("drive")
+        .push("serial");
+    cmd_parser.parse(conf)?;
+    let mut dev = UsbStorageConfig::new();
+    if let Some(id) = cmd_parser.get_value::<String>("id")? {
+        dev.id = id;
+    } else {
+        bail!("id is none for usb storage");
+    }
+    dev.scsi_cfg.id = dev.id.clone();
+    dev.scsi_cfg.serial = cmd_parser.get_value::<String>("serial")?;
+
+    let drive = if let Some(drive) = cmd_parser.get_value::<String>("drive")? {
+        drive
+    } else {
+        return Err(anyhow!(ConfigError::FieldIsMissing("drive", "usb storage")));
+    };
+    if let Some(drive_arg) = &vm_config.drives.remove(&drive) {
+        dev.scsi_cfg.path_on_host = drive_arg.path_on_host.clone();
+        dev.scsi_cfg.read_only = drive_arg.read_only;
+        dev.scsi_cfg.direct = drive_arg.direct;
+        dev.scsi_cfg.aio_type = drive_arg.aio;
+        dev.scsi_cfg.format = drive_arg.format;
+    }
+    dev.check()?;
+    Ok(dev)
+}
+
 fn check_id(id: &str) -> Result<()> {
     if id.len() > MAX_STRING_LENGTH {
         return Err(anyhow!(ConfigError::StringLengthTooLong(
@@ -164,3 +226,35 @@ fn check_id(id: &str) -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DiskFormat;
+
+    #[test]
+    fn test_parse_usb_storage() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=drive0,file=/path/to/disk.img,format=qcow2,readonly=on")
+            .is_ok());
+        let config = parse_usb_storage(&mut vm_config, "usb-storage,id=stg0,drive=drive0");
+        assert!(config.is_ok());
+        let config = config.unwrap();
+        assert_eq!(config.id, "stg0");
+        assert_eq!(config.scsi_cfg.id, "stg0");
+        assert_eq!(config.scsi_cfg.path_on_host, "/path/to/disk.img");
+        assert_eq!(config.scsi_cfg.format, DiskFormat::Qcow2);
+        assert!(config.scsi_cfg.read_only);
+        assert!(!vm_config.drives.contains_key("drive0"));
+
+        // The drive is missing or has been used by another device.
+        assert!(parse_usb_storage(&mut vm_config, "usb-storage,id=stg1,drive=drive0").is_err());
+        assert!(parse_usb_storage(&mut vm_config, "usb-storage,id=stg1").is_err());
+        // The id is missing.
+        assert!(vm_config
+            .add_drive("id=drive1,file=/path/to/disk1.img")
+            .is_ok());
+        assert!(parse_usb_storage(&mut vm_config, "usb-storage,drive=drive1").is_err());
+    }
+}
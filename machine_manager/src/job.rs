@@ -0,0 +1,435 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! # Job
+//!
+//! Background jobs for long running operations, such as block and snapshot
+//! operations, which must not block the QMP thread.
+//!
+//! Every job runs in its own thread and is identified by a unique id. The job
+//! body reports progress and calls `Job::pause_point` regularly, where it is
+//! held while the job is paused and stopped when the job is cancelled. Every
+//! status transition is notified with the `JOB_STATUS_CHANGE` event, and the
+//! jobs are managed with `query-jobs`, `job-pause`, `job-resume`, `job-cancel`,
+//! `job-complete` and `job-dismiss`.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+
+use anyhow::{bail, Context, Result};
+use log::{error, info};
+use once_cell::sync::Lazy;
+
+use crate::event;
+use crate::qmp::qmp_schema::{self, BlockJobInfo, JobInfo, JobStatus, JobType};
+use crate::qmp::QmpChannel;
+
+static JOB_MANAGER: Lazy<JobManager> = Lazy::new(|| JobManager {
+    jobs: Mutex::new(BTreeMap::new()),
+});
+
+struct JobState {
+    status: JobStatus,
+    /// The status to go back to when the paused job is resumed.
+    resume_status: JobStatus,
+    cancelled: bool,
+    complete_requested: bool,
+    current_progress: u64,
+    total_progress: u64,
+    error: Option<String>,
+}
+
+/// A background job.
+pub struct Job {
+    id: String,
+    job_type: JobType,
+    /// Remove the job once it is concluded, without waiting for `job-dismiss`.
+    auto_dismiss: bool,
+    state: Mutex<JobState>,
+    /// Wakes up the job body held in `pause_point`.
+    cond: Condvar,
+}
+
+impl Job {
+    fn new(id: &str, job_type: JobType, auto_dismiss: bool) -> Self {
+        Job {
+            id: id.to_string(),
+            job_type,
+            auto_dismiss,
+            state: Mutex::new(JobState {
+                status: JobStatus::created,
+                resume_status: JobStatus::created,
+                cancelled: false,
+                complete_requested: false,
+                current_progress: 0,
+                total_progress: 0,
+                error: None,
+            }),
+            cond: Condvar::new(),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn job_type(&self) -> JobType {
+        self.job_type
+    }
+
+    /// Set the total amount of work, in any unit chosen by the job.
+    pub fn set_total_progress(&self, total: u64) {
+        self.state.lock().unwrap().total_progress = total;
+    }
+
+    /// Report `done` more units of finished work.
+    pub fn progress(&self, done: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.current_progress = state.current_progress.saturating_add(done);
+    }
+
+    /// Must be called regularly by the job body. It waits while the job is
+    /// paused and fails if the job is cancelled.
+    pub fn pause_point(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        while state.status == JobStatus::paused && !state.cancelled {
+            state = self.cond.wait(state).unwrap();
+        }
+        if state.cancelled {
+            bail!("Job {} is cancelled", self.id);
+        }
+        Ok(())
+    }
+
+    /// Mark the job as ready, it waits for `job-complete` from now on.
+    pub fn set_ready(&self) {
+        let mut state = self.state.lock().unwrap();
+        match state.status {
+            JobStatus::running => self.transition(&mut state, JobStatus::ready),
+            JobStatus::paused => state.resume_status = JobStatus::ready,
+            _ => {}
+        }
+    }
+
+    /// Whether `job-complete` is requested for the ready job.
+    pub fn should_complete(&self) -> bool {
+        self.state.lock().unwrap().complete_requested
+    }
+
+    pub fn info(&self) -> JobInfo {
+        let state = self.state.lock().unwrap();
+        JobInfo {
+            id: self.id.clone(),
+            job_type: self.job_type,
+            status: state.status,
+            current_progress: state.current_progress,
+            total_progress: state.total_progress,
+            error: state.error.clone(),
+        }
+    }
+
+    fn block_info(&self) -> BlockJobInfo {
+        let state = self.state.lock().unwrap();
+        BlockJobInfo {
+            job_type: self.job_type,
+            device: self.id.clone(),
+            len: state.total_progress,
+            offset: state.current_progress,
+            busy: state.status == JobStatus::running,
+            paused: state.status == JobStatus::paused,
+            speed: 0,
+            ready: state.status == JobStatus::ready,
+            status: state.status,
+            auto_dismiss: self.auto_dismiss,
+        }
+    }
+
+    fn transition(&self, state: &mut MutexGuard<JobState>, status: JobStatus) {
+        state.status = status;
+        info!("Job {} transitions to {:?}", self.id, status);
+        if QmpChannel::is_connected() {
+            event!(JobStatusChange; qmp_schema::JobStatusChange {
+                id: self.id.clone(),
+                status,
+            });
+        }
+    }
+
+    fn start(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.cancelled {
+            bail!("Job {} is cancelled", self.id);
+        }
+        self.transition(&mut state, JobStatus::running);
+        Ok(())
+    }
+
+    fn pause(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        match state.status {
+            JobStatus::running | JobStatus::ready if !state.cancelled => {
+                state.resume_status = state.status;
+                self.transition(&mut state, JobStatus::paused);
+                Ok(())
+            }
+            status => bail!("Job {} in status {:?} can't be paused", self.id, status),
+        }
+    }
+
+    fn resume(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.status != JobStatus::paused {
+            bail!("Job {} is not paused", self.id);
+        }
+        let status = state.resume_status;
+        self.transition(&mut state, status);
+        self.cond.notify_all();
+        Ok(())
+    }
+
+    fn cancel(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if matches!(state.status, JobStatus::concluded | JobStatus::null) {
+            bail!("Job {} is already concluded", self.id);
+        }
+        state.cancelled = true;
+        self.cond.notify_all();
+        Ok(())
+    }
+
+    fn complete(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.status != JobStatus::ready || state.cancelled {
+            bail!("Job {} is not ready", self.id);
+        }
+        state.complete_requested = true;
+        Ok(())
+    }
+
+    fn conclude(&self, result: Result<()>) {
+        let mut state = self.state.lock().unwrap();
+        if let Err(e) = result {
+            error!("Job {} failed: {:?}", self.id, e);
+            state.error = Some(e.to_string());
+        }
+        self.transition(&mut state, JobStatus::concluded);
+    }
+
+    fn dismiss(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.status != JobStatus::concluded {
+            bail!("Job {} is not concluded", self.id);
+        }
+        self.transition(&mut state, JobStatus::null);
+        Ok(())
+    }
+}
+
+/// The global registry of jobs.
+pub struct JobManager {
+    jobs: Mutex<BTreeMap<String, Arc<Job>>>,
+}
+
+impl JobManager {
+    /// Create a job and run `body` in a new thread. The job concludes when
+    /// `body` returns, with an error if `body` fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The unique job identifier.
+    /// * `job_type` - The type of job.
+    /// * `auto_dismiss` - Remove the job once it's concluded.
+    /// * `body` - The work of the job.
+    pub fn create<F>(id: &str, job_type: JobType, auto_dismiss: bool, body: F) -> Result<Arc<Job>>
+    where
+        F: FnOnce(&Job) -> Result<()> + Send + 'static,
+    {
+        let job = Arc::new(Job::new(id, job_type, auto_dismiss));
+        {
+            let mut jobs = JOB_MANAGER.jobs.lock().unwrap();
+            if jobs.contains_key(id) {
+                bail!("Job {} already exists", id);
+            }
+            jobs.insert(id.to_string(), job.clone());
+        }
+        job.transition(&mut job.state.lock().unwrap(), JobStatus::created);
+
+        let job_clone = job.clone();
+        let spawned = thread::Builder::new()
+            .name(format!("job-{}", id))
+            .spawn(move || {
+                let result = job_clone.start().and_then(|_| body(&job_clone));
+                job_clone.conclude(result);
+                if job_clone.auto_dismiss {
+                    let _ = JobManager::dismiss(&job_clone.id);
+                }
+            });
+        if let Err(e) = spawned {
+            JOB_MANAGER.jobs.lock().unwrap().remove(id);
+            return Err(e).with_context(|| format!("Failed to start job {}", id));
+        }
+        Ok(job)
+    }
+
+    fn find(id: &str) -> Result<Arc<Job>> {
+        match JOB_MANAGER.jobs.lock().unwrap().get(id) {
+            Some(job) => Ok(job.clone()),
+            None => bail!("Job {} not found", id),
+        }
+    }
+
+    pub fn pause(id: &str) -> Result<()> {
+        Self::find(id)?.pause()
+    }
+
+    pub fn resume(id: &str) -> Result<()> {
+        Self::find(id)?.resume()
+    }
+
+    pub fn cancel(id: &str) -> Result<()> {
+        Self::find(id)?.cancel()
+    }
+
+    pub fn complete(id: &str) -> Result<()> {
+        Self::find(id)?.complete()
+    }
+
+    pub fn dismiss(id: &str) -> Result<()> {
+        Self::find(id)?.dismiss()?;
+        JOB_MANAGER.jobs.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    pub fn query_jobs() -> Vec<JobInfo> {
+        Self::jobs().iter().map(|job| job.info()).collect()
+    }
+
+    pub fn query_block_jobs() -> Vec<BlockJobInfo> {
+        Self::jobs()
+            .iter()
+            .filter(|job| job.job_type.is_block_job())
+            .map(|job| job.block_info())
+            .collect()
+    }
+
+    /// Don't hold the registry lock while querying the jobs.
+    fn jobs() -> Vec<Arc<Job>> {
+        JOB_MANAGER.jobs.lock().unwrap().values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn job_info(id: &str) -> Option<JobInfo> {
+        JobManager::query_jobs()
+            .into_iter()
+            .find(|job| job.id == id)
+    }
+
+    fn wait_for_status(id: &str, status: JobStatus) -> bool {
+        for _ in 0..1000 {
+            if job_info(id).map(|job| job.status) == Some(status) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        false
+    }
+
+    #[test]
+    fn test_job_lifecycle() {
+        QmpChannel::object_init();
+        let (sender, receiver) = channel();
+        JobManager::create("test-lifecycle", JobType::mirror, false, move |job| {
+            job.set_total_progress(2);
+            job.progress(1);
+            sender.send(()).unwrap();
+            while !job.should_complete() {
+                job.pause_point()?;
+                job.set_ready();
+                thread::sleep(Duration::from_millis(1));
+            }
+            job.progress(1);
+            Ok(())
+        })
+        .unwrap();
+        assert!(JobManager::create("test-lifecycle", JobType::mirror, false, |_| Ok(())).is_err());
+
+        receiver.recv().unwrap();
+        JobManager::pause("test-lifecycle").unwrap();
+        assert_eq!(
+            job_info("test-lifecycle").unwrap().status,
+            JobStatus::paused
+        );
+        assert!(JobManager::pause("test-lifecycle").is_err());
+        let block_jobs = JobManager::query_block_jobs();
+        let block_job = block_jobs.iter().find(|job| job.device == "test-lifecycle");
+        assert!(block_job.unwrap().paused);
+
+        JobManager::resume("test-lifecycle").unwrap();
+        assert!(wait_for_status("test-lifecycle", JobStatus::ready));
+        assert!(JobManager::dismiss("test-lifecycle").is_err());
+        JobManager::complete("test-lifecycle").unwrap();
+        assert!(wait_for_status("test-lifecycle", JobStatus::concluded));
+
+        let info = job_info("test-lifecycle").unwrap();
+        assert_eq!(info.current_progress, 2);
+        assert_eq!(info.total_progress, 2);
+        assert!(info.error.is_none());
+        JobManager::dismiss("test-lifecycle").unwrap();
+        assert!(job_info("test-lifecycle").is_none());
+        assert!(JobManager::dismiss("test-lifecycle").is_err());
+    }
+
+    #[test]
+    fn test_job_cancel() {
+        QmpChannel::object_init();
+        let body = |job: &Job| loop {
+            job.pause_point()?;
+            thread::sleep(Duration::from_millis(1));
+        };
+        JobManager::create("test-cancel", JobType::snapshot_save, false, body).unwrap();
+        assert!(wait_for_status("test-cancel", JobStatus::running));
+        assert!(JobManager::query_block_jobs()
+            .iter()
+            .all(|job| job.device != "test-cancel"));
+
+        // A paused job can be cancelled too.
+        JobManager::pause("test-cancel").unwrap();
+        JobManager::cancel("test-cancel").unwrap();
+        assert!(wait_for_status("test-cancel", JobStatus::concluded));
+        assert!(job_info("test-cancel").unwrap().error.is_some());
+        assert!(JobManager::cancel("test-cancel").is_err());
+        JobManager::dismiss("test-cancel").unwrap();
+
+        // The failed job is removed once concluded with auto dismiss.
+        let job = JobManager::create("test-auto-dismiss", JobType::snapshot_save, true, |_| {
+            bail!("failed")
+        })
+        .unwrap();
+        for _ in 0..1000 {
+            if job_info("test-auto-dismiss").is_none() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(job_info("test-auto-dismiss").is_none());
+        assert_eq!(job.info().status, JobStatus::null);
+        assert_eq!(job.info().error, Some("failed".to_string()));
+    }
+}
@@ -10,19 +10,22 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::collections::HashMap;
 use std::os::unix::io::RawFd;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 use once_cell::sync::Lazy;
 use strum::VariantNames;
 
 use crate::config::ShutdownAction;
+use crate::job::JobManager;
 use crate::qmp::qmp_schema::{
-    BlockDevAddArgument, CharDevAddArgument, ChardevInfo, Cmd, CmdLine, DeviceAddArgument,
-    DeviceProps, Events, GicCap, IothreadInfo, KvmInfo, MachineInfo, MigrateCapabilities,
-    NetDevAddArgument, PropList, QmpCommand, QmpEvent, Target, TypeLists, UpdateRegionArgument,
+    BlockDevAddArgument, BlockDeviceInfo, BlockInfo, BlockStats, CharDevAddArgument, ChardevInfo,
+    Cmd, CmdLine, DeviceAddArgument, DeviceProps, Events, GicCap, IothreadInfo, KvmInfo,
+    MachineInfo, MigrateCapabilities, MigrateSetParametersArgument, NetDevAddArgument, PropList,
+    QmpCommand, QmpErrorClass, QmpEvent, Target, TypeLists, UpdateRegionArgument,
 };
-use crate::qmp::{Response, Version};
+use crate::qmp::{introspect, Response, Version};
 
 #[derive(Clone)]
 pub struct PathInfo {
@@ -169,6 +172,22 @@ pub trait DeviceInterface {
     /// Delete a block device.
     fn blockdev_del(&self, node_name: String) -> Response;
 
+    /// Create an internal snapshot in the image of a block device.
+    fn blockdev_snapshot_internal_sync(&self, _device: String, _name: String) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("Internal snapshot is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Delete an internal snapshot in the image of a block device.
+    fn blockdev_snapshot_delete_internal_sync(&self, _device: String, _name: String) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("Internal snapshot is not supported".to_string()),
+            None,
+        )
+    }
+
     /// Create a new network device.
     fn netdev_add(&mut self, args: Box<NetDevAddArgument>) -> Response;
 
@@ -307,6 +326,7 @@ pub trait DeviceInterface {
             ("nec-usb-xhci", "base-xhci"),
             ("usb-tablet", "usb-hid"),
             ("usb-kbd", "usb-hid"),
+            ("usb-storage", "usb-storage-dev"),
             ("virtio-gpu-pci", "virtio-gpu"),
         ];
 
@@ -362,7 +382,10 @@ pub trait DeviceInterface {
     }
 
     fn query_qmp_schema(&self) -> Response {
-        Response::create_empty_response()
+        Response::create_response(
+            serde_json::to_value(introspect::qmp_schema()).unwrap(),
+            None,
+        )
     }
 
     fn query_sev_capabilities(&self) -> Response {
@@ -396,24 +419,23 @@ pub trait DeviceInterface {
     }
 
     fn query_block(&self) -> Response {
-        let vec_cmd: Vec<ChardevInfo> = Vec::new();
+        let vec_cmd: Vec<BlockInfo> = Vec::new();
         Response::create_response(serde_json::to_value(vec_cmd).unwrap(), None)
     }
 
     fn query_named_block_nodes(&self) -> Response {
-        let vec_cmd: Vec<ChardevInfo> = Vec::new();
+        let vec_cmd: Vec<BlockDeviceInfo> = Vec::new();
         Response::create_response(serde_json::to_value(vec_cmd).unwrap(), None)
     }
 
     fn query_blockstats(&self) -> Response {
-        let vec_cmd: Vec<ChardevInfo> = Vec::new();
+        let vec_cmd: Vec<BlockStats> = Vec::new();
         Response::create_response(serde_json::to_value(vec_cmd).unwrap(), None)
     }
 
     fn query_block_jobs(&self) -> Response {
-        // Fix me: qmp command call, return none temporarily.
-        let vec_cmd: Vec<ChardevInfo> = Vec::new();
-        Response::create_response(serde_json::to_value(vec_cmd).unwrap(), None)
+        let block_jobs = JobManager::query_block_jobs();
+        Response::create_response(serde_json::to_value(block_jobs).unwrap(), None)
     }
 
     fn query_gic_capabilities(&self) -> Response {
@@ -445,7 +467,12 @@ pub trait DeviceInterface {
 /// Some external api for migration.
 pub trait MigrateInterface {
     /// Migrates the current running guest to another VM or file.
-    fn migrate(&self, _uri: String) -> Response {
+    fn migrate(
+        &self,
+        _uri: String,
+        _parent: Option<String>,
+        _incremental: Option<bool>,
+    ) -> Response {
         Response::create_empty_response()
     }
 
@@ -457,6 +484,16 @@ pub trait MigrateInterface {
     fn cancel_migrate(&self) -> Response {
         Response::create_empty_response()
     }
+
+    /// Sets parameters of migration.
+    fn migrate_set_parameters(&self, _args: MigrateSetParametersArgument) -> Response {
+        Response::create_empty_response()
+    }
+
+    /// Returns parameters of migration.
+    fn query_migrate_parameters(&self) -> Response {
+        Response::create_empty_response()
+    }
 }
 
 /// Machine interface which is exposed to inner hypervisor.
@@ -470,3 +507,36 @@ pub trait MachineTestInterface: MachineAddressInterface {}
 
 pub static PTY_PATH: Lazy<Mutex<Vec<PathInfo>>> = Lazy::new(|| Mutex::new(Vec::new()));
 pub static IOTHREADS: Lazy<Mutex<Vec<IothreadInfo>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+type VmResumeNotifier = Arc<dyn Fn() + Send + Sync>;
+
+/// Callbacks of the devices which need to know when the VM is resumed, such as
+/// block devices which retry the requests failed while the VM was stopped.
+static VM_RESUME_NOTIFIERS: Lazy<Mutex<HashMap<String, VmResumeNotifier>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Register `notifier` which is called every time the VM is resumed, `id` is
+/// the unique id of the device.
+pub fn register_vm_resume_notifier(id: &str, notifier: VmResumeNotifier) {
+    VM_RESUME_NOTIFIERS
+        .lock()
+        .unwrap()
+        .insert(id.to_string(), notifier);
+}
+
+pub fn unregister_vm_resume_notifier(id: &str) {
+    VM_RESUME_NOTIFIERS.lock().unwrap().remove(id);
+}
+
+/// Call all the registered notifiers after the VM is resumed.
+pub fn notify_vm_resumed() {
+    let notifiers: Vec<VmResumeNotifier> = VM_RESUME_NOTIFIERS
+        .lock()
+        .unwrap()
+        .values()
+        .cloned()
+        .collect();
+    for notifier in notifiers {
+        notifier();
+    }
+}
@@ -0,0 +1,155 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Rate limiting of the QMP events which can be triggered by the guest.
+//!
+//! At most one event of a kind is sent per `EVENT_THROTTLE_INTERVAL`. Events
+//! arriving within the interval are not sent immediately, only the latest of
+//! them is kept and sent when the interval expires, so the client always sees
+//! the last state without being flooded by a misbehaving guest.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use util::time::NANOSECONDS_PER_SECOND;
+
+use super::qmp_schema::QmpEvent;
+
+/// Minimum interval between two events of the same kind, in nanoseconds.
+pub const EVENT_THROTTLE_INTERVAL: u64 = NANOSECONDS_PER_SECOND;
+
+/// Get the key which identifies the kind of a rate limited event, return
+/// `None` if the event is never rate limited.
+pub fn event_throttle_key(event: &QmpEvent) -> Option<String> {
+    match event {
+        QmpEvent::RtcChange { .. } => Some("RTC_CHANGE".to_string()),
+        QmpEvent::Watchdog { .. } => Some("WATCHDOG".to_string()),
+        QmpEvent::BalloonChanged { .. } => Some("BALLOON_CHANGED".to_string()),
+        QmpEvent::BlockIoError { data, .. } => Some(format!("BLOCK_IO_ERROR:{}", data.device)),
+        QmpEvent::NicRxFilterChanged { data, .. } => {
+            Some(format!("NIC_RX_FILTER_CHANGED:{}", data.path))
+        }
+        _ => None,
+    }
+}
+
+/// What to do with an event passed to `EventThrottle::throttle`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ThrottleAction {
+    /// Send the event now.
+    Send,
+    /// The event is kept as pending, `flush` must be called after the given
+    /// nanoseconds to send it.
+    Delay(u64),
+    /// The event replaced an earlier pending one, a flush is already scheduled.
+    Queued,
+}
+
+struct ThrottleState {
+    last_sent: Instant,
+    pending: Option<QmpEvent>,
+}
+
+#[derive(Default)]
+pub struct EventThrottle {
+    states: HashMap<String, ThrottleState>,
+}
+
+impl EventThrottle {
+    /// Decide whether the `event` of kind `key` can be sent at `now`.
+    pub fn throttle(&mut self, key: &str, event: &QmpEvent, now: Instant) -> ThrottleAction {
+        let interval = Duration::from_nanos(EVENT_THROTTLE_INTERVAL);
+        match self.states.get_mut(key) {
+            Some(state) if now < state.last_sent + interval => {
+                if state.pending.replace(event.clone()).is_some() {
+                    return ThrottleAction::Queued;
+                }
+                ThrottleAction::Delay((state.last_sent + interval - now).as_nanos() as u64)
+            }
+            _ => {
+                self.states.insert(
+                    key.to_string(),
+                    ThrottleState {
+                        last_sent: now,
+                        pending: None,
+                    },
+                );
+                ThrottleAction::Send
+            }
+        }
+    }
+
+    /// Take the pending event of kind `key` which is sent at `now`.
+    pub fn flush(&mut self, key: &str, now: Instant) -> Option<QmpEvent> {
+        let state = self.states.get_mut(key)?;
+        let event = state.pending.take()?;
+        state.last_sent = now;
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qmp::create_timestamp;
+    use crate::qmp::qmp_schema::RtcChange;
+
+    fn rtc_change(offset: i64) -> QmpEvent {
+        QmpEvent::RtcChange {
+            data: RtcChange { offset },
+            timestamp: create_timestamp(),
+        }
+    }
+
+    #[test]
+    fn test_event_throttle() {
+        let mut throttle = EventThrottle::default();
+        let start = Instant::now();
+        let key = event_throttle_key(&rtc_change(1)).unwrap();
+        assert!(event_throttle_key(&QmpEvent::Stop {
+            data: Default::default(),
+            timestamp: create_timestamp(),
+        })
+        .is_none());
+
+        assert_eq!(
+            throttle.throttle(&key, &rtc_change(1), start),
+            ThrottleAction::Send
+        );
+        assert_eq!(
+            throttle.throttle(&key, &rtc_change(2), start + Duration::from_millis(400)),
+            ThrottleAction::Delay(600_000_000)
+        );
+        assert_eq!(
+            throttle.throttle(&key, &rtc_change(3), start + Duration::from_millis(500)),
+            ThrottleAction::Queued
+        );
+
+        // Only the latest event is sent when the interval expires.
+        let flushed = start + Duration::from_secs(1);
+        match throttle.flush(&key, flushed) {
+            Some(QmpEvent::RtcChange { data, .. }) => assert_eq!(data.offset, 3),
+            _ => panic!("The pending event is not flushed"),
+        }
+        assert!(throttle.flush(&key, flushed).is_none());
+
+        // The flushed event starts a new interval.
+        assert_eq!(
+            throttle.throttle(&key, &rtc_change(4), flushed + Duration::from_millis(500)),
+            ThrottleAction::Delay(500_000_000)
+        );
+        assert_eq!(
+            throttle.throttle(&key, &rtc_change(5), flushed + Duration::from_secs(2)),
+            ThrottleAction::Send
+        );
+    }
+}
@@ -0,0 +1,411 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Proxy of the qemu guest agent.
+//!
+//! The guest-* QMP commands are forwarded as json requests to the guest agent
+//! through a `guest-agent` chardev. Every request carries its own numeric
+//! `id`, which is echoed by the guest agent, so that replies can be matched
+//! to the QMP request they belong to. The QMP response is sent asynchronously
+//! once the reply arrives, or an error is sent if the guest agent does not
+//! answer in `GUEST_AGENT_TIMEOUT_SECS`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+use log::{error, warn};
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use util::time::NANOSECONDS_PER_SECOND;
+
+use super::qmp_schema::QmpErrorClass;
+use super::{Empty, QmpChannel, Response};
+use crate::event_loop::EventLoop;
+
+/// Time to wait for the reply of the guest agent.
+const GUEST_AGENT_TIMEOUT_SECS: u64 = 10;
+/// Max length of a reply line which is not yet completed.
+const MAX_REPLY_LEN: usize = 16 * 1024 * 1024;
+/// Delimiter byte which may be emitted by the guest agent to flush its stream.
+const GUEST_AGENT_DELIMITER: u8 = 0xff;
+
+/// Commands for which the guest agent never replies on success.
+const NO_REPLY_COMMANDS: [&str; 1] = ["guest-shutdown"];
+
+/// The channel used to deliver requests to the guest agent.
+pub trait GuestAgentTransport: Send + Sync {
+    /// Send a serialized request to the guest agent.
+    fn send(&self, data: &[u8]) -> Result<()>;
+
+    /// Whether the channel is still bound to a device.
+    fn is_alive(&self) -> bool;
+}
+
+struct PendingRequest {
+    /// Name of the guest agent command.
+    command: String,
+    /// Id of the QMP request.
+    qmp_id: Option<String>,
+    /// Id of the QMP client which issued the request.
+    client: u64,
+}
+
+#[derive(Default)]
+struct GuestAgent {
+    /// Channel to the guest agent.
+    transport: Option<Arc<dyn GuestAgentTransport>>,
+    /// Id of the next request.
+    next_id: u64,
+    /// Requests waiting for the reply, indexed by request id.
+    pending: HashMap<u64, PendingRequest>,
+    /// Data received from the guest agent which is not a complete line yet.
+    buffer: Vec<u8>,
+}
+
+static GUEST_AGENT: Lazy<Mutex<GuestAgent>> = Lazy::new(|| Mutex::new(GuestAgent::default()));
+
+impl GuestAgent {
+    fn is_connected(&self) -> bool {
+        matches!(&self.transport, Some(transport) if transport.is_alive())
+    }
+
+    /// Build the json request of `command` and record it as pending if a reply
+    /// is expected. Returns the request id and the serialized request.
+    fn new_request(
+        &mut self,
+        command: &str,
+        arguments: Option<Value>,
+        qmp_id: Option<String>,
+        client: u64,
+    ) -> (u64, Vec<u8>) {
+        let req_id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let mut request = json!({ "execute": command, "id": req_id });
+        if let Some(args) = arguments {
+            request["arguments"] = args;
+        }
+        if !NO_REPLY_COMMANDS.contains(&command) {
+            self.pending.insert(
+                req_id,
+                PendingRequest {
+                    command: command.to_string(),
+                    qmp_id,
+                    client,
+                },
+            );
+        }
+
+        let mut data = request.to_string().into_bytes();
+        data.push(b'\n');
+        (req_id, data)
+    }
+
+    /// Parse the output of the guest agent and return the responses of the
+    /// requests which are completed, with the clients to send them to.
+    fn receive(&mut self, data: &[u8]) -> Vec<(u64, Response)> {
+        self.buffer
+            .extend(data.iter().filter(|b| **b != GUEST_AGENT_DELIMITER));
+
+        let mut responses = Vec::new();
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<Value>(line) {
+                Ok(reply) => {
+                    if let Some(resp) = self.handle_reply(&reply) {
+                        responses.push(resp);
+                    }
+                }
+                Err(e) => warn!("Invalid reply from guest agent: {:?}", e),
+            }
+        }
+
+        if self.buffer.len() > MAX_REPLY_LEN {
+            warn!("Reply from guest agent is too long, discard it");
+            self.buffer.clear();
+        }
+        responses
+    }
+
+    fn handle_reply(&mut self, reply: &Value) -> Option<(u64, Response)> {
+        let req = match reply
+            .get("id")
+            .and_then(Value::as_u64)
+            .and_then(|id| self.pending.remove(&id))
+        {
+            Some(req) => req,
+            None => {
+                warn!("Drop unexpected reply from guest agent: {}", reply);
+                return None;
+            }
+        };
+
+        if let Some(ret) = reply.get("return") {
+            return Some((
+                req.client,
+                Response::create_response(ret.clone(), req.qmp_id),
+            ));
+        }
+        let desc = reply
+            .get("error")
+            .and_then(|err| err.get("desc"))
+            .and_then(Value::as_str)
+            .unwrap_or("invalid reply");
+        Some((
+            req.client,
+            Response::create_error_response(
+                QmpErrorClass::GenericError(format!(
+                    "Guest agent command {} failed: {}",
+                    req.command, desc
+                )),
+                req.qmp_id,
+            ),
+        ))
+    }
+
+    /// Remove the request `req_id` if it is still pending, and return the
+    /// timeout error for it.
+    fn expire(&mut self, req_id: u64) -> Option<(u64, Response)> {
+        let req = self.pending.remove(&req_id)?;
+        Some((
+            req.client,
+            Response::create_error_response(
+                QmpErrorClass::GenericError(format!(
+                    "Guest agent command {} timed out",
+                    req.command
+                )),
+                req.qmp_id,
+            ),
+        ))
+    }
+
+    /// Fail all pending requests, used when the channel is gone.
+    fn fail_all(&mut self, reason: &str) -> Vec<(u64, Response)> {
+        self.buffer.clear();
+        self.pending
+            .drain()
+            .map(|(_, req)| {
+                let resp = Response::create_error_response(
+                    QmpErrorClass::GenericError(format!(
+                        "Guest agent command {} failed: {}",
+                        req.command, reason
+                    )),
+                    req.qmp_id,
+                );
+                (req.client, resp)
+            })
+            .collect()
+    }
+}
+
+/// Bind the channel to the guest agent. Only one guest agent is supported.
+///
+/// # Arguments
+///
+/// * `transport` - The channel used to send requests to the guest agent.
+pub fn register_guest_agent(transport: Arc<dyn GuestAgentTransport>) -> Result<()> {
+    let mut agent = GUEST_AGENT.lock().unwrap();
+    if agent.is_connected() {
+        bail!("Only one guest agent channel is supported");
+    }
+    agent.transport = Some(transport);
+    let responses = agent.fail_all("guest agent channel is reset");
+    drop(agent);
+
+    for (client, resp) in responses {
+        QmpChannel::send_response(client, &resp);
+    }
+    Ok(())
+}
+
+/// Whether a guest agent channel is bound.
+pub fn guest_agent_registered() -> bool {
+    GUEST_AGENT.lock().unwrap().is_connected()
+}
+
+/// Handle the output of the guest agent.
+///
+/// # Arguments
+///
+/// * `data` - The data sent by the guest agent.
+pub fn guest_agent_receive(data: &[u8]) {
+    let responses = GUEST_AGENT.lock().unwrap().receive(data);
+    for (client, resp) in responses {
+        QmpChannel::send_response(client, &resp);
+    }
+}
+
+/// Forward a guest-* QMP command to the guest agent.
+///
+/// Returns the response if it can be sent immediately, or `None` if the
+/// response will be sent once the guest agent replies or the request times out.
+///
+/// # Arguments
+///
+/// * `command` - Name of the guest agent command.
+/// * `arguments` - Arguments of the command.
+/// * `qmp_id` - Id of the QMP request.
+/// * `client` - Id of the QMP client which issued the request.
+pub fn guest_agent_execute(
+    command: &str,
+    arguments: Option<Value>,
+    qmp_id: Option<String>,
+    client: u64,
+) -> Option<Response> {
+    let mut agent = GUEST_AGENT.lock().unwrap();
+    if !agent.is_connected() {
+        return Some(Response::create_error_response(
+            QmpErrorClass::GenericError("Guest agent is not configured".to_string()),
+            qmp_id,
+        ));
+    }
+    let (req_id, data) = agent.new_request(command, arguments, qmp_id.clone(), client);
+    let transport = agent.transport.clone().unwrap();
+    // The transport may deliver the request to the device synchronously, so do
+    // not hold the lock while sending.
+    drop(agent);
+
+    if let Err(e) = transport.send(&data) {
+        error!("Failed to send request to guest agent: {:?}", e);
+        GUEST_AGENT.lock().unwrap().pending.remove(&req_id);
+        return Some(Response::create_error_response(
+            QmpErrorClass::GenericError(format!("Guest agent is not available: {}", e)),
+            qmp_id,
+        ));
+    }
+
+    if NO_REPLY_COMMANDS.contains(&command) {
+        return Some(Response::create_response(
+            serde_json::to_value(Empty {}).unwrap(),
+            qmp_id,
+        ));
+    }
+
+    if let Some(ctx) = EventLoop::get_ctx(None) {
+        let timeout = Box::new(move || {
+            let resp = GUEST_AGENT.lock().unwrap().expire(req_id);
+            if let Some((client, resp)) = resp {
+                QmpChannel::send_response(client, &resp);
+            }
+        });
+        ctx.delay_call(timeout, GUEST_AGENT_TIMEOUT_SECS * NANOSECONDS_PER_SECOND);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response_json(resp: &Response) -> Value {
+        serde_json::to_value(resp).unwrap()
+    }
+
+    #[test]
+    fn test_guest_agent_request() {
+        let mut agent = GuestAgent::default();
+
+        let (id, data) = agent.new_request("guest-ping", None, Some("ping".to_string()), 0);
+        let req: Value = serde_json::from_slice(&data).unwrap();
+        assert_eq!(data.last(), Some(&b'\n'));
+        assert_eq!(req, json!({ "execute": "guest-ping", "id": id }));
+        assert!(agent.pending.contains_key(&id));
+
+        let args = json!({ "path": "/bin/true" });
+        let (id, data) = agent.new_request("guest-exec", Some(args.clone()), None, 0);
+        let req: Value = serde_json::from_slice(&data).unwrap();
+        assert_eq!(req["arguments"], args);
+        assert_eq!(req["id"], json!(id));
+
+        // No reply is expected for guest-shutdown.
+        let (id, _) = agent.new_request("guest-shutdown", None, None, 0);
+        assert!(!agent.pending.contains_key(&id));
+        assert_eq!(agent.pending.len(), 2);
+    }
+
+    #[test]
+    fn test_guest_agent_reply_match() {
+        let mut agent = GuestAgent::default();
+        // The requests are issued by different qmp clients.
+        let (id0, _) = agent.new_request("guest-ping", None, Some("a".to_string()), 1);
+        let (id1, _) = agent.new_request("guest-fsfreeze-freeze", None, Some("b".to_string()), 2);
+
+        // Replies may arrive out of order and split across several reads.
+        let mut reply = vec![GUEST_AGENT_DELIMITER];
+        reply.extend_from_slice(
+            format!(
+                "{{\"return\": 3, \"id\": {}}}\n{{\"return\": {{}}, \"id\"",
+                id1
+            )
+            .as_bytes(),
+        );
+        let responses = agent.receive(&reply);
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].0, 2);
+        assert_eq!(
+            response_json(&responses[0].1),
+            json!({ "return": 3, "id": "b" })
+        );
+
+        let responses = agent.receive(format!(": {}}}\n", id0).as_bytes());
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].0, 1);
+        assert_eq!(
+            response_json(&responses[0].1),
+            json!({ "return": {}, "id": "a" })
+        );
+        assert!(agent.pending.is_empty());
+        assert!(agent.buffer.is_empty());
+
+        // Unknown ids and garbage are dropped.
+        let responses = agent.receive(b"{\"return\": {}, \"id\": 100}\nnot json\n");
+        assert!(responses.is_empty());
+    }
+
+    #[test]
+    fn test_guest_agent_error_and_timeout() {
+        let mut agent = GuestAgent::default();
+        let (id0, _) = agent.new_request("guest-exec", None, Some("a".to_string()), 0);
+        let (id1, _) = agent.new_request("guest-ping", None, Some("b".to_string()), 0);
+
+        let reply = format!(
+            "{{\"error\": {{\"class\": \"GenericError\", \"desc\": \"no such file\"}}, \"id\": {}}}\n",
+            id0
+        );
+        let responses = agent.receive(reply.as_bytes());
+        let resp = response_json(&responses[0].1);
+        assert_eq!(resp["id"], json!("a"));
+        assert_eq!(resp["error"]["class"], json!("GenericError"));
+        assert_eq!(
+            resp["error"]["desc"],
+            json!("Guest agent command guest-exec failed: no such file")
+        );
+
+        let resp = response_json(&agent.expire(id1).unwrap().1);
+        assert_eq!(resp["id"], json!("b"));
+        assert_eq!(
+            resp["error"]["desc"],
+            json!("Guest agent command guest-ping timed out")
+        );
+        // The late reply of an expired request is ignored.
+        assert!(agent.expire(id1).is_none());
+        let responses =
+            agent.receive(format!("{{\"return\": {{}}, \"id\": {}}}\n", id1).as_bytes());
+        assert!(responses.is_empty());
+    }
+}
@@ -0,0 +1,303 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! QMP introspection.
+//!
+//! The `query-qmp-schema` document is generated from the types in
+//! `qmp_schema.rs` through the `QmpSchema` derive, so it always describes
+//! exactly what the QMP server accepts. The layout follows Qemu's
+//! `SchemaInfo`: every entry has a `name` and a `meta-type` which is one of
+//! `builtin`, `enum`, `array`, `object`, `command` or `event`.
+
+use std::collections::HashMap;
+
+pub use machine_manager_derive::QmpSchema;
+use serde_json::{json, Value};
+
+use super::qmp_schema::{QmpCommand, QmpEvent};
+
+/// Types which can be described in the QMP introspection document.
+pub trait QmpSchema {
+    /// Register the type and all the types it refers to in `schema`,
+    /// returns the name used to reference this type.
+    fn qmp_schema(schema: &mut SchemaBuilder) -> String;
+}
+
+/// Collector of the introspection entries.
+#[derive(Default)]
+pub struct SchemaBuilder {
+    entries: Vec<Value>,
+    index: HashMap<String, usize>,
+}
+
+impl SchemaBuilder {
+    pub fn new() -> Self {
+        SchemaBuilder::default()
+    }
+
+    /// Reserve an entry for `name`. Returns false if the entry is already known,
+    /// in which case it must not be defined again.
+    pub fn declare(&mut self, name: &str) -> bool {
+        if self.index.contains_key(name) {
+            return false;
+        }
+        self.index.insert(name.to_string(), self.entries.len());
+        self.entries.push(Value::Null);
+        true
+    }
+
+    fn define(&mut self, name: &str, mut entry: Value) {
+        entry["name"] = Value::String(name.to_string());
+        match self.index.get(name) {
+            Some(idx) => self.entries[*idx] = entry,
+            None => {
+                self.index.insert(name.to_string(), self.entries.len());
+                self.entries.push(entry);
+            }
+        }
+    }
+
+    /// Build an object member description.
+    pub fn member(name: &str, ty: String, optional: bool) -> Value {
+        let mut member = json!({ "name": name, "type": ty });
+        if optional {
+            member["default"] = Value::Null;
+        }
+        member
+    }
+
+    /// Get the members of the object `name`, used for flattened fields.
+    pub fn members(&self, name: &str) -> Vec<Value> {
+        self.index
+            .get(name)
+            .and_then(|idx| self.entries[*idx]["members"].as_array().cloned())
+            .unwrap_or_default()
+    }
+
+    pub fn define_builtin(&mut self, name: &str, json_type: &str) -> String {
+        if self.declare(name) {
+            self.define(
+                name,
+                json!({ "meta-type": "builtin", "json-type": json_type }),
+            );
+        }
+        name.to_string()
+    }
+
+    pub fn define_enum(&mut self, name: &str, values: &[&str]) {
+        self.define(name, json!({ "meta-type": "enum", "values": values }));
+    }
+
+    pub fn define_array(&mut self, element: String) -> String {
+        let name = format!("[{}]", element);
+        if self.declare(&name) {
+            self.define(
+                &name,
+                json!({ "meta-type": "array", "element-type": element }),
+            );
+        }
+        name
+    }
+
+    pub fn define_object(&mut self, name: &str, members: Vec<Value>) {
+        self.define(name, json!({ "meta-type": "object", "members": members }));
+    }
+
+    /// Define a union whose variant is selected by the `tag` member, `variants`
+    /// are pairs of the tag value and the object type of that variant.
+    pub fn define_union(
+        &mut self,
+        name: &str,
+        tag: &str,
+        tag_type: String,
+        variants: Vec<(&str, String)>,
+    ) {
+        let variants: Vec<Value> = variants
+            .into_iter()
+            .map(|(case, ty)| json!({ "case": case, "type": ty }))
+            .collect();
+        self.define(
+            name,
+            json!({
+                "meta-type": "object",
+                "members": [SchemaBuilder::member(tag, tag_type, false)],
+                "tag": tag,
+                "variants": variants,
+            }),
+        );
+    }
+
+    pub fn define_command(&mut self, name: &str, arg_type: String, ret_type: String) {
+        self.define(
+            name,
+            json!({ "meta-type": "command", "arg-type": arg_type, "ret-type": ret_type }),
+        );
+    }
+
+    pub fn define_event(&mut self, name: &str, arg_type: String) {
+        self.define(name, json!({ "meta-type": "event", "arg-type": arg_type }));
+    }
+
+    pub fn build(self) -> Vec<Value> {
+        self.entries
+    }
+}
+
+macro_rules! impl_builtin {
+    ($name:expr, $json_type:expr, $($ty:ty),+) => {
+        $(
+            impl QmpSchema for $ty {
+                fn qmp_schema(schema: &mut SchemaBuilder) -> String {
+                    schema.define_builtin($name, $json_type)
+                }
+            }
+        )+
+    };
+}
+
+impl_builtin!("str", "string", String);
+impl_builtin!("bool", "boolean", bool);
+impl_builtin!("int", "int", u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+impl_builtin!("number", "number", f32, f64);
+impl_builtin!("any", "value", Value);
+
+impl<T: QmpSchema> QmpSchema for Vec<T> {
+    fn qmp_schema(schema: &mut SchemaBuilder) -> String {
+        let element = T::qmp_schema(schema);
+        schema.define_array(element)
+    }
+}
+
+impl<T: QmpSchema> QmpSchema for Option<T> {
+    fn qmp_schema(schema: &mut SchemaBuilder) -> String {
+        T::qmp_schema(schema)
+    }
+}
+
+impl<T: QmpSchema> QmpSchema for Box<T> {
+    fn qmp_schema(schema: &mut SchemaBuilder) -> String {
+        T::qmp_schema(schema)
+    }
+}
+
+/// Generate the whole introspection document for `query-qmp-schema`.
+pub fn qmp_schema() -> Vec<Value> {
+    let mut schema = SchemaBuilder::new();
+    QmpCommand::qmp_schema(&mut schema);
+    QmpEvent::qmp_schema(&mut schema);
+    schema.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, QmpSchema)]
+    struct Inner {
+        #[serde(rename = "inner-id")]
+        id: u32,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Serialize, Deserialize, QmpSchema)]
+    enum Color {
+        #[serde(rename = "red")]
+        Red,
+        #[serde(rename = "deep-blue")]
+        Blue,
+    }
+
+    #[derive(Serialize, Deserialize, QmpSchema)]
+    struct Outer {
+        name: String,
+        #[serde(default)]
+        enabled: bool,
+        color: Option<Color>,
+        children: Vec<Outer>,
+        #[serde(flatten)]
+        inner: Inner,
+        #[serde(skip)]
+        _private: u8,
+    }
+
+    fn find<'a>(schema: &'a [Value], name: &str) -> &'a Value {
+        schema
+            .iter()
+            .find(|entry| entry["name"] == name)
+            .unwrap_or_else(|| panic!("{} is not in the schema", name))
+    }
+
+    #[test]
+    fn test_derive_object() {
+        let mut builder = SchemaBuilder::new();
+        assert_eq!(Outer::qmp_schema(&mut builder), "Outer");
+        let schema = builder.build();
+
+        let outer = find(&schema, "Outer");
+        assert_eq!(
+            outer["members"],
+            json!([
+                { "name": "name", "type": "str" },
+                { "name": "enabled", "type": "bool", "default": null },
+                { "name": "color", "type": "Color", "default": null },
+                { "name": "children", "type": "[Outer]" },
+                { "name": "inner-id", "type": "int" },
+            ])
+        );
+        assert_eq!(
+            find(&schema, "Color")["values"],
+            json!(["red", "deep-blue"])
+        );
+        assert_eq!(find(&schema, "[Outer]")["element-type"], "Outer");
+        assert_eq!(find(&schema, "int")["json-type"], "int");
+        assert_eq!(
+            schema
+                .iter()
+                .filter(|entry| entry["name"] == "Outer")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_qmp_schema() {
+        let schema = qmp_schema();
+
+        let status = find(&schema, "query-status");
+        assert_eq!(status["meta-type"], "command");
+        assert_eq!(status["ret-type"], "StatusInfo");
+        let running_state = find(&schema, "RunState")["values"].as_array().unwrap();
+        assert!(running_state.contains(&json!("running")));
+        assert_eq!(find(&schema, "STOP")["meta-type"], "event");
+        assert_eq!(find(&schema, "CpuInfo")["tag"], "arch");
+
+        // Every referenced type must be defined in the document.
+        let defined: Vec<&Value> = schema.iter().map(|entry| &entry["name"]).collect();
+        let mut referenced = Vec::new();
+        for entry in schema.iter() {
+            assert!(entry.is_object());
+            for key in ["arg-type", "ret-type", "element-type"] {
+                referenced.push(&entry[key]);
+            }
+            for list in ["members", "variants"] {
+                if let Some(items) = entry[list].as_array() {
+                    referenced.extend(items.iter().map(|item| &item["type"]));
+                }
+            }
+        }
+        for ty in referenced.into_iter().filter(|ty| !ty.is_null()) {
+            assert!(defined.contains(&ty), "{} is not defined", ty);
+        }
+    }
+}
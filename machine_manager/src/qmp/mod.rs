@@ -26,16 +26,18 @@
 //! `qmp-schema.json`. It's can be compatible by Qemu's zoology. Those
 //! transformed structures can be found in `machine_manager/src/qmp/qmp_schema.rs`
 
+pub mod event_throttle;
+pub mod guest_agent;
+pub mod introspect;
 #[allow(non_upper_case_globals)]
 #[allow(non_camel_case_types)]
 #[allow(non_snake_case)]
 pub mod qmp_schema;
 
 use std::collections::BTreeMap;
-use std::io::Write;
 use std::os::unix::io::RawFd;
 use std::sync::{Arc, Mutex, RwLock};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use log::{error, info, warn};
 use serde::de::DeserializeOwned;
@@ -45,10 +47,14 @@ use util::leak_bucket::LeakBucket;
 use util::set_termi_canon_mode;
 use util::time::NANOSECONDS_PER_SECOND;
 
+use self::event_throttle::{event_throttle_key, EventThrottle, ThrottleAction};
+use self::guest_agent::guest_agent_execute;
+use self::introspect::{QmpSchema, SchemaBuilder};
 use self::qmp_schema::{self as schema, QmpCommand};
 use crate::event_loop::EventLoop;
+use crate::job::JobManager;
 use crate::machine::MachineExternalInterface;
-use crate::socket::SocketRWHandler;
+use crate::socket::SocketConnection;
 use crate::temp_cleaner::TempCleaner;
 use anyhow::{Context, Result};
 
@@ -160,7 +166,7 @@ struct Greeting {
     capabilities: Vec<String>,
 }
 
-#[derive(Default, Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Default, Debug, Serialize, Deserialize, PartialEq, QmpSchema)]
 pub struct Version {
     #[serde(rename = "qemu")]
     application: VersionNumber,
@@ -181,7 +187,7 @@ impl Version {
     }
 }
 
-#[derive(Default, Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Default, Debug, Serialize, Deserialize, PartialEq, QmpSchema)]
 struct VersionNumber {
     micro: u8,
     minor: u8,
@@ -311,7 +317,7 @@ impl ErrorMessage {
 }
 
 /// Empty message for QMP.
-#[derive(Default, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Default, Debug, Serialize, Deserialize, PartialEq, Eq, QmpSchema)]
 pub struct Empty {}
 
 /// Command trait for Deserialize and find back Response.
@@ -346,26 +352,24 @@ pub fn create_timestamp() -> TimeStamp {
 ///
 /// # Arguments
 ///
-/// * `stream_fd` - The input stream file description.
+/// * `client` - The client which sends the command.
 /// * `controller` - The controller which execute actual qmp command.
 /// * `leak_bucket` - The LeakBucket flow controller for qmp command.
 ///
 /// # Errors
 ///
-/// This function will fail when json parser failed or socket file description broke.
+/// This function will fail when socket file description broke.
 pub fn handle_qmp(
-    stream_fd: RawFd,
+    client: &SocketConnection,
     controller: &Arc<Mutex<dyn MachineExternalInterface>>,
     leak_bucket: &mut LeakBucket,
 ) -> Result<()> {
-    let mut qmp_service = crate::socket::SocketHandler::new(stream_fd);
-
     // If flow over `LEAK_BUCKET_LIMIT` per seconds, discard the request and return
     // a `OperationThrottled` error.
     if leak_bucket.throttled(EventLoop::get_ctx(None).unwrap(), 1_u64) {
-        qmp_service.discard()?;
+        client.discard()?;
         let err_resp = schema::QmpErrorClass::OperationThrottled(crate::socket::LEAK_BUCKET_LIMIT);
-        qmp_service
+        client
             .send_str(&serde_json::to_string(&Response::create_error_response(
                 err_resp, None,
             ))?)
@@ -373,14 +377,24 @@ pub fn handle_qmp(
         return Ok(());
     }
 
-    match qmp_service.decode_line() {
-        (Ok(None), _) => Ok(()),
-        (Ok(buffer), if_fd) => {
-            info!("QMP: <-- {:?}", buffer);
-            let qmp_command: schema::QmpCommand = buffer.unwrap();
-            let (return_msg, shutdown_flag) = qmp_command_exec(qmp_command, controller, if_fd);
-            info!("QMP: --> {:?}", return_msg);
-            qmp_service.send_str(&return_msg)?;
+    let decoded = client.receive().and_then(|msg| match msg {
+        Some((buffer, if_fd)) => Ok(Some((
+            serde_json::from_str::<schema::QmpCommand>(&buffer)?,
+            if_fd,
+        ))),
+        None => Ok(None),
+    });
+    match decoded {
+        Ok(None) => Ok(()),
+        Ok(Some((qmp_command, if_fd))) => {
+            info!("QMP: <-- {:?}", qmp_command);
+            let (return_msg, shutdown_flag) =
+                qmp_command_exec(qmp_command, controller, if_fd, client.id());
+            // Response of the command forwarded to guest agent is sent later.
+            if let Some(return_msg) = return_msg {
+                info!("QMP: --> {:?}", return_msg);
+                client.send_str(&return_msg)?;
+            }
 
             // handle shutdown command
             if shutdown_flag {
@@ -397,10 +411,10 @@ pub fn handle_qmp(
 
             Ok(())
         }
-        (Err(e), _) => {
+        Err(e) => {
             let err_resp = schema::QmpErrorClass::GenericError(format!("{}", &e));
             warn!("Qmp json parser made an error:{}", e);
-            qmp_service.send_str(&serde_json::to_string(&Response::create_error_response(
+            client.send_str(&serde_json::to_string(&Response::create_error_response(
                 err_resp, None,
             ))?)?;
             Ok(())
@@ -408,13 +422,27 @@ pub fn handle_qmp(
     }
 }
 
+/// Forward the guest-* qmp command to guest agent. The response is returned
+/// only if it can't be delivered to guest agent or no reply is expected.
+fn guest_agent_command_exec<T: Serialize>(
+    command: &str,
+    arguments: Option<T>,
+    id: Option<String>,
+    client: u64,
+) -> Option<String> {
+    let arguments = arguments.map(|args| serde_json::to_value(args).unwrap());
+    guest_agent_execute(command, arguments, id, client)
+        .map(|response| serde_json::to_string(&response).unwrap() + "\r")
+}
+
 /// Create a match , where `qmp_command` and its arguments matching by handle
 /// function, and exec this qmp command.
 fn qmp_command_exec(
     qmp_command: QmpCommand,
     controller: &Arc<Mutex<dyn MachineExternalInterface>>,
     if_fd: Option<RawFd>,
-) -> (String, bool) {
+    client: u64,
+) -> (Option<String>, bool) {
     let mut qmp_response = Response::create_empty_response();
     let mut shutdown_flag = false;
 
@@ -449,6 +477,7 @@ fn qmp_command_exec(
         (query_iothreads, query_iothreads),
         (query_migrate, query_migrate),
         (cancel_migrate, cancel_migrate),
+        (query_migrate_parameters, query_migrate_parameters),
         (query_cpus, query_cpus),
         (query_balloon, query_balloon),
         (query_vnc, query_vnc),
@@ -458,15 +487,18 @@ fn qmp_command_exec(
         (device_list_properties, device_list_properties, typename),
         (device_del, device_del, id),
         (blockdev_del, blockdev_del, node_name),
+        (blockdev_snapshot_internal_sync, blockdev_snapshot_internal_sync, device, name),
+        (blockdev_snapshot_delete_internal_sync, blockdev_snapshot_delete_internal_sync, device, name),
         (netdev_del, netdev_del, id),
         (chardev_remove, chardev_remove, id),
         (balloon, balloon, value),
-        (migrate, migrate, uri);
+        (migrate, migrate, uri, parent, incremental);
         (device_add, device_add),
         (blockdev_add, blockdev_add),
         (netdev_add, netdev_add),
         (chardev_add, chardev_add),
-        (update_region, update_region)
+        (update_region, update_region),
+        (migrate_set_parameters, migrate_set_parameters)
     );
 
     // Handle the Qmp command which macro can't cover
@@ -481,6 +513,69 @@ fn qmp_command_exec(
                 qmp_response = controller.lock().unwrap().getfd(arguments.fd_name, if_fd);
                 id
             }
+            QmpCommand::query_jobs { id, .. } => {
+                let jobs = JobManager::query_jobs();
+                qmp_response = Response::create_response(serde_json::to_value(jobs).unwrap(), None);
+                id
+            }
+            QmpCommand::job_pause { arguments, id } => {
+                qmp_response = job_command_exec(JobManager::pause(&arguments.id));
+                id
+            }
+            QmpCommand::job_resume { arguments, id } => {
+                qmp_response = job_command_exec(JobManager::resume(&arguments.id));
+                id
+            }
+            QmpCommand::job_cancel { arguments, id } => {
+                qmp_response = job_command_exec(JobManager::cancel(&arguments.id));
+                id
+            }
+            QmpCommand::job_complete { arguments, id } => {
+                qmp_response = job_command_exec(JobManager::complete(&arguments.id));
+                id
+            }
+            QmpCommand::job_dismiss { arguments, id } => {
+                qmp_response = job_command_exec(JobManager::dismiss(&arguments.id));
+                id
+            }
+            QmpCommand::guest_ping { id, .. } => {
+                return (
+                    guest_agent_command_exec::<Empty>("guest-ping", None, id, client),
+                    false,
+                );
+            }
+            QmpCommand::guest_fsfreeze_freeze { id, .. } => {
+                let msg =
+                    guest_agent_command_exec::<Empty>("guest-fsfreeze-freeze", None, id, client);
+                return (msg, false);
+            }
+            QmpCommand::guest_fsfreeze_thaw { id, .. } => {
+                let msg =
+                    guest_agent_command_exec::<Empty>("guest-fsfreeze-thaw", None, id, client);
+                return (msg, false);
+            }
+            QmpCommand::guest_exec { arguments, id } => {
+                let msg = guest_agent_command_exec("guest-exec", Some(arguments), id, client);
+                return (msg, false);
+            }
+            QmpCommand::guest_exec_status { arguments, id } => {
+                let msg =
+                    guest_agent_command_exec("guest-exec-status", Some(arguments), id, client);
+                return (msg, false);
+            }
+            QmpCommand::guest_network_get_interfaces { id, .. } => {
+                let msg = guest_agent_command_exec::<Empty>(
+                    "guest-network-get-interfaces",
+                    None,
+                    id,
+                    client,
+                );
+                return (msg, false);
+            }
+            QmpCommand::guest_shutdown { arguments, id } => {
+                let msg = guest_agent_command_exec("guest-shutdown", Some(arguments), id, client);
+                return (msg, false);
+            }
             _ => None,
         }
     }
@@ -488,20 +583,32 @@ fn qmp_command_exec(
     // Change response id with input qmp message
     qmp_response.change_id(id);
     (
-        serde_json::to_string(&qmp_response).unwrap() + "\r",
+        Some(serde_json::to_string(&qmp_response).unwrap() + "\r"),
         shutdown_flag,
     )
 }
 
+fn job_command_exec(result: Result<()>) -> Response {
+    match result {
+        Ok(()) => Response::create_empty_response(),
+        Err(e) => Response::create_error_response(
+            schema::QmpErrorClass::GenericError(e.to_string()),
+            None,
+        ),
+    }
+}
+
 /// The struct `QmpChannel` is the only struct can handle Global variable
 /// `QMP_CHANNEL`.
-/// It is used to send event to qmp client and restore some file descriptor
+/// It is used to send event to qmp clients and restore some file descriptor
 /// which was sended by client.
 pub struct QmpChannel {
-    /// The `writer` to send `QmpEvent`.
-    event_writer: RwLock<Option<SocketRWHandler>>,
+    /// The clients to send `QmpEvent` to, indexed by client id.
+    event_writers: RwLock<BTreeMap<u64, Arc<SocketConnection>>>,
     /// Restore file descriptor received from client.
     fds: Arc<RwLock<BTreeMap<String, RawFd>>>,
+    /// Rate limiter of the events triggered by the guest.
+    event_throttle: Mutex<EventThrottle>,
 }
 
 impl QmpChannel {
@@ -510,30 +617,43 @@ impl QmpChannel {
         unsafe {
             if QMP_CHANNEL.is_none() {
                 QMP_CHANNEL = Some(Arc::new(QmpChannel {
-                    event_writer: RwLock::new(None),
+                    event_writers: RwLock::new(BTreeMap::new()),
                     fds: Arc::new(RwLock::new(BTreeMap::new())),
+                    event_throttle: Mutex::new(EventThrottle::default()),
                 }));
             }
         }
     }
 
-    /// Bind a `SocketRWHandler` to `QMP_CHANNEL`.
+    /// Bind a client to `QMP_CHANNEL`, events are broadcast to all bound clients.
     ///
     /// # Arguments
     ///
-    /// * `writer` - The `SocketRWHandler` used to communicate with client.
-    pub fn bind_writer(writer: SocketRWHandler) {
-        *Self::inner().event_writer.write().unwrap() = Some(writer);
+    /// * `writer` - The client connection used to communicate with client.
+    pub fn bind_writer(writer: Arc<SocketConnection>) {
+        Self::inner()
+            .event_writers
+            .write()
+            .unwrap()
+            .insert(writer.id(), writer);
     }
 
-    /// Unbind `SocketRWHandler` from `QMP_CHANNEL`.
-    pub fn unbind() {
-        *Self::inner().event_writer.write().unwrap() = None;
+    /// Unbind a client from `QMP_CHANNEL`.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the client.
+    pub fn unbind(id: u64) {
+        Self::inner().event_writers.write().unwrap().remove(&id);
     }
 
-    /// Check whether a `SocketRWHandler` bind with `QMP_CHANNEL` or not.
+    /// Check whether any client is bound with `QMP_CHANNEL` or not.
     pub fn is_connected() -> bool {
-        Self::inner().event_writer.read().unwrap().is_some()
+        // SAFETY: `QMP_CHANNEL` is only written once in `object_init`.
+        match unsafe { &*std::ptr::addr_of!(QMP_CHANNEL) } {
+            Some(channel) => !channel.event_writers.read().unwrap().is_empty(),
+            None => false,
+        }
     }
 
     /// Restore extern file descriptor in `QMP_CHANNEL`.
@@ -555,31 +675,87 @@ impl QmpChannel {
         Self::inner().fds.read().unwrap().get(name).copied()
     }
 
-    /// Send a `QmpEvent` to client.
+    /// Send a `QmpEvent` to all clients.
     ///
     /// # Arguments
     ///
     /// * `event` - The `QmpEvent` sent to client.
-    #[allow(clippy::unused_io_amount)]
     pub fn send_event(event: &schema::QmpEvent) {
-        if Self::is_connected() {
-            let mut event_str = serde_json::to_string(&event).unwrap();
-            let mut writer_unlocked = Self::inner().event_writer.write().unwrap();
-            let writer = writer_unlocked.as_mut().unwrap();
-
-            if let Err(e) = writer.flush() {
-                error!("flush err, {:?}", e);
-                return;
-            }
-            event_str.push_str("\r\n");
-            if let Err(e) = writer.write(event_str.as_bytes()) {
-                error!("write err, {:?}", e);
-                return;
+        let key = match event_throttle_key(event) {
+            Some(key) => key,
+            None => return Self::emit_event(event),
+        };
+
+        let action =
+            Self::inner()
+                .event_throttle
+                .lock()
+                .unwrap()
+                .throttle(&key, event, Instant::now());
+        match action {
+            ThrottleAction::Send => Self::emit_event(event),
+            ThrottleAction::Delay(delay) => {
+                let flush = Box::new(move || {
+                    let pending = Self::inner()
+                        .event_throttle
+                        .lock()
+                        .unwrap()
+                        .flush(&key, Instant::now());
+                    if let Some(event) = pending {
+                        Self::emit_event(&event);
+                    }
+                });
+                if let Some(ctx) = EventLoop::get_ctx(None) {
+                    ctx.delay_call(flush, delay);
+                }
             }
+            ThrottleAction::Queued => {}
+        }
+    }
+
+    fn emit_event(event: &schema::QmpEvent) {
+        if Self::send_msg(None, serde_json::to_string(&event).unwrap()) {
             info!("EVENT: --> {:?}", event);
         }
     }
 
+    /// Send a `Response` to the client out of the command handling, for the
+    /// command whose result is delivered asynchronously.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The id of the client which issued the command.
+    /// * `response` - The `Response` sent to client.
+    pub fn send_response(client: u64, response: &Response) {
+        if Self::send_msg(Some(client), serde_json::to_string(&response).unwrap()) {
+            info!("QMP: --> {:?}", response);
+        }
+    }
+
+    /// Send `msg` to the client `client`, or to all clients if it's `None`.
+    /// Return whether the message is sent to any client.
+    fn send_msg(client: Option<u64>, mut msg: String) -> bool {
+        // Don't hold the lock while writing, the tls session of the client may
+        // be busy.
+        let writers: Vec<Arc<SocketConnection>> = {
+            let locked_writers = Self::inner().event_writers.read().unwrap();
+            match client {
+                Some(id) => locked_writers.get(&id).into_iter().cloned().collect(),
+                None => locked_writers.values().cloned().collect(),
+            }
+        };
+
+        msg.push('\r');
+        let mut sent = false;
+        for writer in writers {
+            match writer.send_str(&msg) {
+                Ok(()) => sent = true,
+                Err(e) => error!("write err, {:?}", e),
+            }
+        }
+        sent
+    }
+
     fn inner() -> &'static std::sync::Arc<QmpChannel> {
         unsafe {
             match &QMP_CHANNEL {
@@ -691,7 +867,7 @@ mod tests {
 
     #[test]
     fn test_qmp_event_macro() {
-        use crate::socket::{Socket, SocketRWHandler};
+        use crate::socket::{Socket, SocketStream};
         use std::io::Read;
 
         // Pre test. Environment preparation
@@ -701,8 +877,8 @@ mod tests {
 
         // Use event! macro to send event msg to client
         let socket = Socket::from_unix_listener(listener, None);
-        socket.bind_unix_stream(server);
-        QmpChannel::bind_writer(SocketRWHandler::new(socket.get_stream_fd()));
+        let connection = socket.bind_stream(SocketStream::Unix(server)).unwrap();
+        QmpChannel::bind_writer(connection.clone());
 
         // 1.send no-content event
         event!(Stop);
@@ -736,13 +912,15 @@ mod tests {
             _ => assert!(false),
         }
 
+        QmpChannel::unbind(connection.id());
+
         // After test. Environment Recover
         recover_unix_socket_environment("06");
     }
 
     #[test]
     fn test_qmp_send_response() {
-        use crate::socket::Socket;
+        use crate::socket::{Socket, SocketStream};
         use std::io::Read;
 
         // Pre test. Environment preparation
@@ -751,10 +929,10 @@ mod tests {
 
         // Use event! macro to send event msg to client
         let socket = Socket::from_unix_listener(listener, None);
-        socket.bind_unix_stream(server);
+        let connection = socket.bind_stream(SocketStream::Unix(server)).unwrap();
 
         // 1.send greeting response
-        let res = socket.send_response(true);
+        let res = connection.send_response(true);
         let length = client.read(&mut buffer).unwrap();
         let qmp_response: QmpGreeting =
             serde_json::from_str(&(String::from_utf8_lossy(&buffer[..length]))).unwrap();
@@ -763,7 +941,7 @@ mod tests {
         assert_eq!(res.is_err(), false);
 
         // 2.send empty response
-        let res = socket.send_response(false);
+        let res = connection.send_response(false);
         let length = client.read(&mut buffer).unwrap();
         let qmp_response: Response =
             serde_json::from_str(&(String::from_utf8_lossy(&buffer[..length]))).unwrap();
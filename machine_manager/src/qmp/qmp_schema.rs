@@ -14,6 +14,7 @@ use serde::{Deserialize, Serialize};
 pub use serde_json::Value as Any;
 use strum_macros::{EnumIter, EnumString, EnumVariantNames};
 
+use super::introspect::{QmpSchema, SchemaBuilder};
 use super::Version;
 use crate::qmp::{Command, Empty, TimeStamp};
 
@@ -51,8 +52,11 @@ impl QmpErrorClass {
 }
 
 /// A enum to store all command struct
-#[derive(Debug, Clone, Serialize, Deserialize, EnumIter, EnumVariantNames, EnumString)]
+#[derive(
+    Debug, Clone, Serialize, Deserialize, EnumIter, EnumVariantNames, EnumString, QmpSchema,
+)]
 #[serde(tag = "execute")]
+#[qmp_schema(commands)]
 #[serde(deny_unknown_fields)]
 pub enum QmpCommand {
     #[serde(rename = "qmp_capabilities")]
@@ -164,6 +168,20 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "blockdev-snapshot-internal-sync")]
+    #[strum(serialize = "blockdev-snapshot-internal-sync")]
+    blockdev_snapshot_internal_sync {
+        arguments: blockdev_snapshot_internal,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "blockdev-snapshot-delete-internal-sync")]
+    #[strum(serialize = "blockdev-snapshot-delete-internal-sync")]
+    blockdev_snapshot_delete_internal_sync {
+        arguments: blockdev_snapshot_internal,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
     #[serde(rename = "balloon")]
     balloon {
         #[serde(default)]
@@ -199,6 +217,22 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "migrate-set-parameters")]
+    #[strum(serialize = "migrate-set-parameters")]
+    migrate_set_parameters {
+        #[serde(default)]
+        arguments: migrate_set_parameters,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-migrate-parameters")]
+    #[strum(serialize = "query-migrate-parameters")]
+    query_migrate_parameters {
+        #[serde(default)]
+        arguments: query_migrate_parameters,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
     #[serde(rename = "migrate_cancel")]
     cancel_migrate {
         #[serde(default)]
@@ -369,6 +403,49 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "query-jobs")]
+    #[strum(serialize = "query-jobs")]
+    query_jobs {
+        #[serde(default)]
+        arguments: query_jobs,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "job-pause")]
+    #[strum(serialize = "job-pause")]
+    job_pause {
+        arguments: job_pause,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "job-resume")]
+    #[strum(serialize = "job-resume")]
+    job_resume {
+        arguments: job_resume,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "job-cancel")]
+    #[strum(serialize = "job-cancel")]
+    job_cancel {
+        arguments: job_cancel,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "job-complete")]
+    #[strum(serialize = "job-complete")]
+    job_complete {
+        arguments: job_complete,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "job-dismiss")]
+    #[strum(serialize = "job-dismiss")]
+    job_dismiss {
+        arguments: job_dismiss,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
     #[serde(rename = "query-gic-capabilities")]
     #[strum(serialize = "query-gic-capabilities")]
     query_gic_capabilities {
@@ -399,6 +476,60 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "guest-ping")]
+    #[strum(serialize = "guest-ping")]
+    guest_ping {
+        #[serde(default)]
+        arguments: guest_ping,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "guest-fsfreeze-freeze")]
+    #[strum(serialize = "guest-fsfreeze-freeze")]
+    guest_fsfreeze_freeze {
+        #[serde(default)]
+        arguments: guest_fsfreeze_freeze,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "guest-fsfreeze-thaw")]
+    #[strum(serialize = "guest-fsfreeze-thaw")]
+    guest_fsfreeze_thaw {
+        #[serde(default)]
+        arguments: guest_fsfreeze_thaw,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "guest-exec")]
+    #[strum(serialize = "guest-exec")]
+    guest_exec {
+        arguments: guest_exec,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "guest-exec-status")]
+    #[strum(serialize = "guest-exec-status")]
+    guest_exec_status {
+        arguments: guest_exec_status,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "guest-network-get-interfaces")]
+    #[strum(serialize = "guest-network-get-interfaces")]
+    guest_network_get_interfaces {
+        #[serde(default)]
+        arguments: guest_network_get_interfaces,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "guest-shutdown")]
+    #[strum(serialize = "guest-shutdown")]
+    guest_shutdown {
+        #[serde(default)]
+        arguments: guest_shutdown,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
 }
 
 /// qmp_capabilities
@@ -411,7 +542,7 @@ pub enum QmpCommand {
 /// -> { "execute": "qmp_capabilities" }
 /// <- { "return": {} }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct qmp_capabilities {}
 
@@ -436,7 +567,7 @@ impl Command for qmp_capabilities {
 /// -> { "execute": "quit" }
 /// <- { "return": {}}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct quit {}
 
@@ -458,7 +589,7 @@ impl Command for quit {
 /// -> { "execute": "stop" }
 /// <- { "return": {} }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct stop {}
 
@@ -480,7 +611,7 @@ impl Command for stop {
 /// -> { "execute": "cont" }
 /// <- { "return": {} }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct cont {}
 
@@ -502,7 +633,7 @@ impl Command for cont {
 /// -> { "execute": "system_powerdown" }
 /// <- { "return": {} }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct system_powerdown {}
 
@@ -524,7 +655,7 @@ impl Command for system_powerdown {
 /// -> { "execute": "system_reset" }
 /// <- { "return": {} }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct system_reset {}
 
@@ -553,7 +684,7 @@ impl Command for system_reset {
 ///      "arguments": { "id": "net-0", "driver": "virtio-net-mmio", "addr": "0x0"}}
 /// <- { "return": {} }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct device_add {
     #[serde(rename = "id")]
@@ -595,6 +726,24 @@ pub struct device_add {
     pub sysfsdev: Option<String>,
     #[serde(rename = "queue-size")]
     pub queue_size: Option<u16>,
+    pub name: Option<String>,
+    pub nr: Option<u32>,
+    #[serde(rename = "scsi-id")]
+    pub scsi_id: Option<u8>,
+    pub rng: Option<String>,
+    #[serde(rename = "max-bytes")]
+    pub max_bytes: Option<u64>,
+    pub period: Option<u64>,
+    #[serde(rename = "deflate-on-oom")]
+    pub deflate_on_oom: Option<bool>,
+    #[serde(rename = "free-page-reporting")]
+    pub free_page_reporting: Option<bool>,
+    pub max_ports: Option<u32>,
+    pub max_outputs: Option<u32>,
+    pub edid: Option<bool>,
+    pub xres: Option<u32>,
+    pub yres: Option<u32>,
+    pub max_hostmem: Option<u64>,
 }
 
 pub type DeviceAddArgument = device_add;
@@ -630,7 +779,7 @@ impl Command for device_add {
 ///      "arguments": { "update_type": "add", "region_type": "io_region", "offset": 0, "size": 4096, "priority": 99 }}
 /// <- { "return": {} }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct update_region {
     #[serde(rename = "update_type")]
@@ -665,14 +814,14 @@ impl Command for update_region {
     }
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct FileOptions {
     pub driver: String,
     pub filename: String,
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct CacheOptions {
     #[serde(rename = "no-flush")]
@@ -688,6 +837,10 @@ pub struct CacheOptions {
 /// * `file` - the backend file information.
 /// * `cache` - if use direct io.
 /// * `read_only` - if readonly.
+/// * `discard` - whether to pass discard requests to the file, `unmap` or `ignore`.
+/// * `detect_zeroes` - detect write requests of zeroes, `on`, `off` or `unmap`.
+/// * `werror` - action on write error, `stop`, `report`, `ignore` or `enospc`.
+/// * `rerror` - action on read error, `stop`, `report`, `ignore` or `enospc`.
 ///
 /// Additional arguments depend on the type.
 ///
@@ -700,7 +853,7 @@ pub struct CacheOptions {
 ///                     "cache": {"direct": true}, "read-only": false }}
 /// <- { "return": {} }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct blockdev_add {
     #[serde(rename = "node-name")]
@@ -714,6 +867,10 @@ pub struct blockdev_add {
     pub driver: Option<String>,
     pub backing: Option<String>,
     pub discard: Option<String>,
+    #[serde(rename = "detect-zeroes")]
+    pub detect_zeroes: Option<String>,
+    pub werror: Option<String>,
+    pub rerror: Option<String>,
     pub id: Option<String>,
     pub options: Option<String>,
     #[serde(rename = "throttling.iops-total")]
@@ -737,6 +894,10 @@ impl Command for blockdev_add {
 /// * `id` - the device's ID, must be unique.
 /// * `ifname` - the backend tap dev name.
 /// * `fds` - the file fd opened by upper level.
+/// * `net` - the virtual network of user netdev, such as `10.0.2.0/24`.
+/// * `hostfwd` - the host forwarding rules of user netdev.
+/// * `listen` - the address listened by socket netdev, such as `:1234`.
+/// * `connect` - the address connected by socket netdev.
 ///
 /// Additional arguments depend on the type.
 ///
@@ -747,7 +908,7 @@ impl Command for blockdev_add {
 ///      "arguments":  {"id": "net-0", "ifname": "tap0", "fds": 123 }}
 /// <- { "return": {} }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct netdev_add {
     pub id: String,
@@ -764,6 +925,17 @@ pub struct netdev_add {
     pub script: Option<String>,
     pub queues: Option<u16>,
     pub chardev: Option<String>,
+    pub net: Option<String>,
+    pub host: Option<String>,
+    pub dns: Option<String>,
+    pub dhcpstart: Option<String>,
+    pub hostfwd: Option<Vec<String>>,
+    pub restrict: Option<bool>,
+    pub listen: Option<String>,
+    pub connect: Option<String>,
+    pub mcast: Option<String>,
+    pub udp: Option<String>,
+    pub localaddr: Option<String>,
 }
 
 pub type NetDevAddArgument = netdev_add;
@@ -776,13 +948,13 @@ impl Command for netdev_add {
     }
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct AddrDataOptions {
     pub path: String,
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct AddrOptions {
     #[serde(rename = "type")]
@@ -791,14 +963,14 @@ pub struct AddrOptions {
     pub addr_data: AddrDataOptions,
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct BackendDataOptions {
     pub addr: AddrOptions,
     pub server: bool,
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct BackendOptions {
     #[serde(rename = "type")]
@@ -825,7 +997,7 @@ pub struct BackendOptions {
 ///            "server": false }}}}
 /// <- { "return": {} }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct chardev_add {
     pub id: String,
@@ -860,7 +1032,7 @@ impl Command for chardev_add {
 /// -> { "execute": "chardev-remove", "arguments": { "id": "chardev_id" } }
 /// <- { "return": {} }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct chardev_remove {
     pub id: String,
@@ -902,7 +1074,7 @@ impl Command for chardev_remove {
 ///      "arguments": { "id": "net-0" } }
 /// <- { "return": {} }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct device_del {
     pub id: String,
@@ -916,7 +1088,7 @@ impl Command for device_del {
     }
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct blockdev_del {
     #[serde(rename = "node-name")]
@@ -931,6 +1103,69 @@ impl Command for blockdev_del {
     }
 }
 
+/// blockdev-snapshot-internal-sync
+///
+/// Create an internal snapshot in the qcow2 image of a block device, the VM
+/// should be paused.
+///
+/// # Arguments
+///
+/// * `device` - the id of the block device.
+/// * `name` - the name of the snapshot.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "blockdev-snapshot-internal-sync",
+///      "arguments": { "device": "drive-0", "name": "snapshot0" } }
+/// <- { "return": {} }
+/// ```
+///
+/// blockdev-snapshot-delete-internal-sync
+///
+/// Delete an internal snapshot in the qcow2 image of a block device, the VM
+/// should be paused. The deleted snapshot is returned.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "blockdev-snapshot-delete-internal-sync",
+///      "arguments": { "device": "drive-0", "name": "snapshot0" } }
+/// <- { "return": { "id": "1", "name": "snapshot0", "vm-state-size": 0,
+///                  "date-sec": 1000012, "date-nsec": 10, "vm-clock-sec": 100,
+///                  "vm-clock-nsec": 20 } }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+#[serde(deny_unknown_fields)]
+pub struct blockdev_snapshot_internal {
+    pub device: String,
+    pub name: String,
+}
+
+impl Command for blockdev_snapshot_internal {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+pub struct SnapshotInfo {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "vm-state-size")]
+    pub vm_state_size: u64,
+    #[serde(rename = "date-sec")]
+    pub date_sec: u32,
+    #[serde(rename = "date-nsec")]
+    pub date_nsec: u32,
+    #[serde(rename = "vm-clock-sec")]
+    pub vm_clock_sec: u64,
+    #[serde(rename = "vm-clock-nsec")]
+    pub vm_clock_nsec: u64,
+}
+
 /// netdev_del
 ///
 /// Remove a network backend.
@@ -949,7 +1184,7 @@ impl Command for blockdev_del {
 /// -> { "execute": "netdev_del", "arguments": { "id": "net-0" } }
 /// <- { "return": {} }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct netdev_del {
     pub id: String,
@@ -986,7 +1221,7 @@ impl Command for netdev_del {
 ///      }
 ///    ]}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct query_hotpluggable_cpus {}
 
@@ -999,7 +1234,7 @@ impl Command for query_hotpluggable_cpus {
 }
 
 #[allow(clippy::upper_case_acronyms)]
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct HotpluggableCPU {
     #[serde(rename = "type")]
     pub type_: String,
@@ -1011,7 +1246,7 @@ pub struct HotpluggableCPU {
     pub qom_path: Option<String>,
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct CpuInstanceProperties {
     #[serde(rename = "node-id", default, skip_serializing_if = "Option::is_none")]
     pub node_id: Option<isize>,
@@ -1069,7 +1304,7 @@ pub struct CpuInstanceProperties {
 ///       ]
 ///    }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct query_cpus {}
 
@@ -1081,7 +1316,7 @@ impl Command for query_cpus {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct CpuInfoCommon {
     #[serde(rename = "current")]
     pub current: bool,
@@ -1097,7 +1332,7 @@ pub struct CpuInfoCommon {
     pub thread_id: isize,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(tag = "arch")]
 pub enum CpuInfo {
     #[serde(rename = "x86")]
@@ -1118,10 +1353,10 @@ pub enum CpuInfo {
     },
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct CpuInfoX86 {}
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct CpuInfoArm {}
 
 /// query-status
@@ -1140,7 +1375,7 @@ pub struct CpuInfoArm {}
 ///                  "singlestep": false,
 ///                  "status": "running" } }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct query_status {}
 
@@ -1152,7 +1387,7 @@ impl Command for query_status {
     }
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct StatusInfo {
     #[serde(rename = "singlestep")]
     pub singlestep: bool,
@@ -1162,7 +1397,7 @@ pub struct StatusInfo {
     pub status: RunState,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub enum RunState {
     #[serde(rename = "debug")]
     debug,
@@ -1213,10 +1448,27 @@ impl Default for RunState {
 /// # Arguments
 ///
 /// * `uri` - the Uniform Resource Identifier of the destination VM or file.
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+/// * `parent` - the parent snapshot dir, only pages changed since it are saved
+///   into the snapshot. Only valid for file uri.
+/// * `incremental` - keep logging dirty pages after the snapshot, so that it can be
+///   used as parent of an incremental snapshot. Only valid for file uri, default false.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "migrate",
+///      "arguments": { "uri": "file:path/to/snapshot1", "parent": "path/to/snapshot0",
+///                     "incremental": true } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct migrate {
     #[serde(rename = "uri")]
     pub uri: String,
+    #[serde(rename = "parent")]
+    pub parent: Option<String>,
+    #[serde(rename = "incremental")]
+    pub incremental: Option<bool>,
 }
 
 impl Command for migrate {
@@ -1230,7 +1482,16 @@ impl Command for migrate {
 /// query-migrate:
 ///
 /// Returns information about current migration.
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "query-migrate" }
+/// <- { "return": { "status": "postcopy-active",
+///                  "postcopy": { "total-pages": 65536, "transferred-pages": 1024,
+///                                "remaining-pages": 64512, "requested-pages": 32 } } }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_migrate {}
 
 impl Command for query_migrate {
@@ -1244,7 +1505,7 @@ impl Command for query_migrate {
 /// cancel-migrate:
 ///
 /// Cancel migrate the current VM.
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct cancel_migrate {}
 
 impl Command for cancel_migrate {
@@ -1255,10 +1516,127 @@ impl Command for cancel_migrate {
     }
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, QmpSchema)]
 pub struct MigrationInfo {
     #[serde(rename = "status", default, skip_serializing_if = "Option::is_none")]
     pub status: Option<String>,
+    #[serde(rename = "postcopy", default, skip_serializing_if = "Option::is_none")]
+    pub postcopy: Option<PostcopyInfo>,
+}
+
+/// Page counts of post-copy, present once migration switches to post-copy.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, QmpSchema)]
+pub struct PostcopyInfo {
+    #[serde(rename = "total-pages")]
+    pub total_pages: u64,
+    #[serde(rename = "transferred-pages")]
+    pub transferred_pages: u64,
+    #[serde(rename = "remaining-pages")]
+    pub remaining_pages: u64,
+    #[serde(rename = "requested-pages")]
+    pub requested_pages: u64,
+}
+
+/// migrate-set-parameters
+///
+/// Set migration parameters, the omitted parameters are unchanged.
+///
+/// # Arguments
+///
+/// * `max-bandwidth` - Max bandwidth of migration in bytes per second, 0 means no limit.
+/// * `downtime-limit` - Max tolerable downtime in milliseconds.
+/// * `auto-converge` - Throttle vCPUs if the guest dirties memory too fast.
+/// * `cpu-throttle-initial` - Initial percentage of vCPU throttling, 1 to 99.
+/// * `cpu-throttle-increment` - Increment percentage of vCPU throttling, 1 to 99.
+/// * `multifd-channels` - Number of extra channels to send memory in parallel, 0 to 16.
+/// * `compression` - Compression algorithm of memory pages: none, zstd or lz4.
+/// * `zero-page-detection` - Skip sending pages which are filled with zero.
+/// * `postcopy-ram` - Switch to post-copy if pre-copy doesn't converge.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "migrate-set-parameters",
+///      "arguments": { "max-bandwidth": 104857600, "downtime-limit": 300, "auto-converge": true } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+#[serde(deny_unknown_fields)]
+pub struct migrate_set_parameters {
+    #[serde(rename = "max-bandwidth")]
+    pub max_bandwidth: Option<u64>,
+    #[serde(rename = "downtime-limit")]
+    pub downtime_limit: Option<u64>,
+    #[serde(rename = "auto-converge")]
+    pub auto_converge: Option<bool>,
+    #[serde(rename = "cpu-throttle-initial")]
+    pub cpu_throttle_initial: Option<u8>,
+    #[serde(rename = "cpu-throttle-increment")]
+    pub cpu_throttle_increment: Option<u8>,
+    #[serde(rename = "multifd-channels")]
+    pub multifd_channels: Option<u16>,
+    #[serde(rename = "compression")]
+    pub compression: Option<String>,
+    #[serde(rename = "zero-page-detection")]
+    pub zero_page_detection: Option<bool>,
+    #[serde(rename = "postcopy-ram")]
+    pub postcopy_ram: Option<bool>,
+}
+
+pub type MigrateSetParametersArgument = migrate_set_parameters;
+
+impl Command for migrate_set_parameters {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// query-migrate-parameters
+///
+/// Returns the current migration parameters.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "query-migrate-parameters" }
+/// <- { "return": { "max-bandwidth": 0, "downtime-limit": 50, "auto-converge": false,
+///                  "cpu-throttle-initial": 20, "cpu-throttle-increment": 10,
+///                  "multifd-channels": 0, "compression": "none", "zero-page-detection": false,
+///                  "postcopy-ram": false } }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+pub struct query_migrate_parameters {}
+
+impl Command for query_migrate_parameters {
+    type Res = MigrationParameters;
+
+    fn back(self) -> MigrationParameters {
+        Default::default()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, QmpSchema)]
+pub struct MigrationParameters {
+    #[serde(rename = "max-bandwidth")]
+    pub max_bandwidth: u64,
+    #[serde(rename = "downtime-limit")]
+    pub downtime_limit: u64,
+    #[serde(rename = "auto-converge")]
+    pub auto_converge: bool,
+    #[serde(rename = "cpu-throttle-initial")]
+    pub cpu_throttle_initial: u8,
+    #[serde(rename = "cpu-throttle-increment")]
+    pub cpu_throttle_increment: u8,
+    #[serde(rename = "multifd-channels")]
+    pub multifd_channels: u16,
+    #[serde(rename = "compression")]
+    pub compression: String,
+    #[serde(rename = "zero-page-detection")]
+    pub zero_page_detection: bool,
+    #[serde(rename = "postcopy-ram")]
+    pub postcopy_ram: bool,
 }
 
 /// getfd
@@ -1275,7 +1653,7 @@ pub struct MigrationInfo {
 /// -> { "execute": "getfd", "arguments": { "fdname": "fd1" } }
 /// <- { "return": {} }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct getfd {
     #[serde(rename = "fdname")]
@@ -1299,7 +1677,7 @@ impl Command for getfd {
 ///
 /// If the command-line option "-no-shutdown" has been specified, StratoVirt
 /// will not exit, and a STOP event will eventually follow the SHUTDOWN event
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Shutdown {
     /// If true, the shutdown was triggered by a guest request (such as
@@ -1313,7 +1691,7 @@ pub struct Shutdown {
 /// Reset
 ///
 /// Emitted when the virtual machine is reset
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Reset {
     /// If true, the reset was triggered by a guest request (such as
@@ -1326,21 +1704,21 @@ pub struct Reset {
 /// Stop
 ///
 /// Emitted when the virtual machine is stopped
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Stop {}
 
 /// Resume
 ///
 /// Emitted when the virtual machine resumes execution
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Resume {}
 
 /// Powerdown
 ///
 /// Emitted when the virtual machine powerdown execution
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Powerdown {}
 
@@ -1358,7 +1736,7 @@ pub struct Powerdown {}
 ///                "path": "/machine/peripheral/virtio-net-mmio-0" },
 ///      "timestamp": { "seconds": 1265044230, "microseconds": 450486 } }
 /// ```
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, QmpSchema)]
 #[serde(deny_unknown_fields)]
 pub struct DeviceDeleted {
     /// Device name.
@@ -1369,8 +1747,230 @@ pub struct DeviceDeleted {
     pub path: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, EnumIter, EnumVariantNames, EnumString)]
+/// JOB_STATUS_CHANGE
+///
+/// Emitted when a job transitions to a different status.
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "JOB_STATUS_CHANGE",
+///      "data": { "id": "snapshot0", "status": "running" },
+///      "timestamp": { "seconds": 1265044230, "microseconds": 450486 } }
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize, QmpSchema)]
+#[serde(deny_unknown_fields)]
+pub struct JobStatusChange {
+    /// The job identifier.
+    pub id: String,
+    /// The new job status.
+    pub status: JobStatus,
+}
+
+/// MIGRATION
+///
+/// Emitted when the migration status changes.
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "MIGRATION",
+///      "data": { "status": "completed" },
+///      "timestamp": { "seconds": 1432121972, "microseconds": 744001 } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default, QmpSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Migration {
+    /// The new migration status, see `query-migrate`.
+    pub status: String,
+}
+
+/// The operation of a failed I/O request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, QmpSchema)]
+pub enum IoOperationType {
+    #[serde(rename = "read")]
+    read,
+    #[serde(rename = "write")]
+    write,
+}
+
+impl Default for IoOperationType {
+    fn default() -> Self {
+        IoOperationType::read
+    }
+}
+
+/// The action taken on a block I/O error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, QmpSchema)]
+pub enum BlockErrorAction {
+    /// The error is ignored and the request is completed successfully.
+    #[serde(rename = "ignore")]
+    ignore,
+    /// The error is reported to the guest.
+    #[serde(rename = "report")]
+    report,
+    /// The virtual machine is stopped.
+    #[serde(rename = "stop")]
+    stop,
+}
+
+impl Default for BlockErrorAction {
+    fn default() -> Self {
+        BlockErrorAction::report
+    }
+}
+
+/// BLOCK_IO_ERROR
+///
+/// Emitted when a disk I/O error occurs.
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "BLOCK_IO_ERROR",
+///      "data": { "device": "drive0", "operation": "write", "action": "report",
+///                "nospace": false, "reason": "Input/output error" },
+///      "timestamp": { "seconds": 1265044230, "microseconds": 450486 } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default, QmpSchema)]
+#[serde(deny_unknown_fields)]
+pub struct BlockIoError {
+    /// The id of the block device.
+    pub device: String,
+    /// The operation of the failed request.
+    pub operation: IoOperationType,
+    /// The action taken on the error.
+    pub action: BlockErrorAction,
+    /// True if the error is caused by no space on the host device.
+    pub nospace: bool,
+    /// Human readable description of the error.
+    pub reason: String,
+}
+
+/// The action taken on a guest panic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, QmpSchema)]
+pub enum GuestPanicAction {
+    #[serde(rename = "pause")]
+    pause,
+    #[serde(rename = "poweroff")]
+    poweroff,
+    #[serde(rename = "run")]
+    run,
+}
+
+impl Default for GuestPanicAction {
+    fn default() -> Self {
+        GuestPanicAction::pause
+    }
+}
+
+/// GUEST_PANICKED
+///
+/// Emitted when the guest reports a panic.
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "GUEST_PANICKED",
+///      "data": { "action": "pause" },
+///      "timestamp": { "seconds": 1648245231, "microseconds": 900001 } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default, QmpSchema)]
+#[serde(deny_unknown_fields)]
+pub struct GuestPanicked {
+    /// The action taken on the panic.
+    pub action: GuestPanicAction,
+}
+
+/// NIC_RX_FILTER_CHANGED
+///
+/// Emitted when the guest changes the MAC address, the MAC filter table or
+/// the rx mode of a net device through the control queue.
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "NIC_RX_FILTER_CHANGED",
+///      "data": { "name": "net0", "path": "/machine/peripheral/net0/virtio-backend" },
+///      "timestamp": { "seconds": 1368697518, "microseconds": 326866 } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default, QmpSchema)]
+#[serde(deny_unknown_fields)]
+pub struct NicRxFilterChanged {
+    /// The id of the net device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The QOM path of the net device.
+    pub path: String,
+}
+
+/// RTC_CHANGE
+///
+/// Emitted when the guest changes the RTC time.
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "RTC_CHANGE",
+///      "data": { "offset": 78 },
+///      "timestamp": { "seconds": 1267020223, "microseconds": 435656 } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default, QmpSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RtcChange {
+    /// Offset in seconds between the guest RTC and the host time.
+    pub offset: i64,
+}
+
+/// The action taken when the watchdog timer expires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, QmpSchema)]
+pub enum WatchdogAction {
+    #[serde(rename = "reset")]
+    reset,
+    #[serde(rename = "shutdown")]
+    shutdown,
+    #[serde(rename = "poweroff")]
+    poweroff,
+    #[serde(rename = "pause")]
+    pause,
+    #[serde(rename = "debug")]
+    debug,
+    #[serde(rename = "none")]
+    none,
+    #[serde(rename = "inject-nmi")]
+    inject_nmi,
+}
+
+impl Default for WatchdogAction {
+    fn default() -> Self {
+        WatchdogAction::reset
+    }
+}
+
+/// WATCHDOG
+///
+/// Emitted when the watchdog timer of the guest expires. It is defined for
+/// compatibility, no watchdog device emits it yet.
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "WATCHDOG",
+///      "data": { "action": "reset" },
+///      "timestamp": { "seconds": 1267061043, "microseconds": 959568 } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default, QmpSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Watchdog {
+    /// The action taken by the watchdog.
+    pub action: WatchdogAction,
+}
+
+#[derive(
+    Debug, Clone, Serialize, Deserialize, EnumIter, EnumVariantNames, EnumString, QmpSchema,
+)]
 #[serde(tag = "event")]
+#[qmp_schema(events)]
 pub enum QmpEvent {
     #[serde(rename = "SHUTDOWN")]
     Shutdown {
@@ -1407,6 +2007,41 @@ pub enum QmpEvent {
         data: BalloonInfo,
         timestamp: TimeStamp,
     },
+    #[serde(rename = "JOB_STATUS_CHANGE")]
+    JobStatusChange {
+        data: JobStatusChange,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "MIGRATION")]
+    Migration {
+        data: Migration,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "BLOCK_IO_ERROR")]
+    BlockIoError {
+        data: BlockIoError,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "GUEST_PANICKED")]
+    GuestPanicked {
+        data: GuestPanicked,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "NIC_RX_FILTER_CHANGED")]
+    NicRxFilterChanged {
+        data: NicRxFilterChanged,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "RTC_CHANGE")]
+    RtcChange {
+        data: RtcChange,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "WATCHDOG")]
+    Watchdog {
+        data: Watchdog,
+        timestamp: TimeStamp,
+    },
 }
 
 /// query-balloon:
@@ -1423,7 +2058,7 @@ pub enum QmpEvent {
 /// -> { "execute": "query-balloon" }
 /// <- {"return":{"actual":8589934592}}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_balloon {}
 impl Command for query_balloon {
     type Res = BalloonInfo;
@@ -1432,7 +2067,7 @@ impl Command for query_balloon {
     }
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct BalloonInfo {
     pub actual: u64,
 }
@@ -1459,7 +2094,7 @@ pub struct BalloonInfo {
 ///         }
 ///     }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_vnc {}
 impl Command for query_vnc {
     type Res = VncInfo;
@@ -1468,7 +2103,7 @@ impl Command for query_vnc {
     }
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct VncInfo {
     #[serde(rename = "enabled")]
     pub enabled: bool,
@@ -1484,7 +2119,7 @@ pub struct VncInfo {
     pub clients: Vec<VncClientInfo>,
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct VncClientInfo {
     #[serde(rename = "host")]
     pub host: String,
@@ -1513,7 +2148,7 @@ pub struct VncClientInfo {
 /// -> { "execute": "balloon", "arguments": { "value": 589934492 } }
 /// <- {"return":{}}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct balloon {
     #[serde(rename = "value")]
     pub value: u64,
@@ -1536,7 +2171,7 @@ impl Command for balloon {
 /// -> { "execute": "query-version" }
 /// <- {"return":{"package":"StratoVirt-0.3.0","qemu":{"major":4,"micro":0,"minor":1}}}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_version {}
 
 impl Command for query_version {
@@ -1563,10 +2198,10 @@ impl Command for query_version {
 /// {"name":"migrate"},{"name":"query_migrate"},{"name":"query_version"},
 /// {"name":"query_target"},{"name":"query_commands"}]}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_commands {}
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct Cmd {
     pub name: String,
 }
@@ -1594,10 +2229,10 @@ impl Command for query_commands {
 /// -> { "execute": "query-target" }
 /// <- {"return":{"arch":"aarch64"}}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_target {}
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct Target {
     pub arch: String,
 }
@@ -1622,10 +2257,10 @@ impl Command for query_target {
 /// {"cpu-max":255,"deprecated":false,"hotpluggable-cpus":true,"name":"microvm","numa-mem-supported":false},
 /// {"cpu-max":255,"deprecated":false,"hotpluggable-cpus":true,"name":"standardvm","numa-mem-supported":false}]}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_machines {}
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct MachineInfo {
     #[serde(rename = "hotpluggable-cpus")]
     pub hotplug: bool,
@@ -1657,12 +2292,12 @@ impl Command for query_machines {
 /// {"name":"Stop"},{"name":"Resume"},{"name":"DeviceDeleted"},
 /// {"name":"BalloonChanged"}]}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct Events {
     pub name: String,
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_events {}
 
 impl Command for query_events {
@@ -1683,10 +2318,10 @@ impl Command for query_events {
 /// -> { "execute": "query-kvm" }
 /// <- {"return":{"enabled":true,"present":true}}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_kvm {}
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct KvmInfo {
     pub enabled: bool,
     pub present: bool,
@@ -1711,10 +2346,10 @@ impl Command for query_kvm {
 /// {"name":"pcie-pci-bridge","parent":"base-pci-bridge"},
 /// {"name":"pci-bridge","parent":"base-pci-bridge"}]}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct list_type {}
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct TypeLists {
     name: String,
     parent: String,
@@ -1742,12 +2377,12 @@ impl Command for list_type {
 /// -> { "execute": "device-list-properties", "arguments": {"typename": "virtio-blk-pci"} }
 /// <- {"return":[]}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct device_list_properties {
     pub typename: String,
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct DeviceProps {
     pub name: String,
     #[serde(rename = "type")]
@@ -1762,7 +2397,7 @@ impl Command for device_list_properties {
     }
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct block_commit {}
 
 impl Command for block_commit {
@@ -1781,7 +2416,7 @@ impl Command for block_commit {
 /// -> { "execute": "query-tpm-models" }
 /// <- {"return":[]}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_tpm_models {}
 
 impl Command for query_tpm_models {
@@ -1800,7 +2435,7 @@ impl Command for query_tpm_models {
 /// -> { "execute": "query-tpm-types" }
 /// <- {"return":[]}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_tpm_types {}
 
 impl Command for query_tpm_types {
@@ -1819,10 +2454,10 @@ impl Command for query_tpm_types {
 /// -> { "execute": "query-command-line-options" }
 /// <- {"return":[]}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_command_line_options {}
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct CmdParameter {
     name: String,
     help: String,
@@ -1830,7 +2465,7 @@ pub struct CmdParameter {
     paramter_type: String,
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct CmdLine {
     pub parameters: Vec<CmdParameter>,
     pub option: String,
@@ -1852,10 +2487,10 @@ impl Command for query_command_line_options {
 /// -> { "execute": "query-migrate-capabilities" }
 /// <- {"return":[]}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_migrate_capabilities {}
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct MigrateCapabilities {
     pub state: bool,
     pub capability: String,
@@ -1869,21 +2504,22 @@ impl Command for query_migrate_capabilities {
     }
 }
 
-/// Query target of StratoVirt.
+/// Query the QMP introspection document of StratoVirt.
 ///
 /// # Example
 ///
 /// ```text
 /// -> { "execute": "query-qmp-schema" }
-/// <- {"return":{}}
+/// <- {"return":[{"name":"query-status","meta-type":"command",
+///                "arg-type":"query_status","ret-type":"StatusInfo"},...]}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_qmp_schema {}
 
 impl Command for query_qmp_schema {
-    type Res = Empty;
+    type Res = Vec<Any>;
 
-    fn back(self) -> Empty {
+    fn back(self) -> Vec<Any> {
         Default::default()
     }
 }
@@ -1896,7 +2532,7 @@ impl Command for query_qmp_schema {
 /// -> { "execute": "query-sev-capabilities" }
 /// <- {"return":{}}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_sev_capabilities {}
 
 impl Command for query_sev_capabilities {
@@ -1915,10 +2551,10 @@ impl Command for query_sev_capabilities {
 /// -> { "execute": "qom-list" }
 /// <- {"return":[]}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct qom_list {}
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct PropList {
     pub name: String,
     #[serde(rename = "type")]
@@ -1941,10 +2577,10 @@ impl Command for qom_list {
 /// -> { "execute": "query-chardev" }
 /// <- {"return":[]}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_chardev {}
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct ChardevInfo {
     #[serde(rename = "frontend-open")]
     pub open: bool,
@@ -1968,13 +2604,13 @@ impl Command for query_chardev {
 /// -> { "execute": "qom_get" }
 /// <- {"return":[]}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct qom_get {}
 
 impl Command for qom_get {
-    type Res = bool;
+    type Res = Any;
 
-    fn back(self) -> bool {
+    fn back(self) -> Any {
         Default::default()
     }
 }
@@ -1985,34 +2621,80 @@ impl Command for qom_get {
 ///
 /// ```text
 /// -> { "execute": "query-block" }
-/// <- {"return":[]}
+/// <- { "return": [{ "device": "drive-0", "qdev": "drive-0", "type": "unknown",
+///      "removable": false, "locked": false,
+///      "inserted": { "node-name": "drive-0", "file": "/path/to/disk.img",
+///      "ro": false, "drv": "raw", "encrypted": false, "detect_zeroes": "off",
+///      "aio": "native", "iops": 0,
+///      "cache": { "writeback": true, "direct": true, "no-flush": false } } }] }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_block {}
 
 impl Command for query_block {
-    type Res = Vec<Cmd>;
+    type Res = Vec<BlockInfo>;
 
-    fn back(self) -> Vec<Cmd> {
+    fn back(self) -> Vec<BlockInfo> {
         Default::default()
     }
 }
 
+/// Cache mode of a block device.
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+pub struct BlockdevCacheInfo {
+    pub writeback: bool,
+    pub direct: bool,
+    #[serde(rename = "no-flush")]
+    pub no_flush: bool,
+}
+
+/// Information of the image which is inserted to a block device.
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+pub struct BlockDeviceInfo {
+    #[serde(rename = "node-name")]
+    pub node_name: String,
+    pub file: String,
+    pub ro: bool,
+    pub drv: String,
+    pub encrypted: bool,
+    pub detect_zeroes: String,
+    pub aio: String,
+    /// Total IO operations per second, 0 means no limit.
+    pub iops: u64,
+    pub cache: BlockdevCacheInfo,
+}
+
+/// Information of a block device.
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+pub struct BlockInfo {
+    pub device: String,
+    pub qdev: String,
+    #[serde(rename = "type")]
+    pub block_type: String,
+    pub removable: bool,
+    pub locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inserted: Option<BlockDeviceInfo>,
+}
+
 /// Query named block node.
 ///
 /// # Example
 ///
 /// ```text
 /// -> { "execute": "query-named-block-nodes" }
-/// <- {"return":[]}
+/// <- { "return": [{ "node-name": "drive-0", "file": "/path/to/disk.img",
+///      "ro": false, "drv": "raw", "encrypted": false, "detect_zeroes": "off",
+///      "aio": "native", "iops": 0,
+///      "cache": { "writeback": true, "direct": true, "no-flush": false } }] }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_named_block_nodes {}
 
 impl Command for query_named_block_nodes {
-    type Res = Vec<Cmd>;
+    type Res = Vec<BlockDeviceInfo>;
 
-    fn back(self) -> Vec<Cmd> {
+    fn back(self) -> Vec<BlockDeviceInfo> {
         Default::default()
     }
 }
@@ -2023,34 +2705,327 @@ impl Command for query_named_block_nodes {
 ///
 /// ```text
 /// -> { "execute": "query-blockstats" }
-/// <- {"return":[]}
+/// <- { "return": [{ "device": "drive-0", "qdev": "drive-0", "node-name": "drive-0",
+///      "stats": { "rd_bytes": 4096, "wr_bytes": 0, "unmap_bytes": 0,
+///      "rd_operations": 1, "wr_operations": 0, "flush_operations": 0,
+///      "unmap_operations": 0, "rd_total_time_ns": 52000, "wr_total_time_ns": 0,
+///      "flush_total_time_ns": 0, "unmap_total_time_ns": 0,
+///      "failed_rd_operations": 0, "failed_wr_operations": 0,
+///      "failed_flush_operations": 0, "failed_unmap_operations": 0,
+///      "rd_in_flight": 0, "wr_in_flight": 0, "flush_in_flight": 0,
+///      "unmap_in_flight": 0 } }] }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_blockstats {}
 
 impl Command for query_blockstats {
-    type Res = Vec<Cmd>;
+    type Res = Vec<BlockStats>;
 
-    fn back(self) -> Vec<Cmd> {
+    fn back(self) -> Vec<BlockStats> {
         Default::default()
     }
 }
 
+/// IO statistics of a block device.
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+pub struct BlockDeviceStats {
+    pub rd_bytes: u64,
+    pub wr_bytes: u64,
+    pub unmap_bytes: u64,
+    pub rd_operations: u64,
+    pub wr_operations: u64,
+    pub flush_operations: u64,
+    pub unmap_operations: u64,
+    pub rd_total_time_ns: u64,
+    pub wr_total_time_ns: u64,
+    pub flush_total_time_ns: u64,
+    pub unmap_total_time_ns: u64,
+    pub failed_rd_operations: u64,
+    pub failed_wr_operations: u64,
+    pub failed_flush_operations: u64,
+    pub failed_unmap_operations: u64,
+    pub rd_in_flight: u64,
+    pub wr_in_flight: u64,
+    pub flush_in_flight: u64,
+    pub unmap_in_flight: u64,
+}
+
+/// Statistics of a block device.
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+pub struct BlockStats {
+    pub device: String,
+    pub qdev: String,
+    #[serde(rename = "node-name")]
+    pub node_name: String,
+    pub stats: BlockDeviceStats,
+}
+
 /// Query jobs of blocks.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "query-block_jobs" }
-/// <- {"return":[]}
+/// -> { "execute": "query-block-jobs" }
+/// <- { "return": [{ "type": "mirror", "device": "drive0", "len": 1073741824,
+///                   "offset": 536870912, "busy": true, "paused": false,
+///                   "speed": 0, "ready": false, "status": "running",
+///                   "auto-dismiss": true }] }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_block_jobs {}
 
 impl Command for query_block_jobs {
-    type Res = Vec<Cmd>;
+    type Res = Vec<BlockJobInfo>;
 
-    fn back(self) -> Vec<Cmd> {
+    fn back(self) -> Vec<BlockJobInfo> {
+        Default::default()
+    }
+}
+
+/// Information about a block job.
+#[derive(Debug, Clone, Serialize, Deserialize, QmpSchema)]
+pub struct BlockJobInfo {
+    #[serde(rename = "type")]
+    pub job_type: JobType,
+    pub device: String,
+    pub len: u64,
+    pub offset: u64,
+    pub busy: bool,
+    pub paused: bool,
+    pub speed: u64,
+    pub ready: bool,
+    pub status: JobStatus,
+    #[serde(rename = "auto-dismiss")]
+    pub auto_dismiss: bool,
+}
+
+/// Type of a background job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, QmpSchema)]
+pub enum JobType {
+    #[serde(rename = "commit")]
+    commit,
+    #[serde(rename = "stream")]
+    stream,
+    #[serde(rename = "mirror")]
+    mirror,
+    #[serde(rename = "backup")]
+    backup,
+    #[serde(rename = "create")]
+    create,
+    #[serde(rename = "snapshot-load")]
+    snapshot_load,
+    #[serde(rename = "snapshot-save")]
+    snapshot_save,
+    #[serde(rename = "snapshot-delete")]
+    snapshot_delete,
+}
+
+impl JobType {
+    /// Whether the job works on a block device and is reported by
+    /// `query-block-jobs`.
+    pub fn is_block_job(&self) -> bool {
+        matches!(
+            self,
+            JobType::commit | JobType::stream | JobType::mirror | JobType::backup
+        )
+    }
+}
+
+/// Status of a background job.
+///
+/// A job starts as `created` and turns to `running` once it is started. A
+/// running job can be `paused`, or become `ready` when it waits for
+/// `job-complete`. Finished, failed or cancelled jobs are `concluded` until
+/// they are dismissed, which turns them to `null`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, QmpSchema)]
+pub enum JobStatus {
+    #[serde(rename = "created")]
+    created,
+    #[serde(rename = "running")]
+    running,
+    #[serde(rename = "paused")]
+    paused,
+    #[serde(rename = "ready")]
+    ready,
+    #[serde(rename = "concluded")]
+    concluded,
+    #[serde(rename = "null")]
+    null,
+}
+
+impl Default for JobStatus {
+    fn default() -> Self {
+        JobStatus::created
+    }
+}
+
+/// Query all background jobs.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-jobs" }
+/// <- { "return": [{ "id": "snapshot0", "type": "snapshot-save",
+///                   "status": "running", "current-progress": 1024,
+///                   "total-progress": 4096 }] }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+pub struct query_jobs {}
+
+impl Command for query_jobs {
+    type Res = Vec<JobInfo>;
+
+    fn back(self) -> Vec<JobInfo> {
+        Default::default()
+    }
+}
+
+/// Information about a background job.
+#[derive(Debug, Clone, Serialize, Deserialize, QmpSchema)]
+pub struct JobInfo {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub job_type: JobType,
+    pub status: JobStatus,
+    #[serde(rename = "current-progress")]
+    pub current_progress: u64,
+    #[serde(rename = "total-progress")]
+    pub total_progress: u64,
+    /// The error message if the job concluded with an error.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Pause an active job.
+///
+/// The job stops at its next pause point and stays `paused` until `job-resume`.
+///
+/// # Arguments
+///
+/// * `id` - The job identifier.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "job-pause", "arguments": { "id": "snapshot0" } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+#[serde(deny_unknown_fields)]
+pub struct job_pause {
+    pub id: String,
+}
+
+impl Command for job_pause {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// Resume a paused job.
+///
+/// # Arguments
+///
+/// * `id` - The job identifier.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "job-resume", "arguments": { "id": "snapshot0" } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+#[serde(deny_unknown_fields)]
+pub struct job_resume {
+    pub id: String,
+}
+
+impl Command for job_resume {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// Cancel an active job.
+///
+/// The job stops at its next pause point and concludes with an error.
+///
+/// # Arguments
+///
+/// * `id` - The job identifier.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "job-cancel", "arguments": { "id": "snapshot0" } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+#[serde(deny_unknown_fields)]
+pub struct job_cancel {
+    pub id: String,
+}
+
+impl Command for job_cancel {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// Complete a job in the `ready` status.
+///
+/// # Arguments
+///
+/// * `id` - The job identifier.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "job-complete", "arguments": { "id": "mirror0" } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+#[serde(deny_unknown_fields)]
+pub struct job_complete {
+    pub id: String,
+}
+
+impl Command for job_complete {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// Dismiss a concluded job, which removes it from `query-jobs`.
+///
+/// # Arguments
+///
+/// * `id` - The job identifier.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "job-dismiss", "arguments": { "id": "snapshot0" } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+#[serde(deny_unknown_fields)]
+pub struct job_dismiss {
+    pub id: String,
+}
+
+impl Command for job_dismiss {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
         Default::default()
     }
 }
@@ -2063,10 +3038,10 @@ impl Command for query_block_jobs {
 /// -> { "execute": "query-gic-capabilities" }
 /// <- {"return":[]}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_gic_capabilities {}
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct GicCap {
     emulated: bool,
     version: u32,
@@ -2089,10 +3064,10 @@ impl Command for query_gic_capabilities {
 /// -> { "execute": "query-iothreads" }
 /// <- {"return":[]}
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct query_iothreads {}
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct IothreadInfo {
     #[serde(rename = "poll-shrink")]
     pub shrink: u32,
@@ -2126,16 +3101,266 @@ impl Command for query_iothreads {
 ///      "arguments": { "key": "pointer", "value": "100,200,1" }}
 /// <- { "return": {} }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
 pub struct input_event {
     pub key: String,
     pub value: String,
 }
 
 impl Command for input_event {
-    type Res = Vec<input_event>;
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// guest-ping
+///
+/// Ping the guest agent, a non-error return implies success.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "guest-ping" }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+#[serde(deny_unknown_fields)]
+pub struct guest_ping {}
+
+impl Command for guest_ping {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
 
-    fn back(self) -> Vec<input_event> {
+/// guest-fsfreeze-freeze
+///
+/// Sync and freeze all freezable, local guest filesystems.
+///
+/// # Returns
+///
+/// Number of file systems currently frozen.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "guest-fsfreeze-freeze" }
+/// <- { "return": 2 }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+#[serde(deny_unknown_fields)]
+pub struct guest_fsfreeze_freeze {}
+
+impl Command for guest_fsfreeze_freeze {
+    type Res = u64;
+
+    fn back(self) -> u64 {
+        Default::default()
+    }
+}
+
+/// guest-fsfreeze-thaw
+///
+/// Unfreeze all frozen guest filesystems.
+///
+/// # Returns
+///
+/// Number of file systems thawed by this call.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "guest-fsfreeze-thaw" }
+/// <- { "return": 2 }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+#[serde(deny_unknown_fields)]
+pub struct guest_fsfreeze_thaw {}
+
+impl Command for guest_fsfreeze_thaw {
+    type Res = u64;
+
+    fn back(self) -> u64 {
+        Default::default()
+    }
+}
+
+/// guest-exec
+///
+/// Execute a command in the guest.
+///
+/// # Arguments
+///
+/// * `path` - Path or executable name to execute.
+/// * `arg` - Argument list to pass to executable.
+/// * `env` - Environment variables to pass to executable.
+/// * `input_data` - Base64 encoded data to be passed to process stdin.
+/// * `capture_output` - Whether to capture the stdout/stderr of the process.
+///
+/// # Returns
+///
+/// The pid of the process, which is used by `guest-exec-status`.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "guest-exec",
+///      "arguments": { "path": "/bin/ls", "arg": [ "/" ], "capture-output": true } }
+/// <- { "return": { "pid": 1234 } }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+#[serde(deny_unknown_fields)]
+pub struct guest_exec {
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arg: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<Vec<String>>,
+    #[serde(rename = "input-data")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_data: Option<String>,
+    #[serde(rename = "capture-output")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture_output: Option<bool>,
+}
+
+impl Command for guest_exec {
+    type Res = GuestExec;
+
+    fn back(self) -> GuestExec {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+pub struct GuestExec {
+    pub pid: i64,
+}
+
+/// guest-exec-status
+///
+/// Check the status of a process started by `guest-exec`.
+///
+/// # Arguments
+///
+/// * `pid` - The pid returned by `guest-exec`.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "guest-exec-status", "arguments": { "pid": 1234 } }
+/// <- { "return": { "exited": true, "exitcode": 0, "out-data": "YmluCmJvb3QK" } }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+#[serde(deny_unknown_fields)]
+pub struct guest_exec_status {
+    pub pid: i64,
+}
+
+impl Command for guest_exec_status {
+    type Res = GuestExecStatus;
+
+    fn back(self) -> GuestExecStatus {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+pub struct GuestExecStatus {
+    pub exited: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exitcode: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i64>,
+    #[serde(rename = "out-data")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub out_data: Option<String>,
+    #[serde(rename = "err-data")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub err_data: Option<String>,
+    #[serde(rename = "out-truncated")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub out_truncated: Option<bool>,
+    #[serde(rename = "err-truncated")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub err_truncated: Option<bool>,
+}
+
+/// guest-network-get-interfaces
+///
+/// Get list of guest IP addresses, MAC addresses and netmasks.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "guest-network-get-interfaces" }
+/// <- { "return": [ { "name": "lo", "hardware-address": "00:00:00:00:00:00",
+///                    "ip-addresses": [ { "ip-address": "127.0.0.1",
+///                                        "ip-address-type": "ipv4",
+///                                        "prefix": 8 } ] } ] }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+#[serde(deny_unknown_fields)]
+pub struct guest_network_get_interfaces {}
+
+impl Command for guest_network_get_interfaces {
+    type Res = Vec<GuestNetworkInterface>;
+
+    fn back(self) -> Vec<GuestNetworkInterface> {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+pub struct GuestNetworkInterface {
+    pub name: String,
+    #[serde(rename = "hardware-address")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hardware_address: Option<String>,
+    #[serde(rename = "ip-addresses")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip_addresses: Option<Vec<GuestIpAddress>>,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+pub struct GuestIpAddress {
+    #[serde(rename = "ip-address")]
+    pub ip_address: String,
+    #[serde(rename = "ip-address-type")]
+    pub ip_address_type: String,
+    pub prefix: i64,
+}
+
+/// guest-shutdown
+///
+/// Initiate guest-activated shutdown. The guest agent does not reply on
+/// success, so an empty return is sent once the request is delivered.
+///
+/// # Arguments
+///
+/// * `mode` - "halt", "powerdown" (default) or "reboot".
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "guest-shutdown", "arguments": { "mode": "reboot" } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize, QmpSchema)]
+#[serde(deny_unknown_fields)]
+pub struct guest_shutdown {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+}
+
+impl Command for guest_shutdown {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
         Default::default()
     }
 }
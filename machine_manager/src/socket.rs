@@ -10,15 +10,18 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use log::{error, info};
+use serde::Deserialize;
 use util::leak_bucket::LeakBucket;
 use util::loop_context::{
     gen_delete_notifiers, read_fd, EventNotifier, EventNotifierHelper, NotifierCallback,
@@ -32,78 +35,99 @@ use crate::qmp::{QmpChannel, QmpGreeting, Response};
 const MAX_SOCKET_MSG_LENGTH: usize = 8192;
 pub(crate) const LEAK_BUCKET_LIMIT: u64 = 100;
 
-/// The wrapper over Unix socket and socket handler.
+/// Id of the next client accepted by any `Socket`.
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// The wrapper over socket listener and the clients accepted by it.
 ///
 /// # Example
 ///
 /// ```no_run
 /// use std::os::unix::net::{UnixListener, UnixStream};
-/// use std::os::unix::io::AsRawFd;
-/// use std::io::prelude::*;
 ///
 /// use machine_manager::socket::Socket;
 ///
-/// fn main() -> std::io::Result<()> {
+/// fn main() -> anyhow::Result<()> {
 ///     let listener = UnixListener::bind("/path/to/my/socket")?;
 ///     let socket = Socket::from_unix_listener(listener, None);
 ///     assert!(!socket.is_connected());
 ///
 ///     let client_stream = UnixStream::connect("/path/to/my/socket")?;
-///     let server_stream = socket.accept_unix_stream();
-///     socket.bind_unix_stream(server_stream);
+///     let client = socket.accept()?;
 ///     assert!(socket.is_connected());
+///
+///     socket.drop_stream(client.id());
+///     assert!(!socket.is_connected());
 ///     Ok(())
 /// }
 /// ```
 pub struct Socket {
     /// Type for Socket
     sock_type: SocketType,
-    /// Socket listener tuple
-    listener: UnixListener,
-    /// Socket stream with RwLock
-    stream: RwLock<Option<SocketStream>>,
+    /// Socket listener
+    listener: SocketListener,
+    /// Tls configuration, the accepted streams are encrypted if it's set
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+    /// Clients accepted by the listener, indexed by client id
+    clients: RwLock<BTreeMap<u64, Arc<SocketConnection>>>,
     /// Perform socket command
     performer: Option<Arc<Mutex<dyn MachineExternalInterface>>>,
 }
 
 impl Socket {
-    /// Allocates a new `Socket` with `UnixListener`.
+    /// Allocates a new `Socket` with `SocketListener`.
     ///
     /// # Arguments
     ///
-    /// * `listener` - The `UnixListener` bind to `Socket`.
+    /// * `listener` - The `SocketListener` bind to `Socket`.
     /// * `performer` - The `VM` to perform socket command.
-    pub fn from_unix_listener(
-        listener: UnixListener,
+    pub fn new(
+        listener: SocketListener,
         performer: Option<Arc<Mutex<dyn MachineExternalInterface>>>,
     ) -> Self {
         Socket {
-            sock_type: SocketType::Unix,
+            sock_type: listener.socket_type(),
             listener,
-            stream: RwLock::new(None),
+            tls_config: None,
+            clients: RwLock::new(BTreeMap::new()),
             performer,
         }
     }
 
+    /// Allocates a new `Socket` with `UnixListener`.
+    ///
+    /// # Arguments
+    ///
+    /// * `listener` - The `UnixListener` bind to `Socket`.
+    /// * `performer` - The `VM` to perform socket command.
+    pub fn from_unix_listener(
+        listener: UnixListener,
+        performer: Option<Arc<Mutex<dyn MachineExternalInterface>>>,
+    ) -> Self {
+        Self::new(SocketListener::Unix(listener), performer)
+    }
+
+    /// Encrypt the streams accepted later with tls.
+    ///
+    /// # Arguments
+    ///
+    /// * `tls_config` - The tls configuration of server.
+    pub fn set_tls_config(&mut self, tls_config: Arc<rustls::ServerConfig>) {
+        self.tls_config = Some(tls_config);
+    }
+
     /// Get listener's fd from `Socket`.
     pub fn get_listener_fd(&self) -> RawFd {
         self.listener.as_raw_fd()
     }
 
-    /// Accept stream and bind to Socket.
-    pub fn accept(&self) {
-        match self.sock_type {
-            SocketType::Unix => {
-                let stream = self.accept_unix_stream();
-                self.bind_unix_stream(stream);
-            }
-        }
-    }
-
-    /// Accept a new incoming connection unix stream from unix listener.
-    pub fn accept_unix_stream(&self) -> UnixStream {
-        let (stream, _) = self.listener.accept().unwrap();
-        stream
+    /// Accept a new incoming connection and bind it to `Socket`.
+    pub fn accept(&self) -> Result<Arc<SocketConnection>> {
+        let stream = self
+            .listener
+            .accept()
+            .with_context(|| "Failed to accept socket connection")?;
+        self.bind_stream(stream)
     }
 
     /// Get socket type from `Socket`.
@@ -111,57 +135,48 @@ impl Socket {
         self.sock_type
     }
 
-    /// Bind `Socket` with a `UnixStream`.
+    /// Bind `Socket` with a new client on `stream`.
     ///
     /// # Arguments
     ///
-    /// * `unix_stream` - The `UnixStream` bind to `Socket`.
-    pub fn bind_unix_stream(&self, unix_stream: UnixStream) {
-        let stream = SocketStream::from_unix_stream(unix_stream);
-        *self.stream.write().unwrap() = Some(stream);
+    /// * `stream` - The `SocketStream` of the client.
+    pub fn bind_stream(&self, stream: SocketStream) -> Result<Arc<SocketConnection>> {
+        let tls = match &self.tls_config {
+            Some(config) => Some(Mutex::new(
+                rustls::ServerConnection::new(config.clone())
+                    .with_context(|| "Failed to create tls session")?,
+            )),
+            None => None,
+        };
+        let client = Arc::new(SocketConnection {
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::SeqCst),
+            stream,
+            tls,
+        });
+        self.clients
+            .write()
+            .unwrap()
+            .insert(client.id, client.clone());
+        Ok(client)
     }
 
-    /// Unbind stream from `Socket`, reset the state.
-    pub fn drop_stream(&self) {
-        *self.stream.write().unwrap() = None;
+    /// Unbind the client from `Socket`.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the client.
+    pub fn drop_stream(&self, id: u64) {
+        self.clients.write().unwrap().remove(&id);
     }
 
-    /// Confirm whether socket stream bind to `Socket` or not.
+    /// Confirm whether any client is bound to `Socket` or not.
     pub fn is_connected(&self) -> bool {
-        self.stream.read().unwrap().is_some()
+        !self.clients.read().unwrap().is_empty()
     }
 
-    /// Get socket fd from `Socket`, it a private function.
-    pub fn get_stream_fd(&self) -> RawFd {
-        if self.is_connected() {
-            self.stream.read().unwrap().as_ref().unwrap().as_raw_fd()
-        } else {
-            panic!("Failed to get socket fd!");
-        }
-    }
-
-    /// Get a `SocketHandler` from `Socket`.
-    pub fn get_socket_handler(&self) -> SocketHandler {
-        SocketHandler::new(self.get_stream_fd())
-    }
-
-    /// In qmp feature, send empty or greeting response to client.
-    ///
-    /// # Arguments
-    ///
-    /// * `is_greeting` - Whether sending greeting response or not.
-    pub fn send_response(&self, is_greeting: bool) -> std::io::Result<()> {
-        if self.is_connected() {
-            let mut handler = self.get_socket_handler();
-            let resp = if is_greeting {
-                serde_json::to_string(&QmpGreeting::create_greeting(1, 0, 5)).unwrap() + "\r"
-            } else {
-                serde_json::to_string(&Response::create_empty_response()).unwrap() + "\r"
-            };
-            handler.send_str(&resp)?;
-            info!("QMP: --> {:?}", resp);
-        }
-        Ok(())
+    /// Get the number of clients bound to `Socket`.
+    pub fn clients_count(&self) -> usize {
+        self.clients.read().unwrap().len()
     }
 
     /// Create socket's accepted stream to `event_notifier`.
@@ -177,32 +192,38 @@ impl Socket {
         let shared_leak_bucket = leak_bucket.clone();
         let leak_bucket_fd = leak_bucket.lock().unwrap().as_raw_fd();
 
-        self.accept();
-        QmpChannel::bind_writer(SocketRWHandler::new(self.get_stream_fd()));
-        if let Err(e) = self.send_response(true) {
+        let client = match self.accept() {
+            Ok(client) => client,
+            Err(e) => {
+                error!("{:?}", e);
+                return notifiers;
+            }
+        };
+        QmpChannel::bind_writer(client.clone());
+        if let Err(e) = client.send_response(true) {
             error!("{:?}", e);
-            QmpChannel::unbind();
+            QmpChannel::unbind(client.id());
+            self.drop_stream(client.id());
             return notifiers;
         }
-        let handler: Rc<NotifierCallback> = Rc::new(move |event, _| {
-            if event == EventSet::IN {
-                let socket_mutexed = shared_socket.lock().unwrap();
-                let stream_fd = socket_mutexed.get_stream_fd();
 
-                let performer = &socket_mutexed.performer.as_ref().unwrap();
+        let stream_fd = client.as_raw_fd();
+        let performer = self.performer.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |event, _| {
+            if event.contains(EventSet::IN) {
+                let performer = performer.as_ref().unwrap();
                 if let Err(e) = crate::qmp::handle_qmp(
-                    stream_fd,
+                    &client,
                     performer,
                     &mut shared_leak_bucket.lock().unwrap(),
                 ) {
                     error!("{:?}", e);
                 }
             }
-            if event & EventSet::HANG_UP == EventSet::HANG_UP {
-                let socket_mutexed = shared_socket.lock().unwrap();
-                let stream_fd = socket_mutexed.get_stream_fd();
-
-                QmpChannel::unbind();
+            // Peer closing of tcp stream is only reported as READ_HANG_UP.
+            if event.intersects(EventSet::HANG_UP | EventSet::READ_HANG_UP) {
+                QmpChannel::unbind(client.id());
+                shared_socket.lock().unwrap().drop_stream(client.id());
                 Some(gen_delete_notifiers(&[stream_fd, leak_bucket_fd]))
             } else {
                 None
@@ -210,9 +231,9 @@ impl Socket {
         });
         let qmp_notifier = EventNotifier::new(
             NotifierOperation::AddShared,
-            self.get_stream_fd(),
-            Some(self.get_listener_fd()),
-            EventSet::IN | EventSet::HANG_UP,
+            stream_fd,
+            None,
+            EventSet::IN | EventSet::HANG_UP | EventSet::READ_HANG_UP,
             vec![handler],
         );
         notifiers.push(qmp_notifier);
@@ -258,21 +279,220 @@ impl EventNotifierHelper for Socket {
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum SocketType {
     Unix = 1,
+    Tcp = 2,
 }
 
-/// Wrapper over UnixSteam.
+/// Listener of api socket.
 #[derive(Debug)]
-struct SocketStream(UnixStream);
+pub enum SocketListener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+impl SocketListener {
+    fn socket_type(&self) -> SocketType {
+        match self {
+            SocketListener::Unix(_) => SocketType::Unix,
+            SocketListener::Tcp(_) => SocketType::Tcp,
+        }
+    }
+
+    fn accept(&self) -> std::io::Result<SocketStream> {
+        match self {
+            SocketListener::Unix(listener) => Ok(SocketStream::Unix(listener.accept()?.0)),
+            SocketListener::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                // Qmp messages are small, send them without delay.
+                stream.set_nodelay(true)?;
+                Ok(SocketStream::Tcp(stream))
+            }
+        }
+    }
+}
 
-impl SocketStream {
-    fn from_unix_stream(stream: UnixStream) -> Self {
-        SocketStream(stream)
+impl AsRawFd for SocketListener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            SocketListener::Unix(listener) => listener.as_raw_fd(),
+            SocketListener::Tcp(listener) => listener.as_raw_fd(),
+        }
     }
 }
 
+/// Stream accepted by api socket.
+#[derive(Debug)]
+pub enum SocketStream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
 impl AsRawFd for SocketStream {
     fn as_raw_fd(&self) -> RawFd {
-        self.0.as_raw_fd()
+        match self {
+            SocketStream::Unix(stream) => stream.as_raw_fd(),
+            SocketStream::Tcp(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+impl Read for &SocketStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            SocketStream::Unix(stream) => {
+                let mut stream: &UnixStream = stream;
+                stream.read(buf)
+            }
+            SocketStream::Tcp(stream) => {
+                let mut stream: &TcpStream = stream;
+                stream.read(buf)
+            }
+        }
+    }
+}
+
+impl Write for &SocketStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            SocketStream::Unix(stream) => {
+                let mut stream: &UnixStream = stream;
+                stream.write(buf)
+            }
+            SocketStream::Tcp(stream) => {
+                let mut stream: &TcpStream = stream;
+                stream.write(buf)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A client accepted by `Socket`. The messages are sent and received through
+/// the tls session if tls is enabled for the socket.
+pub struct SocketConnection {
+    /// Id of the client
+    id: u64,
+    /// Stream connected with the client
+    stream: SocketStream,
+    /// Tls session over the stream
+    tls: Option<Mutex<rustls::ServerConnection>>,
+}
+
+impl SocketConnection {
+    /// Get the id of the client.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Receive the message available from the client, with the last file
+    /// descriptor passed in it. Return `None` if there is no message.
+    ///
+    /// # Errors
+    /// The socket is broken, the tls message is invalid or the message is
+    /// too long.
+    pub fn receive(&self) -> Result<Option<(String, Option<RawFd>)>> {
+        let tls = match &self.tls {
+            Some(tls) => tls,
+            None => {
+                let mut handler = SocketRWHandler::new(self.as_raw_fd());
+                handler.read_fd()?;
+                if handler.pos == 0 {
+                    return Ok(None);
+                }
+                return Ok(Some((handler.get_buf_string()?, handler.getfd())));
+            }
+        };
+
+        let mut tls = tls.lock().unwrap();
+        let mut stream = &self.stream;
+        if tls
+            .read_tls(&mut stream)
+            .with_context(|| "Failed to read tls message")?
+            == 0
+        {
+            return Ok(None);
+        }
+        let state = tls.process_new_packets();
+        // Send the handshake messages or the alert generated by the packets.
+        Self::flush_tls(&mut tls, &self.stream)?;
+        state.with_context(|| "Failed to process tls message")?;
+
+        let mut buf = Vec::new();
+        match tls.reader().read_to_end(&mut buf) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e).with_context(|| "Failed to read tls plaintext"),
+        }
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        if buf.len() > MAX_SOCKET_MSG_LENGTH {
+            bail!("The socket message is too long.");
+        }
+        Ok(Some((
+            String::from_utf8_lossy(&buf).trim().to_string(),
+            None,
+        )))
+    }
+
+    /// Discard the message available from the client.
+    pub fn discard(&self) -> Result<()> {
+        self.receive()?;
+        Ok(())
+    }
+
+    /// Send String to the client.
+    ///
+    /// # Arguments
+    ///
+    /// * `s` - The `String` send to the client.
+    ///
+    /// # Errors
+    /// The socket is broken.
+    pub fn send_str(&self, s: &str) -> std::io::Result<()> {
+        match &self.tls {
+            Some(tls) => {
+                let mut tls = tls.lock().unwrap();
+                tls.writer().write_all(s.as_bytes())?;
+                tls.writer().write_all(b"\n")?;
+                Self::flush_tls(&mut tls, &self.stream)
+            }
+            None => SocketHandler::new(self.as_raw_fd()).send_str(s),
+        }
+    }
+
+    /// In qmp feature, send empty or greeting response to client.
+    ///
+    /// # Arguments
+    ///
+    /// * `is_greeting` - Whether sending greeting response or not.
+    pub fn send_response(&self, is_greeting: bool) -> std::io::Result<()> {
+        let resp = if is_greeting {
+            serde_json::to_string(&QmpGreeting::create_greeting(1, 0, 5)).unwrap() + "\r"
+        } else {
+            serde_json::to_string(&Response::create_empty_response()).unwrap() + "\r"
+        };
+        self.send_str(&resp)?;
+        info!("QMP: --> {:?}", resp);
+        Ok(())
+    }
+
+    fn flush_tls(
+        tls: &mut rustls::ServerConnection,
+        mut stream: &SocketStream,
+    ) -> std::io::Result<()> {
+        while tls.wants_write() {
+            tls.write_tls(&mut stream)?;
+        }
+        Ok(())
+    }
+}
+
+impl AsRawFd for SocketConnection {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
     }
 }
 
@@ -642,13 +862,14 @@ impl SocketHandler {
 #[cfg(test)]
 mod tests {
     use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
     use std::os::unix::io::{AsRawFd, RawFd};
     use std::os::unix::net::{UnixListener, UnixStream};
     use std::time::Duration;
 
     use serde::{Deserialize, Serialize};
 
-    use super::{Socket, SocketHandler, SocketRWHandler, SocketType};
+    use super::{Socket, SocketHandler, SocketListener, SocketRWHandler, SocketStream, SocketType};
 
     // Environment Preparation for UnixSocket
     fn prepare_unix_socket_environment(socket_id: &str) -> (UnixListener, UnixStream, UnixStream) {
@@ -830,23 +1051,53 @@ mod tests {
         // life cycle test
         // 1.Unconnected
         assert_eq!(socket.is_connected(), false);
+        assert_eq!(socket.get_socket_type(), SocketType::Unix);
 
         // 2.Connected
-        socket.bind_unix_stream(server);
+        let client = socket.bind_stream(SocketStream::Unix(server)).unwrap();
         assert_eq!(socket.is_connected(), true);
-        assert_eq!(socket.get_socket_type(), SocketType::Unix);
-
-        // 3.Unbind SocketStream, reset state
-        socket.drop_stream();
-        assert_eq!(socket.is_connected(), false);
 
-        // 4.Accept and reconnect a new UnixStream
+        // 3.Accept a new UnixStream, both clients are bound
         let _new_client = UnixStream::connect("test_04.sock");
-        let new_server = socket.accept_unix_stream();
-        socket.bind_unix_stream(new_server);
-        assert_eq!(socket.is_connected(), true);
+        let new_client = socket.accept().unwrap();
+        assert_ne!(client.id(), new_client.id());
+        assert_eq!(socket.clients_count(), 2);
+
+        // 4.Unbind the clients, reset state
+        socket.drop_stream(client.id());
+        assert_eq!(socket.clients_count(), 1);
+        socket.drop_stream(new_client.id());
+        assert_eq!(socket.is_connected(), false);
 
         // After test. Environment Recover
         recover_unix_socket_environment("04");
     }
+
+    #[test]
+    fn test_tcp_socket_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let socket = Socket::new(SocketListener::Tcp(listener), None);
+        assert_eq!(socket.get_socket_type(), SocketType::Tcp);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let client = socket.accept().unwrap();
+
+        // Send string to tcp client.
+        client.send_str("I am a test str").unwrap();
+        let mut response = [0u8; 50];
+        let length = stream.read(&mut response).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&response[..length]),
+            "I am a test str\n".to_string()
+        );
+
+        // Receive message from tcp client.
+        assert!(client.receive().unwrap().is_none());
+        stream.write_all(b"{\"execute\": \"stop\"}\r\n").unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        let (msg, fd) = client.receive().unwrap().unwrap();
+        assert_eq!(msg, "{\"execute\": \"stop\"}");
+        assert!(fd.is_none());
+    }
 }
@@ -0,0 +1,121 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use syn::{Attribute, Lit, Meta, NestedMeta};
+
+/// Attribute `qmp_schema` is used above the `QmpCommand` and `QmpEvent` enums,
+/// for example: `#[qmp_schema(commands)]` or `#[qmp_schema(events)]`.
+const ATTRIBUTE_NAME: &str = "qmp_schema";
+
+/// What the derived type describes in the schema.
+pub enum SchemaKind {
+    /// A type used by commands and events.
+    Type,
+    /// Every variant is a command, the `arguments` field is its argument.
+    Commands,
+    /// Every variant is an event, the `data` field is its argument.
+    Events,
+}
+
+/// The `serde` attributes which affect the wire format.
+#[derive(Default)]
+pub struct SerdeAttributes {
+    pub rename: Option<String>,
+    pub tag: Option<String>,
+    pub default: bool,
+    pub flatten: bool,
+    pub skip: bool,
+}
+
+pub fn parse_schema_kind(attributes: &[Attribute]) -> SchemaKind {
+    let mut kind = SchemaKind::Type;
+    for attribute in attributes {
+        if !attribute.path.is_ident(ATTRIBUTE_NAME) {
+            continue;
+        }
+        for path in nested_metas(attribute)
+            .into_iter()
+            .filter_map(|meta| match meta {
+                Meta::Path(path) => Some(path),
+                _ => None,
+            })
+        {
+            if path.is_ident("commands") {
+                kind = SchemaKind::Commands;
+            } else if path.is_ident("events") {
+                kind = SchemaKind::Events;
+            } else {
+                panic!("Unsupported qmp_schema attribute.");
+            }
+        }
+    }
+    kind
+}
+
+pub fn parse_serde_attributes(attributes: &[Attribute]) -> SerdeAttributes {
+    let mut serde = SerdeAttributes::default();
+    for attribute in attributes {
+        if !attribute.path.is_ident("serde") {
+            continue;
+        }
+        for meta in nested_metas(attribute) {
+            match meta {
+                Meta::Path(path) => {
+                    if path.is_ident("default") {
+                        serde.default = true;
+                    } else if path.is_ident("flatten") {
+                        serde.flatten = true;
+                    } else if path.is_ident("skip") {
+                        serde.skip = true;
+                    }
+                }
+                Meta::NameValue(name_value) => {
+                    let value = match name_value.lit {
+                        Lit::Str(value) => value.value(),
+                        _ => continue,
+                    };
+                    if name_value.path.is_ident("rename") {
+                        serde.rename = Some(value);
+                    } else if name_value.path.is_ident("tag") {
+                        serde.tag = Some(value);
+                    } else if name_value.path.is_ident("default") {
+                        serde.default = true;
+                    }
+                }
+                Meta::List(_) => {}
+            }
+        }
+    }
+    serde
+}
+
+/// The `cfg` attributes, which must also guard the generated code.
+pub fn cfg_attributes(attributes: &[Attribute]) -> Vec<&Attribute> {
+    attributes
+        .iter()
+        .filter(|attribute| attribute.path.is_ident("cfg"))
+        .collect()
+}
+
+fn nested_metas(attribute: &Attribute) -> Vec<Meta> {
+    match attribute.parse_meta() {
+        Ok(Meta::List(list)) => list
+            .nested
+            .into_iter()
+            .filter_map(|nested| match nested {
+                NestedMeta::Meta(meta) => Some(meta),
+                NestedMeta::Lit(_) => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
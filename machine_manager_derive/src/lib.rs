@@ -0,0 +1,285 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! # machine_manager_derive
+//!
+//! Exports the `QmpSchema` derive which implements
+//! `machine_manager::qmp::introspect::QmpSchema` for the QMP message types,
+//! so that `query-qmp-schema` is generated from the same definitions serde
+//! uses to parse the messages. The `serde` attributes `rename`, `tag`,
+//! `default`, `flatten` and `skip` are honored.
+//!
+//! Supported inputs are:
+//! 1. Structs with named fields, described as `object`.
+//! 2. Enums with only unit variants, described as `enum`.
+//! 3. Internally tagged enums (`#[serde(tag = "...")]`), described as an
+//!    `object` with variants.
+//! 4. `QmpCommand` and `QmpEvent`, marked with `#[qmp_schema(commands)]` and
+//!    `#[qmp_schema(events)]`, which describe every variant as a `command`
+//!    or an `event`.
+//!
+//! The generated code refers to `QmpSchema` and `SchemaBuilder`, and also to
+//! `Command` for commands, which must be in scope.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DataEnum, DeriveInput, Fields, Type};
+
+mod attr_parser;
+
+use attr_parser::{cfg_attributes, parse_schema_kind, parse_serde_attributes, SchemaKind};
+
+#[proc_macro_derive(QmpSchema, attributes(qmp_schema))]
+pub fn derive_qmp_schema(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let ident = &input.ident;
+    let name = parse_serde_attributes(&input.attrs)
+        .rename
+        .unwrap_or_else(|| ident.to_string());
+
+    let body = match (&input.data, parse_schema_kind(&input.attrs)) {
+        (Data::Struct(data), SchemaKind::Type) => object_schema(&name, &data.fields),
+        (Data::Enum(data), SchemaKind::Type) => enum_schema(&name, &input.attrs, data),
+        (Data::Enum(data), SchemaKind::Commands) => commands_schema(&name, data),
+        (Data::Enum(data), SchemaKind::Events) => events_schema(&name, data),
+        _ => panic!("Unsupported type for QmpSchema."),
+    };
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    (quote! {
+        impl #impl_generics QmpSchema for #ident #ty_generics #where_clause {
+            fn qmp_schema(schema: &mut SchemaBuilder) -> String {
+                #body
+            }
+        }
+    })
+    .into()
+}
+
+/// Statements which collect the members of `fields` into `members`.
+fn collect_members(fields: &Fields) -> TokenStream2 {
+    let fields = match fields {
+        Fields::Named(fields) => &fields.named,
+        Fields::Unit => return quote! {},
+        Fields::Unnamed(_) => panic!("Only named fields are supported!"),
+    };
+
+    let statements = fields.iter().filter_map(|field| {
+        let serde = parse_serde_attributes(&field.attrs);
+        if serde.skip {
+            return None;
+        }
+        let ty = &field.ty;
+        let cfgs = cfg_attributes(&field.attrs);
+        if serde.flatten {
+            return Some(quote! {
+                #(#cfgs)*
+                {
+                    let flattened = <#ty as QmpSchema>::qmp_schema(schema);
+                    members.extend(schema.members(&flattened));
+                }
+            });
+        }
+        let name = serde
+            .rename
+            .unwrap_or_else(|| field.ident.as_ref().unwrap().to_string());
+        let optional = serde.default || is_option(ty);
+        Some(quote! {
+            #(#cfgs)*
+            {
+                let ty = <#ty as QmpSchema>::qmp_schema(schema);
+                members.push(SchemaBuilder::member(#name, ty, #optional));
+            }
+        })
+    });
+
+    quote! { #(#statements)* }
+}
+
+fn object_schema(name: &str, fields: &Fields) -> TokenStream2 {
+    let members = collect_members(fields);
+    quote! {
+        let name = #name;
+        if schema.declare(name) {
+            #[allow(unused_mut)]
+            let mut members = Vec::new();
+            #members
+            schema.define_object(name, members);
+        }
+        name.to_string()
+    }
+}
+
+fn enum_schema(name: &str, attrs: &[syn::Attribute], data: &DataEnum) -> TokenStream2 {
+    let cases: Vec<String> = data
+        .variants
+        .iter()
+        .map(|variant| {
+            parse_serde_attributes(&variant.attrs)
+                .rename
+                .unwrap_or_else(|| variant.ident.to_string())
+        })
+        .collect();
+
+    let tag = match parse_serde_attributes(attrs).tag {
+        Some(tag) => tag,
+        None => {
+            if data
+                .variants
+                .iter()
+                .any(|variant| !matches!(variant.fields, Fields::Unit))
+            {
+                panic!("Only unit variants are supported in untagged enum.");
+            }
+            return quote! {
+                let name = #name;
+                if schema.declare(name) {
+                    schema.define_enum(name, &[#(#cases),*]);
+                }
+                name.to_string()
+            };
+        }
+    };
+
+    // Internally tagged enum, the tag is an enum named by the union and the
+    // tag, such as `CpuInfoArch`, and every variant is an object named
+    // `<union>-<case>`.
+    let mut tag_chars = tag.chars();
+    let tag_type = format!(
+        "{}{}{}",
+        name,
+        tag_chars.next().unwrap().to_uppercase(),
+        tag_chars.as_str()
+    );
+    let variants = data
+        .variants
+        .iter()
+        .zip(cases.iter())
+        .map(|(variant, case)| {
+            let variant_name = format!("{}-{}", name, case);
+            let members = collect_members(&variant.fields);
+            let cfgs = cfg_attributes(&variant.attrs);
+            quote! {
+                #(#cfgs)*
+                {
+                    let variant = #variant_name;
+                    if schema.declare(variant) {
+                        #[allow(unused_mut)]
+                        let mut members = Vec::new();
+                        #members
+                        schema.define_object(variant, members);
+                    }
+                    variants.push((#case, variant.to_string()));
+                }
+            }
+        });
+
+    quote! {
+        let name = #name;
+        if schema.declare(name) {
+            schema.define_enum(#tag_type, &[#(#cases),*]);
+            let mut variants = Vec::new();
+            #(#variants)*
+            schema.define_union(name, #tag, #tag_type.to_string(), variants);
+        }
+        name.to_string()
+    }
+}
+
+/// Find the type of field `field_name` in every variant of `data`, with the
+/// wire name and `cfg` attributes of the variant.
+fn variant_fields<'a>(
+    data: &'a DataEnum,
+    field_name: &str,
+) -> Vec<(String, Vec<&'a syn::Attribute>, &'a Type)> {
+    data.variants
+        .iter()
+        .map(|variant| {
+            let name = parse_serde_attributes(&variant.attrs)
+                .rename
+                .unwrap_or_else(|| variant.ident.to_string());
+            let field = variant
+                .fields
+                .iter()
+                .find(|field| matches!(&field.ident, Some(ident) if ident == field_name))
+                .unwrap_or_else(|| panic!("{} has no field {}", name, field_name));
+            (name, cfg_attributes(&variant.attrs), unbox(&field.ty))
+        })
+        .collect()
+}
+
+fn commands_schema(name: &str, data: &DataEnum) -> TokenStream2 {
+    let commands = variant_fields(data, "arguments")
+        .into_iter()
+        .map(|(command, cfgs, ty)| {
+            quote! {
+                #(#cfgs)*
+                {
+                    let arg_type = <#ty as QmpSchema>::qmp_schema(schema);
+                    let ret_type = <<#ty as Command>::Res as QmpSchema>::qmp_schema(schema);
+                    schema.define_command(#command, arg_type, ret_type);
+                }
+            }
+        });
+
+    quote! {
+        #(#commands)*
+        #name.to_string()
+    }
+}
+
+fn events_schema(name: &str, data: &DataEnum) -> TokenStream2 {
+    let events = variant_fields(data, "data")
+        .into_iter()
+        .map(|(event, cfgs, ty)| {
+            quote! {
+                #(#cfgs)*
+                {
+                    let arg_type = <#ty as QmpSchema>::qmp_schema(schema);
+                    schema.define_event(#event, arg_type);
+                }
+            }
+        });
+
+    quote! {
+        #(#events)*
+        #name.to_string()
+    }
+}
+
+/// Get the last path segment of `ty` with its generic argument if any.
+fn last_segment(ty: &Type) -> Option<(String, Option<&Type>)> {
+    let segment = match ty {
+        Type::Path(path) => path.path.segments.last()?,
+        _ => return None,
+    };
+    let argument = match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) => match args.args.first() {
+            Some(syn::GenericArgument::Type(ty)) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    };
+    Some((segment.ident.to_string(), argument))
+}
+
+fn is_option(ty: &Type) -> bool {
+    matches!(last_segment(ty), Some((ident, _)) if ident == "Option")
+}
+
+fn unbox(ty: &Type) -> &Type {
+    match last_segment(ty) {
+        Some((ident, Some(inner))) if ident == "Box" => inner,
+        _ => ty,
+    }
+}
@@ -68,4 +68,6 @@ pub enum MigrationError {
     MigrationConfigErr(String, String, String),
     #[error("Invalid snapshot path for restoring snapshot")]
     InvalidSnapshotPath,
+    #[error("Migration is blocked by device {0}: {1}")]
+    MigrationBlocked(String, String),
 }
@@ -21,6 +21,8 @@ use crate::protocol::{
 };
 use crate::{MigrationError, MigrationManager};
 use anyhow::{anyhow, Context, Result};
+use machine_manager::event;
+use machine_manager::qmp::{qmp_schema as schema, QmpChannel};
 use util::{byte_code::ByteCode, unix::host_page_size};
 
 impl MigrationManager {
@@ -38,6 +40,7 @@ impl MigrationManager {
             header.desc_len = match format {
                 FileFormat::Device => Self::desc_db_len()?,
                 FileFormat::MemoryFull => (host_page_size() as usize) * 2 - HEADER_LENGTH,
+                FileFormat::MemoryIncremental => 0,
             };
         } else {
             header.desc_len = Self::desc_db_len()?;
@@ -190,8 +193,17 @@ impl MigrationManager {
     ///
     /// * `new_status`: new migration status, the transform must be illegal.
     pub fn set_status(new_status: MigrationStatus) -> Result<()> {
-        let mut status = MIGRATION_MANAGER.status.write().unwrap();
-        *status = status.transfer(new_status)?;
+        {
+            let mut status = MIGRATION_MANAGER.status.write().unwrap();
+            *status = status.transfer(new_status)?;
+        }
+
+        if QmpChannel::is_connected() {
+            let migration_event = schema::Migration {
+                status: new_status.to_string(),
+            };
+            event!(Migration; migration_event);
+        }
 
         Ok(())
     }
@@ -201,6 +213,11 @@ impl MigrationManager {
         Self::status() == MigrationStatus::Active
     }
 
+    /// Check whether dirty pages are logged for incremental snapshot.
+    pub fn is_tracking_snapshot() -> bool {
+        MIGRATION_MANAGER.last_snapshot.lock().unwrap().is_some()
+    }
+
     /// Check whether current migration status is cancel.
     pub fn is_canceled() -> bool {
         Self::status() == MigrationStatus::Canceled
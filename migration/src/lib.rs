@@ -17,9 +17,13 @@
 pub mod general;
 pub mod manager;
 pub mod migration;
+pub mod postcopy;
 pub mod protocol;
 pub mod snapshot;
+pub mod throttle;
+pub mod transfer;
 
+use std::sync::atomic::Ordering;
 use std::time::Duration;
 use std::{net::TcpStream, os::unix::net::UnixStream, thread};
 
@@ -27,7 +31,7 @@ use anyhow::anyhow;
 pub use anyhow::Result;
 use log::error;
 use machine_manager::qmp::{qmp_schema, Response};
-pub use manager::{MigrationHook, MigrationManager};
+pub use manager::{DeviceDataStage, DirtyPageTracker, MigrationHook, MigrationManager};
 pub use protocol::{DeviceStateDesc, FieldDesc, MemBlock, MigrationStatus, StateTransfer};
 pub mod error;
 pub use error::MigrationError;
@@ -37,8 +41,10 @@ pub use error::MigrationError;
 /// # Arguments
 ///
 /// * `path` - snapshot dir path. If path dir not exists, will create it.
-pub fn snapshot(path: String) -> Response {
-    if let Err(e) = MigrationManager::save_snapshot(&path) {
+/// * `parent` - parent snapshot dir path for incremental snapshot.
+/// * `incremental` - whether to log dirty pages for the next incremental snapshot.
+pub fn snapshot(path: String, parent: Option<String>, incremental: bool) -> Response {
+    if let Err(e) = MigrationManager::save_snapshot(&path, parent.as_deref(), incremental) {
         error!("Failed to migrate to path \'{:?}\': {:?}", path, e);
         let _ = MigrationManager::set_status(MigrationStatus::Failed).map_err(|e| anyhow!("{}", e));
         return Response::create_error_response(
@@ -56,30 +62,39 @@ pub fn snapshot(path: String) -> Response {
 ///
 /// * `path` - Unix socket path, as /tmp/migration.socket.
 pub fn migration_unix_mode(path: String) -> Response {
-    let mut socket = match UnixStream::connect(path) {
-        Ok(_sock) => {
-            // Specify the tcp receiving or send timeout.
-            let time_out = Some(Duration::from_secs(30));
-            _sock
-                .set_read_timeout(time_out)
-                .unwrap_or_else(|e| error!("{:?}", e));
-            _sock
-                .set_write_timeout(time_out)
-                .unwrap_or_else(|e| error!("{:?}", e));
-            _sock
-        }
-        Err(e) => {
-            return Response::create_error_response(
-                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
-                None,
-            )
-        }
+    if let Err(e) = MigrationManager::check_migration_blockers() {
+        return Response::create_error_response(
+            qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+            None,
+        );
+    }
+
+    let connect = || -> std::io::Result<UnixStream> {
+        let sock = UnixStream::connect(&path)?;
+        // Specify the unix receiving or send timeout.
+        let time_out = Some(Duration::from_secs(30));
+        sock.set_read_timeout(time_out)
+            .unwrap_or_else(|e| error!("{:?}", e));
+        sock.set_write_timeout(time_out)
+            .unwrap_or_else(|e| error!("{:?}", e));
+        Ok(sock)
     };
+    // Extra channels must be connected after the main channel.
+    let (mut socket, (channels, postcopy)) =
+        match connect().and_then(|sock| Ok((sock, connect_channels(connect)?))) {
+            Ok(sockets) => sockets,
+            Err(e) => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                )
+            }
+        };
 
     if let Err(e) = thread::Builder::new()
         .name("unix_migrate".to_string())
         .spawn(move || {
-            if let Err(e) = MigrationManager::send_migration(&mut socket) {
+            if let Err(e) = MigrationManager::send_migration(&mut socket, channels, postcopy) {
                 error!("Failed to send migration: {:?}", e);
                 let _ = MigrationManager::recover_from_migration();
                 let _ = MigrationManager::set_status(MigrationStatus::Failed)
@@ -102,30 +117,39 @@ pub fn migration_unix_mode(path: String) -> Response {
 ///
 /// * `path` - Tcp ip and port, as 192.168.1.1:4446.
 pub fn migration_tcp_mode(path: String) -> Response {
-    let mut socket = match TcpStream::connect(path) {
-        Ok(_sock) => {
-            // Specify the tcp receiving or send timeout.
-            let time_out = Some(Duration::from_secs(30));
-            _sock
-                .set_read_timeout(time_out)
-                .unwrap_or_else(|e| error!("{}", e));
-            _sock
-                .set_write_timeout(time_out)
-                .unwrap_or_else(|e| error!("{}", e));
-            _sock
-        }
-        Err(e) => {
-            return Response::create_error_response(
-                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
-                None,
-            )
-        }
+    if let Err(e) = MigrationManager::check_migration_blockers() {
+        return Response::create_error_response(
+            qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+            None,
+        );
+    }
+
+    let connect = || -> std::io::Result<TcpStream> {
+        let sock = TcpStream::connect(&path)?;
+        // Specify the tcp receiving or send timeout.
+        let time_out = Some(Duration::from_secs(30));
+        sock.set_read_timeout(time_out)
+            .unwrap_or_else(|e| error!("{}", e));
+        sock.set_write_timeout(time_out)
+            .unwrap_or_else(|e| error!("{}", e));
+        Ok(sock)
     };
+    // Extra channels must be connected after the main channel.
+    let (mut socket, (channels, postcopy)) =
+        match connect().and_then(|sock| Ok((sock, connect_channels(connect)?))) {
+            Ok(sockets) => sockets,
+            Err(e) => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                )
+            }
+        };
 
     if let Err(e) = thread::Builder::new()
         .name("tcp_migrate".to_string())
         .spawn(move || {
-            if let Err(e) = MigrationManager::send_migration(&mut socket) {
+            if let Err(e) = MigrationManager::send_migration(&mut socket, channels, postcopy) {
                 error!("Failed to send migration: {:?}", e);
                 let _ = MigrationManager::recover_from_migration();
                 let _ = MigrationManager::set_status(MigrationStatus::Failed)
@@ -142,11 +166,47 @@ pub fn migration_tcp_mode(path: String) -> Response {
     Response::create_empty_response()
 }
 
+/// Channels to send pages and receive page requests in post-copy.
+type PostcopyChannels<T> = Option<(T, T)>;
+
+/// Connect extra channels to send memory in parallel, and the channels to
+/// send pages and receive page requests in post-copy.
+fn connect_channels<T, F>(connect: F) -> std::io::Result<(Vec<T>, PostcopyChannels<T>)>
+where
+    F: Fn() -> std::io::Result<T>,
+{
+    let parameters = MigrationManager::parameters();
+    let channels = (0..parameters.multifd_channels)
+        .map(|_| connect())
+        .collect::<std::io::Result<Vec<T>>>()?;
+    let postcopy = if parameters.postcopy_ram {
+        Some((connect()?, connect()?))
+    } else {
+        None
+    };
+
+    Ok((channels, postcopy))
+}
+
 /// Query the current migration status.
 pub fn query_migrate() -> Response {
-    let status_str = MigrationManager::status().to_string();
+    let status = MigrationManager::status();
+    let stats = &manager::MIGRATION_MANAGER.postcopy_stats;
+    let total_pages = stats.total_pages.load(Ordering::SeqCst);
+    let postcopy = if status == MigrationStatus::PostcopyActive || total_pages != 0 {
+        let transferred_pages = stats.transferred_pages.load(Ordering::SeqCst);
+        Some(qmp_schema::PostcopyInfo {
+            total_pages,
+            transferred_pages,
+            remaining_pages: total_pages.saturating_sub(transferred_pages),
+            requested_pages: stats.requested_pages.load(Ordering::SeqCst),
+        })
+    } else {
+        None
+    };
     let migration_info = qmp_schema::MigrationInfo {
-        status: Some(status_str),
+        status: Some(status.to_string()),
+        postcopy,
     };
 
     Response::create_response(serde_json::to_value(migration_info).unwrap(), None)
@@ -163,3 +223,26 @@ pub fn cancel_migrate() -> Response {
 
     Response::create_empty_response()
 }
+
+/// Set the migration parameters.
+///
+/// # Arguments
+///
+/// * `args` - The parameters to be set, the omitted ones are unchanged.
+pub fn migrate_set_parameters(args: qmp_schema::MigrateSetParametersArgument) -> Response {
+    if let Err(e) = MigrationManager::set_parameters(&args) {
+        return Response::create_error_response(
+            qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+            None,
+        );
+    }
+
+    Response::create_empty_response()
+}
+
+/// Query the migration parameters.
+pub fn query_migrate_parameters() -> Response {
+    let parameters = MigrationManager::parameters();
+
+    Response::create_response(serde_json::to_value(parameters).unwrap(), None)
+}
@@ -10,22 +10,30 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::hash::Hash;
 use std::io::{Read, Write};
+use std::str::FromStr;
 use std::sync::{Arc, Mutex, RwLock};
-use std::time::Instant;
+use std::thread::JoinHandle;
 
 use log::info;
 use once_cell::sync::Lazy;
 
+use crate::error::MigrationError;
 use crate::general::translate_id;
 use crate::migration::DirtyBitmap;
-use crate::protocol::{DeviceStateDesc, MemBlock, MigrationStatus, StateTransfer};
-use anyhow::{Context, Result};
+use crate::postcopy::{PostcopyStats, RamBlock};
+use crate::protocol::{
+    CompressionType, DeviceStateDesc, MemBlock, MigrationStatus, StateTransfer,
+    MAX_MULTIFD_CHANNELS,
+};
+use crate::throttle::CPU_THROTTLE_MAX;
+use anyhow::{anyhow, bail, Context, Result};
 use machine_manager::config::VmConfig;
 use machine_manager::machine::MachineLifecycle;
+use machine_manager::qmp::qmp_schema::{MigrateSetParametersArgument, MigrationParameters};
 use util::byte_code::ByteCode;
 
 /// Global MigrationManager to manage all migration combined interface.
@@ -35,6 +43,10 @@ pub(crate) static MIGRATION_MANAGER: Lazy<MigrationManager> = Lazy::new(|| Migra
     status: Arc::new(RwLock::new(MigrationStatus::None)),
     vmm_bitmaps: Arc::new(RwLock::new(HashMap::new())),
     limit: Arc::new(RwLock::new(MigrationLimit::default())),
+    blockers: Arc::new(RwLock::new(BTreeMap::new())),
+    postcopy_stats: Arc::new(PostcopyStats::default()),
+    postcopy_threads: Arc::new(Mutex::new(Vec::new())),
+    last_snapshot: Arc::new(Mutex::new(None)),
 });
 
 /// A hook for `Device` to save device state to `Write` object and load device
@@ -138,6 +150,91 @@ pub trait MigrationHook: StateTransfer {
     fn resume(&mut self) -> Result<()> {
         Ok(())
     }
+
+    /// Throttle the device during live migration.
+    ///
+    /// # Notes
+    ///
+    /// For vCPU, it should sleep `percentage` of time to lower the speed of
+    /// dirtying memory. 0 means stop throttling.
+    ///
+    /// # Arguments
+    ///
+    /// * `_percentage` - Percentage of time to be throttled.
+    fn set_throttle(&self, _percentage: u8) -> Result<()> {
+        Ok(())
+    }
+
+    /// Get the guest RAM mapped in host, which is registered to userfaultfd
+    /// to fetch missing pages on demand in post-copy.
+    fn postcopy_ram(&self) -> Result<Vec<RamBlock>> {
+        Ok(Vec::new())
+    }
+
+    /// Whether the device has data which is opaque to VMM, such as the
+    /// internal state of passthrough device. The data is streamed in
+    /// migration besides the device state.
+    fn has_device_data(&self) -> bool {
+        false
+    }
+
+    /// Move the device to the stage of migrating its opaque data.
+    ///
+    /// # Arguments
+    ///
+    /// * `_stage` - The stage of migration in source VM.
+    fn set_device_data_stage(&mut self, _stage: DeviceDataStage) -> Result<()> {
+        Ok(())
+    }
+
+    /// Read the opaque device data available in current stage, empty data
+    /// means no more data is available for now.
+    ///
+    /// # Arguments
+    ///
+    /// * `_max_len` - Max length of data to read.
+    fn read_device_data(&mut self, _max_len: usize) -> Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+
+    /// Load the opaque device data received from source VM.
+    ///
+    /// # Arguments
+    ///
+    /// * `_data` - The device data in the order it is read in source VM.
+    fn write_device_data(&mut self, _data: &[u8]) -> Result<()> {
+        bail!("Device has no data to load")
+    }
+}
+
+/// Stage of migrating the opaque device data in source VM.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DeviceDataStage {
+    /// Device keeps running and its data is sent iteratively.
+    PreCopy,
+    /// Device is stopped and the remaining data is sent.
+    StopCopy,
+    /// Device runs again as migration is canceled or failed.
+    Running,
+}
+
+/// A tracker of the guest memory dirtied without being logged by kvm, such
+/// as DMA of passthrough devices.
+pub trait DirtyPageTracker {
+    /// Start tracking dirty pages.
+    fn start_dirty_tracking(&self) -> Result<()>;
+
+    /// Stop tracking dirty pages.
+    fn stop_dirty_tracking(&self) -> Result<()>;
+
+    /// Get and clear the dirty bitmap of guest memory, one bit for each
+    /// host page.
+    ///
+    /// # Arguments
+    ///
+    /// * `gpa` - Guest physical address of the memory.
+    /// * `len` - Length of the memory.
+    fn get_dirty_bitmap(&self, gpa: u64, len: u64) -> Result<Vec<u64>>;
 }
 
 /// The instance represents a single object in VM.
@@ -171,6 +268,8 @@ pub struct Vmm {
     pub transports: HashMap<u64, Arc<Mutex<dyn MigrationHook + Send + Sync>>>,
     /// Trait to represent devices.
     pub devices: HashMap<u64, Arc<Mutex<dyn MigrationHook + Send + Sync>>>,
+    /// Trackers of pages dirtied by devices.
+    pub dirty_trackers: HashMap<u64, Arc<Mutex<dyn DirtyPageTracker + Send + Sync>>>,
     #[cfg(target_arch = "aarch64")]
     /// Trait to represent GIC devices(GICv3, GICv3 ITS).
     pub gic_group: HashMap<u64, Arc<dyn MigrationHook + Send + Sync>>,
@@ -179,22 +278,141 @@ pub struct Vmm {
     pub kvm: Option<Arc<dyn MigrationHook + Send + Sync>>,
 }
 
+/// Max tolerable downtime of migration in milliseconds.
+const MAX_DOWNTIME_LIMIT: u64 = 2_000_000;
+
 /// Limit of migration.
 pub struct MigrationLimit {
-    /// Start time of each iteration.
-    pub iteration_start_time: Instant,
-    /// Virtual machine downtime.
+    /// Max bandwidth of migration in bytes per second, 0 means no limit.
+    pub max_bandwidth: u64,
+    /// Max tolerable virtual machine downtime in milliseconds.
     pub limit_downtime: u64,
     /// Max number of iterations during iteratively sending dirty memory.
     pub max_dirty_iterations: u16,
+    /// Throttle vCPUs if the guest dirties memory faster than it can be sent.
+    pub auto_converge: bool,
+    /// Initial percentage of vCPU throttling when auto-converge starts.
+    pub cpu_throttle_initial: u8,
+    /// Increment percentage of vCPU throttling for each later adjustment.
+    pub cpu_throttle_increment: u8,
+    /// Number of extra channels to send memory in parallel.
+    pub multifd_channels: u16,
+    /// Compression algorithm of memory pages.
+    pub compression: CompressionType,
+    /// Skip sending pages which are filled with zero.
+    pub zero_page: bool,
+    /// Switch to post-copy if pre-copy doesn't converge.
+    pub postcopy: bool,
 }
 
 impl Default for MigrationLimit {
     fn default() -> Self {
         Self {
-            iteration_start_time: Instant::now(),
+            max_bandwidth: 0,
             limit_downtime: 50,
             max_dirty_iterations: 30,
+            auto_converge: false,
+            cpu_throttle_initial: 20,
+            cpu_throttle_increment: 10,
+            multifd_channels: 0,
+            compression: CompressionType::None,
+            zero_page: false,
+            postcopy: false,
+        }
+    }
+}
+
+impl MigrationLimit {
+    /// Update the limit with migration parameters, nothing is changed if any
+    /// parameter is invalid.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - The parameters set by QMP command.
+    pub fn set_parameters(&mut self, args: &MigrateSetParametersArgument) -> Result<()> {
+        if let Some(downtime) = args.downtime_limit {
+            if downtime > MAX_DOWNTIME_LIMIT {
+                bail!(
+                    "Parameter downtime-limit {} is out of range [0, {}]",
+                    downtime,
+                    MAX_DOWNTIME_LIMIT
+                );
+            }
+        }
+        for (name, value) in [
+            ("cpu-throttle-initial", args.cpu_throttle_initial),
+            ("cpu-throttle-increment", args.cpu_throttle_increment),
+        ] {
+            if let Some(percentage) = value {
+                if !(1..=CPU_THROTTLE_MAX).contains(&percentage) {
+                    bail!(
+                        "Parameter {} {} is out of range [1, {}]",
+                        name,
+                        percentage,
+                        CPU_THROTTLE_MAX
+                    );
+                }
+            }
+        }
+
+        if let Some(channels) = args.multifd_channels {
+            if channels > MAX_MULTIFD_CHANNELS {
+                bail!(
+                    "Parameter multifd-channels {} is out of range [0, {}]",
+                    channels,
+                    MAX_MULTIFD_CHANNELS
+                );
+            }
+        }
+        let compression = args
+            .compression
+            .as_ref()
+            .map(|compression| CompressionType::from_str(compression))
+            .transpose()?;
+
+        if let Some(bandwidth) = args.max_bandwidth {
+            self.max_bandwidth = bandwidth;
+        }
+        if let Some(downtime) = args.downtime_limit {
+            self.limit_downtime = downtime;
+        }
+        if let Some(auto_converge) = args.auto_converge {
+            self.auto_converge = auto_converge;
+        }
+        if let Some(percentage) = args.cpu_throttle_initial {
+            self.cpu_throttle_initial = percentage;
+        }
+        if let Some(percentage) = args.cpu_throttle_increment {
+            self.cpu_throttle_increment = percentage;
+        }
+        if let Some(channels) = args.multifd_channels {
+            self.multifd_channels = channels;
+        }
+        if let Some(compression) = compression {
+            self.compression = compression;
+        }
+        if let Some(zero_page) = args.zero_page_detection {
+            self.zero_page = zero_page;
+        }
+        if let Some(postcopy) = args.postcopy_ram {
+            self.postcopy = postcopy;
+        }
+
+        Ok(())
+    }
+
+    /// Get the migration parameters.
+    pub fn parameters(&self) -> MigrationParameters {
+        MigrationParameters {
+            max_bandwidth: self.max_bandwidth,
+            downtime_limit: self.limit_downtime,
+            auto_converge: self.auto_converge,
+            cpu_throttle_initial: self.cpu_throttle_initial,
+            cpu_throttle_increment: self.cpu_throttle_increment,
+            multifd_channels: self.multifd_channels,
+            compression: self.compression.to_string(),
+            zero_page_detection: self.zero_page,
+            postcopy_ram: self.postcopy,
         }
     }
 }
@@ -212,6 +430,15 @@ pub struct MigrationManager {
     pub vmm_bitmaps: Arc<RwLock<HashMap<u32, DirtyBitmap>>>,
     /// Limiting elements of migration.
     pub limit: Arc<RwLock<MigrationLimit>>,
+    /// Devices which can't be migrated, mapped from device id to the reason.
+    pub blockers: Arc<RwLock<BTreeMap<String, String>>>,
+    /// Statistics of post-copy.
+    pub postcopy_stats: Arc<PostcopyStats>,
+    /// Threads of destination VM receiving memory in post-copy.
+    pub postcopy_threads: Arc<Mutex<Vec<JoinHandle<Result<()>>>>>,
+    /// Id of the last snapshot taken, dirty pages are logged against it to
+    /// take incremental snapshot.
+    pub last_snapshot: Arc<Mutex<Option<String>>>,
 }
 
 impl MigrationManager {
@@ -382,6 +609,99 @@ impl MigrationManager {
         let mut locked_vmm = MIGRATION_MANAGER.vmm.write().unwrap();
         locked_vmm.devices.remove(&translate_id(&name));
     }
+
+    /// Register dirty page tracker to vmm.
+    ///
+    /// # Arguments
+    ///
+    /// * `tracker` - The tracker with DirtyPageTracker trait.
+    /// * `id` - The unique id for tracker.
+    pub fn register_dirty_tracker<T>(tracker: Arc<Mutex<T>>, id: &str)
+    where
+        T: DirtyPageTracker + Sync + Send + 'static,
+    {
+        let mut locked_vmm = MIGRATION_MANAGER.vmm.write().unwrap();
+        locked_vmm.dirty_trackers.insert(translate_id(id), tracker);
+    }
+
+    /// Unregister dirty page tracker from vmm.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The unique id for tracker.
+    pub fn unregister_dirty_tracker(id: &str) {
+        let mut locked_vmm = MIGRATION_MANAGER.vmm.write().unwrap();
+        locked_vmm.dirty_trackers.remove(&translate_id(id));
+    }
+
+    /// Check that the opaque data of devices can be saved, which is only
+    /// streamed in live migration.
+    pub fn check_snapshot_device_data() -> Result<()> {
+        let locked_vmm = MIGRATION_MANAGER.vmm.read().unwrap();
+        if locked_vmm
+            .devices
+            .values()
+            .any(|device| device.lock().unwrap().has_device_data())
+        {
+            bail!("Snapshot is not supported for devices with opaque data, such as vfio devices");
+        }
+        Ok(())
+    }
+
+    /// Register a migration blocker for a device whose state can't be migrated.
+    /// Snapshot and migration will fail as long as the blocker exists.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The unique id for device.
+    /// * `reason` - Why the device blocks migration.
+    pub fn register_migration_blocker(id: &str, reason: &str) {
+        info!("Register migration blocker {}: {}", id, reason);
+        MIGRATION_MANAGER
+            .blockers
+            .write()
+            .unwrap()
+            .insert(id.to_string(), reason.to_string());
+    }
+
+    /// Unregister the migration blocker of device.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The unique id for device.
+    pub fn unregister_migration_blocker(id: &str) {
+        MIGRATION_MANAGER.blockers.write().unwrap().remove(id);
+    }
+
+    /// Check that no device blocks snapshot or migration.
+    pub fn check_migration_blockers() -> Result<()> {
+        let blockers = MIGRATION_MANAGER.blockers.read().unwrap();
+        if let Some((id, reason)) = blockers.iter().next() {
+            return Err(anyhow!(MigrationError::MigrationBlocked(
+                id.clone(),
+                reason.clone()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Set migration parameters, they take effect on the running migration.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - The parameters to be set, the omitted ones are unchanged.
+    pub fn set_parameters(args: &MigrateSetParametersArgument) -> Result<()> {
+        MIGRATION_MANAGER
+            .limit
+            .write()
+            .unwrap()
+            .set_parameters(args)
+    }
+
+    /// Get current migration parameters.
+    pub fn parameters() -> MigrationParameters {
+        MIGRATION_MANAGER.limit.read().unwrap().parameters()
+    }
 }
 
 #[cfg(test)]
@@ -423,4 +743,57 @@ mod tests {
             translate_id("DeviceV2State")
         );
     }
+
+    #[test]
+    fn test_migration_blocker() {
+        MigrationManager::register_migration_blocker("vfio0", "vfio device");
+        let err = MigrationManager::check_migration_blockers().unwrap_err();
+        assert!(err.to_string().contains("vfio0"));
+        MigrationManager::unregister_migration_blocker("vfio0");
+        assert!(MigrationManager::check_migration_blockers().is_ok());
+    }
+
+    #[test]
+    fn test_migration_parameters() {
+        let mut limit = MigrationLimit::default();
+        let mut args = MigrateSetParametersArgument {
+            max_bandwidth: Some(100 << 20),
+            downtime_limit: Some(300),
+            auto_converge: Some(true),
+            cpu_throttle_initial: Some(30),
+            cpu_throttle_increment: None,
+            multifd_channels: Some(4),
+            compression: Some("zstd".to_string()),
+            zero_page_detection: Some(true),
+            postcopy_ram: Some(true),
+        };
+        assert!(limit.set_parameters(&args).is_ok());
+        let params = limit.parameters();
+        assert_eq!(params.max_bandwidth, 100 << 20);
+        assert_eq!(params.downtime_limit, 300);
+        assert!(params.auto_converge);
+        assert_eq!(params.cpu_throttle_initial, 30);
+        assert_eq!(params.cpu_throttle_increment, 10);
+        assert_eq!(params.multifd_channels, 4);
+        assert_eq!(params.compression, "zstd");
+        assert!(params.zero_page_detection);
+        assert!(params.postcopy_ram);
+
+        // Nothing is changed with invalid parameter.
+        args.max_bandwidth = Some(0);
+        args.cpu_throttle_increment = Some(100);
+        assert!(limit.set_parameters(&args).is_err());
+        args.cpu_throttle_increment = Some(0);
+        assert!(limit.set_parameters(&args).is_err());
+        args.cpu_throttle_increment = None;
+        args.downtime_limit = Some(MAX_DOWNTIME_LIMIT + 1);
+        assert!(limit.set_parameters(&args).is_err());
+        args.downtime_limit = None;
+        args.multifd_channels = Some(MAX_MULTIFD_CHANNELS + 1);
+        assert!(limit.set_parameters(&args).is_err());
+        args.multifd_channels = None;
+        args.compression = Some("gzip".to_string());
+        assert!(limit.set_parameters(&args).is_err());
+        assert_eq!(limit.parameters().max_bandwidth, 100 << 20);
+    }
 }
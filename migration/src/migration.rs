@@ -13,19 +13,33 @@
 use std::collections::HashMap;
 use std::io::{Read, Write};
 use std::mem::size_of;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 use std::time::{Duration, Instant};
 
 use kvm_bindings::kvm_userspace_memory_region as MemorySlot;
-use log::{info, warn};
+use log::{error, info, warn};
 
 use crate::general::Lifecycle;
-use crate::manager::MIGRATION_MANAGER;
-use crate::protocol::{MemBlock, MigrationStatus, Request, Response, TransStatus};
+use crate::manager::{DeviceDataStage, MigrationHook, MIGRATION_MANAGER};
+use crate::postcopy::{
+    recv_page_requests, send_postcopy_pages, PageSet, PostcopyIncoming, RamBlock,
+};
+use crate::protocol::{
+    MemBlock, MemCapsHeader, MemTransferCaps, MigrationHeader, MigrationStatus, Request, Response,
+    TransStatus, MEM_CHUNK_SIZE,
+};
+use crate::throttle::{CpuThrottle, TokenBucket};
+use crate::transfer::{
+    consume_bandwidth, recv_chunks, run_on_channels, send_chunks, split_blocks, MemTransfer,
+    MigrationListener,
+};
 use crate::{MigrationError, MigrationManager};
 use anyhow::{anyhow, bail, Context, Result};
 use hypervisor::kvm::KVM_FDS;
 use machine_manager::config::{get_pci_bdf, PciBdf, VmConfig};
+use util::byte_code::ByteCode;
 use util::unix::host_page_size;
 
 impl MigrationManager {
@@ -36,34 +50,50 @@ impl MigrationManager {
     /// * `fd` - The fd implements `Read` and `Write` trait object. it
     /// will send source VM memory data and devices state to destination VM.
     /// And, it will receive confirmation from destination VM.
-    pub fn send_migration<T>(fd: &mut T) -> Result<()>
+    /// * `channels` - Extra channels to send memory in parallel, connected
+    ///   to destination after `fd`.
+    /// * `postcopy` - Channels to send pages and receive page requests in
+    ///   post-copy, connected after `channels`. None means post-copy is disabled.
+    pub fn send_migration<T>(fd: &mut T, channels: Vec<T>, postcopy: Option<(T, T)>) -> Result<()>
     where
-        T: Read + Write,
+        T: Read + Write + Send + 'static,
     {
+        if postcopy.is_some() && Self::has_device_data() {
+            bail!("Post-copy is not supported for devices with opaque data, such as vfio devices");
+        }
+        let caps = {
+            let limit = MIGRATION_MANAGER.limit.read().unwrap();
+            MemTransferCaps {
+                channels: channels.len() as u16,
+                compression: limit.compression,
+                zero_page: limit.zero_page,
+                postcopy: postcopy.is_some(),
+            }
+        };
+
         // Activate the migration status of source and destination virtual machine.
-        Self::active_migration(fd).with_context(|| "Failed to active migration")?;
+        Self::active_migration(fd, &caps).with_context(|| "Failed to active migration")?;
 
         // Send source virtual machine configuration.
         Self::send_vm_config(fd).with_context(|| "Failed to send vm config")?;
 
         // Start logging dirty pages.
         Self::start_dirty_log().with_context(|| "Failed to start logging dirty page")?;
+        let bucket = TokenBucket::new(MIGRATION_MANAGER.limit.read().unwrap().max_bandwidth);
+        let mut transfer = MemTransfer::new(caps, channels, bucket);
+
+        // Send the opaque device data while devices are running, if supported.
+        Self::set_device_data_stage(DeviceDataStage::PreCopy)
+            .with_context(|| "Failed to start pre-copy of device data")?;
 
         // Send all memory of virtual machine itself to destination.
-        Self::send_vm_memory(fd).with_context(|| "Failed to send VM memory")?;
+        Self::send_vm_memory(fd, &mut transfer).with_context(|| "Failed to send VM memory")?;
 
         // Iteratively send virtual machine dirty memory.
-        let iterations = MIGRATION_MANAGER.limit.read().unwrap().max_dirty_iterations;
-        for _ in 0..iterations {
-            // Check the migration is active.
-            if !Self::is_active() {
-                break;
-            }
-
-            if !Self::iteration_send(fd)? {
-                break;
-            }
-        }
+        let result = Self::iteration_send(fd, &mut transfer);
+        // The vCPUs will be paused or keep running, no need to throttle them.
+        Self::set_cpu_throttle(0);
+        let pending = result?;
 
         // Check whether the migration is canceled.
         if Self::is_canceled() {
@@ -75,8 +105,29 @@ impl MigrationManager {
         // Pause virtual machine.
         Self::pause()?;
 
+        // Stop devices and send their remaining opaque data.
+        Self::set_device_data_stage(DeviceDataStage::StopCopy)
+            .with_context(|| "Failed to start stop-copy of device data")?;
+        Self::send_device_data(fd, &transfer.bucket)
+            .with_context(|| "Failed to send device data")?;
+
+        // Run destination virtual machine before sending the remaining memory,
+        // if pre-copy doesn't converge.
+        let mut pending = match (pending, postcopy) {
+            (None, Some(postcopy)) => {
+                Self::send_postcopy(fd, &transfer, postcopy)
+                    .with_context(|| "Failed to send memory in post-copy")?;
+                return Ok(());
+            }
+            (pending, _) => pending.unwrap_or_default(),
+        };
+
         // Send remaining virtual machine dirty memory.
-        Self::send_dirty_memory(fd).with_context(|| "Failed to send dirty memory")?;
+        pending.extend(Self::collect_dirty_memory()?);
+        if !pending.is_empty() {
+            Self::send_memory(fd, pending, &mut transfer)
+                .with_context(|| "Failed to send dirty memory")?;
+        }
 
         // Stop logging dirty pages.
         Self::stop_dirty_log().with_context(|| "Failed to stop logging dirty page")?;
@@ -100,22 +151,58 @@ impl MigrationManager {
     /// * `fd` - The fd implements `Read` and `Write` trait object. it
     /// will receive source VM memory data and devices state. And,
     /// it will send confirmation to source VM.
-    pub fn recv_migration<T>(fd: &mut T) -> Result<()>
+    /// * `listener` - The listener to accept extra channels of memory.
+    pub fn recv_migration<T, L>(fd: &mut T, listener: &L) -> Result<()>
     where
         T: Read + Write,
+        L: MigrationListener,
+        L::Channel: 'static,
     {
         // Activate the migration status.
         let request = Request::recv_msg(fd)?;
-        if request.status == TransStatus::Active {
+        let caps = if request.status == TransStatus::Active {
             info!("Active the migration");
+            let caps = Self::recv_mem_caps(fd, request.length).and_then(|caps| {
+                if caps.postcopy {
+                    PostcopyIncoming::check(&Self::postcopy_ram()?)
+                        .with_context(|| "Post-copy is not supported")?;
+                }
+                Ok(caps)
+            });
+            let caps = match caps {
+                Ok(caps) => caps,
+                Err(e) => {
+                    Response::send_msg(fd, TransStatus::Error)?;
+                    return Err(e);
+                }
+            };
             Self::set_status(MigrationStatus::Active)?;
+            MIGRATION_MANAGER.postcopy_stats.reset(0);
             Response::send_msg(fd, TransStatus::Ok)?;
+            caps
         } else {
             Response::send_msg(fd, TransStatus::Error)?;
             return Err(anyhow!(MigrationError::MigrationStatusErr(
                 (request.status as u16).to_string(),
                 TransStatus::Active.to_string(),
             )));
+        };
+
+        // Accept extra channels which are connected after the main channel.
+        let mut channels = Vec::new();
+        for _ in 0..caps.channels {
+            channels.push(listener.accept_channel()?);
+        }
+        let mut postcopy = if caps.postcopy {
+            Some((listener.accept_channel()?, listener.accept_channel()?))
+        } else {
+            None
+        };
+        if !caps.is_plain() {
+            info!(
+                "Memory transfer with {} extra channels, compression {}, zero page detection {}, post-copy {}",
+                caps.channels, caps.compression, caps.zero_page, caps.postcopy
+            );
         }
 
         // Check source and destination virtual machine configuration.
@@ -137,7 +224,17 @@ impl MigrationManager {
             match request.status {
                 TransStatus::Memory => {
                     info!("Receive Memory status");
-                    Self::recv_vm_memory(fd, request.length)?;
+                    Self::recv_vm_memory(fd, request.length, &caps, &mut channels)?;
+                }
+                TransStatus::Postcopy => {
+                    info!("Receive Postcopy status");
+                    let postcopy = postcopy.take().ok_or_else(|| {
+                        anyhow!("Post-copy is not negotiated when migration is activated")
+                    })?;
+                    Self::recv_postcopy(fd, request.length, &caps, postcopy)?;
+                }
+                TransStatus::DeviceData => {
+                    Self::recv_device_data(fd, request.length)?;
                 }
                 TransStatus::State => {
                     info!("Receive State status");
@@ -275,38 +372,80 @@ impl MigrationManager {
         Ok(())
     }
 
-    /// Start to send dirty memory page iteratively. Return true if it should
-    /// continue to the next iteration. Otherwise, return false.
+    /// Start to send dirty memory page iteratively, until the remaining dirty
+    /// memory can be sent within the downtime limit. Return the dirty memory
+    /// blocks which are left to be sent after VM is paused, or None if it
+    /// doesn't converge.
     ///
     /// # Arguments
     ///
     /// * `fd` - The fd implements `Read` and `Write` trait object.
-    fn iteration_send<T>(fd: &mut T) -> Result<bool>
+    /// * `transfer` - The context of sending memory.
+    fn iteration_send<T>(fd: &mut T, transfer: &mut MemTransfer<T>) -> Result<Option<Vec<MemBlock>>>
     where
-        T: Write + Read,
+        T: Write + Read + Send + 'static,
     {
-        let mut state =
-            Self::send_dirty_memory(fd).with_context(|| "Failed to send dirty memory")?;
+        let mut throttle = CpuThrottle::default();
+        let iterations = MIGRATION_MANAGER.limit.read().unwrap().max_dirty_iterations;
+        for _ in 0..iterations {
+            // Check the migration is active.
+            if !Self::is_active() {
+                break;
+            }
 
-        // Check the virtual machine downtime.
-        if MIGRATION_MANAGER
-            .limit
-            .read()
-            .unwrap()
-            .iteration_start_time
-            .elapsed()
-            < Duration::from_millis(MIGRATION_MANAGER.limit.read().unwrap().limit_downtime)
-        {
-            state = false;
+            let blocks = Self::collect_dirty_memory()?;
+            let dirty_bytes = blocks.iter().map(|block| block.len).sum();
+            let estimator = &mut transfer.estimator;
+            estimator.record_dirty(dirty_bytes, Instant::now());
+
+            // Stop and copy if the remaining dirty memory can be sent within
+            // the downtime.
+            let limit_downtime =
+                Duration::from_millis(MIGRATION_MANAGER.limit.read().unwrap().limit_downtime);
+            let downtime = estimator.expected_downtime(dirty_bytes);
+            if downtime <= limit_downtime {
+                info!(
+                    "Migration converged, {} bytes dirty memory left, expected downtime {:?}",
+                    dirty_bytes, downtime
+                );
+                return Ok(Some(blocks));
+            }
+
+            Self::send_memory(fd, blocks, transfer)
+                .with_context(|| "Failed to send dirty memory")?;
+            Self::send_device_data(fd, &transfer.bucket)
+                .with_context(|| "Failed to send device data")?;
+
+            let estimator = &transfer.estimator;
+            let dirty_rate_high = estimator.is_dirty_rate_high();
+            let percentage =
+                throttle.update(&MIGRATION_MANAGER.limit.read().unwrap(), dirty_rate_high);
+            if let Some(percentage) = percentage {
+                info!(
+                    "Dirty rate {} bytes/s is too high for bandwidth {} bytes/s, throttle vCPUs {}%",
+                    estimator.dirty_rate(),
+                    estimator.bandwidth(),
+                    percentage
+                );
+                Self::set_cpu_throttle(percentage);
+            }
         }
-        // Update iteration start time.
-        MIGRATION_MANAGER
-            .limit
-            .write()
-            .unwrap()
-            .iteration_start_time = Instant::now();
 
-        Ok(state)
+        warn!("Migration doesn't converge in {} iterations", iterations);
+        Ok(None)
+    }
+
+    /// Throttle all vCPUs of virtual machine, 0 means stop throttling.
+    ///
+    /// # Arguments
+    ///
+    /// * `percentage` - Percentage of time vCPUs are throttled.
+    fn set_cpu_throttle(percentage: u8) {
+        for cpu in MIGRATION_MANAGER.vmm.read().unwrap().cpus.values() {
+            if let Err(e) = cpu.set_throttle(percentage) {
+                warn!("Failed to throttle vCPU: {:?}", e);
+            }
+        }
     }
 
     /// Receive memory data from source VM.
@@ -315,10 +454,34 @@ impl MigrationManager {
     ///
     /// * `fd` - The fd implements `Read` and `Write` trait object.
     /// * `len` - The length of Block data.
-    fn recv_vm_memory<T>(fd: &mut T, len: u64) -> Result<()>
+    /// * `caps` - Negotiated capabilities of memory transfer.
+    /// * `channels` - Extra channels to receive memory in parallel.
+    fn recv_vm_memory<T, C>(
+        fd: &mut T,
+        len: u64,
+        caps: &MemTransferCaps,
+        channels: &mut Vec<C>,
+    ) -> Result<()>
     where
         T: Write + Read,
+        C: Read + Send + 'static,
     {
+        let memory = MIGRATION_MANAGER.vmm.read().unwrap().memory.clone();
+        if !caps.is_plain() {
+            if let Some(memory) = &memory {
+                if channels.is_empty() {
+                    recv_chunks(fd, memory, caps)?;
+                } else {
+                    let (memory, caps) = (memory.clone(), *caps);
+                    run_on_channels(channels, move |_, channel| {
+                        recv_chunks(channel, &memory, &caps)
+                    })?;
+                }
+            }
+            Response::send_msg(fd, TransStatus::Ok)?;
+            return Ok(());
+        }
+
         let mut blocks = Vec::<MemBlock>::new();
         blocks.resize_with(len as usize / (size_of::<MemBlock>()), Default::default);
         fd.read_exact(unsafe {
@@ -328,7 +491,7 @@ impl MigrationManager {
             )
         })?;
 
-        if let Some(locked_memory) = &MIGRATION_MANAGER.vmm.read().unwrap().memory {
+        if let Some(locked_memory) = &memory {
             for block in blocks.iter() {
                 locked_memory.recv_memory(
                     fd,
@@ -351,25 +514,54 @@ impl MigrationManager {
     ///
     /// * `fd` - The fd implements `Read` and `Write` trait object.
     /// * `blocks` - The memory blocks need to be sent.
-    fn send_memory<T>(fd: &mut T, blocks: Vec<MemBlock>) -> Result<()>
+    /// * `transfer` - The context of sending memory.
+    fn send_memory<T>(
+        fd: &mut T,
+        blocks: Vec<MemBlock>,
+        transfer: &mut MemTransfer<T>,
+    ) -> Result<()>
     where
-        T: Read + Write,
+        T: Read + Write + Send + 'static,
     {
-        let len = size_of::<MemBlock>() * blocks.len();
-        Request::send_msg(fd, TransStatus::Memory, len as u64)?;
-        fd.write_all(unsafe {
-            std::slice::from_raw_parts(blocks.as_ptr() as *const MemBlock as *const u8, len)
-        })?;
-
-        if let Some(locked_memory) = &MIGRATION_MANAGER.vmm.read().unwrap().memory {
-            for block in blocks.iter() {
-                locked_memory.send_memory(
-                    fd,
-                    MemBlock {
-                        gpa: block.gpa,
-                        len: block.len,
-                    },
-                )?;
+        let start = Instant::now();
+        let memory = MIGRATION_MANAGER.vmm.read().unwrap().memory.clone();
+        // Memory is sent by chunks, so that the bandwidth limit is smooth and
+        // can be changed during sending.
+        let chunks = split_blocks(&blocks);
+        let caps = transfer.caps;
+        let bucket = &transfer.bucket;
+        let mut sent_bytes = 0;
+        if caps.is_plain() {
+            let len = size_of::<MemBlock>() * blocks.len();
+            Request::send_msg(fd, TransStatus::Memory, len as u64)?;
+            fd.write_all(unsafe {
+                std::slice::from_raw_parts(blocks.as_ptr() as *const MemBlock as *const u8, len)
+            })?;
+
+            if let Some(locked_memory) = &memory {
+                for chunk in chunks {
+                    consume_bandwidth(bucket, chunk.len);
+                    sent_bytes += chunk.len;
+                    locked_memory.send_memory(fd, chunk)?;
+                }
+            }
+        } else {
+            Request::send_msg(fd, TransStatus::Memory, 0)?;
+            if let Some(memory) = &memory {
+                let channels = &mut transfer.channels;
+                if channels.is_empty() {
+                    sent_bytes = send_chunks(fd, memory, chunks.iter(), &caps, bucket)?;
+                } else {
+                    let num = channels.len();
+                    let (memory, chunks, bucket) =
+                        (memory.clone(), Arc::new(chunks), bucket.clone());
+                    sent_bytes = run_on_channels(channels, move |idx, channel| {
+                        let chunks = chunks.iter().skip(idx).step_by(num);
+                        send_chunks(channel, &memory, chunks, &caps, &bucket)
+                    })?
+                    .iter()
+                    .sum();
+                }
             }
         }
 
@@ -377,6 +569,9 @@ impl MigrationManager {
         if result.is_err() {
             return Err(anyhow!(MigrationError::ResponseErr));
         }
+        transfer
+            .estimator
+            .record_transfer(sent_bytes, start.elapsed());
 
         Ok(())
     }
@@ -386,9 +581,10 @@ impl MigrationManager {
     /// # Arguments
     ///
     /// * `fd` - The fd implements `Read` and `Write` trait object.
-    fn send_vm_memory<T>(fd: &mut T) -> Result<()>
+    /// * `transfer` - The context of sending memory.
+    fn send_vm_memory<T>(fd: &mut T, transfer: &mut MemTransfer<T>) -> Result<()>
     where
-        T: Read + Write,
+        T: Read + Write + Send + 'static,
     {
         let mut blocks: Vec<MemBlock> = Vec::new();
         let slots = KVM_FDS.load().get_mem_slots();
@@ -399,34 +595,136 @@ impl MigrationManager {
             });
         }
 
-        Self::send_memory(fd, blocks)?;
+        Self::send_memory(fd, blocks, transfer)?;
+
+        Ok(())
+    }
+
+    /// Collect dirty memory blocks of VM and clear the dirty log.
+    pub(crate) fn collect_dirty_memory() -> Result<Vec<MemBlock>> {
+        let mut blocks: Vec<MemBlock> = Vec::new();
+        let mem_slots = KVM_FDS.load().get_mem_slots();
+        for (_, slot) in mem_slots.lock().unwrap().iter() {
+            let sub_blocks: Vec<MemBlock> = Self::get_dirty_log(slot)?;
+            blocks.extend(sub_blocks);
+        }
+
+        Ok(blocks)
+    }
+
+    /// Check whether any device has opaque data to migrate.
+    fn has_device_data() -> bool {
+        MIGRATION_MANAGER
+            .vmm
+            .read()
+            .unwrap()
+            .devices
+            .values()
+            .any(|device| device.lock().unwrap().has_device_data())
+    }
+
+    /// Move devices with opaque data to the stage of migration.
+    ///
+    /// # Arguments
+    ///
+    /// * `stage` - The stage of migration in source VM.
+    fn set_device_data_stage(stage: DeviceDataStage) -> Result<()> {
+        let locked_devices = &MIGRATION_MANAGER.vmm.read().unwrap().devices;
+        for device in locked_devices.values() {
+            let mut locked_device = device.lock().unwrap();
+            if locked_device.has_device_data() {
+                locked_device.set_device_data_stage(stage)?;
+            }
+        }
 
         Ok(())
     }
 
-    /// Send dirty memory data to destination VM.
+    /// Send the opaque device data available in current stage to destination
+    /// VM. The data is sent by chunks, each one is headed by the device id.
     ///
     /// # Arguments
     ///
     /// * `fd` - The fd implements `Read` and `Write` trait object.
-    fn send_dirty_memory<T>(fd: &mut T) -> Result<bool>
+    /// * `bucket` - The token bucket to limit bandwidth.
+    fn send_device_data<T>(fd: &mut T, bucket: &Mutex<TokenBucket>) -> Result<()>
     where
         T: Read + Write,
     {
-        let mut blocks: Vec<MemBlock> = Vec::new();
-        let mem_slots = KVM_FDS.load().get_mem_slots();
-        for (_, slot) in mem_slots.lock().unwrap().iter() {
-            let sub_blocks: Vec<MemBlock> = Self::get_dirty_log(slot)?;
-            blocks.extend(sub_blocks);
+        let devices: Vec<(u64, Arc<Mutex<dyn MigrationHook + Send + Sync>>)> = MIGRATION_MANAGER
+            .vmm
+            .read()
+            .unwrap()
+            .devices
+            .iter()
+            .filter(|(_, device)| device.lock().unwrap().has_device_data())
+            .map(|(id, device)| (*id, device.clone()))
+            .collect();
+
+        for (id, device) in devices {
+            loop {
+                let data = device
+                    .lock()
+                    .unwrap()
+                    .read_device_data(MEM_CHUNK_SIZE as usize)?;
+                if data.is_empty() {
+                    break;
+                }
+
+                consume_bandwidth(bucket, data.len() as u64);
+                let len = size_of::<u64>() + data.len();
+                Request::send_msg(fd, TransStatus::DeviceData, len as u64)?;
+                fd.write_all(&id.to_le_bytes())?;
+                fd.write_all(&data)?;
+
+                let result = Response::recv_msg(fd)?;
+                if result.is_err() {
+                    return Err(anyhow!(MigrationError::ResponseErr));
+                }
+            }
         }
 
-        if blocks.is_empty() {
-            return Ok(false);
+        Ok(())
+    }
+
+    /// Receive a chunk of opaque device data from source VM.
+    ///
+    /// # Arguments
+    ///
+    /// * `fd` - The fd implements `Read` and `Write` trait object.
+    /// * `len` - The length of device id and data.
+    fn recv_device_data<T>(fd: &mut T, len: u64) -> Result<()>
+    where
+        T: Read + Write,
+    {
+        if len <= size_of::<u64>() as u64 {
+            Response::send_msg(fd, TransStatus::Error)?;
+            bail!("Invalid length {} of device data", len);
         }
+        let mut id = [0_u8; size_of::<u64>()];
+        fd.read_exact(&mut id)?;
+        let id = u64::from_le_bytes(id);
+        let mut data = vec![0_u8; len as usize - size_of::<u64>()];
+        fd.read_exact(&mut data)?;
 
-        Self::send_memory(fd, blocks)?;
+        let device = MIGRATION_MANAGER
+            .vmm
+            .read()
+            .unwrap()
+            .devices
+            .get(&id)
+            .cloned();
+        let result = match device {
+            Some(device) => device.lock().unwrap().write_device_data(&data),
+            None => Err(anyhow!("Device {:x} of device data is not found", id)),
+        };
+        if let Err(e) = result {
+            Response::send_msg(fd, TransStatus::Error)?;
+            return Err(e);
+        }
+        Response::send_msg(fd, TransStatus::Ok)?;
 
-        Ok(true)
+        Ok(())
     }
 
     /// Send VM state data to destination VM.
@@ -470,17 +768,197 @@ impl MigrationManager {
         Ok(())
     }
 
+    /// Switch to post-copy after source VM is paused. The destination VM runs
+    /// once device state is sent, and the remaining dirty memory is sent in
+    /// background or on demand of destination.
+    ///
+    /// # Arguments
+    ///
+    /// * `fd` - The fd implements `Read` and `Write` trait object.
+    /// * `transfer` - The context of sending memory.
+    /// * `postcopy` - Channels to send pages and receive page requests.
+    fn send_postcopy<T>(fd: &mut T, transfer: &MemTransfer<T>, postcopy: (T, T)) -> Result<()>
+    where
+        T: Read + Write + Send + 'static,
+    {
+        let (mut page_channel, mut request_channel) = postcopy;
+        let memory = MIGRATION_MANAGER
+            .vmm
+            .read()
+            .unwrap()
+            .memory
+            .clone()
+            .ok_or_else(|| anyhow!("No memory to migrate"))?;
+
+        // Collect the memory dirtied after the last iteration, which is
+        // discarded by destination and fetched later.
+        let blocks = Self::collect_dirty_memory()?;
+        Self::stop_dirty_log().with_context(|| "Failed to stop logging dirty page")?;
+        let ranges: Vec<MemBlock> = KVM_FDS
+            .load()
+            .get_mem_slots()
+            .lock()
+            .unwrap()
+            .values()
+            .map(|slot| MemBlock {
+                gpa: slot.guest_phys_addr,
+                len: slot.memory_size,
+            })
+            .collect();
+        let pages = Arc::new(PageSet::new(&ranges, host_page_size()));
+        for block in blocks.iter() {
+            pages.insert(block)?;
+        }
+        MIGRATION_MANAGER.postcopy_stats.reset(pages.count());
+
+        let len = size_of::<MemBlock>() * blocks.len();
+        Request::send_msg(fd, TransStatus::Postcopy, len as u64)?;
+        fd.write_all(unsafe { std::slice::from_raw_parts(blocks.as_ptr() as *const u8, len) })?;
+        let result = Response::recv_msg(fd)?;
+        if result.is_err() {
+            return Err(anyhow!(MigrationError::ResponseErr));
+        }
+        // From now on, source VM can't be resumed as destination VM may run.
+        Self::set_status(MigrationStatus::PostcopyActive)?;
+        info!("Switch to post-copy with {} pages left", pages.count());
+
+        let caps = transfer.caps;
+        let bucket = transfer.bucket.clone();
+        let failed = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::channel();
+        let requests_failed = failed.clone();
+        let requests = thread::spawn(move || {
+            recv_page_requests(&mut request_channel, &sender, &requests_failed)
+        });
+        let pages_failed = failed.clone();
+        let pages = thread::spawn(move || {
+            let result = send_postcopy_pages(
+                &mut page_channel,
+                &memory,
+                &pages,
+                &receiver,
+                &caps,
+                &bucket,
+            );
+            if result.is_err() {
+                pages_failed.store(true, Ordering::SeqCst);
+            }
+            result
+        });
+
+        // Destination VM runs after receiving the device state.
+        let result = Self::send_vmstate(fd).with_context(|| "Failed to send vm state");
+        if result.is_err() {
+            failed.store(true, Ordering::SeqCst);
+        }
+        pages
+            .join()
+            .map_err(|_| anyhow!("Post-copy sending thread panicked"))??;
+        requests
+            .join()
+            .map_err(|_| anyhow!("Post-copy request thread panicked"))??;
+        result?;
+
+        // Complete the migration.
+        Self::complete_migration(fd).with_context(|| "Failed to completing migration")?;
+
+        // Destroy virtual machine.
+        Self::clear_migration().with_context(|| "Failed to clear migration")?;
+
+        Ok(())
+    }
+
+    /// Switch to post-copy in destination VM. Discard the memory dirtied in
+    /// source VM, and start threads to fetch it when guest accesses it.
+    ///
+    /// # Arguments
+    ///
+    /// * `fd` - The fd implements `Read` and `Write` trait object.
+    /// * `len` - The length of Block data.
+    /// * `caps` - Negotiated capabilities of memory transfer.
+    /// * `postcopy` - Channels to receive pages and send page requests.
+    fn recv_postcopy<T, C>(
+        fd: &mut T,
+        len: u64,
+        caps: &MemTransferCaps,
+        postcopy: (C, C),
+    ) -> Result<()>
+    where
+        T: Write + Read,
+        C: Read + Write + Send + 'static,
+    {
+        let mut blocks = Vec::<MemBlock>::new();
+        blocks.resize_with(len as usize / (size_of::<MemBlock>()), Default::default);
+        fd.read_exact(unsafe {
+            std::slice::from_raw_parts_mut(
+                blocks.as_ptr() as *mut MemBlock as *mut u8,
+                len as usize,
+            )
+        })?;
+
+        let incoming =
+            match Self::postcopy_ram().and_then(|ram| PostcopyIncoming::new(ram, &blocks)) {
+                Ok(incoming) => Arc::new(incoming),
+                Err(e) => {
+                    Response::send_msg(fd, TransStatus::Error)?;
+                    return Err(e);
+                }
+            };
+        Self::set_status(MigrationStatus::PostcopyActive)?;
+
+        // Page faults are handled before device state is restored, as devices
+        // may access guest memory when restoring.
+        let (mut page_channel, mut request_channel) = postcopy;
+        let caps = *caps;
+        let fault_incoming = incoming.clone();
+        let fault_handler = thread::Builder::new()
+            .name("postcopy_fault".to_string())
+            .spawn(move || fault_incoming.handle_faults(&mut request_channel))?;
+        let receiver = thread::Builder::new()
+            .name("postcopy_recv".to_string())
+            .spawn(move || incoming.recv_pages(&mut page_channel, &caps))?;
+        MIGRATION_MANAGER
+            .postcopy_threads
+            .lock()
+            .unwrap()
+            .extend([fault_handler, receiver]);
+
+        Response::send_msg(fd, TransStatus::Ok)?;
+
+        Ok(())
+    }
+
+    /// Get the guest RAM to be registered to userfaultfd in post-copy.
+    fn postcopy_ram() -> Result<Vec<RamBlock>> {
+        match &MIGRATION_MANAGER.vmm.read().unwrap().memory {
+            Some(memory) => memory.postcopy_ram(),
+            None => Ok(Vec::new()),
+        }
+    }
+
     /// Active migration status and synchronize the state of destination VM.
     ///
     /// # Arguments
     ///
     /// * `fd` - The fd implements `Read` and `Write` trait object.
-    fn active_migration<T>(fd: &mut T) -> Result<()>
+    /// * `caps` - Capabilities of memory transfer to negotiate.
+    fn active_migration<T>(fd: &mut T, caps: &MemTransferCaps) -> Result<()>
     where
         T: Read + Write,
     {
         Self::set_status(MigrationStatus::Active)?;
-        Request::send_msg(fd, TransStatus::Active, 0)?;
+        MIGRATION_MANAGER.postcopy_stats.reset(0);
+        if caps.is_plain() {
+            // Keep compatible with destination which doesn't know the capabilities.
+            Request::send_msg(fd, TransStatus::Active, 0)?;
+        } else {
+            let header = MigrationHeader::default();
+            let caps_header = MemCapsHeader::new(caps);
+            let len = size_of::<MigrationHeader>() + size_of::<MemCapsHeader>();
+            Request::send_msg(fd, TransStatus::Active, len as u64)?;
+            fd.write_all(header.as_bytes())?;
+            fd.write_all(caps_header.as_bytes())?;
+        }
         let result = Response::recv_msg(fd)?;
         if result.is_err() {
             return Err(anyhow!(MigrationError::ResponseErr));
@@ -489,6 +967,31 @@ impl MigrationManager {
         Ok(())
     }
 
+    /// Receive the memory transfer capabilities from source VM.
+    ///
+    /// # Arguments
+    ///
+    /// * `fd` - The fd implements `Read` and `Write` trait object.
+    /// * `len` - The length of headers, 0 means no capabilities.
+    fn recv_mem_caps<T>(fd: &mut T, len: u64) -> Result<MemTransferCaps>
+    where
+        T: Read + Write,
+    {
+        if len == 0 {
+            return Ok(MemTransferCaps::default());
+        }
+        if len != (size_of::<MigrationHeader>() + size_of::<MemCapsHeader>()) as u64 {
+            bail!("Invalid length {} of memory transfer capabilities", len);
+        }
+
+        let mut header = MigrationHeader::default();
+        fd.read_exact(header.as_mut_bytes())?;
+        header.check_header()?;
+        let mut caps_header = MemCapsHeader::default();
+        fd.read_exact(caps_header.as_mut_bytes())?;
+        caps_header.caps()
+    }
+
     /// Synchronize the `Completed` status of destination VM
     ///
     /// # Arguments
@@ -521,6 +1024,7 @@ impl MigrationManager {
         let request = Request::recv_msg(fd)?;
         if request.status == TransStatus::Complete {
             info!("Receive Complete status");
+            Self::join_postcopy_threads()?;
             Self::set_status(MigrationStatus::Completed)?;
             Response::send_msg(fd, TransStatus::Ok)?;
         } else {
@@ -533,6 +1037,24 @@ impl MigrationManager {
         Ok(())
     }
 
+    /// Wait for the threads of post-copy in destination VM to exit.
+    fn join_postcopy_threads() -> Result<()> {
+        let threads: Vec<_> = MIGRATION_MANAGER
+            .postcopy_threads
+            .lock()
+            .unwrap()
+            .drain(..)
+            .collect();
+        for thread in threads {
+            thread
+                .join()
+                .map_err(|_| anyhow!("Post-copy thread panicked"))?
+                .with_context(|| "Failed to receive memory in post-copy")?;
+        }
+
+        Ok(())
+    }
+
     /// Cancel live migration.
     ///
     /// # Arguments
@@ -544,6 +1066,8 @@ impl MigrationManager {
     {
         // Stop logging dirty pages.
         Self::stop_dirty_log().with_context(|| "Failed to stop logging dirty page")?;
+        Self::set_device_data_stage(DeviceDataStage::Running)
+            .with_context(|| "Failed to stop migrating device data")?;
 
         Request::send_msg(fd, TransStatus::Cancel, 0)?;
         let result = Response::recv_msg(fd)?;
@@ -565,6 +1089,15 @@ impl MigrationManager {
 
     /// Recover the virtual machine if migration is failed.
     pub fn recover_from_migration() -> Result<()> {
+        Self::set_cpu_throttle(0);
+        if Self::status() == MigrationStatus::PostcopyActive {
+            // Destination VM may have run with part of the memory.
+            error!("Post-copy failed, virtual machine can't be recovered");
+            return Ok(());
+        }
+        if let Err(e) = Self::set_device_data_stage(DeviceDataStage::Running) {
+            error!("Failed to stop migrating device data: {:?}", e);
+        }
         if let Some(locked_vm) = &MIGRATION_MANAGER.vmm.read().unwrap().vm {
             locked_vm.lock().unwrap().resume();
         }
@@ -661,10 +1194,23 @@ pub trait Migratable {
         }
         let mut vm_bitmaps = MIGRATION_MANAGER.vmm_bitmaps.write().unwrap();
         *vm_bitmaps = bitmaps;
+        // Dirty pages since the last snapshot are dropped with old bitmaps.
+        *MIGRATION_MANAGER.last_snapshot.lock().unwrap() = None;
 
         // Start logging dirty memory in kvm.
         KVM_FDS.load().start_dirty_log()?;
 
+        // Start tracking memory dirtied by devices.
+        for tracker in MIGRATION_MANAGER
+            .vmm
+            .read()
+            .unwrap()
+            .dirty_trackers
+            .values()
+        {
+            tracker.lock().unwrap().start_dirty_tracking()?;
+        }
+
         Ok(())
     }
 
@@ -673,10 +1219,22 @@ pub trait Migratable {
         // Clear dirty bitmaps from vmm.
         let mut vm_bitmaps = MIGRATION_MANAGER.vmm_bitmaps.write().unwrap();
         *vm_bitmaps = HashMap::new();
+        *MIGRATION_MANAGER.last_snapshot.lock().unwrap() = None;
 
         // Stop logging dirty memory in kvm.
         KVM_FDS.load().stop_dirty_log()?;
 
+        // Stop tracking memory dirtied by devices.
+        for tracker in MIGRATION_MANAGER
+            .vmm
+            .read()
+            .unwrap()
+            .dirty_trackers
+            .values()
+        {
+            tracker.lock().unwrap().stop_dirty_tracking()?;
+        }
+
         Ok(())
     }
 
@@ -702,12 +1260,29 @@ pub trait Migratable {
             .unwrap();
 
         // Merge dirty bitmap.
-        let dirty_bitmap: Vec<u64> = vm_dirty_bitmap
+        let mut dirty_bitmap: Vec<u64> = vm_dirty_bitmap
             .iter()
             .zip(vmm_dirty_bitmap.iter())
             .map(|(x, y)| x | y)
             .collect();
 
+        // Merge memory dirtied by devices.
+        for tracker in MIGRATION_MANAGER
+            .vmm
+            .read()
+            .unwrap()
+            .dirty_trackers
+            .values()
+        {
+            let device_bitmap = tracker
+                .lock()
+                .unwrap()
+                .get_dirty_bitmap(slot.guest_phys_addr, slot.memory_size)?;
+            for (x, y) in dirty_bitmap.iter_mut().zip(device_bitmap.iter()) {
+                *x |= y;
+            }
+        }
+
         // Convert dirty bitmaps to memory blocks.
         Ok(Self::sync_dirty_bitmap(dirty_bitmap, slot.guest_phys_addr))
     }
@@ -719,7 +1294,7 @@ pub trait Migratable {
     /// * `addr` - Start address of dirty memory.
     /// * `len` - Length of dirty memory.
     fn mark_dirty_log(addr: u64, len: u64) {
-        if !MigrationManager::is_active() {
+        if !MigrationManager::is_active() && !MigrationManager::is_tracking_snapshot() {
             return;
         }
 
@@ -775,3 +1350,79 @@ pub trait Migratable {
 }
 
 impl Migratable for MigrationManager {}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+    use crate::general::translate_id;
+    use crate::protocol::StateTransfer;
+
+    struct DataDevice {
+        chunks: Vec<Vec<u8>>,
+        received: Vec<u8>,
+    }
+
+    impl StateTransfer for DataDevice {
+        fn get_state_vec(&self) -> Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+
+        fn get_device_alias(&self) -> u64 {
+            !0
+        }
+    }
+
+    impl MigrationHook for DataDevice {
+        fn has_device_data(&self) -> bool {
+            true
+        }
+
+        fn read_device_data(&mut self, max_len: usize) -> Result<Vec<u8>> {
+            if self.chunks.is_empty() {
+                return Ok(Vec::new());
+            }
+            let chunk = self.chunks.remove(0);
+            assert!(chunk.len() <= max_len);
+            Ok(chunk)
+        }
+
+        fn write_device_data(&mut self, data: &[u8]) -> Result<()> {
+            self.received.extend_from_slice(data);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_device_data() {
+        let device = Arc::new(Mutex::new(DataDevice {
+            chunks: vec![vec![1_u8; 16], vec![2_u8; 8]],
+            received: Vec::new(),
+        }));
+        let id = translate_id("DataDevice/data0");
+        MIGRATION_MANAGER
+            .vmm
+            .write()
+            .unwrap()
+            .devices
+            .insert(id, device.clone());
+
+        let (mut src, mut dst) = UnixStream::pair().unwrap();
+        let receiver = thread::spawn(move || {
+            for _ in 0..2 {
+                let request = Request::recv_msg(&mut dst).unwrap();
+                assert!(request.status == TransStatus::DeviceData);
+                MigrationManager::recv_device_data(&mut dst, request.length).unwrap();
+            }
+        });
+        let bucket = Mutex::new(TokenBucket::new(0));
+        MigrationManager::send_device_data(&mut src, &bucket).unwrap();
+        receiver.join().unwrap();
+
+        MIGRATION_MANAGER.vmm.write().unwrap().devices.remove(&id);
+        let mut expected = vec![1_u8; 16];
+        expected.extend_from_slice(&[2_u8; 8]);
+        assert_eq!(device.lock().unwrap().received, expected);
+    }
+}
@@ -0,0 +1,688 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::{error, info, warn};
+use machine_manager::event_loop::EventLoop;
+use vmm_sys_util::eventfd::EventFd;
+
+use crate::general::Lifecycle;
+use crate::manager::{MigrationHook, MIGRATION_MANAGER};
+use crate::protocol::{MemBlock, MemChunkHeader, MemTransferCaps, PageRequest, MEM_CHUNK_SIZE};
+use crate::throttle::TokenBucket;
+use crate::transfer::{read_chunk, write_chunk};
+use crate::{MigrationManager, MigrationStatus};
+use util::byte_code::ByteCode;
+use util::num_ops::div_round_up;
+use util::unix::host_page_size;
+use util::userfaultfd::Userfaultfd;
+
+/// Guest RAM mapped in host, which is registered to userfaultfd in post-copy.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RamBlock {
+    /// Guest address.
+    pub gpa: u64,
+    /// Host virtual address.
+    pub hva: u64,
+    /// Size of memory.
+    pub len: u64,
+}
+
+/// Statistics of post-copy, reported by `query-migrate`.
+#[derive(Default)]
+pub struct PostcopyStats {
+    /// Pages left to be sent when switching to post-copy.
+    pub total_pages: AtomicU64,
+    /// Pages sent or received in post-copy.
+    pub transferred_pages: AtomicU64,
+    /// Pages requested by destination on page faults.
+    pub requested_pages: AtomicU64,
+}
+
+impl PostcopyStats {
+    pub fn reset(&self, total_pages: u64) {
+        self.total_pages.store(total_pages, Ordering::SeqCst);
+        self.transferred_pages.store(0, Ordering::SeqCst);
+        self.requested_pages.store(0, Ordering::SeqCst);
+    }
+}
+
+/// Set of guest pages, to track the pages not transferred yet in post-copy.
+pub struct PageSet {
+    page_size: u64,
+    /// Guest memory ranges and the bitmaps of their pages.
+    ranges: Vec<(MemBlock, Vec<AtomicU64>)>,
+}
+
+/// Position to continue scanning in `PageSet`.
+#[derive(Default)]
+pub struct PageCursor {
+    range: usize,
+    page: u64,
+}
+
+impl PageSet {
+    /// Create an empty set.
+    ///
+    /// # Arguments
+    ///
+    /// * `ranges` - Guest memory ranges the pages belong to.
+    /// * `page_size` - Size of a page.
+    pub fn new(ranges: &[MemBlock], page_size: u64) -> Self {
+        let ranges = ranges
+            .iter()
+            .map(|range| {
+                let words = div_round_up(div_round_up(range.len, page_size), 64);
+                let map = (0..words).map(|_| AtomicU64::new(0)).collect();
+                (range.clone(), map)
+            })
+            .collect();
+
+        PageSet { page_size, ranges }
+    }
+
+    pub fn page_size(&self) -> u64 {
+        self.page_size
+    }
+
+    fn locate(&self, gpa: u64) -> Option<(usize, u64)> {
+        self.ranges
+            .iter()
+            .position(|(range, _)| gpa >= range.gpa && gpa < range.gpa + range.len)
+            .map(|idx| (idx, (gpa - self.ranges[idx].0.gpa) / self.page_size))
+    }
+
+    fn test_and_clear(&self, range: usize, page: u64) -> bool {
+        let bit = 1 << (page % 64);
+        let word = &self.ranges[range].1[(page / 64) as usize];
+        word.fetch_and(!bit, Ordering::SeqCst) & bit != 0
+    }
+
+    /// Add the pages of a memory block to the set.
+    pub fn insert(&self, block: &MemBlock) -> Result<()> {
+        if block.len == 0 {
+            return Ok(());
+        }
+        let (idx, first) = self
+            .locate(block.gpa)
+            .with_context(|| format!("Memory 0x{:x} is not guest RAM", block.gpa))?;
+        let last = (block.gpa + block.len - 1 - self.ranges[idx].0.gpa) / self.page_size;
+        if last * self.page_size >= self.ranges[idx].0.len {
+            bail!(
+                "Memory 0x{:x} len 0x{:x} crosses guest RAM",
+                block.gpa,
+                block.len
+            );
+        }
+        for page in first..=last {
+            self.ranges[idx].1[(page / 64) as usize].fetch_or(1 << (page % 64), Ordering::SeqCst);
+        }
+
+        Ok(())
+    }
+
+    /// Check whether the page containing `gpa` is in the set.
+    pub fn contains(&self, gpa: u64) -> bool {
+        match self.locate(gpa) {
+            Some((idx, page)) => {
+                let word = self.ranges[idx].1[(page / 64) as usize].load(Ordering::SeqCst);
+                word & (1 << (page % 64)) != 0
+            }
+            None => false,
+        }
+    }
+
+    /// Remove the page containing `gpa`, return whether it was in the set.
+    pub fn take(&self, gpa: u64) -> bool {
+        matches!(self.locate(gpa), Some((idx, page)) if self.test_and_clear(idx, page))
+    }
+
+    /// Number of pages in the set.
+    pub fn count(&self) -> u64 {
+        self.ranges
+            .iter()
+            .flat_map(|(_, map)| map.iter())
+            .map(|word| word.load(Ordering::SeqCst).count_ones() as u64)
+            .sum()
+    }
+
+    /// Remove the next contiguous pages after `cursor`, at most `max_len`
+    /// bytes. Return None if no page is left after `cursor`.
+    pub fn take_next(&self, cursor: &mut PageCursor, max_len: u64) -> Option<MemBlock> {
+        while cursor.range < self.ranges.len() {
+            let (range, map) = &self.ranges[cursor.range];
+            let pages = div_round_up(range.len, self.page_size);
+            let mut block: Option<MemBlock> = None;
+            while cursor.page < pages {
+                let page = cursor.page;
+                // Skip the empty words quickly.
+                if page % 64 == 0
+                    && block.is_none()
+                    && map[(page / 64) as usize].load(Ordering::SeqCst) == 0
+                {
+                    cursor.page += 64;
+                    continue;
+                }
+                if !self.test_and_clear(cursor.range, page) {
+                    cursor.page += 1;
+                    if block.is_some() {
+                        break;
+                    }
+                    continue;
+                }
+                cursor.page += 1;
+                let gpa = range.gpa + page * self.page_size;
+                let len = self.page_size.min(range.len - page * self.page_size);
+                match &mut block {
+                    Some(block) => block.len += len,
+                    None => block = Some(MemBlock { gpa, len }),
+                }
+                if block.as_ref().unwrap().len + self.page_size > max_len {
+                    break;
+                }
+            }
+            if block.is_some() {
+                return block;
+            }
+            cursor.range += 1;
+            cursor.page = 0;
+        }
+
+        None
+    }
+}
+
+/// Receive page requests from destination through the return path, until
+/// the end request.
+///
+/// # Arguments
+///
+/// * `fd` - The return path from destination.
+/// * `requests` - Forward the guest address of requested pages.
+/// * `failed` - Whether post-copy has failed in other threads.
+pub fn recv_page_requests(
+    fd: &mut dyn Read,
+    requests: &Sender<u64>,
+    failed: &AtomicBool,
+) -> Result<()> {
+    loop {
+        let mut request = PageRequest::default();
+        let buf = request.as_mut_bytes();
+        let mut filled = 0;
+        while filled < buf.len() {
+            match fd.read(&mut buf[filled..]) {
+                Ok(0) => bail!("Return path of post-copy is closed"),
+                Ok(len) => filled += len,
+                // Guest may not access the missing pages for a long time.
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                    ) =>
+                {
+                    if failed.load(Ordering::SeqCst) {
+                        bail!("Stop receiving page requests as post-copy failed");
+                    }
+                }
+                Err(e) => return Err(e).with_context(|| "Failed to receive page request"),
+            }
+        }
+        if request.is_end() {
+            return Ok(());
+        }
+        // Requests are useless once all pages are sent.
+        let _ = requests.send(request.gpa);
+    }
+}
+
+/// Send the remaining pages to destination in post-copy, and end it with an
+/// empty chunk. The requested pages are sent first without bandwidth limit,
+/// as guest is waiting for them.
+///
+/// # Arguments
+///
+/// * `fd` - The channel to send pages.
+/// * `memory` - The memory to read pages from.
+/// * `pages` - The pages not sent yet.
+/// * `requests` - Guest address of pages requested by destination.
+/// * `caps` - Negotiated capabilities of memory transfer.
+/// * `bucket` - The token bucket to limit bandwidth.
+pub fn send_postcopy_pages(
+    fd: &mut dyn Write,
+    memory: &Arc<dyn MigrationHook + Send + Sync>,
+    pages: &PageSet,
+    requests: &Receiver<u64>,
+    caps: &MemTransferCaps,
+    bucket: &Mutex<TokenBucket>,
+) -> Result<()> {
+    let stats = &MIGRATION_MANAGER.postcopy_stats;
+    let page_size = pages.page_size();
+    let mut cursor = PageCursor::default();
+    let mut data = Vec::with_capacity(MEM_CHUNK_SIZE as usize);
+    loop {
+        while let Ok(gpa) = requests.try_recv() {
+            let gpa = gpa & !(page_size - 1);
+            if !pages.take(gpa) {
+                continue;
+            }
+            data.clear();
+            memory.send_memory(
+                &mut data,
+                MemBlock {
+                    gpa,
+                    len: page_size,
+                },
+            )?;
+            write_chunk(fd, caps, gpa, &data, None)?;
+            fd.flush()?;
+            stats.requested_pages.fetch_add(1, Ordering::SeqCst);
+            stats.transferred_pages.fetch_add(1, Ordering::SeqCst);
+        }
+
+        let block = match pages.take_next(&mut cursor, MEM_CHUNK_SIZE) {
+            Some(block) => block,
+            None => break,
+        };
+        data.clear();
+        memory.send_memory(&mut data, block.clone())?;
+        write_chunk(fd, caps, block.gpa, &data, Some(bucket))?;
+        stats
+            .transferred_pages
+            .fetch_add(div_round_up(block.len, page_size), Ordering::SeqCst);
+    }
+    fd.write_all(MemChunkHeader::default().as_bytes())?;
+    fd.flush()?;
+
+    Ok(())
+}
+
+/// Fail the post-copy in destination VM. Guest would hang on accessing the
+/// pages which are never received, so it's stopped.
+fn fail_postcopy() {
+    if let Err(e) = MigrationManager::set_status(MigrationStatus::Failed) {
+        error!("Failed to set post-copy failed: {:?}", e);
+    }
+    // The vCPUs may wait for the missing pages with the lock of the VM held,
+    // so pause the VM in the main loop.
+    let pause_vm = Box::new(|| {
+        if let Err(e) = MigrationManager::pause() {
+            error!("Failed to stop VM on post-copy failure: {:?}", e);
+        }
+    });
+    if let Some(ctx) = EventLoop::get_ctx(None) {
+        ctx.delay_call(pause_vm, 0);
+    } else {
+        error!("Failed to get ctx in event loop context to stop VM on post-copy failure");
+    }
+}
+
+/// Context of destination VM in post-copy.
+pub struct PostcopyIncoming {
+    uffd: Userfaultfd,
+    ram: Vec<RamBlock>,
+    /// Pages which are not received yet.
+    missing: PageSet,
+    /// Notify the fault handler that all pages are received.
+    done_evt: EventFd,
+}
+
+impl PostcopyIncoming {
+    /// Check whether post-copy is supported by destination VM.
+    ///
+    /// # Arguments
+    ///
+    /// * `ram` - The guest RAM to register to userfaultfd.
+    pub fn check(ram: &[RamBlock]) -> Result<()> {
+        if ram.is_empty() {
+            bail!("No guest RAM can be registered to userfaultfd");
+        }
+        Userfaultfd::new()?;
+
+        Ok(())
+    }
+
+    /// Register guest RAM to userfaultfd and discard the pages dirtied
+    /// after pre-copy, so that accessing them is reported as page faults.
+    ///
+    /// # Arguments
+    ///
+    /// * `ram` - The guest RAM to register to userfaultfd.
+    /// * `discard` - The memory which is dirty in source VM.
+    pub fn new(ram: Vec<RamBlock>, discard: &[MemBlock]) -> Result<Self> {
+        Self::check(&ram)?;
+        let uffd = Userfaultfd::new()?;
+        let ranges: Vec<MemBlock> = ram
+            .iter()
+            .map(|block| MemBlock {
+                gpa: block.gpa,
+                len: block.len,
+            })
+            .collect();
+        let incoming = PostcopyIncoming {
+            uffd,
+            ram,
+            missing: PageSet::new(&ranges, host_page_size()),
+            done_evt: EventFd::new(libc::EFD_NONBLOCK)
+                .with_context(|| "Failed to create eventfd of post-copy")?,
+        };
+
+        for block in incoming.ram.iter() {
+            incoming.uffd.register(block.hva, block.len)?;
+        }
+        for block in discard {
+            incoming.missing.insert(block)?;
+            let hva = incoming.hva(block.gpa, block.len)?;
+            // SAFETY: The range is guest RAM mapped privately, the pages are
+            // filled by userfaultfd later.
+            let ret = unsafe {
+                libc::madvise(
+                    hva as *mut libc::c_void,
+                    block.len as libc::size_t,
+                    libc::MADV_DONTNEED,
+                )
+            };
+            if ret != 0 {
+                return Err(std::io::Error::last_os_error())
+                    .with_context(|| format!("Failed to discard memory 0x{:x}", block.gpa));
+            }
+        }
+
+        let total_pages = incoming.missing.count();
+        MIGRATION_MANAGER.postcopy_stats.reset(total_pages);
+        info!("Post-copy starts with {} missing pages", total_pages);
+
+        Ok(incoming)
+    }
+
+    fn hva(&self, gpa: u64, len: u64) -> Result<u64> {
+        self.ram
+            .iter()
+            .find(|block| gpa >= block.gpa && gpa + len <= block.gpa + block.len)
+            .map(|block| block.hva + gpa - block.gpa)
+            .ok_or_else(|| anyhow!("Memory 0x{:x} len 0x{:x} is not guest RAM", gpa, len))
+    }
+
+    fn gpa(&self, hva: u64) -> Option<u64> {
+        self.ram
+            .iter()
+            .find(|block| hva >= block.hva && hva < block.hva + block.len)
+            .map(|block| block.gpa + hva - block.hva)
+    }
+
+    /// Receive pages from source and place them atomically, until the empty
+    /// chunk ending the transfer.
+    ///
+    /// # Arguments
+    ///
+    /// * `fd` - The channel to receive pages.
+    /// * `caps` - Negotiated capabilities of memory transfer.
+    pub fn recv_pages(&self, fd: &mut dyn Read, caps: &MemTransferCaps) -> Result<()> {
+        let result = self.do_recv_pages(fd, caps);
+        // Stop the fault handler anyway, the source can't serve requests.
+        self.done_evt
+            .write(1)
+            .with_context(|| "Failed to stop post-copy fault handler")?;
+        if let Err(ref e) = result {
+            error!("Post-copy failed, stop VM: {:?}", e);
+            fail_postcopy();
+        }
+        result
+    }
+
+    fn do_recv_pages(&self, fd: &mut dyn Read, caps: &MemTransferCaps) -> Result<()> {
+        let stats = &MIGRATION_MANAGER.postcopy_stats;
+        let page_size = self.missing.page_size();
+        let mut payload = Vec::new();
+        while let Some((gpa, data)) = read_chunk(fd, caps, &mut payload)? {
+            let len = data.len() as u64;
+            let hva = self.hva(gpa, len)?;
+            self.uffd.copy(hva, &data)?;
+            // Mark the pages after they are placed, the faults on them before
+            // are still requested.
+            let mut page = gpa;
+            while page < gpa + len {
+                self.missing.take(page);
+                page += page_size;
+            }
+            stats
+                .transferred_pages
+                .fetch_add(div_round_up(len, page_size), Ordering::SeqCst);
+        }
+
+        let missing = self.missing.count();
+        if missing != 0 {
+            bail!("{} pages are not received in post-copy", missing);
+        }
+        Ok(())
+    }
+
+    /// Handle page faults on guest RAM and request the missing pages from
+    /// source through the return path, until all pages are received.
+    ///
+    /// # Arguments
+    ///
+    /// * `fd` - The return path to source.
+    pub fn handle_faults(&self, fd: &mut dyn Write) -> Result<()> {
+        let stats = &MIGRATION_MANAGER.postcopy_stats;
+        let page_size = self.missing.page_size();
+        loop {
+            let mut fds = [
+                libc::pollfd {
+                    fd: self.uffd.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: self.done_evt.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+            ];
+            // SAFETY: The fds are valid during polling.
+            let ret = unsafe {
+                libc::ppoll(
+                    fds.as_mut_ptr(),
+                    fds.len() as libc::nfds_t,
+                    std::ptr::null(),
+                    std::ptr::null(),
+                )
+            };
+            if ret < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err).with_context(|| "Failed to poll userfaultfd");
+            }
+            if fds[1].revents & libc::POLLIN != 0 {
+                break;
+            }
+
+            while let Some(hva) = self.uffd.read_fault()? {
+                let gpa = match self.gpa(hva) {
+                    Some(gpa) => gpa & !(page_size - 1),
+                    None => {
+                        warn!("Page fault at 0x{:x} is not in guest RAM", hva);
+                        continue;
+                    }
+                };
+                // The page may be placed after the fault.
+                if !self.missing.contains(gpa) {
+                    continue;
+                }
+                fd.write_all(PageRequest { gpa }.as_bytes())?;
+                fd.flush()?;
+                stats.requested_pages.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        fd.write_all(PageRequest::END.as_bytes())?;
+        fd.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_set() {
+        let ranges = [
+            MemBlock {
+                gpa: 0,
+                len: 0x10_0000,
+            },
+            MemBlock {
+                gpa: 0x100_0000,
+                len: 0x10_0000,
+            },
+        ];
+        let pages = PageSet::new(&ranges, 0x1000);
+        assert_eq!(pages.count(), 0);
+
+        pages
+            .insert(&MemBlock {
+                gpa: 0x1000,
+                len: 0x3000,
+            })
+            .unwrap();
+        pages
+            .insert(&MemBlock {
+                gpa: 0x100_0000,
+                len: 0x1000,
+            })
+            .unwrap();
+        pages
+            .insert(&MemBlock {
+                gpa: 0x10_0000 - 0x1000,
+                len: 0x1000,
+            })
+            .unwrap();
+        assert_eq!(pages.count(), 5);
+        // Out of guest RAM.
+        assert!(pages
+            .insert(&MemBlock {
+                gpa: 0x10_0000,
+                len: 0x1000,
+            })
+            .is_err());
+        assert!(pages
+            .insert(&MemBlock {
+                gpa: 0xf_f000,
+                len: 0x2000,
+            })
+            .is_err());
+
+        assert!(pages.contains(0x2fff));
+        assert!(pages.take(0x2000));
+        assert!(!pages.take(0x2000));
+        assert!(!pages.contains(0x2fff));
+
+        // Contiguous pages are taken together, limited by the max length.
+        let mut cursor = PageCursor::default();
+        let block = pages.take_next(&mut cursor, 0x1000).unwrap();
+        assert_eq!((block.gpa, block.len), (0x1000, 0x1000));
+        let block = pages.take_next(&mut cursor, 0x10_0000).unwrap();
+        assert_eq!((block.gpa, block.len), (0x3000, 0x1000));
+        let block = pages.take_next(&mut cursor, 0x10_0000).unwrap();
+        assert_eq!((block.gpa, block.len), (0xf_f000, 0x1000));
+        let block = pages.take_next(&mut cursor, 0x10_0000).unwrap();
+        assert_eq!((block.gpa, block.len), (0x100_0000, 0x1000));
+        assert!(pages.take_next(&mut cursor, 0x10_0000).is_none());
+        assert_eq!(pages.count(), 0);
+    }
+
+    #[test]
+    fn test_page_requests() {
+        let mut stream = Vec::new();
+        for gpa in [0x1000_u64, 0x5000] {
+            stream.extend_from_slice(PageRequest { gpa }.as_bytes());
+        }
+        stream.extend_from_slice(PageRequest::END.as_bytes());
+        // Requests after the end are not received.
+        stream.extend_from_slice(PageRequest { gpa: 0x9000 }.as_bytes());
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let failed = AtomicBool::new(false);
+        recv_page_requests(&mut stream.as_slice(), &sender, &failed).unwrap();
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<u64>>(),
+            vec![0x1000, 0x5000]
+        );
+
+        // The closed return path is an error.
+        let mut stream = PageRequest { gpa: 0x1000 }.as_bytes().to_vec();
+        stream.truncate(4);
+        assert!(recv_page_requests(&mut stream.as_slice(), &sender, &failed).is_err());
+    }
+
+    #[test]
+    fn test_recv_missing_pages() {
+        let page_size = host_page_size();
+        let len = page_size * 4;
+        // SAFETY: The anonymous mapping is only used in the test and unmapped at last.
+        let hva = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len as libc::size_t,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(hva, libc::MAP_FAILED);
+        let ram = vec![RamBlock {
+            gpa: 0,
+            hva: hva as u64,
+            len,
+        }];
+        let discard = [MemBlock {
+            gpa: 0,
+            len: page_size * 2,
+        }];
+        // Userfaultfd may be not permitted in the test environment.
+        if let Ok(incoming) = PostcopyIncoming::new(ram, &discard) {
+            let caps = MemTransferCaps::default();
+            let page = vec![0x5a_u8; page_size as usize];
+            let mut stream = Vec::new();
+            write_chunk(&mut stream, &caps, 0, &page, None).unwrap();
+            stream.extend_from_slice(MemChunkHeader::default().as_bytes());
+
+            // The stream ends with the second page missing.
+            let err = incoming
+                .do_recv_pages(&mut stream.as_slice(), &caps)
+                .unwrap_err();
+            assert!(err.to_string().contains("1 pages are not received"));
+            assert!(!incoming.missing.contains(0));
+            assert!(incoming.missing.contains(page_size));
+
+            let mut stream = Vec::new();
+            write_chunk(&mut stream, &caps, page_size, &page, None).unwrap();
+            stream.extend_from_slice(MemChunkHeader::default().as_bytes());
+            incoming
+                .do_recv_pages(&mut stream.as_slice(), &caps)
+                .unwrap();
+            assert_eq!(incoming.missing.count(), 0);
+        }
+
+        // SAFETY: The mapping is created above.
+        unsafe { libc::munmap(hva, len as libc::size_t) };
+    }
+}
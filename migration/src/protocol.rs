@@ -14,6 +14,7 @@ use std::cmp::Ordering;
 use std::io::{Read, Write};
 use std::mem::size_of;
 use std::slice::{from_raw_parts, from_raw_parts_mut};
+use std::str::FromStr;
 
 use kvm_ioctls::Kvm;
 use serde::{Deserialize, Serialize};
@@ -29,10 +30,12 @@ use util::byte_code::ByteCode;
 /// None -----------> Setup: set up migration resource.
 /// Setup ----------> Active: migration is ready.
 /// Active ---------> Completed: migration is successful.
+/// Active ---------> PostcopyActive: destination VM runs and fetches memory.
+/// PostcopyActive -> Completed: all memory is sent to destination.
 /// Completed ------> Active: make migration become ready again.
 /// Failed ---------> Setup: reset migration resource.
 /// Any ------------> Failed: something wrong in migration.
-/// Any ------------> Canceled: cancel migration.
+/// Any ------------> Canceled: cancel migration, except in post-copy.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum MigrationStatus {
     /// Migration resource is not prepared all
@@ -41,6 +44,8 @@ pub enum MigrationStatus {
     Setup,
     /// Migration is active.
     Active,
+    /// Destination VM is running and the remaining memory is in transit.
+    PostcopyActive,
     /// Migration completed.
     Completed,
     /// Migration failed.
@@ -58,6 +63,7 @@ impl std::fmt::Display for MigrationStatus {
                 MigrationStatus::None => "none",
                 MigrationStatus::Setup => "setup",
                 MigrationStatus::Active => "active",
+                MigrationStatus::PostcopyActive => "postcopy-active",
                 MigrationStatus::Completed => "completed",
                 MigrationStatus::Failed => "failed",
                 MigrationStatus::Canceled => "canceled",
@@ -86,12 +92,20 @@ impl MigrationStatus {
             },
             MigrationStatus::Active => match new_status {
                 MigrationStatus::Completed
+                | MigrationStatus::PostcopyActive
                 | MigrationStatus::Failed
                 | MigrationStatus::Canceled => Ok(new_status),
                 _ => Err(anyhow!(MigrationError::InvalidStatusTransfer(
                     self, new_status
                 ))),
             },
+            // Neither VM has the whole memory, it can't be canceled.
+            MigrationStatus::PostcopyActive => match new_status {
+                MigrationStatus::Completed | MigrationStatus::Failed => Ok(new_status),
+                _ => Err(anyhow!(MigrationError::InvalidStatusTransfer(
+                    self, new_status
+                ))),
+            },
             MigrationStatus::Completed => match new_status {
                 MigrationStatus::Active => Ok(new_status),
                 _ => Err(anyhow!(MigrationError::InvalidStatusTransfer(
@@ -131,6 +145,10 @@ pub enum TransStatus {
     Error,
     /// Unknown status in migration .
     Unknown,
+    /// Switch to post-copy stage in migration.
+    Postcopy,
+    /// Processing device data which is opaque to VMM in migration.
+    DeviceData,
 }
 
 impl Default for TransStatus {
@@ -154,6 +172,8 @@ impl std::fmt::Display for TransStatus {
                 TransStatus::Ok => "Ok",
                 TransStatus::Error => "Error",
                 TransStatus::Unknown => "Unknown",
+                TransStatus::Postcopy => "Postcopy",
+                TransStatus::DeviceData => "DeviceData",
             }
         )
     }
@@ -282,6 +302,188 @@ pub struct MemBlock {
     pub len: u64,
 }
 
+/// Max size of memory sent at once.
+pub const MEM_CHUNK_SIZE: u64 = 1 << 20;
+/// Page size to detect zero page in memory chunk.
+pub const MEM_ENCODE_PAGE_SIZE: u64 = 4096;
+/// Max number of extra channels to send memory in parallel.
+pub const MAX_MULTIFD_CHANNELS: u16 = 16;
+
+/// Compression algorithm of memory pages in migration.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CompressionType {
+    None = 0,
+    Zstd = 1,
+    Lz4 = 2,
+}
+
+impl Default for CompressionType {
+    fn default() -> Self {
+        CompressionType::None
+    }
+}
+
+impl TryFrom<u8> for CompressionType {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(CompressionType::None),
+            1 => Ok(CompressionType::Zstd),
+            2 => Ok(CompressionType::Lz4),
+            _ => bail!("Unknown compression type {}", value),
+        }
+    }
+}
+
+impl FromStr for CompressionType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(CompressionType::None),
+            "zstd" => Ok(CompressionType::Zstd),
+            "lz4" => Ok(CompressionType::Lz4),
+            _ => bail!(
+                "Unknown compression type {}, it should be none, zstd or lz4",
+                s
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for CompressionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                CompressionType::None => "none",
+                CompressionType::Zstd => "zstd",
+                CompressionType::Lz4 => "lz4",
+            }
+        )
+    }
+}
+
+/// Capabilities of memory transfer, negotiated when migration is activated.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemTransferCaps {
+    /// Number of extra channels to send memory in parallel.
+    pub channels: u16,
+    /// Compression algorithm of memory pages.
+    pub compression: CompressionType,
+    /// Skip sending pages which are filled with zero.
+    pub zero_page: bool,
+    /// Switch to post-copy if pre-copy doesn't converge.
+    pub postcopy: bool,
+}
+
+impl MemTransferCaps {
+    /// Memory is sent as raw data in the main channel, which is compatible
+    /// with the destination not supporting memory transfer capabilities.
+    pub fn is_plain(&self) -> bool {
+        *self == MemTransferCaps::default()
+    }
+}
+
+/// Header of an encoded memory chunk. The payload following it is the
+/// non-zero pages of the chunk, compressed if it's negotiated. A header
+/// with zero `len` ends the memory transfer of a channel.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct MemChunkHeader {
+    /// Guest address of the chunk.
+    pub gpa: u64,
+    /// Size of the chunk, at most `MEM_CHUNK_SIZE`.
+    pub len: u64,
+    /// Size of the payload.
+    pub data_len: u64,
+    /// Bitmap of zero pages which are not included in the payload.
+    pub zero_bitmap: [u64; 4],
+}
+
+impl ByteCode for MemChunkHeader {}
+
+/// Memory transfer capabilities in wire format. It follows `MigrationHeader`
+/// in the request to activate migration.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct MemCapsHeader {
+    /// Number of extra channels to send memory in parallel.
+    channels: u16,
+    /// Compression algorithm of memory pages.
+    compression: u8,
+    /// Whether zero pages are skipped.
+    zero_page: u8,
+    /// Whether post-copy is enabled.
+    postcopy: u8,
+    reserved: u8,
+}
+
+impl ByteCode for MemCapsHeader {}
+
+impl MemCapsHeader {
+    pub fn new(caps: &MemTransferCaps) -> Self {
+        MemCapsHeader {
+            channels: caps.channels,
+            compression: caps.compression as u8,
+            zero_page: caps.zero_page as u8,
+            postcopy: caps.postcopy as u8,
+            reserved: 0,
+        }
+    }
+
+    /// Get the memory transfer capabilities negotiated by source.
+    pub fn caps(&self) -> Result<MemTransferCaps> {
+        if self.channels > MAX_MULTIFD_CHANNELS {
+            return Err(anyhow!(MigrationError::HeaderItemNotFit(
+                "Multifd channels".to_string()
+            )));
+        }
+        let compression = CompressionType::try_from(self.compression)
+            .map_err(|_| anyhow!(MigrationError::HeaderItemNotFit("Compression".to_string())))?;
+        if self.zero_page > 1 {
+            return Err(anyhow!(MigrationError::HeaderItemNotFit(
+                "Zero page".to_string()
+            )));
+        }
+        if self.postcopy > 1 {
+            return Err(anyhow!(MigrationError::HeaderItemNotFit(
+                "Postcopy".to_string()
+            )));
+        }
+
+        Ok(MemTransferCaps {
+            channels: self.channels,
+            compression,
+            zero_page: self.zero_page == 1,
+            postcopy: self.postcopy == 1,
+        })
+    }
+}
+
+/// Page request from destination VM in post-copy, sent through the return
+/// path when guest accesses a page which is not received yet.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct PageRequest {
+    /// Guest address of the page.
+    pub gpa: u64,
+}
+
+impl ByteCode for PageRequest {}
+
+impl PageRequest {
+    /// The request ending the return path, all memory has been received.
+    pub const END: PageRequest = PageRequest { gpa: u64::MAX };
+
+    pub fn is_end(&self) -> bool {
+        self.gpa == u64::MAX
+    }
+}
+
 /// Magic number for migration header. Those bytes represent "STRATOVIRT".
 const MAGIC_NUMBER: [u8; 16] = [
     0x53, 0x54, 0x52, 0x41, 0x54, 0x4f, 0x56, 0x49, 0x52, 0x54, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
@@ -301,6 +503,8 @@ pub const HEADER_LENGTH: usize = 4096;
 pub enum FileFormat {
     Device,
     MemoryFull,
+    /// Memory pages dirtied since the parent snapshot.
+    MemoryIncremental,
 }
 
 /// The endianness of byte order.
@@ -644,6 +848,23 @@ pub mod tests {
         assert_eq!(status, MigrationStatus::Setup);
     }
 
+    #[test]
+    fn test_postcopy_transfer() {
+        let status = MigrationStatus::Active;
+
+        // Active to PostcopyActive.
+        let status = status.transfer(MigrationStatus::PostcopyActive).unwrap();
+
+        // PostcopyActive can't be canceled or go back to Active.
+        assert!(status.transfer(MigrationStatus::Canceled).is_err());
+        assert!(status.transfer(MigrationStatus::Active).is_err());
+
+        // PostcopyActive to Completed or Failed.
+        assert!(status.transfer(MigrationStatus::Failed).is_ok());
+        let status = status.transfer(MigrationStatus::Completed).unwrap();
+        assert_eq!(status, MigrationStatus::Completed);
+    }
+
     #[test]
     fn test_abnormal_transfer_with_error() {
         let mut status = MigrationStatus::None;
@@ -1115,4 +1336,39 @@ pub mod tests {
         let header = MigrationHeader::default();
         assert_eq!(header.check_header().is_ok(), true);
     }
+    #[test]
+    fn test_mem_caps_header() {
+        assert!(MemCapsHeader::default().caps().unwrap().is_plain());
+
+        let caps = MemTransferCaps {
+            channels: 4,
+            compression: CompressionType::Zstd,
+            zero_page: true,
+            postcopy: true,
+        };
+        let header = MemCapsHeader::new(&caps);
+        let header = *MemCapsHeader::from_bytes(header.as_bytes()).unwrap();
+        assert_eq!(header.caps().unwrap(), caps);
+
+        let mut header = MemCapsHeader {
+            compression: 3,
+            ..Default::default()
+        };
+        assert!(header.caps().is_err());
+        header.compression = CompressionType::Lz4 as u8;
+        header.channels = MAX_MULTIFD_CHANNELS + 1;
+        assert!(header.caps().is_err());
+        header.channels = 0;
+        header.zero_page = 2;
+        assert!(header.caps().is_err());
+        header.zero_page = 0;
+        header.postcopy = 2;
+        assert!(header.caps().is_err());
+
+        assert_eq!(
+            CompressionType::from_str("lz4").unwrap(),
+            CompressionType::Lz4
+        );
+        assert!(CompressionType::from_str("gzip").is_err());
+    }
 }
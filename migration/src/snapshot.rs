@@ -12,13 +12,17 @@
 
 use crate::general::{translate_id, Lifecycle};
 use crate::manager::{MigrationManager, MIGRATION_MANAGER};
-use crate::protocol::{DeviceStateDesc, FileFormat, MigrationStatus, HEADER_LENGTH};
+use crate::migration::Migratable;
+use crate::protocol::{DeviceStateDesc, FileFormat, MemBlock, MigrationStatus, HEADER_LENGTH};
 use crate::MigrationError;
 use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::fs::{create_dir, File};
+use std::fs::{canonicalize, create_dir, rename, File};
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use util::checksum::checksum;
 use util::unix::host_page_size;
 
 pub const SERIAL_SNAPSHOT_ID: &str = "serial";
@@ -27,11 +31,196 @@ pub const GICV3_SNAPSHOT_ID: &str = "gicv3";
 pub const GICV3_ITS_SNAPSHOT_ID: &str = "gicv3_its";
 pub const PL011_SNAPSHOT_ID: &str = "pl011";
 pub const PL031_SNAPSHOT_ID: &str = "pl031";
+pub const RTC_SNAPSHOT_ID: &str = "rtc";
+pub const FWCFG_SNAPSHOT_ID: &str = "fwcfg";
+pub const PCI_HOST_SNAPSHOT_ID: &str = "pci_host";
 
 /// The suffix used for snapshot memory storage.
 const MEMORY_PATH_SUFFIX: &str = "memory";
 /// The suffix used for snapshot device state storage.
 const DEVICE_PATH_SUFFIX: &str = "state";
+/// The suffix used for snapshot manifest storage.
+const MANIFEST_PATH_SUFFIX: &str = "manifest";
+/// The suffix used for snapshot file being written.
+const TEMP_PATH_SUFFIX: &str = ".tmp";
+/// Max number of snapshots in a chain.
+const MAX_SNAPSHOT_CHAIN: usize = 256;
+
+/// Manifest to describe a snapshot and its parent.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotManifest {
+    /// Unique id of the snapshot.
+    pub id: String,
+    /// Id of the parent snapshot, it's None for full snapshot.
+    pub parent_id: Option<String>,
+    /// Absolute path of the parent snapshot dir.
+    pub parent_path: Option<String>,
+    /// Seconds since UNIX epoch when the snapshot is taken.
+    pub timestamp: u64,
+    /// Hash of machine config, all snapshots in a chain share the same memory layout.
+    pub config_hash: u64,
+    /// Checksum of device state file.
+    pub state_checksum: u8,
+    /// Checksum of memory file.
+    pub memory_checksum: u8,
+    /// Size of memory file.
+    pub memory_size: u64,
+}
+
+impl SnapshotManifest {
+    /// Load manifest from snapshot dir, return None for snapshot without manifest.
+    fn load(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(MANIFEST_PATH_SUFFIX);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file = File::open(&path)
+            .with_context(|| format!("Failed to open snapshot manifest {:?}", path))?;
+        let manifest = serde_json::from_reader(file)
+            .with_context(|| format!("Failed to parse snapshot manifest {:?}", path))?;
+
+        Ok(Some(manifest))
+    }
+
+    /// Save manifest to snapshot dir.
+    fn save(&self, dir: &Path) -> Result<()> {
+        let file = File::create(dir.join(MANIFEST_PATH_SUFFIX))
+            .with_context(|| "Failed to create snapshot manifest file")?;
+        serde_json::to_writer_pretty(file, self)
+            .with_context(|| "Failed to write snapshot manifest")?;
+
+        Ok(())
+    }
+
+    /// Check checksums of files in snapshot dir.
+    ///
+    /// Memory file of full snapshot is mapped to guest lazily, calculating its checksum
+    /// would read the whole file before the VM resumes, which costs as much as the eager
+    /// restore it replaces. Only its size is checked instead, as guest access to memory
+    /// mapped beyond the end of a truncated file raises SIGBUS.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - snapshot dir path.
+    /// * `check_state` - whether to check device state file, which is only needed
+    ///   for the last snapshot in a chain.
+    fn verify(&self, dir: &Path, check_state: bool) -> Result<()> {
+        let mut files = Vec::new();
+        if self.parent_id.is_some() {
+            files.push((MEMORY_PATH_SUFFIX, self.memory_checksum));
+        } else {
+            let path = dir.join(MEMORY_PATH_SUFFIX);
+            let size = path
+                .metadata()
+                .with_context(|| format!("Failed to open snapshot file {:?}", path))?
+                .len();
+            if size != self.memory_size {
+                bail!(
+                    "Size of snapshot file {:?} mismatch: expected 0x{:x}, actual 0x{:x}",
+                    path,
+                    self.memory_size,
+                    size
+                );
+            }
+        }
+        if check_state {
+            files.push((DEVICE_PATH_SUFFIX, self.state_checksum));
+        }
+        for (name, expected) in files {
+            let path = dir.join(name);
+            let actual = file_checksum(&path)?;
+            if actual != expected {
+                bail!(
+                    "Checksum of snapshot file {:?} mismatch: expected 0x{:x}, actual 0x{:x}",
+                    path,
+                    expected,
+                    actual
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A writer calculates checksum and length of all data written through it.
+struct ChecksumWriter<W: Write> {
+    inner: W,
+    sum: u8,
+    len: u64,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    fn new(inner: W) -> Self {
+        ChecksumWriter {
+            inner,
+            sum: 0,
+            len: 0,
+        }
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.sum = self.sum.wrapping_add(checksum(&buf[..len]));
+        self.len += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Calculate checksum of the whole file.
+fn file_checksum(path: &Path) -> Result<u8> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open snapshot file {:?}", path))?;
+    let mut buf = vec![0_u8; 1 << 20];
+    let mut sum = 0_u8;
+    loop {
+        let len = file
+            .read(&mut buf)
+            .with_context(|| format!("Failed to read snapshot file {:?}", path))?;
+        if len == 0 {
+            break;
+        }
+        sum = sum.wrapping_add(checksum(&buf[..len]));
+    }
+
+    Ok(sum)
+}
+
+/// Create a temporary file for snapshot file `name`, which is renamed to `name` after
+/// written. Guest memory of VMs restored from an old snapshot is mapped to its memory
+/// file, replacing instead of truncating the file keeps their mapping valid.
+fn create_snapshot_file(dir: &Path, name: &str) -> std::io::Result<File> {
+    File::create(dir.join(format!("{}{}", name, TEMP_PATH_SUFFIX)))
+}
+
+/// Replace snapshot file `name` with its temporary file.
+fn commit_snapshot_file(dir: &Path, name: &str) -> Result<()> {
+    rename(
+        dir.join(format!("{}{}", name, TEMP_PATH_SUFFIX)),
+        dir.join(name),
+    )
+    .with_context(|| format!("Failed to save snapshot file {}", name))
+}
+
+/// Open a snapshot file and check its header.
+fn open_snapshot_file(dir: &Path, name: &str, format: FileFormat) -> Result<(File, usize)> {
+    let path = dir.join(name);
+    let mut file =
+        File::open(&path).with_context(|| format!("Failed to open snapshot file {:?}", path))?;
+    let header = MigrationManager::restore_header(&mut file)?;
+    header.check_header()?;
+    if header.format != format {
+        bail!("Invalid snapshot file {:?}", path);
+    }
+
+    Ok((file, header.desc_len))
+}
 
 impl MigrationManager {
     /// Save snapshot for `VM`.
@@ -39,13 +228,22 @@ impl MigrationManager {
     /// # Notes
     ///
     /// Offers a interface for snapshot functions. This function will make a snapshot dir
-    /// for input path. It will create two file in snapshot dir - device state file `state`
-    /// and memory file `memory`.
+    /// for input path. It will create three file in snapshot dir - device state file `state`,
+    /// memory file `memory` and manifest file `manifest`.
+    ///
+    /// If `incremental` is set, the VM keeps logging dirty pages after snapshot, so the
+    /// snapshot can be used as parent of an incremental snapshot, whose memory file only
+    /// contains pages changed since the parent. Otherwise dirty logging is stopped.
     ///
     /// # Argument
     ///
     /// * `path` - snapshot dir path. If path dir not exists, will create it.
-    pub fn save_snapshot(path: &str) -> Result<()> {
+    /// * `parent` - parent snapshot dir path for incremental snapshot.
+    /// * `incremental` - whether to log dirty pages for the next incremental snapshot.
+    pub fn save_snapshot(path: &str, parent: Option<&str>, incremental: bool) -> Result<()> {
+        Self::check_migration_blockers()?;
+        Self::check_snapshot_device_data()?;
+
         // Set status to `Active`
         MigrationManager::set_status(MigrationStatus::Active)?;
 
@@ -55,29 +253,59 @@ impl MigrationManager {
                 bail!("Failed to create snapshot dir: {}", e);
             }
         }
+        let snapshot_path = PathBuf::from(path);
+        let parent = match parent {
+            Some(parent) => Some(Self::check_snapshot_parent(&snapshot_path, parent)?),
+            None => None,
+        };
 
         // Save device state
-        let mut vm_state_path = PathBuf::from(path);
-        vm_state_path.push(DEVICE_PATH_SUFFIX);
-        match File::create(vm_state_path) {
-            Ok(mut state_file) => {
-                Self::save_vmstate(Some(FileFormat::Device), &mut state_file)?;
-            }
+        let mut state_file = match create_snapshot_file(&snapshot_path, DEVICE_PATH_SUFFIX) {
+            Ok(state_file) => ChecksumWriter::new(state_file),
             Err(e) => {
                 bail!("Failed to create snapshot state file: {}", e);
             }
-        }
+        };
+        Self::save_vmstate(Some(FileFormat::Device), &mut state_file)?;
 
         // Save memory data
-        let mut vm_memory_path = PathBuf::from(path);
-        vm_memory_path.push(MEMORY_PATH_SUFFIX);
-        match File::create(vm_memory_path) {
-            Ok(mut memory_file) => {
-                Self::save_memory(Some(FileFormat::MemoryFull), &mut memory_file)?;
-            }
+        let mut memory_file = match create_snapshot_file(&snapshot_path, MEMORY_PATH_SUFFIX) {
+            Ok(memory_file) => ChecksumWriter::new(memory_file),
             Err(e) => {
                 bail!("Failed to create snapshot memory file: {}", e);
             }
+        };
+        if parent.is_some() {
+            Self::save_dirty_memory(&mut memory_file)?;
+        } else {
+            Self::save_memory(Some(FileFormat::MemoryFull), &mut memory_file)?;
+        }
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let (parent_path, parent_id) = match parent {
+            Some((path, manifest)) => (Some(path), Some(manifest.id)),
+            None => (None, None),
+        };
+        let manifest = SnapshotManifest {
+            id: format!("{:016x}", timestamp.as_nanos() as u64),
+            parent_id,
+            parent_path,
+            timestamp: timestamp.as_secs(),
+            config_hash: Self::config_hash()?,
+            state_checksum: state_file.sum,
+            memory_checksum: memory_file.sum,
+            memory_size: memory_file.len,
+        };
+        commit_snapshot_file(&snapshot_path, DEVICE_PATH_SUFFIX)?;
+        commit_snapshot_file(&snapshot_path, MEMORY_PATH_SUFFIX)?;
+        manifest.save(&snapshot_path)?;
+
+        if incremental {
+            // Log dirty pages from now on, for incremental snapshot based on this one.
+            Self::start_dirty_log().with_context(|| "Failed to start logging dirty page")?;
+            *MIGRATION_MANAGER.last_snapshot.lock().unwrap() = Some(manifest.id);
+        } else if Self::is_tracking_snapshot() {
+            Self::stop_dirty_log().with_context(|| "Failed to stop logging dirty page")?;
         }
 
         // Set status to `Completed`
@@ -92,6 +320,10 @@ impl MigrationManager {
     ///
     /// Offers a interface for restore snapshot functions. This function will make VM
     /// back to the state restored in snapshot file including both device and memory.
+    /// Guest memory is mapped to the memory file of full snapshot copy-on-write, so
+    /// the VM resumes without reading the whole file. For incremental snapshot, memory
+    /// is restored from the full snapshot at the root of the chain, and then the
+    /// changed pages of each snapshot in order.
     ///
     /// # Argument
     ///
@@ -100,33 +332,29 @@ impl MigrationManager {
         // Set status to `Active`
         MigrationManager::set_status(MigrationStatus::Active)?;
 
-        let mut snapshot_path = PathBuf::from(path);
+        let snapshot_path = PathBuf::from(path);
         if !snapshot_path.is_dir() {
             return Err(anyhow!(MigrationError::InvalidSnapshotPath));
         }
 
-        snapshot_path.push(MEMORY_PATH_SUFFIX);
-        let mut memory_file =
-            File::open(&snapshot_path).with_context(|| "Failed to open memory snapshot file")?;
-        let memory_header = Self::restore_header(&mut memory_file)?;
-        memory_header.check_header()?;
-        if memory_header.format != FileFormat::MemoryFull {
-            bail!("Invalid memory snapshot file");
-        }
-        snapshot_path.pop();
-        snapshot_path.push(DEVICE_PATH_SUFFIX);
-        let mut device_state_file = File::open(&snapshot_path)
-            .with_context(|| "Failed to open device state snapshot file")?;
-        let device_state_header = Self::restore_header(&mut device_state_file)?;
-        device_state_header.check_header()?;
-        if device_state_header.format != FileFormat::Device {
-            bail!("Invalid device state snapshot file");
-        }
+        let chain = Self::resolve_snapshot_chain(&snapshot_path)?;
+        let (mut memory_file, _) =
+            open_snapshot_file(&chain[0], MEMORY_PATH_SUFFIX, FileFormat::MemoryFull)
+                .with_context(|| "Failed to open memory snapshot file")?;
+        let (mut device_state_file, desc_len) =
+            open_snapshot_file(&snapshot_path, DEVICE_PATH_SUFFIX, FileFormat::Device)
+                .with_context(|| "Failed to open device state snapshot file")?;
 
         Self::restore_memory(&mut memory_file).with_context(|| "Failed to load snapshot memory")?;
-        let snapshot_desc_db =
-            Self::restore_desc_db(&mut device_state_file, device_state_header.desc_len)
-                .with_context(|| "Failed to load device descriptor db")?;
+        for dir in chain[1..].iter() {
+            let (mut memory_file, _) =
+                open_snapshot_file(dir, MEMORY_PATH_SUFFIX, FileFormat::MemoryIncremental)
+                    .with_context(|| "Failed to open incremental memory snapshot file")?;
+            Self::restore_dirty_memory(&mut memory_file)
+                .with_context(|| format!("Failed to load snapshot memory from {:?}", dir))?;
+        }
+        let snapshot_desc_db = Self::restore_desc_db(&mut device_state_file, desc_len)
+            .with_context(|| "Failed to load device descriptor db")?;
         Self::restore_vmstate(snapshot_desc_db, &mut device_state_file)
             .with_context(|| "Failed to load snapshot device state")?;
         Self::resume()?;
@@ -137,6 +365,89 @@ impl MigrationManager {
         Ok(())
     }
 
+    /// Check the parent of incremental snapshot, return its absolute path and manifest.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - snapshot dir path.
+    /// * `parent` - parent snapshot dir path.
+    fn check_snapshot_parent(path: &Path, parent: &str) -> Result<(String, SnapshotManifest)> {
+        let parent_path = canonicalize(parent)
+            .with_context(|| format!("Invalid parent snapshot path {}", parent))?;
+        if canonicalize(path)? == parent_path {
+            bail!("Incremental snapshot can't overwrite its parent {}", parent);
+        }
+        let manifest = SnapshotManifest::load(&parent_path)?
+            .with_context(|| format!("Parent snapshot {} has no manifest", parent))?;
+        if MIGRATION_MANAGER.last_snapshot.lock().unwrap().as_ref() != Some(&manifest.id) {
+            bail!(
+                "Parent snapshot {} is not the last incremental snapshot taken by this VM",
+                parent
+            );
+        }
+        if manifest.config_hash != Self::config_hash()? {
+            bail!("Machine config is changed since parent snapshot {}", parent);
+        }
+
+        Ok((parent_path.to_string_lossy().to_string(), manifest))
+    }
+
+    /// Resolve the chain of snapshot by following its parent, and check the
+    /// consistency of snapshots. Return the chain ordered from the full snapshot.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - snapshot dir path.
+    fn resolve_snapshot_chain(path: &Path) -> Result<Vec<PathBuf>> {
+        let mut chain = vec![path.to_path_buf()];
+        // Snapshot without manifest is a single full snapshot.
+        let mut manifest = match SnapshotManifest::load(path)? {
+            Some(manifest) => manifest,
+            None => return Ok(chain),
+        };
+        manifest.verify(path, true)?;
+
+        let mut ids = vec![manifest.id.clone()];
+        while let (Some(parent_id), Some(parent_path)) =
+            (manifest.parent_id.as_ref(), manifest.parent_path.as_ref())
+        {
+            if chain.len() >= MAX_SNAPSHOT_CHAIN || ids.contains(parent_id) {
+                bail!("Invalid snapshot chain of {:?}", path);
+            }
+            let dir = PathBuf::from(parent_path);
+            let parent = SnapshotManifest::load(&dir)?
+                .with_context(|| format!("Parent snapshot {:?} has no manifest", dir))?;
+            if &parent.id != parent_id {
+                bail!(
+                    "Parent snapshot {:?} id mismatch: expected {}, actual {}",
+                    dir,
+                    parent_id,
+                    parent.id
+                );
+            }
+            if parent.config_hash != manifest.config_hash {
+                bail!("Machine config of parent snapshot {:?} mismatch", dir);
+            }
+            parent.verify(&dir, false)?;
+
+            ids.push(parent.id.clone());
+            chain.push(dir);
+            manifest = parent;
+        }
+        chain.reverse();
+
+        Ok(chain)
+    }
+
+    /// Hash of machine config, which decides the memory layout of VM.
+    fn config_hash() -> Result<u64> {
+        let locked_vmm = MIGRATION_MANAGER.vmm.read().unwrap();
+        let machine_config =
+            serde_json::to_string(&locked_vmm.config.lock().unwrap().machine_config)?;
+
+        Ok(translate_id(&machine_config))
+    }
+
     /// Save memory state and data to `Write` trait object.
     ///
     /// # Arguments
@@ -151,6 +462,51 @@ impl MigrationManager {
         Ok(())
     }
 
+    /// Save memory pages dirtied since the last snapshot to `Write` trait object.
+    ///
+    /// # Arguments
+    ///
+    /// * `fd` - The `Write` trait object to save memory data.
+    fn save_dirty_memory(fd: &mut dyn Write) -> Result<()> {
+        Self::save_header(Some(FileFormat::MemoryIncremental), fd)?;
+
+        let blocks = Self::collect_dirty_memory()?;
+        let locked_vmm = MIGRATION_MANAGER.vmm.read().unwrap();
+        let memory = locked_vmm.memory.as_ref().unwrap();
+        for block in blocks {
+            fd.write_all(&block.gpa.to_le_bytes())?;
+            fd.write_all(&block.len.to_le_bytes())?;
+            memory.send_memory(fd, block)?;
+        }
+        // Memory block with zero length ends the file.
+        fd.write_all(&[0_u8; 16])?;
+
+        Ok(())
+    }
+
+    /// Load dirty memory pages from incremental snapshot memory file.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - incremental snapshot memory file.
+    fn restore_dirty_memory(file: &mut File) -> Result<()> {
+        let locked_vmm = MIGRATION_MANAGER.vmm.read().unwrap();
+        let memory = locked_vmm.memory.as_ref().unwrap();
+        let mut bytes = [0_u8; 8];
+        loop {
+            file.read_exact(&mut bytes)?;
+            let gpa = u64::from_le_bytes(bytes);
+            file.read_exact(&mut bytes)?;
+            let len = u64::from_le_bytes(bytes);
+            if len == 0 {
+                break;
+            }
+            memory.recv_memory(file, MemBlock { gpa, len })?;
+        }
+
+        Ok(())
+    }
+
     /// Load and restore memory from snapshot memory file.
     ///
     /// # Arguments
@@ -303,3 +659,98 @@ impl MigrationManager {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{remove_dir_all, write};
+
+    use super::*;
+
+    fn create_snapshot(
+        dir: &Path,
+        parent: Option<&SnapshotManifest>,
+        id: &str,
+    ) -> SnapshotManifest {
+        create_dir(dir).unwrap();
+        write(dir.join(MEMORY_PATH_SUFFIX), id.as_bytes()).unwrap();
+        write(dir.join(DEVICE_PATH_SUFFIX), [1_u8, 2, 3]).unwrap();
+        let manifest = SnapshotManifest {
+            id: id.to_string(),
+            parent_id: parent.map(|p| p.id.clone()),
+            parent_path: parent.map(|_| dir.with_extension("parent").to_string_lossy().to_string()),
+            timestamp: 0,
+            config_hash: 0x1234,
+            state_checksum: file_checksum(&dir.join(DEVICE_PATH_SUFFIX)).unwrap(),
+            memory_checksum: file_checksum(&dir.join(MEMORY_PATH_SUFFIX)).unwrap(),
+            memory_size: id.len() as u64,
+        };
+        manifest.save(dir).unwrap();
+        manifest
+    }
+
+    #[test]
+    fn test_snapshot_chain() {
+        let root = PathBuf::from("/tmp/test_snapshot_chain");
+        let _ = remove_dir_all(&root);
+        create_dir(&root).unwrap();
+
+        // Parent path of each snapshot is "<dir>.parent".
+        let base = root.join("snap1.parent");
+        let base_manifest = create_snapshot(&base, None, "base");
+        assert_eq!(
+            SnapshotManifest::load(&base).unwrap(),
+            Some(base_manifest.clone())
+        );
+        let top = root.join("snap1");
+        create_snapshot(&top, Some(&base_manifest), "top");
+        assert_eq!(
+            MigrationManager::resolve_snapshot_chain(&top).unwrap(),
+            vec![base.clone(), top.clone()]
+        );
+
+        // Memory file of full snapshot is not read, but incremental one is checked.
+        write(base.join(MEMORY_PATH_SUFFIX), b"bsae").unwrap();
+        assert!(MigrationManager::resolve_snapshot_chain(&top).is_ok());
+        // Truncated memory file of full snapshot is refused.
+        write(base.join(MEMORY_PATH_SUFFIX), b"bas").unwrap();
+        assert!(MigrationManager::resolve_snapshot_chain(&top).is_err());
+        assert!(MigrationManager::resolve_snapshot_chain(&base).is_err());
+        write(base.join(MEMORY_PATH_SUFFIX), b"base").unwrap();
+        write(top.join(MEMORY_PATH_SUFFIX), b"bad").unwrap();
+        assert!(MigrationManager::resolve_snapshot_chain(&top).is_err());
+        write(top.join(MEMORY_PATH_SUFFIX), b"top").unwrap();
+
+        // Snapshot file is replaced after written.
+        let mut file = create_snapshot_file(&top, DEVICE_PATH_SUFFIX).unwrap();
+        file.write_all(&[1, 2, 3]).unwrap();
+        assert!(top.join("state.tmp").exists());
+        commit_snapshot_file(&top, DEVICE_PATH_SUFFIX).unwrap();
+        assert!(!top.join("state.tmp").exists());
+        assert!(MigrationManager::resolve_snapshot_chain(&top).is_ok());
+
+        // Parent is replaced by another snapshot.
+        remove_dir_all(&base).unwrap();
+        create_snapshot(&base, None, "other");
+        assert!(MigrationManager::resolve_snapshot_chain(&top).is_err());
+
+        // Snapshot without manifest.
+        let legacy = root.join("legacy");
+        create_dir(&legacy).unwrap();
+        assert_eq!(
+            MigrationManager::resolve_snapshot_chain(&legacy).unwrap(),
+            vec![legacy.clone()]
+        );
+
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_checksum_writer() {
+        let mut writer = ChecksumWriter::new(Vec::new());
+        writer.write_all(&[0xff, 0x02]).unwrap();
+        writer.write_all(&[0x10]).unwrap();
+        assert_eq!(writer.sum, 0x11);
+        assert_eq!(writer.len, 3);
+        assert_eq!(writer.inner, vec![0xff, 0x02, 0x10]);
+    }
+}
@@ -0,0 +1,312 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::manager::MigrationLimit;
+
+const NANOSECONDS_PER_SECOND: u128 = 1_000_000_000;
+/// Burst of the token bucket, expressed as the time to drain it at full rate.
+const TOKEN_BUCKET_BURST_MS: u128 = 100;
+/// Max vCPU throttle percentage, vCPUs must keep some time to run.
+pub const CPU_THROTTLE_MAX: u8 = 99;
+/// Number of consecutive iterations with high dirty rate to raise the throttle.
+const DIRTY_RATE_HIGH_THRESHOLD: u8 = 2;
+
+/// Blocking token bucket to limit the bandwidth of migration stream.
+pub struct TokenBucket {
+    /// Bytes allowed to be sent per second, 0 means no limit.
+    rate: u64,
+    /// Bytes can be sent right now.
+    tokens: u64,
+    /// Last time the tokens were refilled.
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Create a token bucket with `rate` bytes per second, 0 means no limit.
+    pub fn new(rate: u64) -> Self {
+        TokenBucket {
+            rate,
+            tokens: 0,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Change the rate of token bucket, it takes effect from the next `consume`.
+    pub fn set_rate(&mut self, rate: u64) {
+        if self.rate != rate {
+            self.rate = rate;
+            self.tokens = self.tokens.min(self.capacity());
+        }
+    }
+
+    fn capacity(&self) -> u64 {
+        (self.rate as u128 * TOKEN_BUCKET_BURST_MS / 1000) as u64
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_nanos();
+        let new_tokens = elapsed * self.rate as u128 / NANOSECONDS_PER_SECOND;
+        self.tokens = (self.tokens as u128 + new_tokens).min(self.capacity() as u128) as u64;
+        self.last_refill = now;
+    }
+
+    /// Take `bytes` tokens from the bucket and return how long the caller has
+    /// to wait before sending them.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - Bytes going to be sent.
+    /// * `now` - Current time.
+    pub fn reserve(&mut self, bytes: u64, now: Instant) -> Duration {
+        if self.rate == 0 {
+            return Duration::ZERO;
+        }
+
+        self.refill(now);
+        if self.tokens >= bytes {
+            self.tokens -= bytes;
+            return Duration::ZERO;
+        }
+
+        let deficit = (bytes - self.tokens) as u128;
+        self.tokens = 0;
+        let wait =
+            Duration::from_nanos((deficit * NANOSECONDS_PER_SECOND / self.rate as u128) as u64);
+        // Tokens before the end of waiting are already spent.
+        self.last_refill = now + wait;
+        wait
+    }
+
+    /// Block the current thread until `bytes` can be sent.
+    pub fn consume(&mut self, bytes: u64) {
+        let wait = self.reserve(bytes, Instant::now());
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// Estimate the migration bandwidth and the guest dirty rate to decide when
+/// to stop VM and copy the remaining dirty memory.
+pub struct DirtyRateEstimator {
+    /// Bandwidth of migration stream in bytes per second, 0 means unknown.
+    bandwidth: u64,
+    /// Bytes dirtied by guest per second.
+    dirty_rate: u64,
+    /// Last time dirty log was synchronized.
+    last_sync: Instant,
+}
+
+impl DirtyRateEstimator {
+    /// Create an estimator, it should be called when dirty log starts.
+    pub fn new(now: Instant) -> Self {
+        DirtyRateEstimator {
+            bandwidth: 0,
+            dirty_rate: 0,
+            last_sync: now,
+        }
+    }
+
+    pub fn bandwidth(&self) -> u64 {
+        self.bandwidth
+    }
+
+    pub fn dirty_rate(&self) -> u64 {
+        self.dirty_rate
+    }
+
+    fn rate(bytes: u64, elapsed: Duration) -> u64 {
+        let nanos = elapsed.as_nanos().max(1);
+        (bytes as u128 * NANOSECONDS_PER_SECOND / nanos).min(u64::MAX as u128) as u64
+    }
+
+    /// Record `bytes` of memory sent in `elapsed` time.
+    pub fn record_transfer(&mut self, bytes: u64, elapsed: Duration) {
+        if bytes == 0 {
+            return;
+        }
+        let sample = Self::rate(bytes, elapsed);
+        self.bandwidth = if self.bandwidth == 0 {
+            sample
+        } else {
+            // Smooth the bandwidth as transfers of small dirty blocks are noisy.
+            self.bandwidth / 2 + sample / 2
+        };
+    }
+
+    /// Record `bytes` of dirty memory collected at `now`.
+    pub fn record_dirty(&mut self, bytes: u64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_sync);
+        self.dirty_rate = Self::rate(bytes, elapsed);
+        self.last_sync = now;
+    }
+
+    /// Expected downtime to send `bytes` of dirty memory with VM stopped.
+    pub fn expected_downtime(&self, bytes: u64) -> Duration {
+        if bytes == 0 {
+            return Duration::ZERO;
+        }
+        if self.bandwidth == 0 {
+            return Duration::MAX;
+        }
+        Duration::from_nanos(
+            (bytes as u128 * NANOSECONDS_PER_SECOND / self.bandwidth as u128).min(u64::MAX as u128)
+                as u64,
+        )
+    }
+
+    /// Whether the guest dirties memory faster than half of the bandwidth,
+    /// which makes the migration hard to converge.
+    pub fn is_dirty_rate_high(&self) -> bool {
+        self.dirty_rate > self.bandwidth / 2
+    }
+}
+
+/// Auto-converge policy of vCPU throttling.
+#[derive(Default)]
+pub struct CpuThrottle {
+    /// Current throttle percentage, 0 means vCPUs are not throttled.
+    percentage: u8,
+    /// Count of consecutive iterations with high dirty rate.
+    dirty_rate_high_count: u8,
+}
+
+impl CpuThrottle {
+    pub fn percentage(&self) -> u8 {
+        self.percentage
+    }
+
+    /// Update the throttle with result of one iteration. Return the new
+    /// throttle percentage if it's changed.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - Migration parameters.
+    /// * `dirty_rate_high` - Whether dirty rate is too high in this iteration.
+    pub fn update(&mut self, limit: &MigrationLimit, dirty_rate_high: bool) -> Option<u8> {
+        if !limit.auto_converge || !dirty_rate_high {
+            self.dirty_rate_high_count = 0;
+            return None;
+        }
+
+        self.dirty_rate_high_count += 1;
+        if self.dirty_rate_high_count < DIRTY_RATE_HIGH_THRESHOLD {
+            return None;
+        }
+        self.dirty_rate_high_count = 0;
+
+        let percentage = if self.percentage == 0 {
+            limit.cpu_throttle_initial
+        } else {
+            self.percentage.saturating_add(limit.cpu_throttle_increment)
+        }
+        .min(CPU_THROTTLE_MAX);
+        if percentage == self.percentage {
+            return None;
+        }
+        self.percentage = percentage;
+        Some(percentage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let now = Instant::now();
+
+        // No limit.
+        let mut bucket = TokenBucket::new(0);
+        assert_eq!(bucket.reserve(u64::MAX, now), Duration::ZERO);
+
+        // 1MiB per second, empty bucket needs to wait for all bytes.
+        let mut bucket = TokenBucket::new(1 << 20);
+        bucket.last_refill = now;
+        assert_eq!(bucket.reserve(1 << 19, now), Duration::from_millis(500));
+        // Tokens before the end of last waiting are already spent.
+        assert_eq!(
+            bucket.reserve(1 << 19, now + Duration::from_millis(500)),
+            Duration::from_millis(500)
+        );
+
+        // Tokens are refilled after idle, but not more than the burst.
+        let later = now + Duration::from_secs(10);
+        assert_eq!(bucket.reserve(1 << 10, later), Duration::ZERO);
+        assert_eq!(bucket.tokens, bucket.capacity() - (1 << 10));
+
+        // Lower the rate drops the extra tokens.
+        bucket.set_rate(1 << 10);
+        assert_eq!(bucket.tokens, bucket.capacity());
+    }
+
+    #[test]
+    fn test_dirty_rate_estimator() {
+        let now = Instant::now();
+        let mut estimator = DirtyRateEstimator::new(now);
+        assert_eq!(estimator.expected_downtime(0), Duration::ZERO);
+        assert_eq!(estimator.expected_downtime(1), Duration::MAX);
+
+        estimator.record_transfer(100 << 20, Duration::from_secs(1));
+        assert_eq!(estimator.bandwidth(), 100 << 20);
+        estimator.record_transfer(50 << 20, Duration::from_secs(1));
+        assert_eq!(estimator.bandwidth(), 75 << 20);
+        assert_eq!(
+            estimator.expected_downtime(75 << 19),
+            Duration::from_millis(500)
+        );
+
+        estimator.record_dirty(10 << 20, now + Duration::from_secs(2));
+        assert_eq!(estimator.dirty_rate(), 5 << 20);
+        assert!(!estimator.is_dirty_rate_high());
+        estimator.record_dirty(80 << 20, now + Duration::from_secs(3));
+        assert_eq!(estimator.dirty_rate(), 80 << 20);
+        assert!(estimator.is_dirty_rate_high());
+    }
+
+    #[test]
+    fn test_cpu_throttle() {
+        let mut limit = MigrationLimit::default();
+        let mut throttle = CpuThrottle::default();
+
+        // Auto-converge is disabled.
+        assert_eq!(throttle.update(&limit, true), None);
+        assert_eq!(throttle.update(&limit, true), None);
+
+        limit.auto_converge = true;
+        assert_eq!(throttle.update(&limit, true), None);
+        assert_eq!(
+            throttle.update(&limit, true),
+            Some(limit.cpu_throttle_initial)
+        );
+        // Low dirty rate resets the count.
+        assert_eq!(throttle.update(&limit, true), None);
+        assert_eq!(throttle.update(&limit, false), None);
+        assert_eq!(throttle.update(&limit, true), None);
+        assert_eq!(
+            throttle.update(&limit, true),
+            Some(limit.cpu_throttle_initial + limit.cpu_throttle_increment)
+        );
+
+        // The throttle percentage is capped.
+        limit.cpu_throttle_increment = 90;
+        throttle.update(&limit, true);
+        assert_eq!(throttle.update(&limit, true), Some(CPU_THROTTLE_MAX));
+        throttle.update(&limit, true);
+        assert_eq!(throttle.update(&limit, true), None);
+        assert_eq!(throttle.percentage(), CPU_THROTTLE_MAX);
+    }
+}
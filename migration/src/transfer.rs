@@ -0,0 +1,545 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::borrow::Cow;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::manager::{MigrationHook, MIGRATION_MANAGER};
+use crate::protocol::{
+    CompressionType, MemBlock, MemChunkHeader, MemTransferCaps, MEM_CHUNK_SIZE,
+    MEM_ENCODE_PAGE_SIZE,
+};
+use crate::throttle::{DirtyRateEstimator, TokenBucket};
+use util::byte_code::ByteCode;
+
+/// Compression level of zstd, prefer speed to ratio.
+const ZSTD_LEVEL: i32 = 1;
+/// Max payload size of a chunk, the compressed data may be a bit larger
+/// than the raw data.
+const MAX_CHUNK_PAYLOAD: u64 = MEM_CHUNK_SIZE * 2;
+
+/// Listener of destination VM to accept extra channels of migration.
+pub trait MigrationListener {
+    type Channel: Read + Write + Send + 'static;
+
+    /// Accept an extra channel from source VM.
+    fn accept_channel(&self) -> Result<Self::Channel>;
+}
+
+impl MigrationListener for TcpListener {
+    type Channel = TcpStream;
+
+    fn accept_channel(&self) -> Result<TcpStream> {
+        let (stream, _) = self
+            .accept()
+            .with_context(|| "Failed to accept migration channel")?;
+        Ok(stream)
+    }
+}
+
+impl MigrationListener for UnixListener {
+    type Channel = UnixStream;
+
+    fn accept_channel(&self) -> Result<UnixStream> {
+        let (stream, _) = self
+            .accept()
+            .with_context(|| "Failed to accept migration channel")?;
+        Ok(stream)
+    }
+}
+
+/// Context of sending memory in source VM.
+pub struct MemTransfer<T> {
+    /// Negotiated capabilities of memory transfer.
+    pub caps: MemTransferCaps,
+    /// Extra channels to send memory in parallel.
+    pub channels: Vec<T>,
+    /// The token bucket to limit bandwidth, shared by all channels.
+    pub bucket: Arc<Mutex<TokenBucket>>,
+    /// The estimator of bandwidth and dirty rate.
+    pub estimator: DirtyRateEstimator,
+}
+
+impl<T> MemTransfer<T> {
+    pub fn new(caps: MemTransferCaps, channels: Vec<T>, bucket: TokenBucket) -> Self {
+        MemTransfer {
+            caps,
+            channels,
+            bucket: Arc::new(Mutex::new(bucket)),
+            estimator: DirtyRateEstimator::new(Instant::now()),
+        }
+    }
+}
+
+/// Wait until `bytes` can be sent within the max bandwidth, which may be
+/// changed during migration.
+pub fn consume_bandwidth(bucket: &Mutex<TokenBucket>, bytes: u64) {
+    let rate = MIGRATION_MANAGER.limit.read().unwrap().max_bandwidth;
+    let mut locked_bucket = bucket.lock().unwrap();
+    locked_bucket.set_rate(rate);
+    // Other channels wait for the lock, so that they share the bandwidth.
+    locked_bucket.consume(bytes);
+}
+
+/// Run `f` with each channel in its own thread, and return the results in
+/// the order of channels. The channels are moved into the threads and put
+/// back when all threads finish.
+///
+/// # Arguments
+///
+/// * `channels` - The channels to transfer memory in parallel.
+/// * `f` - The transfer, called with the index of the channel and the channel.
+pub fn run_on_channels<C, R, F>(channels: &mut Vec<C>, f: F) -> Result<Vec<R>>
+where
+    C: Send + 'static,
+    R: Send + 'static,
+    F: Fn(usize, &mut C) -> Result<R> + Clone + Send + 'static,
+{
+    let handles: Vec<_> = channels
+        .drain(..)
+        .enumerate()
+        .map(|(idx, mut channel)| {
+            let f = f.clone();
+            thread::spawn(move || {
+                let result = f(idx, &mut channel);
+                (channel, result)
+            })
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(handles.len());
+    for handle in handles {
+        let (channel, result) = handle
+            .join()
+            .map_err(|_| anyhow!("Memory transfer thread panicked"))?;
+        channels.push(channel);
+        results.push(result);
+    }
+    results.into_iter().collect()
+}
+
+/// Split memory blocks into chunks of at most `MEM_CHUNK_SIZE`.
+pub fn split_blocks(blocks: &[MemBlock]) -> Vec<MemBlock> {
+    let mut chunks = Vec::new();
+    for block in blocks {
+        let end = block.gpa + block.len;
+        let mut gpa = block.gpa;
+        while gpa < end {
+            let len = MEM_CHUNK_SIZE.min(end - gpa);
+            chunks.push(MemBlock { gpa, len });
+            gpa += len;
+        }
+    }
+    chunks
+}
+
+fn is_zero_page(page: &[u8]) -> bool {
+    // Fold by small pieces so that it can be vectorized.
+    page.chunks(64)
+        .all(|piece| piece.iter().fold(0, |acc, byte| acc | byte) == 0)
+}
+
+/// Encode a memory chunk with zero page detection and compression.
+///
+/// # Arguments
+///
+/// * `caps` - Negotiated capabilities of memory transfer.
+/// * `gpa` - Guest address of the chunk.
+/// * `data` - Raw data of the chunk.
+pub fn encode_chunk(
+    caps: &MemTransferCaps,
+    gpa: u64,
+    data: &[u8],
+) -> Result<(MemChunkHeader, Vec<u8>)> {
+    if data.len() as u64 > MEM_CHUNK_SIZE {
+        bail!("Memory chunk size {} exceeds the limit", data.len());
+    }
+    let mut header = MemChunkHeader {
+        gpa,
+        len: data.len() as u64,
+        ..Default::default()
+    };
+
+    let pages = if caps.zero_page {
+        let mut pages = Vec::with_capacity(data.len());
+        for (idx, page) in data.chunks(MEM_ENCODE_PAGE_SIZE as usize).enumerate() {
+            if is_zero_page(page) {
+                header.zero_bitmap[idx / 64] |= 1 << (idx % 64);
+            } else {
+                pages.extend_from_slice(page);
+            }
+        }
+        Cow::Owned(pages)
+    } else {
+        Cow::Borrowed(data)
+    };
+
+    let payload = match caps.compression {
+        _ if pages.is_empty() => Vec::new(),
+        CompressionType::None => pages.into_owned(),
+        CompressionType::Zstd => zstd::bulk::compress(&pages, ZSTD_LEVEL)
+            .with_context(|| "Failed to compress memory with zstd")?,
+        CompressionType::Lz4 => lz4_flex::block::compress(&pages),
+    };
+    header.data_len = payload.len() as u64;
+
+    Ok((header, payload))
+}
+
+/// Decode a memory chunk to raw data.
+///
+/// # Arguments
+///
+/// * `caps` - Negotiated capabilities of memory transfer.
+/// * `header` - Header of the chunk.
+/// * `payload` - Payload following the header.
+pub fn decode_chunk(
+    caps: &MemTransferCaps,
+    header: &MemChunkHeader,
+    payload: &[u8],
+) -> Result<Vec<u8>> {
+    if header.len > MEM_CHUNK_SIZE {
+        bail!("Memory chunk size {} exceeds the limit", header.len);
+    }
+    let page_size = MEM_ENCODE_PAGE_SIZE as usize;
+    let len = header.len as usize;
+    let is_zero = |idx: usize| (header.zero_bitmap[idx / 64] >> (idx % 64)) & 1 == 1;
+    let pages_len: usize = (0..len)
+        .step_by(page_size)
+        .enumerate()
+        .filter(|(idx, _)| !is_zero(*idx))
+        .map(|(_, offset)| page_size.min(len - offset))
+        .sum();
+
+    let pages = match caps.compression {
+        _ if pages_len == 0 => Vec::new(),
+        CompressionType::None => payload.to_vec(),
+        CompressionType::Zstd => zstd::bulk::decompress(payload, pages_len)
+            .with_context(|| "Failed to decompress memory with zstd")?,
+        CompressionType::Lz4 => lz4_flex::block::decompress(payload, pages_len)
+            .with_context(|| "Failed to decompress memory with lz4")?,
+    };
+    if pages.len() != pages_len {
+        bail!(
+            "Invalid memory chunk at 0x{:x}, expect {} bytes, got {}",
+            header.gpa,
+            pages_len,
+            pages.len()
+        );
+    }
+
+    let mut data = vec![0_u8; len];
+    let mut offset = 0;
+    for (idx, page) in data.chunks_mut(page_size).enumerate() {
+        if !is_zero(idx) {
+            page.copy_from_slice(&pages[offset..offset + page.len()]);
+            offset += page.len();
+        }
+    }
+
+    Ok(data)
+}
+
+/// Encode a memory chunk and send it to a channel.
+///
+/// # Arguments
+///
+/// * `fd` - The channel to send memory.
+/// * `caps` - Negotiated capabilities of memory transfer.
+/// * `gpa` - Guest address of the chunk.
+/// * `data` - Raw data of the chunk.
+/// * `bucket` - The token bucket to limit bandwidth, None means no limit.
+pub fn write_chunk(
+    fd: &mut dyn Write,
+    caps: &MemTransferCaps,
+    gpa: u64,
+    data: &[u8],
+    bucket: Option<&Mutex<TokenBucket>>,
+) -> Result<()> {
+    let (header, payload) = encode_chunk(caps, gpa, data)?;
+    if let Some(bucket) = bucket {
+        let wire_bytes = (header.as_bytes().len() + payload.len()) as u64;
+        consume_bandwidth(bucket, wire_bytes);
+    }
+
+    fd.write_all(header.as_bytes())?;
+    fd.write_all(&payload)?;
+
+    Ok(())
+}
+
+/// Receive a memory chunk from a channel and decode it. Return None if it's
+/// the empty chunk ending the transfer.
+///
+/// # Arguments
+///
+/// * `fd` - The channel to receive memory.
+/// * `caps` - Negotiated capabilities of memory transfer.
+/// * `payload` - Buffer to receive the payload, reused between chunks.
+pub fn read_chunk(
+    fd: &mut dyn Read,
+    caps: &MemTransferCaps,
+    payload: &mut Vec<u8>,
+) -> Result<Option<(u64, Vec<u8>)>> {
+    let mut header = MemChunkHeader::default();
+    fd.read_exact(header.as_mut_bytes())?;
+    if header.len == 0 {
+        return Ok(None);
+    }
+    if header.data_len > MAX_CHUNK_PAYLOAD {
+        bail!(
+            "Memory chunk payload size {} exceeds the limit",
+            header.data_len
+        );
+    }
+
+    payload.resize(header.data_len as usize, 0);
+    fd.read_exact(payload)?;
+    let data = decode_chunk(caps, &header, payload)?;
+
+    Ok(Some((header.gpa, data)))
+}
+
+/// Send encoded memory chunks to a channel, and end it with an empty chunk.
+/// Return the raw size of memory sent.
+///
+/// # Arguments
+///
+/// * `fd` - The channel to send memory.
+/// * `memory` - The memory to read chunks from.
+/// * `chunks` - The memory chunks need to be sent.
+/// * `caps` - Negotiated capabilities of memory transfer.
+/// * `bucket` - The token bucket to limit bandwidth.
+pub fn send_chunks<'a>(
+    fd: &mut dyn Write,
+    memory: &Arc<dyn MigrationHook + Send + Sync>,
+    chunks: impl Iterator<Item = &'a MemBlock>,
+    caps: &MemTransferCaps,
+    bucket: &Mutex<TokenBucket>,
+) -> Result<u64> {
+    let mut sent_bytes = 0;
+    let mut data = Vec::with_capacity(MEM_CHUNK_SIZE as usize);
+    for chunk in chunks {
+        data.clear();
+        memory.send_memory(&mut data, chunk.clone())?;
+        write_chunk(fd, caps, chunk.gpa, &data, Some(bucket))?;
+        sent_bytes += chunk.len;
+    }
+    fd.write_all(MemChunkHeader::default().as_bytes())?;
+    fd.flush()?;
+
+    Ok(sent_bytes)
+}
+
+/// Receive encoded memory chunks from a channel until an empty chunk.
+///
+/// # Arguments
+///
+/// * `fd` - The channel to receive memory.
+/// * `memory` - The memory to write chunks to.
+/// * `caps` - Negotiated capabilities of memory transfer.
+pub fn recv_chunks(
+    fd: &mut dyn Read,
+    memory: &Arc<dyn MigrationHook + Send + Sync>,
+    caps: &MemTransferCaps,
+) -> Result<()> {
+    let mut payload = Vec::new();
+    while let Some((gpa, data)) = read_chunk(fd, caps, &mut payload)? {
+        memory.recv_memory(
+            &mut data.as_slice(),
+            MemBlock {
+                gpa,
+                len: data.len() as u64,
+            },
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::StateTransfer;
+
+    /// Guest memory starting from address 0.
+    struct TestMemory {
+        data: Mutex<Vec<u8>>,
+    }
+
+    impl StateTransfer for TestMemory {
+        fn get_state_vec(&self) -> Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+
+        fn get_device_alias(&self) -> u64 {
+            0
+        }
+    }
+
+    impl MigrationHook for TestMemory {
+        fn send_memory(&self, fd: &mut dyn Write, range: MemBlock) -> Result<()> {
+            let data = self.data.lock().unwrap();
+            fd.write_all(&data[range.gpa as usize..(range.gpa + range.len) as usize])?;
+            Ok(())
+        }
+
+        fn recv_memory(&self, fd: &mut dyn Read, range: MemBlock) -> Result<()> {
+            let mut data = self.data.lock().unwrap();
+            fd.read_exact(&mut data[range.gpa as usize..(range.gpa + range.len) as usize])?;
+            Ok(())
+        }
+    }
+
+    fn test_data(len: usize) -> Vec<u8> {
+        let mut data = vec![0_u8; len];
+        for (idx, page) in data.chunks_mut(MEM_ENCODE_PAGE_SIZE as usize).enumerate() {
+            // Every third page is zero.
+            if idx % 3 != 0 {
+                page.fill(idx as u8);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_split_blocks() {
+        let blocks = vec![
+            MemBlock {
+                gpa: 0,
+                len: MEM_CHUNK_SIZE * 2 + 4096,
+            },
+            MemBlock {
+                gpa: 0x1000_0000,
+                len: 4096,
+            },
+        ];
+        let chunks = split_blocks(&blocks);
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(chunks[1].gpa, MEM_CHUNK_SIZE);
+        assert_eq!(chunks[2].len, 4096);
+        assert_eq!(chunks[3].gpa, 0x1000_0000);
+    }
+
+    #[test]
+    fn test_run_on_channels() {
+        let mut channels = vec![10_u32, 20, 30];
+        let results = run_on_channels(&mut channels, |idx, channel: &mut u32| {
+            *channel += 1;
+            Ok(idx as u32 + *channel)
+        })
+        .unwrap();
+        assert_eq!(results, vec![11, 22, 33]);
+        assert_eq!(channels, vec![11, 21, 31]);
+
+        // The channels are put back even if the transfer fails.
+        let result = run_on_channels(&mut channels, |idx, _: &mut u32| {
+            if idx == 1 {
+                bail!("Channel {} failed", idx);
+            }
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert_eq!(channels.len(), 3);
+    }
+
+    #[test]
+    fn test_encode_decode_chunk() {
+        // The last page is partial.
+        let data = test_data(MEM_CHUNK_SIZE as usize - 100);
+        for compression in [
+            CompressionType::None,
+            CompressionType::Zstd,
+            CompressionType::Lz4,
+        ] {
+            for zero_page in [false, true] {
+                let caps = MemTransferCaps {
+                    compression,
+                    zero_page,
+                    ..Default::default()
+                };
+                let (header, payload) = encode_chunk(&caps, 0x1000, &data).unwrap();
+                assert_eq!(header.len, data.len() as u64);
+                assert_eq!(header.data_len, payload.len() as u64);
+                if compression != CompressionType::None || zero_page {
+                    assert!(payload.len() < data.len());
+                }
+                assert_eq!(decode_chunk(&caps, &header, &payload).unwrap(), data);
+
+                // Corrupted chunk is refused.
+                if !payload.is_empty() {
+                    assert!(decode_chunk(&caps, &header, &payload[1..]).is_err());
+                }
+            }
+        }
+
+        // Chunk with only zero pages has no payload.
+        let caps = MemTransferCaps {
+            compression: CompressionType::Zstd,
+            zero_page: true,
+            ..Default::default()
+        };
+        let zero = vec![0_u8; 8192];
+        let (header, payload) = encode_chunk(&caps, 0, &zero).unwrap();
+        assert!(payload.is_empty());
+        assert_eq!(header.zero_bitmap[0], 0b11);
+        assert_eq!(decode_chunk(&caps, &header, &payload).unwrap(), zero);
+    }
+
+    #[test]
+    fn test_send_recv_chunks() {
+        let len = MEM_CHUNK_SIZE as usize * 3;
+        let src: Arc<dyn MigrationHook + Send + Sync> = Arc::new(TestMemory {
+            data: Mutex::new(test_data(len)),
+        });
+        let dst: Arc<dyn MigrationHook + Send + Sync> = Arc::new(TestMemory {
+            data: Mutex::new(vec![0xff_u8; len]),
+        });
+        let caps = MemTransferCaps {
+            compression: CompressionType::Lz4,
+            zero_page: true,
+            ..Default::default()
+        };
+        let chunks = split_blocks(&[MemBlock {
+            gpa: 0,
+            len: len as u64,
+        }]);
+        let bucket = Mutex::new(TokenBucket::new(0));
+
+        let mut stream = Vec::new();
+        let sent = send_chunks(&mut stream, &src, chunks.iter(), &caps, &bucket).unwrap();
+        assert_eq!(sent, len as u64);
+        assert!(stream.len() < len);
+
+        recv_chunks(&mut stream.as_slice(), &dst, &caps).unwrap();
+        let range = MemBlock {
+            gpa: 0,
+            len: len as u64,
+        };
+        let mut src_data = Vec::new();
+        src.send_memory(&mut src_data, range.clone()).unwrap();
+        let mut dst_data = Vec::new();
+        dst.send_memory(&mut dst_data, range).unwrap();
+        assert_eq!(src_data, dst_data);
+
+        // Stream without end chunk is broken.
+        let broken = &stream[..stream.len() - std::mem::size_of::<MemChunkHeader>()];
+        assert!(recv_chunks(&mut &broken[..], &dst, &caps).is_err());
+    }
+}
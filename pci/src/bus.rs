@@ -251,7 +251,7 @@ mod tests {
     use crate::bus::PciBus;
     use crate::config::{PciConfig, PCI_CONFIG_SPACE_SIZE};
     use crate::root_port::RootPort;
-    use crate::PciHost;
+    use crate::{pci_intx_gsi, PciHost, PCI_INTX_GSI_BASE};
     use anyhow::Result;
 
     #[derive(Clone)]
@@ -379,6 +379,28 @@ mod tests {
         assert_eq!(dev.lock().unwrap().name(), "test2");
     }
 
+    #[test]
+    fn test_pci_intx_gsi() {
+        let pci_host = create_pci_host();
+        let locked_pci_host = pci_host.lock().unwrap();
+        let root_bus = Arc::downgrade(&locked_pci_host.root_bus);
+
+        let root_port = RootPort::new("pcie.1".to_string(), 8, 0, root_bus.clone(), false);
+        root_port.realize().unwrap();
+
+        // INTA# of slot 1 on the root bus.
+        assert_eq!(
+            pci_intx_gsi(&root_bus, 10, 0).unwrap(),
+            PCI_INTX_GSI_BASE + 1
+        );
+        // INTB# of slot 0 behind the root port in slot 1 is swizzled to INTC# of slot 0.
+        let bus = PciBus::find_bus_by_name(&locked_pci_host.root_bus, "pcie.1").unwrap();
+        assert_eq!(
+            pci_intx_gsi(&Arc::downgrade(&bus), 0, 1).unwrap(),
+            PCI_INTX_GSI_BASE + 2
+        );
+    }
+
     #[test]
     fn test_detach_device() {
         let pci_host = create_pci_host();
@@ -101,7 +101,8 @@ pub const PRIMARY_BUS_NUM: u8 = 0x18;
 pub const IO_LIMIT: u8 = 0x1d;
 pub const PREF_MEM_BASE_UPPER: u8 = 0x28;
 const CAP_LIST: u8 = 0x34;
-const INTERRUPT_LINE: u8 = 0x3c;
+pub const INTERRUPT_LINE: u8 = 0x3c;
+pub const INTERRUPT_PIN: u8 = 0x3d;
 pub const BRIDGE_CONTROL: u8 = 0x3e;
 
 const BRIDGE_CTL_PARITY_ENABLE: u16 = 0x0001;
@@ -139,6 +140,11 @@ pub const BAR_SPACE_UNMAPPED: u64 = 0xffff_ffff_ffff_ffff;
 pub const BAR_NUM_MAX_FOR_ENDPOINT: u8 = 6;
 /// The maximum Bar ID numbers of a Type 1 device
 pub const BAR_NUM_MAX_FOR_BRIDGE: u8 = 2;
+/// Bar ID of the expansion ROM of a Type 0 device, which follows the standard BARs.
+pub const ROM_BAR_ID: usize = BAR_NUM_MAX_FOR_ENDPOINT as usize;
+/// Enable bit of the expansion ROM base address register.
+pub const ROM_ADDRESS_ENABLE: u32 = 0x1;
+const ROM_BASE_ADDR_MASK: u32 = 0xffff_f800;
 /// mmio bar's minimum size shall be 4KB
 pub const MINMUM_BAR_SIZE_FOR_MMIO: usize = 0x1000;
 /// pio bar's minimum size shall be 4B
@@ -645,6 +651,9 @@ impl PciConfig {
     /// * `cap_id` - Capability ID.
     pub fn find_pci_cap(&self, cap_id: u8) -> usize {
         let mut offset = self.config[CAP_LIST as usize];
+        if offset == 0 {
+            return 0xff;
+        }
         let mut cache_offsets = HashSet::new();
         cache_offsets.insert(offset);
         loop {
@@ -668,6 +677,13 @@ impl PciConfig {
     /// * `id` - Index of the BAR.
     pub fn get_bar_address(&self, id: usize) -> u64 {
         let command = le_read_u16(&self.config, COMMAND as usize).unwrap();
+        if id == ROM_BAR_ID {
+            let rom_val = le_read_u32(&self.config, ROM_ADDRESS).unwrap();
+            if command & COMMAND_MEMORY_SPACE == 0 || rom_val & ROM_ADDRESS_ENABLE == 0 {
+                return BAR_SPACE_UNMAPPED;
+            }
+            return (rom_val & ROM_BASE_ADDR_MASK) as u64;
+        }
         let offset: usize = BAR_0 as usize + id * REG_SIZE;
         if self.config[offset] & BAR_IO_SPACE > 0 {
             if command & COMMAND_IO_SPACE == 0 {
@@ -712,6 +728,27 @@ impl PciConfig {
     ) -> Result<()> {
         self.validate_bar_id(id)?;
         self.validate_bar_size(region_type, size)?;
+        if id == ROM_BAR_ID {
+            if region_type != RegionType::Mem32Bit {
+                return Err(anyhow!(PciError::InvalidConf(
+                    "ROM bar type".to_string(),
+                    region_type.to_string(),
+                )));
+            }
+            let write_mask = (!(size - 1) as u32 & ROM_BASE_ADDR_MASK) | ROM_ADDRESS_ENABLE;
+            le_write_u32(&mut self.write_mask, ROM_ADDRESS, write_mask).unwrap();
+        } else {
+            self.set_bar_type(id, region_type, prefetchable, size);
+        }
+
+        self.bars[id].region_type = region_type;
+        self.bars[id].address = BAR_SPACE_UNMAPPED;
+        self.bars[id].size = size;
+        self.bars[id].region = Some(region);
+        Ok(())
+    }
+
+    fn set_bar_type(&mut self, id: usize, region_type: RegionType, prefetchable: bool, size: u64) {
         let offset: usize = BAR_0 as usize + id * REG_SIZE;
         match region_type {
             RegionType::Io => {
@@ -732,12 +769,6 @@ impl PciConfig {
         if prefetchable {
             self.config[offset] |= BAR_PREFETCH;
         }
-
-        self.bars[id].region_type = region_type;
-        self.bars[id].address = BAR_SPACE_UNMAPPED;
-        self.bars[id].size = size;
-        self.bars[id].region = Some(region);
-        Ok(())
     }
 
     /// Unregister region in PciConfig::bars.
@@ -1094,8 +1125,8 @@ impl PciConfig {
     }
 
     fn validate_bar_id(&self, id: usize) -> Result<()> {
-        if (self.config[HEADER_TYPE as usize] == HEADER_TYPE_ENDPOINT
-            && id >= BAR_NUM_MAX_FOR_ENDPOINT as usize)
+        if id >= self.bars.len()
+            || (self.config[HEADER_TYPE as usize] == HEADER_TYPE_ENDPOINT && id > ROM_BAR_ID)
             || (self.config[HEADER_TYPE as usize] == HEADER_TYPE_BRIDGE
                 && id >= BAR_NUM_MAX_FOR_BRIDGE as usize)
         {
@@ -1237,6 +1268,51 @@ mod tests {
         assert_eq!(pci_config.get_bar_address(2), MEM_BASE_ADDR_MASK);
     }
 
+    #[test]
+    fn test_get_rom_bar_address() {
+        let read_ops = move |_data: &mut [u8], _addr: GuestAddress, _offset: u64| -> bool { true };
+        let write_ops = move |_data: &[u8], _addr: GuestAddress, _offset: u64| -> bool { true };
+        let region_ops = RegionOps {
+            read: Arc::new(read_ops),
+            write: Arc::new(write_ops),
+        };
+        let region = Region::init_io_region(8192, region_ops);
+        let mut pci_config = PciConfig::new(PCI_CONFIG_SPACE_SIZE, ROM_BAR_ID as u8 + 1);
+        assert!(pci_config
+            .register_bar(
+                ROM_BAR_ID,
+                region.clone(),
+                RegionType::Mem64Bit,
+                false,
+                8192
+            )
+            .is_err());
+        assert!(pci_config
+            .register_bar(ROM_BAR_ID, region, RegionType::Mem32Bit, false, 8192)
+            .is_ok());
+        assert_eq!(
+            le_read_u32(&pci_config.write_mask, ROM_ADDRESS).unwrap(),
+            0xffff_e001
+        );
+
+        le_write_u16(
+            &mut pci_config.config,
+            COMMAND as usize,
+            COMMAND_MEMORY_SPACE,
+        )
+        .unwrap();
+        le_write_u32(&mut pci_config.config, ROM_ADDRESS, 0x1000_0000).unwrap();
+        // ROM address decoding is not enabled.
+        assert_eq!(pci_config.get_bar_address(ROM_BAR_ID), BAR_SPACE_UNMAPPED);
+        le_write_u32(
+            &mut pci_config.config,
+            ROM_ADDRESS,
+            0x1000_0000 | ROM_ADDRESS_ENABLE,
+        )
+        .unwrap();
+        assert_eq!(pci_config.get_bar_address(ROM_BAR_ID), 0x1000_0000);
+    }
+
     #[test]
     fn test_update_bar_mapping() {
         let read_ops = move |_data: &mut [u8], _addr: GuestAddress, _offset: u64| -> bool { true };
@@ -13,20 +13,28 @@
 use std::sync::{Arc, Mutex};
 
 use acpi::{
-    AmlAddressSpaceDecode, AmlAnd, AmlArg, AmlBuilder, AmlCacheable, AmlCreateDWordField,
+    AmlAddressSpaceDecode, AmlAnd, AmlArg, AmlBuilder, AmlCacheable, AmlCreateDWordField, AmlDWord,
     AmlDWordDesc, AmlDevice, AmlEisaId, AmlElse, AmlEqual, AmlISARanges, AmlIf, AmlInteger,
-    AmlLNot, AmlLocal, AmlMethod, AmlName, AmlNameDecl, AmlOr, AmlReadAndWrite, AmlResTemplate,
-    AmlReturn, AmlScopeBuilder, AmlStore, AmlToUuid, AmlWordDesc, AmlZero,
+    AmlLNot, AmlLocal, AmlMethod, AmlName, AmlNameDecl, AmlOr, AmlPackage, AmlReadAndWrite,
+    AmlResTemplate, AmlReturn, AmlScopeBuilder, AmlStore, AmlToUuid, AmlWordDesc, AmlZero,
 };
 #[cfg(target_arch = "x86_64")]
 use acpi::{AmlIoDecode, AmlIoResource};
 #[cfg(target_arch = "aarch64")]
-use acpi::{AmlOne, AmlQWordDesc};
+use acpi::{AmlOne, AmlQWordDesc, INTERRUPT_PPIS_COUNT, INTERRUPT_SGIS_COUNT};
 use address_space::{AddressSpace, GuestAddress, RegionOps};
+#[cfg(target_arch = "x86_64")]
+use anyhow::anyhow;
 use anyhow::Context;
+#[cfg(target_arch = "x86_64")]
+use migration::{DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager, StateTransfer};
+#[cfg(target_arch = "x86_64")]
+use migration_derive::{ByteCode, Desc};
 use sysbus::SysBusDevOps;
+#[cfg(target_arch = "x86_64")]
+use util::byte_code::ByteCode;
 
-use crate::{bus::PciBus, PciDevOps};
+use crate::{bus::PciBus, pci_swizzle_intx, PciDevOps, PCI_INTX_GSI_BASE, PCI_INTX_PIN_NUM};
 #[cfg(target_arch = "x86_64")]
 use crate::{le_read_u32, le_write_u32};
 
@@ -44,6 +52,17 @@ const CONFIG_DEVFN_MASK: u32 = 0xff;
 const ECAM_BUS_SHIFT: u32 = 20;
 const ECAM_DEVFN_SHIFT: u32 = 12;
 const ECAM_OFFSET_MASK: u64 = 0xfff;
+const PCI_SLOT_MAX: u32 = 32;
+
+/// Status of PCI host, the config spaces are saved by the devices themselves.
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Copy, Clone, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+pub struct PciHostState {
+    /// Value latched in CONFIG_ADDRESS port.
+    config_addr: u32,
+}
 
 #[derive(Clone)]
 pub struct PciHost {
@@ -361,6 +380,32 @@ fn build_osc_for_aml(pci_host_bridge: &mut AmlDevice) {
     pci_host_bridge.append_child(method);
 }
 
+#[cfg(target_arch = "x86_64")]
+impl StateTransfer for PciHost {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        let state = PciHostState {
+            config_addr: self.config_addr,
+        };
+
+        Ok(state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        let host_state = PciHostState::from_bytes(state)
+            .ok_or_else(|| anyhow!(migration::MigrationError::FromBytesError("PCI_HOST")))?;
+        self.config_addr = host_state.config_addr;
+
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&PciHostState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl MigrationHook for PciHost {}
+
 impl AmlBuilder for PciHost {
     fn aml_bytes(&self) -> Vec<u8> {
         let mut pci_host_bridge = AmlDevice::new("PCI0");
@@ -453,11 +498,34 @@ impl AmlBuilder for PciHost {
             pcie_mmio.1 as u32,
         ));
         pci_host_bridge.append_child(AmlNameDecl::new("_CRS", crs));
+        pci_host_bridge.append_child(AmlNameDecl::new("_PRT", build_prt_for_aml()));
 
         pci_host_bridge.aml_bytes()
     }
 }
 
+/// Build "\_SB.PCI0._PRT", which routes the INTx pins of all slots on the root bus.
+fn build_prt_for_aml() -> AmlPackage {
+    let mut prt = AmlPackage::new((PCI_SLOT_MAX * PCI_INTX_PIN_NUM) as u8);
+    for slot in 0..PCI_SLOT_MAX {
+        for pin in 0..PCI_INTX_PIN_NUM {
+            let gsi = PCI_INTX_GSI_BASE + pci_swizzle_intx((slot << 3) as u8, pin as u8) as u32;
+            // SPI start at interrupt number 32 on aarch64 platform.
+            #[cfg(target_arch = "aarch64")]
+            let gsi = gsi + INTERRUPT_PPIS_COUNT + INTERRUPT_SGIS_COUNT;
+
+            // Each entry is a package of address, pin, source and source index.
+            let mut entry = AmlPackage::new(4);
+            entry.append_child(AmlDWord((slot << 16) | 0xffff));
+            entry.append_child(AmlInteger(pin as u64));
+            entry.append_child(AmlZero);
+            entry.append_child(AmlInteger(gsi as u64));
+            prt.append_child(entry);
+        }
+    }
+    prt
+}
+
 #[cfg(test)]
 pub mod tests {
     use std::sync::Weak;
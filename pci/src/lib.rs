@@ -20,11 +20,15 @@ pub mod msix;
 mod bus;
 pub mod demo_device;
 mod host;
+mod pvpanic;
 mod root_port;
 
 pub use bus::PciBus;
 pub use host::PciHost;
+#[cfg(target_arch = "x86_64")]
+pub use host::PciHostState;
 pub use msix::init_msix;
+pub use pvpanic::PvPanicPci;
 pub use root_port::RootPort;
 use util::AsAny;
 
@@ -33,6 +37,7 @@ use std::{
     sync::{Arc, Mutex, Weak},
 };
 
+use anyhow::Context;
 pub use anyhow::{bail, Result};
 use byteorder::{ByteOrder, LittleEndian};
 
@@ -40,6 +45,12 @@ use crate::config::{HEADER_TYPE, HEADER_TYPE_MULTIFUNC, MAX_FUNC};
 
 const BDF_FUNC_SHIFT: u8 = 3;
 
+/// INTx pins of the slots on the root bus are routed to `PCI_INTX_GSI_BASE` to
+/// `PCI_INTX_GSI_BASE + PCI_INTX_PIN_NUM - 1`, which are reserved level-triggered GSIs.
+pub const PCI_INTX_GSI_BASE: u32 = 16;
+/// Number of INTx pins (INTA# to INTD#).
+pub const PCI_INTX_PIN_NUM: u32 = 4;
+
 /// Macros that write data in little endian.
 macro_rules! le_write {
     ($name: ident, $func: ident, $type: tt) => {
@@ -109,6 +120,50 @@ fn pci_func(devfn: u8) -> u8 {
     devfn & 0x07
 }
 
+/// Swizzle the INTx pin of a device to the pin of its upstream bridge or bus.
+///
+/// # Arguments
+///
+/// * `devfn` - Devfn number of the device.
+/// * `pin` - INTx pin of the device, 0 for INTA#.
+pub fn pci_swizzle_intx(devfn: u8, pin: u8) -> u8 {
+    ((pin as u32 + pci_slot(devfn) as u32) % PCI_INTX_PIN_NUM) as u8
+}
+
+/// Get the GSI which the INTx pin of the device is routed to, by swizzling the pin
+/// through all the bridges up to the root bus.
+///
+/// # Arguments
+///
+/// * `parent_bus` - Parent bus of the device.
+/// * `devfn` - Devfn number of the device.
+/// * `pin` - INTx pin of the device, 0 for INTA#.
+pub fn pci_intx_gsi(parent_bus: &Weak<Mutex<PciBus>>, devfn: u8, pin: u8) -> Result<u32> {
+    let mut bus = parent_bus
+        .upgrade()
+        .with_context(|| "Failed to get parent bus")?;
+    let mut devfn = devfn;
+    let mut pin = pin;
+    loop {
+        let bridge = match bus.lock().unwrap().parent_bridge.as_ref() {
+            Some(bridge) => bridge
+                .upgrade()
+                .with_context(|| "Failed to get parent bridge")?,
+            None => break,
+        };
+        pin = pci_swizzle_intx(devfn, pin);
+        let locked_bridge = bridge.lock().unwrap();
+        devfn = locked_bridge
+            .devfn()
+            .with_context(|| format!("Failed to get devfn of {}", locked_bridge.name()))?;
+        bus = locked_bridge
+            .parent_bus()
+            .and_then(|b| b.upgrade())
+            .with_context(|| format!("Failed to get parent bus of {}", locked_bridge.name()))?;
+    }
+    Ok(PCI_INTX_GSI_BASE + pci_swizzle_intx(devfn, pin) as u32)
+}
+
 pub fn pci_ext_cap_id(header: u32) -> u16 {
     (header & 0xffff) as u16
 }
@@ -180,6 +235,11 @@ pub trait PciDevOps: Send + AsAny {
         None
     }
 
+    /// Get the bus where the device resides.
+    fn parent_bus(&self) -> Option<Weak<Mutex<PciBus>>> {
+        None
+    }
+
     /// Get the path of the PCI bus where the device resides.
     fn get_parent_dev_path(&self, parent_bus: Arc<Mutex<PciBus>>) -> String {
         let locked_parent_bus = parent_bus.lock().unwrap();
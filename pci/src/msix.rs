@@ -512,7 +512,7 @@ impl StateTransfer for Msix {
         self.func_masked = msix_state.func_masked;
         self.enabled = msix_state.enabled;
         self.msix_cap_offset = msix_state.msix_cap_offset;
-        self.dev_id = Arc::new(AtomicU16::new(msix_state.dev_id));
+        self.dev_id.store(msix_state.dev_id, Ordering::Release);
 
         Ok(())
     }
@@ -0,0 +1,278 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{Arc, Mutex, Weak};
+
+use address_space::{GuestAddress, Region, RegionOps};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{error, info};
+use migration::{
+    DeviceStateDesc, FieldDesc, MigrationError, MigrationHook, MigrationManager, StateTransfer,
+};
+use migration_derive::{ByteCode, Desc};
+use util::byte_code::ByteCode;
+use vmm_sys_util::eventfd::EventFd;
+
+use crate::config::{
+    PciConfig, RegionType, DEVICE_ID, HEADER_TYPE, HEADER_TYPE_ENDPOINT, MINMUM_BAR_SIZE_FOR_MMIO,
+    PCI_CONFIG_SPACE_SIZE, PCI_VENDOR_ID_REDHAT, REVISION_ID, SUB_CLASS_CODE, VENDOR_ID,
+};
+use crate::{le_write_u16, PciBus, PciDevOps};
+
+const PCI_DEVICE_ID_REDHAT_PVPANIC: u16 = 0x0011;
+const PCI_CLASS_SYSTEM_OTHER: u16 = 0x0880;
+
+/// The guest kernel has panicked.
+pub const PVPANIC_PANICKED: u8 = 1 << 0;
+/// The guest kernel is going to boot the crash kernel.
+pub const PVPANIC_CRASH_LOADED: u8 = 1 << 1;
+
+/// Device state of pvpanic.
+#[repr(C)]
+#[derive(Copy, Clone, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+pub struct PvPanicState {
+    /// Length of config_space is PCI_CONFIG_SPACE_SIZE.
+    config_space: [u8; 256],
+    write_mask: [u8; 256],
+    write_clear_mask: [u8; 256],
+}
+
+/// Paravirtualized panic device, with which the guest kernel notifies the host of its panic.
+/// It has a one byte register in bar 0: reading it gets the events supported, writing it
+/// reports the events happened.
+pub struct PvPanicPci {
+    name: String,
+    devfn: u8,
+    config: PciConfig,
+    parent_bus: Weak<Mutex<PciBus>>,
+    /// Notified when the guest panicked.
+    panic_req: Arc<EventFd>,
+}
+
+impl PvPanicPci {
+    pub fn new(
+        name: String,
+        devfn: u8,
+        parent_bus: Weak<Mutex<PciBus>>,
+        panic_req: Arc<EventFd>,
+    ) -> Self {
+        PvPanicPci {
+            name,
+            devfn,
+            config: PciConfig::new(PCI_CONFIG_SPACE_SIZE, 1),
+            parent_bus,
+            panic_req,
+        }
+    }
+
+    fn register_bar(&mut self) -> Result<()> {
+        let region = Region::init_io_region(
+            MINMUM_BAR_SIZE_FOR_MMIO as u64,
+            pvpanic_region_ops(self.name.clone(), self.panic_req.clone()),
+        );
+        self.config.register_bar(
+            0,
+            region,
+            RegionType::Mem32Bit,
+            false,
+            MINMUM_BAR_SIZE_FOR_MMIO as u64,
+        )
+    }
+}
+
+fn pvpanic_region_ops(name: String, panic_req: Arc<EventFd>) -> RegionOps {
+    let read_ops = move |data: &mut [u8], _addr: GuestAddress, offset: u64| -> bool {
+        if offset == 0 && !data.is_empty() {
+            data[0] = PVPANIC_PANICKED | PVPANIC_CRASH_LOADED;
+        }
+        true
+    };
+
+    let write_ops = move |data: &[u8], _addr: GuestAddress, offset: u64| -> bool {
+        if offset != 0 || data.is_empty() {
+            return true;
+        }
+        if data[0] & PVPANIC_CRASH_LOADED != 0 {
+            info!("Guest of {} is loading the crash kernel", name);
+        }
+        if data[0] & PVPANIC_PANICKED != 0 {
+            info!("Guest of {} panicked", name);
+            if let Err(e) = panic_req.write(1) {
+                error!("Failed to write panic request of {}: {:?}", name, e);
+            }
+        }
+        true
+    };
+
+    RegionOps {
+        read: Arc::new(read_ops),
+        write: Arc::new(write_ops),
+    }
+}
+
+impl PciDevOps for PvPanicPci {
+    fn init_write_mask(&mut self) -> Result<()> {
+        self.config.init_common_write_mask()
+    }
+
+    fn init_write_clear_mask(&mut self) -> Result<()> {
+        self.config.init_common_write_clear_mask()
+    }
+
+    fn realize(mut self) -> Result<()> {
+        self.init_write_mask()?;
+        self.init_write_clear_mask()?;
+
+        let config = &mut self.config.config;
+        le_write_u16(config, VENDOR_ID as usize, PCI_VENDOR_ID_REDHAT)?;
+        le_write_u16(config, DEVICE_ID as usize, PCI_DEVICE_ID_REDHAT_PVPANIC)?;
+        config[REVISION_ID] = 1;
+        le_write_u16(config, SUB_CLASS_CODE as usize, PCI_CLASS_SYSTEM_OTHER)?;
+        config[HEADER_TYPE as usize] = HEADER_TYPE_ENDPOINT;
+        self.register_bar()?;
+
+        let devfn = self.devfn;
+        let name = self.name.clone();
+        let parent_bus = self.parent_bus.upgrade().unwrap();
+        let mut locked_parent_bus = parent_bus.lock().unwrap();
+        if let Some(dev) = locked_parent_bus.devices.get(&devfn) {
+            bail!(
+                "Devfn {:?} has been used by {:?}",
+                devfn,
+                dev.lock().unwrap().name()
+            );
+        }
+        let dev = Arc::new(Mutex::new(self));
+        locked_parent_bus.devices.insert(devfn, dev.clone());
+        MigrationManager::register_device_instance(PvPanicState::descriptor(), dev, &name);
+
+        Ok(())
+    }
+
+    fn unrealize(&mut self) -> Result<()> {
+        MigrationManager::unregister_device_instance(PvPanicState::descriptor(), &self.name);
+        Ok(())
+    }
+
+    fn read_config(&mut self, offset: usize, data: &mut [u8]) {
+        self.config.read(offset, data);
+    }
+
+    fn write_config(&mut self, offset: usize, data: &[u8]) {
+        let parent_bus = self.parent_bus.upgrade().unwrap();
+        let locked_parent_bus = parent_bus.lock().unwrap();
+
+        self.config.write(
+            offset,
+            data,
+            0,
+            #[cfg(target_arch = "x86_64")]
+            Some(&locked_parent_bus.io_region),
+            Some(&locked_parent_bus.mem_region),
+        );
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn reset(&mut self, _reset_child_device: bool) -> Result<()> {
+        self.config.reset_common_regs()
+    }
+
+    fn devfn(&self) -> Option<u8> {
+        Some(self.devfn)
+    }
+
+    fn parent_bus(&self) -> Option<Weak<Mutex<PciBus>>> {
+        Some(self.parent_bus.clone())
+    }
+}
+
+impl StateTransfer for PvPanicPci {
+    fn get_state_vec(&self) -> Result<Vec<u8>> {
+        let mut state = PvPanicState::default();
+        state.config_space.copy_from_slice(&self.config.config);
+        state.write_mask.copy_from_slice(&self.config.write_mask);
+        state
+            .write_clear_mask
+            .copy_from_slice(&self.config.write_clear_mask);
+
+        Ok(state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        let pvpanic_state = *PvPanicState::from_bytes(state)
+            .ok_or_else(|| anyhow!(MigrationError::FromBytesError("PVPANIC")))?;
+
+        self.config.config = pvpanic_state.config_space.to_vec();
+        self.config.write_mask = pvpanic_state.write_mask.to_vec();
+        self.config.write_clear_mask = pvpanic_state.write_clear_mask.to_vec();
+
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&PvPanicState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+impl MigrationHook for PvPanicPci {
+    fn resume(&mut self) -> migration::Result<()> {
+        let parent_bus = self.parent_bus.upgrade().unwrap();
+        let locked_parent_bus = parent_bus.lock().unwrap();
+        self.config
+            .update_bar_mapping(
+                #[cfg(target_arch = "x86_64")]
+                Some(&locked_parent_bus.io_region),
+                Some(&locked_parent_bus.mem_region),
+            )
+            .with_context(|| format!("Failed to update bar of pvpanic {}", self.name))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::tests::create_pci_host;
+
+    #[test]
+    fn test_pvpanic_panicked() {
+        let pci_host = create_pci_host();
+        let root_bus = Arc::downgrade(&pci_host.lock().unwrap().root_bus);
+        let panic_req = Arc::new(EventFd::new(libc::EFD_NONBLOCK).unwrap());
+        let pvpanic = PvPanicPci::new("pvpanic0".to_string(), 8, root_bus, panic_req.clone());
+        pvpanic.realize().unwrap();
+
+        let dev = pci_host.lock().unwrap().find_device(0, 8).unwrap();
+        let mut buf = [0_u8; 4];
+        dev.lock()
+            .unwrap()
+            .read_config(VENDOR_ID as usize, &mut buf);
+        assert_eq!(buf, [0x36, 0x1b, 0x11, 0x00]);
+
+        let ops = pvpanic_region_ops("pvpanic0".to_string(), panic_req.clone());
+        let mut data = [0_u8];
+        assert!((ops.read)(&mut data, GuestAddress(0), 0));
+        assert_eq!(data[0], PVPANIC_PANICKED | PVPANIC_CRASH_LOADED);
+
+        // Loading the crash kernel is not a panic request.
+        assert!((ops.write)(&[PVPANIC_CRASH_LOADED], GuestAddress(0), 0));
+        assert!(panic_req.read().is_err());
+
+        assert!((ops.write)(&[PVPANIC_PANICKED], GuestAddress(0), 0));
+        assert_eq!(panic_req.read().unwrap(), 1);
+    }
+}
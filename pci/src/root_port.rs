@@ -439,6 +439,14 @@ impl PciDevOps for RootPort {
         self.name.clone()
     }
 
+    fn devfn(&self) -> Option<u8> {
+        Some(self.devfn)
+    }
+
+    fn parent_bus(&self) -> Option<Weak<Mutex<PciBus>>> {
+        Some(self.parent_bus.clone())
+    }
+
     /// Only set slot status to on, and no other device reset actions are implemented.
     fn reset(&mut self, reset_child_device: bool) -> Result<()> {
         if reset_child_device {
@@ -612,7 +620,20 @@ impl StateTransfer for RootPort {
     }
 }
 
-impl MigrationHook for RootPort {}
+impl MigrationHook for RootPort {
+    fn resume(&mut self) -> migration::Result<()> {
+        self.config
+            .update_bar_mapping(
+                #[cfg(target_arch = "x86_64")]
+                Some(&self.io_region),
+                Some(&self.mem_region),
+            )
+            .with_context(|| format!("Failed to update bar of root port {}", self.name))?;
+        self.register_region();
+
+        Ok(())
+    }
+}
 
 #[cfg(test)]
 mod tests {
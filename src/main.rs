@@ -18,10 +18,11 @@ use anyhow::{bail, Context, Result};
 use log::{error, info};
 use machine::{LightMachine, MachineOps, StdMachine};
 use machine_manager::{
-    cmdline::{check_api_channel, create_args_parser, create_vmconfig},
+    cmdline::{check_api_channel, create_args_parser, create_vmconfig, ApiChannel},
     config::MachineType,
     config::VmConfig,
     event_loop::EventLoop,
+    machine::MachineExternalInterface,
     qmp::QmpChannel,
     signal_handler::{exit_with_code, register_kill_signal, VM_EXIT_GENE_ERR},
     socket::Socket,
@@ -31,6 +32,8 @@ use machine_manager::{
 use util::loop_context::EventNotifierHelper;
 use util::test_helper::{is_test_enabled, set_test_enabled};
 use util::{arg_parser, daemonize::daemonize, logger, set_termi_canon_mode};
+#[cfg(not(target_env = "musl"))]
+use vnc::vencrypt::{make_vencrypt_config, TlsCreds};
 
 use thiserror::Error;
 
@@ -175,8 +178,8 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
     EventLoop::object_init(&vm_config.iothreads)?;
     register_kill_signal();
 
-    let listeners = check_api_channel(cmd_args, vm_config)?;
-    let mut sockets = Vec::new();
+    let api_channels = check_api_channel(cmd_args, vm_config)?;
+    let sockets;
     let vm: Arc<Mutex<dyn MachineOps + Send + Sync>> = match vm_config.machine_config.mach_type {
         MachineType::MicroVm => {
             if is_test_enabled() {
@@ -188,9 +191,7 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
             MachineOps::realize(&vm, vm_config).with_context(|| "Failed to realize micro VM.")?;
             EventLoop::set_manager(vm.clone(), None);
 
-            for listener in listeners {
-                sockets.push(Socket::from_unix_listener(listener, Some(vm.clone())));
-            }
+            sockets = create_api_sockets(api_channels, vm_config, vm.clone())?;
             vm
         }
         MachineType::StandardVm => {
@@ -213,9 +214,7 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
                 .with_context(|| "Failed to add test socket to MainLoop")?;
             }
 
-            for listener in listeners {
-                sockets.push(Socket::from_unix_listener(listener, Some(vm.clone())));
-            }
+            sockets = create_api_sockets(api_channels, vm_config, vm.clone())?;
             vm
         }
         MachineType::None => {
@@ -227,9 +226,7 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
             ));
             EventLoop::set_manager(vm.clone(), None);
 
-            for listener in listeners {
-                sockets.push(Socket::from_unix_listener(listener, Some(vm.clone())));
-            }
+            sockets = create_api_sockets(api_channels, vm_config, vm.clone())?;
             vm
         }
     };
@@ -255,3 +252,46 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
     EventLoop::loop_run().with_context(|| "MainLoop exits unexpectedly: error occurs")?;
     Ok(())
 }
+
+fn create_api_sockets(
+    api_channels: Vec<ApiChannel>,
+    vm_config: &VmConfig,
+    performer: Arc<Mutex<dyn MachineExternalInterface>>,
+) -> Result<Vec<Socket>> {
+    let mut sockets = Vec::new();
+    for channel in api_channels {
+        let mut socket = Socket::new(channel.listener, Some(performer.clone()));
+        if let Some(id) = channel.tls_creds {
+            set_socket_tls(&mut socket, vm_config, &id)?;
+        }
+        sockets.push(socket);
+    }
+    Ok(sockets)
+}
+
+#[cfg(not(target_env = "musl"))]
+fn set_socket_tls(socket: &mut Socket, vm_config: &VmConfig, id: &str) -> Result<()> {
+    let tls_cred = vm_config
+        .object
+        .tls_object
+        .get(id)
+        .with_context(|| format!("No tls-creds object found: {}", id))?;
+    let tlscred = TlsCreds {
+        cred_type: tls_cred.cred_type.clone(),
+        dir: tls_cred.dir.clone(),
+        endpoint: tls_cred.endpoint.clone(),
+        verifypeer: tls_cred.verifypeer,
+    };
+    let tls_config = make_vencrypt_config(&tlscred)
+        .with_context(|| format!("Failed to load tls-creds {} for qmp socket", id))?;
+    socket.set_tls_config(tls_config);
+    Ok(())
+}
+
+#[cfg(target_env = "musl")]
+fn set_socket_tls(_socket: &mut Socket, _vm_config: &VmConfig, id: &str) -> Result<()> {
+    bail!(
+        "Tls-creds {} for qmp socket is not supported by musl build",
+        id
+    );
+}
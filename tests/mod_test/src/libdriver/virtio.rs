@@ -23,6 +23,8 @@ use util::offset_of;
 
 pub const VIRTIO_F_BAD_FEATURE: u64 = 0x40000000;
 pub const VIRTIO_F_VERSION_1: u64 = 32;
+/// The driver only supports split virtqueue, it never accepts packed virtqueue.
+pub const VIRTIO_F_RING_PACKED: u64 = 34;
 pub const VIRTIO_CONFIG_S_ACKNOWLEDGE: u8 = 1;
 pub const VIRTIO_CONFIG_S_DRIVER: u8 = 2;
 pub const VIRTIO_CONFIG_S_DRIVER_OK: u8 = 4;
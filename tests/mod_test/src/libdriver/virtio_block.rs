@@ -21,7 +21,8 @@ use super::virtio::TestVirtQueue;
 use super::virtio::VirtioDeviceOps;
 use super::virtio_pci_modern::TestVirtioPciDev;
 use crate::libdriver::virtio::{
-    TestVringDescEntry, VIRTIO_F_BAD_FEATURE, VIRTIO_RING_F_EVENT_IDX, VIRTIO_RING_F_INDIRECT_DESC,
+    TestVringDescEntry, VIRTIO_F_BAD_FEATURE, VIRTIO_F_RING_PACKED, VIRTIO_RING_F_EVENT_IDX,
+    VIRTIO_RING_F_INDIRECT_DESC,
 };
 use crate::libtest::{test_init, TestState};
 use crate::utils::{cleanup_img, create_img, TEST_IMAGE_SIZE};
@@ -346,7 +347,8 @@ pub fn virtio_blk_defalut_feature(blk: Rc<RefCell<TestVirtioPciDev>>) -> u64 {
     features &= !(VIRTIO_F_BAD_FEATURE
         | 1 << VIRTIO_RING_F_INDIRECT_DESC
         | 1 << VIRTIO_RING_F_EVENT_IDX
-        | 1 << VIRTIO_BLK_F_SCSI);
+        | 1 << VIRTIO_BLK_F_SCSI
+        | 1 << VIRTIO_F_RING_PACKED);
 
     features
 }
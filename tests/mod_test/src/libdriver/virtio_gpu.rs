@@ -16,7 +16,9 @@ use super::{
     pci::{PCIBarAddr, TestPciDev, PCI_VENDOR_ID},
     pci_bus::TestPciBus,
 };
-use crate::libdriver::virtio::{TestVirtQueue, TestVringDescEntry, VirtioDeviceOps};
+use crate::libdriver::virtio::{
+    TestVirtQueue, TestVringDescEntry, VirtioDeviceOps, VIRTIO_F_RING_PACKED,
+};
 use crate::libdriver::virtio_pci_modern::TestVirtioPciDev;
 use crate::libtest::{test_init, TestState};
 
@@ -420,7 +422,8 @@ impl TestVirtioGpu {
         self.device
             .borrow_mut()
             .setup_msix_configuration_vector(self.allocator.clone(), 0);
-        let features = self.device.borrow_mut().get_device_features();
+        let features =
+            self.device.borrow_mut().get_device_features() & !(1 << VIRTIO_F_RING_PACKED);
         self.device.borrow_mut().negotiate_features(features);
         self.device.borrow_mut().set_features_ok();
 
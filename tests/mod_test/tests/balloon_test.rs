@@ -12,7 +12,9 @@
 
 use mod_test::libdriver::machine::TestStdMachine;
 use mod_test::libdriver::malloc::GuestAllocator;
-use mod_test::libdriver::virtio::{TestVirtQueue, TestVringDescEntry, VirtioDeviceOps};
+use mod_test::libdriver::virtio::{
+    TestVirtQueue, TestVringDescEntry, VirtioDeviceOps, VIRTIO_F_RING_PACKED,
+};
 use mod_test::libdriver::virtio_pci_modern::{TestVirtioPciDev, VirtioPciCommonCfg};
 use mod_test::libtest::{test_init, TestState};
 use serde_json::json;
@@ -106,7 +108,7 @@ impl VirtioBalloonTest {
         let dev = Rc::new(RefCell::new(TestVirtioPciDev::new(machine.pci_bus.clone())));
         dev.borrow_mut().init(pci_slot, 0);
 
-        let features = dev.borrow_mut().get_device_features();
+        let features = dev.borrow_mut().get_device_features() & !(1 << VIRTIO_F_RING_PACKED);
         let inf_queue;
         let def_queue;
         let mut fpr_queue = None;
@@ -519,6 +521,7 @@ fn balloon_feature_002() {
     assert_eq!(
         features,
         1u64 << BALLOON_F_VERSION1_TEST
+            | 1u64 << VIRTIO_F_RING_PACKED
             | 1u64 << BALLOON_F_PRPORTING_TEST
             | 1u64 << BALLOON_F_DEFLATE_ON_OOM_TEST
     );
@@ -14,7 +14,7 @@ use virtio::block::VirtioBlkConfig;
 
 use mod_test::libdriver::malloc::GuestAllocator;
 use mod_test::libdriver::virtio::TestVringDescEntry;
-use mod_test::libdriver::virtio::{TestVirtQueue, VirtioDeviceOps};
+use mod_test::libdriver::virtio::{TestVirtQueue, VirtioDeviceOps, VIRTIO_F_RING_PACKED};
 use mod_test::libdriver::virtio_block::{
     add_blk_request, create_blk, set_up, tear_down, virtio_blk_defalut_feature, virtio_blk_read,
     virtio_blk_request, virtio_blk_write, TestVirtBlkReq, DEFAULT_IO_REQS, REQ_ADDR_LEN,
@@ -208,7 +208,7 @@ fn blk_features_negotiate() {
     blk.borrow_mut()
         .setup_msix_configuration_vector(alloc.clone(), 0);
 
-    let mut features = blk.borrow().get_device_features();
+    let mut features = blk.borrow().get_device_features() & !(1 << VIRTIO_F_RING_PACKED);
     features |= 1 << VIRTIO_BLK_F_SEG_MAX
         | 1 << VIRTIO_BLK_F_RO
         | 1 << VIRTIO_BLK_F_FLUSH
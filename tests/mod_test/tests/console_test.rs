@@ -21,7 +21,7 @@ use std::rc::Rc;
 use std::time;
 
 use mod_test::libdriver::malloc::GuestAllocator;
-use mod_test::libdriver::virtio::{TestVirtQueue, VirtioDeviceOps};
+use mod_test::libdriver::virtio::{TestVirtQueue, VirtioDeviceOps, VIRTIO_F_RING_PACKED};
 use mod_test::libdriver::virtio_console::{create_console, ChardevType};
 use mod_test::libdriver::virtio_pci_modern::TestVirtioPciDev;
 use mod_test::libtest::TestState;
@@ -40,7 +40,9 @@ fn console_setup(
     test_state: Rc<RefCell<TestState>>,
     alloc: Rc<RefCell<GuestAllocator>>,
 ) -> Vec<Rc<RefCell<TestVirtQueue>>> {
-    let features = console.borrow().get_device_features();
+    // Only port 0 is used, the control queues are not set up.
+    let features = console.borrow().get_device_features()
+        & !(1 << VIRTIO_CONSOLE_F_MULTIPORT | 1 << VIRTIO_F_RING_PACKED);
     let vqs = console
         .borrow_mut()
         .init_device(test_state, alloc, features, 2);
@@ -216,21 +218,16 @@ fn console_features_negotiate() {
     let pci_fn = 0x0;
     let (console, test_state, alloc) = create_console(chardev, pci_slot, pci_fn);
 
-    let mut features = console.borrow().get_device_features();
+    let mut features = console.borrow().get_device_features() & !(1 << VIRTIO_F_RING_PACKED);
     features |= 1 << VIRTIO_CONSOLE_F_SIZE;
     console.borrow_mut().negotiate_features(features);
     console.borrow_mut().set_features_ok();
     assert_eq!(features, console.borrow_mut().get_guest_features());
 
-    let unsupported_features = 1 << VIRTIO_CONSOLE_F_MULTIPORT;
-    features |= unsupported_features;
+    features |= 1 << VIRTIO_CONSOLE_F_MULTIPORT;
     console.borrow_mut().negotiate_features(features);
     console.borrow_mut().set_features_ok();
-    assert_ne!(features, console.borrow_mut().get_guest_features());
-    assert_eq!(
-        unsupported_features & console.borrow_mut().get_guest_features(),
-        0
-    );
+    assert_eq!(features, console.borrow_mut().get_guest_features());
 
     let unsupported_features = 1 << VIRTIO_CONSOLE_F_EMERG_WRITE;
     features |= unsupported_features;
@@ -20,7 +20,7 @@ use util::offset_of;
 use mod_test::libdriver::malloc::GuestAllocator;
 use mod_test::libdriver::virtio::{
     get_vring_size, TestVirtQueue, TestVringIndirectDesc, VirtioDeviceOps, VringDesc,
-    VIRTIO_CONFIG_S_NEEDS_RESET, VIRTIO_F_VERSION_1, VIRTIO_PCI_VRING_ALIGN,
+    VIRTIO_CONFIG_S_NEEDS_RESET, VIRTIO_F_RING_PACKED, VIRTIO_F_VERSION_1, VIRTIO_PCI_VRING_ALIGN,
     VIRTIO_RING_F_EVENT_IDX, VIRTIO_RING_F_INDIRECT_DESC, VRING_AVAIL_F_NO_INTERRUPT,
     VRING_DESC_F_INDIRECT, VRING_DESC_F_NEXT, VRING_DESC_F_WRITE, VRING_DESC_SIZE,
 };
@@ -1291,7 +1291,7 @@ fn virtio_init_device_repeat() {
     blk.borrow_mut().set_driver();
     blk.borrow_mut().set_driver();
 
-    let features = blk.borrow().get_device_features()
+    let features = blk.borrow().get_device_features() & !(1 << VIRTIO_F_RING_PACKED)
         | 1 << VIRTIO_RING_F_INDIRECT_DESC
         | 1 << VIRTIO_RING_F_EVENT_IDX;
     blk.borrow_mut().negotiate_features(features);
@@ -169,6 +169,8 @@ pub const USB_INTERFACE_CLASS_IN_REQUEST: u8 =
     USB_DIRECTION_DEVICE_TO_HOST | USB_TYPE_CLASS | USB_RECIPIENT_INTERFACE;
 pub const USB_INTERFACE_CLASS_OUT_REQUEST: u8 =
     USB_DIRECTION_HOST_TO_DEVICE | USB_TYPE_CLASS | USB_RECIPIENT_INTERFACE;
+pub const USB_ENDPOINT_OUT_REQUEST: u8 =
+    USB_DIRECTION_HOST_TO_DEVICE | USB_TYPE_STANDARD | USB_RECIPIENT_ENDPOINT;
 
 /// USB Standard Request Code. 9.4 Standard Device Requests
 pub const USB_REQUEST_GET_STATUS: u8 = 0;
@@ -225,3 +227,4 @@ pub const USB_CONFIGURATION_ATTR_REMOTE_WAKEUP: u8 = 1 << 5;
 
 // USB Class
 pub const USB_CLASS_HID: u8 = 3;
+pub const USB_CLASS_MASS_STORAGE: u8 = 8;
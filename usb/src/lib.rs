@@ -19,6 +19,7 @@ mod descriptor;
 pub mod hid;
 #[cfg(not(target_env = "musl"))]
 pub mod keyboard;
+pub mod storage;
 #[cfg(not(target_env = "musl"))]
 pub mod tablet;
 pub mod usb;
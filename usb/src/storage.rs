@@ -0,0 +1,1170 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::cmp::min;
+use std::collections::HashMap;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, Weak};
+
+use anyhow::{anyhow, Result};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use log::{debug, error, info};
+use once_cell::sync::Lazy;
+use vmm_sys_util::{epoll::EventSet, eventfd::EventFd};
+
+use crate::config::*;
+use crate::descriptor::{
+    UsbConfigDescriptor, UsbDescConfig, UsbDescDevice, UsbDescEndpoint, UsbDescIface,
+    UsbDescriptorOps, UsbDeviceDescriptor, UsbEndpointDescriptor, UsbInterfaceDescriptor,
+};
+use crate::usb::{
+    notify_controller, UsbDevice, UsbDeviceOps, UsbDeviceRequest, UsbEndpoint, UsbPacket,
+    UsbPacketStatus,
+};
+use crate::xhci::xhci_controller::XhciDevice;
+use block_backend::stats::{block_acct_start, BlockAcctCookie, BlockAcctType};
+use block_backend::{iov_slice, submit_rw_request};
+use machine_manager::config::{DriveFile, UsbStorageConfig};
+use machine_manager::event_loop::EventLoop;
+use util::aio::{Aio, AioCb, Iovec, OpCode, WriteZeroesState};
+use util::loop_context::{
+    read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+};
+use virtio::ScsiBus::{
+    scsi_command_emulate, scsi_operation_type, scsi_parse_cdb, ScsiSense, ALLOW_MEDIUM_REMOVAL,
+    EMULATE_SCSI_OPS, GOOD, REPORT_LUNS, REQUEST_SENSE, SCSI_CMD_BUF_SIZE,
+    SCSI_SENSE_INVALID_FIELD, SCSI_SENSE_INVALID_OPCODE, SCSI_SENSE_IO_ERROR,
+    SCSI_SENSE_LBA_OUT_OF_RANGE, SCSI_SENSE_LUN_NOT_SUPPORTED, SCSI_SENSE_NO_SENSE,
+    SCSI_SENSE_WRITE_PROTECTED, START_STOP, SYNCHRONIZE_CACHE, VERIFY_10, VERIFY_12, VERIFY_16,
+};
+use virtio::ScsiCntlr::{ScsiXferMode, VIRTIO_SCSI_CDB_DEFAULT_SIZE};
+use virtio::ScsiDisk::{ScsiDevice, SCSI_DISK_F_REMOVABLE, SCSI_TYPE_DISK, SECTOR_SHIFT};
+
+/// USB Storage device descriptor
+static DESC_DEVICE_STORAGE: Lazy<Arc<UsbDescDevice>> = Lazy::new(|| {
+    Arc::new(UsbDescDevice {
+        device_desc: UsbDeviceDescriptor {
+            bLength: USB_DT_DEVICE_SIZE,
+            bDescriptorType: USB_DT_DEVICE,
+            idVendor: 0x0627,
+            idProduct: 0x0002,
+            bcdDevice: 0,
+            iManufacturer: STR_MANUFACTURER_INDEX,
+            iProduct: STR_PRODUCT_STORAGE_INDEX,
+            iSerialNumber: STR_SERIAL_STORAGE_INDEX,
+            bcdUSB: 0x0200,
+            bDeviceClass: 0,
+            bDeviceSubClass: 0,
+            bDeviceProtocol: 0,
+            bMaxPacketSize0: 64,
+            bNumConfigurations: 1,
+        },
+        configs: vec![Arc::new(UsbDescConfig {
+            config_desc: UsbConfigDescriptor {
+                bLength: USB_DT_CONFIG_SIZE,
+                bDescriptorType: USB_DT_CONFIGURATION,
+                wTotalLength: 0,
+                bNumInterfaces: 1,
+                bConfigurationValue: 1,
+                iConfiguration: STR_CONFIG_STORAGE_INDEX,
+                bmAttributes: USB_CONFIGURATION_ATTR_ONE | USB_CONFIGURATION_ATTR_SELF_POWER,
+                bMaxPower: 50,
+            },
+            interfaces: vec![DESC_IFACE_STORAGE.clone()],
+        })],
+    })
+});
+
+/// USB Storage interface descriptor
+static DESC_IFACE_STORAGE: Lazy<Arc<UsbDescIface>> = Lazy::new(|| {
+    Arc::new(UsbDescIface {
+        interface_desc: UsbInterfaceDescriptor {
+            bLength: USB_DT_INTERFACE_SIZE,
+            bDescriptorType: USB_DT_INTERFACE,
+            bInterfaceNumber: 0,
+            bAlternateSetting: 0,
+            bNumEndpoints: 2,
+            bInterfaceClass: USB_CLASS_MASS_STORAGE,
+            bInterfaceSubClass: USB_SUBCLASS_SCSI,
+            bInterfaceProtocol: USB_IFACE_PROTOCOL_BOT,
+            iInterface: 0,
+        },
+        other_desc: vec![],
+        endpoints: vec![
+            Arc::new(UsbDescEndpoint {
+                endpoint_desc: UsbEndpointDescriptor {
+                    bLength: USB_DT_ENDPOINT_SIZE,
+                    bDescriptorType: USB_DT_ENDPOINT,
+                    bEndpointAddress: USB_DIRECTION_DEVICE_TO_HOST | STORAGE_EP_IN,
+                    bmAttributes: USB_ENDPOINT_ATTR_BULK,
+                    wMaxPacketSize: 512,
+                    bInterval: 0,
+                },
+                extra: None,
+            }),
+            Arc::new(UsbDescEndpoint {
+                endpoint_desc: UsbEndpointDescriptor {
+                    bLength: USB_DT_ENDPOINT_SIZE,
+                    bDescriptorType: USB_DT_ENDPOINT,
+                    bEndpointAddress: USB_DIRECTION_HOST_TO_DEVICE | STORAGE_EP_OUT,
+                    bmAttributes: USB_ENDPOINT_ATTR_BULK,
+                    wMaxPacketSize: 512,
+                    bInterval: 0,
+                },
+                extra: None,
+            }),
+        ],
+    })
+});
+
+/// String descriptor index
+const STR_MANUFACTURER_INDEX: u8 = 1;
+const STR_PRODUCT_STORAGE_INDEX: u8 = 2;
+const STR_CONFIG_STORAGE_INDEX: u8 = 3;
+const STR_SERIAL_STORAGE_INDEX: u8 = 4;
+
+/// String descriptor
+const DESC_STRINGS: [&str; 5] = [
+    "",
+    "StratoVirt",
+    "StratoVirt USB Storage",
+    "High speed config (usb 2.0)",
+    "1",
+];
+
+/// Interface subclass and protocol of the mass storage class.
+const USB_SUBCLASS_SCSI: u8 = 0x06;
+const USB_IFACE_PROTOCOL_BOT: u8 = 0x50;
+
+/// Bulk endpoints of the storage device.
+const STORAGE_EP_IN: u8 = 1;
+const STORAGE_EP_OUT: u8 = 2;
+
+/// Class specific requests of the Bulk-Only Transport.
+const GET_MAX_LUN: u8 = 0xfe;
+const MASS_STORAGE_RESET: u8 = 0xff;
+
+/// Feature selector of the endpoint halt.
+const USB_ENDPOINT_HALT: u16 = 0;
+
+/// Signature of the Command Block Wrapper, "USBC" in little endian.
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+/// Signature of the Command Status Wrapper, "USBS" in little endian.
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+const CBW_SIZE: usize = 31;
+const CSW_SIZE: usize = 13;
+/// Data phase is from device to host if set in the flags of CBW.
+const CBW_FLAG_IN: u8 = 1 << 7;
+
+/// Status of the Command Status Wrapper.
+const CSW_STATUS_PASSED: u8 = 0;
+const CSW_STATUS_FAILED: u8 = 1;
+
+/// Length of the fixed format sense data.
+const SCSI_FIXED_SENSE_LEN: usize = 18;
+
+/// Phase of the Bulk-Only Transport.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UsbMsdMode {
+    /// Waiting for the Command Block Wrapper from host.
+    Cbw,
+    /// Receiving data from host.
+    DataOut,
+    /// Sending data to host.
+    DataIn,
+    /// Sending the Command Status Wrapper to host.
+    Csw,
+}
+
+/// Command Block Wrapper.
+#[derive(Debug, Default, Clone, Copy)]
+struct UsbMsdCbw {
+    sig: u32,
+    tag: u32,
+    data_len: u32,
+    flags: u8,
+    lun: u8,
+    cmd_len: u8,
+    cmd: [u8; SCSI_CMD_BUF_SIZE],
+}
+
+impl UsbMsdCbw {
+    fn convert(&mut self, data: &[u8; CBW_SIZE]) {
+        self.sig = LittleEndian::read_u32(&data[0..4]);
+        self.tag = LittleEndian::read_u32(&data[4..8]);
+        self.data_len = LittleEndian::read_u32(&data[8..12]);
+        self.flags = data[12];
+        self.lun = data[13] & 0xf;
+        self.cmd_len = data[14] & 0x1f;
+        self.cmd.copy_from_slice(&data[15..]);
+    }
+}
+
+/// Command Status Wrapper.
+#[derive(Debug, Default, Clone, Copy)]
+struct UsbMsdCsw {
+    sig: u32,
+    tag: u32,
+    residue: u32,
+    status: u8,
+}
+
+impl UsbMsdCsw {
+    fn convert(&self, data: &mut [u8; CSW_SIZE]) {
+        LittleEndian::write_u32(&mut data[0..4], self.sig);
+        LittleEndian::write_u32(&mut data[4..8], self.tag);
+        LittleEndian::write_u32(&mut data[8..12], self.residue);
+        data[12] = self.status;
+    }
+}
+
+/// State of the Bulk-Only Transport.
+struct UsbStorageState {
+    mode: UsbMsdMode,
+    cbw: UsbMsdCbw,
+    csw: UsbMsdCsw,
+    /// Read or write the image file in the data phase, or flush it before CSW,
+    /// None for the emulated commands.
+    io: Option<OpCode>,
+    /// Id of the IO in flight, the packet waiting for it is NAKed.
+    io_id: Option<u64>,
+    /// Data of the emulated command to be sent to host.
+    data: Vec<u8>,
+    /// Offset of the image file for the next read/write.
+    offset: u64,
+    /// Bytes the device is going to transfer in the data phase.
+    data_left: u32,
+    /// Bytes the device has transferred in the data phase.
+    data_done: u32,
+    /// Bytes host is going to transfer in the data phase.
+    host_left: u32,
+    /// Sense of the last failed command, reported by REQUEST SENSE.
+    sense: Option<ScsiSense>,
+}
+
+impl UsbStorageState {
+    fn new() -> Self {
+        UsbStorageState {
+            mode: UsbMsdMode::Cbw,
+            cbw: UsbMsdCbw::default(),
+            csw: UsbMsdCsw::default(),
+            io: None,
+            io_id: None,
+            data: Vec::new(),
+            offset: 0,
+            data_left: 0,
+            data_done: 0,
+            host_left: 0,
+            sense: None,
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+/// IO of the image file, submitted by the IO handler.
+struct UsbStorageIoReq {
+    id: u64,
+    opcode: OpCode,
+    iovec: Vec<Iovec>,
+    offset: u64,
+    nbytes: u64,
+}
+
+/// IO shared by the usb storage and its IO handler.
+#[derive(Default)]
+struct UsbStorageIoQueue {
+    /// Request to be submitted.
+    req: Option<UsbStorageIoReq>,
+    /// Id and result of the last completed request, the result is negative if failed.
+    done: Option<(u64, i64)>,
+}
+
+/// Record the result of the IO, and let the controller retry the packet waiting for it.
+fn usb_storage_io_done(
+    io: &Arc<Mutex<UsbStorageIoQueue>>,
+    dev: &Weak<Mutex<UsbStorage>>,
+    id: u64,
+    ret: i64,
+) {
+    io.lock().unwrap().done = Some((id, ret));
+    if let Some(dev) = dev.upgrade() {
+        if let Err(e) = notify_controller(&(dev as Arc<Mutex<dyn UsbDeviceOps>>)) {
+            error!("Failed to notify controller of usb storage IO: {:?}", e);
+        }
+    }
+}
+
+#[derive(Clone)]
+struct UsbStorageIoCb {
+    id: u64,
+    io: Arc<Mutex<UsbStorageIoQueue>>,
+    dev: Weak<Mutex<UsbStorage>>,
+    /// Accounting of the IO.
+    acct: Option<BlockAcctCookie>,
+}
+
+fn usb_storage_io_complete(aiocb: &AioCb<UsbStorageIoCb>, ret: i64) -> Result<()> {
+    let complete_cb = &aiocb.iocompletecb;
+    if let Some(acct) = complete_cb.acct.as_ref() {
+        acct.done(ret < 0);
+    }
+    usb_storage_io_done(&complete_cb.io, &complete_cb.dev, complete_cb.id, ret);
+    Ok(())
+}
+
+/// Handler which submits the IO of usb storage in the main loop.
+struct UsbStorageIoHandler {
+    io: Arc<Mutex<UsbStorageIoQueue>>,
+    io_evt: Arc<EventFd>,
+    scsi_dev: Arc<Mutex<ScsiDevice>>,
+    dev: Weak<Mutex<UsbStorage>>,
+    aio: Box<Aio<UsbStorageIoCb>>,
+}
+
+impl UsbStorageIoHandler {
+    fn handle_io(&mut self) {
+        let req = match self.io.lock().unwrap().req.take() {
+            Some(req) => req,
+            None => return,
+        };
+        let id = req.id;
+        if let Err(e) = self.submit_io(req) {
+            error!("Failed to submit usb storage IO: {:?}", e);
+            usb_storage_io_done(&self.io, &self.dev, id, -libc::EIO as i64);
+        }
+    }
+
+    fn submit_io(&mut self, req: UsbStorageIoReq) -> Result<()> {
+        let locked_scsi_dev = self.scsi_dev.lock().unwrap();
+        let disk_image = locked_scsi_dev
+            .disk_image
+            .as_ref()
+            .ok_or_else(|| anyhow!("No image file for usb storage"))?;
+        let acct_type = match req.opcode {
+            OpCode::Preadv => BlockAcctType::Read,
+            OpCode::Pwritev => BlockAcctType::Write,
+            _ => BlockAcctType::Flush,
+        };
+        let aiocb = AioCb {
+            direct: locked_scsi_dev.config.direct,
+            req_align: locked_scsi_dev.req_align,
+            buf_align: locked_scsi_dev.buf_align,
+            discard: false,
+            write_zeroes: WriteZeroesState::Off,
+            file_fd: disk_image.as_raw_fd(),
+            opcode: req.opcode,
+            iovec: iov_slice(&req.iovec, 0, req.nbytes),
+            offset: req.offset as usize,
+            nbytes: req.nbytes,
+            user_data: 0,
+            iocompletecb: UsbStorageIoCb {
+                id: req.id,
+                io: self.io.clone(),
+                dev: self.dev.clone(),
+                acct: Some(block_acct_start(
+                    &locked_scsi_dev.stats,
+                    acct_type,
+                    req.nbytes,
+                    1,
+                )),
+            },
+            combine_req: None,
+        };
+        let backend = locked_scsi_dev.block_backend.clone();
+        drop(locked_scsi_dev);
+
+        if req.opcode == OpCode::Fdsync {
+            self.aio.submit_request(aiocb)?;
+        } else {
+            submit_rw_request(&mut self.aio, &backend, aiocb)?;
+        }
+        self.aio.flush_request()
+    }
+}
+
+fn build_event_notifier(fd: RawFd, handler: Rc<NotifierCallback>) -> EventNotifier {
+    EventNotifier::new(
+        NotifierOperation::AddShared,
+        fd,
+        None,
+        EventSet::IN,
+        vec![handler],
+    )
+}
+
+impl EventNotifierHelper for UsbStorageIoHandler {
+    fn internal_notifiers(handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let mut notifiers = Vec::new();
+        let locked_handler = handler.lock().unwrap();
+
+        // Register event notifier for the IO request.
+        let h_clone = handler.clone();
+        let h: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            h_clone.lock().unwrap().handle_io();
+            None
+        });
+        notifiers.push(build_event_notifier(locked_handler.io_evt.as_raw_fd(), h));
+
+        // Register event notifier for aio.
+        let h_clone = handler.clone();
+        let h: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            if let Err(e) = h_clone.lock().unwrap().aio.handle_complete() {
+                error!("Failed to handle aio of usb storage: {:?}", e);
+            }
+            None
+        });
+        notifiers.push(build_event_notifier(locked_handler.aio.fd.as_raw_fd(), h));
+
+        notifiers
+    }
+}
+
+/// USB storage device which implements the Bulk-Only Transport.
+pub struct UsbStorage {
+    id: String,
+    usb_device: UsbDevice,
+    state: UsbStorageState,
+    /// Configuration of the usb storage.
+    config: UsbStorageConfig,
+    /// The scsi disk which emulates the scsi commands.
+    scsi_dev: Arc<Mutex<ScsiDevice>>,
+    /// IO submitted to the IO handler.
+    io: Arc<Mutex<UsbStorageIoQueue>>,
+    /// Notify the IO handler to submit the IO.
+    io_evt: Arc<EventFd>,
+    /// Id of the next IO.
+    next_io_id: u64,
+    /// USB controller used to notify controller to transfer data.
+    ctrl: Option<Weak<Mutex<XhciDevice>>>,
+}
+
+impl UsbStorage {
+    pub fn new(
+        config: UsbStorageConfig,
+        drive_files: Arc<Mutex<HashMap<String, DriveFile>>>,
+    ) -> Result<Self> {
+        let scsi_dev = ScsiDevice::new(config.scsi_cfg.clone(), SCSI_TYPE_DISK, drive_files);
+        Ok(Self {
+            id: config.id.clone(),
+            usb_device: UsbDevice::new(),
+            state: UsbStorageState::new(),
+            config,
+            scsi_dev: Arc::new(Mutex::new(scsi_dev)),
+            io: Arc::new(Mutex::new(UsbStorageIoQueue::default())),
+            io_evt: Arc::new(EventFd::new(libc::EFD_NONBLOCK)?),
+            next_io_id: 0,
+            ctrl: None,
+        })
+    }
+
+    pub fn realize(mut self) -> Result<Arc<Mutex<Self>>> {
+        self.usb_device.reset_usb_endpoint();
+        self.usb_device.speed = USB_SPEED_HIGH;
+        let mut s: Vec<String> = DESC_STRINGS.iter().map(|&s| s.to_string()).collect();
+        if let Some(serial) = &self.config.scsi_cfg.serial {
+            s[STR_SERIAL_STORAGE_INDEX as usize] = serial.clone();
+        }
+        self.usb_device
+            .init_descriptor(DESC_DEVICE_STORAGE.clone(), s)?;
+
+        let mut locked_scsi_dev = self.scsi_dev.lock().unwrap();
+        locked_scsi_dev.realize()?;
+        locked_scsi_dev.state.features |= 1 << SCSI_DISK_F_REMOVABLE;
+        drop(locked_scsi_dev);
+
+        let aio = Aio::new(
+            Arc::new(usb_storage_io_complete),
+            self.config.scsi_cfg.aio_type,
+        )?;
+        let io = self.io.clone();
+        let io_evt = self.io_evt.clone();
+        let scsi_dev = self.scsi_dev.clone();
+        let storage = Arc::new(Mutex::new(self));
+        let handler = UsbStorageIoHandler {
+            io,
+            io_evt,
+            scsi_dev,
+            dev: Arc::downgrade(&storage),
+            aio: Box::new(aio),
+        };
+        EventLoop::update_event(
+            EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler))),
+            None,
+        )?;
+
+        Ok(storage)
+    }
+
+    fn handle_cbw(&mut self, packet: &mut UsbPacket) {
+        if packet_len(packet) != CBW_SIZE {
+            error!("Bad CBW size {}", packet_len(packet));
+            packet.status = UsbPacketStatus::Stall;
+            return;
+        }
+        let mut buf = [0_u8; CBW_SIZE];
+        packet.transfer_packet(&mut buf, CBW_SIZE);
+        let mut cbw = UsbMsdCbw::default();
+        cbw.convert(&buf);
+        if cbw.sig != CBW_SIGNATURE {
+            error!("Bad CBW signature {:x}", cbw.sig);
+            packet.status = UsbPacketStatus::Stall;
+            return;
+        }
+        debug!("USB storage CBW {:?}", cbw);
+
+        self.state.reset_command(cbw);
+        match self.setup_command() {
+            Ok(()) => self.state.csw.status = CSW_STATUS_PASSED,
+            Err(sense) => self.fail_command(sense),
+        }
+        self.state.mode = if cbw.data_len == 0 {
+            UsbMsdMode::Csw
+        } else if cbw.flags & CBW_FLAG_IN == CBW_FLAG_IN {
+            UsbMsdMode::DataIn
+        } else {
+            UsbMsdMode::DataOut
+        };
+    }
+
+    /// Prepare the data phase of the scsi command in CBW. Commands which don't
+    /// have data phase are completed here.
+    fn setup_command(&mut self) -> std::result::Result<(), ScsiSense> {
+        let last_sense = self.state.sense.take();
+        let cbw = self.state.cbw;
+        if cbw.lun != 0 {
+            return Err(SCSI_SENSE_LUN_NOT_SUPPORTED);
+        }
+        let mut cdb = [0_u8; VIRTIO_SCSI_CDB_DEFAULT_SIZE];
+        cdb[..SCSI_CMD_BUF_SIZE].copy_from_slice(&cbw.cmd);
+        let cmd = scsi_parse_cdb(cdb, self.scsi_dev.clone()).ok_or(SCSI_SENSE_INVALID_OPCODE)?;
+        let data_in = cbw.flags & CBW_FLAG_IN == CBW_FLAG_IN;
+
+        match cmd.command {
+            REQUEST_SENSE => {
+                let sense = last_sense.unwrap_or(SCSI_SENSE_NO_SENSE);
+                self.state.data = build_fixed_sense(&sense);
+                self.state.data.truncate(cmd.xfer as usize);
+            }
+            REPORT_LUNS => {
+                // Only lun 0 is reported: 8 bytes header followed by one 8 bytes lun.
+                let mut data = vec![0_u8; 16];
+                BigEndian::write_u32(&mut data[0..4], 8);
+                data.truncate(cmd.xfer as usize);
+                self.state.data = data;
+            }
+            ALLOW_MEDIUM_REMOVAL | START_STOP | VERIFY_10 | VERIFY_12 | VERIFY_16 => {}
+            // The image file is flushed before CSW is sent.
+            SYNCHRONIZE_CACHE => self.state.io = Some(OpCode::Fdsync),
+            op if scsi_operation_type(op) == EMULATE_SCSI_OPS => {
+                let (status, sense, data) = scsi_command_emulate(&cmd, &self.scsi_dev, 0, 0);
+                if status != GOOD {
+                    return Err(sense.unwrap_or(SCSI_SENSE_INVALID_FIELD));
+                }
+                self.state.data = data;
+            }
+            _ => {
+                let to_dev = matches!(cmd.mode, ScsiXferMode::ScsiXferToDev);
+                if to_dev == data_in && cmd.xfer != 0 {
+                    return Err(SCSI_SENSE_INVALID_FIELD);
+                }
+                let locked_scsi_dev = self.scsi_dev.lock().unwrap();
+                if to_dev && locked_scsi_dev.config.read_only {
+                    return Err(SCSI_SENSE_WRITE_PROTECTED);
+                }
+                let disk_size = locked_scsi_dev.disk_sectors << SECTOR_SHIFT;
+                let offset = cmd
+                    .lba
+                    .checked_mul(locked_scsi_dev.block_size as u64)
+                    .ok_or(SCSI_SENSE_LBA_OUT_OF_RANGE)?;
+                drop(locked_scsi_dev);
+                if offset
+                    .checked_add(cmd.xfer as u64)
+                    .filter(|&end| end <= disk_size)
+                    .is_none()
+                {
+                    return Err(SCSI_SENSE_LBA_OUT_OF_RANGE);
+                }
+                self.state.io = Some(if to_dev {
+                    OpCode::Pwritev
+                } else {
+                    OpCode::Preadv
+                });
+                self.state.offset = offset;
+                self.state.data_left = min(cmd.xfer, cbw.data_len);
+                return Ok(());
+            }
+        }
+
+        if !self.state.data.is_empty() && !data_in {
+            return Err(SCSI_SENSE_INVALID_FIELD);
+        }
+        self.state.data_left = min(self.state.data.len() as u32, cbw.data_len);
+        Ok(())
+    }
+
+    fn fail_command(&mut self, sense: ScsiSense) {
+        info!(
+            "USB storage command {:#x} failed, sense key {:#x} asc {:#x} ascq {:#x}",
+            self.state.cbw.cmd[0], sense.key, sense.asc, sense.ascq
+        );
+        self.state.csw.status = CSW_STATUS_FAILED;
+        self.state.sense = Some(sense);
+        self.state.io = None;
+        self.state.data.clear();
+        self.state.data_left = 0;
+    }
+
+    fn handle_data_in(&mut self, packet: &mut UsbPacket) {
+        let len = min(packet_len(packet) as u32, self.state.data_left);
+        if let Some(OpCode::Preadv) = self.state.io {
+            match self.do_io(packet, OpCode::Preadv, len) {
+                Some(Ok(())) => {}
+                Some(Err(e)) => {
+                    error!("Failed to read usb storage {}: {:?}", self.id, e);
+                    self.fail_command(SCSI_SENSE_IO_ERROR);
+                }
+                None => return,
+            }
+        } else {
+            let start = self.state.data_done as usize;
+            let end = start + len as usize;
+            packet.transfer_packet(&mut self.state.data[start..end], len as usize);
+        }
+        if self.state.csw.status != CSW_STATUS_PASSED {
+            packet.actual_length = 0;
+        } else {
+            self.state.data_done += len;
+            self.state.data_left -= len;
+            packet.actual_length = len;
+        }
+        self.state.host_left -= packet.actual_length;
+        // Short packet ends the data phase.
+        if (packet.actual_length as usize) < packet_len(packet) || self.state.host_left == 0 {
+            self.state.mode = UsbMsdMode::Csw;
+        }
+    }
+
+    fn handle_data_out(&mut self, packet: &mut UsbPacket) {
+        let len = min(packet_len(packet) as u32, self.state.data_left);
+        if let Some(OpCode::Pwritev) = self.state.io {
+            match self.do_io(packet, OpCode::Pwritev, len) {
+                Some(Ok(())) => {
+                    self.state.data_done += len;
+                    self.state.data_left -= len;
+                }
+                Some(Err(e)) => {
+                    error!("Failed to write usb storage {}: {:?}", self.id, e);
+                    self.fail_command(SCSI_SENSE_IO_ERROR);
+                }
+                None => return,
+            }
+        }
+        // Data which is not needed by the device is dropped.
+        let consumed = min(packet_len(packet) as u32, self.state.host_left);
+        self.state.host_left -= consumed;
+        packet.actual_length = consumed;
+        if self.state.host_left == 0 {
+            self.state.mode = UsbMsdMode::Csw;
+        }
+    }
+
+    fn handle_csw(&mut self, packet: &mut UsbPacket) {
+        if packet_len(packet) < CSW_SIZE {
+            error!("Bad CSW size {}", packet_len(packet));
+            packet.status = UsbPacketStatus::Stall;
+            return;
+        }
+        if let Some(OpCode::Fdsync) = self.state.io {
+            match self.do_io(packet, OpCode::Fdsync, 0) {
+                Some(Ok(())) => self.state.io = None,
+                Some(Err(e)) => {
+                    error!("Failed to flush usb storage {}: {:?}", self.id, e);
+                    self.fail_command(SCSI_SENSE_IO_ERROR);
+                }
+                None => return,
+            }
+        }
+        let csw = &mut self.state.csw;
+        csw.residue = self.state.cbw.data_len - self.state.data_done;
+        let mut buf = [0_u8; CSW_SIZE];
+        csw.convert(&mut buf);
+        debug!("USB storage CSW {:?}", csw);
+        packet.transfer_packet(&mut buf, CSW_SIZE);
+        self.state.mode = UsbMsdMode::Cbw;
+    }
+
+    /// Read/write `len` bytes of the image file to/from `packet`, or flush it. The IO
+    /// is submitted by the IO handler, and `packet` is NAKed until the IO completes,
+    /// then the controller retries it to get the result. Return None if it is NAKed.
+    fn do_io(&mut self, packet: &mut UsbPacket, opcode: OpCode, len: u32) -> Option<Result<()>> {
+        let mut io = self.io.lock().unwrap();
+        if let Some(id) = self.state.io_id {
+            match io.done {
+                Some((done_id, ret)) if done_id == id => {
+                    io.done = None;
+                    self.state.io_id = None;
+                    if ret < 0 {
+                        return Some(Err(anyhow!(
+                            "IO of {} bytes at {} failed: {}",
+                            len,
+                            self.state.offset,
+                            ret
+                        )));
+                    }
+                    self.state.offset += len as u64;
+                    return Some(Ok(()));
+                }
+                _ => {
+                    packet.status = UsbPacketStatus::Nak;
+                    return None;
+                }
+            }
+        }
+
+        let mut iovec = Vec::new();
+        if opcode != OpCode::Fdsync {
+            for iov in &packet.iovecs {
+                iovec.push(Iovec {
+                    iov_base: iov.iov_base,
+                    iov_len: iov.iov_len as u64,
+                });
+            }
+        }
+        let id = self.next_io_id;
+        self.next_io_id = self.next_io_id.wrapping_add(1);
+        io.req = Some(UsbStorageIoReq {
+            id,
+            opcode,
+            iovec,
+            offset: self.state.offset,
+            nbytes: len as u64,
+        });
+        drop(io);
+        if let Err(e) = self.io_evt.write(1) {
+            return Some(Err(anyhow!("Failed to notify usb storage IO: {:?}", e)));
+        }
+        self.state.io_id = Some(id);
+        packet.status = UsbPacketStatus::Nak;
+        None
+    }
+}
+
+impl UsbStorageState {
+    fn reset_command(&mut self, cbw: UsbMsdCbw) {
+        self.cbw = cbw;
+        self.csw = UsbMsdCsw {
+            sig: CSW_SIGNATURE,
+            tag: cbw.tag,
+            residue: 0,
+            status: CSW_STATUS_PASSED,
+        };
+        self.io = None;
+        self.io_id = None;
+        self.data.clear();
+        self.offset = 0;
+        self.data_left = 0;
+        self.data_done = 0;
+        self.host_left = cbw.data_len;
+    }
+}
+
+/// Total length of the buffers in `packet`.
+fn packet_len(packet: &UsbPacket) -> usize {
+    packet.iovecs.iter().map(|iov| iov.iov_len).sum()
+}
+
+/// Build the fixed format sense data.
+fn build_fixed_sense(sense: &ScsiSense) -> Vec<u8> {
+    let mut buf = vec![0_u8; SCSI_FIXED_SENSE_LEN];
+    // Response code: current errors(0x70).
+    buf[0] = 0x70;
+    buf[2] = sense.key;
+    // Additional sense length: sense len - 8.
+    buf[7] = (SCSI_FIXED_SENSE_LEN - 8) as u8;
+    buf[12] = sense.asc;
+    buf[13] = sense.ascq;
+    buf
+}
+
+impl UsbDeviceOps for UsbStorage {
+    fn reset(&mut self) {
+        info!("Storage device reset");
+        self.usb_device.remote_wakeup = 0;
+        self.usb_device.addr = 0;
+        self.state.reset();
+    }
+
+    fn handle_control(&mut self, packet: &mut UsbPacket, device_req: &UsbDeviceRequest) {
+        debug!("handle_control request {:?}", device_req);
+        match self
+            .usb_device
+            .handle_control_for_descriptor(packet, device_req)
+        {
+            Ok(handled) => {
+                if handled {
+                    debug!("Storage control handled by descriptor, return directly.");
+                    return;
+                }
+            }
+            Err(e) => {
+                error!("Storage descriptor error {}", e);
+                packet.status = UsbPacketStatus::Stall;
+                return;
+            }
+        }
+
+        match (device_req.request_type, device_req.request) {
+            (USB_ENDPOINT_OUT_REQUEST, USB_REQUEST_CLEAR_FEATURE)
+                if device_req.value == USB_ENDPOINT_HALT =>
+            {
+                // Host clears the halted bulk endpoint after a stall, nothing to do.
+            }
+            (USB_INTERFACE_CLASS_OUT_REQUEST, MASS_STORAGE_RESET) => {
+                info!("Storage Bulk-Only mass storage reset");
+                self.state.reset();
+            }
+            (USB_INTERFACE_CLASS_IN_REQUEST, GET_MAX_LUN) => {
+                // Only one lun is supported.
+                self.usb_device.data_buf[0] = 0;
+                packet.actual_length = 1;
+            }
+            _ => {
+                error!("Unhandled storage control request {:?}", device_req);
+                packet.status = UsbPacketStatus::Stall;
+            }
+        }
+    }
+
+    fn handle_data(&mut self, packet: &mut UsbPacket) {
+        let in_direction = packet.pid as u8 == USB_TOKEN_IN;
+        match (self.state.mode, in_direction) {
+            (UsbMsdMode::Cbw, false) => self.handle_cbw(packet),
+            (UsbMsdMode::DataOut, false) => self.handle_data_out(packet),
+            (UsbMsdMode::DataIn, true) => self.handle_data_in(packet),
+            (UsbMsdMode::Csw, true) => self.handle_csw(packet),
+            (mode, _) => {
+                error!(
+                    "Unexpected usb storage packet in {:?} phase, pid {:#x}",
+                    mode, packet.pid
+                );
+                packet.status = UsbPacketStatus::Stall;
+            }
+        }
+    }
+
+    fn device_id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn get_usb_device(&self) -> &UsbDevice {
+        &self.usb_device
+    }
+
+    fn get_mut_usb_device(&mut self) -> &mut UsbDevice {
+        &mut self.usb_device
+    }
+
+    fn set_controller(&mut self, ctrl: Weak<Mutex<XhciDevice>>) {
+        self.ctrl = Some(ctrl);
+    }
+
+    fn get_controller(&self) -> Option<Weak<Mutex<XhciDevice>>> {
+        self.ctrl.clone()
+    }
+
+    fn get_wakeup_endpoint(&self) -> &UsbEndpoint {
+        // Wake up the endpoint whose packet waits for the IO.
+        if self.state.mode == UsbMsdMode::DataOut {
+            self.usb_device.get_endpoint(false, STORAGE_EP_OUT)
+        } else {
+            self.usb_device.get_endpoint(true, STORAGE_EP_IN)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Once;
+
+    use super::*;
+    use crate::usb::Iovec as UsbIovec;
+    use machine_manager::config::{ScsiDevConfig, VmConfig};
+    use util::aio::AioEngine;
+    use virtio::ScsiBus::{INQUIRY, READ_10, TEST_UNIT_READY, WRITE_10};
+
+    fn build_storage(id: &str, path: &str) -> Arc<Mutex<UsbStorage>> {
+        static INIT_EVENT_LOOP: Once = Once::new();
+        INIT_EVENT_LOOP.call_once(|| EventLoop::object_init(&None).unwrap());
+
+        let mut drive_files = HashMap::new();
+        let mut scsi_cfg = ScsiDevConfig {
+            id: id.to_string(),
+            ..Default::default()
+        };
+        if !path.is_empty() {
+            VmConfig::add_drive_file(&mut drive_files, path, false, false).unwrap();
+            scsi_cfg.path_on_host = path.to_string();
+            scsi_cfg.direct = false;
+            scsi_cfg.aio_type = AioEngine::Off;
+        }
+        let config = UsbStorageConfig {
+            id: id.to_string(),
+            scsi_cfg,
+        };
+        UsbStorage::new(config, Arc::new(Mutex::new(drive_files)))
+            .unwrap()
+            .realize()
+            .unwrap()
+    }
+
+    /// Handle the packet, run the main loop to complete its IO if it is NAKed.
+    fn handle_packet_io(stg: &Arc<Mutex<UsbStorage>>, packet: &mut UsbPacket) {
+        loop {
+            stg.lock().unwrap().handle_packet(packet);
+            if packet.status != UsbPacketStatus::Nak {
+                return;
+            }
+            EventLoop::get_ctx(None).unwrap().run().unwrap();
+        }
+    }
+
+    fn build_packet(pid: u8, ep_number: u8, buf: &mut [u8]) -> UsbPacket {
+        let mut packet = UsbPacket::default();
+        packet.init(pid as u32, ep_number);
+        packet.iovecs = vec![UsbIovec::new(buf.as_mut_ptr() as u64, buf.len())];
+        packet
+    }
+
+    fn send_cbw(stg: &Arc<Mutex<UsbStorage>>, tag: u32, data_len: u32, flags: u8, cdb: &[u8]) {
+        let mut buf = [0_u8; CBW_SIZE];
+        LittleEndian::write_u32(&mut buf[0..4], CBW_SIGNATURE);
+        LittleEndian::write_u32(&mut buf[4..8], tag);
+        LittleEndian::write_u32(&mut buf[8..12], data_len);
+        buf[12] = flags;
+        buf[14] = cdb.len() as u8;
+        buf[15..(15 + cdb.len())].copy_from_slice(cdb);
+        let mut packet = build_packet(USB_TOKEN_OUT, STORAGE_EP_OUT, &mut buf);
+        stg.lock().unwrap().handle_packet(&mut packet);
+        assert_eq!(packet.status, UsbPacketStatus::Success);
+        assert_eq!(packet.actual_length as usize, CBW_SIZE);
+    }
+
+    fn recv_csw(stg: &Arc<Mutex<UsbStorage>>) -> UsbMsdCsw {
+        let mut buf = [0_u8; CSW_SIZE];
+        let mut packet = build_packet(USB_TOKEN_IN, STORAGE_EP_IN, &mut buf);
+        stg.lock().unwrap().handle_packet(&mut packet);
+        assert_eq!(packet.status, UsbPacketStatus::Success);
+        assert_eq!(packet.actual_length as usize, CSW_SIZE);
+        assert_eq!(LittleEndian::read_u32(&buf[0..4]), CSW_SIGNATURE);
+        UsbMsdCsw {
+            sig: CSW_SIGNATURE,
+            tag: LittleEndian::read_u32(&buf[4..8]),
+            residue: LittleEndian::read_u32(&buf[8..12]),
+            status: buf[12],
+        }
+    }
+
+    #[test]
+    fn test_usb_storage_cbw_csw_convert() {
+        let mut buf = [0_u8; CBW_SIZE];
+        LittleEndian::write_u32(&mut buf[0..4], CBW_SIGNATURE);
+        LittleEndian::write_u32(&mut buf[4..8], 0x1234);
+        LittleEndian::write_u32(&mut buf[8..12], 512);
+        buf[12] = CBW_FLAG_IN;
+        buf[13] = 0xf1;
+        buf[14] = 10;
+        buf[15] = 0x28;
+        let mut cbw = UsbMsdCbw::default();
+        cbw.convert(&buf);
+        assert_eq!(cbw.sig, CBW_SIGNATURE);
+        assert_eq!(cbw.tag, 0x1234);
+        assert_eq!(cbw.data_len, 512);
+        assert_eq!(cbw.flags, CBW_FLAG_IN);
+        assert_eq!(cbw.lun, 1);
+        assert_eq!(cbw.cmd_len, 10);
+        assert_eq!(cbw.cmd[0], 0x28);
+
+        let csw = UsbMsdCsw {
+            sig: CSW_SIGNATURE,
+            tag: 0x1234,
+            residue: 16,
+            status: CSW_STATUS_FAILED,
+        };
+        let mut buf = [0_u8; CSW_SIZE];
+        csw.convert(&mut buf);
+        assert_eq!(
+            buf,
+            [
+                0x55,
+                0x53,
+                0x42,
+                0x53,
+                0x34,
+                0x12,
+                0,
+                0,
+                16,
+                0,
+                0,
+                0,
+                CSW_STATUS_FAILED
+            ]
+        );
+    }
+
+    #[test]
+    fn test_usb_storage_bulk_only_transport() {
+        let stg = build_storage("stg0", "");
+
+        // INQUIRY returns the standard inquiry data with removable bit.
+        send_cbw(&stg, 1, 36, CBW_FLAG_IN, &[INQUIRY, 0, 0, 0, 36, 0]);
+        let mut data = [0_u8; 36];
+        let mut packet = build_packet(USB_TOKEN_IN, STORAGE_EP_IN, &mut data);
+        stg.lock().unwrap().handle_packet(&mut packet);
+        assert_eq!(packet.status, UsbPacketStatus::Success);
+        assert_eq!(packet.actual_length, 36);
+        assert_eq!(data[0], SCSI_TYPE_DISK as u8);
+        assert_eq!(data[1], 0x80);
+        let csw = recv_csw(&stg);
+        assert_eq!(csw.tag, 1);
+        assert_eq!(csw.status, CSW_STATUS_PASSED);
+        assert_eq!(csw.residue, 0);
+
+        // TEST UNIT READY fails as there is no image file.
+        send_cbw(&stg, 2, 0, CBW_FLAG_IN, &[TEST_UNIT_READY, 0, 0, 0, 0, 0]);
+        let csw = recv_csw(&stg);
+        assert_eq!(csw.tag, 2);
+        assert_eq!(csw.status, CSW_STATUS_FAILED);
+
+        // REQUEST SENSE reports the sense of the failed command, the host expects
+        // more data than the device has, so the data phase ends with a short packet.
+        send_cbw(&stg, 3, 252, CBW_FLAG_IN, &[REQUEST_SENSE, 0, 0, 0, 252, 0]);
+        let mut data = [0_u8; 252];
+        let mut packet = build_packet(USB_TOKEN_IN, STORAGE_EP_IN, &mut data);
+        stg.lock().unwrap().handle_packet(&mut packet);
+        assert_eq!(packet.actual_length as usize, SCSI_FIXED_SENSE_LEN);
+        assert_eq!(data[0], 0x70);
+        assert_eq!(data[2], SCSI_SENSE_INVALID_FIELD.key);
+        assert_eq!(data[12], SCSI_SENSE_INVALID_FIELD.asc);
+        let csw = recv_csw(&stg);
+        assert_eq!(csw.status, CSW_STATUS_PASSED);
+        assert_eq!(csw.residue, 252 - SCSI_FIXED_SENSE_LEN as u32);
+
+        // Sense is cleared after it is reported.
+        send_cbw(&stg, 4, 18, CBW_FLAG_IN, &[REQUEST_SENSE, 0, 0, 0, 18, 0]);
+        let mut data = [0xff_u8; 18];
+        let mut packet = build_packet(USB_TOKEN_IN, STORAGE_EP_IN, &mut data);
+        stg.lock().unwrap().handle_packet(&mut packet);
+        assert_eq!(data[2], SCSI_SENSE_NO_SENSE.key);
+        assert_eq!(recv_csw(&stg).status, CSW_STATUS_PASSED);
+
+        // CSW is not expected before the CBW.
+        let mut buf = [0_u8; CSW_SIZE];
+        let mut packet = build_packet(USB_TOKEN_IN, STORAGE_EP_IN, &mut buf);
+        stg.lock().unwrap().handle_packet(&mut packet);
+        assert_eq!(packet.status, UsbPacketStatus::Stall);
+
+        // Invalid CBW signature.
+        let mut buf = [0_u8; CBW_SIZE];
+        let mut packet = build_packet(USB_TOKEN_OUT, STORAGE_EP_OUT, &mut buf);
+        stg.lock().unwrap().handle_packet(&mut packet);
+        assert_eq!(packet.status, UsbPacketStatus::Stall);
+    }
+
+    #[test]
+    fn test_usb_storage_read_write() {
+        let path = std::env::temp_dir().join("usb_storage_test.img");
+        let path = path.to_str().unwrap();
+        let file = std::fs::File::create(path).unwrap();
+        file.set_len(1 << 20).unwrap();
+        let stg = build_storage("stg1", path);
+
+        // Write one sector at lba 1, the packet is NAKed until the IO completes.
+        send_cbw(&stg, 1, 512, 0, &[WRITE_10, 0, 0, 0, 0, 1, 0, 0, 1, 0]);
+        let mut data = [0x5a_u8; 512];
+        let mut packet = build_packet(USB_TOKEN_OUT, STORAGE_EP_OUT, &mut data);
+        stg.lock().unwrap().handle_packet(&mut packet);
+        assert_eq!(packet.status, UsbPacketStatus::Nak);
+        assert_eq!(
+            stg.lock().unwrap().get_wakeup_endpoint().ep_number,
+            STORAGE_EP_OUT
+        );
+        stg.lock().unwrap().handle_packet(&mut packet);
+        assert_eq!(packet.status, UsbPacketStatus::Nak);
+        handle_packet_io(&stg, &mut packet);
+        assert_eq!(packet.status, UsbPacketStatus::Success);
+        assert_eq!(packet.actual_length, 512);
+        let csw = recv_csw(&stg);
+        assert_eq!(csw.status, CSW_STATUS_PASSED);
+        assert_eq!(csw.residue, 0);
+
+        // Read lba 0 and lba 1 back in two packets.
+        send_cbw(
+            &stg,
+            2,
+            1024,
+            CBW_FLAG_IN,
+            &[READ_10, 0, 0, 0, 0, 0, 0, 0, 2, 0],
+        );
+        for expect in [0_u8, 0x5a] {
+            let mut data = [0xff_u8; 512];
+            let mut packet = build_packet(USB_TOKEN_IN, STORAGE_EP_IN, &mut data);
+            handle_packet_io(&stg, &mut packet);
+            assert_eq!(packet.actual_length, 512);
+            assert!(data.iter().all(|&b| b == expect));
+        }
+        assert_eq!(recv_csw(&stg).status, CSW_STATUS_PASSED);
+
+        // SYNCHRONIZE CACHE flushes the image file before CSW.
+        send_cbw(
+            &stg,
+            4,
+            0,
+            0,
+            &[SYNCHRONIZE_CACHE, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        );
+        let mut buf = [0_u8; CSW_SIZE];
+        let mut packet = build_packet(USB_TOKEN_IN, STORAGE_EP_IN, &mut buf);
+        stg.lock().unwrap().handle_packet(&mut packet);
+        assert_eq!(packet.status, UsbPacketStatus::Nak);
+        handle_packet_io(&stg, &mut packet);
+        assert_eq!(packet.status, UsbPacketStatus::Success);
+        assert_eq!(LittleEndian::read_u32(&buf[4..8]), 4);
+        assert_eq!(buf[12], CSW_STATUS_PASSED);
+
+        // Read beyond the end of the disk, no data is sent.
+        send_cbw(
+            &stg,
+            3,
+            512,
+            CBW_FLAG_IN,
+            &[READ_10, 0, 0, 0, 0x8, 0, 0, 0, 1, 0],
+        );
+        let mut data = [0_u8; 512];
+        let mut packet = build_packet(USB_TOKEN_IN, STORAGE_EP_IN, &mut data);
+        stg.lock().unwrap().handle_packet(&mut packet);
+        assert_eq!(packet.actual_length, 0);
+        let csw = recv_csw(&stg);
+        assert_eq!(csw.status, CSW_STATUS_FAILED);
+        assert_eq!(csw.residue, 512);
+        let sense = stg.lock().unwrap().state.sense.take().unwrap();
+        assert_eq!(sense.asc, SCSI_SENSE_LBA_OUT_OF_RANGE.asc);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
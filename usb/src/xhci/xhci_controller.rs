@@ -23,9 +23,11 @@ use address_space::{AddressSpace, GuestAddress};
 use byteorder::{ByteOrder, LittleEndian};
 use log::{debug, error, info, warn};
 use machine_manager::config::XhciConfig;
+use migration_derive::ByteCode;
 use util::num_ops::{read_u32, write_u64_low};
 
 use crate::config::*;
+use crate::descriptor::UsbDescriptorOps;
 use crate::usb::{Iovec, UsbDeviceOps, UsbDeviceRequest, UsbEndpoint, UsbPacket, UsbPacketStatus};
 use crate::xhci::xhci_regs::{XchiOperReg, XhciInterrupter};
 use crate::UsbError;
@@ -227,6 +229,37 @@ impl XhciEpContext {
         Ok(())
     }
 
+    /// Get the state for migration. The TD of the pending transfer is fetched again
+    /// after migration, so the dequeue pointer is rewound to it.
+    fn get_migration_state(&self) -> XhciEpState {
+        let (dequeue, ccs) = match self.transfers.front().and_then(|xfer| xfer.td.first()) {
+            Some(trb) => (trb.addr, trb.ccs),
+            None => (self.ring.dequeue, self.ring.ccs),
+        };
+        XhciEpState {
+            enabled: self.enabled,
+            ep_type: self.ep_type as u32,
+            state: self.state,
+            interval: self.interval,
+            output_ctx_addr: self.output_ctx_addr,
+            dequeue,
+            ccs,
+        }
+    }
+
+    fn set_migration_state(&mut self, epid: u32, state: &XhciEpState) {
+        self.epid = epid;
+        self.enabled = state.enabled;
+        self.ep_type = state.ep_type.into();
+        self.state = state.state;
+        self.interval = state.interval;
+        self.output_ctx_addr = state.output_ctx_addr;
+        self.ring.dequeue = state.dequeue;
+        self.ring.ccs = state.ccs;
+        self.transfers.clear();
+        self.retry = None;
+    }
+
     /// Flush the transfer list, remove the transfer which is completed.
     fn flush_transfer(&mut self) {
         let mut undo = LinkedList::new();
@@ -356,6 +389,75 @@ impl UsbPort {
     }
 }
 
+/// State of endpoint context for migration.
+#[repr(C)]
+#[derive(Copy, Clone, ByteCode)]
+pub struct XhciEpState {
+    enabled: bool,
+    ep_type: u32,
+    state: u32,
+    interval: u32,
+    output_ctx_addr: u64,
+    dequeue: u64,
+    ccs: bool,
+}
+
+/// State of device slot for migration.
+#[repr(C)]
+#[derive(Copy, Clone, ByteCode)]
+pub struct XhciSlotState {
+    enabled: bool,
+    addressed: bool,
+    slot_ctx_addr: u64,
+    /// ID of the port which the slot is bound to, 0 means none.
+    port_id: u8,
+    endpoints: [XhciEpState; 31],
+}
+
+/// State of interrupter for migration.
+#[repr(C)]
+#[derive(Copy, Clone, ByteCode)]
+pub struct XhciIntrState {
+    iman: u32,
+    imod: u32,
+    erstsz: u32,
+    erstba: u64,
+    erdp: u64,
+    er_pcs: bool,
+    er_start: u64,
+    er_size: u32,
+    er_ep_idx: u32,
+}
+
+/// State of usb port and the attached usb device for migration.
+#[repr(C)]
+#[derive(Copy, Clone, ByteCode)]
+pub struct XhciPortState {
+    portsc: u32,
+    dev_addr: u8,
+    dev_remote_wakeup: u32,
+    /// bConfigurationValue of the selected configuration, 0 means not configured.
+    dev_config_value: u8,
+    dev_altsetting: [u32; 16],
+}
+
+/// State of xhci controller for migration.
+#[repr(C)]
+#[derive(Copy, Clone, ByteCode)]
+pub struct XhciState {
+    usb_cmd: u32,
+    usb_status: u32,
+    dev_notify_ctrl: u32,
+    cmd_ring_ctrl: u64,
+    dcbaap: u64,
+    config: u32,
+    cmd_ring_dequeue: u64,
+    cmd_ring_ccs: bool,
+    intrs: [XhciIntrState; 16],
+    ports: [XhciPortState; 30],
+    slots: [XhciSlotState; 64],
+}
+
 /// Event usually send to drivers.
 #[derive(Debug)]
 pub struct XhciEvent {
@@ -1501,7 +1603,7 @@ impl XhciDevice {
             || epctx.ep_type == EpType::IsoIn
             || epctx.ep_type == EpType::BulkIn
             || epctx.ep_type == EpType::IntrIn;
-        if epctx.ep_type != EpType::IntrOut && epctx.ep_type != EpType::IntrIn {
+        if epctx.ep_type == EpType::IsoOut || epctx.ep_type == EpType::IsoIn {
             warn!("Unhandled ep_type {:?}", epctx.ep_type);
         }
         if let Err(e) = self.setup_usb_packet(xfer) {
@@ -1862,6 +1964,137 @@ impl XhciDevice {
         }
         None
     }
+
+    /// Get the state of controller, ports and attached usb devices for migration.
+    pub fn get_migration_state(&self) -> XhciState {
+        let mut state = XhciState {
+            usb_cmd: self.oper.usb_cmd,
+            usb_status: self.oper.usb_status,
+            dev_notify_ctrl: self.oper.dev_notify_ctrl,
+            cmd_ring_ctrl: self.oper.cmd_ring_ctrl,
+            dcbaap: self.oper.dcbaap,
+            config: self.oper.config,
+            cmd_ring_dequeue: self.cmd_ring.dequeue,
+            cmd_ring_ccs: self.cmd_ring.ccs,
+            ..Default::default()
+        };
+        for (intr, intr_state) in self.intrs.iter().zip(state.intrs.iter_mut()) {
+            *intr_state = XhciIntrState {
+                iman: intr.iman,
+                imod: intr.imod,
+                erstsz: intr.erstsz,
+                erstba: intr.erstba,
+                erdp: intr.erdp,
+                er_pcs: intr.er_pcs,
+                er_start: intr.er_start,
+                er_size: intr.er_size,
+                er_ep_idx: intr.er_ep_idx,
+            };
+        }
+        for (port, port_state) in self.usb_ports.iter().zip(state.ports.iter_mut()) {
+            let locked_port = port.lock().unwrap();
+            port_state.portsc = locked_port.portsc;
+            if let Some(dev) = &locked_port.dev {
+                let locked_dev = dev.lock().unwrap();
+                let usb_dev = locked_dev.get_usb_device();
+                port_state.dev_addr = usb_dev.addr;
+                port_state.dev_remote_wakeup = usb_dev.remote_wakeup;
+                if let Some(conf) = &usb_dev.descriptor.configuration_selected {
+                    port_state.dev_config_value = conf.config_desc.bConfigurationValue;
+                }
+                port_state
+                    .dev_altsetting
+                    .copy_from_slice(&usb_dev.descriptor.altsetting);
+            }
+        }
+        for (slot, slot_state) in self.slots.iter().zip(state.slots.iter_mut()) {
+            slot_state.enabled = slot.enabled;
+            slot_state.addressed = slot.addressed;
+            slot_state.slot_ctx_addr = slot.slot_ctx_addr;
+            if let Some(port) = &slot.usb_port {
+                slot_state.port_id = port.lock().unwrap().port_id;
+            }
+            for (ep, ep_state) in slot.endpoints.iter().zip(slot_state.endpoints.iter_mut()) {
+                *ep_state = ep.get_migration_state();
+            }
+        }
+        state
+    }
+
+    /// Restore the state of controller, ports and attached usb devices after migration.
+    pub fn set_migration_state(&mut self, state: &XhciState) -> Result<()> {
+        self.oper.usb_cmd = state.usb_cmd;
+        self.oper.usb_status = state.usb_status;
+        self.oper.dev_notify_ctrl = state.dev_notify_ctrl;
+        self.oper.cmd_ring_ctrl = state.cmd_ring_ctrl;
+        self.oper.dcbaap = state.dcbaap;
+        self.oper.config = state.config;
+        self.cmd_ring.dequeue = state.cmd_ring_dequeue;
+        self.cmd_ring.ccs = state.cmd_ring_ccs;
+        for (intr, intr_state) in self.intrs.iter_mut().zip(state.intrs.iter()) {
+            intr.iman = intr_state.iman;
+            intr.imod = intr_state.imod;
+            intr.erstsz = intr_state.erstsz;
+            intr.erstba = intr_state.erstba;
+            intr.erdp = intr_state.erdp;
+            intr.er_pcs = intr_state.er_pcs;
+            intr.er_start = intr_state.er_start;
+            intr.er_size = intr_state.er_size;
+            intr.er_ep_idx = intr_state.er_ep_idx;
+        }
+        for (port, port_state) in self.usb_ports.iter().zip(state.ports.iter()) {
+            let mut locked_port = port.lock().unwrap();
+            locked_port.portsc = port_state.portsc;
+            if let Some(dev) = &locked_port.dev {
+                let mut locked_dev = dev.lock().unwrap();
+                let usb_dev = locked_dev.get_mut_usb_device();
+                usb_dev.addr = port_state.dev_addr;
+                usb_dev.remote_wakeup = port_state.dev_remote_wakeup;
+                usb_dev.set_config_descriptor(port_state.dev_config_value)?;
+                for i in 0..usb_dev.descriptor.interface_number {
+                    usb_dev.set_interface_descriptor(i, port_state.dev_altsetting[i as usize])?;
+                }
+            }
+        }
+        for (slot, slot_state) in self.slots.iter_mut().zip(state.slots.iter()) {
+            slot.enabled = slot_state.enabled;
+            slot.addressed = slot_state.addressed;
+            slot.slot_ctx_addr = slot_state.slot_ctx_addr;
+            slot.usb_port = None;
+            if slot_state.port_id != 0 {
+                let port = self
+                    .usb_ports
+                    .get(slot_state.port_id as usize - 1)
+                    .with_context(|| format!("Invalid usb port {}", slot_state.port_id))?;
+                slot.usb_port = Some(port.clone());
+            }
+            for (index, (ep, ep_state)) in slot
+                .endpoints
+                .iter_mut()
+                .zip(slot_state.endpoints.iter())
+                .enumerate()
+            {
+                ep.set_migration_state(index as u32 + 1, ep_state);
+            }
+        }
+        Ok(())
+    }
+
+    /// Fetch the transfers of running endpoints again after migration.
+    pub fn kick_running_endpoints(&mut self) -> Result<()> {
+        for slot_id in 1..=self.slots.len() as u32 {
+            if !self.slots[(slot_id - 1) as usize].enabled {
+                continue;
+            }
+            for ep_id in 1..=MAX_ENDPOINTS {
+                let epctx = &self.slots[(slot_id - 1) as usize].endpoints[(ep_id - 1) as usize];
+                if epctx.enabled && epctx.state == EP_RUNNING {
+                    self.kick_endpoint(slot_id, ep_id)?;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 // DMA read/write helpers.
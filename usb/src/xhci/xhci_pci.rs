@@ -17,20 +17,25 @@ use std::sync::{Arc, Mutex, Weak};
 use address_space::{AddressSpace, Region};
 use log::debug;
 use machine_manager::config::XhciConfig;
+use migration::{
+    DeviceStateDesc, FieldDesc, MigrationError, MigrationHook, MigrationManager, StateTransfer,
+};
+use migration_derive::{ByteCode, Desc};
 use pci::config::{
     PciConfig, RegionType, DEVICE_ID, MINMUM_BAR_SIZE_FOR_MMIO, PCI_CONFIG_SPACE_SIZE,
     PCI_DEVICE_ID_REDHAT_XHCI, PCI_VENDOR_ID_REDHAT, REVISION_ID, SUB_CLASS_CODE, VENDOR_ID,
 };
 use pci::msix::update_dev_id;
 use pci::{init_msix, le_write_u16, PciBus, PciDevOps};
+use util::byte_code::ByteCode;
 
 use crate::usb::UsbDeviceOps;
-use crate::xhci::xhci_controller::{XhciDevice, MAX_INTRS, MAX_SLOTS};
+use crate::xhci::xhci_controller::{XhciDevice, XhciState, MAX_INTRS, MAX_SLOTS};
 use crate::xhci::xhci_regs::{
     build_cap_ops, build_doorbell_ops, build_oper_ops, build_port_ops, build_runtime_ops,
     XHCI_CAP_LENGTH, XHCI_OFF_DOORBELL, XHCI_OFF_RUNTIME,
 };
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 
 /// 5.2 PCI Configuration Registers(USB)
 const PCI_CLASS_PI: u16 = 0x09;
@@ -60,6 +65,15 @@ const XHCI_MSIX_PBA_OFFSET: u32 = 0x3800;
 /// 0x0    0x40    0x440    0x1000    0x2000      0x3000   0x4000
 /// | cap  | oper  | port   | runtime | doorbell  | MSIX   |      
 
+/// State of xhci pci device for migration.
+#[repr(C)]
+#[derive(Copy, Clone, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+pub struct XhciPciState {
+    config_space: [u8; 256],
+    xhci: XhciState,
+}
+
 /// XHCI pci device which can be attached to PCI bus.
 pub struct XhciPciDevice {
     pci_config: PciConfig,
@@ -231,13 +245,15 @@ impl PciDevOps for XhciPciDevice {
                 .unwrap()
                 .notify(n as u16, cloned_dev_id.load(Ordering::Acquire));
         }));
+        let name = self.name.clone();
         let dev = Arc::new(Mutex::new(self));
         // Attach to the PCI bus.
         let pci_bus = dev.lock().unwrap().parent_bus.upgrade().unwrap();
         let mut locked_pci_bus = pci_bus.lock().unwrap();
         let pci_device = locked_pci_bus.devices.get(&devfn);
         if pci_device.is_none() {
-            locked_pci_bus.devices.insert(devfn, dev);
+            locked_pci_bus.devices.insert(devfn, dev.clone());
+            MigrationManager::register_device_instance(XhciPciState::descriptor(), dev, &name);
         } else {
             bail!(
                 "Devfn {:?} has been used by {:?}",
@@ -249,6 +265,7 @@ impl PciDevOps for XhciPciDevice {
     }
 
     fn unrealize(&mut self) -> pci::Result<()> {
+        MigrationManager::unregister_device_instance(XhciPciState::descriptor(), &self.name);
         Ok(())
     }
 
@@ -287,3 +304,52 @@ impl PciDevOps for XhciPciDevice {
         Ok(())
     }
 }
+
+impl StateTransfer for XhciPciDevice {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        let mut state = XhciPciState {
+            xhci: self.xhci.lock().unwrap().get_migration_state(),
+            ..Default::default()
+        };
+        state.config_space[..self.pci_config.config.len()].copy_from_slice(&self.pci_config.config);
+
+        Ok(state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        let xhci_state = *XhciPciState::from_bytes(state)
+            .ok_or_else(|| anyhow!(MigrationError::FromBytesError("XHCI_PCI")))?;
+
+        let length = self.pci_config.config.len();
+        self.pci_config.config = xhci_state.config_space[..length].to_vec();
+        self.xhci
+            .lock()
+            .unwrap()
+            .set_migration_state(&xhci_state.xhci)
+            .with_context(|| format!("Failed to restore xhci {}", self.name))?;
+
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&XhciPciState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+impl MigrationHook for XhciPciDevice {
+    fn resume(&mut self) -> migration::Result<()> {
+        update_dev_id(&self.parent_bus, self.devfn, &self.dev_id);
+        let parent_bus = self.parent_bus.upgrade().unwrap();
+        let locked_parent_bus = parent_bus.lock().unwrap();
+        self.pci_config
+            .update_bar_mapping(
+                #[cfg(target_arch = "x86_64")]
+                Some(&locked_parent_bus.io_region),
+                Some(&locked_parent_bus.mem_region),
+            )
+            .with_context(|| format!("Failed to update bar of xhci {}", self.name))?;
+        drop(locked_parent_bus);
+
+        self.xhci.lock().unwrap().kick_running_endpoints()
+    }
+}
@@ -17,6 +17,7 @@ mod uring;
 use std::clone::Clone;
 use std::io::Write;
 use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicI64, AtomicU32, Ordering};
 use std::sync::Arc;
 use std::{cmp, str::FromStr};
 
@@ -44,6 +45,12 @@ const AIO_NATIVE: &str = "native";
 const AIO_IOURING: &str = "io_uring";
 /// Max bytes of bounce buffer for misaligned IO.
 const MAX_LEN_BOUNCE_BUFF: u64 = 1 << 20;
+/// Write zeroes is disabled.
+const WRITE_ZEROES_OFF: &str = "off";
+/// Write zeroes is enabled.
+const WRITE_ZEROES_ON: &str = "on";
+/// Write zeroes is enabled, and the space may be unmapped.
+const WRITE_ZEROES_UNMAP: &str = "unmap";
 
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy)]
 pub enum AioEngine {
@@ -65,6 +72,28 @@ impl FromStr for AioEngine {
     }
 }
 
+/// Whether to detect the write requests which only contain zeroes, and turn
+/// them into write zeroes requests.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy)]
+pub enum WriteZeroesState {
+    Off,
+    On,
+    Unmap,
+}
+
+impl FromStr for WriteZeroesState {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            WRITE_ZEROES_OFF => Ok(WriteZeroesState::Off),
+            WRITE_ZEROES_ON => Ok(WriteZeroesState::On),
+            WRITE_ZEROES_UNMAP => Ok(WriteZeroesState::Unmap),
+            _ => Err(()),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Iovec {
     pub iov_base: u64,
@@ -91,12 +120,19 @@ pub enum OpCode {
     Preadv = 1,
     Pwritev = 2,
     Fdsync = 3,
+    Discard = 4,
+    WriteZeroes = 5,
+    WriteZeroesUnmap = 6,
 }
 
 pub struct AioCb<T: Clone> {
     pub direct: bool,
     pub req_align: u32,
     pub buf_align: u32,
+    /// Discard request is passed to the file.
+    pub discard: bool,
+    /// Detect write requests which only contain zeroes.
+    pub write_zeroes: WriteZeroesState,
     pub file_fd: RawFd,
     pub opcode: OpCode,
     pub iovec: Vec<Iovec>,
@@ -104,6 +140,26 @@ pub struct AioCb<T: Clone> {
     pub nbytes: u64,
     pub user_data: u64,
     pub iocompletecb: T,
+    /// Shared (remaining count, result) if this request is one part of a combined request.
+    pub combine_req: Option<(Arc<AtomicU32>, Arc<AtomicI64>)>,
+}
+
+/// One host IO of a guest request which has been split by the block format driver.
+#[derive(Debug, Clone)]
+pub struct CombineRequest {
+    pub iov: Vec<Iovec>,
+    pub offset: u64,
+    pub nbytes: u64,
+}
+
+impl CombineRequest {
+    pub fn new(iov: Vec<Iovec>, offset: u64, nbytes: u64) -> Self {
+        CombineRequest {
+            iov,
+            offset,
+            nbytes,
+        }
+    }
 }
 
 pub type AioCompleteFunc<T> = fn(&AioCb<T>, i64) -> Result<()>;
@@ -159,6 +215,16 @@ impl<T: Clone + 'static> Aio<T> {
     }
 
     pub fn submit_request(&mut self, mut cb: AioCb<T>) -> Result<()> {
+        if cb.opcode == OpCode::Pwritev
+            && cb.write_zeroes != WriteZeroesState::Off
+            && iovec_is_zero(&cb.iovec)
+        {
+            cb.opcode = OpCode::WriteZeroes;
+            if cb.write_zeroes == WriteZeroesState::Unmap && cb.discard {
+                cb.opcode = OpCode::WriteZeroesUnmap;
+            }
+        }
+
         if self.request_misaligned(&cb) {
             let max_len = round_down(cb.nbytes + cb.req_align as u64 * 2, cb.req_align as u64)
                 .ok_or_else(|| anyhow!("Failed to round down request length."))?;
@@ -170,20 +236,20 @@ impl<T: Clone + 'static> Aio<T> {
                 unsafe { libc::memalign(host_page_size() as usize, buff_len as usize) };
             if bounce_buffer.is_null() {
                 error!("Failed to alloc memory for misaligned read/write.");
-                return (self.complete_func)(&cb, -1);
+                return Self::complete_aiocb(&self.complete_func, &cb, -libc::EIO as i64);
             }
 
             let res = match self.handle_misaligned_rw(&mut cb, bounce_buffer, buff_len) {
                 Ok(()) => 0,
                 Err(e) => {
                     error!("{:?}", e);
-                    -1
+                    -libc::EIO as i64
                 }
             };
 
             // SAFETY: the memory is allocated by us and will not be used anymore.
             unsafe { libc::free(bounce_buffer) };
-            return (self.complete_func)(&cb, res);
+            return Self::complete_aiocb(&self.complete_func, &cb, res);
         }
 
         match cb.opcode {
@@ -201,10 +267,75 @@ impl<T: Clone + 'static> Aio<T> {
                     self.flush_sync(cb)
                 }
             }
+            // Fallocate is not supported by libaio, so do it synchronously.
+            OpCode::Discard => self.discard_sync(cb),
+            OpCode::WriteZeroes | OpCode::WriteZeroesUnmap => self.write_zeroes_sync(cb),
             OpCode::Noop => Err(anyhow!("Aio opcode is not specified.")),
         }
     }
 
+    /// Submit a guest request which has been split into several host requests. The
+    /// complete callback is called only once, when all of the host requests are finished.
+    /// An empty `reqs` means the request has been completed by the caller.
+    pub fn submit_combine_requests(
+        &mut self,
+        cb: AioCb<T>,
+        mut reqs: Vec<CombineRequest>,
+    ) -> Result<()> {
+        if reqs.is_empty() {
+            return Self::complete_aiocb(&self.complete_func, &cb, cb.nbytes as i64);
+        }
+        if reqs.len() == 1 {
+            let req = reqs.pop().unwrap();
+            let mut cb = cb;
+            cb.iovec = req.iov;
+            cb.offset = req.offset as usize;
+            cb.nbytes = req.nbytes;
+            return self.submit_request(cb);
+        }
+
+        let cnt = Arc::new(AtomicU32::new(reqs.len() as u32));
+        let res = Arc::new(AtomicI64::new(cb.nbytes as i64));
+        for req in reqs {
+            let sub_cb = AioCb {
+                direct: cb.direct,
+                req_align: cb.req_align,
+                buf_align: cb.buf_align,
+                discard: cb.discard,
+                write_zeroes: cb.write_zeroes,
+                file_fd: cb.file_fd,
+                opcode: cb.opcode,
+                iovec: req.iov,
+                offset: req.offset as usize,
+                nbytes: req.nbytes,
+                user_data: 0,
+                iocompletecb: cb.iocompletecb.clone(),
+                combine_req: Some((cnt.clone(), res.clone())),
+            };
+            self.submit_request(sub_cb)?;
+        }
+        Ok(())
+    }
+
+    /// Complete the request with `res` without submitting it, e.g. it fails before doing IO.
+    pub fn complete_request(&mut self, cb: AioCb<T>, res: i64) -> Result<()> {
+        Self::complete_aiocb(&self.complete_func, &cb, res)
+    }
+
+    fn complete_aiocb(func: &Arc<AioCompleteFunc<T>>, cb: &AioCb<T>, res: i64) -> Result<()> {
+        if let Some((cnt, combine_res)) = cb.combine_req.as_ref() {
+            if res < 0 {
+                combine_res.store(res, Ordering::SeqCst);
+            }
+            // Wait for the other parts of the combined request.
+            if cnt.fetch_sub(1, Ordering::SeqCst) > 1 {
+                return Ok(());
+            }
+            return (func)(cb, combine_res.load(Ordering::SeqCst));
+        }
+        (func)(cb, res)
+    }
+
     pub fn flush_request(&mut self) -> Result<()> {
         if self.ctx.is_some() {
             self.process_list()
@@ -231,10 +362,10 @@ impl<T: Clone + 'static> Aio<T> {
                         "Async IO request failed, status {} res {}",
                         evt.status, evt.res
                     );
-                    -1
+                    incomplete_io_res(&(*node).value, evt.res)
                 };
 
-                (self.complete_func)(&(*node).value, res)?;
+                Self::complete_aiocb(&self.complete_func, &(*node).value, res)?;
                 self.aio_in_flight.unlink(&(*node));
                 // Construct Box to free mem automatically.
                 drop(Box::from_raw(node));
@@ -284,7 +415,7 @@ impl<T: Clone + 'static> Aio<T> {
             if is_err {
                 // Fail one request, retry the rest.
                 if let Some(node) = self.aio_in_queue.pop_tail() {
-                    (self.complete_func)(&(node).value, -1)?;
+                    Self::complete_aiocb(&self.complete_func, &(node).value, -libc::EIO as i64)?;
                 }
             } else if nr == 0 {
                 // If can't submit any request, break the loop
@@ -311,15 +442,15 @@ impl<T: Clone + 'static> Aio<T> {
         let mut ret = match cb.opcode {
             OpCode::Preadv => raw_readv(cb.file_fd, &cb.iovec, cb.offset),
             OpCode::Pwritev => raw_writev(cb.file_fd, &cb.iovec, cb.offset),
-            _ => -1,
+            _ => -libc::EIO as i64,
         };
         if ret < 0 {
             error!("Failed to do sync read/write.");
         } else if ret as u64 != cb.nbytes {
             error!("Incomplete sync read/write.");
-            ret = -1;
+            ret = incomplete_io_res(&cb, ret);
         }
-        (self.complete_func)(&cb, ret)
+        Self::complete_aiocb(&self.complete_func, &cb, ret)
     }
 
     fn request_misaligned(&self, cb: &AioCb<T>) -> bool {
@@ -486,7 +617,88 @@ impl<T: Clone + 'static> Aio<T> {
         if ret < 0 {
             error!("Failed to do sync flush.");
         }
-        (self.complete_func)(&cb, ret)
+        Self::complete_aiocb(&self.complete_func, &cb, ret)
+    }
+
+    fn discard_sync(&mut self, cb: AioCb<T>) -> Result<()> {
+        let ret = raw_discard(cb.file_fd, cb.offset, cb.nbytes);
+        if ret < 0 && ret != -libc::ENOTSUP as i64 {
+            error!("Failed to do sync discard.");
+        }
+        // Discard is only a hint, it does not fail.
+        Self::complete_aiocb(&self.complete_func, &cb, 0)
+    }
+
+    fn write_zeroes_sync(&mut self, mut cb: AioCb<T>) -> Result<()> {
+        // Punching hole beyond the end of file does not extend it, do it only inside the file.
+        if cb.opcode == OpCode::WriteZeroesUnmap
+            && cb.offset as u64 + cb.nbytes <= raw_file_size(cb.file_fd) as u64
+            && raw_discard(cb.file_fd, cb.offset, cb.nbytes) == 0
+        {
+            return Self::complete_aiocb(&self.complete_func, &cb, 0);
+        }
+
+        let mut ret = raw_write_zeroes(cb.file_fd, cb.offset, cb.nbytes);
+        if ret == -libc::ENOTSUP as i64 {
+            if !cb.iovec.is_empty() {
+                // The buffer of the request is all zeroes, just write it.
+                cb.opcode = OpCode::Pwritev;
+                cb.write_zeroes = WriteZeroesState::Off;
+                return self.submit_request(cb);
+            }
+            ret = self.write_zeroes_by_buffer(&cb);
+        }
+        if ret < 0 {
+            error!("Failed to do sync write zeroes.");
+        }
+        Self::complete_aiocb(&self.complete_func, &cb, ret)
+    }
+
+    /// Write zeroes by writing a buffer of zeroes, used when fallocate is not supported.
+    fn write_zeroes_by_buffer(&self, cb: &AioCb<T>) -> i64 {
+        let buff_len = cmp::min(cb.nbytes, MAX_LEN_BOUNCE_BUFF);
+        // SAFETY: we allocate aligned memory and free it later.
+        let buffer = unsafe { libc::memalign(host_page_size() as usize, buff_len as usize) };
+        if buffer.is_null() {
+            error!("Failed to alloc memory for write zeroes.");
+            return -libc::EIO as i64;
+        }
+        // SAFETY: the memory is allocated by us with length buff_len.
+        unsafe { libc::memset(buffer, 0, buff_len as usize) };
+
+        let mut ret = 0;
+        let mut offset = 0;
+        while offset < cb.nbytes {
+            let len = cmp::min(cb.nbytes - offset, buff_len);
+            let res = raw_write(
+                cb.file_fd,
+                buffer as u64,
+                len as usize,
+                cb.offset + offset as usize,
+            );
+            if res < 0 || res as u64 != len {
+                ret = if res < 0 { res } else { -libc::ENOSPC as i64 };
+                break;
+            }
+            offset += len;
+        }
+
+        // SAFETY: the memory is allocated by us and will not be used anymore.
+        unsafe { libc::free(buffer) };
+        ret
+    }
+}
+
+/// Get the result of the request `cb` which is not fully done, `res` is the
+/// negative errno or the number of bytes done. A short write means that there
+/// is no space left on the host device.
+fn incomplete_io_res<T: Clone>(cb: &AioCb<T>, res: i64) -> i64 {
+    if res < 0 {
+        res
+    } else if cb.opcode == OpCode::Pwritev {
+        -libc::ENOSPC as i64
+    } else {
+        -libc::EIO as i64
     }
 }
 
@@ -539,6 +751,24 @@ pub fn iov_to_buf_direct(iovec: &[Iovec], buf: &mut [u8]) -> Result<usize> {
     Ok(end)
 }
 
+/// Check whether all the bytes of iovec are zero.
+pub fn iovec_is_zero(iovecs: &[Iovec]) -> bool {
+    for iov in iovecs {
+        // SAFETY: iov_base and iov_len has been checked in pop_avail().
+        let slice =
+            unsafe { std::slice::from_raw_parts(iov.iov_base as *const u8, iov.iov_len as usize) };
+        // SAFETY: u64 is valid for any bit pattern.
+        let (prefix, middle, suffix) = unsafe { slice.align_to::<u64>() };
+        if prefix.iter().any(|x| *x != 0)
+            || middle.iter().any(|x| *x != 0)
+            || suffix.iter().any(|x| *x != 0)
+        {
+            return false;
+        }
+    }
+    true
+}
+
 /// Discard "size" bytes of the front of iovec.
 pub fn iov_discard_front_direct(iovec: &mut [Iovec], mut size: u64) -> Option<&mut [Iovec]> {
     for (index, iov) in iovec.iter_mut().enumerate() {
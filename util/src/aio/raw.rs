@@ -11,10 +11,14 @@
 // See the Mulan PSL v2 for more details.
 
 use super::Iovec;
-use libc::{c_int, c_void, fdatasync, iovec, off_t, pread, preadv, pwrite, pwritev, size_t};
+use libc::{
+    c_int, c_void, fallocate, fdatasync, iovec, off_t, pread, preadv, pwrite, pwritev, size_t,
+    FALLOC_FL_KEEP_SIZE, FALLOC_FL_PUNCH_HOLE, FALLOC_FL_ZERO_RANGE,
+};
 use log::error;
 use std::os::unix::io::RawFd;
 
+/// The read/write helpers return the negative errno on failure.
 pub fn raw_read(fd: RawFd, buf: u64, size: usize, offset: usize) -> i64 {
     let mut ret;
     loop {
@@ -32,12 +36,10 @@ pub fn raw_read(fd: RawFd, buf: u64, size: usize, offset: usize) -> i64 {
         }
     }
     if ret < 0 {
+        ret = -errno::errno().0 as i64;
         error!(
             "Failed to pread: buf{}, size{}, offset{}, errno{}.",
-            buf,
-            size,
-            offset,
-            errno::errno().0
+            buf, size, offset, -ret
         );
     }
     ret
@@ -60,11 +62,8 @@ pub fn raw_readv(fd: RawFd, iovec: &[Iovec], offset: usize) -> i64 {
         }
     }
     if ret < 0 {
-        error!(
-            "Failed to preadv: offset{}, errno{}.",
-            offset,
-            errno::errno().0,
-        );
+        ret = -errno::errno().0 as i64;
+        error!("Failed to preadv: offset{}, errno{}.", offset, -ret);
     }
     ret
 }
@@ -86,12 +85,10 @@ pub fn raw_write(fd: RawFd, buf: u64, size: usize, offset: usize) -> i64 {
         }
     }
     if ret < 0 {
+        ret = -errno::errno().0 as i64;
         error!(
             "Failed to pwrite: buf{}, size{}, offset{}, errno{}.",
-            buf,
-            size,
-            offset,
-            errno::errno().0,
+            buf, size, offset, -ret
         );
     }
     ret
@@ -114,20 +111,68 @@ pub fn raw_writev(fd: RawFd, iovec: &[Iovec], offset: usize) -> i64 {
         }
     }
     if ret < 0 {
-        error!(
-            "Failed to pwritev: offset{}, errno{}.",
-            offset,
-            errno::errno().0,
-        );
+        ret = -errno::errno().0 as i64;
+        error!("Failed to pwritev: offset{}, errno{}.", offset, -ret);
     }
     ret
 }
 
 pub fn raw_datasync(fd: RawFd) -> i64 {
     // SAFETY: fd is valid.
-    let ret = unsafe { i64::from(fdatasync(fd)) };
+    let mut ret = unsafe { i64::from(fdatasync(fd)) };
     if ret < 0 {
-        error!("Failed to fdatasync: errno{}.", errno::errno().0);
+        ret = -errno::errno().0 as i64;
+        error!("Failed to fdatasync: errno{}.", -ret);
+    }
+    ret
+}
+
+fn do_fallocate(fd: RawFd, mode: c_int, offset: usize, size: u64) -> i64 {
+    let mut ret;
+    loop {
+        // SAFETY: fd is valid.
+        ret = unsafe { fallocate(fd as c_int, mode, offset as off_t, size as off_t) as i64 };
+        if !(ret < 0 && errno::errno().0 == libc::EINTR) {
+            break;
+        }
+    }
+    if ret < 0 {
+        // Return the negative errno, so that the caller can fall back if it's not supported.
+        ret = -errno::errno().0 as i64;
+    }
+    ret
+}
+
+pub fn raw_discard(fd: RawFd, offset: usize, size: u64) -> i64 {
+    let ret = do_fallocate(fd, FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE, offset, size);
+    if ret < 0 && ret != -libc::ENOTSUP as i64 {
+        error!(
+            "Failed to fallocate for discard: offset{}, size{}, errno{}.",
+            offset, size, -ret,
+        );
     }
     ret
 }
+
+pub fn raw_write_zeroes(fd: RawFd, offset: usize, size: u64) -> i64 {
+    let ret = do_fallocate(fd, FALLOC_FL_ZERO_RANGE, offset, size);
+    if ret < 0 && ret != -libc::ENOTSUP as i64 {
+        error!(
+            "Failed to fallocate for write zeroes: offset{}, size{}, errno{}.",
+            offset, size, -ret,
+        );
+    }
+    ret
+}
+
+pub fn raw_file_size(fd: RawFd) -> i64 {
+    // SAFETY: fd is valid, stat is initialized by fstat.
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    // SAFETY: fd and stat are valid.
+    let ret = unsafe { libc::fstat(fd, &mut stat) };
+    if ret < 0 {
+        error!("Failed to fstat: errno{}.", errno::errno().0);
+        return -1;
+    }
+    stat.st_size
+}
@@ -25,18 +25,21 @@ pub mod leak_bucket;
 mod link_list;
 pub mod logger;
 pub mod loop_context;
+pub mod net_backend;
 pub mod num_ops;
 pub mod offsetof;
 #[cfg(not(target_env = "musl"))]
 pub mod pixman;
 pub mod reader;
 pub mod seccomp;
+pub mod slirp;
 pub mod syscall;
 pub mod tap;
 pub mod test_helper;
 pub mod time;
 pub mod trace;
 pub mod unix;
+pub mod userfaultfd;
 pub use anyhow::Result;
 pub use error::UtilError;
 use libc::{tcgetattr, tcsetattr, termios, OPOST, TCSANOW};
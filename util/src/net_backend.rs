@@ -0,0 +1,1406 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Backends of the virtio net device.
+//!
+//! Besides the tap device, the frames can be exchanged with a peer through:
+//! - a stream socket (unix or tcp), each frame is prefixed with its length in
+//!   4 bytes big endian;
+//! - a datagram socket (unix, udp unicast or udp multicast), one frame per
+//!   datagram.
+//!
+//! There is no virtio net header on the wire of the socket backends, so the
+//! offload features must not be offered to the guest.
+
+use std::cmp;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Result as IoResult, Write};
+use std::net::{
+    Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, TcpStream, ToSocketAddrs, UdpSocket,
+};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::{UnixDatagram, UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use vmm_sys_util::epoll::{ControlOperation, Epoll, EpollEvent, EventSet};
+use vmm_sys_util::timerfd::TimerFd;
+
+use crate::tap::Tap;
+
+/// Max length of the frame received from the socket backends.
+const MAX_FRAME_LEN: usize = 65536;
+/// Frames shorter than this are padded, the same as ethernet does.
+const MIN_FRAME_LEN: usize = 60;
+/// Length of the frame length prefix of the stream backend.
+const STREAM_LEN_SIZE: usize = 4;
+/// Offset of `num_buffers` in virtio net header.
+const VNET_HDR_NUM_BUFFERS_OFFSET: usize = 10;
+
+/// The backend which sends and receives the frames of a queue pair.
+pub trait NetBackend: Send + Sync {
+    /// Receive one frame into `iovecs`, which start with the virtio net header.
+    /// Returns the length filled in `iovecs` including the header.
+    fn recv(&mut self, iovecs: &[libc::iovec]) -> IoResult<usize>;
+
+    /// Send one frame in `iovecs`, which start with the virtio net header.
+    /// `ErrorKind::WouldBlock` means the frame should be sent again later.
+    fn send(&mut self, iovecs: &[libc::iovec]) -> IoResult<usize>;
+
+    /// The fd which is readable when there are frames to receive.
+    fn as_raw_fd(&self) -> RawFd;
+
+    /// Whether the backend carries the virtio net header, which is required
+    /// by the checksum and segmentation offload.
+    fn has_vnet_hdr(&self) -> bool;
+
+    fn has_ufo(&self) -> bool {
+        false
+    }
+
+    fn set_offload(&self, _flags: u32) -> Result<()> {
+        Ok(())
+    }
+
+    fn clone_box(&self) -> Box<dyn NetBackend>;
+}
+
+impl Clone for Box<dyn NetBackend> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+fn writev_fd(fd: RawFd, iovecs: &[libc::iovec]) -> IoResult<usize> {
+    loop {
+        // SAFETY: the arguments of writev has been checked and is correct.
+        let size = unsafe { libc::writev(fd, iovecs.as_ptr(), iovecs.len() as libc::c_int) };
+        if size >= 0 {
+            return Ok(size as usize);
+        }
+        let e = Error::last_os_error();
+        if e.kind() != ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+impl NetBackend for Tap {
+    fn recv(&mut self, iovecs: &[libc::iovec]) -> IoResult<usize> {
+        // SAFETY: the arguments of readv has been checked and is correct.
+        let size = unsafe {
+            libc::readv(
+                self.as_raw_fd() as libc::c_int,
+                iovecs.as_ptr(),
+                iovecs.len() as libc::c_int,
+            )
+        };
+        if size >= 0 {
+            return Ok(size as usize);
+        }
+
+        let e = Error::last_os_error();
+        if e.kind() != ErrorKind::WouldBlock {
+            // If the backend tap device is removed, readv returns less than 0.
+            // At this time, the content in the tap needs to be cleaned up.
+            // Here, read is called to process, otherwise handle_rx may be triggered all the time.
+            let mut buf = [0; 1024];
+            match self.read(&mut buf) {
+                Ok(cnt) => error!("Failed to call readv but tap read is ok: cnt {}", cnt),
+                Err(e) => {
+                    // When the backend tap device is abnormally removed, read return EBADFD.
+                    error!("Failed to read tap: {}", e);
+                }
+            }
+            error!("Failed to call readv for net handle_rx: {}", e);
+        }
+        Err(e)
+    }
+
+    fn send(&mut self, iovecs: &[libc::iovec]) -> IoResult<usize> {
+        writev_fd(self.as_raw_fd(), iovecs)
+    }
+
+    fn as_raw_fd(&self) -> RawFd {
+        Tap::as_raw_fd(self)
+    }
+
+    fn has_vnet_hdr(&self) -> bool {
+        true
+    }
+
+    fn has_ufo(&self) -> bool {
+        Tap::has_ufo(self)
+    }
+
+    fn set_offload(&self, flags: u32) -> Result<()> {
+        Tap::set_offload(self, flags)
+    }
+
+    fn clone_box(&self) -> Box<dyn NetBackend> {
+        Box::new(self.clone())
+    }
+}
+
+/// Backend over the seqpacket socket connected with the userspace network, each
+/// message is a frame with the virtio net header.
+pub struct SeqpacketBackend {
+    file: File,
+}
+
+impl SeqpacketBackend {
+    pub fn new(file: File) -> Self {
+        SeqpacketBackend { file }
+    }
+}
+
+impl NetBackend for SeqpacketBackend {
+    fn recv(&mut self, iovecs: &[libc::iovec]) -> IoResult<usize> {
+        loop {
+            // SAFETY: the arguments of readv has been checked and is correct.
+            let size = unsafe {
+                libc::readv(
+                    self.file.as_raw_fd(),
+                    iovecs.as_ptr(),
+                    iovecs.len() as libc::c_int,
+                )
+            };
+            if size >= 0 {
+                return Ok(size as usize);
+            }
+            let e = Error::last_os_error();
+            if e.kind() != ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+    }
+
+    fn send(&mut self, iovecs: &[libc::iovec]) -> IoResult<usize> {
+        writev_fd(self.file.as_raw_fd(), iovecs)
+    }
+
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+
+    /// The header is carried but ignored by the userspace network.
+    fn has_vnet_hdr(&self) -> bool {
+        false
+    }
+
+    fn clone_box(&self) -> Box<dyn NetBackend> {
+        Box::new(SeqpacketBackend {
+            file: self.file.try_clone().unwrap(),
+        })
+    }
+}
+
+/// Address of the socket backends.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NetSocketAddr {
+    Inet { host: String, port: u16 },
+    Unix { path: String },
+}
+
+impl FromStr for NetSocketAddr {
+    type Err = anyhow::Error;
+
+    /// Parse the inet address with format `[host]:port`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (host, port) = s
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow!("Invalid socket address {}, expected [host]:port", s))?;
+        let port = port
+            .parse::<u16>()
+            .map_err(|_| anyhow!("Invalid port of socket address {}", s))?;
+        Ok(NetSocketAddr::Inet {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl NetSocketAddr {
+    /// Resolve the inet address, the empty host means any address.
+    fn inet_addr(host: &str, port: u16) -> Result<SocketAddr> {
+        let host = if host.is_empty() { "0.0.0.0" } else { host };
+        (host, port)
+            .to_socket_addrs()
+            .with_context(|| format!("Failed to resolve address {}:{}", host, port))?
+            .next()
+            .ok_or_else(|| anyhow!("No address found for {}:{}", host, port))
+    }
+
+    /// Returns the group if it is an ipv4 multicast address.
+    pub fn multicast_group(&self) -> Option<Ipv4Addr> {
+        match self {
+            NetSocketAddr::Inet { host, .. } => host
+                .parse::<Ipv4Addr>()
+                .ok()
+                .filter(|addr| addr.is_multicast()),
+            NetSocketAddr::Unix { .. } => None,
+        }
+    }
+
+    fn check(&self) -> Result<()> {
+        if let NetSocketAddr::Unix { path } = self {
+            if path.is_empty() {
+                bail!("The path of unix socket address is empty");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Config of the socket backends.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NetSocketConfig {
+    /// Listen on (`server` is true) or connect to the stream socket `addr`.
+    /// The client connects again every `reconnect` seconds after the
+    /// connection is closed, 0 means never.
+    Stream {
+        server: bool,
+        addr: NetSocketAddr,
+        reconnect: u64,
+    },
+    /// Receive the datagrams on `local` and send to `remote`. If `remote` is
+    /// an ipv4 multicast group, `local` is the address of the interface which
+    /// joins the group.
+    Dgram {
+        local: Option<NetSocketAddr>,
+        remote: Option<NetSocketAddr>,
+    },
+}
+
+impl NetSocketConfig {
+    pub fn check(&self) -> Result<()> {
+        match self {
+            NetSocketConfig::Stream {
+                server,
+                addr,
+                reconnect,
+            } => {
+                if *server && *reconnect != 0 {
+                    bail!("Argument \'reconnect\' is only supported by the client of stream");
+                }
+                addr.check()
+            }
+            NetSocketConfig::Dgram { local, remote } => {
+                let remote = remote
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("The remote address of dgram netdev is missing"))?;
+                remote.check()?;
+                if remote.multicast_group().is_some() {
+                    if let Some(NetSocketAddr::Unix { .. }) = local {
+                        bail!("Unix local address is not supported by multicast");
+                    }
+                    return Ok(());
+                }
+                let local = local
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("The local address of dgram netdev is missing"))?;
+                local.check()?;
+                if std::mem::discriminant(local) != std::mem::discriminant(remote) {
+                    bail!("The local and remote address of dgram netdev have different types");
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Create the socket backend. The server of stream socket waits for the
+/// connection of the peer here, and accepts the new connection after the
+/// old one is closed.
+///
+/// # Arguments
+///
+/// * `config` - Config of the socket backend.
+/// * `hdr_len` - Length of the virtio net header.
+pub fn create_socket_backend(
+    config: &NetSocketConfig,
+    hdr_len: usize,
+) -> Result<Box<dyn NetBackend>> {
+    config.check()?;
+    match config {
+        NetSocketConfig::Stream {
+            server,
+            addr,
+            reconnect,
+        } => {
+            let (file, reconnect) = if *server {
+                let listener = StreamListener::bind(addr)?;
+                let file = listener
+                    .accept()
+                    .with_context(|| "Failed to accept connection of net stream backend")?;
+                listener
+                    .set_nonblocking()
+                    .with_context(|| "Failed to set stream listener nonblocking")?;
+                (file, StreamReconnect::Accept(listener))
+            } else if *reconnect != 0 {
+                let timer = TimerFd::new().with_context(|| "Failed to create timer")?;
+                // SAFETY: the fd is the timer created above.
+                unsafe { libc::fcntl(timer.as_raw_fd(), libc::F_SETFL, libc::O_NONBLOCK) };
+                (
+                    stream_connect(addr)?,
+                    StreamReconnect::Connect(addr.clone(), Duration::from_secs(*reconnect), timer),
+                )
+            } else {
+                (stream_connect(addr)?, StreamReconnect::None)
+            };
+            Ok(Box::new(StreamBackend::new(file, reconnect, hdr_len)?))
+        }
+        NetSocketConfig::Dgram { local, remote } => {
+            let socket = dgram_open(local.as_ref(), remote.as_ref().unwrap())?;
+            Ok(Box::new(DgramBackend::new(socket, hdr_len)))
+        }
+    }
+}
+
+/// Remove the stale unix socket file before binding.
+fn unlink_unix_socket(path: &str) -> Result<()> {
+    if let Ok(meta) = std::fs::metadata(path) {
+        if !meta.file_type().is_socket() {
+            bail!("{} exists and is not a unix socket", path);
+        }
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove unix socket {}", path))?;
+    }
+    Ok(())
+}
+
+/// Listener of the stream server.
+enum StreamListener {
+    Tcp(TcpListener),
+    /// Unix listener and its path.
+    Unix(UnixListener, String),
+}
+
+impl StreamListener {
+    fn bind(addr: &NetSocketAddr) -> Result<Self> {
+        match addr {
+            NetSocketAddr::Inet { host, port } => {
+                let addr = NetSocketAddr::inet_addr(host, *port)?;
+                let listener = TcpListener::bind(addr)
+                    .with_context(|| format!("Failed to listen on {}", addr))?;
+                info!("Net stream backend is waiting for connection on {}", addr);
+                Ok(StreamListener::Tcp(listener))
+            }
+            NetSocketAddr::Unix { path } => {
+                unlink_unix_socket(path)?;
+                let listener = UnixListener::bind(path)
+                    .with_context(|| format!("Failed to listen on {}", path))?;
+                info!("Net stream backend is waiting for connection on {}", path);
+                Ok(StreamListener::Unix(listener, path.clone()))
+            }
+        }
+    }
+
+    fn accept(&self) -> IoResult<File> {
+        let fd = match self {
+            StreamListener::Tcp(listener) => {
+                let (stream, peer) = listener.accept()?;
+                info!("Net stream backend is connected by {}", peer);
+                stream.set_nodelay(true)?;
+                stream.set_nonblocking(true)?;
+                stream.into_raw_fd()
+            }
+            StreamListener::Unix(listener, path) => {
+                let (stream, _) = listener.accept()?;
+                info!("Net stream backend is connected on {}", path);
+                stream.set_nonblocking(true)?;
+                stream.into_raw_fd()
+            }
+        };
+        // SAFETY: the fd is just taken from the connected stream.
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+
+    fn set_nonblocking(&self) -> IoResult<()> {
+        match self {
+            StreamListener::Tcp(listener) => listener.set_nonblocking(true),
+            StreamListener::Unix(listener, _) => listener.set_nonblocking(true),
+        }
+    }
+
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            StreamListener::Tcp(listener) => listener.as_raw_fd(),
+            StreamListener::Unix(listener, _) => listener.as_raw_fd(),
+        }
+    }
+}
+
+fn stream_connect(addr: &NetSocketAddr) -> Result<File> {
+    let fd = match addr {
+        NetSocketAddr::Inet { host, port } => {
+            let addr = NetSocketAddr::inet_addr(host, *port)?;
+            let stream =
+                TcpStream::connect(addr).with_context(|| format!("Failed to connect {}", addr))?;
+            stream
+                .set_nodelay(true)
+                .with_context(|| "Failed to set tcp nodelay")?;
+            stream
+                .set_nonblocking(true)
+                .with_context(|| "Failed to set stream socket nonblocking")?;
+            stream.into_raw_fd()
+        }
+        NetSocketAddr::Unix { path } => {
+            let stream =
+                UnixStream::connect(path).with_context(|| format!("Failed to connect {}", path))?;
+            stream
+                .set_nonblocking(true)
+                .with_context(|| "Failed to set stream socket nonblocking")?;
+            stream.into_raw_fd()
+        }
+    };
+    // SAFETY: the fd is just taken from the connected stream.
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Bind the udp socket with `SO_REUSEADDR`, so that several VMs on the same
+/// host can join the multicast group.
+fn bind_reuse_addr(addr: SocketAddrV4) -> Result<UdpSocket> {
+    // SAFETY: the arguments of socket is valid.
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        bail!("Failed to create udp socket: {}", Error::last_os_error());
+    }
+    // SAFETY: fd is the new created socket which is owned here.
+    let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+    let reuse: libc::c_int = 1;
+    // SAFETY: the option value is valid and the length is correct.
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_REUSEADDR,
+            &reuse as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        bail!("Failed to set SO_REUSEADDR: {}", Error::last_os_error());
+    }
+    let sockaddr = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: addr.port().to_be(),
+        sin_addr: libc::in_addr {
+            s_addr: u32::from(*addr.ip()).to_be(),
+        },
+        sin_zero: [0; 8],
+    };
+    // SAFETY: sockaddr is valid and the length is correct.
+    let ret = unsafe {
+        libc::bind(
+            fd,
+            &sockaddr as *const libc::sockaddr_in as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        bail!("Failed to bind {}: {}", addr, Error::last_os_error());
+    }
+    Ok(socket)
+}
+
+fn dgram_open(local: Option<&NetSocketAddr>, remote: &NetSocketAddr) -> Result<DgramSocket> {
+    if let Some(group) = remote.multicast_group() {
+        let port = match remote {
+            NetSocketAddr::Inet { port, .. } => *port,
+            NetSocketAddr::Unix { .. } => unreachable!(),
+        };
+        let iface = match local {
+            Some(NetSocketAddr::Inet { host, .. }) if !host.is_empty() => host
+                .parse::<Ipv4Addr>()
+                .map_err(|_| anyhow!("Invalid multicast interface address {}", host))?,
+            _ => Ipv4Addr::UNSPECIFIED,
+        };
+        let socket = bind_reuse_addr(SocketAddrV4::new(group, port))?;
+        socket
+            .join_multicast_v4(&group, &iface)
+            .with_context(|| format!("Failed to join multicast group {}", group))?;
+        // Other VMs on the same host receive the frames through loopback.
+        socket
+            .set_multicast_loop_v4(true)
+            .with_context(|| "Failed to enable multicast loop")?;
+        if !iface.is_unspecified() {
+            let addr = libc::in_addr {
+                s_addr: u32::from(iface).to_be(),
+            };
+            // SAFETY: the option value is valid and the length is correct.
+            let ret = unsafe {
+                libc::setsockopt(
+                    socket.as_raw_fd(),
+                    libc::IPPROTO_IP,
+                    libc::IP_MULTICAST_IF,
+                    &addr as *const libc::in_addr as *const libc::c_void,
+                    std::mem::size_of::<libc::in_addr>() as libc::socklen_t,
+                )
+            };
+            if ret < 0 {
+                bail!(
+                    "Failed to set multicast interface {}: {}",
+                    iface,
+                    Error::last_os_error()
+                );
+            }
+        }
+        socket
+            .set_nonblocking(true)
+            .with_context(|| "Failed to set dgram socket nonblocking")?;
+        return Ok(DgramSocket::Udp(
+            socket,
+            SocketAddr::V4(SocketAddrV4::new(group, port)),
+        ));
+    }
+
+    match (local, remote) {
+        (
+            Some(NetSocketAddr::Inet { host, port }),
+            NetSocketAddr::Inet {
+                host: remote_host,
+                port: remote_port,
+            },
+        ) => {
+            let local = NetSocketAddr::inet_addr(host, *port)?;
+            let remote = NetSocketAddr::inet_addr(remote_host, *remote_port)?;
+            let socket =
+                UdpSocket::bind(local).with_context(|| format!("Failed to bind {}", local))?;
+            socket
+                .set_nonblocking(true)
+                .with_context(|| "Failed to set dgram socket nonblocking")?;
+            Ok(DgramSocket::Udp(socket, remote))
+        }
+        (Some(NetSocketAddr::Unix { path }), NetSocketAddr::Unix { path: remote_path }) => {
+            unlink_unix_socket(path)?;
+            let socket =
+                UnixDatagram::bind(path).with_context(|| format!("Failed to bind {}", path))?;
+            socket
+                .set_nonblocking(true)
+                .with_context(|| "Failed to set dgram socket nonblocking")?;
+            Ok(DgramSocket::Unix(socket, PathBuf::from(remote_path)))
+        }
+        _ => bail!("Invalid local and remote address of dgram netdev"),
+    }
+}
+
+/// Copy the frame into `iovecs` after a virtio net header without any
+/// offload. Returns the copied length including the header.
+fn frame_to_iovecs(iovecs: &[libc::iovec], hdr_len: usize, frame: &[u8]) -> usize {
+    let mut hdr = vec![0_u8; hdr_len];
+    if hdr_len >= VNET_HDR_NUM_BUFFERS_OFFSET + 2 {
+        hdr[VNET_HDR_NUM_BUFFERS_OFFSET..VNET_HDR_NUM_BUFFERS_OFFSET + 2]
+            .copy_from_slice(&1_u16.to_le_bytes());
+    }
+    let padding = vec![0_u8; MIN_FRAME_LEN.saturating_sub(frame.len())];
+    let mut copied = 0;
+    let mut iovecs = iovecs.iter();
+    let mut iov_off = 0;
+    let mut cur = iovecs.next();
+    for mut src in [hdr.as_slice(), frame, padding.as_slice()] {
+        while !src.is_empty() {
+            let iov = match cur {
+                Some(iov) => iov,
+                None => return copied,
+            };
+            let len = cmp::min(src.len(), iov.iov_len - iov_off);
+            // SAFETY: the iovec is the host address of guest memory which has
+            // been checked, and the copied range is inside the iovec.
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    src.as_ptr(),
+                    (iov.iov_base as *mut u8).add(iov_off),
+                    len,
+                )
+            };
+            src = &src[len..];
+            copied += len;
+            iov_off += len;
+            if iov_off == iov.iov_len {
+                cur = iovecs.next();
+                iov_off = 0;
+            }
+        }
+    }
+    copied
+}
+
+/// Gather the frame after the virtio net header from `iovecs`.
+fn iovecs_to_frame(iovecs: &[libc::iovec], hdr_len: usize, frame: &mut Vec<u8>) {
+    frame.clear();
+    let mut skip = hdr_len;
+    for iov in iovecs {
+        if skip >= iov.iov_len {
+            skip -= iov.iov_len;
+            continue;
+        }
+        // SAFETY: the iovec is the host address of guest memory which has been checked.
+        let data = unsafe {
+            std::slice::from_raw_parts((iov.iov_base as *const u8).add(skip), iov.iov_len - skip)
+        };
+        frame.extend_from_slice(data);
+        skip = 0;
+    }
+}
+
+/// How the stream backend gets a new connection after the old one is closed.
+enum StreamReconnect {
+    /// The server accepts the connection on the listener.
+    Accept(StreamListener),
+    /// The client connects the address again every interval.
+    Connect(NetSocketAddr, Duration, TimerFd),
+    None,
+}
+
+struct StreamState {
+    /// The connected socket, None after the connection is closed.
+    file: Option<File>,
+    /// Length prefix of the receiving frame.
+    rx_len: [u8; STREAM_LEN_SIZE],
+    rx_len_read: usize,
+    rx_frame: Vec<u8>,
+    rx_frame_read: usize,
+    /// Data which is not written to the socket yet.
+    tx_pending: Vec<u8>,
+    tx_written: usize,
+    /// Polls the connected socket, or the listener and the timer of reconnection
+    /// after the connection is closed, so the fd of backend is kept unchanged.
+    epoll: Epoll,
+    reconnect: StreamReconnect,
+}
+
+impl StreamState {
+    /// Exchange the frames on the new connection.
+    fn connect(&mut self, file: File) -> Result<()> {
+        self.epoll
+            .ctl(
+                ControlOperation::Add,
+                file.as_raw_fd(),
+                EpollEvent::new(EventSet::IN, 0),
+            )
+            .with_context(|| "Failed to poll the stream socket")?;
+        self.file = Some(file);
+        self.rx_len_read = 0;
+        self.rx_frame.clear();
+        self.rx_frame_read = 0;
+        self.tx_pending.clear();
+        self.tx_written = 0;
+        Ok(())
+    }
+
+    fn close(&mut self, reason: &str) {
+        // The socket is removed from epoll when it is dropped.
+        if self.file.take().is_none() {
+            return;
+        }
+        error!("Net stream backend is closed: {}", reason);
+        let ret = match &mut self.reconnect {
+            StreamReconnect::Accept(listener) => self
+                .epoll
+                .ctl(
+                    ControlOperation::Add,
+                    listener.as_raw_fd(),
+                    EpollEvent::new(EventSet::IN, 0),
+                )
+                .with_context(|| "Failed to poll the stream listener"),
+            StreamReconnect::Connect(_, interval, timer) => {
+                info!(
+                    "Net stream backend reconnects every {} seconds",
+                    interval.as_secs()
+                );
+                timer
+                    .reset(*interval, Some(*interval))
+                    .with_context(|| "Failed to start the reconnection timer")
+            }
+            StreamReconnect::None => Ok(()),
+        };
+        if let Err(e) = ret {
+            error!("Net stream backend can not reconnect: {:?}", e);
+        }
+    }
+
+    /// Try to get a new connection after the connection is closed.
+    fn reconnect(&mut self) {
+        let ret = match &mut self.reconnect {
+            StreamReconnect::Accept(listener) => match listener.accept() {
+                Ok(file) => self
+                    .epoll
+                    .ctl(
+                        ControlOperation::Delete,
+                        listener.as_raw_fd(),
+                        EpollEvent::default(),
+                    )
+                    .with_context(|| "Failed to stop polling the stream listener")
+                    .map(|_| file),
+                Err(e) => {
+                    if e.kind() != ErrorKind::WouldBlock {
+                        error!("Failed to accept connection of net stream backend: {}", e);
+                    }
+                    return;
+                }
+            },
+            StreamReconnect::Connect(addr, _, timer) => {
+                // The timer is not expired.
+                if timer.wait().is_err() {
+                    return;
+                }
+                match stream_connect(addr) {
+                    Ok(file) => timer
+                        .clear()
+                        .with_context(|| "Failed to stop the reconnection timer")
+                        .map(|_| file),
+                    Err(_) => return,
+                }
+            }
+            StreamReconnect::None => return,
+        };
+        if let Err(e) = ret.and_then(|file| self.connect(file)) {
+            error!("Net stream backend can not reconnect: {:?}", e);
+        }
+    }
+
+    /// Read into `buf`, returns None if no data is read.
+    fn read_some(&mut self, range: std::ops::Range<usize>, len: bool) -> IoResult<Option<usize>> {
+        let buf = if len {
+            &mut self.rx_len[range]
+        } else {
+            &mut self.rx_frame[range]
+        };
+        let mut file = match self.file.as_ref() {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        loop {
+            match file.read(buf) {
+                Ok(0) => {
+                    self.close("connection closed by peer");
+                    return Ok(None);
+                }
+                Ok(n) => return Ok(Some(n)),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        let mut file = match self.file.as_ref() {
+            Some(file) => file,
+            None => return Err(Error::from(ErrorKind::NotConnected)),
+        };
+        while self.tx_written < self.tx_pending.len() {
+            match file.write(&self.tx_pending[self.tx_written..]) {
+                Ok(0) => return Err(Error::from(ErrorKind::WriteZero)),
+                Ok(n) => self.tx_written += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        self.tx_pending.clear();
+        self.tx_written = 0;
+        Ok(())
+    }
+}
+
+/// Backend over the stream socket, each frame is prefixed with its length.
+pub struct StreamBackend {
+    state: Arc<Mutex<StreamState>>,
+    hdr_len: usize,
+}
+
+impl StreamBackend {
+    fn new(file: File, reconnect: StreamReconnect, hdr_len: usize) -> Result<Self> {
+        let mut state = StreamState {
+            file: None,
+            rx_len: [0; STREAM_LEN_SIZE],
+            rx_len_read: 0,
+            rx_frame: Vec::new(),
+            rx_frame_read: 0,
+            tx_pending: Vec::new(),
+            tx_written: 0,
+            epoll: Epoll::new().with_context(|| "Failed to create epoll")?,
+            reconnect,
+        };
+        if let StreamReconnect::Connect(_, _, timer) = &state.reconnect {
+            state
+                .epoll
+                .ctl(
+                    ControlOperation::Add,
+                    timer.as_raw_fd(),
+                    EpollEvent::new(EventSet::IN, 0),
+                )
+                .with_context(|| "Failed to poll the reconnection timer")?;
+        }
+        state.connect(file)?;
+        Ok(StreamBackend {
+            state: Arc::new(Mutex::new(state)),
+            hdr_len,
+        })
+    }
+}
+
+impl NetBackend for StreamBackend {
+    fn recv(&mut self, iovecs: &[libc::iovec]) -> IoResult<usize> {
+        let mut state = self.state.lock().unwrap();
+        // Only the needed bytes are read, so that the left data in the socket
+        // triggers the edge-triggered event again.
+        loop {
+            if state.file.is_none() {
+                state.reconnect();
+                if state.file.is_none() {
+                    return Err(Error::from(ErrorKind::WouldBlock));
+                }
+            }
+            if state.rx_len_read < STREAM_LEN_SIZE {
+                let start = state.rx_len_read;
+                let n = match state.read_some(start..STREAM_LEN_SIZE, true) {
+                    Ok(Some(n)) => n,
+                    Ok(None) => continue,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => return Err(e),
+                    Err(e) => {
+                        state.close(&e.to_string());
+                        continue;
+                    }
+                };
+                state.rx_len_read += n;
+                if state.rx_len_read == STREAM_LEN_SIZE {
+                    let len = u32::from_be_bytes(state.rx_len) as usize;
+                    if len > MAX_FRAME_LEN {
+                        state.close(&format!("invalid frame length {}", len));
+                        continue;
+                    }
+                    state.rx_frame.resize(len, 0);
+                    state.rx_frame_read = 0;
+                }
+                continue;
+            }
+
+            if state.rx_frame_read < state.rx_frame.len() {
+                let range = state.rx_frame_read..state.rx_frame.len();
+                let n = match state.read_some(range, false) {
+                    Ok(Some(n)) => n,
+                    Ok(None) => continue,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => return Err(e),
+                    Err(e) => {
+                        state.close(&e.to_string());
+                        continue;
+                    }
+                };
+                state.rx_frame_read += n;
+                continue;
+            }
+
+            state.rx_len_read = 0;
+            if state.rx_frame.is_empty() {
+                continue;
+            }
+            return Ok(frame_to_iovecs(iovecs, self.hdr_len, &state.rx_frame));
+        }
+    }
+
+    fn send(&mut self, iovecs: &[libc::iovec]) -> IoResult<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.file.is_some() {
+            match state.flush() {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Err(e),
+                Err(e) => state.close(&e.to_string()),
+            }
+        }
+
+        let mut frame = Vec::new();
+        iovecs_to_frame(iovecs, self.hdr_len, &mut frame);
+        // Frames are dropped silently after the connection is closed.
+        if state.file.is_none() || frame.is_empty() {
+            return Ok(frame.len());
+        }
+        state
+            .tx_pending
+            .extend_from_slice(&(frame.len() as u32).to_be_bytes());
+        state.tx_pending.extend_from_slice(&frame);
+        // The frame is accepted even if it is partially written, the rest is
+        // sent before the next frame.
+        match state.flush() {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => state.close(&e.to_string()),
+        }
+        Ok(frame.len())
+    }
+
+    fn as_raw_fd(&self) -> RawFd {
+        self.state.lock().unwrap().epoll.as_raw_fd()
+    }
+
+    fn has_vnet_hdr(&self) -> bool {
+        false
+    }
+
+    fn clone_box(&self) -> Box<dyn NetBackend> {
+        Box::new(StreamBackend {
+            state: self.state.clone(),
+            hdr_len: self.hdr_len,
+        })
+    }
+}
+
+enum DgramSocket {
+    /// Udp socket and the destination address.
+    Udp(UdpSocket, SocketAddr),
+    /// Unix datagram socket and the destination path.
+    Unix(UnixDatagram, PathBuf),
+}
+
+impl DgramSocket {
+    fn try_clone(&self) -> IoResult<Self> {
+        Ok(match self {
+            DgramSocket::Udp(socket, remote) => DgramSocket::Udp(socket.try_clone()?, *remote),
+            DgramSocket::Unix(socket, remote) => {
+                DgramSocket::Unix(socket.try_clone()?, remote.clone())
+            }
+        })
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> IoResult<usize> {
+        match self {
+            DgramSocket::Udp(socket, _) => socket.recv(buf),
+            DgramSocket::Unix(socket, _) => socket.recv(buf),
+        }
+    }
+
+    fn send(&self, buf: &[u8]) -> IoResult<usize> {
+        match self {
+            DgramSocket::Udp(socket, remote) => socket.send_to(buf, remote),
+            DgramSocket::Unix(socket, remote) => socket.send_to(buf, Path::new(remote)),
+        }
+    }
+
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            DgramSocket::Udp(socket, _) => socket.as_raw_fd(),
+            DgramSocket::Unix(socket, _) => socket.as_raw_fd(),
+        }
+    }
+}
+
+/// Backend over the datagram socket, one frame per datagram.
+pub struct DgramBackend {
+    socket: DgramSocket,
+    hdr_len: usize,
+    buf: Vec<u8>,
+}
+
+impl DgramBackend {
+    fn new(socket: DgramSocket, hdr_len: usize) -> Self {
+        DgramBackend {
+            socket,
+            hdr_len,
+            buf: Vec::new(),
+        }
+    }
+}
+
+impl NetBackend for DgramBackend {
+    fn recv(&mut self, iovecs: &[libc::iovec]) -> IoResult<usize> {
+        self.buf.resize(MAX_FRAME_LEN, 0);
+        loop {
+            let len = match self.socket.recv(&mut self.buf) {
+                Ok(len) => len,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if len == 0 {
+                continue;
+            }
+            return Ok(frame_to_iovecs(iovecs, self.hdr_len, &self.buf[..len]));
+        }
+    }
+
+    fn send(&mut self, iovecs: &[libc::iovec]) -> IoResult<usize> {
+        let mut frame = std::mem::take(&mut self.buf);
+        iovecs_to_frame(iovecs, self.hdr_len, &mut frame);
+        let ret = loop {
+            match self.socket.send(&frame) {
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                ret => break ret,
+            }
+        };
+        self.buf = frame;
+        ret
+    }
+
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+
+    fn has_vnet_hdr(&self) -> bool {
+        false
+    }
+
+    fn clone_box(&self) -> Box<dyn NetBackend> {
+        Box::new(DgramBackend {
+            socket: self.socket.try_clone().unwrap(),
+            hdr_len: self.hdr_len,
+            buf: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HDR_LEN: usize = 12;
+
+    fn iovecs_of(bufs: &mut [Vec<u8>]) -> Vec<libc::iovec> {
+        bufs.iter_mut()
+            .map(|buf| libc::iovec {
+                iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buf.len(),
+            })
+            .collect()
+    }
+
+    fn build_frame(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    /// Send `frame` by `tx` and check it is received by `rx`.
+    fn check_transfer(tx: &mut dyn NetBackend, rx: &mut dyn NetBackend, frame: &[u8]) {
+        let mut out = vec![vec![0xff_u8; HDR_LEN], frame.to_vec()];
+        assert_eq!(tx.send(&iovecs_of(&mut out)).unwrap(), frame.len());
+
+        // Split the buffers in the middle of the header.
+        let mut bufs = vec![vec![0_u8; 4], vec![0_u8; MAX_FRAME_LEN]];
+        let iovecs = iovecs_of(&mut bufs);
+        let size = loop {
+            match rx.recv(&iovecs) {
+                Ok(size) => break size,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+                Err(e) => panic!("{}", e),
+            }
+        };
+        let expected_len = frame.len().max(MIN_FRAME_LEN);
+        assert_eq!(size, HDR_LEN + expected_len);
+        let data: Vec<u8> = bufs.concat();
+        assert_eq!(data[..HDR_LEN], [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0]);
+        assert_eq!(&data[HDR_LEN..HDR_LEN + frame.len()], frame);
+        assert!(data[HDR_LEN + frame.len()..size].iter().all(|b| *b == 0));
+    }
+
+    /// Wait until the backend is readable.
+    fn wait_readable(backend: &dyn NetBackend) {
+        let mut fds = [libc::pollfd {
+            fd: backend.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+        // SAFETY: fds is a valid array of 1 element.
+        let ret = unsafe { libc::poll(fds.as_mut_ptr(), 1, 5000) };
+        assert_eq!(ret, 1);
+    }
+
+    /// Exchange the frames between the stream backend and its peer.
+    fn check_stream_peer(backend: &mut dyn NetBackend, peer: &mut UnixStream) {
+        let frame = build_frame(100);
+        let mut out = vec![vec![0_u8; HDR_LEN], frame.clone()];
+        assert_eq!(backend.send(&iovecs_of(&mut out)).unwrap(), 100);
+        let mut buf = [0_u8; STREAM_LEN_SIZE + 100];
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[..STREAM_LEN_SIZE], 100_u32.to_be_bytes());
+        assert_eq!(buf[STREAM_LEN_SIZE..], frame[..]);
+
+        peer.write_all(&buf).unwrap();
+        wait_readable(backend);
+        let mut bufs = vec![vec![0_u8; HDR_LEN + MAX_FRAME_LEN]];
+        assert_eq!(backend.recv(&iovecs_of(&mut bufs)).unwrap(), HDR_LEN + 100);
+        assert_eq!(bufs[0][HDR_LEN..HDR_LEN + 100], frame[..]);
+    }
+
+    /// Drop the peer and wait until the backend finds it.
+    fn drop_stream_peer(backend: &mut dyn NetBackend, peer: UnixStream) {
+        drop(peer);
+        wait_readable(backend);
+        let mut bufs = vec![vec![0_u8; HDR_LEN + MAX_FRAME_LEN]];
+        assert!(backend.recv(&iovecs_of(&mut bufs)).is_err());
+        // Frames are dropped before reconnection.
+        let mut out = vec![vec![0_u8; HDR_LEN], build_frame(64)];
+        assert_eq!(backend.send(&iovecs_of(&mut out)).unwrap(), 64);
+    }
+
+    #[test]
+    fn test_socket_addr_parse() {
+        assert_eq!(
+            "127.0.0.1:1234".parse::<NetSocketAddr>().unwrap(),
+            NetSocketAddr::Inet {
+                host: "127.0.0.1".to_string(),
+                port: 1234
+            }
+        );
+        assert_eq!(
+            ":1234".parse::<NetSocketAddr>().unwrap(),
+            NetSocketAddr::Inet {
+                host: "".to_string(),
+                port: 1234
+            }
+        );
+        assert!("127.0.0.1".parse::<NetSocketAddr>().is_err());
+        assert!("127.0.0.1:port".parse::<NetSocketAddr>().is_err());
+        assert!("127.0.0.1:65536".parse::<NetSocketAddr>().is_err());
+    }
+
+    #[test]
+    fn test_socket_config_check() {
+        let inet = |host: &str, port| NetSocketAddr::Inet {
+            host: host.to_string(),
+            port,
+        };
+        let unix = |path: &str| NetSocketAddr::Unix {
+            path: path.to_string(),
+        };
+
+        let config = NetSocketConfig::Stream {
+            server: true,
+            addr: unix(""),
+            reconnect: 0,
+        };
+        assert!(config.check().is_err());
+        // Only the client reconnects.
+        let config = NetSocketConfig::Stream {
+            server: true,
+            addr: unix("/tmp/a.sock"),
+            reconnect: 1,
+        };
+        assert!(config.check().is_err());
+
+        // Multicast doesn't need the local address.
+        let config = NetSocketConfig::Dgram {
+            local: None,
+            remote: Some(inet("230.0.0.1", 1234)),
+        };
+        assert!(config.check().is_ok());
+        let config = NetSocketConfig::Dgram {
+            local: Some(unix("/tmp/a.sock")),
+            remote: Some(inet("230.0.0.1", 1234)),
+        };
+        assert!(config.check().is_err());
+
+        // Unicast needs the local address of the same type.
+        let config = NetSocketConfig::Dgram {
+            local: None,
+            remote: Some(inet("127.0.0.1", 1234)),
+        };
+        assert!(config.check().is_err());
+        let config = NetSocketConfig::Dgram {
+            local: Some(unix("/tmp/a.sock")),
+            remote: Some(inet("127.0.0.1", 1234)),
+        };
+        assert!(config.check().is_err());
+        let config = NetSocketConfig::Dgram {
+            local: Some(unix("/tmp/a.sock")),
+            remote: Some(unix("/tmp/b.sock")),
+        };
+        assert!(config.check().is_ok());
+        let config = NetSocketConfig::Dgram {
+            local: Some(inet("127.0.0.1", 1234)),
+            remote: None,
+        };
+        assert!(config.check().is_err());
+    }
+
+    #[test]
+    fn test_stream_backend() {
+        let (sock1, sock2) = UnixStream::pair().unwrap();
+        sock1.set_nonblocking(true).unwrap();
+        sock2.set_nonblocking(true).unwrap();
+        // SAFETY: the fds are taken from the new created sockets.
+        let (file1, file2) = unsafe {
+            (
+                File::from_raw_fd(sock1.into_raw_fd()),
+                File::from_raw_fd(sock2.into_raw_fd()),
+            )
+        };
+        let mut peer = file2.try_clone().unwrap();
+        let mut backend1 = StreamBackend::new(file1, StreamReconnect::None, HDR_LEN).unwrap();
+        let mut backend2 = StreamBackend::new(file2, StreamReconnect::None, HDR_LEN).unwrap();
+        assert!(!backend1.has_vnet_hdr());
+
+        check_transfer(&mut backend1, &mut backend2, &build_frame(100));
+        check_transfer(&mut backend2, &mut backend1, &build_frame(1514));
+        // Short frame is padded.
+        check_transfer(&mut backend1, &mut backend2, &build_frame(20));
+
+        // Nothing to receive.
+        let mut bufs = vec![vec![0_u8; HDR_LEN + 1514]];
+        let iovecs = iovecs_of(&mut bufs);
+        let err = backend2.recv(&iovecs).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+
+        // The frame sent by the peer in pieces is received when it is completed.
+        let frame = build_frame(64);
+        peer.write_all(&[0, 0]).unwrap();
+        assert!(backend1.recv(&iovecs).is_err());
+        peer.write_all(&[0, 64]).unwrap();
+        peer.write_all(&frame[..10]).unwrap();
+        assert!(backend1.recv(&iovecs).is_err());
+        peer.write_all(&frame[10..]).unwrap();
+        assert_eq!(backend1.recv(&iovecs).unwrap(), HDR_LEN + 64);
+        assert_eq!(bufs[0][HDR_LEN..HDR_LEN + 64], frame[..]);
+
+        // Frame is dropped after the peer is closed.
+        drop(peer);
+        drop(backend2);
+        assert!(backend1.recv(&iovecs).is_err());
+        let mut out = vec![vec![0_u8; HDR_LEN], build_frame(64)];
+        assert_eq!(backend1.send(&iovecs_of(&mut out)).unwrap(), 64);
+    }
+
+    #[test]
+    fn test_seqpacket_backend() {
+        let mut fds = [0; 2];
+        // SAFETY: fds is a valid array of 2 elements.
+        let ret = unsafe {
+            libc::socketpair(
+                libc::AF_UNIX,
+                libc::SOCK_SEQPACKET | libc::SOCK_NONBLOCK,
+                0,
+                fds.as_mut_ptr(),
+            )
+        };
+        assert_eq!(ret, 0);
+        // SAFETY: the fds are created above and owned by the files.
+        let (file, peer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        let mut backend = SeqpacketBackend::new(file);
+        assert!(!backend.has_vnet_hdr());
+        assert!(!backend.has_ufo());
+
+        // The frame is sent with the header in one message.
+        let mut out = vec![vec![0xff_u8; HDR_LEN], build_frame(100)];
+        assert_eq!(backend.send(&iovecs_of(&mut out)).unwrap(), HDR_LEN + 100);
+        let mut msg = vec![0_u8; MAX_FRAME_LEN];
+        assert_eq!((&peer).read(&mut msg).unwrap(), HDR_LEN + 100);
+        assert_eq!(msg[..HDR_LEN + 100], out.concat()[..]);
+
+        // The message from peer is received as it is.
+        let mut bufs = vec![vec![0_u8; 4], vec![0_u8; MAX_FRAME_LEN]];
+        let iovecs = iovecs_of(&mut bufs);
+        assert_eq!(
+            backend.recv(&iovecs).unwrap_err().kind(),
+            ErrorKind::WouldBlock
+        );
+        (&peer).write_all(&out.concat()).unwrap();
+        assert_eq!(backend.recv(&iovecs).unwrap(), HDR_LEN + 100);
+        assert_eq!(bufs.concat()[..HDR_LEN + 100], out.concat()[..]);
+    }
+
+    #[test]
+    fn test_stream_backend_reconnect() {
+        let path = format!("/tmp/stream-client-{}.sock", std::process::id());
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let config = NetSocketConfig::Stream {
+            server: false,
+            addr: NetSocketAddr::Unix { path: path.clone() },
+            reconnect: 1,
+        };
+        let mut backend = create_socket_backend(&config, HDR_LEN).unwrap();
+        let fd = backend.as_raw_fd();
+        let (mut peer, _) = listener.accept().unwrap();
+        check_stream_peer(backend.as_mut(), &mut peer);
+
+        // The client connects again after the interval.
+        drop_stream_peer(backend.as_mut(), peer);
+        wait_readable(backend.as_ref());
+        let mut bufs = vec![vec![0_u8; HDR_LEN + MAX_FRAME_LEN]];
+        let err = backend.recv(&iovecs_of(&mut bufs)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+        let (mut peer, _) = listener.accept().unwrap();
+        check_stream_peer(backend.as_mut(), &mut peer);
+        assert_eq!(backend.as_raw_fd(), fd);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_stream_backend_reaccept() {
+        let path = format!("/tmp/stream-server-{}.sock", std::process::id());
+        let _ = std::fs::remove_file(&path);
+        let config = NetSocketConfig::Stream {
+            server: true,
+            addr: NetSocketAddr::Unix { path: path.clone() },
+            reconnect: 0,
+        };
+        let peer_path = path.clone();
+        let connector = std::thread::spawn(move || loop {
+            if let Ok(peer) = UnixStream::connect(&peer_path) {
+                return peer;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        });
+        let mut backend = create_socket_backend(&config, HDR_LEN).unwrap();
+        let fd = backend.as_raw_fd();
+        let mut peer = connector.join().unwrap();
+        check_stream_peer(backend.as_mut(), &mut peer);
+
+        // The server accepts the new connection.
+        drop_stream_peer(backend.as_mut(), peer);
+        let mut peer = UnixStream::connect(&path).unwrap();
+        wait_readable(backend.as_ref());
+        let mut bufs = vec![vec![0_u8; HDR_LEN + MAX_FRAME_LEN]];
+        let err = backend.recv(&iovecs_of(&mut bufs)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+        check_stream_peer(backend.as_mut(), &mut peer);
+        assert_eq!(backend.as_raw_fd(), fd);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_dgram_backend() {
+        let socket1 = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket2 = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket1.set_nonblocking(true).unwrap();
+        socket2.set_nonblocking(true).unwrap();
+        let addr1 = socket1.local_addr().unwrap();
+        let addr2 = socket2.local_addr().unwrap();
+        let mut backend1 = DgramBackend::new(DgramSocket::Udp(socket1, addr2), HDR_LEN);
+        let mut backend2 = DgramBackend::new(DgramSocket::Udp(socket2, addr1), HDR_LEN);
+        assert!(!backend1.has_vnet_hdr());
+
+        check_transfer(&mut backend1, &mut backend2, &build_frame(100));
+        check_transfer(&mut backend2, &mut backend1, &build_frame(1514));
+        let mut cloned = backend1.clone_box();
+        check_transfer(cloned.as_mut(), &mut backend2, &build_frame(30));
+
+        let mut bufs = vec![vec![0_u8; HDR_LEN + 1514]];
+        let err = backend2.recv(&iovecs_of(&mut bufs)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn test_frame_truncated() {
+        let frame = build_frame(100);
+        let mut bufs = vec![vec![0_u8; HDR_LEN], vec![0_u8; 50]];
+        let iovecs = iovecs_of(&mut bufs);
+        assert_eq!(frame_to_iovecs(&iovecs, HDR_LEN, &frame), HDR_LEN + 50);
+        assert_eq!(bufs[1][..], frame[..50]);
+
+        let mut out = Vec::new();
+        iovecs_to_frame(&iovecs[..1], HDR_LEN, &mut out);
+        assert!(out.is_empty());
+    }
+}
@@ -62,6 +62,29 @@ pub fn round_down(origin: u64, align: u64) -> Option<u64> {
     }
 }
 
+/// Calculate the quotient of u64 division rounded up.
+///
+/// # Arguments
+///
+/// * `dividend` - the dividend.
+/// * `divisor` - the divisor, which must not be zero.
+///
+/// # Examples
+///
+/// ```rust
+/// extern crate util;
+/// use util::num_ops::div_round_up;
+///
+/// let value = div_round_up(1003 as u64, 4 as u64);
+/// assert!(value == 251);
+/// ```
+pub fn div_round_up(dividend: u64, divisor: u64) -> u64 {
+    match dividend % divisor {
+        0 => dividend / divisor,
+        _ => dividend / divisor + 1,
+    }
+}
+
 /// Get the first half or second half of u64.
 ///
 /// # Arguments
@@ -425,6 +448,13 @@ mod test {
         assert_eq!(result, Some(10100));
     }
 
+    #[test]
+    fn div_round_up_test() {
+        assert_eq!(div_round_up(10001, 100), 101);
+        assert_eq!(div_round_up(10000, 100), 100);
+        assert_eq!(div_round_up(0, 100), 0);
+    }
+
     #[test]
     fn round_down_test() {
         let result = round_down(10001 as u64, 100 as u64);
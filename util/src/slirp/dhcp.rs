@@ -0,0 +1,293 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Minimal DHCP server which leases the addresses from `dhcp_start`.
+
+use std::net::Ipv4Addr;
+
+use super::packet::MAC_ADDR_LEN;
+use super::SlirpConfig;
+
+pub const DHCP_SERVER_PORT: u16 = 67;
+pub const DHCP_CLIENT_PORT: u16 = 68;
+
+const BOOTP_REQUEST: u8 = 1;
+const BOOTP_REPLY: u8 = 2;
+/// Fixed part of the BOOTP message before the magic cookie.
+const BOOTP_HDR_LEN: usize = 236;
+const DHCP_MAGIC: [u8; 4] = [99, 130, 83, 99];
+const DHCP_MIN_LEN: usize = 300;
+
+const DHCP_OPT_PAD: u8 = 0;
+const DHCP_OPT_SUBNET_MASK: u8 = 1;
+const DHCP_OPT_ROUTER: u8 = 3;
+const DHCP_OPT_DNS: u8 = 6;
+const DHCP_OPT_REQUESTED_IP: u8 = 50;
+const DHCP_OPT_LEASE_TIME: u8 = 51;
+const DHCP_OPT_MSG_TYPE: u8 = 53;
+const DHCP_OPT_SERVER_ID: u8 = 54;
+const DHCP_OPT_END: u8 = 255;
+
+const DHCP_DISCOVER: u8 = 1;
+const DHCP_OFFER: u8 = 2;
+const DHCP_REQUEST: u8 = 3;
+const DHCP_ACK: u8 = 5;
+const DHCP_NAK: u8 = 6;
+
+const DHCP_LEASE_TIME: u32 = 86400;
+/// Max number of the leased addresses.
+const DHCP_LEASE_NUM: usize = 16;
+
+/// DHCP message sent by the client.
+struct DhcpRequest {
+    xid: [u8; 4],
+    flags: [u8; 2],
+    ciaddr: Ipv4Addr,
+    chaddr: [u8; MAC_ADDR_LEN],
+    msg_type: u8,
+    requested_ip: Option<Ipv4Addr>,
+}
+
+impl DhcpRequest {
+    fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < BOOTP_HDR_LEN + DHCP_MAGIC.len()
+            || buf[0] != BOOTP_REQUEST
+            || buf[BOOTP_HDR_LEN..BOOTP_HDR_LEN + 4] != DHCP_MAGIC
+        {
+            return None;
+        }
+        let mut xid = [0_u8; 4];
+        xid.copy_from_slice(&buf[4..8]);
+        let mut chaddr = [0_u8; MAC_ADDR_LEN];
+        chaddr.copy_from_slice(&buf[28..28 + MAC_ADDR_LEN]);
+
+        let mut msg_type = 0;
+        let mut requested_ip = None;
+        let options = &buf[BOOTP_HDR_LEN + 4..];
+        let mut i = 0;
+        while i < options.len() {
+            match options[i] {
+                DHCP_OPT_END => break,
+                DHCP_OPT_PAD => i += 1,
+                code => {
+                    if i + 1 >= options.len() {
+                        break;
+                    }
+                    let len = options[i + 1] as usize;
+                    let val = match options.get(i + 2..i + 2 + len) {
+                        Some(val) => val,
+                        None => break,
+                    };
+                    if code == DHCP_OPT_MSG_TYPE && len == 1 {
+                        msg_type = val[0];
+                    } else if code == DHCP_OPT_REQUESTED_IP && len == 4 {
+                        requested_ip = Some(Ipv4Addr::new(val[0], val[1], val[2], val[3]));
+                    }
+                    i += 2 + len;
+                }
+            }
+        }
+
+        Some(DhcpRequest {
+            xid,
+            flags: [buf[10], buf[11]],
+            ciaddr: Ipv4Addr::new(buf[12], buf[13], buf[14], buf[15]),
+            chaddr,
+            msg_type,
+            requested_ip,
+        })
+    }
+}
+
+/// Reply of the DHCP server.
+pub struct DhcpReply {
+    pub data: Vec<u8>,
+    /// Address assigned to the client, None for NAK.
+    pub yiaddr: Option<Ipv4Addr>,
+    pub chaddr: [u8; MAC_ADDR_LEN],
+}
+
+#[derive(Default)]
+pub struct DhcpServer {
+    /// MAC address and the leased IPv4 address.
+    leases: Vec<([u8; MAC_ADDR_LEN], Ipv4Addr)>,
+}
+
+impl DhcpServer {
+    fn lease_addr(&mut self, config: &SlirpConfig, mac: &[u8; MAC_ADDR_LEN]) -> Option<Ipv4Addr> {
+        if let Some((_, addr)) = self.leases.iter().find(|(m, _)| m == mac) {
+            return Some(*addr);
+        }
+        let start = u32::from(config.dhcp_start);
+        let addr = (0..DHCP_LEASE_NUM as u32)
+            .map(|i| Ipv4Addr::from(start + i))
+            .filter(|addr| config.in_net(*addr) && *addr != config.host && *addr != config.dns)
+            .find(|addr| !self.leases.iter().any(|(_, a)| a == addr))?;
+        self.leases.push((*mac, addr));
+        Some(addr)
+    }
+
+    /// Handle the DHCP message from the client, returns the reply if needed.
+    pub fn handle(&mut self, config: &SlirpConfig, buf: &[u8]) -> Option<DhcpReply> {
+        let req = DhcpRequest::parse(buf)?;
+        let (reply_type, yiaddr) = match req.msg_type {
+            DHCP_DISCOVER => (DHCP_OFFER, self.lease_addr(config, &req.chaddr)?),
+            DHCP_REQUEST => {
+                let addr = self.lease_addr(config, &req.chaddr)?;
+                let requested = req.requested_ip.unwrap_or(req.ciaddr);
+                if requested != addr && !requested.is_unspecified() {
+                    (DHCP_NAK, Ipv4Addr::UNSPECIFIED)
+                } else {
+                    (DHCP_ACK, addr)
+                }
+            }
+            _ => return None,
+        };
+
+        let mut data = vec![0_u8; BOOTP_HDR_LEN];
+        data[0] = BOOTP_REPLY;
+        // Hardware type is ethernet.
+        data[1] = 1;
+        data[2] = MAC_ADDR_LEN as u8;
+        data[4..8].copy_from_slice(&req.xid);
+        data[10..12].copy_from_slice(&req.flags);
+        data[16..20].copy_from_slice(&yiaddr.octets());
+        data[20..24].copy_from_slice(&config.host.octets());
+        data[28..28 + MAC_ADDR_LEN].copy_from_slice(&req.chaddr);
+        data.extend_from_slice(&DHCP_MAGIC);
+
+        data.extend_from_slice(&[DHCP_OPT_MSG_TYPE, 1, reply_type]);
+        data.extend_from_slice(&[DHCP_OPT_SERVER_ID, 4]);
+        data.extend_from_slice(&config.host.octets());
+        if reply_type != DHCP_NAK {
+            data.extend_from_slice(&[DHCP_OPT_LEASE_TIME, 4]);
+            data.extend_from_slice(&DHCP_LEASE_TIME.to_be_bytes());
+            data.extend_from_slice(&[DHCP_OPT_SUBNET_MASK, 4]);
+            data.extend_from_slice(&config.netmask().octets());
+            data.extend_from_slice(&[DHCP_OPT_ROUTER, 4]);
+            data.extend_from_slice(&config.host.octets());
+            data.extend_from_slice(&[DHCP_OPT_DNS, 4]);
+            data.extend_from_slice(&config.dns.octets());
+        }
+        data.push(DHCP_OPT_END);
+        if data.len() < DHCP_MIN_LEN {
+            data.resize(DHCP_MIN_LEN, DHCP_OPT_PAD);
+        }
+
+        Some(DhcpReply {
+            data,
+            yiaddr: if reply_type == DHCP_NAK {
+                None
+            } else {
+                Some(yiaddr)
+            },
+            chaddr: req.chaddr,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_request(
+        msg_type: u8,
+        mac: &[u8; MAC_ADDR_LEN],
+        requested: Option<Ipv4Addr>,
+    ) -> Vec<u8> {
+        let mut buf = vec![0_u8; BOOTP_HDR_LEN];
+        buf[0] = BOOTP_REQUEST;
+        buf[1] = 1;
+        buf[2] = MAC_ADDR_LEN as u8;
+        buf[4..8].copy_from_slice(&[1, 2, 3, 4]);
+        buf[28..28 + MAC_ADDR_LEN].copy_from_slice(mac);
+        buf.extend_from_slice(&DHCP_MAGIC);
+        buf.extend_from_slice(&[DHCP_OPT_MSG_TYPE, 1, msg_type]);
+        if let Some(addr) = requested {
+            buf.extend_from_slice(&[DHCP_OPT_REQUESTED_IP, 4]);
+            buf.extend_from_slice(&addr.octets());
+        }
+        buf.push(DHCP_OPT_END);
+        buf
+    }
+
+    fn reply_option(reply: &DhcpReply, code: u8) -> Option<Vec<u8>> {
+        let options = &reply.data[BOOTP_HDR_LEN + 4..];
+        let mut i = 0;
+        while i < options.len() && options[i] != DHCP_OPT_END {
+            let len = options[i + 1] as usize;
+            if options[i] == code {
+                return Some(options[i + 2..i + 2 + len].to_vec());
+            }
+            i += 2 + len;
+        }
+        None
+    }
+
+    #[test]
+    fn test_dhcp_lease() {
+        let config = SlirpConfig::default();
+        let mut server = DhcpServer::default();
+        let mac1 = [0x52, 0x54, 0, 0, 0, 1];
+        let mac2 = [0x52, 0x54, 0, 0, 0, 2];
+
+        let offer = server
+            .handle(&config, &build_request(DHCP_DISCOVER, &mac1, None))
+            .unwrap();
+        assert_eq!(offer.yiaddr, Some(Ipv4Addr::new(10, 0, 2, 15)));
+        assert_eq!(offer.chaddr, mac1);
+        assert_eq!(offer.data[0], BOOTP_REPLY);
+        assert_eq!(offer.data[4..8], [1, 2, 3, 4]);
+        assert_eq!(offer.data[16..20], [10, 0, 2, 15]);
+        assert_eq!(
+            reply_option(&offer, DHCP_OPT_MSG_TYPE),
+            Some(vec![DHCP_OFFER])
+        );
+        assert_eq!(
+            reply_option(&offer, DHCP_OPT_SUBNET_MASK),
+            Some(vec![255, 255, 255, 0])
+        );
+        assert_eq!(
+            reply_option(&offer, DHCP_OPT_ROUTER),
+            Some(vec![10, 0, 2, 2])
+        );
+        assert_eq!(reply_option(&offer, DHCP_OPT_DNS), Some(vec![10, 0, 2, 3]));
+
+        let ack = server
+            .handle(
+                &config,
+                &build_request(DHCP_REQUEST, &mac1, Some(Ipv4Addr::new(10, 0, 2, 15))),
+            )
+            .unwrap();
+        assert_eq!(ack.yiaddr, Some(Ipv4Addr::new(10, 0, 2, 15)));
+        assert_eq!(reply_option(&ack, DHCP_OPT_MSG_TYPE), Some(vec![DHCP_ACK]));
+
+        // The second client gets the next address.
+        let offer = server
+            .handle(&config, &build_request(DHCP_DISCOVER, &mac2, None))
+            .unwrap();
+        assert_eq!(offer.yiaddr, Some(Ipv4Addr::new(10, 0, 2, 16)));
+
+        // Request of the address leased to others is refused.
+        let nak = server
+            .handle(
+                &config,
+                &build_request(DHCP_REQUEST, &mac2, Some(Ipv4Addr::new(10, 0, 2, 15))),
+            )
+            .unwrap();
+        assert_eq!(nak.yiaddr, None);
+        assert_eq!(reply_option(&nak, DHCP_OPT_MSG_TYPE), Some(vec![DHCP_NAK]));
+
+        // Invalid message.
+        assert!(server.handle(&config, &[0_u8; 10]).is_none());
+    }
+}
@@ -0,0 +1,949 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Userspace network backend.
+//!
+//! The guest is connected to a virtual network with a gateway which answers
+//! ARP, DHCP and ICMP echo, forwards DNS queries to the nameserver of host, and
+//! relays the TCP and UDP traffic over the sockets of host. The ports of host
+//! can be forwarded to guest by `hostfwd` rules.
+
+pub mod dhcp;
+pub mod packet;
+pub mod tcp;
+pub mod udp;
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::net::Ipv4Addr;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use dhcp::{DhcpServer, DHCP_CLIENT_PORT, DHCP_SERVER_PORT};
+use packet::{
+    build_eth_frame, build_ipv4_packet, build_udp_packet, ip_checksum, ArpPacket, EthFrame,
+    Ipv4Packet, TcpSegment, UdpPacket, ARP_OP_REPLY, ARP_OP_REQUEST, BROADCAST_MAC, ETH_P_ARP,
+    ETH_P_IP, IPPROTO_TCP, IPPROTO_UDP, MAC_ADDR_LEN,
+};
+use tcp::TcpTable;
+use udp::UdpTable;
+
+/// MAC address of the gateway.
+pub const GATEWAY_MAC: [u8; MAC_ADDR_LEN] = [0x52, 0x55, 0x0a, 0x00, 0x02, 0x02];
+/// Ports of the gateway allocated for the host forwarding connections start from it.
+const FWD_PORT_START: u16 = 49152;
+/// Max number of IPv4 packets waiting to be sent to guest.
+const MAX_QUEUED_PACKETS: usize = 4096;
+/// Max frame size received from guest, including the virtio net header.
+const MAX_FRAME_SIZE: usize = 65562;
+const POLL_TIMEOUT_MS: i64 = 100;
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+const IPPROTO_ICMP: u8 = 1;
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+/// Offset of `num_buffers` in virtio net header.
+const VNET_HDR_NUM_BUFFERS_OFFSET: usize = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HostFwdProto {
+    Tcp,
+    Udp,
+}
+
+/// Forward the connections to `host_addr:host_port` of host to `guest_addr:guest_port`
+/// of guest.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostFwd {
+    pub proto: HostFwdProto,
+    pub host_addr: Ipv4Addr,
+    pub host_port: u16,
+    /// Address assigned by DHCP is used if it's None.
+    pub guest_addr: Option<Ipv4Addr>,
+    pub guest_port: u16,
+}
+
+fn parse_fwd_addr(s: &str) -> Result<(Option<Ipv4Addr>, u16)> {
+    let (addr, port) = s
+        .rsplit_once(':')
+        .with_context(|| format!("Invalid hostfwd address {}", s))?;
+    let addr = if addr.is_empty() {
+        None
+    } else {
+        Some(
+            addr.parse::<Ipv4Addr>()
+                .with_context(|| format!("Invalid hostfwd ip address {}", addr))?,
+        )
+    };
+    let port = port
+        .parse::<u16>()
+        .with_context(|| format!("Invalid hostfwd port {}", port))?;
+    if port == 0 {
+        bail!("Invalid hostfwd port 0");
+    }
+    Ok((addr, port))
+}
+
+impl FromStr for HostFwd {
+    type Err = anyhow::Error;
+
+    /// Parse the rule with format `[tcp|udp]:[hostaddr]:hostport-[guestaddr]:guestport`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (proto, rule) = match s.split_once(':') {
+            Some(("tcp", rule)) | Some(("", rule)) => (HostFwdProto::Tcp, rule),
+            Some(("udp", rule)) => (HostFwdProto::Udp, rule),
+            _ => bail!("Invalid hostfwd rule {}, protocol should be tcp or udp", s),
+        };
+        let (host, guest) = rule
+            .split_once('-')
+            .with_context(|| format!("Invalid hostfwd rule {}", s))?;
+        let (host_addr, host_port) = parse_fwd_addr(host)?;
+        let (guest_addr, guest_port) = parse_fwd_addr(guest)?;
+        Ok(HostFwd {
+            proto,
+            host_addr: host_addr.unwrap_or(Ipv4Addr::UNSPECIFIED),
+            host_port,
+            guest_addr,
+            guest_port,
+        })
+    }
+}
+
+/// Config of the userspace network.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlirpConfig {
+    /// Address of the virtual network.
+    pub net: Ipv4Addr,
+    pub prefix_len: u8,
+    /// Address of the gateway.
+    pub host: Ipv4Addr,
+    /// Address of the DNS server, the queries are forwarded to the nameserver of host.
+    pub dns: Ipv4Addr,
+    /// The first address assigned by DHCP.
+    pub dhcp_start: Ipv4Addr,
+    pub hostfwds: Vec<HostFwd>,
+    /// Forbid guest to reach the services on loopback of host through the gateway.
+    pub restrict: bool,
+}
+
+impl Default for SlirpConfig {
+    fn default() -> Self {
+        SlirpConfig {
+            net: Ipv4Addr::new(10, 0, 2, 0),
+            prefix_len: 24,
+            host: Ipv4Addr::new(10, 0, 2, 2),
+            dns: Ipv4Addr::new(10, 0, 2, 3),
+            dhcp_start: Ipv4Addr::new(10, 0, 2, 15),
+            hostfwds: Vec::new(),
+            restrict: true,
+        }
+    }
+}
+
+impl SlirpConfig {
+    pub fn netmask(&self) -> Ipv4Addr {
+        let mask = u32::MAX
+            .checked_shl(32 - self.prefix_len as u32)
+            .unwrap_or(0);
+        Ipv4Addr::from(mask)
+    }
+
+    pub fn in_net(&self, addr: Ipv4Addr) -> bool {
+        let mask = u32::from(self.netmask());
+        u32::from(addr) & mask == u32::from(self.net) & mask
+            && addr != self.broadcast()
+            && u32::from(addr) & !mask != 0
+    }
+
+    fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.net) | !u32::from(self.netmask()))
+    }
+
+    /// Set the network with format `addr[/prefix_len]`, the addresses of gateway, DNS
+    /// server and DHCP are changed to the default ones within the network.
+    pub fn set_net(&mut self, s: &str) -> Result<()> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, len)) => (
+                addr,
+                len.parse::<u8>()
+                    .with_context(|| format!("Invalid prefix length {}", len))?,
+            ),
+            None => (s, 24),
+        };
+        if !(1..=30).contains(&prefix_len) {
+            bail!("Invalid prefix length {}, should be in 1..=30", prefix_len);
+        }
+        let addr = addr
+            .parse::<Ipv4Addr>()
+            .with_context(|| format!("Invalid network address {}", addr))?;
+        self.prefix_len = prefix_len;
+        let net = u32::from(addr) & u32::from(self.netmask());
+        self.net = Ipv4Addr::from(net);
+        self.host = Ipv4Addr::from(net | 2);
+        self.dns = Ipv4Addr::from(net | 3);
+        self.dhcp_start = Ipv4Addr::from(net | 15);
+        Ok(())
+    }
+
+    pub fn check(&self) -> Result<()> {
+        for (name, addr) in [
+            ("host", self.host),
+            ("dns", self.dns),
+            ("dhcpstart", self.dhcp_start),
+        ] {
+            if !self.in_net(addr) {
+                bail!(
+                    "The {} address {} is not in network {}/{}",
+                    name,
+                    addr,
+                    self.net,
+                    self.prefix_len
+                );
+            }
+        }
+        if self.host == self.dns || self.dhcp_start == self.host || self.dhcp_start == self.dns {
+            bail!("The host, dns and dhcpstart addresses should be different");
+        }
+        Ok(())
+    }
+}
+
+/// State of the virtual network shared by the protocols.
+pub struct VirtualNet {
+    pub config: SlirpConfig,
+    /// Nameserver of host which the DNS queries are forwarded to.
+    nameserver: Ipv4Addr,
+    /// Address of guest learned from ARP or DHCP.
+    guest_ip: Option<Ipv4Addr>,
+}
+
+impl VirtualNet {
+    /// Map the destination address of guest to the address on host, `None` means
+    /// the destination is not reachable.
+    pub fn host_addr(&self, dst: Ipv4Addr) -> Option<Ipv4Addr> {
+        if dst == self.config.host {
+            if self.config.restrict {
+                None
+            } else {
+                Some(Ipv4Addr::LOCALHOST)
+            }
+        } else if dst == self.config.dns {
+            Some(self.nameserver)
+        } else if self.config.in_net(dst)
+            || dst.is_loopback()
+            || dst == self.config.broadcast()
+            || dst.is_broadcast()
+            || dst.is_multicast()
+            || dst.is_unspecified()
+        {
+            None
+        } else {
+            Some(dst)
+        }
+    }
+
+    /// Guest address of the host forwarding rule.
+    pub fn fwd_guest_addr(&self, guest_addr: Option<Ipv4Addr>) -> Ipv4Addr {
+        guest_addr
+            .or(self.guest_ip)
+            .unwrap_or(self.config.dhcp_start)
+    }
+}
+
+/// IPv4 packets to be sent to guest.
+#[derive(Default)]
+pub struct IpQueue {
+    id: u16,
+    packets: VecDeque<Vec<u8>>,
+}
+
+impl IpQueue {
+    pub fn push(&mut self, src: Ipv4Addr, dst: Ipv4Addr, proto: u8, payload: &[u8]) {
+        if self.packets.len() >= MAX_QUEUED_PACKETS {
+            return;
+        }
+        self.id = self.id.wrapping_add(1);
+        self.packets
+            .push_back(build_ipv4_packet(self.id, src, dst, proto, payload));
+    }
+}
+
+fn pollfd(fd: RawFd, events: libc::c_short) -> libc::pollfd {
+    libc::pollfd {
+        fd,
+        events,
+        revents: 0,
+    }
+}
+
+fn host_nameserver() -> Ipv4Addr {
+    std::fs::read_to_string(RESOLV_CONF)
+        .ok()
+        .and_then(|conf| {
+            conf.lines().find_map(|line| {
+                let mut fields = line.split_whitespace();
+                match (fields.next(), fields.next()) {
+                    (Some("nameserver"), Some(addr)) => addr.parse::<Ipv4Addr>().ok(),
+                    _ => None,
+                }
+            })
+        })
+        .unwrap_or(Ipv4Addr::LOCALHOST)
+}
+
+/// The userspace network stack. Ethernet frames of guest are passed by `input`
+/// and the frames to guest are got by `pop_frame`.
+pub struct Slirp {
+    net: VirtualNet,
+    guest_mac: Option<[u8; MAC_ADDR_LEN]>,
+    dhcp: DhcpServer,
+    tcp: TcpTable,
+    udp: UdpTable,
+    out: IpQueue,
+    /// Frames not carrying the packets to guest address, such as ARP and DHCP.
+    frames: VecDeque<Vec<u8>>,
+}
+
+impl Slirp {
+    pub fn new(config: &SlirpConfig) -> Result<Self> {
+        config.check()?;
+        let mut slirp = Slirp {
+            net: VirtualNet {
+                config: config.clone(),
+                nameserver: host_nameserver(),
+                guest_ip: None,
+            },
+            guest_mac: None,
+            dhcp: DhcpServer::default(),
+            tcp: TcpTable::default(),
+            udp: UdpTable::default(),
+            out: IpQueue::default(),
+            frames: VecDeque::new(),
+        };
+        for fwd in config.hostfwds.iter() {
+            match fwd.proto {
+                HostFwdProto::Tcp => slirp.tcp.add_hostfwd(fwd)?,
+                HostFwdProto::Udp => slirp.udp.add_hostfwd(fwd)?,
+            }
+        }
+        Ok(slirp)
+    }
+
+    /// Handle the ethernet frame from guest.
+    pub fn input(&mut self, frame: &[u8]) {
+        let eth = match EthFrame::parse(frame) {
+            Some(eth) => eth,
+            None => return,
+        };
+        match eth.ethertype {
+            ETH_P_ARP => self.handle_arp(eth.payload),
+            ETH_P_IP => {
+                self.guest_mac = Some(eth.src);
+                if let Some(ip) = Ipv4Packet::parse(eth.payload) {
+                    self.handle_ip(&ip);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_arp(&mut self, buf: &[u8]) {
+        let arp = match ArpPacket::parse(buf) {
+            Some(arp) => arp,
+            None => return,
+        };
+        if !arp.sender_ip.is_unspecified() && self.net.config.in_net(arp.sender_ip) {
+            self.guest_mac = Some(arp.sender_mac);
+            self.net.guest_ip = Some(arp.sender_ip);
+        }
+        if arp.op != ARP_OP_REQUEST
+            || (arp.target_ip != self.net.config.host && arp.target_ip != self.net.config.dns)
+        {
+            return;
+        }
+        let reply = ArpPacket {
+            op: ARP_OP_REPLY,
+            sender_mac: GATEWAY_MAC,
+            sender_ip: arp.target_ip,
+            target_mac: arp.sender_mac,
+            target_ip: arp.sender_ip,
+        };
+        self.frames.push_back(build_eth_frame(
+            &arp.sender_mac,
+            &GATEWAY_MAC,
+            ETH_P_ARP,
+            &reply.to_bytes(),
+        ));
+    }
+
+    fn handle_ip(&mut self, ip: &Ipv4Packet) {
+        match ip.proto {
+            IPPROTO_UDP => {
+                let udp = match UdpPacket::parse(ip.payload) {
+                    Some(udp) => udp,
+                    None => return,
+                };
+                if udp.dst_port == DHCP_SERVER_PORT {
+                    self.handle_dhcp(udp.data);
+                    return;
+                }
+                self.udp.input(
+                    &self.net,
+                    ip.src,
+                    ip.dst,
+                    udp.src_port,
+                    udp.dst_port,
+                    udp.data,
+                );
+            }
+            IPPROTO_TCP => {
+                if let Some(seg) = TcpSegment::parse(ip.payload) {
+                    self.tcp
+                        .input(&self.net, ip.src, ip.dst, &seg, &mut self.out);
+                }
+            }
+            IPPROTO_ICMP => self.handle_icmp(ip),
+            _ => {}
+        }
+    }
+
+    fn handle_dhcp(&mut self, buf: &[u8]) {
+        let reply = match self.dhcp.handle(&self.net.config, buf) {
+            Some(reply) => reply,
+            None => return,
+        };
+        if reply.yiaddr.is_some() {
+            self.net.guest_ip = reply.yiaddr;
+        }
+        let udp = build_udp_packet(
+            self.net.config.host,
+            DHCP_SERVER_PORT,
+            Ipv4Addr::BROADCAST,
+            DHCP_CLIENT_PORT,
+            &reply.data,
+        );
+        let ip = build_ipv4_packet(
+            0,
+            self.net.config.host,
+            Ipv4Addr::BROADCAST,
+            IPPROTO_UDP,
+            &udp,
+        );
+        self.frames
+            .push_back(build_eth_frame(&reply.chaddr, &GATEWAY_MAC, ETH_P_IP, &ip));
+    }
+
+    /// Reply the ICMP echo request to the gateway and DNS server.
+    fn handle_icmp(&mut self, ip: &Ipv4Packet) {
+        if (ip.dst != self.net.config.host && ip.dst != self.net.config.dns)
+            || ip.payload.len() < 8
+            || ip.payload[0] != ICMP_ECHO_REQUEST
+        {
+            return;
+        }
+        let mut reply = ip.payload.to_vec();
+        reply[0] = ICMP_ECHO_REPLY;
+        reply[2..4].copy_from_slice(&[0, 0]);
+        let csum = ip_checksum(&reply);
+        reply[2..4].copy_from_slice(&csum.to_be_bytes());
+        self.out.push(ip.dst, ip.src, IPPROTO_ICMP, &reply);
+    }
+
+    /// Relay the data of the host sockets and handle the timers.
+    pub fn poll(&mut self, now: Instant) {
+        self.tcp.poll(&self.net, now, &mut self.out);
+        self.udp.poll(&self.net, now, &mut self.out);
+    }
+
+    /// Sockets of host to be polled.
+    pub fn pollfds(&self) -> Vec<libc::pollfd> {
+        let mut fds = Vec::new();
+        self.tcp.pollfds(&mut fds);
+        self.udp.pollfds(&mut fds);
+        fds
+    }
+
+    pub fn has_frames(&self) -> bool {
+        !self.frames.is_empty() || !self.out.packets.is_empty()
+    }
+
+    /// Get the next ethernet frame to guest.
+    pub fn pop_frame(&mut self) -> Option<Vec<u8>> {
+        if let Some(frame) = self.frames.pop_front() {
+            return Some(frame);
+        }
+        let dst = self.guest_mac.unwrap_or(BROADCAST_MAC);
+        self.out
+            .packets
+            .pop_front()
+            .map(|ip| build_eth_frame(&dst, &GATEWAY_MAC, ETH_P_IP, &ip))
+    }
+
+    /// Put back the frame which is failed to send.
+    pub fn requeue_frame(&mut self, frame: Vec<u8>) {
+        self.frames.push_front(frame);
+    }
+}
+
+/// Thread running the userspace network stack. The frames are exchanged with the
+/// virtio net device over a `SOCK_SEQPACKET` socket pair, each message is a frame
+/// prefixed with the virtio net header, the same as the tap device.
+pub struct SlirpBackend {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SlirpBackend {
+    /// Start the network stack, returns the socket used by the device.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Id of the netdev.
+    /// * `config` - Config of the network.
+    /// * `hdr_len` - Length of the virtio net header.
+    pub fn start(id: &str, config: &SlirpConfig, hdr_len: usize) -> Result<(File, Self)> {
+        let mut slirp = Slirp::new(config)?;
+        let mut fds = [0; 2];
+        // SAFETY: fds is a valid array of 2 elements.
+        let ret = unsafe {
+            libc::socketpair(
+                libc::AF_UNIX,
+                libc::SOCK_SEQPACKET | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                0,
+                fds.as_mut_ptr(),
+            )
+        };
+        if ret < 0 {
+            return Err(anyhow!(
+                "Failed to create socket pair for netdev {}: {}",
+                id,
+                std::io::Error::last_os_error()
+            ));
+        }
+        // SAFETY: the fds are created above and owned by the files.
+        let (device_sock, slirp_sock) =
+            unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = std::thread::Builder::new()
+            .name(format!("slirp-{}", id))
+            .spawn(move || {
+                while !thread_stop.load(Ordering::Acquire) {
+                    if let Err(e) = Self::run_once(&mut slirp, &slirp_sock, hdr_len) {
+                        error!("Userspace network stopped: {:?}", e);
+                        break;
+                    }
+                }
+            })
+            .with_context(|| format!("Failed to create thread for netdev {}", id))?;
+
+        Ok((
+            device_sock,
+            SlirpBackend {
+                stop,
+                thread: Some(thread),
+            },
+        ))
+    }
+
+    fn run_once(slirp: &mut Slirp, sock: &File, hdr_len: usize) -> Result<()> {
+        let sock_fd = sock.as_raw_fd();
+        let mut events = libc::POLLIN;
+        if slirp.has_frames() {
+            events |= libc::POLLOUT;
+        }
+        let mut fds = vec![pollfd(sock_fd, events)];
+        fds.extend(slirp.pollfds());
+        let timeout = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: POLL_TIMEOUT_MS * 1_000_000,
+        };
+        // SAFETY: fds and timeout are valid during the call.
+        let ret = unsafe {
+            libc::ppoll(
+                fds.as_mut_ptr(),
+                fds.len() as libc::nfds_t,
+                &timeout,
+                std::ptr::null(),
+            )
+        };
+        if ret < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() != std::io::ErrorKind::Interrupted {
+                bail!("Failed to poll: {}", err);
+            }
+        }
+
+        let mut buf = vec![0_u8; MAX_FRAME_SIZE];
+        loop {
+            // SAFETY: buf is valid and the length is correct.
+            let len = unsafe {
+                libc::recv(
+                    sock_fd,
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    libc::MSG_DONTWAIT,
+                )
+            };
+            if len == 0 {
+                bail!("The socket of device is closed");
+            } else if len < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::WouldBlock {
+                    break;
+                }
+                bail!("Failed to receive frame: {}", err);
+            }
+            if len as usize > hdr_len {
+                slirp.input(&buf[hdr_len..len as usize]);
+            }
+        }
+
+        slirp.poll(Instant::now());
+
+        while let Some(frame) = slirp.pop_frame() {
+            let mut msg = vec![0_u8; hdr_len];
+            if hdr_len >= VNET_HDR_NUM_BUFFERS_OFFSET + 2 {
+                msg[VNET_HDR_NUM_BUFFERS_OFFSET..VNET_HDR_NUM_BUFFERS_OFFSET + 2]
+                    .copy_from_slice(&1_u16.to_le_bytes());
+            }
+            msg.extend_from_slice(&frame);
+            // SAFETY: msg is valid and the length is correct.
+            let ret = unsafe {
+                libc::send(
+                    sock_fd,
+                    msg.as_ptr() as *const libc::c_void,
+                    msg.len(),
+                    libc::MSG_DONTWAIT,
+                )
+            };
+            if ret < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::WouldBlock {
+                    slirp.requeue_frame(frame);
+                    break;
+                }
+                bail!("Failed to send frame: {}", err);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SlirpBackend {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, UdpSocket};
+    use std::time::Duration;
+
+    use super::packet::{
+        build_tcp_segment, TcpAddr, ARP_PKT_LEN, ETH_HDR_LEN, TCP_ACK, TCP_FIN, TCP_PSH, TCP_RST,
+        TCP_SYN,
+    };
+    use super::*;
+
+    const GUEST_MAC: [u8; MAC_ADDR_LEN] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+    const GUEST_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+
+    fn guest_ip_frame(dst: Ipv4Addr, proto: u8, payload: &[u8]) -> Vec<u8> {
+        let ip = build_ipv4_packet(1, GUEST_IP, dst, proto, payload);
+        build_eth_frame(&GATEWAY_MAC, &GUEST_MAC, ETH_P_IP, &ip)
+    }
+
+    /// Poll the stack until a frame to guest is got.
+    fn wait_frame(slirp: &mut Slirp) -> Vec<u8> {
+        for _ in 0..200 {
+            slirp.poll(Instant::now());
+            if let Some(frame) = slirp.pop_frame() {
+                return frame;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("No frame to guest");
+    }
+
+    fn parse_tcp(frame: &[u8]) -> (u32, u32, u8, Vec<u8>) {
+        let ip = Ipv4Packet::parse(&frame[ETH_HDR_LEN..]).unwrap();
+        assert_eq!(ip.proto, IPPROTO_TCP);
+        let seg = TcpSegment::parse(ip.payload).unwrap();
+        (seg.seq, seg.ack, seg.flags, seg.data.to_vec())
+    }
+
+    #[test]
+    fn test_hostfwd_parse() {
+        let fwd = HostFwd::from_str("tcp::2222-:22").unwrap();
+        assert_eq!(fwd.proto, HostFwdProto::Tcp);
+        assert_eq!(fwd.host_addr, Ipv4Addr::UNSPECIFIED);
+        assert_eq!(fwd.host_port, 2222);
+        assert_eq!(fwd.guest_addr, None);
+        assert_eq!(fwd.guest_port, 22);
+
+        let fwd = HostFwd::from_str("udp:127.0.0.1:5353-10.0.2.20:53").unwrap();
+        assert_eq!(fwd.proto, HostFwdProto::Udp);
+        assert_eq!(fwd.host_addr, Ipv4Addr::LOCALHOST);
+        assert_eq!(fwd.guest_addr, Some(Ipv4Addr::new(10, 0, 2, 20)));
+        assert_eq!(fwd.guest_port, 53);
+
+        assert!(HostFwd::from_str("sctp::1-:1").is_err());
+        assert!(HostFwd::from_str("tcp::2222").is_err());
+        assert!(HostFwd::from_str("tcp::0-:22").is_err());
+        assert!(HostFwd::from_str("tcp:1.2.3:22-:22").is_err());
+    }
+
+    #[test]
+    fn test_slirp_config() {
+        let mut config = SlirpConfig::default();
+        assert!(config.check().is_ok());
+        assert_eq!(config.netmask(), Ipv4Addr::new(255, 255, 255, 0));
+        assert!(config.in_net(Ipv4Addr::new(10, 0, 2, 100)));
+        assert!(!config.in_net(Ipv4Addr::new(10, 0, 3, 100)));
+        assert!(!config.in_net(Ipv4Addr::new(10, 0, 2, 255)));
+
+        config.set_net("192.168.76.0/20").unwrap();
+        assert_eq!(config.net, Ipv4Addr::new(192, 168, 64, 0));
+        assert_eq!(config.host, Ipv4Addr::new(192, 168, 64, 2));
+        assert_eq!(config.dns, Ipv4Addr::new(192, 168, 64, 3));
+        assert_eq!(config.dhcp_start, Ipv4Addr::new(192, 168, 64, 15));
+        assert!(config.check().is_ok());
+
+        config.dns = config.host;
+        assert!(config.check().is_err());
+        config.dns = Ipv4Addr::new(10, 0, 2, 3);
+        assert!(config.check().is_err());
+        assert!(config.set_net("10.0.2.0/31").is_err());
+        assert!(config.set_net("10.0.2/24").is_err());
+    }
+
+    #[test]
+    fn test_slirp_arp_icmp() {
+        let mut slirp = Slirp::new(&SlirpConfig::default()).unwrap();
+        let arp = ArpPacket {
+            op: ARP_OP_REQUEST,
+            sender_mac: GUEST_MAC,
+            sender_ip: GUEST_IP,
+            target_mac: [0; MAC_ADDR_LEN],
+            target_ip: Ipv4Addr::new(10, 0, 2, 2),
+        };
+        slirp.input(&build_eth_frame(
+            &BROADCAST_MAC,
+            &GUEST_MAC,
+            ETH_P_ARP,
+            &arp.to_bytes(),
+        ));
+        let frame = slirp.pop_frame().unwrap();
+        let eth = EthFrame::parse(&frame).unwrap();
+        assert_eq!(eth.dst, GUEST_MAC);
+        assert_eq!(eth.src, GATEWAY_MAC);
+        assert_eq!(eth.payload.len(), ARP_PKT_LEN);
+        let reply = ArpPacket::parse(eth.payload).unwrap();
+        assert_eq!(reply.op, ARP_OP_REPLY);
+        assert_eq!(reply.sender_mac, GATEWAY_MAC);
+        assert_eq!(reply.sender_ip, Ipv4Addr::new(10, 0, 2, 2));
+        assert_eq!(reply.target_ip, GUEST_IP);
+
+        // No reply for other addresses.
+        let arp = ArpPacket {
+            target_ip: Ipv4Addr::new(10, 0, 2, 100),
+            ..arp
+        };
+        slirp.input(&build_eth_frame(
+            &BROADCAST_MAC,
+            &GUEST_MAC,
+            ETH_P_ARP,
+            &arp.to_bytes(),
+        ));
+        assert!(slirp.pop_frame().is_none());
+
+        let mut echo = vec![ICMP_ECHO_REQUEST, 0, 0, 0, 0, 1, 0, 1, 0xaa, 0xbb];
+        let csum = ip_checksum(&echo);
+        echo[2..4].copy_from_slice(&csum.to_be_bytes());
+        slirp.input(&guest_ip_frame(
+            Ipv4Addr::new(10, 0, 2, 3),
+            IPPROTO_ICMP,
+            &echo,
+        ));
+        let frame = slirp.pop_frame().unwrap();
+        let ip = Ipv4Packet::parse(&frame[ETH_HDR_LEN..]).unwrap();
+        assert_eq!(ip.proto, IPPROTO_ICMP);
+        assert_eq!(ip.src, Ipv4Addr::new(10, 0, 2, 3));
+        assert_eq!(ip.dst, GUEST_IP);
+        assert_eq!(ip.payload[0], ICMP_ECHO_REPLY);
+        assert_eq!(ip.payload[4..], echo[4..]);
+        assert_eq!(ip_checksum(ip.payload), 0);
+    }
+
+    #[test]
+    fn test_slirp_udp() {
+        let config = SlirpConfig {
+            restrict: false,
+            ..Default::default()
+        };
+        let mut slirp = Slirp::new(&config).unwrap();
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        // The gateway address is mapped to the loopback address of host.
+        let udp = build_udp_packet(GUEST_IP, 5000, Ipv4Addr::new(10, 0, 2, 2), port, b"ping");
+        slirp.input(&guest_ip_frame(
+            Ipv4Addr::new(10, 0, 2, 2),
+            IPPROTO_UDP,
+            &udp,
+        ));
+        let mut buf = [0_u8; 16];
+        let (len, peer) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"ping");
+        server.send_to(b"pong", peer).unwrap();
+
+        let frame = wait_frame(&mut slirp);
+        let eth = EthFrame::parse(&frame).unwrap();
+        assert_eq!(eth.dst, GUEST_MAC);
+        let ip = Ipv4Packet::parse(eth.payload).unwrap();
+        assert_eq!(ip.src, Ipv4Addr::new(10, 0, 2, 2));
+        assert_eq!(ip.dst, GUEST_IP);
+        let udp = UdpPacket::parse(ip.payload).unwrap();
+        assert_eq!(udp.src_port, port);
+        assert_eq!(udp.dst_port, 5000);
+        assert_eq!(udp.data, b"pong");
+    }
+
+    #[test]
+    fn test_slirp_tcp() {
+        let config = SlirpConfig {
+            restrict: false,
+            ..Default::default()
+        };
+        let mut slirp = Slirp::new(&config).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let gateway = Ipv4Addr::new(10, 0, 2, 2);
+        let addr = TcpAddr {
+            src: GUEST_IP,
+            src_port: 40000,
+            dst: gateway,
+            dst_port: port,
+        };
+        let guest_seg = |seq: u32, ack: u32, flags: u8, data: &[u8]| {
+            let seg = build_tcp_segment(&addr, seq, ack, flags, 65535, Some(1460), data);
+            guest_ip_frame(gateway, IPPROTO_TCP, &seg)
+        };
+
+        slirp.input(&guest_seg(1000, 0, TCP_SYN, &[]));
+        let (mut stream, _) = listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let (iss, ack, flags, _) = parse_tcp(&wait_frame(&mut slirp));
+        assert_eq!(flags, TCP_SYN | TCP_ACK);
+        assert_eq!(ack, 1001);
+
+        // Finish the handshake with data.
+        slirp.input(&guest_seg(1001, iss + 1, TCP_ACK | TCP_PSH, b"hello"));
+        let (_, ack, flags, _) = parse_tcp(&wait_frame(&mut slirp));
+        assert_eq!(flags, TCP_ACK);
+        assert_eq!(ack, 1006);
+        let mut buf = [0_u8; 5];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        stream.write_all(b"world").unwrap();
+        let (seq, _, flags, data) = parse_tcp(&wait_frame(&mut slirp));
+        assert_eq!(seq, iss + 1);
+        assert_eq!(flags, TCP_ACK | TCP_PSH);
+        assert_eq!(data, b"world");
+
+        // Close from host.
+        slirp.input(&guest_seg(1006, iss + 6, TCP_ACK, &[]));
+        drop(stream);
+        let (seq, _, flags, _) = parse_tcp(&wait_frame(&mut slirp));
+        assert_eq!(seq, iss + 6);
+        assert_eq!(flags, TCP_FIN | TCP_ACK);
+        slirp.input(&guest_seg(1006, iss + 7, TCP_FIN | TCP_ACK, &[]));
+        let (_, ack, flags, _) = parse_tcp(&wait_frame(&mut slirp));
+        assert_eq!(flags, TCP_ACK);
+        assert_eq!(ack, 1007);
+        let mut fds = Vec::new();
+        slirp.tcp.pollfds(&mut fds);
+        assert!(fds.is_empty());
+
+        // Connection refused.
+        drop(listener);
+        slirp.input(&guest_seg(2000, 0, TCP_SYN, &[]));
+        let (_, ack, flags, _) = parse_tcp(&wait_frame(&mut slirp));
+        assert_eq!(flags & TCP_RST, TCP_RST);
+        assert_eq!(ack, 2001);
+    }
+
+    #[test]
+    fn test_slirp_restrict() {
+        let mut slirp = Slirp::new(&SlirpConfig::default()).unwrap();
+        let gateway = Ipv4Addr::new(10, 0, 2, 2);
+        assert_eq!(slirp.net.host_addr(gateway), None);
+        assert_eq!(slirp.net.host_addr(Ipv4Addr::LOCALHOST), None);
+        assert_eq!(
+            slirp.net.host_addr(Ipv4Addr::new(1, 2, 3, 4)),
+            Some(Ipv4Addr::new(1, 2, 3, 4))
+        );
+
+        // The services on loopback of host are not reachable through the gateway.
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        server
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let udp = build_udp_packet(GUEST_IP, 5000, gateway, port, b"ping");
+        slirp.input(&guest_ip_frame(gateway, IPPROTO_UDP, &udp));
+        let mut buf = [0_u8; 16];
+        assert!(server.recv_from(&mut buf).is_err());
+        assert!(slirp.pop_frame().is_none());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = TcpAddr {
+            src: GUEST_IP,
+            src_port: 40000,
+            dst: gateway,
+            dst_port: listener.local_addr().unwrap().port(),
+        };
+        let seg = build_tcp_segment(&addr, 1000, 0, TCP_SYN, 65535, Some(1460), &[]);
+        slirp.input(&guest_ip_frame(gateway, IPPROTO_TCP, &seg));
+        let (_, ack, flags, _) = parse_tcp(&wait_frame(&mut slirp));
+        assert_eq!(flags & TCP_RST, TCP_RST);
+        assert_eq!(ack, 1001);
+        assert!(listener.accept().is_err());
+
+        // Loopback of host is never reachable by its own address.
+        slirp.net.config.restrict = false;
+        assert_eq!(slirp.net.host_addr(gateway), Some(Ipv4Addr::LOCALHOST));
+        assert_eq!(slirp.net.host_addr(Ipv4Addr::LOCALHOST), None);
+        assert_eq!(slirp.net.host_addr(Ipv4Addr::new(127, 1, 2, 3)), None);
+    }
+}
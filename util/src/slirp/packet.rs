@@ -0,0 +1,466 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Parsing and building of the ethernet, ARP, IPv4, UDP and TCP headers.
+
+use std::net::Ipv4Addr;
+
+pub const MAC_ADDR_LEN: usize = 6;
+pub const ETH_HDR_LEN: usize = 14;
+pub const ETH_P_IP: u16 = 0x0800;
+pub const ETH_P_ARP: u16 = 0x0806;
+pub const BROADCAST_MAC: [u8; MAC_ADDR_LEN] = [0xff; MAC_ADDR_LEN];
+
+pub const ARP_PKT_LEN: usize = 28;
+pub const ARP_OP_REQUEST: u16 = 1;
+pub const ARP_OP_REPLY: u16 = 2;
+
+pub const IPV4_HDR_LEN: usize = 20;
+pub const IPPROTO_TCP: u8 = 6;
+pub const IPPROTO_UDP: u8 = 17;
+const IPV4_DEFAULT_TTL: u8 = 64;
+/// More fragments flag and fragment offset mask.
+const IPV4_FRAG_MASK: u16 = 0x3fff;
+
+pub const UDP_HDR_LEN: usize = 8;
+
+pub const TCP_HDR_LEN: usize = 20;
+pub const TCP_FIN: u8 = 0x01;
+pub const TCP_SYN: u8 = 0x02;
+pub const TCP_RST: u8 = 0x04;
+pub const TCP_PSH: u8 = 0x08;
+pub const TCP_ACK: u8 = 0x10;
+const TCP_OPT_END: u8 = 0;
+const TCP_OPT_NOP: u8 = 1;
+const TCP_OPT_MSS: u8 = 2;
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+fn read_ipv4(buf: &[u8], offset: usize) -> Ipv4Addr {
+    Ipv4Addr::new(
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    )
+}
+
+/// Sum the data as big endian 16 bits words.
+fn checksum_add(mut sum: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for word in &mut chunks {
+        sum += u16::from_be_bytes([word[0], word[1]]) as u32;
+    }
+    if let [last] = chunks.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    sum
+}
+
+fn checksum_finish(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Internet checksum of the data.
+pub fn ip_checksum(data: &[u8]) -> u16 {
+    checksum_finish(checksum_add(0, data))
+}
+
+/// Checksum of the UDP or TCP packet including the IPv4 pseudo header.
+fn l4_checksum(src: Ipv4Addr, dst: Ipv4Addr, proto: u8, data: &[u8]) -> u16 {
+    let mut sum = checksum_add(0, &src.octets());
+    sum = checksum_add(sum, &dst.octets());
+    sum += proto as u32;
+    sum += data.len() as u32;
+    checksum_finish(checksum_add(sum, data))
+}
+
+/// Ethernet frame.
+pub struct EthFrame<'a> {
+    pub dst: [u8; MAC_ADDR_LEN],
+    pub src: [u8; MAC_ADDR_LEN],
+    pub ethertype: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> EthFrame<'a> {
+    pub fn parse(buf: &'a [u8]) -> Option<Self> {
+        if buf.len() < ETH_HDR_LEN {
+            return None;
+        }
+        let mut dst = [0_u8; MAC_ADDR_LEN];
+        let mut src = [0_u8; MAC_ADDR_LEN];
+        dst.copy_from_slice(&buf[0..6]);
+        src.copy_from_slice(&buf[6..12]);
+        Some(EthFrame {
+            dst,
+            src,
+            ethertype: read_u16(buf, 12),
+            payload: &buf[ETH_HDR_LEN..],
+        })
+    }
+}
+
+pub fn build_eth_frame(
+    dst: &[u8; MAC_ADDR_LEN],
+    src: &[u8; MAC_ADDR_LEN],
+    ethertype: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(ETH_HDR_LEN + payload.len());
+    frame.extend_from_slice(dst);
+    frame.extend_from_slice(src);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// ARP packet for ethernet and IPv4.
+pub struct ArpPacket {
+    pub op: u16,
+    pub sender_mac: [u8; MAC_ADDR_LEN],
+    pub sender_ip: Ipv4Addr,
+    pub target_mac: [u8; MAC_ADDR_LEN],
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpPacket {
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        // Hardware type is ethernet, protocol type is IPv4.
+        if buf.len() < ARP_PKT_LEN
+            || read_u16(buf, 0) != 1
+            || read_u16(buf, 2) != ETH_P_IP
+            || buf[4] != MAC_ADDR_LEN as u8
+            || buf[5] != 4
+        {
+            return None;
+        }
+        let mut sender_mac = [0_u8; MAC_ADDR_LEN];
+        let mut target_mac = [0_u8; MAC_ADDR_LEN];
+        sender_mac.copy_from_slice(&buf[8..14]);
+        target_mac.copy_from_slice(&buf[18..24]);
+        Some(ArpPacket {
+            op: read_u16(buf, 6),
+            sender_mac,
+            sender_ip: read_ipv4(buf, 14),
+            target_mac,
+            target_ip: read_ipv4(buf, 24),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(ARP_PKT_LEN);
+        buf.extend_from_slice(&1_u16.to_be_bytes());
+        buf.extend_from_slice(&ETH_P_IP.to_be_bytes());
+        buf.push(MAC_ADDR_LEN as u8);
+        buf.push(4);
+        buf.extend_from_slice(&self.op.to_be_bytes());
+        buf.extend_from_slice(&self.sender_mac);
+        buf.extend_from_slice(&self.sender_ip.octets());
+        buf.extend_from_slice(&self.target_mac);
+        buf.extend_from_slice(&self.target_ip.octets());
+        buf
+    }
+}
+
+/// IPv4 packet, the options are skipped.
+pub struct Ipv4Packet<'a> {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub proto: u8,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Ipv4Packet<'a> {
+    /// Parse the IPv4 packet, fragments are not supported.
+    pub fn parse(buf: &'a [u8]) -> Option<Self> {
+        if buf.len() < IPV4_HDR_LEN || buf[0] >> 4 != 4 {
+            return None;
+        }
+        let hdr_len = ((buf[0] & 0x0f) as usize) * 4;
+        let total_len = read_u16(buf, 2) as usize;
+        if hdr_len < IPV4_HDR_LEN
+            || total_len < hdr_len
+            || total_len > buf.len()
+            || read_u16(buf, 6) & IPV4_FRAG_MASK != 0
+        {
+            return None;
+        }
+        Some(Ipv4Packet {
+            src: read_ipv4(buf, 12),
+            dst: read_ipv4(buf, 16),
+            proto: buf[9],
+            payload: &buf[hdr_len..total_len],
+        })
+    }
+}
+
+pub fn build_ipv4_packet(
+    id: u16,
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    proto: u8,
+    payload: &[u8],
+) -> Vec<u8> {
+    let total_len = (IPV4_HDR_LEN + payload.len()) as u16;
+    let mut buf = Vec::with_capacity(total_len as usize);
+    buf.push(0x45);
+    buf.push(0);
+    buf.extend_from_slice(&total_len.to_be_bytes());
+    buf.extend_from_slice(&id.to_be_bytes());
+    // Don't fragment.
+    buf.extend_from_slice(&0x4000_u16.to_be_bytes());
+    buf.push(IPV4_DEFAULT_TTL);
+    buf.push(proto);
+    buf.extend_from_slice(&[0, 0]);
+    buf.extend_from_slice(&src.octets());
+    buf.extend_from_slice(&dst.octets());
+    let csum = ip_checksum(&buf[..IPV4_HDR_LEN]);
+    buf[10..12].copy_from_slice(&csum.to_be_bytes());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+/// UDP datagram.
+pub struct UdpPacket<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub data: &'a [u8],
+}
+
+impl<'a> UdpPacket<'a> {
+    pub fn parse(buf: &'a [u8]) -> Option<Self> {
+        if buf.len() < UDP_HDR_LEN {
+            return None;
+        }
+        let len = read_u16(buf, 4) as usize;
+        if len < UDP_HDR_LEN || len > buf.len() {
+            return None;
+        }
+        Some(UdpPacket {
+            src_port: read_u16(buf, 0),
+            dst_port: read_u16(buf, 2),
+            data: &buf[UDP_HDR_LEN..len],
+        })
+    }
+}
+
+pub fn build_udp_packet(
+    src: Ipv4Addr,
+    src_port: u16,
+    dst: Ipv4Addr,
+    dst_port: u16,
+    data: &[u8],
+) -> Vec<u8> {
+    let len = (UDP_HDR_LEN + data.len()) as u16;
+    let mut buf = Vec::with_capacity(len as usize);
+    buf.extend_from_slice(&src_port.to_be_bytes());
+    buf.extend_from_slice(&dst_port.to_be_bytes());
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(&[0, 0]);
+    buf.extend_from_slice(data);
+    let mut csum = l4_checksum(src, dst, IPPROTO_UDP, &buf);
+    if csum == 0 {
+        csum = 0xffff;
+    }
+    buf[6..8].copy_from_slice(&csum.to_be_bytes());
+    buf
+}
+
+/// TCP segment, only the MSS option is parsed.
+pub struct TcpSegment<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub window: u16,
+    pub mss: Option<u16>,
+    pub data: &'a [u8],
+}
+
+impl<'a> TcpSegment<'a> {
+    pub fn parse(buf: &'a [u8]) -> Option<Self> {
+        if buf.len() < TCP_HDR_LEN {
+            return None;
+        }
+        let hdr_len = ((buf[12] >> 4) as usize) * 4;
+        if hdr_len < TCP_HDR_LEN || hdr_len > buf.len() {
+            return None;
+        }
+
+        let mut mss = None;
+        let options = &buf[TCP_HDR_LEN..hdr_len];
+        let mut i = 0;
+        while i < options.len() {
+            match options[i] {
+                TCP_OPT_END => break,
+                TCP_OPT_NOP => i += 1,
+                kind => {
+                    if i + 1 >= options.len() || options[i + 1] < 2 {
+                        break;
+                    }
+                    let len = options[i + 1] as usize;
+                    if kind == TCP_OPT_MSS && len == 4 && i + 4 <= options.len() {
+                        mss = Some(read_u16(options, i + 2));
+                    }
+                    i += len;
+                }
+            }
+        }
+
+        Some(TcpSegment {
+            src_port: read_u16(buf, 0),
+            dst_port: read_u16(buf, 2),
+            seq: read_u32(buf, 4),
+            ack: read_u32(buf, 8),
+            flags: buf[13],
+            window: read_u16(buf, 14),
+            mss,
+            data: &buf[hdr_len..],
+        })
+    }
+}
+
+/// Addresses and ports of a TCP segment.
+pub struct TcpAddr {
+    pub src: Ipv4Addr,
+    pub src_port: u16,
+    pub dst: Ipv4Addr,
+    pub dst_port: u16,
+}
+
+/// Build the TCP segment, the MSS option is added if `mss` is set.
+pub fn build_tcp_segment(
+    addr: &TcpAddr,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    mss: Option<u16>,
+    data: &[u8],
+) -> Vec<u8> {
+    let hdr_len = if mss.is_some() {
+        TCP_HDR_LEN + 4
+    } else {
+        TCP_HDR_LEN
+    };
+    let mut buf = Vec::with_capacity(hdr_len + data.len());
+    buf.extend_from_slice(&addr.src_port.to_be_bytes());
+    buf.extend_from_slice(&addr.dst_port.to_be_bytes());
+    buf.extend_from_slice(&seq.to_be_bytes());
+    buf.extend_from_slice(&ack.to_be_bytes());
+    buf.push(((hdr_len / 4) as u8) << 4);
+    buf.push(flags);
+    buf.extend_from_slice(&window.to_be_bytes());
+    // Checksum and urgent pointer.
+    buf.extend_from_slice(&[0, 0, 0, 0]);
+    if let Some(mss) = mss {
+        buf.push(TCP_OPT_MSS);
+        buf.push(4);
+        buf.extend_from_slice(&mss.to_be_bytes());
+    }
+    buf.extend_from_slice(data);
+    let csum = l4_checksum(addr.src, addr.dst, IPPROTO_TCP, &buf);
+    buf[16..18].copy_from_slice(&csum.to_be_bytes());
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ipv4_packet() {
+        let src = Ipv4Addr::new(10, 0, 2, 15);
+        let dst = Ipv4Addr::new(10, 0, 2, 2);
+        let udp = build_udp_packet(src, 1234, dst, 53, &[1, 2, 3]);
+        let ip = build_ipv4_packet(1, src, dst, IPPROTO_UDP, &udp);
+        // The checksum of a header with correct checksum is zero.
+        assert_eq!(ip_checksum(&ip[..IPV4_HDR_LEN]), 0);
+        assert_eq!(l4_checksum(src, dst, IPPROTO_UDP, &udp), 0);
+
+        let packet = Ipv4Packet::parse(&ip).unwrap();
+        assert_eq!(packet.src, src);
+        assert_eq!(packet.dst, dst);
+        assert_eq!(packet.proto, IPPROTO_UDP);
+        let udp = UdpPacket::parse(packet.payload).unwrap();
+        assert_eq!(udp.src_port, 1234);
+        assert_eq!(udp.dst_port, 53);
+        assert_eq!(udp.data, [1, 2, 3]);
+
+        // Fragment is not supported.
+        let mut frag = ip.clone();
+        frag[6] = 0x20;
+        assert!(Ipv4Packet::parse(&frag).is_none());
+        // Truncated packet.
+        assert!(Ipv4Packet::parse(&ip[..ip.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn test_tcp_segment() {
+        let addr = TcpAddr {
+            src: Ipv4Addr::new(10, 0, 2, 2),
+            src_port: 80,
+            dst: Ipv4Addr::new(10, 0, 2, 15),
+            dst_port: 40000,
+        };
+        let buf = build_tcp_segment(&addr, 100, 200, TCP_SYN | TCP_ACK, 65535, Some(1460), &[]);
+        assert_eq!(l4_checksum(addr.src, addr.dst, IPPROTO_TCP, &buf), 0);
+        let seg = TcpSegment::parse(&buf).unwrap();
+        assert_eq!(seg.src_port, 80);
+        assert_eq!(seg.dst_port, 40000);
+        assert_eq!(seg.seq, 100);
+        assert_eq!(seg.ack, 200);
+        assert_eq!(seg.flags, TCP_SYN | TCP_ACK);
+        assert_eq!(seg.window, 65535);
+        assert_eq!(seg.mss, Some(1460));
+        assert!(seg.data.is_empty());
+
+        let buf = build_tcp_segment(&addr, 1, 2, TCP_ACK | TCP_PSH, 100, None, b"hello");
+        let seg = TcpSegment::parse(&buf).unwrap();
+        assert_eq!(seg.mss, None);
+        assert_eq!(seg.data, b"hello");
+    }
+
+    #[test]
+    fn test_arp_packet() {
+        let arp = ArpPacket {
+            op: ARP_OP_REQUEST,
+            sender_mac: [0x52, 0x54, 0, 0x12, 0x34, 0x56],
+            sender_ip: Ipv4Addr::new(10, 0, 2, 15),
+            target_mac: [0; MAC_ADDR_LEN],
+            target_ip: Ipv4Addr::new(10, 0, 2, 2),
+        };
+        let buf = arp.to_bytes();
+        assert_eq!(buf.len(), ARP_PKT_LEN);
+        let parsed = ArpPacket::parse(&buf).unwrap();
+        assert_eq!(parsed.op, ARP_OP_REQUEST);
+        assert_eq!(parsed.sender_mac, arp.sender_mac);
+        assert_eq!(parsed.sender_ip, arp.sender_ip);
+        assert_eq!(parsed.target_ip, arp.target_ip);
+    }
+}
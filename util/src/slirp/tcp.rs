@@ -0,0 +1,551 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! TCP NAT which terminates the connections of guest and relays the data
+//! over the host sockets.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddrV4, TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use log::error;
+
+use super::packet::{
+    build_tcp_segment, TcpAddr, TcpSegment, IPPROTO_TCP, TCP_ACK, TCP_FIN, TCP_PSH, TCP_RST,
+    TCP_SYN,
+};
+use super::{pollfd, HostFwd, IpQueue, VirtualNet, FWD_PORT_START};
+
+const TCP_MSS: u16 = 1460;
+/// MSS assumed if the peer does not announce it.
+const TCP_DEFAULT_MSS: u16 = 536;
+/// Window advertised to guest. The data is written to the host socket directly.
+const TCP_WINDOW: u16 = 65535;
+/// Max size of the data read from host but not acknowledged by guest.
+const TCP_SEND_BUF_SIZE: usize = 256 * 1024;
+const TCP_READ_SIZE: usize = 16 * 1024;
+const TCP_RTO_INIT: Duration = Duration::from_secs(1);
+const TCP_RTO_MAX: Duration = Duration::from_secs(16);
+const TCP_MAX_RETRIES: u32 = 8;
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TcpState {
+    /// SYN received from guest, connecting to the host.
+    Connecting,
+    /// SYN of the host forwarding connection sent to guest.
+    SynSent,
+    /// SYN-ACK sent to guest.
+    SynReceived,
+    Established,
+    Closed,
+}
+
+#[derive(Clone, Copy, Hash, PartialEq, Eq)]
+struct TcpKey {
+    guest: Ipv4Addr,
+    guest_port: u16,
+    remote: Ipv4Addr,
+    remote_port: u16,
+}
+
+impl TcpKey {
+    /// Addresses of the segment sent to guest.
+    fn guest_addr(&self) -> TcpAddr {
+        TcpAddr {
+            src: self.remote,
+            src_port: self.remote_port,
+            dst: self.guest,
+            dst_port: self.guest_port,
+        }
+    }
+}
+
+struct TcpConn {
+    key: TcpKey,
+    stream: TcpStream,
+    state: TcpState,
+    /// Oldest sequence number not acknowledged by guest.
+    snd_una: u32,
+    /// Next sequence number to send to guest.
+    snd_nxt: u32,
+    /// Next sequence number expected from guest.
+    rcv_nxt: u32,
+    /// Data from `snd_una` read from host.
+    send_buf: VecDeque<u8>,
+    guest_window: u32,
+    guest_mss: u16,
+    /// The host closed the write side of the connection.
+    host_eof: bool,
+    fin_sent: bool,
+    fin_acked: bool,
+    guest_fin: bool,
+    rto: Duration,
+    retries: u32,
+    /// Deadline of retransmission.
+    timer: Option<Instant>,
+    created: Instant,
+}
+
+impl TcpConn {
+    fn new(key: TcpKey, stream: TcpStream, state: TcpState, now: Instant) -> Self {
+        let iss = initial_seq();
+        TcpConn {
+            key,
+            stream,
+            state,
+            snd_una: iss,
+            snd_nxt: iss,
+            rcv_nxt: 0,
+            send_buf: VecDeque::new(),
+            guest_window: 0,
+            guest_mss: TCP_DEFAULT_MSS,
+            host_eof: false,
+            fin_sent: false,
+            fin_acked: false,
+            guest_fin: false,
+            rto: TCP_RTO_INIT,
+            retries: 0,
+            timer: None,
+            created: now,
+        }
+    }
+
+    fn emit(&self, out: &mut IpQueue, seq: u32, flags: u8, mss: Option<u16>, data: &[u8]) {
+        let ack = if flags & TCP_ACK != 0 {
+            self.rcv_nxt
+        } else {
+            0
+        };
+        let addr = self.key.guest_addr();
+        let seg = build_tcp_segment(&addr, seq, ack, flags, TCP_WINDOW, mss, data);
+        out.push(addr.src, addr.dst, IPPROTO_TCP, &seg);
+    }
+
+    fn reset(&mut self, out: &mut IpQueue) {
+        self.emit(out, self.snd_nxt, TCP_RST | TCP_ACK, None, &[]);
+        self.state = TcpState::Closed;
+    }
+
+    fn send_syn(&mut self, out: &mut IpQueue, now: Instant) {
+        let flags = if self.state == TcpState::SynSent {
+            TCP_SYN
+        } else {
+            TCP_SYN | TCP_ACK
+        };
+        self.emit(out, self.snd_una, flags, Some(TCP_MSS), &[]);
+        self.snd_nxt = self.snd_una.wrapping_add(1);
+        self.timer = Some(now + self.rto);
+    }
+
+    fn update_peer(&mut self, seg: &TcpSegment) {
+        self.guest_window = seg.window as u32;
+        if let Some(mss) = seg.mss {
+            self.guest_mss = mss.clamp(TCP_DEFAULT_MSS, TCP_MSS);
+        }
+    }
+
+    fn process_ack(&mut self, ack: u32, now: Instant) {
+        let acked = ack.wrapping_sub(self.snd_una) as usize;
+        let inflight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+        if acked == 0 || acked > inflight {
+            return;
+        }
+        let data_acked = acked.min(self.send_buf.len());
+        self.send_buf.drain(..data_acked);
+        if acked > data_acked {
+            self.fin_acked = true;
+        }
+        self.snd_una = ack;
+        self.rto = TCP_RTO_INIT;
+        self.retries = 0;
+        self.timer = if self.snd_una != self.snd_nxt {
+            Some(now + self.rto)
+        } else {
+            None
+        };
+    }
+
+    /// Handle the segment from guest.
+    fn input(&mut self, seg: &TcpSegment, out: &mut IpQueue, now: Instant) {
+        if seg.flags & TCP_RST != 0 {
+            self.state = TcpState::Closed;
+            return;
+        }
+
+        match self.state {
+            TcpState::Connecting | TcpState::Closed => return,
+            TcpState::SynSent => {
+                if seg.flags & (TCP_SYN | TCP_ACK) == TCP_SYN | TCP_ACK && seg.ack == self.snd_nxt {
+                    self.rcv_nxt = seg.seq.wrapping_add(1);
+                    self.update_peer(seg);
+                    self.process_ack(seg.ack, now);
+                    self.state = TcpState::Established;
+                    self.emit(out, self.snd_nxt, TCP_ACK, None, &[]);
+                }
+                return;
+            }
+            TcpState::SynReceived => {
+                if seg.flags & TCP_SYN != 0 {
+                    // SYN-ACK is lost, send it again.
+                    self.send_syn(out, now);
+                    return;
+                }
+                if seg.flags & TCP_ACK == 0 || seg.ack != self.snd_nxt {
+                    return;
+                }
+                self.process_ack(seg.ack, now);
+                self.state = TcpState::Established;
+            }
+            TcpState::Established => {}
+        }
+
+        if seg.flags & TCP_ACK != 0 {
+            self.process_ack(seg.ack, now);
+        }
+        self.guest_window = seg.window as u32;
+
+        let mut need_ack = false;
+        if !seg.data.is_empty() {
+            need_ack = true;
+            if seg.seq == self.rcv_nxt && !self.guest_fin {
+                // Only the data written to host is acknowledged, guest sends the rest again.
+                match self.stream.write(seg.data) {
+                    Ok(len) => self.rcv_nxt = self.rcv_nxt.wrapping_add(len as u32),
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+                    Err(e) => {
+                        error!("Failed to write tcp stream: {:?}", e);
+                        self.reset(out);
+                        return;
+                    }
+                }
+            }
+        }
+
+        if seg.flags & TCP_FIN != 0 {
+            need_ack = true;
+            let fin_seq = seg.seq.wrapping_add(seg.data.len() as u32);
+            if !self.guest_fin && fin_seq == self.rcv_nxt {
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+                self.guest_fin = true;
+                let _ = self.stream.shutdown(Shutdown::Write);
+            }
+        }
+
+        if need_ack {
+            self.emit(out, self.snd_nxt, TCP_ACK, None, &[]);
+        }
+        self.transmit(out, now);
+        if self.guest_fin && self.fin_acked {
+            self.state = TcpState::Closed;
+        }
+    }
+
+    /// Send the data of host to guest within the window of guest.
+    fn transmit(&mut self, out: &mut IpQueue, now: Instant) {
+        if self.state != TcpState::Established {
+            return;
+        }
+
+        loop {
+            let offset = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            let window = self.guest_window as usize;
+            if offset >= self.send_buf.len() || offset >= window {
+                break;
+            }
+            let len = (self.send_buf.len() - offset)
+                .min(self.guest_mss as usize)
+                .min(window - offset);
+            let data: Vec<u8> = self.send_buf.range(offset..offset + len).copied().collect();
+            self.emit(out, self.snd_nxt, TCP_ACK | TCP_PSH, None, &data);
+            self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+            self.timer.get_or_insert(now + self.rto);
+        }
+
+        let offset = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+        if self.host_eof && !self.fin_sent && offset == self.send_buf.len() {
+            self.emit(out, self.snd_nxt, TCP_FIN | TCP_ACK, None, &[]);
+            self.snd_nxt = self.snd_nxt.wrapping_add(1);
+            self.fin_sent = true;
+            self.timer.get_or_insert(now + self.rto);
+        }
+    }
+
+    fn retransmit(&mut self, out: &mut IpQueue, now: Instant) {
+        match self.timer {
+            Some(deadline) if deadline <= now => {}
+            _ => return,
+        }
+        self.retries += 1;
+        if self.retries > TCP_MAX_RETRIES {
+            self.reset(out);
+            return;
+        }
+        self.rto = (self.rto * 2).min(TCP_RTO_MAX);
+        self.timer = None;
+        // Go back to the oldest unacknowledged data.
+        self.snd_nxt = self.snd_una;
+        match self.state {
+            TcpState::SynSent | TcpState::SynReceived => self.send_syn(out, now),
+            TcpState::Established => {
+                if !self.fin_acked {
+                    self.fin_sent = false;
+                }
+                self.transmit(out, now);
+            }
+            _ => {}
+        }
+    }
+
+    fn poll(&mut self, out: &mut IpQueue, now: Instant) {
+        match self.state {
+            TcpState::Connecting => match self.stream.take_error() {
+                Ok(None) if self.stream.peer_addr().is_ok() => {
+                    self.state = TcpState::SynReceived;
+                    self.send_syn(out, now);
+                }
+                Ok(None) if now.duration_since(self.created) < TCP_CONNECT_TIMEOUT => {}
+                _ => self.reset(out),
+            },
+            TcpState::SynSent | TcpState::SynReceived => self.retransmit(out, now),
+            TcpState::Established => {
+                let mut buf = [0_u8; TCP_READ_SIZE];
+                while !self.host_eof && self.send_buf.len() < TCP_SEND_BUF_SIZE {
+                    let len = buf.len().min(TCP_SEND_BUF_SIZE - self.send_buf.len());
+                    match self.stream.read(&mut buf[..len]) {
+                        Ok(0) => self.host_eof = true,
+                        Ok(len) => self.send_buf.extend(&buf[..len]),
+                        Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                        Err(e) => {
+                            error!("Failed to read tcp stream: {:?}", e);
+                            self.reset(out);
+                            return;
+                        }
+                    }
+                }
+                self.retransmit(out, now);
+                self.transmit(out, now);
+                if self.guest_fin && self.fin_acked {
+                    self.state = TcpState::Closed;
+                }
+            }
+            TcpState::Closed => {}
+        }
+    }
+
+    fn pollfd(&self) -> Option<libc::pollfd> {
+        let events = match self.state {
+            TcpState::Connecting => libc::POLLOUT,
+            TcpState::Established if !self.host_eof && self.send_buf.len() < TCP_SEND_BUF_SIZE => {
+                libc::POLLIN
+            }
+            _ => return None,
+        };
+        Some(pollfd(self.stream.as_raw_fd(), events))
+    }
+}
+
+fn initial_seq() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u32)
+        .unwrap_or(0)
+}
+
+/// Connect to the address without blocking, the connection is completed later.
+fn connect_nonblocking(addr: SocketAddrV4) -> std::io::Result<TcpStream> {
+    // SAFETY: the arguments are valid and the fd is owned by the returned stream.
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_INET,
+            libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            0,
+        )
+    };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: fd is a valid socket created above.
+    let stream = unsafe { TcpStream::from_raw_fd(fd) };
+    let sockaddr = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: addr.port().to_be(),
+        sin_addr: libc::in_addr {
+            s_addr: u32::from(*addr.ip()).to_be(),
+        },
+        sin_zero: [0; 8],
+    };
+    // SAFETY: sockaddr is a valid sockaddr_in and the length is correct.
+    let ret = unsafe {
+        libc::connect(
+            fd,
+            &sockaddr as *const libc::sockaddr_in as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::EINPROGRESS) {
+            return Err(err);
+        }
+    }
+    Ok(stream)
+}
+
+/// Reply RST for the segment not belonging to any connection.
+fn reply_rst(key: &TcpKey, seg: &TcpSegment, out: &mut IpQueue) {
+    let addr = key.guest_addr();
+    let buf = if seg.flags & TCP_ACK != 0 {
+        build_tcp_segment(&addr, seg.ack, 0, TCP_RST, 0, None, &[])
+    } else {
+        let mut ack = seg.seq.wrapping_add(seg.data.len() as u32);
+        if seg.flags & TCP_SYN != 0 {
+            ack = ack.wrapping_add(1);
+        }
+        if seg.flags & TCP_FIN != 0 {
+            ack = ack.wrapping_add(1);
+        }
+        build_tcp_segment(&addr, 0, ack, TCP_RST | TCP_ACK, 0, None, &[])
+    };
+    out.push(addr.src, addr.dst, IPPROTO_TCP, &buf);
+}
+
+struct TcpFwd {
+    listener: TcpListener,
+    config: HostFwd,
+}
+
+#[derive(Default)]
+pub struct TcpTable {
+    conns: HashMap<TcpKey, TcpConn>,
+    fwds: Vec<TcpFwd>,
+    next_port: u16,
+}
+
+impl TcpTable {
+    pub fn add_hostfwd(&mut self, fwd: &HostFwd) -> Result<()> {
+        let addr = SocketAddrV4::new(fwd.host_addr, fwd.host_port);
+        let listener = TcpListener::bind(addr)
+            .with_context(|| format!("Failed to bind tcp hostfwd address {}", addr))?;
+        listener
+            .set_nonblocking(true)
+            .with_context(|| "Failed to set tcp hostfwd listener nonblocking")?;
+        self.fwds.push(TcpFwd {
+            listener,
+            config: fwd.clone(),
+        });
+        Ok(())
+    }
+
+    /// Handle the segment from guest.
+    pub fn input(
+        &mut self,
+        net: &VirtualNet,
+        src: Ipv4Addr,
+        dst: Ipv4Addr,
+        seg: &TcpSegment,
+        out: &mut IpQueue,
+    ) {
+        let key = TcpKey {
+            guest: src,
+            guest_port: seg.src_port,
+            remote: dst,
+            remote_port: seg.dst_port,
+        };
+        let now = Instant::now();
+
+        if let Some(conn) = self.conns.get_mut(&key) {
+            conn.input(seg, out, now);
+            if conn.state == TcpState::Closed {
+                self.conns.remove(&key);
+            }
+            return;
+        }
+
+        if seg.flags & TCP_RST != 0 {
+            return;
+        }
+        if seg.flags & (TCP_SYN | TCP_ACK) != TCP_SYN {
+            reply_rst(&key, seg, out);
+            return;
+        }
+
+        let stream = match net
+            .host_addr(dst)
+            .map(|addr| connect_nonblocking(SocketAddrV4::new(addr, seg.dst_port)))
+        {
+            Some(Ok(stream)) => stream,
+            _ => {
+                reply_rst(&key, seg, out);
+                return;
+            }
+        };
+        let mut conn = TcpConn::new(key, stream, TcpState::Connecting, now);
+        conn.rcv_nxt = seg.seq.wrapping_add(1);
+        conn.update_peer(seg);
+        self.conns.insert(key, conn);
+    }
+
+    fn alloc_port(&mut self, net: &VirtualNet, guest_port: u16) -> u16 {
+        loop {
+            if self.next_port < FWD_PORT_START {
+                self.next_port = FWD_PORT_START;
+            }
+            let port = self.next_port;
+            self.next_port = self.next_port.wrapping_add(1);
+            if !self.conns.keys().any(|k| {
+                k.remote == net.config.host && k.remote_port == port && k.guest_port == guest_port
+            }) {
+                return port;
+            }
+        }
+    }
+
+    /// Accept the host forwarding connections and relay the data of host.
+    pub fn poll(&mut self, net: &VirtualNet, now: Instant, out: &mut IpQueue) {
+        for index in 0..self.fwds.len() {
+            while let Ok((stream, _)) = self.fwds[index].listener.accept() {
+                if let Err(e) = stream.set_nonblocking(true) {
+                    error!("Failed to set tcp stream nonblocking: {:?}", e);
+                    continue;
+                }
+                let fwd = self.fwds[index].config.clone();
+                let key = TcpKey {
+                    guest: net.fwd_guest_addr(fwd.guest_addr),
+                    guest_port: fwd.guest_port,
+                    remote: net.config.host,
+                    remote_port: self.alloc_port(net, fwd.guest_port),
+                };
+                let mut conn = TcpConn::new(key, stream, TcpState::SynSent, now);
+                conn.send_syn(out, now);
+                self.conns.insert(key, conn);
+            }
+        }
+
+        for conn in self.conns.values_mut() {
+            conn.poll(out, now);
+        }
+        self.conns.retain(|_, c| c.state != TcpState::Closed);
+    }
+
+    pub fn pollfds(&self, fds: &mut Vec<libc::pollfd>) {
+        fds.extend(self.conns.values().filter_map(|c| c.pollfd()));
+        fds.extend(
+            self.fwds
+                .iter()
+                .map(|f| pollfd(f.listener.as_raw_fd(), libc::POLLIN)),
+        );
+    }
+}
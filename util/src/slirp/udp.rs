@@ -0,0 +1,237 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! UDP NAT over the host sockets.
+
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use log::error;
+
+use super::packet::{build_udp_packet, IPPROTO_UDP};
+use super::{pollfd, HostFwd, IpQueue, VirtualNet, FWD_PORT_START};
+
+/// The socket is closed if there is no datagram for this time.
+const UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+const UDP_BUF_SIZE: usize = 65536;
+
+#[derive(Clone, Copy, Hash, PartialEq, Eq)]
+struct UdpKey {
+    guest: Ipv4Addr,
+    guest_port: u16,
+    remote: Ipv4Addr,
+    remote_port: u16,
+}
+
+/// Datagrams of the guest sent by the connected host socket.
+struct UdpConn {
+    socket: UdpSocket,
+    last_active: Instant,
+}
+
+/// Datagrams from the peer of the host forwarding socket. The peer is seen
+/// by guest as the gateway with the allocated port.
+struct UdpFwdConn {
+    fwd_index: usize,
+    peer: SocketAddr,
+    key: UdpKey,
+    last_active: Instant,
+}
+
+struct UdpFwd {
+    socket: UdpSocket,
+    config: HostFwd,
+}
+
+#[derive(Default)]
+pub struct UdpTable {
+    conns: HashMap<UdpKey, UdpConn>,
+    fwds: Vec<UdpFwd>,
+    fwd_conns: Vec<UdpFwdConn>,
+    next_port: u16,
+}
+
+impl UdpTable {
+    pub fn add_hostfwd(&mut self, fwd: &HostFwd) -> Result<()> {
+        let addr = SocketAddrV4::new(fwd.host_addr, fwd.host_port);
+        let socket = UdpSocket::bind(addr)
+            .with_context(|| format!("Failed to bind udp hostfwd address {}", addr))?;
+        socket
+            .set_nonblocking(true)
+            .with_context(|| "Failed to set udp hostfwd socket nonblocking")?;
+        self.fwds.push(UdpFwd {
+            socket,
+            config: fwd.clone(),
+        });
+        Ok(())
+    }
+
+    /// Send the datagram of guest to the host socket.
+    pub fn input(
+        &mut self,
+        net: &VirtualNet,
+        src: Ipv4Addr,
+        dst: Ipv4Addr,
+        src_port: u16,
+        dst_port: u16,
+        data: &[u8],
+    ) {
+        let key = UdpKey {
+            guest: src,
+            guest_port: src_port,
+            remote: dst,
+            remote_port: dst_port,
+        };
+
+        if let Some(conn) = self.fwd_conns.iter_mut().find(|c| c.key == key) {
+            conn.last_active = Instant::now();
+            if let Err(e) = self.fwds[conn.fwd_index].socket.send_to(data, conn.peer) {
+                error!("Failed to send udp datagram to {}: {:?}", conn.peer, e);
+            }
+            return;
+        }
+
+        let conn = match self.conns.get_mut(&key) {
+            Some(conn) => conn,
+            None => {
+                let host_addr = match net.host_addr(dst) {
+                    Some(addr) => addr,
+                    None => return,
+                };
+                let socket = match Self::connect(SocketAddrV4::new(host_addr, dst_port)) {
+                    Ok(socket) => socket,
+                    Err(e) => {
+                        error!("{:?}", e);
+                        return;
+                    }
+                };
+                self.conns.entry(key).or_insert(UdpConn {
+                    socket,
+                    last_active: Instant::now(),
+                })
+            }
+        };
+        conn.last_active = Instant::now();
+        if let Err(e) = conn.socket.send(data) {
+            if e.kind() != ErrorKind::WouldBlock {
+                error!("Failed to send udp datagram: {:?}", e);
+            }
+        }
+    }
+
+    fn connect(addr: SocketAddrV4) -> Result<UdpSocket> {
+        let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))
+            .with_context(|| "Failed to bind udp socket")?;
+        socket
+            .connect(addr)
+            .with_context(|| format!("Failed to connect udp socket to {}", addr))?;
+        socket
+            .set_nonblocking(true)
+            .with_context(|| "Failed to set udp socket nonblocking")?;
+        Ok(socket)
+    }
+
+    fn alloc_port(&mut self, net: &VirtualNet) -> u16 {
+        loop {
+            if self.next_port < FWD_PORT_START {
+                self.next_port = FWD_PORT_START;
+            }
+            let port = self.next_port;
+            self.next_port = self.next_port.wrapping_add(1);
+            if !self
+                .fwd_conns
+                .iter()
+                .any(|c| c.key.remote == net.config.host && c.key.remote_port == port)
+            {
+                return port;
+            }
+        }
+    }
+
+    /// Receive the datagrams from host sockets and expire the idle sockets.
+    pub fn poll(&mut self, net: &VirtualNet, now: Instant, out: &mut IpQueue) {
+        let mut buf = vec![0_u8; UDP_BUF_SIZE];
+        for (key, conn) in self.conns.iter_mut() {
+            while let Ok(len) = conn.socket.recv(&mut buf) {
+                conn.last_active = now;
+                let udp = build_udp_packet(
+                    key.remote,
+                    key.remote_port,
+                    key.guest,
+                    key.guest_port,
+                    &buf[..len],
+                );
+                out.push(key.remote, key.guest, IPPROTO_UDP, &udp);
+            }
+        }
+
+        for index in 0..self.fwds.len() {
+            while let Ok((len, peer)) = self.fwds[index].socket.recv_from(&mut buf) {
+                let pos = match self
+                    .fwd_conns
+                    .iter()
+                    .position(|c| c.fwd_index == index && c.peer == peer)
+                {
+                    Some(pos) => pos,
+                    None => {
+                        let remote_port = self.alloc_port(net);
+                        let fwd = &self.fwds[index].config;
+                        let key = UdpKey {
+                            guest: net.fwd_guest_addr(fwd.guest_addr),
+                            guest_port: fwd.guest_port,
+                            remote: net.config.host,
+                            remote_port,
+                        };
+                        self.fwd_conns.push(UdpFwdConn {
+                            fwd_index: index,
+                            peer,
+                            key,
+                            last_active: now,
+                        });
+                        self.fwd_conns.len() - 1
+                    }
+                };
+                let conn = &mut self.fwd_conns[pos];
+                conn.last_active = now;
+                let key = conn.key;
+                let udp = build_udp_packet(
+                    key.remote,
+                    key.remote_port,
+                    key.guest,
+                    key.guest_port,
+                    &buf[..len],
+                );
+                out.push(key.remote, key.guest, IPPROTO_UDP, &udp);
+            }
+        }
+
+        self.conns
+            .retain(|_, c| now.duration_since(c.last_active) < UDP_IDLE_TIMEOUT);
+        self.fwd_conns
+            .retain(|c| now.duration_since(c.last_active) < UDP_IDLE_TIMEOUT);
+    }
+
+    pub fn pollfds(&self, fds: &mut Vec<libc::pollfd>) {
+        let sockets = self
+            .conns
+            .values()
+            .map(|c| c.socket.as_raw_fd())
+            .chain(self.fwds.iter().map(|f| f.socket.as_raw_fd()));
+        for fd in sockets {
+            fds.push(pollfd(fd, libc::POLLIN));
+        }
+    }
+}
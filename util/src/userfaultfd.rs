@@ -0,0 +1,231 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+
+use anyhow::{bail, Context, Result};
+use vmm_sys_util::ioctl::ioctl_with_mut_ref;
+use vmm_sys_util::{ioctl_ioc_nr, ioctl_ior_nr, ioctl_iowr_nr};
+
+use crate::unix::host_page_size;
+
+const UFFD_API: u64 = 0xAA;
+const UFFDIO: u32 = 0xAA;
+const UFFD_EVENT_PAGEFAULT: u8 = 0x12;
+const UFFDIO_REGISTER_MODE_MISSING: u64 = 1;
+
+ioctl_iowr_nr!(UFFDIO_API, UFFDIO, 0x3f, UffdioApi);
+ioctl_iowr_nr!(UFFDIO_REGISTER, UFFDIO, 0x00, UffdioRegister);
+ioctl_ior_nr!(UFFDIO_UNREGISTER, UFFDIO, 0x01, UffdioRange);
+ioctl_iowr_nr!(UFFDIO_COPY, UFFDIO, 0x03, UffdioCopy);
+
+#[repr(C)]
+#[derive(Default)]
+pub struct UffdioApi {
+    api: u64,
+    features: u64,
+    ioctls: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct UffdioRange {
+    start: u64,
+    len: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct UffdioRegister {
+    range: UffdioRange,
+    mode: u64,
+    ioctls: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct UffdioCopy {
+    dst: u64,
+    src: u64,
+    len: u64,
+    mode: u64,
+    copy: i64,
+}
+
+/// Size of `struct uffd_msg` in linux/userfaultfd.h.
+const UFFD_MSG_SIZE: usize = 32;
+
+/// Userfaultfd to handle missing pages of registered memory in userspace.
+pub struct Userfaultfd {
+    file: File,
+}
+
+impl Userfaultfd {
+    /// Create a non-blocking userfaultfd and negotiate the API with kernel.
+    pub fn new() -> Result<Self> {
+        // SAFETY: The syscall has no memory side effect.
+        let fd =
+            unsafe { libc::syscall(libc::SYS_userfaultfd, libc::O_CLOEXEC | libc::O_NONBLOCK) };
+        if fd < 0 {
+            return Err(Error::last_os_error()).with_context(|| "Failed to create userfaultfd");
+        }
+        // SAFETY: The fd is just created and owned by the file.
+        let file = unsafe { File::from_raw_fd(fd as RawFd) };
+
+        let mut api = UffdioApi {
+            api: UFFD_API,
+            ..Default::default()
+        };
+        // SAFETY: The file is a userfaultfd and the argument is valid.
+        let ret = unsafe { ioctl_with_mut_ref(&file, UFFDIO_API(), &mut api) };
+        if ret < 0 {
+            return Err(Error::last_os_error()).with_context(|| "Failed to set userfaultfd API");
+        }
+
+        Ok(Userfaultfd { file })
+    }
+
+    /// Register a memory range to report missing pages.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - Host virtual address of the range, aligned to page size.
+    /// * `len` - Length of the range, aligned to page size.
+    pub fn register(&self, addr: u64, len: u64) -> Result<()> {
+        let mut reg = UffdioRegister {
+            range: UffdioRange { start: addr, len },
+            mode: UFFDIO_REGISTER_MODE_MISSING,
+            ioctls: 0,
+        };
+        // SAFETY: The file is a userfaultfd and the argument is valid.
+        let ret = unsafe { ioctl_with_mut_ref(&self.file, UFFDIO_REGISTER(), &mut reg) };
+        if ret < 0 {
+            return Err(Error::last_os_error()).with_context(|| {
+                format!(
+                    "Failed to register memory 0x{:x} len 0x{:x} to userfaultfd",
+                    addr, len
+                )
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Unregister a memory range, the missing pages are zero filled later.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - Host virtual address of the range.
+    /// * `len` - Length of the range.
+    pub fn unregister(&self, addr: u64, len: u64) -> Result<()> {
+        let mut range = UffdioRange { start: addr, len };
+        // SAFETY: The file is a userfaultfd and the argument is valid.
+        let ret = unsafe { ioctl_with_mut_ref(&self.file, UFFDIO_UNREGISTER(), &mut range) };
+        if ret < 0 {
+            return Err(Error::last_os_error()).with_context(|| {
+                format!(
+                    "Failed to unregister memory 0x{:x} len 0x{:x} from userfaultfd",
+                    addr, len
+                )
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Atomically copy data to the missing pages and wake up the faulting
+    /// threads. It's not an error if some pages are already present.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - Host virtual address of the missing pages.
+    /// * `data` - Data of the pages, its length is aligned to page size.
+    pub fn copy(&self, addr: u64, data: &[u8]) -> Result<()> {
+        let mut offset = 0;
+        while offset < data.len() {
+            let mut copy = UffdioCopy {
+                dst: addr + offset as u64,
+                src: data[offset..].as_ptr() as u64,
+                len: (data.len() - offset) as u64,
+                ..Default::default()
+            };
+            // SAFETY: The file is a userfaultfd and the source buffer is valid
+            // for `len` bytes.
+            let ret = unsafe { ioctl_with_mut_ref(&self.file, UFFDIO_COPY(), &mut copy) };
+            if ret < 0 {
+                let err = Error::last_os_error();
+                match err.raw_os_error() {
+                    // Part of the range is copied, retry the rest.
+                    Some(libc::EAGAIN) if copy.copy > 0 => {}
+                    // The first page is already present, skip it.
+                    Some(libc::EEXIST) => {
+                        copy.copy = host_page_size().min(copy.len) as i64;
+                    }
+                    _ => {
+                        return Err(err).with_context(|| {
+                            format!("Failed to copy page to 0x{:x} by userfaultfd", copy.dst)
+                        })
+                    }
+                }
+            }
+            if copy.copy <= 0 {
+                bail!("Failed to copy page to 0x{:x} by userfaultfd", copy.dst);
+            }
+            offset += copy.copy as usize;
+        }
+
+        Ok(())
+    }
+
+    /// Read a page fault event and return the faulting host virtual address.
+    /// Return None if there is no event right now.
+    pub fn read_fault(&self) -> Result<Option<u64>> {
+        let mut msg = [0_u8; UFFD_MSG_SIZE];
+        match (&self.file).read(&mut msg) {
+            Ok(UFFD_MSG_SIZE) => {}
+            Ok(len) => bail!("Invalid userfaultfd message length {}", len),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+            Err(e) => return Err(e).with_context(|| "Failed to read userfaultfd"),
+        }
+        // The message starts with 8 bytes of event type, and the page fault
+        // event is followed by 8 bytes of flags and 8 bytes of address.
+        if msg[0] != UFFD_EVENT_PAGEFAULT {
+            bail!("Unexpected userfaultfd event 0x{:x}", msg[0]);
+        }
+        let address = u64::from_ne_bytes(msg[16..24].try_into().unwrap());
+
+        Ok(Some(address))
+    }
+}
+
+impl AsRawFd for Userfaultfd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+
+    use super::*;
+
+    #[test]
+    fn test_uffd_layout() {
+        // Structures are defined in linux/userfaultfd.h.
+        assert_eq!(size_of::<UffdioApi>(), 24);
+        assert_eq!(size_of::<UffdioCopy>(), 40);
+        assert_eq!(size_of::<UffdioRegister>(), 32);
+    }
+}
@@ -14,6 +14,7 @@ pub mod error;
 pub use error::VfioError;
 
 mod vfio_dev;
+mod vfio_migration;
 mod vfio_pci;
 
 pub use vfio_dev::{
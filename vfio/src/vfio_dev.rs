@@ -14,7 +14,7 @@ use log::warn;
 use std::collections::HashMap;
 use std::ffi::CString;
 use std::fs::{File, OpenOptions};
-use std::mem::size_of;
+use std::mem::{size_of, size_of_val};
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::os::unix::prelude::FileExt;
 use std::path::{Path, PathBuf};
@@ -34,6 +34,9 @@ use vmm_sys_util::{ioctl_io_nr, ioctl_ioc_nr};
 use super::{CONTAINERS, GROUPS, KVM_DEVICE_FD};
 use crate::VfioError;
 use anyhow::{anyhow, bail, Context, Result};
+use migration::{DirtyPageTracker, MigrationManager};
+use util::num_ops::div_round_up;
+use util::unix::host_page_size;
 
 /// Refer to VFIO in https://github.com/torvalds/linux/blob/master/include/uapi/linux/vfio.h
 const IOMMU_GROUP: &str = "iommu_group";
@@ -94,6 +97,85 @@ ioctl_io_nr!(
     vfio::VFIO_TYPE,
     vfio::VFIO_BASE + 0x0e
 );
+ioctl_io_nr!(VFIO_DEVICE_FEATURE, vfio::VFIO_TYPE, vfio::VFIO_BASE + 0x11);
+ioctl_io_nr!(
+    VFIO_IOMMU_DIRTY_PAGES,
+    vfio::VFIO_TYPE,
+    vfio::VFIO_BASE + 0x11
+);
+ioctl_io_nr!(
+    VFIO_MIG_GET_PRECOPY_INFO,
+    vfio::VFIO_TYPE,
+    vfio::VFIO_BASE + 0x15
+);
+
+/// Device features and migration states of VFIO migration v2 protocol, which are not
+/// included in vfio-bindings.
+const VFIO_DEVICE_FEATURE_GET: u32 = 1 << 16;
+const VFIO_DEVICE_FEATURE_SET: u32 = 1 << 17;
+const VFIO_DEVICE_FEATURE_MIGRATION: u32 = 1;
+const VFIO_DEVICE_FEATURE_MIG_DEVICE_STATE: u32 = 2;
+pub const VFIO_MIGRATION_STOP_COPY: u64 = 1 << 0;
+pub const VFIO_MIGRATION_PRE_COPY: u64 = 1 << 2;
+pub const VFIO_DEVICE_STATE_ERROR: u32 = 0;
+pub const VFIO_DEVICE_STATE_RUNNING: u32 = 2;
+pub const VFIO_DEVICE_STATE_STOP_COPY: u32 = 3;
+pub const VFIO_DEVICE_STATE_RESUMING: u32 = 4;
+pub const VFIO_DEVICE_STATE_PRE_COPY: u32 = 6;
+
+const VFIO_IOMMU_DIRTY_PAGES_FLAG_START: u32 = 1 << 0;
+const VFIO_IOMMU_DIRTY_PAGES_FLAG_STOP: u32 = 1 << 1;
+const VFIO_IOMMU_DIRTY_PAGES_FLAG_GET_BITMAP: u32 = 1 << 2;
+
+/// `struct vfio_device_feature` followed by `struct vfio_device_feature_migration`.
+#[repr(C)]
+#[derive(Debug, Default)]
+struct VfioDeviceFeatureMigration {
+    argsz: u32,
+    flags: u32,
+    migration_flags: u64,
+}
+
+/// `struct vfio_device_feature` followed by `struct vfio_device_feature_mig_state`.
+#[repr(C)]
+#[derive(Debug, Default)]
+struct VfioDeviceFeatureMigState {
+    argsz: u32,
+    flags: u32,
+    device_state: u32,
+    data_fd: i32,
+}
+
+/// `struct vfio_precopy_info`, the length of data available to read in PRE_COPY state.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct VfioPrecopyInfo {
+    argsz: u32,
+    flags: u32,
+    pub initial_bytes: u64,
+    pub dirty_bytes: u64,
+}
+
+/// `struct vfio_iommu_type1_dirty_bitmap` without data.
+#[repr(C)]
+#[derive(Debug, Default)]
+struct VfioIommuDirtyBitmap {
+    argsz: u32,
+    flags: u32,
+}
+
+/// `struct vfio_iommu_type1_dirty_bitmap` followed by `struct vfio_iommu_type1_dirty_bitmap_get`.
+#[repr(C)]
+#[derive(Debug, Default)]
+struct VfioIommuDirtyBitmapGet {
+    argsz: u32,
+    flags: u32,
+    iova: u64,
+    size: u64,
+    pgsize: u64,
+    bitmap_size: u64,
+    bitmap_data: u64,
+}
 
 /// Vfio container class can hold one or more groups. In IOMMUs, page tables are shared between
 /// different groups, vfio container can reduce TLB thrashing and duplicate page tables.
@@ -226,6 +308,24 @@ impl VfioContainer {
         Ok(())
     }
 
+    /// Start or stop tracking the pages dirtied by DMA of devices in the container.
+    fn set_dirty_pages(&self, flags: u32) -> Result<()> {
+        let dirty = VfioIommuDirtyBitmap {
+            argsz: size_of::<VfioIommuDirtyBitmap>() as u32,
+            flags,
+        };
+
+        // Ioctl is safe. Called container file is `/dev/vfio/vfio` fd and we check the return.
+        let ret = unsafe { ioctl_with_ref(&self.fd, VFIO_IOMMU_DIRTY_PAGES(), &dirty) };
+        if ret != 0 {
+            return Err(anyhow!(VfioError::VfioIoctl(
+                "VFIO_IOMMU_DIRTY_PAGES".to_string(),
+                std::io::Error::last_os_error(),
+            )));
+        }
+        Ok(())
+    }
+
     fn add_listener_region(&self, fr: &FlatRange) -> address_space::Result<()> {
         if fr.owner.region_type() != address_space::RegionType::Ram {
             return Ok(());
@@ -303,6 +403,42 @@ impl Listener for VfioContainer {
     }
 }
 
+impl DirtyPageTracker for VfioContainer {
+    fn start_dirty_tracking(&self) -> Result<()> {
+        self.set_dirty_pages(VFIO_IOMMU_DIRTY_PAGES_FLAG_START)
+    }
+
+    fn stop_dirty_tracking(&self) -> Result<()> {
+        self.set_dirty_pages(VFIO_IOMMU_DIRTY_PAGES_FLAG_STOP)
+    }
+
+    fn get_dirty_bitmap(&self, gpa: u64, len: u64) -> Result<Vec<u64>> {
+        let page_size = host_page_size();
+        let pages = div_round_up(len, page_size);
+        let bitmap = vec![0_u64; div_round_up(pages, 64) as usize];
+        let get = VfioIommuDirtyBitmapGet {
+            argsz: size_of::<VfioIommuDirtyBitmapGet>() as u32,
+            flags: VFIO_IOMMU_DIRTY_PAGES_FLAG_GET_BITMAP,
+            iova: gpa,
+            size: len,
+            pgsize: page_size,
+            bitmap_size: size_of_val(bitmap.as_slice()) as u64,
+            bitmap_data: bitmap.as_ptr() as u64,
+        };
+
+        // Ioctl is safe. Called container file is `/dev/vfio/vfio` fd, the bitmap is large
+        // enough for the pages and we check the return.
+        let ret = unsafe { ioctl_with_ref(&self.fd, VFIO_IOMMU_DIRTY_PAGES(), &get) };
+        if ret != 0 {
+            return Err(anyhow!(VfioError::VfioIoctl(
+                "VFIO_IOMMU_DIRTY_PAGES".to_string(),
+                std::io::Error::last_os_error(),
+            )));
+        }
+        Ok(bitmap)
+    }
+}
+
 /// Vfio group is a member of IOMMU group, which contains a set of devices isolated from all
 /// other devices in the system.
 /// A vfio group can be created by opening `/dev/vfio/$group_id`, where $group_id represents the
@@ -437,6 +573,7 @@ impl VfioGroup {
                 .set_iommu(vfio::VFIO_TYPE1v2_IOMMU)?;
 
             let fd = container.lock().unwrap().fd.as_raw_fd();
+            MigrationManager::register_dirty_tracker(container.clone(), &dirty_tracker_id(fd));
             CONTAINERS.lock().unwrap().insert(fd, container);
         }
         self.add_to_kvm_device()?;
@@ -465,7 +602,7 @@ pub struct VfioDevice {
     pub container: Weak<Mutex<VfioContainer>>,
     /// Information of the vfio device instance.
     pub dev_info: VfioDevInfo,
-    /// Unmasked MSI-X vectors.
+    /// Unmasked MSI or MSI-X vectors.
     pub nr_vectors: usize,
 }
 
@@ -497,7 +634,7 @@ struct VfioRegionWithCap {
 
 #[allow(dead_code)]
 pub struct VfioIrq {
-    count: u32,
+    pub count: u32,
     flags: u32,
     index: u32,
 }
@@ -787,28 +924,32 @@ impl VfioDevice {
         Ok(())
     }
 
-    /// Bind irqs to kvm interrupts.
+    /// Set eventfds or flags of irqs by `VFIO_DEVICE_SET_IRQS`.
     ///
     /// # Arguments
     ///
-    /// * `irq_fds` - Irq fds that will be registered to kvm.
+    /// * `index` - Irq index, such as INTx, MSI and MSI-X.
+    /// * `action` - Action to set, such as trigger and unmask.
+    /// * `irq_fds` - Eventfds of the subindexes, no eventfd is set if it is empty.
     /// * `start` - The start of subindexes being specified.
-    pub fn enable_irqs(&mut self, irq_fds: Vec<RawFd>, start: u32) -> Result<()> {
+    fn set_irqs(&self, index: u32, action: u32, irq_fds: &[RawFd], start: u32) -> Result<()> {
+        let data_size = size_of_val(irq_fds);
         let mut irq_set = array_to_vec::<vfio::vfio_irq_set, u32>(irq_fds.len());
-        irq_set[0].argsz =
-            (size_of::<vfio::vfio_irq_set>() + irq_fds.len() * size_of::<RawFd>()) as u32;
-        irq_set[0].flags = vfio::VFIO_IRQ_SET_DATA_EVENTFD | vfio::VFIO_IRQ_SET_ACTION_TRIGGER;
-        irq_set[0].index = vfio::VFIO_PCI_MSIX_IRQ_INDEX;
+        irq_set[0].argsz = (size_of::<vfio::vfio_irq_set>() + data_size) as u32;
+        irq_set[0].flags = if irq_fds.is_empty() {
+            vfio::VFIO_IRQ_SET_DATA_NONE | action
+        } else {
+            vfio::VFIO_IRQ_SET_DATA_EVENTFD | action
+        };
+        irq_set[0].index = index;
         irq_set[0].start = start;
         irq_set[0].count = irq_fds.len() as u32;
 
-        // It is safe as enough memory space to save irq_set data.
-        let data: &mut [u8] = unsafe {
-            irq_set[0]
-                .data
-                .as_mut_slice(irq_fds.len() * size_of::<RawFd>())
-        };
-        LittleEndian::write_i32_into(irq_fds.as_slice(), data);
+        if !irq_fds.is_empty() {
+            // It is safe as enough memory space to save irq_set data.
+            let data: &mut [u8] = unsafe { irq_set[0].data.as_mut_slice(data_size) };
+            LittleEndian::write_i32_into(irq_fds, data);
+        }
         // Safe as device is the owner of file, and we will verify the result is valid.
         let ret = unsafe { ioctl_with_ref(&self.fd, VFIO_DEVICE_SET_IRQS(), &irq_set[0]) };
         if ret < 0 {
@@ -820,33 +961,150 @@ impl VfioDevice {
         Ok(())
     }
 
-    /// Unbind irqs from kvm interrupts.
+    /// Bind irqs to kvm interrupts.
     ///
     /// # Arguments
     ///
+    /// * `index` - Irq index, `VFIO_PCI_MSI_IRQ_INDEX` or `VFIO_PCI_MSIX_IRQ_INDEX`.
     /// * `irq_fds` - Irq fds that will be registered to kvm.
-    pub fn disable_irqs(&mut self) -> Result<()> {
+    /// * `start` - The start of subindexes being specified.
+    pub fn enable_irqs(&mut self, index: u32, irq_fds: Vec<RawFd>, start: u32) -> Result<()> {
+        self.set_irqs(
+            index,
+            vfio::VFIO_IRQ_SET_ACTION_TRIGGER,
+            irq_fds.as_slice(),
+            start,
+        )
+    }
+
+    /// Unbind irqs from kvm interrupts.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Irq index, `VFIO_PCI_MSI_IRQ_INDEX` or `VFIO_PCI_MSIX_IRQ_INDEX`.
+    pub fn disable_irqs(&mut self, index: u32) -> Result<()> {
         if self.nr_vectors == 0 {
             return Ok(());
         }
 
-        let mut irq_set = array_to_vec::<vfio::vfio_irq_set, u32>(0);
-        irq_set[0].argsz = size_of::<vfio::vfio_irq_set>() as u32;
-        irq_set[0].flags = vfio::VFIO_IRQ_SET_DATA_NONE | vfio::VFIO_IRQ_SET_ACTION_TRIGGER;
-        irq_set[0].index = vfio::VFIO_PCI_MSIX_IRQ_INDEX;
-        irq_set[0].start = 0u32;
-        irq_set[0].count = 0u32;
+        self.set_irqs(index, vfio::VFIO_IRQ_SET_ACTION_TRIGGER, &[], 0)?;
+        self.nr_vectors = 0;
+        Ok(())
+    }
+
+    /// Bind INTx to a level-triggered kvm irqfd.
+    ///
+    /// # Arguments
+    ///
+    /// * `trigger_fd` - Eventfd signaled by vfio when the device asserts INTx.
+    /// * `unmask_fd` - Eventfd signaled by kvm when guest acknowledges the interrupt,
+    ///   which unmasks INTx in vfio.
+    pub fn enable_intx(&self, trigger_fd: RawFd, unmask_fd: RawFd) -> Result<()> {
+        self.set_irqs(
+            vfio::VFIO_PCI_INTX_IRQ_INDEX,
+            vfio::VFIO_IRQ_SET_ACTION_TRIGGER,
+            &[trigger_fd],
+            0,
+        )?;
+        self.set_irqs(
+            vfio::VFIO_PCI_INTX_IRQ_INDEX,
+            vfio::VFIO_IRQ_SET_ACTION_UNMASK,
+            &[unmask_fd],
+            0,
+        )
+    }
+
+    /// Unbind INTx from kvm irqfd.
+    pub fn disable_intx(&self) -> Result<()> {
+        self.set_irqs(
+            vfio::VFIO_PCI_INTX_IRQ_INDEX,
+            vfio::VFIO_IRQ_SET_ACTION_TRIGGER,
+            &[],
+            0,
+        )
+    }
+
+    /// Get the option ROM region of the vfio device, the size is zero if the device has no ROM.
+    pub fn rom_region_info(&self) -> Result<VfioRegion> {
+        let info = self
+            .region_info(vfio::VFIO_PCI_ROM_REGION_INDEX)
+            .with_context(|| "Fail to get ROM region info")?;
+        Ok(VfioRegion {
+            size: info.size,
+            region_offset: info.offset,
+            flags: info.flags,
+            mmaps: Vec::new(),
+            guest_phys_addr: 0,
+        })
+    }
+
+    /// Get the migration flags of the device, such as `VFIO_MIGRATION_STOP_COPY` and
+    /// `VFIO_MIGRATION_PRE_COPY`. Return Error if the device doesn't support migration.
+    pub fn migration_flags(&self) -> Result<u64> {
+        let mut feature = VfioDeviceFeatureMigration {
+            argsz: size_of::<VfioDeviceFeatureMigration>() as u32,
+            flags: VFIO_DEVICE_FEATURE_GET | VFIO_DEVICE_FEATURE_MIGRATION,
+            migration_flags: 0,
+        };
 
         // Safe as device is the owner of file, and we will verify the result is valid.
-        let ret = unsafe { ioctl_with_ref(&self.fd, VFIO_DEVICE_SET_IRQS(), &irq_set[0]) };
+        let ret = unsafe { ioctl_with_mut_ref(&self.fd, VFIO_DEVICE_FEATURE(), &mut feature) };
         if ret < 0 {
             return Err(anyhow!(VfioError::VfioIoctl(
-                "VFIO_DEVICE_SET_IRQS".to_string(),
+                "VFIO_DEVICE_FEATURE_MIGRATION".to_string(),
                 std::io::Error::last_os_error(),
             )));
         }
-        self.nr_vectors = 0;
-        Ok(())
+        Ok(feature.migration_flags)
+    }
+
+    /// Get the current migration state of the device.
+    pub fn migration_state(&self) -> Result<u32> {
+        let mut feature = VfioDeviceFeatureMigState {
+            argsz: size_of::<VfioDeviceFeatureMigState>() as u32,
+            flags: VFIO_DEVICE_FEATURE_GET | VFIO_DEVICE_FEATURE_MIG_DEVICE_STATE,
+            device_state: 0,
+            data_fd: -1,
+        };
+
+        // Safe as device is the owner of file, and we will verify the result is valid.
+        let ret = unsafe { ioctl_with_mut_ref(&self.fd, VFIO_DEVICE_FEATURE(), &mut feature) };
+        if ret < 0 {
+            return Err(anyhow!(VfioError::VfioIoctl(
+                "VFIO_DEVICE_FEATURE_MIG_DEVICE_STATE".to_string(),
+                std::io::Error::last_os_error(),
+            )));
+        }
+        Ok(feature.device_state)
+    }
+
+    /// Move the device to a migration state, kernel goes through the intermediate states
+    /// if needed. Return the data fd if the new state transfers device data.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - Migration state, such as `VFIO_DEVICE_STATE_STOP_COPY`.
+    pub fn set_migration_state(&self, state: u32) -> Result<Option<File>> {
+        let mut feature = VfioDeviceFeatureMigState {
+            argsz: size_of::<VfioDeviceFeatureMigState>() as u32,
+            flags: VFIO_DEVICE_FEATURE_SET | VFIO_DEVICE_FEATURE_MIG_DEVICE_STATE,
+            device_state: state,
+            data_fd: -1,
+        };
+
+        // Safe as device is the owner of file, and we will verify the result is valid.
+        let ret = unsafe { ioctl_with_mut_ref(&self.fd, VFIO_DEVICE_FEATURE(), &mut feature) };
+        if ret < 0 {
+            return Err(anyhow!(VfioError::VfioIoctl(
+                format!("VFIO_DEVICE_FEATURE_MIG_DEVICE_STATE {}", state),
+                std::io::Error::last_os_error(),
+            )));
+        }
+        if feature.data_fd < 0 {
+            return Ok(None);
+        }
+        // Safe as kernel returns a new fd which is owned by nobody else.
+        Ok(Some(unsafe { File::from_raw_fd(feature.data_fd) }))
     }
 
     pub fn reset(&self) -> Result<()> {
@@ -865,6 +1123,33 @@ impl VfioDevice {
     }
 }
 
+/// Get the length of device data available to read from data fd in PRE_COPY state.
+///
+/// # Arguments
+///
+/// * `data_fd` - Data fd returned when device enters PRE_COPY state.
+pub fn get_precopy_info(data_fd: &File) -> Result<VfioPrecopyInfo> {
+    let mut info = VfioPrecopyInfo {
+        argsz: size_of::<VfioPrecopyInfo>() as u32,
+        ..Default::default()
+    };
+
+    // Safe as data_fd is the owner of file, and we will verify the result is valid.
+    let ret = unsafe { ioctl_with_mut_ref(data_fd, VFIO_MIG_GET_PRECOPY_INFO(), &mut info) };
+    if ret < 0 {
+        return Err(anyhow!(VfioError::VfioIoctl(
+            "VFIO_MIG_GET_PRECOPY_INFO".to_string(),
+            std::io::Error::last_os_error(),
+        )));
+    }
+    Ok(info)
+}
+
+/// Id of the dirty page tracker of vfio container.
+pub(crate) fn dirty_tracker_id(container_fd: RawFd) -> String {
+    format!("vfio-container-{}", container_fd)
+}
+
 /// In VFIO, there are several structures contains zero-length array, as follows:
 /// ```
 /// use vfio_bindings::bindings::vfio::__IncompleteArrayField;
@@ -887,7 +1172,7 @@ fn array_to_vec<T: Default, F>(len: usize) -> Vec<T> {
 
 #[cfg(test)]
 mod tests {
-    use crate::vfio_dev::array_to_vec;
+    use super::*;
 
     #[test]
     fn test_array_to_vec() {
@@ -900,4 +1185,16 @@ mod tests {
         let vec3 = array_to_vec::<u8, u32>(2);
         assert_eq!(vec3.len(), 9);
     }
+
+    #[test]
+    fn test_migration_uapi() {
+        // Values of `include/uapi/linux/vfio.h`.
+        assert_eq!(VFIO_DEVICE_FEATURE(), 0x3b75);
+        assert_eq!(VFIO_IOMMU_DIRTY_PAGES(), 0x3b75);
+        assert_eq!(VFIO_MIG_GET_PRECOPY_INFO(), 0x3b79);
+        assert_eq!(size_of::<VfioDeviceFeatureMigration>(), 16);
+        assert_eq!(size_of::<VfioDeviceFeatureMigState>(), 16);
+        assert_eq!(size_of::<VfioPrecopyInfo>(), 24);
+        assert_eq!(size_of::<VfioIommuDirtyBitmapGet>(), 48);
+    }
 }
@@ -0,0 +1,543 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context, Result};
+use log::{error, info};
+use migration::DeviceDataStage;
+
+use crate::vfio_dev::*;
+
+/// Operations of the device used by the migration state machine.
+pub trait VfioMigrationDevice: Send {
+    fn migration_flags(&self) -> Result<u64>;
+
+    fn migration_state(&self) -> Result<u32>;
+
+    /// Move the device to `state`, returns the data fd if the state transfers device data.
+    fn set_migration_state(&self, state: u32) -> Result<Option<File>>;
+
+    fn precopy_info(&self, data_fd: &File) -> Result<VfioPrecopyInfo>;
+
+    /// Reset the device, which moves it to RUNNING state.
+    fn reset(&self) -> Result<()>;
+}
+
+impl VfioMigrationDevice for VfioDevice {
+    fn migration_flags(&self) -> Result<u64> {
+        VfioDevice::migration_flags(self)
+    }
+
+    fn migration_state(&self) -> Result<u32> {
+        VfioDevice::migration_state(self)
+    }
+
+    fn set_migration_state(&self, state: u32) -> Result<Option<File>> {
+        VfioDevice::set_migration_state(self, state)
+    }
+
+    fn precopy_info(&self, data_fd: &File) -> Result<VfioPrecopyInfo> {
+        get_precopy_info(data_fd)
+    }
+
+    fn reset(&self) -> Result<()> {
+        VfioDevice::reset(self)
+    }
+}
+
+/// Device state machine of VFIO migration v2 protocol. The device data is read from
+/// or written to the data fd returned by kernel when the device enters STOP_COPY,
+/// PRE_COPY or RESUMING state.
+pub struct VfioMigration {
+    // Vfio device which is bound to.
+    device: Arc<Mutex<dyn VfioMigrationDevice>>,
+    // Migration flags supported by the device.
+    flags: u64,
+    // Current migration state of the device.
+    state: u32,
+    // Fd to transfer device data in current state.
+    data_fd: Option<File>,
+    // Length of data left to read in this round of pre-copy, None if the round is not
+    // started.
+    precopy_left: Option<u64>,
+}
+
+impl VfioMigration {
+    /// Create migration state machine of the vfio device, return None if the device
+    /// doesn't support migration.
+    pub fn new(device: Arc<Mutex<dyn VfioMigrationDevice>>) -> Option<Self> {
+        let flags = device.lock().unwrap().migration_flags().ok()?;
+        if flags & VFIO_MIGRATION_STOP_COPY == 0 {
+            return None;
+        }
+        info!("Vfio device supports migration with flags 0x{:x}", flags);
+
+        Some(VfioMigration {
+            device,
+            flags,
+            state: VFIO_DEVICE_STATE_RUNNING,
+            data_fd: None,
+            precopy_left: None,
+        })
+    }
+
+    fn set_state(&mut self, state: u32) -> Result<()> {
+        if self.state == state {
+            return Ok(());
+        }
+        let ret = self.device.lock().unwrap().set_migration_state(state);
+        let data_fd = match ret {
+            Ok(data_fd) => data_fd,
+            Err(e) => {
+                let old_state = self.state;
+                self.recover();
+                return Err(e).with_context(|| {
+                    format!(
+                        "Failed to move vfio device from state {} to {}",
+                        old_state, state
+                    )
+                });
+            }
+        };
+
+        // The data fd of PRE_COPY is kept in STOP_COPY.
+        match state {
+            VFIO_DEVICE_STATE_STOP_COPY
+            | VFIO_DEVICE_STATE_PRE_COPY
+            | VFIO_DEVICE_STATE_RESUMING => {
+                if data_fd.is_some() {
+                    self.data_fd = data_fd;
+                }
+                if self.data_fd.is_none() {
+                    bail!("No data fd is returned for vfio device state {}", state);
+                }
+            }
+            _ => self.data_fd = None,
+        }
+        self.state = state;
+        self.precopy_left = None;
+        Ok(())
+    }
+
+    /// The device may stop in an intermediate state or ERROR state when it fails to
+    /// move to the new state. Get its real state so that the next move starts from
+    /// there, and reset the device in ERROR state to RUNNING.
+    fn recover(&mut self) {
+        let device = self.device.lock().unwrap();
+        let state = match device.migration_state() {
+            Ok(VFIO_DEVICE_STATE_ERROR) | Err(_) => match device.reset() {
+                Ok(()) => VFIO_DEVICE_STATE_RUNNING,
+                Err(e) => {
+                    error!("Failed to reset vfio device in error state: {:?}", e);
+                    VFIO_DEVICE_STATE_ERROR
+                }
+            },
+            Ok(state) => state,
+        };
+        drop(device);
+
+        info!("Vfio device is recovered to migration state {}", state);
+        if !matches!(
+            state,
+            VFIO_DEVICE_STATE_STOP_COPY | VFIO_DEVICE_STATE_PRE_COPY | VFIO_DEVICE_STATE_RESUMING
+        ) {
+            self.data_fd = None;
+        }
+        self.state = state;
+        self.precopy_left = None;
+    }
+
+    fn data_fd(&self) -> Result<&File> {
+        match self.data_fd.as_ref() {
+            Some(data_fd) => Ok(data_fd),
+            None => bail!("No data fd of vfio device in state {}", self.state),
+        }
+    }
+
+    /// Move the device to the stage of migration in source VM. Pre-copy is skipped if
+    /// the device doesn't support it.
+    pub fn set_stage(&mut self, stage: DeviceDataStage) -> Result<()> {
+        match stage {
+            DeviceDataStage::PreCopy => {
+                if self.flags & VFIO_MIGRATION_PRE_COPY != 0 {
+                    self.set_state(VFIO_DEVICE_STATE_PRE_COPY)?;
+                }
+                Ok(())
+            }
+            DeviceDataStage::StopCopy => self.set_state(VFIO_DEVICE_STATE_STOP_COPY),
+            DeviceDataStage::Running => self.set_state(VFIO_DEVICE_STATE_RUNNING),
+        }
+    }
+
+    /// Read device data in PRE_COPY or STOP_COPY state. In PRE_COPY, a round only
+    /// reads the data available when it starts, so that it ends even if the device
+    /// keeps generating data.
+    pub fn read_data(&mut self, max_len: usize) -> Result<Vec<u8>> {
+        let len = match self.state {
+            VFIO_DEVICE_STATE_STOP_COPY => max_len,
+            VFIO_DEVICE_STATE_PRE_COPY => {
+                let left = match self.precopy_left {
+                    Some(left) => left,
+                    None => {
+                        let data_fd = self.data_fd()?;
+                        let info = self.device.lock().unwrap().precopy_info(data_fd)?;
+                        info.initial_bytes + info.dirty_bytes
+                    }
+                };
+                if left == 0 {
+                    self.precopy_left = None;
+                    return Ok(Vec::new());
+                }
+                self.precopy_left = Some(left);
+                std::cmp::min(left, max_len as u64) as usize
+            }
+            _ => return Ok(Vec::new()),
+        };
+
+        let mut data = vec![0_u8; len];
+        let mut data_fd = self.data_fd()?;
+        let read = loop {
+            match data_fd.read(&mut data) {
+                Ok(read) => break read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                // No more data is available in PRE_COPY for now.
+                Err(e) if e.raw_os_error() == Some(libc::ENOMSG) => break 0,
+                Err(e) => return Err(e).with_context(|| "Failed to read vfio device data"),
+            }
+        };
+        data.truncate(read);
+
+        if let Some(left) = self.precopy_left {
+            // The round ends early if no more data is available.
+            self.precopy_left = match read {
+                0 => None,
+                _ => Some(left.saturating_sub(read as u64)),
+            };
+        }
+        Ok(data)
+    }
+
+    /// Write device data received from source VM, the device enters RESUMING state
+    /// at the first write.
+    pub fn write_data(&mut self, data: &[u8]) -> Result<()> {
+        if self.state != VFIO_DEVICE_STATE_RESUMING {
+            self.set_state(VFIO_DEVICE_STATE_RESUMING)?;
+        }
+        self.data_fd()?
+            .write_all(data)
+            .with_context(|| "Failed to write vfio device data")
+    }
+
+    /// Run the device after its data is loaded, or the device is recovered from the
+    /// failure of loading.
+    pub fn resume(&mut self) -> Result<()> {
+        if self.state != VFIO_DEVICE_STATE_RUNNING {
+            self.set_state(VFIO_DEVICE_STATE_RUNNING)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::net::Shutdown;
+    use std::os::unix::io::{FromRawFd, IntoRawFd};
+    use std::os::unix::net::UnixStream;
+
+    use anyhow::anyhow;
+
+    use super::*;
+
+    const VFIO_DEVICE_STATE_STOP: u32 = 1;
+
+    /// Device following the migration state machine of kernel, the device data is
+    /// transferred by the peer of data fd.
+    struct MockDevice {
+        flags: u64,
+        state: Cell<u32>,
+        /// States passed through by the transitions.
+        path: RefCell<Vec<u32>>,
+        /// The transition into the state fails, the device enters ERROR state if
+        /// the flag is true, otherwise it stays in the last state.
+        fail: Cell<Option<(u32, bool)>>,
+        /// Initial and dirty bytes of PRE_COPY.
+        precopy: Cell<(u64, u64)>,
+        resets: Cell<u32>,
+        peer: RefCell<Option<UnixStream>>,
+    }
+
+    impl MockDevice {
+        fn new(flags: u64) -> Self {
+            MockDevice {
+                flags,
+                state: Cell::new(VFIO_DEVICE_STATE_RUNNING),
+                path: RefCell::new(Vec::new()),
+                fail: Cell::new(None),
+                precopy: Cell::new((0, 0)),
+                resets: Cell::new(0),
+                peer: RefCell::new(None),
+            }
+        }
+
+        /// Next state from `cur` to `target`, the same as the arcs of kernel.
+        fn next_state(cur: u32, target: u32) -> u32 {
+            match cur {
+                VFIO_DEVICE_STATE_RUNNING if target == VFIO_DEVICE_STATE_PRE_COPY => target,
+                VFIO_DEVICE_STATE_RUNNING => VFIO_DEVICE_STATE_STOP,
+                VFIO_DEVICE_STATE_STOP if target == VFIO_DEVICE_STATE_PRE_COPY => {
+                    VFIO_DEVICE_STATE_RUNNING
+                }
+                VFIO_DEVICE_STATE_STOP => target,
+                VFIO_DEVICE_STATE_PRE_COPY if target == VFIO_DEVICE_STATE_RUNNING => target,
+                VFIO_DEVICE_STATE_PRE_COPY => VFIO_DEVICE_STATE_STOP_COPY,
+                _ => VFIO_DEVICE_STATE_STOP,
+            }
+        }
+
+        fn take_peer(&self) -> UnixStream {
+            self.peer.borrow_mut().take().unwrap()
+        }
+    }
+
+    impl VfioMigrationDevice for MockDevice {
+        fn migration_flags(&self) -> Result<u64> {
+            if self.flags == 0 {
+                bail!("Migration is not supported");
+            }
+            Ok(self.flags)
+        }
+
+        fn migration_state(&self) -> Result<u32> {
+            Ok(self.state.get())
+        }
+
+        fn set_migration_state(&self, target: u32) -> Result<Option<File>> {
+            let mut cur = self.state.get();
+            if cur == VFIO_DEVICE_STATE_ERROR {
+                bail!("Device is in error state");
+            }
+            let mut data_fd = None;
+            while cur != target {
+                let next = Self::next_state(cur, target);
+                if let Some((state, error)) = self.fail.get() {
+                    if state == next {
+                        if error {
+                            self.state.set(VFIO_DEVICE_STATE_ERROR);
+                        }
+                        return Err(anyhow!("Failed to enter state {}", next));
+                    }
+                }
+                // The data fd of PRE_COPY is used in STOP_COPY.
+                let new_fd = matches!(
+                    next,
+                    VFIO_DEVICE_STATE_PRE_COPY
+                        | VFIO_DEVICE_STATE_STOP_COPY
+                        | VFIO_DEVICE_STATE_RESUMING
+                ) && cur != VFIO_DEVICE_STATE_PRE_COPY;
+                if next == target && new_fd {
+                    let (sock, peer) = UnixStream::pair().unwrap();
+                    *self.peer.borrow_mut() = Some(peer);
+                    // SAFETY: the fd is taken from the new created socket.
+                    data_fd = Some(unsafe { File::from_raw_fd(sock.into_raw_fd()) });
+                }
+                self.path.borrow_mut().push(next);
+                self.state.set(next);
+                cur = next;
+            }
+            Ok(data_fd)
+        }
+
+        fn precopy_info(&self, _data_fd: &File) -> Result<VfioPrecopyInfo> {
+            let mut info = VfioPrecopyInfo::default();
+            (info.initial_bytes, info.dirty_bytes) = self.precopy.get();
+            Ok(info)
+        }
+
+        fn reset(&self) -> Result<()> {
+            self.resets.set(self.resets.get() + 1);
+            self.state.set(VFIO_DEVICE_STATE_RUNNING);
+            *self.peer.borrow_mut() = None;
+            Ok(())
+        }
+    }
+
+    fn mock_migration(flags: u64) -> (Arc<Mutex<MockDevice>>, VfioMigration) {
+        let device = Arc::new(Mutex::new(MockDevice::new(flags)));
+        let migration = VfioMigration::new(device.clone()).unwrap();
+        (device, migration)
+    }
+
+    fn take_path(device: &Arc<Mutex<MockDevice>>) -> Vec<u32> {
+        device.lock().unwrap().path.borrow_mut().drain(..).collect()
+    }
+
+    #[test]
+    fn test_vfio_migration_unsupported() {
+        let device = Arc::new(Mutex::new(MockDevice::new(0)));
+        assert!(VfioMigration::new(device).is_none());
+        let device = Arc::new(Mutex::new(MockDevice::new(VFIO_MIGRATION_PRE_COPY)));
+        assert!(VfioMigration::new(device).is_none());
+    }
+
+    #[test]
+    fn test_vfio_migration_source() {
+        let (device, mut migration) = mock_migration(VFIO_MIGRATION_STOP_COPY);
+
+        // Pre-copy is skipped, no data is read in RUNNING state.
+        migration.set_stage(DeviceDataStage::PreCopy).unwrap();
+        assert_eq!(migration.state, VFIO_DEVICE_STATE_RUNNING);
+        assert!(migration.read_data(16).unwrap().is_empty());
+        assert!(take_path(&device).is_empty());
+
+        migration.set_stage(DeviceDataStage::StopCopy).unwrap();
+        assert_eq!(
+            take_path(&device),
+            vec![VFIO_DEVICE_STATE_STOP, VFIO_DEVICE_STATE_STOP_COPY]
+        );
+        assert_eq!(migration.state, VFIO_DEVICE_STATE_STOP_COPY);
+        let mut peer = device.lock().unwrap().take_peer();
+        peer.write_all(b"0123456789").unwrap();
+        peer.shutdown(Shutdown::Write).unwrap();
+        assert_eq!(migration.read_data(4).unwrap(), b"0123");
+        assert_eq!(migration.read_data(16).unwrap(), b"456789");
+        assert!(migration.read_data(16).unwrap().is_empty());
+        assert_eq!(migration.precopy_left, None);
+
+        // Migration is cancelled.
+        migration.set_stage(DeviceDataStage::Running).unwrap();
+        assert_eq!(
+            take_path(&device),
+            vec![VFIO_DEVICE_STATE_STOP, VFIO_DEVICE_STATE_RUNNING]
+        );
+        assert!(migration.data_fd.is_none());
+        migration.set_stage(DeviceDataStage::Running).unwrap();
+        assert!(take_path(&device).is_empty());
+    }
+
+    #[test]
+    fn test_vfio_migration_destination() {
+        let (device, mut migration) = mock_migration(VFIO_MIGRATION_STOP_COPY);
+
+        // Nothing to do if no data is loaded.
+        migration.resume().unwrap();
+        assert!(take_path(&device).is_empty());
+
+        migration.write_data(b"0123").unwrap();
+        migration.write_data(b"4567").unwrap();
+        assert_eq!(
+            take_path(&device),
+            vec![VFIO_DEVICE_STATE_STOP, VFIO_DEVICE_STATE_RESUMING]
+        );
+        let mut peer = device.lock().unwrap().take_peer();
+        let mut data = [0_u8; 8];
+        peer.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"01234567");
+
+        migration.resume().unwrap();
+        assert_eq!(
+            take_path(&device),
+            vec![VFIO_DEVICE_STATE_STOP, VFIO_DEVICE_STATE_RUNNING]
+        );
+        assert!(migration.data_fd.is_none());
+    }
+
+    #[test]
+    fn test_vfio_migration_precopy() {
+        let (device, mut migration) =
+            mock_migration(VFIO_MIGRATION_STOP_COPY | VFIO_MIGRATION_PRE_COPY);
+        migration.set_stage(DeviceDataStage::PreCopy).unwrap();
+        assert_eq!(take_path(&device), vec![VFIO_DEVICE_STATE_PRE_COPY]);
+        let mut peer = device.lock().unwrap().take_peer();
+
+        // The round only reads the data available when it starts.
+        device.lock().unwrap().precopy.set((6, 4));
+        peer.write_all(b"0123456789abcdef").unwrap();
+        assert_eq!(migration.read_data(4).unwrap(), b"0123");
+        assert_eq!(migration.precopy_left, Some(6));
+        assert_eq!(migration.read_data(4).unwrap(), b"4567");
+        assert_eq!(migration.read_data(4).unwrap(), b"89");
+        assert_eq!(migration.precopy_left, Some(0));
+        assert!(migration.read_data(4).unwrap().is_empty());
+        assert_eq!(migration.precopy_left, None);
+
+        // New round.
+        device.lock().unwrap().precopy.set((0, 0));
+        assert!(migration.read_data(4).unwrap().is_empty());
+        assert_eq!(migration.precopy_left, None);
+
+        // The round ends early if no more data is available.
+        device.lock().unwrap().precopy.set((0, 8));
+        assert_eq!(migration.read_data(16).unwrap(), b"abcdef");
+        assert_eq!(migration.precopy_left, Some(2));
+        peer.shutdown(Shutdown::Write).unwrap();
+        assert!(migration.read_data(16).unwrap().is_empty());
+        assert_eq!(migration.precopy_left, None);
+
+        // The data fd of PRE_COPY is kept in STOP_COPY.
+        migration.set_stage(DeviceDataStage::StopCopy).unwrap();
+        assert_eq!(take_path(&device), vec![VFIO_DEVICE_STATE_STOP_COPY]);
+        assert!(migration.data_fd.is_some());
+    }
+
+    #[test]
+    fn test_vfio_migration_recover() {
+        let (device, mut migration) = mock_migration(VFIO_MIGRATION_STOP_COPY);
+
+        // The device stops in the intermediate STOP state.
+        device
+            .lock()
+            .unwrap()
+            .fail
+            .set(Some((VFIO_DEVICE_STATE_STOP_COPY, false)));
+        assert!(migration.set_stage(DeviceDataStage::StopCopy).is_err());
+        assert_eq!(take_path(&device), vec![VFIO_DEVICE_STATE_STOP]);
+        assert_eq!(migration.state, VFIO_DEVICE_STATE_STOP);
+        migration.set_stage(DeviceDataStage::Running).unwrap();
+        assert_eq!(take_path(&device), vec![VFIO_DEVICE_STATE_RUNNING]);
+
+        // The device in ERROR state is reset.
+        device
+            .lock()
+            .unwrap()
+            .fail
+            .set(Some((VFIO_DEVICE_STATE_STOP, true)));
+        assert!(migration.set_stage(DeviceDataStage::StopCopy).is_err());
+        assert_eq!(device.lock().unwrap().resets.get(), 1);
+        assert_eq!(migration.state, VFIO_DEVICE_STATE_RUNNING);
+        assert!(migration.data_fd.is_none());
+        device.lock().unwrap().fail.set(None);
+        migration.set_stage(DeviceDataStage::StopCopy).unwrap();
+        assert_eq!(
+            take_path(&device),
+            vec![VFIO_DEVICE_STATE_STOP, VFIO_DEVICE_STATE_STOP_COPY]
+        );
+
+        // The device fails to enter RESUMING state, and runs again.
+        let (device, mut migration) = mock_migration(VFIO_MIGRATION_STOP_COPY);
+        device
+            .lock()
+            .unwrap()
+            .fail
+            .set(Some((VFIO_DEVICE_STATE_RESUMING, false)));
+        assert!(migration.write_data(b"0123").is_err());
+        assert_eq!(migration.state, VFIO_DEVICE_STATE_STOP);
+        migration.resume().unwrap();
+        assert_eq!(
+            take_path(&device),
+            vec![VFIO_DEVICE_STATE_STOP, VFIO_DEVICE_STATE_RUNNING]
+        );
+    }
+}
@@ -22,13 +22,18 @@ use anyhow::{anyhow, bail, Context, Result};
 use byteorder::{ByteOrder, LittleEndian};
 use hypervisor::kvm::{MsiVector, KVM_FDS};
 use log::error;
+use migration::{
+    DeviceDataStage, DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager, StateTransfer,
+};
+use migration_derive::{ByteCode, Desc};
 #[cfg(target_arch = "aarch64")]
 use pci::config::SECONDARY_BUS_NUM;
 use pci::config::{
     PciConfig, RegionType, BAR_0, BAR_5, BAR_IO_SPACE, BAR_MEM_64BIT, BAR_SPACE_UNMAPPED, COMMAND,
     COMMAND_BUS_MASTER, COMMAND_INTERRUPT_DISABLE, COMMAND_IO_SPACE, COMMAND_MEMORY_SPACE,
-    HEADER_TYPE, IO_BASE_ADDR_MASK, MEM_BASE_ADDR_MASK, PCIE_CONFIG_SPACE_SIZE,
-    PCI_CONFIG_SPACE_SIZE, REG_SIZE,
+    HEADER_TYPE, INTERRUPT_LINE, INTERRUPT_PIN, IO_BASE_ADDR_MASK, MEM_BASE_ADDR_MASK,
+    MINMUM_BAR_SIZE_FOR_MMIO, PCIE_CONFIG_SPACE_SIZE, PCI_CONFIG_SPACE_SIZE, REG_SIZE, ROM_ADDRESS,
+    ROM_BAR_ID,
 };
 use pci::msix::{
     is_msix_enabled, update_dev_id, Msix, MSIX_CAP_CONTROL, MSIX_CAP_ENABLE, MSIX_CAP_FUNC_MASK,
@@ -37,19 +42,32 @@ use pci::msix::{
 };
 use pci::{
     init_multifunction, le_read_u16, le_read_u32, le_write_u16, le_write_u32, pci_ext_cap_id,
-    pci_ext_cap_next, pci_ext_cap_ver, ranges_overlap, PciBus, PciDevOps,
+    pci_ext_cap_next, pci_ext_cap_ver, pci_intx_gsi, ranges_overlap, PciBus, PciDevOps,
 };
+use util::byte_code::ByteCode;
 use util::unix::host_page_size;
 use vfio_bindings::bindings::vfio;
 use vmm_sys_util::eventfd::EventFd;
 use vmm_sys_util::ioctl::ioctl_with_mut_ref;
 
 use crate::vfio_dev::*;
+use crate::vfio_migration::VfioMigration;
 use crate::{CONTAINERS, GROUPS};
 
 const PCI_NUM_BARS: u8 = 6;
 const PCI_ROM_SLOT: u8 = 6;
 
+const MSI_CAP_ID: u8 = 0x05;
+const MSI_CAP_CONTROL: usize = 0x02;
+const MSI_CAP_ADDRESS_LO: usize = 0x04;
+const MSI_CAP_ADDRESS_HI: usize = 0x08;
+const MSI_CAP_ENABLE: u16 = 0x0001;
+const MSI_CAP_MULTI_MSG_CAP_SHIFT: u16 = 1;
+const MSI_CAP_MULTI_MSG_ENABLE_SHIFT: u16 = 4;
+const MSI_CAP_MULTI_MSG_MASK: u16 = 0x7;
+const MSI_CAP_64BIT: u16 = 0x0080;
+const MSI_CAP_PER_VECTOR_MASK: u16 = 0x0100;
+
 struct MsixTable {
     table_bar: u8,
     table_offset: u64,
@@ -66,6 +84,104 @@ struct VfioMsixInfo {
     vfio_irq: HashMap<u32, VfioIrq>,
 }
 
+/// Layout of MSI capability, the message data and mask bits are placed after 32-bit
+/// or 64-bit message address.
+#[derive(Clone, Copy)]
+struct VfioMsiInfo {
+    cap_offset: usize,
+    is_64bit: bool,
+    maskable: bool,
+    // Maximum number of vectors the device supports.
+    max_vectors: u32,
+}
+
+impl VfioMsiInfo {
+    fn new(cap_offset: usize, ctrl: u16) -> Self {
+        VfioMsiInfo {
+            cap_offset,
+            is_64bit: ctrl & MSI_CAP_64BIT != 0,
+            maskable: ctrl & MSI_CAP_PER_VECTOR_MASK != 0,
+            max_vectors: 1 << ((ctrl >> MSI_CAP_MULTI_MSG_CAP_SHIFT) & MSI_CAP_MULTI_MSG_MASK),
+        }
+    }
+
+    fn cap_size(&self) -> usize {
+        match (self.is_64bit, self.maskable) {
+            (false, false) => 0x0a,
+            (true, false) => 0x0e,
+            (false, true) => 0x14,
+            (true, true) => 0x18,
+        }
+    }
+
+    fn data_offset(&self) -> usize {
+        self.cap_offset + if self.is_64bit { 0x0c } else { 0x08 }
+    }
+
+    fn mask_offset(&self) -> usize {
+        self.cap_offset + if self.is_64bit { 0x10 } else { 0x0c }
+    }
+
+    fn is_enabled(&self, config: &[u8]) -> bool {
+        let ctrl = le_read_u16(config, self.cap_offset + MSI_CAP_CONTROL);
+        matches!(ctrl, Ok(ctrl) if ctrl & MSI_CAP_ENABLE != 0)
+    }
+
+    /// Number of vectors enabled by guest, which never exceeds the capability.
+    fn nr_vectors(&self, config: &[u8]) -> Result<u32> {
+        let ctrl = le_read_u16(config, self.cap_offset + MSI_CAP_CONTROL)?;
+        let vectors = 1 << ((ctrl >> MSI_CAP_MULTI_MSG_ENABLE_SHIFT) & MSI_CAP_MULTI_MSG_MASK);
+        Ok(std::cmp::min(vectors, self.max_vectors))
+    }
+
+    fn is_vector_masked(&self, config: &[u8], vector: u32) -> Result<bool> {
+        if !self.maskable {
+            return Ok(false);
+        }
+        Ok(le_read_u32(config, self.mask_offset())? & (1 << vector) != 0)
+    }
+
+    /// Get the message of the vector, the low bits of message data carry the vector number
+    /// if multiple vectors are enabled.
+    #[allow(unused_variables)]
+    fn get_message(&self, config: &[u8], vector: u32, dev_id: u16) -> Result<MsiVector> {
+        let nr_vectors = self.nr_vectors(config)?;
+        let data = le_read_u16(config, self.data_offset())? as u32;
+        let msg_addr_hi = if self.is_64bit {
+            le_read_u32(config, self.cap_offset + MSI_CAP_ADDRESS_HI)?
+        } else {
+            0
+        };
+        Ok(MsiVector {
+            msg_addr_lo: le_read_u32(config, self.cap_offset + MSI_CAP_ADDRESS_LO)?,
+            msg_addr_hi,
+            msg_data: (data & !(nr_vectors - 1)) | vector,
+            masked: false,
+            #[cfg(target_arch = "aarch64")]
+            dev_id: dev_id as u32,
+        })
+    }
+}
+
+/// MSI vector routed to kvm, the irqfd is only registered when the vector is unmasked.
+struct MsiRoute {
+    irq_fd: EventFd,
+    gsi: u32,
+    masked: bool,
+}
+
+/// Level-triggered INTx of vfio device.
+struct VfioIntx {
+    // GSI the INTx pin is routed to.
+    gsi: u32,
+    // Signaled by vfio when the device asserts INTx.
+    trigger_fd: EventFd,
+    // Signaled by kvm when guest acknowledges the interrupt, which unmasks INTx in vfio.
+    resample_fd: EventFd,
+    // Whether INTx is bound to kvm, it is unbound when MSI or MSI-X is enabled.
+    enabled: bool,
+}
+
 struct VfioBar {
     vfio_region: VfioRegion,
     region_type: RegionType,
@@ -78,6 +194,19 @@ struct GsiMsiRoute {
     nr: u32,
 }
 
+/// The state of vfio-pci device emulated by StratoVirt, the internal state of the
+/// physical device is migrated as device data.
+#[repr(C)]
+#[derive(Copy, Clone, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+pub struct VfioPciState {
+    dev_id: u16,
+    /// Max length of config_space is 4096.
+    config_space: [u8; 4096],
+    /// MSI-X entries table. Max number of entries is 2048.
+    msix_table: [u8; 32768],
+}
+
 /// VfioPciDevice is a VFIO PCI device. It implements PciDevOps trait for a PCI device.
 /// And it is bound to a VFIO device.
 pub struct VfioPciDevice {
@@ -89,8 +218,18 @@ pub struct VfioPciDevice {
     vfio_device: Arc<Mutex<VfioDevice>>,
     // Cache of MSI-X setup.
     msix_info: Option<VfioMsixInfo>,
+    // Cache of MSI setup.
+    msi_info: Option<VfioMsiInfo>,
+    // Routes of the enabled MSI vectors.
+    msi_routes: Vec<MsiRoute>,
+    // INTx setup, None if the device has no INTx pin.
+    intx: Option<VfioIntx>,
     // Bars information without ROM.
     vfio_bars: Arc<Mutex<Vec<VfioBar>>>,
+    // Ops of MSI-X table, which replay the table after migration.
+    msix_table_ops: Option<RegionOps>,
+    // Migration state machine, None if the device doesn't support migration.
+    migration: Option<VfioMigration>,
     // Maintains a list of GSI with irqfds that are registered to kvm.
     gsi_msi_routes: Arc<Mutex<Vec<GsiMsiRoute>>>,
     devfn: u8,
@@ -114,12 +253,17 @@ impl VfioPciDevice {
     ) -> Self {
         Self {
             // Unknown PCI or PCIe type here, allocate enough space to match the two types.
-            pci_config: PciConfig::new(PCIE_CONFIG_SPACE_SIZE, PCI_NUM_BARS),
+            pci_config: PciConfig::new(PCIE_CONFIG_SPACE_SIZE, PCI_ROM_SLOT + 1),
             config_size: 0,
             config_offset: 0,
             vfio_device,
             msix_info: None,
+            msi_info: None,
+            msi_routes: Vec::new(),
+            intx: None,
             vfio_bars: Arc::new(Mutex::new(Vec::with_capacity(PCI_NUM_BARS as usize))),
+            msix_table_ops: None,
+            migration: None,
             gsi_msi_routes: Arc::new(Mutex::new(Vec::new())),
             devfn,
             dev_id: Arc::new(AtomicU16::new(0)),
@@ -226,6 +370,7 @@ impl VfioPciDevice {
                 )?;
             }
         }
+        le_write_u32(&mut self.pci_config.config, ROM_ADDRESS, 0)?;
 
         Ok(())
     }
@@ -301,7 +446,9 @@ impl VfioPciDevice {
             });
         }
 
-        self.fixup_msix_region(&mut vfio_bars)?;
+        if self.msix_info.is_some() {
+            self.fixup_msix_region(&mut vfio_bars)?;
+        }
 
         Ok(vfio_bars)
     }
@@ -354,18 +501,25 @@ impl VfioPciDevice {
     }
 
     fn register_bars(&mut self) -> Result<()> {
-        let msix_info = self
-            .msix_info
-            .as_ref()
-            .with_context(|| "Failed to get MSIX info")?;
-        let table_bar = msix_info.table.table_bar;
-        let table_offset = msix_info.table.table_offset;
-        let table_size = msix_info.table.table_size;
+        let (table_bar, table_offset, table_size) = match self.msix_info.as_ref() {
+            Some(msix_info) => (
+                Some(msix_info.table.table_bar),
+                msix_info.table.table_offset,
+                msix_info.table.table_size,
+            ),
+            None => (None, 0, 0),
+        };
         // Create a separate region for MSI-X table, VFIO won't allow to map the MSI-X table area.
-        let table_ops = self
-            .get_table_region_ops()
-            .with_context(|| "Failed to get table region ops")?;
+        let table_ops = if table_bar.is_some() {
+            Some(
+                self.get_table_region_ops()
+                    .with_context(|| "Failed to get table region ops")?,
+            )
+        } else {
+            None
+        };
         let bar_ops = self.get_bar_region_ops();
+        self.msix_table_ops = table_ops.clone();
 
         for i in 0..PCI_ROM_SLOT {
             {
@@ -386,10 +540,10 @@ impl VfioPciDevice {
             let size = vfio_bar.size;
 
             let region = Region::init_container_region(size);
-            let bar_region = if i == table_bar {
+            let bar_region = if table_bar == Some(i) {
                 region
                     .add_subregion(
-                        Region::init_io_region(table_size, table_ops.clone()),
+                        Region::init_io_region(table_size, table_ops.clone().unwrap()),
                         table_offset,
                     )
                     .with_context(|| anyhow!(VfioError::AddRegBar(i as usize)))?;
@@ -431,6 +585,55 @@ impl VfioPciDevice {
         Ok(())
     }
 
+    /// Expose the option ROM of the device as an emulated expansion ROM BAR. The ROM is read
+    /// from vfio device on guest access, and never mapped into guest.
+    fn register_rom_bar(&mut self) -> Result<()> {
+        let rom = self.vfio_device.lock().unwrap().rom_region_info()?;
+        if rom.size == 0 {
+            return Ok(());
+        }
+
+        let rom_offset = rom.region_offset;
+        let rom_size = rom.size;
+        let cloned_dev = self.vfio_device.clone();
+        let read = move |data: &mut [u8], _: GuestAddress, offset: u64| -> bool {
+            // The BAR may be larger than the ROM, read the area out of ROM as 0xff.
+            if offset + data.len() as u64 > rom_size {
+                data.fill(0xff);
+                return true;
+            }
+            if let Err(e) = cloned_dev
+                .lock()
+                .unwrap()
+                .read_region(data, rom_offset, offset)
+            {
+                error!(
+                    "Failed to read ROM region, offset is {}, error is {}",
+                    offset, e
+                );
+            }
+            true
+        };
+        // ROM is read-only, writes are just ignored.
+        let write = move |_: &[u8], _: GuestAddress, _: u64| -> bool { true };
+
+        let size = std::cmp::max(
+            rom_size.next_power_of_two(),
+            MINMUM_BAR_SIZE_FOR_MMIO as u64,
+        );
+        let rom_ops = RegionOps {
+            read: Arc::new(read),
+            write: Arc::new(write),
+        };
+        self.pci_config.register_bar(
+            ROM_BAR_ID,
+            Region::init_io_region(size, rom_ops),
+            RegionType::Mem32Bit,
+            false,
+            size,
+        )
+    }
+
     fn unregister_bars(&mut self) -> Result<()> {
         let bus = self.parent_bus.upgrade().unwrap();
         self.pci_config.unregister_bars(&bus)?;
@@ -554,11 +757,12 @@ impl VfioPciDevice {
             let mut locked_dev = cloned_dev.lock().unwrap();
             if (vector + 1) > (locked_dev.nr_vectors as u64) {
                 locked_dev
-                    .disable_irqs()
+                    .disable_irqs(vfio::VFIO_PCI_MSIX_IRQ_INDEX)
                     .unwrap_or_else(|e| error!("Failed to disable irq, error is {}", e));
 
                 locked_dev
                     .enable_irqs(
+                        vfio::VFIO_PCI_MSIX_IRQ_INDEX,
                         get_irq_rawfds(&locked_gsi_routes, 0, (vector + 1) as u32),
                         0,
                     )
@@ -567,6 +771,7 @@ impl VfioPciDevice {
             } else {
                 locked_dev
                     .enable_irqs(
+                        vfio::VFIO_PCI_MSIX_IRQ_INDEX,
                         get_irq_rawfds(&locked_gsi_routes, vector as u32, 1),
                         vector as u32,
                     )
@@ -711,7 +916,176 @@ impl VfioPciDevice {
         Ok(())
     }
 
+    /// Route INTx pin of the device to kvm through a level-triggered irqfd. Guest's EOI signals
+    /// the resample eventfd, which unmasks INTx in vfio.
+    fn setup_intx(&mut self) -> Result<()> {
+        let pin = self.pci_config.config[INTERRUPT_PIN as usize];
+        let intx_count = {
+            let locked_dev = self.vfio_device.lock().unwrap();
+            locked_dev
+                .get_irqs_info(locked_dev.dev_info.num_irqs)?
+                .get(&vfio::VFIO_PCI_INTX_IRQ_INDEX)
+                .map_or(0, |irq| irq.count)
+        };
+        if pin == 0 || pin > 4 || intx_count == 0 {
+            self.pci_config.config[INTERRUPT_PIN as usize] = 0;
+            return Ok(());
+        }
+
+        let gsi = pci_intx_gsi(&self.parent_bus, self.devfn, pin - 1)?;
+        let trigger_fd = EventFd::new(libc::EFD_NONBLOCK)?;
+        let resample_fd = EventFd::new(libc::EFD_NONBLOCK)?;
+        KVM_FDS
+            .load()
+            .register_irqfd_with_resample(&trigger_fd, &resample_fd, gsi)?;
+        self.pci_config.config[INTERRUPT_LINE as usize] = gsi as u8;
+        self.intx = Some(VfioIntx {
+            gsi,
+            trigger_fd,
+            resample_fd,
+            enabled: false,
+        });
+        self.vfio_enable_intx()
+    }
+
+    fn vfio_enable_intx(&mut self) -> Result<()> {
+        if let Some(intx) = self.intx.as_mut() {
+            if !intx.enabled {
+                self.vfio_device
+                    .lock()
+                    .unwrap()
+                    .enable_intx(intx.trigger_fd.as_raw_fd(), intx.resample_fd.as_raw_fd())
+                    .with_context(|| "Failed to enable INTx")?;
+                intx.enabled = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn vfio_disable_intx(&mut self) -> Result<()> {
+        if let Some(intx) = self.intx.as_mut() {
+            if intx.enabled {
+                self.vfio_device
+                    .lock()
+                    .unwrap()
+                    .disable_intx()
+                    .with_context(|| "Failed to disable INTx")?;
+                intx.enabled = false;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get MSI capability layout, and only allow guest to change the enable bits of its
+    /// message control.
+    fn init_msi_info(&mut self) -> Result<()> {
+        let cap_offset = self.pci_config.find_pci_cap(MSI_CAP_ID);
+        if cap_offset == 0xff {
+            return Ok(());
+        }
+        let offset = cap_offset + MSI_CAP_CONTROL;
+        let ctrl = le_read_u16(&self.pci_config.config, offset)?;
+        le_write_u16(
+            &mut self.pci_config.write_mask,
+            offset,
+            MSI_CAP_ENABLE | (MSI_CAP_MULTI_MSG_MASK << MSI_CAP_MULTI_MSG_ENABLE_SHIFT),
+        )?;
+        self.msi_info = Some(VfioMsiInfo::new(cap_offset, ctrl));
+        Ok(())
+    }
+
+    /// Allocate a GSI for each MSI vector enabled by guest, and bind the vectors to kvm irqfds.
+    fn vfio_enable_msi(&mut self) -> Result<()> {
+        let msi_info = self.msi_info.with_context(|| "Failed to get MSI info")?;
+        self.vfio_disable_intx()?;
+
+        update_dev_id(&self.parent_bus, self.devfn, &self.dev_id);
+        let dev_id = self.dev_id.load(Ordering::Acquire);
+        let config = &self.pci_config.config;
+        let kvm_fds = KVM_FDS.load();
+        for vector in 0..msi_info.nr_vectors(config)? {
+            let msi_vector = msi_info.get_message(config, vector, dev_id)?;
+            let mut locked_irq_table = kvm_fds.irq_route_table.lock().unwrap();
+            let gsi = locked_irq_table.allocate_gsi()?;
+            self.msi_routes.push(MsiRoute {
+                irq_fd: EventFd::new(libc::EFD_NONBLOCK)?,
+                gsi,
+                masked: msi_info.is_vector_masked(config, vector)?,
+            });
+            locked_irq_table.add_msi_route(gsi, msi_vector)?;
+        }
+        kvm_fds.commit_irq_routing()?;
+        for route in self.msi_routes.iter().filter(|r| !r.masked) {
+            kvm_fds.register_irqfd(&route.irq_fd, route.gsi)?;
+        }
+
+        let irq_fds = self
+            .msi_routes
+            .iter()
+            .map(|r| r.irq_fd.as_raw_fd())
+            .collect::<Vec<RawFd>>();
+        let mut locked_dev = self.vfio_device.lock().unwrap();
+        locked_dev
+            .enable_irqs(vfio::VFIO_PCI_MSI_IRQ_INDEX, irq_fds, 0)
+            .with_context(|| "Failed enable MSI irqfds in kvm")?;
+        locked_dev.nr_vectors = self.msi_routes.len();
+        Ok(())
+    }
+
+    /// Update routes of MSI vectors after guest changes the message or mask bits. A masked
+    /// vector is unbound from kvm, and the interrupt pending in irqfd is injected on unmask.
+    fn vfio_update_msi(&mut self) -> Result<()> {
+        let msi_info = self.msi_info.with_context(|| "Failed to get MSI info")?;
+        let dev_id = self.dev_id.load(Ordering::Acquire);
+        let config = &self.pci_config.config;
+        let kvm_fds = KVM_FDS.load();
+        for (vector, route) in self.msi_routes.iter().enumerate() {
+            let msi_vector = msi_info.get_message(config, vector as u32, dev_id)?;
+            kvm_fds
+                .irq_route_table
+                .lock()
+                .unwrap()
+                .update_msi_route(route.gsi, msi_vector)?;
+        }
+        kvm_fds.commit_irq_routing()?;
+
+        for (vector, route) in self.msi_routes.iter_mut().enumerate() {
+            let masked = msi_info.is_vector_masked(config, vector as u32)?;
+            if masked && !route.masked {
+                kvm_fds.unregister_irqfd(&route.irq_fd, route.gsi)?;
+            } else if !masked && route.masked {
+                kvm_fds.register_irqfd(&route.irq_fd, route.gsi)?;
+            }
+            route.masked = masked;
+        }
+        Ok(())
+    }
+
+    fn vfio_disable_msi(&mut self) -> Result<()> {
+        self.vfio_device
+            .lock()
+            .unwrap()
+            .disable_irqs(vfio::VFIO_PCI_MSI_IRQ_INDEX)
+            .with_context(|| "Failed disable MSI irqfds in kvm")?;
+
+        let kvm_fds = KVM_FDS.load();
+        for route in self.msi_routes.drain(..) {
+            if !route.masked {
+                kvm_fds.unregister_irqfd(&route.irq_fd, route.gsi)?;
+            }
+            kvm_fds
+                .irq_route_table
+                .lock()
+                .unwrap()
+                .release_gsi(route.gsi)?;
+        }
+        kvm_fds.commit_irq_routing()?;
+
+        self.vfio_enable_intx()
+    }
+
     fn vfio_enable_msix(&mut self) -> Result<()> {
+        self.vfio_disable_intx()?;
         let mut gsi_routes = self.gsi_msi_routes.lock().unwrap();
         if gsi_routes.len() == 0 {
             let irq_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
@@ -734,11 +1108,15 @@ impl VfioPciDevice {
         }
         // Register a vector of irqfd to kvm interrupts. If one of the device interrupt vector is
         // triggered, the corresponding irqfd is written, and interrupt is injected into VM finally.
-        self.vfio_device
-            .lock()
-            .unwrap()
-            .enable_irqs(get_irq_rawfds(&gsi_routes, 0, 1), 0)
+        let mut locked_dev = self.vfio_device.lock().unwrap();
+        locked_dev
+            .enable_irqs(
+                vfio::VFIO_PCI_MSIX_IRQ_INDEX,
+                get_irq_rawfds(&gsi_routes, 0, 1),
+                0,
+            )
             .with_context(|| "Failed enable irqfds in kvm")?;
+        locked_dev.nr_vectors = std::cmp::max(locked_dev.nr_vectors, 1);
 
         Ok(())
     }
@@ -747,8 +1125,20 @@ impl VfioPciDevice {
         self.vfio_device
             .lock()
             .unwrap()
-            .disable_irqs()
+            .disable_irqs(vfio::VFIO_PCI_MSIX_IRQ_INDEX)
             .with_context(|| "Failed disable irqfds in kvm")?;
+        self.vfio_enable_intx()
+    }
+
+    fn vfio_unregister_intx_irqfd(&mut self) -> Result<()> {
+        if let Some(intx) = self.intx.take() {
+            if intx.enabled {
+                self.vfio_device.lock().unwrap().disable_intx()?;
+            }
+            KVM_FDS
+                .load()
+                .unregister_irqfd(&intx.trigger_fd, intx.gsi)?;
+        }
         Ok(())
     }
 
@@ -776,8 +1166,12 @@ impl VfioPciDevice {
     }
 
     fn unrealize(&mut self) -> Result<()> {
+        if !self.msi_routes.is_empty() {
+            self.vfio_disable_msi()?;
+        }
         self.vfio_disable_msix()?;
         self.vfio_unregister_all_irqfd()?;
+        self.vfio_unregister_intx_irqfd()?;
         self.unregister_bars()?;
 
         let locked_dev = self.vfio_device.lock().unwrap();
@@ -797,6 +1191,7 @@ impl VfioPciDevice {
                 drop(groups);
                 drop(locked_container);
                 self.mem_as.unregister_listener(container.clone())?;
+                MigrationManager::unregister_dirty_tracker(&dirty_tracker_id(container_fd));
                 CONTAINERS.lock().unwrap().remove(&container_fd);
             }
         }
@@ -848,22 +1243,30 @@ impl PciDevOps for VfioPciDevice {
             self.dev_id = Arc::new(AtomicU16::new(self.set_dev_id(bus_num, self.devfn)));
         }
 
-        self.msix_info = Some(pci::Result::with_context(self.get_msix_info(), || {
-            "Failed to get MSI-X info"
-        })?);
+        if self.pci_config.find_pci_cap(MSIX_CAP_ID) != 0xff {
+            self.msix_info = Some(pci::Result::with_context(self.get_msix_info(), || {
+                "Failed to get MSI-X info"
+            })?);
+        }
+        pci::Result::with_context(self.init_msi_info(), || "Failed to get MSI info")?;
         self.vfio_bars = Arc::new(Mutex::new(pci::Result::with_context(
             self.bar_region_info(),
             || "Failed to get bar region info",
         )?));
         pci::Result::with_context(self.register_bars(), || "Failed to register bars")?;
+        pci::Result::with_context(self.register_rom_bar(), || "Failed to register ROM bar")?;
+        pci::Result::with_context(self.setup_intx(), || "Failed to setup INTx")?;
+        self.migration = VfioMigration::new(self.vfio_device.clone());
 
         let devfn = self.devfn;
+        let name = self.name.clone();
+        let migratable = self.migration.is_some();
         let dev = Arc::new(Mutex::new(self));
         let pci_bus = dev.lock().unwrap().parent_bus.upgrade().unwrap();
         let mut locked_pci_bus = pci_bus.lock().unwrap();
         let pci_device = locked_pci_bus.devices.get(&devfn);
         if pci_device.is_none() {
-            locked_pci_bus.devices.insert(devfn, dev);
+            locked_pci_bus.devices.insert(devfn, dev.clone());
         } else {
             bail!(
                 "Devfn {:?} has been used by {:?}",
@@ -871,6 +1274,15 @@ impl PciDevOps for VfioPciDevice {
                 pci_device.unwrap().lock().unwrap().name()
             );
         }
+        if migratable {
+            MigrationManager::register_device_instance(VfioPciState::descriptor(), dev, &name);
+        } else {
+            // The state of the physical device is not visible to StratoVirt.
+            MigrationManager::register_migration_blocker(
+                &name,
+                "vfio-pci device doesn't support VFIO migration",
+            );
+        }
 
         Ok(())
     }
@@ -880,6 +1292,8 @@ impl PciDevOps for VfioPciDevice {
             error!("{}", format!("{:?}", e));
             bail!("Failed to unrealize vfio-pci.");
         }
+        MigrationManager::unregister_migration_blocker(&self.name);
+        MigrationManager::unregister_device_instance(VfioPciState::descriptor(), &self.name);
         Ok(())
     }
 
@@ -899,8 +1313,16 @@ impl PciDevOps for VfioPciDevice {
             return;
         }
 
-        // BAR, header_type and extended caps are always controlled by StratoVirt.
+        // BAR, ROM, interrupt line/pin, header_type and extended caps are always controlled
+        // by StratoVirt.
         if ranges_overlap(offset, end, BAR_0 as usize, (BAR_5 as usize) + REG_SIZE)
+            || ranges_overlap(offset, end, ROM_ADDRESS, ROM_ADDRESS + REG_SIZE)
+            || ranges_overlap(
+                offset,
+                end,
+                INTERRUPT_LINE as usize,
+                (INTERRUPT_PIN as usize) + 1,
+            )
             || ranges_overlap(
                 offset,
                 end,
@@ -920,13 +1342,6 @@ impl PciDevOps for VfioPciDevice {
                 .read_region(data, self.config_offset, offset as u64)
         {
             error!("Failed to read device pci config, error is {}", e);
-            return;
-        }
-        for (i, data) in data.iter_mut().enumerate().take(size) {
-            if i + offset == 0x3d {
-                // Clear INIx
-                *data &= 0;
-            }
         }
     }
 
@@ -959,6 +1374,8 @@ impl PciDevOps for VfioPciDevice {
             .as_ref()
             .map_or(0, |m| m.lock().unwrap().msix_cap_offset as usize);
         let was_enable = is_msix_enabled(cap_offset, &self.pci_config.config);
+        let msi_was_enable =
+            matches!(self.msi_info, Some(msi) if msi.is_enabled(&self.pci_config.config));
         let parent_bus = self.parent_bus.upgrade().unwrap();
         let locked_parent_bus = parent_bus.lock().unwrap();
         self.pci_config.write(
@@ -969,6 +1386,7 @@ impl PciDevOps for VfioPciDevice {
             Some(&locked_parent_bus.io_region),
             Some(&locked_parent_bus.mem_region),
         );
+        drop(locked_parent_bus);
 
         if ranges_overlap(offset, end, COMMAND as usize, COMMAND as usize + REG_SIZE) {
             if le_read_u32(&self.pci_config.config, offset).unwrap() & COMMAND_MEMORY_SPACE as u32
@@ -978,7 +1396,9 @@ impl PciDevOps for VfioPciDevice {
                     error!("Failed to map bar regions, error is {}", format!("{:?}", e));
                 }
             }
-        } else if ranges_overlap(offset, end, cap_offset, cap_offset + MSIX_CAP_SIZE as usize) {
+        } else if self.pci_config.msix.is_some()
+            && ranges_overlap(offset, end, cap_offset, cap_offset + MSIX_CAP_SIZE as usize)
+        {
             let is_enable = is_msix_enabled(cap_offset, &self.pci_config.config);
 
             if !was_enable && is_enable {
@@ -990,6 +1410,25 @@ impl PciDevOps for VfioPciDevice {
                     error!("{}\nFailed to disable MSI-X.", format!("{:?}", e));
                 }
             }
+        } else if let Some(msi_info) = self.msi_info {
+            if !ranges_overlap(
+                offset,
+                end,
+                msi_info.cap_offset,
+                msi_info.cap_offset + msi_info.cap_size(),
+            ) {
+                return;
+            }
+            let is_enable = msi_info.is_enabled(&self.pci_config.config);
+            let result = match (msi_was_enable, is_enable) {
+                (false, true) => self.vfio_enable_msi(),
+                (true, false) => self.vfio_disable_msi(),
+                (true, true) => self.vfio_update_msi(),
+                (false, false) => Ok(()),
+            };
+            if let Err(e) = result {
+                error!("{}\nFailed to update MSI.", format!("{:?}", e));
+            }
         }
     }
 
@@ -998,12 +1437,144 @@ impl PciDevOps for VfioPciDevice {
     }
 
     fn reset(&mut self, _reset_child_device: bool) -> pci::Result<()> {
+        if let Some(msi_info) = self.msi_info {
+            if !self.msi_routes.is_empty() {
+                pci::Result::with_context(self.vfio_disable_msi(), || "Fail to disable MSI")?;
+            }
+            let offset = msi_info.cap_offset + MSI_CAP_CONTROL;
+            let ctrl = le_read_u16(&self.pci_config.config, offset)?;
+            le_write_u16(&mut self.pci_config.config, offset, ctrl & !MSI_CAP_ENABLE)?;
+        }
         pci::Result::with_context(self.vfio_device.lock().unwrap().reset(), || {
             "Fail to reset vfio dev"
         })
     }
 }
 
+impl StateTransfer for VfioPciDevice {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        let mut state = VfioPciState {
+            dev_id: self.dev_id.load(Ordering::Acquire),
+            ..Default::default()
+        };
+        let config_len = self.pci_config.config.len();
+        state.config_space[..config_len].copy_from_slice(&self.pci_config.config);
+        if let Some(msix) = &self.pci_config.msix {
+            let locked_msix = msix.lock().unwrap();
+            let table_len = locked_msix.table.len();
+            state.msix_table[..table_len].copy_from_slice(&locked_msix.table);
+        }
+
+        Ok(state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        let state = VfioPciState::from_bytes(state)
+            .with_context(|| migration::error::MigrationError::FromBytesError("VFIO_PCI"))?;
+
+        self.dev_id.store(state.dev_id, Ordering::Release);
+        let config_len = self.pci_config.config.len();
+        self.pci_config
+            .config
+            .copy_from_slice(&state.config_space[..config_len]);
+        if let Some(msix) = &self.pci_config.msix {
+            let mut locked_msix = msix.lock().unwrap();
+            let table_len = locked_msix.table.len();
+            locked_msix
+                .table
+                .copy_from_slice(&state.msix_table[..table_len]);
+            let cap_offset = locked_msix.msix_cap_offset as usize;
+            let ctrl = le_read_u16(
+                &self.pci_config.config,
+                cap_offset + MSIX_CAP_CONTROL as usize,
+            )?;
+            locked_msix.enabled = ctrl & MSIX_CAP_ENABLE != 0;
+            locked_msix.func_masked = ctrl & MSIX_CAP_FUNC_MASK != 0;
+        }
+
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&VfioPciState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+impl MigrationHook for VfioPciDevice {
+    fn resume(&mut self) -> migration::Result<()> {
+        // Map BARs at the addresses assigned by guest.
+        {
+            let parent_bus = self.parent_bus.upgrade().unwrap();
+            let locked_parent_bus = parent_bus.lock().unwrap();
+            self.pci_config.update_bar_mapping(
+                #[cfg(target_arch = "x86_64")]
+                Some(&locked_parent_bus.io_region),
+                Some(&locked_parent_bus.mem_region),
+            )?;
+        }
+        let cmd = le_read_u16(&self.pci_config.config, COMMAND as usize)?;
+        if cmd & COMMAND_MEMORY_SPACE != 0 {
+            self.setup_bars_mmap()?;
+        }
+        self.vfio_device.lock().unwrap().write_region(
+            &cmd.to_le_bytes(),
+            self.config_offset,
+            COMMAND as u64,
+        )?;
+
+        // Restore interrupts, the routes of MSI-X vectors are set up by replaying the table.
+        if matches!(self.msi_info, Some(msi) if msi.is_enabled(&self.pci_config.config)) {
+            self.vfio_enable_msi()?;
+        } else if let Some(msix) = self.pci_config.msix.clone() {
+            let (enabled, table) = {
+                let locked_msix = msix.lock().unwrap();
+                (locked_msix.enabled, locked_msix.table.clone())
+            };
+            if enabled {
+                self.vfio_enable_msix()?;
+                let table_ops = self.msix_table_ops.as_ref().unwrap();
+                let entry_size = MSIX_TABLE_ENTRY_SIZE as usize;
+                for (vector, entry) in table.chunks_exact(entry_size).enumerate() {
+                    if msix.lock().unwrap().is_vector_masked(vector as u16) {
+                        continue;
+                    }
+                    (table_ops.write)(entry, GuestAddress(0), (vector * entry_size) as u64);
+                }
+            }
+        }
+
+        if let Some(migration) = self.migration.as_mut() {
+            migration.resume()?;
+        }
+        Ok(())
+    }
+
+    fn has_device_data(&self) -> bool {
+        self.migration.is_some()
+    }
+
+    fn set_device_data_stage(&mut self, stage: DeviceDataStage) -> migration::Result<()> {
+        match self.migration.as_mut() {
+            Some(migration) => migration.set_stage(stage),
+            None => Ok(()),
+        }
+    }
+
+    fn read_device_data(&mut self, max_len: usize) -> migration::Result<Vec<u8>> {
+        match self.migration.as_mut() {
+            Some(migration) => migration.read_data(max_len),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn write_device_data(&mut self, data: &[u8]) -> migration::Result<()> {
+        self.migration
+            .as_mut()
+            .with_context(|| format!("Vfio device {} doesn't support migration", self.name))?
+            .write_data(data)
+    }
+}
+
 fn get_irq_rawfds(gsi_msi_routes: &[GsiMsiRoute], start: u32, count: u32) -> Vec<RawFd> {
     let mut rawfds: Vec<RawFd> = Vec::new();
     for r in gsi_msi_routes.iter() {
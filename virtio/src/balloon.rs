@@ -33,6 +33,8 @@ use machine_manager::{
     qmp::qmp_schema::BalloonInfo,
     qmp::QmpChannel,
 };
+use migration::{DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager, StateTransfer};
+use migration_derive::{ByteCode, Desc};
 use util::{
     bitmap::Bitmap,
     byte_code::ByteCode,
@@ -47,7 +49,8 @@ use vmm_sys_util::{epoll::EventSet, eventfd::EventFd, timerfd::TimerFd};
 
 use super::{
     error::*, virtio_has_feature, Element, Queue, VirtioDevice, VirtioInterrupt,
-    VirtioInterruptType, VirtioTrace, VIRTIO_F_VERSION_1, VIRTIO_TYPE_BALLOON,
+    VirtioInterruptType, VirtioTrace, VIRTIO_F_RING_PACKED, VIRTIO_F_VERSION_1,
+    VIRTIO_TYPE_BALLOON,
 };
 
 const VIRTIO_BALLOON_F_DEFLATE_ON_OOM: u32 = 2;
@@ -73,6 +76,7 @@ struct GuestIovec {
 }
 
 /// Balloon configuration, which would be used to transport data between `Guest` and `Host`.
+#[repr(C)]
 #[derive(Copy, Clone, Default)]
 #[allow(dead_code)]
 struct VirtioBalloonConfig {
@@ -743,8 +747,23 @@ impl EventNotifierHelper for BalloonIoHandler {
     }
 }
 
+/// State of balloon device.
+#[repr(C)]
+#[derive(Clone, Copy, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+pub struct BalloonState {
+    /// Balloon device features.
+    device_features: u64,
+    /// Driver features.
+    driver_features: u64,
+    /// Config space of the balloon device.
+    config_space: VirtioBalloonConfig,
+}
+
 /// A balloon device with some necessary information.
 pub struct Balloon {
+    /// Balloon device id.
+    id: String,
     /// Balloon device features.
     device_features: u64,
     /// Driver features.
@@ -774,7 +793,7 @@ impl Balloon {
     ///
     /// * `bln_cfg` - Balloon configuration.
     pub fn new(bln_cfg: &BalloonConfig, mem_space: Arc<AddressSpace>, mem_share: bool) -> Balloon {
-        let mut device_features = 1u64 << VIRTIO_F_VERSION_1;
+        let mut device_features = 1u64 << VIRTIO_F_VERSION_1 | 1u64 << VIRTIO_F_RING_PACKED;
         if bln_cfg.deflate_on_oom {
             device_features |= 1u64 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM;
         }
@@ -783,6 +802,7 @@ impl Balloon {
         }
 
         Balloon {
+            id: bln_cfg.id.clone(),
             device_features,
             driver_features: 0u64,
             actual: Arc::new(AtomicU32::new(0)),
@@ -807,6 +827,15 @@ impl Balloon {
         }
     }
 
+    /// Release the global balloon object when the device is removed.
+    fn object_fini() {
+        // Safe, because there is no confliction when writing global variable BALLOON_DEV, in other words,
+        // this function will not be called simultaneously.
+        unsafe {
+            BALLOON_DEV = None;
+        }
+    }
+
     /// Notify configuration changes to VM.
     fn signal_config_change(&self) -> Result<()> {
         if let Some(interrupt_cb) = &self.interrupt_cb {
@@ -869,6 +898,16 @@ impl VirtioDevice for Balloon {
         Ok(())
     }
 
+    /// Unrealize a balloon device.
+    fn unrealize(&mut self) -> Result<()> {
+        self.mem_space
+            .unregister_listener(self.mem_info.clone())
+            .with_context(|| "Failed to unregister memory listener defined by balloon device.")?;
+        MigrationManager::unregister_device_instance(BalloonState::descriptor(), &self.id);
+        Self::object_fini();
+        Ok(())
+    }
+
     /// Get the type of balloon.
     fn device_type(&self) -> u32 {
         VIRTIO_TYPE_BALLOON
@@ -1045,6 +1084,37 @@ impl VirtioDevice for Balloon {
     }
 }
 
+impl StateTransfer for Balloon {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        let state = BalloonState {
+            device_features: self.device_features,
+            driver_features: self.driver_features,
+            config_space: VirtioBalloonConfig {
+                num_pages: self.num_pages,
+                actual: self.actual.load(Ordering::Acquire),
+            },
+        };
+        Ok(state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        let state = BalloonState::from_bytes(state)
+            .ok_or_else(|| anyhow!(migration::error::MigrationError::FromBytesError("BALLOON")))?;
+        self.device_features = state.device_features;
+        self.driver_features = state.driver_features;
+        self.num_pages = state.config_space.num_pages;
+        self.actual
+            .store(state.config_space.actual, Ordering::Release);
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&BalloonState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+impl MigrationHook for Balloon {}
+
 pub fn qmp_balloon(target: u64) -> bool {
     // Safe, because there is no confliction when writing global variable BALLOON_DEV, in other words,
     // this function will not be called simultaneously.
@@ -1148,7 +1218,9 @@ mod tests {
         assert_eq!(bln.actual.load(Ordering::Acquire), 0);
         assert_eq!(bln.num_pages, 0);
         assert!(bln.interrupt_cb.is_none());
-        let feature = (1u64 << VIRTIO_F_VERSION_1) | (1u64 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM);
+        let feature = (1u64 << VIRTIO_F_VERSION_1)
+            | (1u64 << VIRTIO_F_RING_PACKED)
+            | (1u64 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM);
         assert_eq!(bln.device_features, feature);
 
         let fts = bln.get_device_features(0);
@@ -1180,6 +1252,29 @@ mod tests {
         assert!(bln.update_config(None).is_err());
     }
 
+    #[test]
+    fn test_balloon_state_transfer() {
+        let bln_cfg = BalloonConfig {
+            id: "bln".to_string(),
+            deflate_on_oom: true,
+            free_page_reporting: Default::default(),
+        };
+        let mem_space = address_space_init();
+        let mut bln = Balloon::new(&bln_cfg, mem_space.clone(), false);
+        bln.driver_features = 1u64 << VIRTIO_F_VERSION_1;
+        bln.num_pages = 64;
+        bln.actual.store(32, Ordering::Release);
+        let state = bln.get_state_vec().unwrap();
+
+        let mut dst = Balloon::new(&bln_cfg, mem_space, false);
+        dst.set_state_mut(&state).unwrap();
+        assert_eq!(dst.device_features, bln.device_features);
+        assert_eq!(dst.driver_features, 1u64 << VIRTIO_F_VERSION_1);
+        assert_eq!(dst.num_pages, 64);
+        assert_eq!(dst.actual.load(Ordering::Acquire), 32);
+        assert!(dst.set_state_mut(&state[1..]).is_err());
+    }
+
     #[test]
     fn test_read_config() {
         let bln_cfg = BalloonConfig {
@@ -1511,6 +1606,7 @@ mod tests {
         assert_eq!(bln.num_pages, 0);
         assert!(bln.interrupt_cb.is_none());
         let feature = (1u64 << VIRTIO_F_VERSION_1)
+            | (1u64 << VIRTIO_F_RING_PACKED)
             | (1u64 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM | 1u64 << VIRTIO_BALLOON_F_REPORTING);
         assert_eq!(bln.device_features, feature);
 
@@ -10,39 +10,54 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::cell::{Cell, RefCell};
 use std::cmp;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{Seek, SeekFrom, Write};
+use std::io::Write;
 use std::mem::size_of;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::rc::Rc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
 use super::{
-    iov_discard_back, iov_discard_front, iov_to_buf, report_virtio_error, virtio_has_feature,
-    Element, Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType, VirtioTrace,
-    VIRTIO_BLK_F_FLUSH, VIRTIO_BLK_F_MQ, VIRTIO_BLK_F_RO, VIRTIO_BLK_F_SEG_MAX,
-    VIRTIO_BLK_ID_BYTES, VIRTIO_BLK_S_IOERR, VIRTIO_BLK_S_OK, VIRTIO_BLK_S_UNSUPP,
-    VIRTIO_BLK_T_FLUSH, VIRTIO_BLK_T_GET_ID, VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT,
-    VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_VERSION_1, VIRTIO_TYPE_BLOCK,
+    handle_block_io_error, iov_discard_back, iov_discard_front, iov_to_buf, report_virtio_error,
+    virtio_has_feature, Element, Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType,
+    VirtioTrace, VIRTIO_BLK_F_DISCARD, VIRTIO_BLK_F_FLUSH, VIRTIO_BLK_F_MQ, VIRTIO_BLK_F_RO,
+    VIRTIO_BLK_F_SEG_MAX, VIRTIO_BLK_F_WRITE_ZEROES, VIRTIO_BLK_ID_BYTES, VIRTIO_BLK_S_IOERR,
+    VIRTIO_BLK_S_OK, VIRTIO_BLK_S_UNSUPP, VIRTIO_BLK_T_DISCARD, VIRTIO_BLK_T_FLUSH,
+    VIRTIO_BLK_T_GET_ID, VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT, VIRTIO_BLK_T_WRITE_ZEROES,
+    VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP, VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC,
+    VIRTIO_F_RING_PACKED, VIRTIO_F_VERSION_1, VIRTIO_TYPE_BLOCK,
 };
 use crate::VirtioError;
 use address_space::{AddressSpace, GuestAddress};
 use anyhow::{anyhow, bail, Context, Result};
+use block_backend::stats::{
+    block_acct_start, register_block_drive, unregister_block_drive, BlockAcctCookie,
+    BlockAcctStats, BlockAcctType, BlockDriveInfo, BlockStatsRef,
+};
+use block_backend::{
+    create_block_backend, register_block_backend, submit_rw_request, unregister_block_backend,
+    BlockBackend, BlockProperty,
+};
 use byteorder::{ByteOrder, LittleEndian};
 use log::{error, warn};
-use machine_manager::config::{BlkDevConfig, ConfigCheck, DriveFile, VmConfig};
+use machine_manager::config::{BlkDevConfig, BlockErrorPolicy, ConfigCheck, DriveFile, VmConfig};
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper, EventLoop};
+use machine_manager::machine::{register_vm_resume_notifier, unregister_vm_resume_notifier};
+use machine_manager::qmp::qmp_schema::{BlockErrorAction, IoOperationType};
 use migration::{
     migration::Migratable, DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager,
     StateTransfer,
 };
 use migration_derive::{ByteCode, Desc};
-use util::aio::{iov_from_buf_direct, raw_datasync, Aio, AioCb, AioEngine, Iovec, OpCode};
+use util::aio::{
+    iov_from_buf_direct, raw_datasync, Aio, AioCb, AioEngine, Iovec, OpCode, WriteZeroesState,
+};
 use util::byte_code::ByteCode;
 use util::leak_bucket::LeakBucket;
 use util::loop_context::{
@@ -67,15 +82,22 @@ const MAX_NUM_MERGE_IOVS: usize = 1024;
 const MAX_NUM_MERGE_BYTES: u64 = i32::MAX as u64;
 /// Max time for every round of process queue.
 const MAX_MILLIS_TIME_PROCESS_QUEUE: u16 = 100;
+/// Max number sectors of a discard or write zeroes request.
+const MAX_REQUEST_SECTORS: u32 = (i32::MAX as u32) >> SECTOR_SHIFT;
 
 type SenderConfig = (
     Option<Arc<File>>,
+    Option<BlockBackend>,
     u32,
     u32,
     u64,
     Option<String>,
     bool,
     AioEngine,
+    bool,
+    WriteZeroesState,
+    BlockErrorPolicy,
+    BlockErrorPolicy,
 );
 
 fn get_serial_num_config(serial_num: &str) -> Vec<u8> {
@@ -97,6 +119,48 @@ struct RequestOutHeader {
 
 impl ByteCode for RequestOutHeader {}
 
+/// The segment of discard or write zeroes request.
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct DiscardWriteZeroesSeg {
+    /// The start sector of the segment.
+    sector: u64,
+    /// The number of sectors of the segment.
+    num_sectors: u32,
+    /// Only VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP is defined for write zeroes request.
+    flags: u32,
+}
+
+impl ByteCode for DiscardWriteZeroesSeg {}
+
+/// Error handling state shared by the IO handler and its requests.
+struct BlockErrorCtx {
+    /// The id of the block device.
+    device_id: String,
+    /// Action on write error.
+    werror: Cell<BlockErrorPolicy>,
+    /// Action on read error.
+    rerror: Cell<BlockErrorPolicy>,
+    /// Requests stopped by the error policy, they are retried when the VM is resumed.
+    failed_reqs: RefCell<Vec<Rc<Request>>>,
+    /// Number of the stopped requests of all queues of the block device.
+    failed_num: Arc<AtomicUsize>,
+}
+
+impl BlockErrorCtx {
+    fn push_failed(&self, req: Rc<Request>) {
+        self.failed_reqs.borrow_mut().push(req);
+        self.failed_num.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn take_failed(&self) -> Vec<Rc<Request>> {
+        let failed_reqs = self.failed_reqs.take();
+        self.failed_num
+            .fetch_sub(failed_reqs.len(), Ordering::SeqCst);
+        failed_reqs
+    }
+}
+
 #[derive(Clone)]
 pub struct AioCompleteCb {
     queue: Arc<Mutex<Queue>>,
@@ -105,6 +169,10 @@ pub struct AioCompleteCb {
     req: Rc<Request>,
     interrupt_cb: Arc<VirtioInterrupt>,
     driver_features: u64,
+    /// Accounting of the request, None if the request is not accounted.
+    acct: Option<BlockAcctCookie>,
+    /// Error handling state of the block device.
+    error_ctx: Rc<BlockErrorCtx>,
 }
 
 impl AioCompleteCb {
@@ -114,6 +182,7 @@ impl AioCompleteCb {
         req: Rc<Request>,
         interrupt_cb: Arc<VirtioInterrupt>,
         driver_features: u64,
+        error_ctx: Rc<BlockErrorCtx>,
     ) -> Self {
         AioCompleteCb {
             queue,
@@ -121,6 +190,8 @@ impl AioCompleteCb {
             req,
             interrupt_cb,
             driver_features,
+            acct: None,
+            error_ctx,
         }
     }
 
@@ -174,6 +245,8 @@ struct Request {
     data_len: u64,
     in_len: u32,
     in_header: GuestAddress,
+    /// The segment of discard or write zeroes request.
+    segment: DiscardWriteZeroesSeg,
     /// Point to the next merged Request.
     next: Box<Option<Request>>,
 }
@@ -221,6 +294,7 @@ impl Request {
             data_len: 0,
             in_len: 0,
             in_header,
+            segment: DiscardWriteZeroesSeg::default(),
             next: Box::new(None),
         };
 
@@ -259,6 +333,22 @@ impl Request {
                 }
             }
             VIRTIO_BLK_T_FLUSH => (),
+            VIRTIO_BLK_T_DISCARD | VIRTIO_BLK_T_WRITE_ZEROES => {
+                let feature = if out_header.request_type == VIRTIO_BLK_T_DISCARD {
+                    VIRTIO_BLK_F_DISCARD
+                } else {
+                    VIRTIO_BLK_F_WRITE_ZEROES
+                };
+                if !virtio_has_feature(handler.driver_features, feature) {
+                    error!(
+                        "Request type {} is not negotiated for block",
+                        out_header.request_type
+                    );
+                    *status = VIRTIO_BLK_S_UNSUPP;
+                } else {
+                    *status = request.parse_segment(handler, elem)?;
+                }
+            }
             others => {
                 error!("Request type {} is not supported for block", others);
                 *status = VIRTIO_BLK_S_UNSUPP;
@@ -272,6 +362,43 @@ impl Request {
         Ok(request)
     }
 
+    /// Parse the only one segment of discard or write zeroes request, the status
+    /// of the request is returned.
+    fn parse_segment(&mut self, handler: &BlockIoHandler, elem: &mut Element) -> Result<u8> {
+        let data_iovec =
+            iov_discard_front(&mut elem.out_iovec, size_of::<RequestOutHeader>() as u64)
+                .with_context(|| "Empty data for discard or write zeroes request")?;
+        let data_len: u64 = data_iovec.iter().map(|iov| u64::from(iov.len)).sum();
+        // Only one segment is supported, refer to max_discard_seg and max_write_zeroes_seg.
+        if data_len != size_of::<DiscardWriteZeroesSeg>() as u64 {
+            error!(
+                "Invalid data length {} for discard or write zeroes request",
+                data_len
+            );
+            return Ok(VIRTIO_BLK_S_UNSUPP);
+        }
+
+        let mut segment = DiscardWriteZeroesSeg::default();
+        iov_to_buf(&handler.mem_space, data_iovec, segment.as_mut_bytes())?;
+        segment.sector = LittleEndian::read_u64(segment.sector.as_bytes());
+        segment.num_sectors = LittleEndian::read_u32(segment.num_sectors.as_bytes());
+        segment.flags = LittleEndian::read_u32(segment.flags.as_bytes());
+        let valid_flags = if self.out_header.request_type == VIRTIO_BLK_T_DISCARD {
+            0
+        } else {
+            VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP
+        };
+        if segment.flags & !valid_flags != 0 {
+            error!(
+                "Invalid flags {:#x} for discard or write zeroes request",
+                segment.flags
+            );
+            return Ok(VIRTIO_BLK_S_UNSUPP);
+        }
+        self.segment = segment;
+        Ok(VIRTIO_BLK_S_OK)
+    }
+
     fn execute(
         &self,
         iohandler: &mut BlockIoHandler,
@@ -293,27 +420,52 @@ impl Request {
         }
 
         let request_type = self.out_header.request_type;
-        if MigrationManager::is_active()
+        if (MigrationManager::is_active() || MigrationManager::is_tracking_snapshot())
             && (request_type == VIRTIO_BLK_T_IN || request_type == VIRTIO_BLK_T_GET_ID)
         {
             // FIXME: mark dirty page needs to be managed by `AddressSpace` crate.
             for iov in aiocb.iovec.iter() {
-                // Mark vmm dirty page manually if live migration is active.
+                // Mark vmm dirty page manually if dirty page is being logged.
                 MigrationManager::mark_dirty_log(iov.iov_base, iov.iov_len);
             }
         }
 
+        let acct_type = match request_type {
+            VIRTIO_BLK_T_IN => Some(BlockAcctType::Read),
+            VIRTIO_BLK_T_OUT | VIRTIO_BLK_T_WRITE_ZEROES => Some(BlockAcctType::Write),
+            VIRTIO_BLK_T_FLUSH => Some(BlockAcctType::Flush),
+            VIRTIO_BLK_T_DISCARD => Some(BlockAcctType::Discard),
+            _ => None,
+        };
+        if let Some(acct_type) = acct_type {
+            let bytes = match request_type {
+                VIRTIO_BLK_T_DISCARD | VIRTIO_BLK_T_WRITE_ZEROES => {
+                    u64::from(self.segment.num_sectors) << SECTOR_SHIFT
+                }
+                _ => aiocb.nbytes,
+            };
+            let mut ops = 0;
+            let mut req = Some(self);
+            while let Some(req_raw) = req {
+                ops += 1;
+                req = req_raw.next.as_ref().as_ref();
+            }
+            aiocb.iocompletecb.acct =
+                Some(block_acct_start(&iohandler.stats, acct_type, bytes, ops));
+        }
+
         let aio = &mut iohandler.aio;
+        let backend = &iohandler.block_backend;
         let serial_num = &iohandler.serial_num;
         match request_type {
             VIRTIO_BLK_T_IN => {
                 aiocb.opcode = OpCode::Preadv;
-                aio.submit_request(aiocb)
+                submit_rw_request(aio, backend, aiocb)
                     .with_context(|| "Failed to process block request for reading")?;
             }
             VIRTIO_BLK_T_OUT => {
                 aiocb.opcode = OpCode::Pwritev;
-                aio.submit_request(aiocb)
+                submit_rw_request(aio, backend, aiocb)
                     .with_context(|| "Failed to process block request for writing")?;
             }
             VIRTIO_BLK_T_FLUSH => {
@@ -321,6 +473,26 @@ impl Request {
                 aio.submit_request(aiocb)
                     .with_context(|| "Failed to process block request for flushing")?;
             }
+            VIRTIO_BLK_T_DISCARD => {
+                aiocb.opcode = OpCode::Discard;
+                aiocb.offset = (self.segment.sector << SECTOR_SHIFT) as usize;
+                aiocb.nbytes = u64::from(self.segment.num_sectors) << SECTOR_SHIFT;
+                submit_rw_request(aio, backend, aiocb)
+                    .with_context(|| "Failed to process block request for discarding")?;
+            }
+            VIRTIO_BLK_T_WRITE_ZEROES => {
+                aiocb.opcode = if self.segment.flags & VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP != 0
+                    && aiocb.discard
+                {
+                    OpCode::WriteZeroesUnmap
+                } else {
+                    OpCode::WriteZeroes
+                };
+                aiocb.offset = (self.segment.sector << SECTOR_SHIFT) as usize;
+                aiocb.nbytes = u64::from(self.segment.num_sectors) << SECTOR_SHIFT;
+                submit_rw_request(aio, backend, aiocb)
+                    .with_context(|| "Failed to process block request for writing zeroes")?;
+            }
             VIRTIO_BLK_T_GET_ID => {
                 let serial = serial_num.clone().unwrap_or_else(|| String::from(""));
                 let serial_vec = get_serial_num_config(&serial);
@@ -360,6 +532,29 @@ impl Request {
                 }
                 true
             }
+            VIRTIO_BLK_T_DISCARD | VIRTIO_BLK_T_WRITE_ZEROES => {
+                if self.segment.num_sectors > MAX_REQUEST_SECTORS {
+                    error!(
+                        "Too many sectors {} for discard or write zeroes request",
+                        self.segment.num_sectors
+                    );
+                    return false;
+                }
+                if self
+                    .segment
+                    .sector
+                    .checked_add(u64::from(self.segment.num_sectors))
+                    .filter(|&off| off <= disk_sectors)
+                    .is_none()
+                {
+                    error!(
+                        "offset {} invalid, disk sector {}",
+                        self.segment.sector, disk_sectors
+                    );
+                    return false;
+                }
+                true
+            }
             _ => true,
         }
     }
@@ -379,6 +574,8 @@ struct BlockIoHandler {
     mem_space: Arc<AddressSpace>,
     /// The image file opened by the block device.
     disk_image: Option<Arc<File>>,
+    /// The format driver of the image file.
+    block_backend: Option<BlockBackend>,
     /// The align requirement of request(offset/len).
     pub req_align: u32,
     /// The align requirement of buffer(iova_base).
@@ -389,6 +586,10 @@ struct BlockIoHandler {
     serial_num: Option<String>,
     /// If use direct access io.
     direct: bool,
+    /// Pass the discard requests to the file.
+    discard: bool,
+    /// Detect the write requests of zeroes.
+    write_zeroes: WriteZeroesState,
     /// Aio context.
     aio: Box<Aio<AioCompleteCb>>,
     /// Bit mask of features negotiated by the backend and the frontend.
@@ -405,6 +606,12 @@ struct BlockIoHandler {
     iothread: Option<String>,
     /// Using the leak bucket to implement IO limits
     leak_bucket: Option<LeakBucket>,
+    /// IO statistics of the block device.
+    stats: BlockStatsRef,
+    /// Error handling state of the block device.
+    error_ctx: Rc<BlockErrorCtx>,
+    /// Eventfd to retry the failed requests after the VM is resumed.
+    retry_evt: Arc<EventFd>,
 }
 
 impl BlockIoHandler {
@@ -487,6 +694,7 @@ impl BlockIoHandler {
                     Rc::new(req),
                     self.interrupt_cb.clone(),
                     self.driver_features,
+                    self.error_ctx.clone(),
                 );
                 // unlock queue, because it will be hold below.
                 drop(queue);
@@ -507,38 +715,59 @@ impl BlockIoHandler {
 
         let merge_req_queue = self.merge_req_queue(req_queue);
         for req in merge_req_queue.into_iter() {
-            let req_rc = Rc::new(req);
-            let aiocompletecb = AioCompleteCb::new(
-                self.queue.clone(),
-                self.mem_space.clone(),
-                req_rc.clone(),
-                self.interrupt_cb.clone(),
-                self.driver_features,
-            );
-            if let Some(disk_img) = self.disk_image.as_ref() {
-                let aiocb = AioCb {
-                    direct: self.direct,
-                    req_align: self.req_align,
-                    buf_align: self.buf_align,
-                    file_fd: disk_img.as_raw_fd(),
-                    opcode: OpCode::Noop,
-                    iovec: Vec::new(),
-                    offset: (req_rc.out_header.sector << SECTOR_SHIFT) as usize,
-                    nbytes: 0,
-                    user_data: 0,
-                    iocompletecb: aiocompletecb,
-                };
-                req_rc.execute(self, aiocb)?;
-            } else {
-                warn!("Failed to execute block request, disk_img not specified");
-                aiocompletecb.complete_request(VIRTIO_BLK_S_IOERR)?;
-            }
+            self.submit_request(Rc::new(req))?;
         }
         self.aio.flush_request()?;
 
         Ok(done)
     }
 
+    fn submit_request(&mut self, req_rc: Rc<Request>) -> Result<()> {
+        let aiocompletecb = AioCompleteCb::new(
+            self.queue.clone(),
+            self.mem_space.clone(),
+            req_rc.clone(),
+            self.interrupt_cb.clone(),
+            self.driver_features,
+            self.error_ctx.clone(),
+        );
+        if let Some(disk_img) = self.disk_image.as_ref() {
+            let aiocb = AioCb {
+                direct: self.direct,
+                req_align: self.req_align,
+                buf_align: self.buf_align,
+                discard: self.discard,
+                write_zeroes: self.write_zeroes,
+                file_fd: disk_img.as_raw_fd(),
+                opcode: OpCode::Noop,
+                iovec: Vec::new(),
+                offset: (req_rc.out_header.sector << SECTOR_SHIFT) as usize,
+                nbytes: 0,
+                user_data: 0,
+                iocompletecb: aiocompletecb,
+                combine_req: None,
+            };
+            req_rc.execute(self, aiocb)
+        } else {
+            warn!("Failed to execute block request, disk_img not specified");
+            aiocompletecb.complete_request(VIRTIO_BLK_S_IOERR)
+        }
+    }
+
+    /// Submit again the requests stopped by the error policy, then go on with
+    /// the requests in the virtqueue.
+    fn retry_failed_requests(&mut self) -> Result<bool> {
+        let failed_reqs = self.error_ctx.take_failed();
+        if failed_reqs.is_empty() {
+            return Ok(false);
+        }
+        for req in failed_reqs {
+            self.submit_request(req)?;
+        }
+        self.aio.flush_request()?;
+        self.process_queue()
+    }
+
     fn process_queue_suppress_notify(&mut self) -> Result<bool> {
         let mut done = false;
         let start_time = Instant::now();
@@ -547,6 +776,11 @@ impl BlockIoHandler {
             done = true;
             return Ok(done);
         }
+        // Keep the order of the requests, the new ones are processed after the
+        // stopped ones are retried.
+        if !self.error_ctx.failed_reqs.borrow().is_empty() {
+            return Ok(done);
+        }
         while self
             .queue
             .lock()
@@ -610,10 +844,32 @@ impl BlockIoHandler {
         };
 
         let complete_cb = &aiocb.iocompletecb;
+        if ret < 0 {
+            let error_ctx = &complete_cb.error_ctx;
+            let (operation, policy) = match aiocb.opcode {
+                OpCode::Preadv => (IoOperationType::read, error_ctx.rerror.get()),
+                _ => (IoOperationType::write, error_ctx.werror.get()),
+            };
+            match handle_block_io_error(&error_ctx.device_id, operation, policy, ret) {
+                BlockErrorAction::stop => {
+                    // The request is not completed, keep it to retry on resume.
+                    if let Some(acct) = complete_cb.acct.as_ref() {
+                        acct.cancel();
+                    }
+                    error_ctx.push_failed(complete_cb.req.clone());
+                    return Ok(());
+                }
+                BlockErrorAction::ignore => status = VIRTIO_BLK_S_OK,
+                BlockErrorAction::report => {}
+            }
+        }
         // When driver does not accept FLUSH feature, the device must be of
         // writethrough cache type, so flush data before updating used ring.
         if !virtio_has_feature(complete_cb.driver_features, VIRTIO_BLK_F_FLUSH)
-            && aiocb.opcode == OpCode::Pwritev
+            && matches!(
+                aiocb.opcode,
+                OpCode::Pwritev | OpCode::WriteZeroes | OpCode::WriteZeroesUnmap
+            )
             && ret >= 0
             && raw_datasync(aiocb.file_fd) < 0
         {
@@ -621,6 +877,9 @@ impl BlockIoHandler {
             status = VIRTIO_BLK_S_IOERR;
         }
 
+        if let Some(acct) = complete_cb.acct.as_ref() {
+            acct.done(status != VIRTIO_BLK_S_OK);
+        }
         complete_cb.complete_request(status)
     }
 
@@ -638,27 +897,65 @@ impl BlockIoHandler {
     fn update_evt_handler(&mut self) {
         let aio_engine;
         match self.receiver.recv() {
-            Ok((image, req_align, buf_align, disk_sectors, serial_num, direct, aio)) => {
+            Ok((
+                image,
+                backend,
+                req_align,
+                buf_align,
+                disk_sectors,
+                serial_num,
+                direct,
+                aio,
+                discard,
+                write_zeroes,
+                werror,
+                rerror,
+            )) => {
                 self.disk_sectors = disk_sectors;
                 self.disk_image = image;
+                self.block_backend = backend;
                 self.req_align = req_align;
                 self.buf_align = buf_align;
                 self.serial_num = serial_num;
                 self.direct = direct;
+                self.discard = discard;
+                self.write_zeroes = write_zeroes;
+                self.error_ctx.werror.set(werror);
+                self.error_ctx.rerror.set(rerror);
                 aio_engine = aio;
             }
             Err(e) => {
                 error!("Failed to receive config in updating handler {:?}", e);
                 self.disk_sectors = 0;
                 self.disk_image = None;
+                self.block_backend = None;
                 self.req_align = 1;
                 self.buf_align = 1;
                 self.serial_num = None;
                 self.direct = true;
+                self.discard = false;
+                self.write_zeroes = WriteZeroesState::Off;
+                self.error_ctx.werror.set(BlockErrorPolicy::Report);
+                self.error_ctx.rerror.set(BlockErrorPolicy::Report);
                 aio_engine = AioEngine::Native;
             }
         };
 
+        // The stopped requests belong to the replaced image, fail them.
+        for req in self.error_ctx.take_failed() {
+            let aiocompletecb = AioCompleteCb::new(
+                self.queue.clone(),
+                self.mem_space.clone(),
+                req,
+                self.interrupt_cb.clone(),
+                self.driver_features,
+                self.error_ctx.clone(),
+            );
+            if let Err(e) = aiocompletecb.complete_request(VIRTIO_BLK_S_IOERR) {
+                error!("Failed to complete the stopped block request {:?}", e);
+            }
+        }
+
         if self.aio.get_engine() != aio_engine {
             match Aio::new(Arc::new(Self::complete_func), aio_engine) {
                 Ok(aio) => {
@@ -773,6 +1070,25 @@ impl EventNotifierHelper for BlockIoHandler {
             Some(handler_iopoll),
         ));
 
+        // Register event notifier for retry_evt.
+        let h_clone = handler.clone();
+        let h: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            let mut h_lock = h_clone.lock().unwrap();
+            if h_lock.device_broken.load(Ordering::SeqCst) {
+                return None;
+            }
+            if let Err(ref e) = h_lock.retry_failed_requests() {
+                error!("Failed to retry block IO {:?}", e);
+            }
+            None
+        });
+        notifiers.push(build_event_notifier(
+            handler_raw.retry_evt.as_raw_fd(),
+            vec![h],
+            None,
+        ));
+
         // Register timer event notifier for IO limits
         if let Some(lb) = handler_raw.leak_bucket.as_ref() {
             let h_clone = handler.clone();
@@ -915,6 +1231,8 @@ pub struct Block {
     blk_cfg: BlkDevConfig,
     /// Image file opened.
     disk_image: Option<Arc<File>>,
+    /// The format driver of the image file.
+    block_backend: Option<BlockBackend>,
     /// The align requirement of request(offset/len).
     pub req_align: u32,
     /// The align requirement of buffer(iova_base).
@@ -929,12 +1247,18 @@ pub struct Block {
     senders: Vec<Sender<SenderConfig>>,
     /// Eventfd for config space update.
     update_evts: Vec<Arc<EventFd>>,
+    /// Eventfd to retry the failed requests.
+    retry_evts: Vec<Arc<EventFd>>,
+    /// Number of requests stopped by the error policy, which are not saved with the device.
+    failed_num: Arc<AtomicUsize>,
     /// Eventfd for device deactivate.
     deactivate_evts: Vec<RawFd>,
     /// Device is broken or not.
     broken: Arc<AtomicBool>,
     /// Drive backend files.
     drive_files: Arc<Mutex<HashMap<String, DriveFile>>>,
+    /// IO statistics of the block device.
+    stats: BlockStatsRef,
 }
 
 impl Block {
@@ -945,6 +1269,7 @@ impl Block {
         Self {
             blk_cfg,
             disk_image: None,
+            block_backend: None,
             req_align: 1,
             buf_align: 1,
             disk_sectors: 0,
@@ -952,9 +1277,12 @@ impl Block {
             interrupt_cb: None,
             senders: Vec::new(),
             update_evts: Vec::new(),
+            retry_evts: Vec::new(),
+            failed_num: Arc::new(AtomicUsize::new(0)),
             deactivate_evts: Vec::new(),
             broken: Arc::new(AtomicBool::new(false)),
             drive_files,
+            stats: Arc::new(Mutex::new(BlockAcctStats::default())),
         }
     }
 
@@ -964,6 +1292,29 @@ impl Block {
         self.state.config_space.capacity = num_sectors;
         // seg_max = queue_size - 2: 32bits
         self.state.config_space.seg_max = self.queue_size() as u32 - 2;
+
+        let config = &mut self.state.config_space;
+        let discard = virtio_has_feature(self.state.device_features, VIRTIO_BLK_F_DISCARD);
+        config.max_discard_sectors = if discard { MAX_REQUEST_SECTORS } else { 0 };
+        config.max_discard_seg = discard as u32;
+        config.discard_sector_alignment = discard as u32;
+        let write_zeroes =
+            virtio_has_feature(self.state.device_features, VIRTIO_BLK_F_WRITE_ZEROES);
+        config.max_write_zeroes_sectors = if write_zeroes { MAX_REQUEST_SECTORS } else { 0 };
+        config.max_write_zeroes_seg = write_zeroes as u32;
+        config.write_zeroes_may_unmap = discard as u8;
+    }
+
+    /// Get the length of the config space, the fields of discard and write zeroes
+    /// exist only if the features are offered.
+    fn get_blk_config_size(&self) -> u64 {
+        if virtio_has_feature(self.state.device_features, VIRTIO_BLK_F_WRITE_ZEROES) {
+            offset_of!(VirtioBlkConfig, unused1) as u64
+        } else if virtio_has_feature(self.state.device_features, VIRTIO_BLK_F_DISCARD) {
+            offset_of!(VirtioBlkConfig, max_write_zeroes_sectors) as u64
+        } else {
+            offset_of!(VirtioBlkConfig, max_discard_sectors) as u64
+        }
     }
 }
 
@@ -987,6 +1338,13 @@ impl VirtioDevice for Block {
         self.state.device_features |= 1_u64 << VIRTIO_F_RING_INDIRECT_DESC;
         self.state.device_features |= 1_u64 << VIRTIO_BLK_F_SEG_MAX;
         self.state.device_features |= 1_u64 << VIRTIO_F_RING_EVENT_IDX;
+        self.state.device_features |= 1_u64 << VIRTIO_F_RING_PACKED;
+        if !self.blk_cfg.read_only {
+            if self.blk_cfg.discard {
+                self.state.device_features |= 1_u64 << VIRTIO_BLK_F_DISCARD;
+            }
+            self.state.device_features |= 1_u64 << VIRTIO_BLK_F_WRITE_ZEROES;
+        }
 
         self.build_device_config_space();
 
@@ -996,18 +1354,44 @@ impl VirtioDevice for Block {
         }
 
         self.disk_image = None;
+        self.block_backend = None;
         self.disk_sectors = DUMMY_IMG_SIZE >> SECTOR_SHIFT;
         self.req_align = 1;
         self.buf_align = 1;
         if !self.blk_cfg.path_on_host.is_empty() {
             let drive_files = self.drive_files.lock().unwrap();
-            let mut file = VmConfig::fetch_drive_file(&drive_files, &self.blk_cfg.path_on_host)?;
+            let file = Arc::new(VmConfig::fetch_drive_file(
+                &drive_files,
+                &self.blk_cfg.path_on_host,
+            )?);
             let alignments = VmConfig::fetch_drive_align(&drive_files, &self.blk_cfg.path_on_host)?;
-            let disk_size = file
-                .seek(SeekFrom::End(0))
-                .with_context(|| "Failed to seek the end for block")?;
+            let prop = BlockProperty {
+                format: self.blk_cfg.format,
+                read_only: self.blk_cfg.read_only,
+                direct: self.blk_cfg.direct,
+                req_align: alignments.0,
+                buf_align: alignments.1,
+                path: self.blk_cfg.path_on_host.clone(),
+            };
+            let backend = create_block_backend(file.clone(), prop)?;
+            let disk_size = backend.lock().unwrap().disk_size()?;
+            register_block_backend(&self.blk_cfg.id, backend.clone());
+            register_block_drive(
+                BlockDriveInfo {
+                    id: self.blk_cfg.id.clone(),
+                    file: self.blk_cfg.path_on_host.clone(),
+                    format: self.blk_cfg.format,
+                    read_only: self.blk_cfg.read_only,
+                    direct: self.blk_cfg.direct,
+                    aio: self.blk_cfg.aio,
+                    iops: self.blk_cfg.iops,
+                    write_zeroes: self.blk_cfg.write_zeroes,
+                },
+                self.stats.clone(),
+            );
 
-            self.disk_image = Some(Arc::new(file));
+            self.disk_image = Some(file);
+            self.block_backend = Some(backend);
             self.disk_sectors = disk_size >> SECTOR_SHIFT;
             self.req_align = alignments.0;
             self.buf_align = alignments.1;
@@ -1019,6 +1403,8 @@ impl VirtioDevice for Block {
 
     fn unrealize(&mut self) -> Result<()> {
         MigrationManager::unregister_device_instance(BlockState::descriptor(), &self.blk_cfg.id);
+        unregister_block_backend(&self.blk_cfg.id);
+        unregister_block_drive(&self.blk_cfg.id);
         Ok(())
     }
 
@@ -1054,8 +1440,7 @@ impl VirtioDevice for Block {
 
     /// Read data of config from guest.
     fn read_config(&self, offset: u64, mut data: &mut [u8]) -> Result<()> {
-        // F_DISCARD is not supported for now, so related config does not exist.
-        let config_len = offset_of!(VirtioBlkConfig, max_discard_sectors) as u64;
+        let config_len = self.get_blk_config_size();
         let read_end = offset as usize + data.len();
         if offset
             .checked_add(data.len() as u64)
@@ -1073,8 +1458,7 @@ impl VirtioDevice for Block {
 
     /// Write data to config from guest.
     fn write_config(&mut self, offset: u64, data: &[u8]) -> Result<()> {
-        // F_DISCARD is not supported for now, so related config does not exist.
-        let config_len = offset_of!(VirtioBlkConfig, max_discard_sectors) as u64;
+        let config_len = self.get_blk_config_size();
         if offset
             .checked_add(data.len() as u64)
             .filter(|&end| end <= config_len)
@@ -1105,6 +1489,7 @@ impl VirtioDevice for Block {
             }
             let (sender, receiver) = channel();
             let update_evt = Arc::new(EventFd::new(libc::EFD_NONBLOCK)?);
+            let retry_evt = Arc::new(EventFd::new(libc::EFD_NONBLOCK)?);
             let aio = Box::new(Aio::new(
                 Arc::new(BlockIoHandler::complete_func),
                 self.blk_cfg.aio,
@@ -1114,10 +1499,13 @@ impl VirtioDevice for Block {
                 queue_evt,
                 mem_space: mem_space.clone(),
                 disk_image: self.disk_image.clone(),
+                block_backend: self.block_backend.clone(),
                 req_align: self.req_align,
                 buf_align: self.buf_align,
                 disk_sectors: self.disk_sectors,
                 direct: self.blk_cfg.direct,
+                discard: self.blk_cfg.discard,
+                write_zeroes: self.blk_cfg.write_zeroes,
                 serial_num: self.blk_cfg.serial_num.clone(),
                 aio,
                 driver_features: self.state.driver_features,
@@ -1130,6 +1518,15 @@ impl VirtioDevice for Block {
                     Some(iops) => Some(LeakBucket::new(iops)?),
                     None => None,
                 },
+                stats: self.stats.clone(),
+                error_ctx: Rc::new(BlockErrorCtx {
+                    device_id: self.blk_cfg.id.clone(),
+                    werror: Cell::new(self.blk_cfg.werror),
+                    rerror: Cell::new(self.blk_cfg.rerror),
+                    failed_reqs: RefCell::new(Vec::new()),
+                    failed_num: self.failed_num.clone(),
+                }),
+                retry_evt: retry_evt.clone(),
             };
 
             let notifiers = EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler)));
@@ -1139,8 +1536,20 @@ impl VirtioDevice for Block {
                 &mut self.deactivate_evts,
             )?;
             self.update_evts.push(update_evt);
+            self.retry_evts.push(retry_evt);
             self.senders.push(sender);
         }
+        let retry_evts = self.retry_evts.clone();
+        register_vm_resume_notifier(
+            &self.blk_cfg.id,
+            Arc::new(move || {
+                for retry_evt in retry_evts.iter() {
+                    if let Err(e) = retry_evt.write(1) {
+                        error!("Failed to write retry event of block: {:?}", e);
+                    }
+                }
+            }),
+        );
         self.broken.store(false, Ordering::SeqCst);
 
         Ok(())
@@ -1148,12 +1557,18 @@ impl VirtioDevice for Block {
 
     fn deactivate(&mut self) -> Result<()> {
         unregister_event_helper(self.blk_cfg.iothread.as_ref(), &mut self.deactivate_evts)?;
+        unregister_vm_resume_notifier(&self.blk_cfg.id);
         self.update_evts.clear();
+        self.retry_evts.clear();
+        // The stopped requests are dropped with the IO handlers.
+        self.failed_num.store(0, Ordering::SeqCst);
         self.senders.clear();
         Ok(())
     }
 
     fn update_config(&mut self, dev_config: Option<Arc<dyn ConfigCheck>>) -> Result<()> {
+        unregister_block_backend(&self.blk_cfg.id);
+        unregister_block_drive(&self.blk_cfg.id);
         if let Some(conf) = dev_config {
             self.blk_cfg = conf
                 .as_any()
@@ -1172,12 +1587,17 @@ impl VirtioDevice for Block {
             sender
                 .send((
                     self.disk_image.clone(),
+                    self.block_backend.clone(),
                     self.req_align,
                     self.buf_align,
                     self.disk_sectors,
                     self.blk_cfg.serial_num.clone(),
                     self.blk_cfg.direct,
                     self.blk_cfg.aio,
+                    self.blk_cfg.discard,
+                    self.blk_cfg.write_zeroes,
+                    self.blk_cfg.werror,
+                    self.blk_cfg.rerror,
                 ))
                 .with_context(|| anyhow!(VirtioError::ChannelSend("image fd".to_string())))?;
         }
@@ -1198,6 +1618,16 @@ unsafe impl Sync for Block {}
 
 impl StateTransfer for Block {
     fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        // The requests stopped by the error policy are only kept in the IO handlers,
+        // they need to be retried by resuming the VM before the state is saved.
+        let failed_num = self.failed_num.load(Ordering::SeqCst);
+        if failed_num != 0 {
+            bail!(
+                "Block device {} has {} requests stopped by I/O error, resume the VM first",
+                self.blk_cfg.id,
+                failed_num
+            );
+        }
         let mut state = self.state;
         state.broken = self.broken.load(Ordering::SeqCst);
         Ok(state.as_bytes().to_vec())
@@ -1230,6 +1660,9 @@ mod tests {
     use super::*;
     use address_space::{AddressSpace, GuestAddress, HostMemMapping, Region};
     use machine_manager::config::{IothreadConfig, VmConfig, DEFAULT_VIRTQUEUE_SIZE};
+    use machine_manager::machine::notify_vm_resumed;
+    use std::fs::OpenOptions;
+    use std::io::Read;
     use std::sync::atomic::{AtomicU32, Ordering};
     use std::{thread, time::Duration};
     use vmm_sys_util::tempfile::TempFile;
@@ -1245,6 +1678,7 @@ mod tests {
             Block {
                 blk_cfg: Default::default(),
                 disk_image: None,
+                block_backend: None,
                 req_align: 1,
                 buf_align: 1,
                 disk_sectors: 0,
@@ -1252,9 +1686,12 @@ mod tests {
                 interrupt_cb: None,
                 senders: Vec::new(),
                 update_evts: Vec::new(),
+                retry_evts: Vec::new(),
+                failed_num: Arc::new(AtomicUsize::new(0)),
                 deactivate_evts: Vec::new(),
                 broken: Arc::new(AtomicBool::new(false)),
                 drive_files: Arc::new(Mutex::new(HashMap::new())),
+                stats: Arc::new(Mutex::new(BlockAcctStats::default())),
             }
         }
     }
@@ -1343,6 +1780,52 @@ mod tests {
             .is_err());
     }
 
+    // Test the features and config space of discard and write zeroes. They are offered
+    // according to the `discard` and `read_only` configuration.
+    #[test]
+    fn test_discard_write_zeroes_config() {
+        let discard_offset = offset_of!(VirtioBlkConfig, max_discard_sectors) as u64;
+        let write_zeroes_offset = offset_of!(VirtioBlkConfig, max_write_zeroes_sectors) as u64;
+        let mut buf = [0_u8; 4];
+
+        let mut block = Block::default();
+        block.realize().unwrap();
+        assert!(!virtio_has_feature(
+            block.state.device_features,
+            VIRTIO_BLK_F_DISCARD
+        ));
+        assert!(virtio_has_feature(
+            block.state.device_features,
+            VIRTIO_BLK_F_WRITE_ZEROES
+        ));
+        block.read_config(write_zeroes_offset, &mut buf).unwrap();
+        assert_eq!(LittleEndian::read_u32(&buf), MAX_REQUEST_SECTORS);
+        assert_eq!(block.state.config_space.write_zeroes_may_unmap, 0);
+
+        block.blk_cfg.discard = true;
+        block.realize().unwrap();
+        assert!(virtio_has_feature(
+            block.state.device_features,
+            VIRTIO_BLK_F_DISCARD
+        ));
+        block.read_config(discard_offset, &mut buf).unwrap();
+        assert_eq!(LittleEndian::read_u32(&buf), MAX_REQUEST_SECTORS);
+        assert_eq!(block.state.config_space.write_zeroes_may_unmap, 1);
+
+        // Read-only device supports neither of them.
+        block.blk_cfg.read_only = true;
+        block.realize().unwrap();
+        assert!(!virtio_has_feature(
+            block.state.device_features,
+            VIRTIO_BLK_F_DISCARD
+        ));
+        assert!(!virtio_has_feature(
+            block.state.device_features,
+            VIRTIO_BLK_F_WRITE_ZEROES
+        ));
+        assert!(block.read_config(discard_offset, &mut buf).is_err());
+    }
+
     // Test `get_device_features` and `set_driver_features`. The main contests include: If the
     // device feature is 0, all driver features are not supported; If both the device feature bit
     // and the front-end driver feature bit are supported at the same time,  this driver feature
@@ -1561,4 +2044,138 @@ mod tests {
             }
         }
     }
+
+    // Test that the write request failed by the `stop` error policy is kept until the VM
+    // is resumed, and then it's submitted again.
+    #[test]
+    fn test_io_error_stop_and_retry() {
+        let thread_name = "io1".to_string();
+        let io_conf = IothreadConfig {
+            id: thread_name.clone(),
+        };
+        EventLoop::object_init(&Some(vec![io_conf])).unwrap();
+
+        // Writing to the file opened read only fails.
+        let file = TempFile::new().unwrap();
+        let read_only_file = File::open(file.as_path()).unwrap();
+        let mut block = Block::default();
+        block.blk_cfg.id = "blk_stop".to_string();
+        block.blk_cfg.iothread = Some(thread_name);
+        block.blk_cfg.direct = false;
+        block.blk_cfg.werror = BlockErrorPolicy::Stop;
+        block.disk_image = Some(Arc::new(read_only_file));
+        block.disk_sectors = 8;
+
+        let mem_space = address_space_init();
+        let interrupt_cb = Arc::new(Box::new(
+            move |_int_type: &VirtioInterruptType, _queue: Option<&Queue>, _needs_reset: bool| {
+                Ok(())
+            },
+        ) as VirtioInterrupt);
+        let mut queue_config = QueueConfig::new(DEFAULT_VIRTQUEUE_SIZE);
+        queue_config.desc_table = GuestAddress(0);
+        queue_config.addr_cache.desc_table_host =
+            mem_space.get_host_address(queue_config.desc_table).unwrap();
+        queue_config.avail_ring = GuestAddress(16 * DEFAULT_VIRTQUEUE_SIZE as u64);
+        queue_config.addr_cache.avail_ring_host =
+            mem_space.get_host_address(queue_config.avail_ring).unwrap();
+        queue_config.used_ring = GuestAddress(32 * DEFAULT_VIRTQUEUE_SIZE as u64);
+        queue_config.addr_cache.used_ring_host =
+            mem_space.get_host_address(queue_config.used_ring).unwrap();
+        queue_config.size = DEFAULT_VIRTQUEUE_SIZE;
+        queue_config.ready = true;
+        let queues: Vec<Arc<Mutex<Queue>>> =
+            vec![Arc::new(Mutex::new(Queue::new(queue_config, 1).unwrap()))];
+        let event = Arc::new(EventFd::new(libc::EFD_NONBLOCK).unwrap());
+        block
+            .activate(
+                mem_space.clone(),
+                interrupt_cb,
+                &queues,
+                vec![event.clone()],
+            )
+            .unwrap();
+
+        // Write one sector: header, data and status descriptors.
+        let descs = [
+            (0x8000, 16, VIRTQ_DESC_F_NEXT),
+            (0x9000, 512, VIRTQ_DESC_F_NEXT),
+            (0xa000, 1, VIRTQ_DESC_F_WRITE),
+        ];
+        for (index, (addr, len, flags)) in descs.iter().enumerate() {
+            let desc = SplitVringDesc {
+                addr: GuestAddress(*addr),
+                len: *len,
+                flags: *flags,
+                next: index as u16 + 1,
+            };
+            mem_space
+                .write_object::<SplitVringDesc>(&desc, GuestAddress(16 * index as u64))
+                .unwrap();
+        }
+        let req_head = RequestOutHeader {
+            request_type: VIRTIO_BLK_T_OUT,
+            io_prio: 0,
+            sector: 0,
+        };
+        mem_space
+            .write_object::<RequestOutHeader>(&req_head, GuestAddress(0x8000))
+            .unwrap();
+        mem_space
+            .write(&mut [0xa5_u8; 512].as_ref(), GuestAddress(0x9000), 512)
+            .unwrap();
+        mem_space
+            .write_object::<u8>(&0xff, GuestAddress(0xa000))
+            .unwrap();
+        mem_space
+            .write_object::<u16>(&0, GuestAddress(queue_config.avail_ring.0 + 4))
+            .unwrap();
+        mem_space
+            .write_object::<u16>(&1, GuestAddress(queue_config.avail_ring.0 + 2))
+            .unwrap();
+        event.write(1).unwrap();
+
+        // The failed request is stopped instead of being completed.
+        let mut wait = 10;
+        while block.failed_num.load(Ordering::SeqCst) == 0 {
+            thread::sleep(Duration::from_millis(200));
+            wait -= 1;
+            assert_ne!(wait, 0);
+        }
+        let used_idx = |mem_space: &Arc<AddressSpace>| {
+            mem_space
+                .read_object::<u16>(GuestAddress(queue_config.used_ring.0 + 2))
+                .unwrap()
+        };
+        assert_eq!(used_idx(&mem_space), 0);
+        assert!(block.get_state_vec().is_err());
+
+        // Make the file writable behind the same fd, then resume the VM.
+        let writable_file = OpenOptions::new().write(true).open(file.as_path()).unwrap();
+        let fd = block.disk_image.as_ref().unwrap().as_raw_fd();
+        // SAFETY: both fds are valid and owned by the test.
+        assert_eq!(unsafe { libc::dup2(writable_file.as_raw_fd(), fd) }, fd);
+        notify_vm_resumed();
+
+        let mut wait = 10;
+        while used_idx(&mem_space) != 1 {
+            thread::sleep(Duration::from_millis(200));
+            wait -= 1;
+            assert_ne!(wait, 0);
+        }
+        assert_eq!(
+            mem_space.read_object::<u8>(GuestAddress(0xa000)).unwrap(),
+            VIRTIO_BLK_S_OK
+        );
+        assert_eq!(block.failed_num.load(Ordering::SeqCst), 0);
+        assert!(block.get_state_vec().is_ok());
+        let mut data = Vec::new();
+        File::open(file.as_path())
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, vec![0xa5_u8; 512]);
+
+        block.deactivate().unwrap();
+    }
 }
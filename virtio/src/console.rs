@@ -17,16 +17,17 @@ use std::sync::{Arc, Mutex};
 use std::{cmp, usize};
 
 use super::{
-    Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType, VirtioTrace, VIRTIO_CONSOLE_F_SIZE,
-    VIRTIO_F_VERSION_1, VIRTIO_TYPE_CONSOLE,
+    iov_to_buf, virtio_has_feature, ElemIovec, Queue, VirtioDevice, VirtioInterrupt,
+    VirtioInterruptType, VirtioTrace, VIRTIO_CONSOLE_F_MULTIPORT, VIRTIO_CONSOLE_F_SIZE,
+    VIRTIO_F_RING_PACKED, VIRTIO_F_VERSION_1, VIRTIO_TYPE_CONSOLE,
 };
 use crate::VirtioError;
 use address_space::AddressSpace;
 use anyhow::{anyhow, bail, Context, Result};
 use devices::legacy::{Chardev, InputReceiver};
-use log::{debug, error};
+use log::{debug, error, warn};
 use machine_manager::{
-    config::{VirtioConsole, DEFAULT_VIRTQUEUE_SIZE},
+    config::{VirtioSerialInfo, VirtioSerialPort, DEFAULT_VIRTQUEUE_SIZE},
     event_loop::EventLoop,
     event_loop::{register_event_helper, unregister_event_helper},
 };
@@ -34,17 +35,37 @@ use migration::{DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager, Sta
 use migration_derive::{ByteCode, Desc};
 use util::byte_code::ByteCode;
 use util::loop_context::{
-    read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+    gen_delete_notifiers, read_fd, EventNotifier, EventNotifierHelper, NotifierCallback,
+    NotifierOperation,
 };
 use util::num_ops::read_u32;
 use vmm_sys_util::epoll::EventSet;
 use vmm_sys_util::eventfd::EventFd;
 
-/// Number of virtqueues.
+/// Number of virtqueues of port 0, the only port when multiport is not negotiated.
 const QUEUE_NUM_CONSOLE: usize = 2;
 
 const BUFF_SIZE: usize = 4096;
 
+// Events of control messages, refer to Virtio Spec.
+/// The driver is ready for the device.
+const VIRTIO_CONSOLE_DEVICE_READY: u16 = 0;
+/// A new port is added by the device.
+const VIRTIO_CONSOLE_PORT_ADD: u16 = 1;
+/// A port is removed by the device.
+const VIRTIO_CONSOLE_PORT_REMOVE: u16 = 2;
+/// The driver is ready for the port.
+const VIRTIO_CONSOLE_PORT_READY: u16 = 3;
+/// The port is a console port.
+const VIRTIO_CONSOLE_CONSOLE_PORT: u16 = 4;
+/// The port is opened or closed.
+const VIRTIO_CONSOLE_PORT_OPEN: u16 = 6;
+/// The name of the port follows the control message.
+const VIRTIO_CONSOLE_PORT_NAME: u16 = 7;
+
+/// Ports attached to the virtio-serial device.
+type SerialPorts = Arc<Mutex<Vec<Arc<Mutex<SerialPort>>>>>;
+
 #[derive(Copy, Clone, Debug, Default)]
 #[repr(C)]
 struct VirtioConsoleConfig {
@@ -58,36 +79,158 @@ impl ByteCode for VirtioConsoleConfig {}
 
 impl VirtioConsoleConfig {
     /// Create configuration of virtio-console devices.
-    pub fn new() -> Self {
+    pub fn new(max_nr_ports: u32) -> Self {
         VirtioConsoleConfig {
             cols: 0_u16,
             rows: 0_u16,
-            max_nr_ports: 1_u32,
+            max_nr_ports,
             emerg_wr: 0_u32,
         }
     }
 }
 
-struct ConsoleHandler {
+/// Control message exchanged on the control queues.
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+struct VirtioConsoleControl {
+    /// Port number.
+    id: u32,
+    /// The kind of control event.
+    event: u16,
+    /// Extra information for the event.
+    value: u16,
+}
+
+impl ByteCode for VirtioConsoleControl {}
+
+/// Get the queue index of receiveq and transmitq of the port.
+fn port_queue_index(nr: u32) -> (usize, usize) {
+    // Queue 2 and 3 are control queues, which are between the queues of port 0 and port 1.
+    let rx = if nr == 0 { 0 } else { (nr as usize + 1) * 2 };
+    (rx, rx + 1)
+}
+
+/// Write the buffer to the iovec, return the number of written bytes.
+fn iov_from_buf(mem_space: &AddressSpace, iovec: &[ElemIovec], buf: &[u8]) -> Result<usize> {
+    let mut written = 0_usize;
+    for iov in iovec {
+        if written >= buf.len() {
+            break;
+        }
+        let end = cmp::min(written + iov.len as usize, buf.len());
+        let mut slice = &buf[written..end];
+        mem_space
+            .write(&mut slice, iov.addr, (end - written) as u64)
+            .with_context(|| format!("Failed to write buffer to addr {:X}", iov.addr.0))?;
+        written = end;
+    }
+    Ok(written)
+}
+
+/// Virtio serial port, created by virtconsole or virtserialport.
+pub struct SerialPort {
+    /// Id of the port.
+    id: String,
+    /// Name of the port, which is exposed to the guest.
+    name: Option<String>,
+    /// Port number.
+    nr: u32,
+    /// Whether the port is a console port.
+    is_console: bool,
+    /// Character device for redirection.
+    chardev: Arc<Mutex<Chardev>>,
+    /// Whether the port is opened by the guest.
+    guest_connected: bool,
+}
+
+impl SerialPort {
+    fn new(port_cfg: VirtioSerialPort, nr: u32) -> Self {
+        SerialPort {
+            id: port_cfg.id,
+            name: port_cfg.name,
+            nr,
+            is_console: port_cfg.is_console,
+            chardev: Arc::new(Mutex::new(Chardev::new(port_cfg.chardev))),
+            guest_connected: false,
+        }
+    }
+
+    fn realize(&mut self) -> Result<()> {
+        self.chardev
+            .lock()
+            .unwrap()
+            .realize()
+            .with_context(|| "Failed to realize chardev")?;
+        self.chardev.lock().unwrap().deactivated = true;
+        EventLoop::update_event(
+            EventNotifierHelper::internal_notifiers(self.chardev.clone()),
+            None,
+        )?;
+        Ok(())
+    }
+
+    fn unrealize(&mut self) -> Result<()> {
+        let mut locked_chardev = self.chardev.lock().unwrap();
+        locked_chardev.deactivated = true;
+        let mut fds = Vec::new();
+        if let Some(stream_fd) = locked_chardev.stream_fd {
+            fds.push(stream_fd);
+        } else if let Some(input) = &locked_chardev.input {
+            fds.push(input.lock().unwrap().as_raw_fd());
+        }
+        if let Some(listener) = &locked_chardev.listener {
+            fds.push(listener.as_raw_fd());
+        }
+        drop(locked_chardev);
+        EventLoop::update_event(gen_delete_notifiers(&fds), None)?;
+        Ok(())
+    }
+
+    /// Bind the chardev of the port to the handler of the port queues.
+    fn activate(&mut self, handler: &Arc<Mutex<SerialPortHandler>>) {
+        let mut locked_chardev = self.chardev.lock().unwrap();
+        locked_chardev.set_input_callback(handler);
+        locked_chardev.deactivated = false;
+    }
+
+    fn deactivate(&mut self) {
+        self.chardev.lock().unwrap().deactivated = true;
+        self.guest_connected = false;
+    }
+}
+
+/// Handler of the receiveq and transmitq of one port.
+struct SerialPortHandler {
     input_queue: Arc<Mutex<Queue>>,
     output_queue: Arc<Mutex<Queue>>,
     output_queue_evt: Arc<EventFd>,
     mem_space: Arc<AddressSpace>,
     interrupt_cb: Arc<VirtioInterrupt>,
     driver_features: u64,
-    chardev: Arc<Mutex<Chardev>>,
+    /// The port which owns the queues, None if no port is plugged with the port number.
+    port: Option<Arc<Mutex<SerialPort>>>,
 }
 
-impl InputReceiver for ConsoleHandler {
-    #[allow(clippy::useless_asref)]
+impl InputReceiver for SerialPortHandler {
     fn input_handle(&mut self, buffer: &[u8]) {
-        let mut queue_lock = self.input_queue.lock().unwrap();
-
         let count = buffer.len();
         if count == 0 {
             return;
         }
+        if let Some(port) = &self.port {
+            // Without multiport, there is no way to know whether the console is opened.
+            if virtio_has_feature(self.driver_features, VIRTIO_CONSOLE_F_MULTIPORT as u32)
+                && !port.lock().unwrap().guest_connected
+            {
+                debug!("Port is not opened by guest, drop the input data");
+                return;
+            }
+        } else {
+            return;
+        }
 
+        let mut queue_lock = self.input_queue.lock().unwrap();
+        let mut write_count = 0_usize;
         while let Ok(elem) = queue_lock
             .vring
             .pop_avail(&self.mem_space, self.driver_features)
@@ -95,45 +238,27 @@ impl InputReceiver for ConsoleHandler {
             if elem.desc_num == 0 {
                 break;
             }
-            let mut write_count = 0_usize;
-            for elem_iov in elem.in_iovec.iter() {
-                let allow_write_count = cmp::min(write_count + elem_iov.len as usize, count);
-                let source_slice = &buffer[write_count..allow_write_count];
-
-                let write_result = self.mem_space.write(
-                    &mut source_slice.as_ref(),
-                    elem_iov.addr,
-                    source_slice.len() as u64,
-                );
-                match write_result {
-                    Ok(_) => {
-                        write_count = allow_write_count;
-                    }
-                    Err(ref e) => {
-                        error!(
-                            "Failed to write slice for input console: addr {:X} len {} {:?}",
-                            elem_iov.addr.0,
-                            source_slice.len(),
-                            e
-                        );
-                        break;
-                    }
+            let len = match iov_from_buf(&self.mem_space, &elem.in_iovec, &buffer[write_count..]) {
+                Ok(len) => len,
+                Err(ref e) => {
+                    error!("Failed to write slice for input console: {:?}", e);
+                    0
                 }
-            }
+            };
 
-            if let Err(ref e) =
-                queue_lock
-                    .vring
-                    .add_used(&self.mem_space, elem.index, write_count as u32)
+            if let Err(ref e) = queue_lock
+                .vring
+                .add_used(&self.mem_space, elem.index, len as u32)
             {
                 error!(
                     "Failed to add used ring for input console, index: {} len: {} {:?}",
-                    elem.index, write_count, e
+                    elem.index, len, e
                 );
                 break;
             }
 
-            if write_count >= count {
+            write_count += len;
+            if write_count >= count || len == 0 {
                 break;
             }
         }
@@ -154,11 +279,11 @@ impl InputReceiver for ConsoleHandler {
     }
 }
 
-impl ConsoleHandler {
+impl SerialPortHandler {
     fn output_handle(&mut self) {
         self.trace_request("Console".to_string(), "to IO".to_string());
         let mut queue_lock = self.output_queue.lock().unwrap();
-        let mut buffer = [0_u8; 4096];
+        let mut buffer = [0_u8; BUFF_SIZE];
 
         while let Ok(elem) = queue_lock
             .vring
@@ -167,41 +292,28 @@ impl ConsoleHandler {
             if elem.desc_num == 0 {
                 break;
             }
-            let mut read_count = 0_usize;
-            for elem_iov in elem.out_iovec.iter() {
-                let allow_read_count = cmp::min(read_count + elem_iov.len as usize, buffer.len());
-                let mut slice = &mut buffer[read_count..allow_read_count];
-
-                let read_result = self.mem_space.read(
-                    &mut slice,
-                    elem_iov.addr,
-                    (allow_read_count - read_count) as u64,
-                );
-                match read_result {
-                    Ok(_) => {
-                        read_count = allow_read_count;
+            let read_count = match iov_to_buf(&self.mem_space, &elem.out_iovec, &mut buffer) {
+                Ok(len) => len,
+                Err(ref e) => {
+                    error!("Failed to read buffer for output console: {:?}", e);
+                    0
+                }
+            };
+
+            if let Some(port) = &self.port {
+                let chardev = port.lock().unwrap().chardev.clone();
+                let output = chardev.lock().unwrap().output.clone();
+                if let Some(output) = output {
+                    let mut locked_output = output.lock().unwrap();
+                    if let Err(e) = locked_output.write_all(&buffer[..read_count]) {
+                        error!("Failed to write to console output: {:?}", e);
                     }
-                    Err(ref e) => {
-                        error!(
-                            "Failed to read buffer for output console: addr: {:X}, len: {} {:?}",
-                            elem_iov.addr.0,
-                            allow_read_count - read_count,
-                            e
-                        );
-                        break;
+                    if let Err(e) = locked_output.flush() {
+                        error!("Failed to flush console output: {:?}", e);
                     }
-                };
-            }
-            if let Some(output) = &mut self.chardev.lock().unwrap().output {
-                let mut locked_output = output.lock().unwrap();
-                if let Err(e) = locked_output.write_all(&buffer[..read_count]) {
-                    error!("Failed to write to console output: {:?}", e);
-                }
-                if let Err(e) = locked_output.flush() {
-                    error!("Failed to flush console output: {:?}", e);
+                } else {
+                    debug!("Failed to get output fd");
                 }
-            } else {
-                debug!("Failed to get output fd");
             }
 
             if let Err(ref e) = queue_lock.vring.add_used(&self.mem_space, elem.index, 0) {
@@ -212,14 +324,24 @@ impl ConsoleHandler {
                 break;
             }
         }
+
+        if let Err(ref e) =
+            (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue_lock), false)
+        {
+            error!(
+                "Failed to trigger interrupt for console, int-type {:?} {:?} ",
+                VirtioInterruptType::Vring,
+                e
+            )
+        }
     }
 }
 
-impl EventNotifierHelper for ConsoleHandler {
-    fn internal_notifiers(console_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+impl EventNotifierHelper for SerialPortHandler {
+    fn internal_notifiers(port_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
         let mut notifiers = Vec::new();
 
-        let cloned_cls = console_handler.clone();
+        let cloned_cls = port_handler.clone();
         let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
             read_fd(fd);
             cloned_cls.lock().unwrap().output_handle();
@@ -227,7 +349,200 @@ impl EventNotifierHelper for ConsoleHandler {
         });
         notifiers.push(EventNotifier::new(
             NotifierOperation::AddShared,
-            console_handler.lock().unwrap().output_queue_evt.as_raw_fd(),
+            port_handler.lock().unwrap().output_queue_evt.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        ));
+
+        notifiers
+    }
+}
+
+/// Handler of the control receiveq and control transmitq.
+struct SerialControlHandler {
+    /// Control receiveq, which passes the control messages from device to driver.
+    input_queue: Arc<Mutex<Queue>>,
+    /// Control transmitq, which passes the control messages from driver to device.
+    output_queue: Arc<Mutex<Queue>>,
+    output_queue_evt: Arc<EventFd>,
+    mem_space: Arc<AddressSpace>,
+    interrupt_cb: Arc<VirtioInterrupt>,
+    driver_features: u64,
+    ports: SerialPorts,
+}
+
+impl SerialControlHandler {
+    fn output_control(&mut self) {
+        let mut queue_lock = self.output_queue.lock().unwrap();
+        let mut msgs = Vec::new();
+
+        while let Ok(elem) = queue_lock
+            .vring
+            .pop_avail(&self.mem_space, self.driver_features)
+        {
+            if elem.desc_num == 0 {
+                break;
+            }
+            let mut ctrl = VirtioConsoleControl::default();
+            match iov_to_buf(&self.mem_space, &elem.out_iovec, ctrl.as_mut_bytes()) {
+                Ok(len) if len == std::mem::size_of::<VirtioConsoleControl>() => msgs.push(ctrl),
+                Ok(len) => error!("Invalid length {} of virtio serial control message", len),
+                Err(ref e) => error!("Failed to read virtio serial control message: {:?}", e),
+            }
+
+            if let Err(ref e) = queue_lock.vring.add_used(&self.mem_space, elem.index, 0) {
+                error!(
+                    "Failed to add used ring for control queue, index: {} {:?}",
+                    elem.index, e
+                );
+                break;
+            }
+        }
+
+        if let Err(ref e) =
+            (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue_lock), false)
+        {
+            error!(
+                "Failed to trigger interrupt for console, int-type {:?} {:?} ",
+                VirtioInterruptType::Vring,
+                e
+            )
+        }
+        drop(queue_lock);
+
+        for ctrl in msgs.iter() {
+            self.handle_control_message(ctrl);
+        }
+    }
+
+    fn handle_control_message(&mut self, ctrl: &VirtioConsoleControl) {
+        if ctrl.event == VIRTIO_CONSOLE_DEVICE_READY {
+            if ctrl.value == 0 {
+                error!("Guest failed to initialize the virtio serial device");
+                return;
+            }
+            let ports = self.ports.clone();
+            for port in ports.lock().unwrap().iter() {
+                let nr = port.lock().unwrap().nr;
+                self.send_control_event(nr, VIRTIO_CONSOLE_PORT_ADD, 1, &[]);
+            }
+            return;
+        }
+
+        let port = if let Some(port) = self
+            .ports
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|port| port.lock().unwrap().nr == ctrl.id)
+        {
+            port.clone()
+        } else {
+            error!("Invalid port number {} of control message", ctrl.id);
+            return;
+        };
+
+        match ctrl.event {
+            VIRTIO_CONSOLE_PORT_READY => {
+                if ctrl.value == 0 {
+                    error!(
+                        "Guest failed to initialize the virtio serial port {}",
+                        ctrl.id
+                    );
+                    return;
+                }
+                let locked_port = port.lock().unwrap();
+                if locked_port.is_console {
+                    self.send_control_event(ctrl.id, VIRTIO_CONSOLE_CONSOLE_PORT, 1, &[]);
+                }
+                if let Some(name) = &locked_port.name {
+                    let mut extra = name.as_bytes().to_vec();
+                    extra.push(0);
+                    self.send_control_event(ctrl.id, VIRTIO_CONSOLE_PORT_NAME, 1, &extra);
+                }
+                // The chardev backend is always considered as connected.
+                self.send_control_event(ctrl.id, VIRTIO_CONSOLE_PORT_OPEN, 1, &[]);
+            }
+            VIRTIO_CONSOLE_PORT_OPEN => {
+                port.lock().unwrap().guest_connected = ctrl.value != 0;
+            }
+            _ => {
+                warn!(
+                    "Unsupported virtio serial control event {} for port {}",
+                    ctrl.event, ctrl.id
+                );
+            }
+        }
+    }
+
+    fn send_control_event(&mut self, id: u32, event: u16, value: u16, extra: &[u8]) {
+        let ctrl = VirtioConsoleControl { id, event, value };
+        let mut msg = ctrl.as_bytes().to_vec();
+        msg.extend_from_slice(extra);
+
+        let mut queue_lock = self.input_queue.lock().unwrap();
+        let elem = match queue_lock
+            .vring
+            .pop_avail(&self.mem_space, self.driver_features)
+        {
+            Ok(elem) if elem.desc_num != 0 => elem,
+            Ok(_) => {
+                error!(
+                    "No buffer in control queue for event {} of port {}",
+                    event, id
+                );
+                return;
+            }
+            Err(ref e) => {
+                error!("Failed to pop avail ring of control queue: {:?}", e);
+                return;
+            }
+        };
+
+        let len = match iov_from_buf(&self.mem_space, &elem.in_iovec, &msg) {
+            Ok(len) => len,
+            Err(ref e) => {
+                error!("Failed to write virtio serial control message: {:?}", e);
+                0
+            }
+        };
+        if let Err(ref e) = queue_lock
+            .vring
+            .add_used(&self.mem_space, elem.index, len as u32)
+        {
+            error!(
+                "Failed to add used ring for control queue, index: {} len: {} {:?}",
+                elem.index, len, e
+            );
+            return;
+        }
+
+        if let Err(ref e) =
+            (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue_lock), false)
+        {
+            error!(
+                "Failed to trigger interrupt for console, int-type {:?} {:?} ",
+                VirtioInterruptType::Vring,
+                e
+            )
+        }
+    }
+}
+
+impl EventNotifierHelper for SerialControlHandler {
+    fn internal_notifiers(ctrl_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let mut notifiers = Vec::new();
+
+        let cloned_cls = ctrl_handler.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            cloned_cls.lock().unwrap().output_control();
+            None
+        });
+        notifiers.push(EventNotifier::new(
+            NotifierOperation::AddShared,
+            ctrl_handler.lock().unwrap().output_queue_evt.as_raw_fd(),
             None,
             EventSet::IN,
             vec![handler],
@@ -250,49 +565,167 @@ pub struct VirtioConsoleState {
     config_space: VirtioConsoleConfig,
 }
 
-/// Virtio console device structure.
+/// Virtio serial device structure, the ports are attached to it.
 pub struct Console {
+    /// Id of the device.
+    id: String,
     /// Status of console device.
     state: VirtioConsoleState,
     /// EventFd for device deactivate.
     deactivate_evts: Vec<RawFd>,
-    /// Character device for redirection.
-    chardev: Arc<Mutex<Chardev>>,
+    /// Ports attached to the device.
+    ports: SerialPorts,
+    /// Handlers of the port queues indexed by port number, empty if device is not activated.
+    port_handlers: Vec<Arc<Mutex<SerialPortHandler>>>,
+    /// Handler of the control queues, only exists when multiport is negotiated.
+    ctrl_handler: Option<Arc<Mutex<SerialControlHandler>>>,
 }
 
 impl Console {
-    /// Create a virtio-console device.
+    /// Create a virtio-serial device.
     ///
     /// # Arguments
     ///
-    /// * `console_cfg` - Device configuration set by user.
-    pub fn new(console_cfg: VirtioConsole) -> Self {
+    /// * `serial_cfg` - Device configuration set by user.
+    pub fn new(serial_cfg: &VirtioSerialInfo) -> Self {
         Console {
+            id: serial_cfg.id.clone(),
             state: VirtioConsoleState {
                 device_features: 0_u64,
                 driver_features: 0_u64,
-                config_space: VirtioConsoleConfig::new(),
+                config_space: VirtioConsoleConfig::new(serial_cfg.max_ports),
             },
             deactivate_evts: Vec::new(),
-            chardev: Arc::new(Mutex::new(Chardev::new(console_cfg.chardev))),
+            ports: Arc::new(Mutex::new(Vec::new())),
+            port_handlers: Vec::new(),
+            ctrl_handler: None,
+        }
+    }
+
+    /// Add a virtconsole or virtserialport to the device. If the device is running,
+    /// the port is hot plugged to the guest.
+    ///
+    /// # Arguments
+    ///
+    /// * `port_cfg` - Port configuration set by user.
+    pub fn add_port(&mut self, port_cfg: VirtioSerialPort) -> Result<()> {
+        if self.has_port(&port_cfg.id) {
+            bail!("Serial port {} already exists", port_cfg.id);
+        }
+        let nr = self.get_free_nr(port_cfg.nr, port_cfg.is_console)?;
+        let port = Arc::new(Mutex::new(SerialPort::new(port_cfg, nr)));
+        port.lock().unwrap().realize()?;
+        self.ports.lock().unwrap().push(port.clone());
+
+        if let Some(handler) = self.port_handlers.get(nr as usize) {
+            handler.lock().unwrap().port = Some(port.clone());
+            port.lock().unwrap().activate(handler);
+            if let Some(ctrl_handler) = &self.ctrl_handler {
+                ctrl_handler.lock().unwrap().send_control_event(
+                    nr,
+                    VIRTIO_CONSOLE_PORT_ADD,
+                    1,
+                    &[],
+                );
+            }
         }
+        Ok(())
+    }
+
+    /// Remove the port from the device, and notify the guest if the device is running.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Id of the port.
+    pub fn remove_port(&mut self, id: &str) -> Result<()> {
+        let mut locked_ports = self.ports.lock().unwrap();
+        let index = locked_ports
+            .iter()
+            .position(|port| port.lock().unwrap().id == id)
+            .with_context(|| format!("Serial port {} not found", id))?;
+        let port = locked_ports.remove(index);
+        drop(locked_ports);
+
+        let nr = port.lock().unwrap().nr;
+        if let Some(handler) = self.port_handlers.get(nr as usize) {
+            handler.lock().unwrap().port = None;
+            if let Some(ctrl_handler) = &self.ctrl_handler {
+                ctrl_handler.lock().unwrap().send_control_event(
+                    nr,
+                    VIRTIO_CONSOLE_PORT_REMOVE,
+                    1,
+                    &[],
+                );
+            }
+        }
+        let mut locked_port = port.lock().unwrap();
+        locked_port.deactivate();
+        locked_port.unrealize()
+    }
+
+    /// Check whether the port with the id is attached to the device.
+    pub fn has_port(&self, id: &str) -> bool {
+        self.ports
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|port| port.lock().unwrap().id == id)
+    }
+
+    /// Check whether any port is attached to the device.
+    pub fn has_ports(&self) -> bool {
+        !self.ports.lock().unwrap().is_empty()
+    }
+
+    fn get_free_nr(&self, nr: Option<u32>, is_console: bool) -> Result<u32> {
+        let max_nr_ports = self.state.config_space.max_nr_ports;
+        let locked_ports = self.ports.lock().unwrap();
+        let in_use = |nr: u32| locked_ports.iter().any(|p| p.lock().unwrap().nr == nr);
+
+        if let Some(nr) = nr {
+            if nr >= max_nr_ports {
+                bail!(
+                    "Port number {} exceeds the max ports {} of virtio serial",
+                    nr,
+                    max_nr_ports
+                );
+            }
+            if in_use(nr) {
+                bail!("Port number {} of virtio serial is already in use", nr);
+            }
+            return Ok(nr);
+        }
+
+        // Port 0 is reserved for console port to keep the compatibility with old guests.
+        let start = if is_console && !in_use(0) { 0 } else { 1 };
+        (start..max_nr_ports)
+            .find(|nr| !in_use(*nr))
+            .with_context(|| "No free port number for virtio serial port")
+    }
+
+    fn find_port(&self, nr: u32) -> Option<Arc<Mutex<SerialPort>>> {
+        self.ports
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|port| port.lock().unwrap().nr == nr)
+            .cloned()
     }
 }
 
 impl VirtioDevice for Console {
     /// Realize virtio console device.
     fn realize(&mut self) -> Result<()> {
-        self.state.device_features = 1_u64 << VIRTIO_F_VERSION_1 | 1_u64 << VIRTIO_CONSOLE_F_SIZE;
-        self.chardev
-            .lock()
-            .unwrap()
-            .realize()
-            .with_context(|| "Failed to realize chardev")?;
-        self.chardev.lock().unwrap().deactivated = true;
-        EventLoop::update_event(
-            EventNotifierHelper::internal_notifiers(self.chardev.clone()),
-            None,
-        )?;
+        self.state.device_features = 1_u64 << VIRTIO_F_VERSION_1
+            | 1_u64 << VIRTIO_CONSOLE_F_SIZE
+            | 1_u64 << VIRTIO_CONSOLE_F_MULTIPORT
+            | 1_u64 << VIRTIO_F_RING_PACKED;
+        Ok(())
+    }
+
+    /// Unrealize virtio console device.
+    fn unrealize(&mut self) -> Result<()> {
+        MigrationManager::unregister_device_instance(VirtioConsoleState::descriptor(), &self.id);
         Ok(())
     }
 
@@ -303,7 +736,8 @@ impl VirtioDevice for Console {
 
     /// Get the count of virtio device queues.
     fn queue_num(&self) -> usize {
-        QUEUE_NUM_CONSOLE
+        // Each port has a pair of queues, and a pair of control queues is used for multiport.
+        (self.state.config_space.max_nr_ports as usize + 1) * QUEUE_NUM_CONSOLE
     }
 
     /// Get the queue size of virtio device.
@@ -353,31 +787,61 @@ impl VirtioDevice for Console {
         mem_space: Arc<AddressSpace>,
         interrupt_cb: Arc<VirtioInterrupt>,
         queues: &[Arc<Mutex<Queue>>],
-        mut queue_evts: Vec<Arc<EventFd>>,
+        queue_evts: Vec<Arc<EventFd>>,
     ) -> Result<()> {
-        queue_evts.remove(0); // input_queue_evt never used
-
-        let handler = ConsoleHandler {
-            input_queue: queues[0].clone(),
-            output_queue: queues[1].clone(),
-            output_queue_evt: queue_evts.remove(0),
-            mem_space,
-            interrupt_cb,
-            driver_features: self.state.driver_features,
-            chardev: self.chardev.clone(),
+        let driver_features = self.state.driver_features;
+        let multiport = virtio_has_feature(driver_features, VIRTIO_CONSOLE_F_MULTIPORT as u32);
+        let nr_ports = if multiport {
+            self.state.config_space.max_nr_ports
+        } else {
+            1
         };
 
-        let dev = Arc::new(Mutex::new(handler));
-        let notifiers = EventNotifierHelper::internal_notifiers(dev.clone());
-        register_event_helper(notifiers, None, &mut self.deactivate_evts)?;
+        for nr in 0..nr_ports {
+            let (rx, tx) = port_queue_index(nr);
+            let port = self.find_port(nr);
+            let handler = Arc::new(Mutex::new(SerialPortHandler {
+                input_queue: queues[rx].clone(),
+                output_queue: queues[tx].clone(),
+                output_queue_evt: queue_evts[tx].clone(),
+                mem_space: mem_space.clone(),
+                interrupt_cb: interrupt_cb.clone(),
+                driver_features,
+                port: port.clone(),
+            }));
+            let notifiers = EventNotifierHelper::internal_notifiers(handler.clone());
+            register_event_helper(notifiers, None, &mut self.deactivate_evts)?;
+
+            if let Some(port) = port {
+                port.lock().unwrap().activate(&handler);
+            }
+            self.port_handlers.push(handler);
+        }
+
+        if multiport {
+            let ctrl_handler = Arc::new(Mutex::new(SerialControlHandler {
+                input_queue: queues[2].clone(),
+                output_queue: queues[3].clone(),
+                output_queue_evt: queue_evts[3].clone(),
+                mem_space,
+                interrupt_cb,
+                driver_features,
+                ports: self.ports.clone(),
+            }));
+            let notifiers = EventNotifierHelper::internal_notifiers(ctrl_handler.clone());
+            register_event_helper(notifiers, None, &mut self.deactivate_evts)?;
+            self.ctrl_handler = Some(ctrl_handler);
+        }
 
-        self.chardev.lock().unwrap().set_input_callback(&dev);
-        self.chardev.lock().unwrap().deactivated = false;
         Ok(())
     }
 
     fn deactivate(&mut self) -> Result<()> {
-        self.chardev.lock().unwrap().deactivated = true;
+        for port in self.ports.lock().unwrap().iter() {
+            port.lock().unwrap().deactivate();
+        }
+        self.port_handlers.clear();
+        self.ctrl_handler = None;
         unregister_event_helper(None, &mut self.deactivate_evts)
     }
 }
@@ -407,7 +871,7 @@ impl StateTransfer for Console {
 
 impl MigrationHook for Console {}
 
-impl VirtioTrace for ConsoleHandler {}
+impl VirtioTrace for SerialPortHandler {}
 
 #[cfg(test)]
 mod tests {
@@ -415,21 +879,160 @@ mod tests {
     pub use super::*;
     use std::mem::size_of;
 
-    use machine_manager::config::{ChardevConfig, ChardevType};
+    use address_space::{GuestAddress, HostMemMapping, Region};
+    use machine_manager::config::{ChardevConfig, ChardevType, DEFAULT_VIRTQUEUE_SIZE};
+
+    const VIRTQ_DESC_F_WRITE: u16 = 0x02;
+    const SYSTEM_SPACE_SIZE: u64 = (1024 * 1024) as u64;
+    const CTRL_BUF_LEN: u32 = 64;
+
+    // build dummy address space of vm
+    fn address_space_init() -> Arc<AddressSpace> {
+        let root = Region::init_container_region(1 << 36);
+        let sys_space = AddressSpace::new(root).unwrap();
+        let host_mmap = Arc::new(
+            HostMemMapping::new(
+                GuestAddress(0),
+                None,
+                SYSTEM_SPACE_SIZE,
+                None,
+                false,
+                false,
+                false,
+            )
+            .unwrap(),
+        );
+        sys_space
+            .root()
+            .add_subregion(
+                Region::init_ram_region(host_mmap.clone()),
+                host_mmap.start_address().raw_value(),
+            )
+            .unwrap();
+        sys_space
+    }
+
+    fn queue_config_init(mem_space: &Arc<AddressSpace>, base: u64) -> QueueConfig {
+        let mut queue_config = QueueConfig::new(DEFAULT_VIRTQUEUE_SIZE);
+        queue_config.desc_table = GuestAddress(base);
+        queue_config.addr_cache.desc_table_host =
+            mem_space.get_host_address(queue_config.desc_table).unwrap();
+        queue_config.avail_ring = GuestAddress(base + 16 * DEFAULT_VIRTQUEUE_SIZE as u64);
+        queue_config.addr_cache.avail_ring_host =
+            mem_space.get_host_address(queue_config.avail_ring).unwrap();
+        queue_config.used_ring = GuestAddress(base + 32 * DEFAULT_VIRTQUEUE_SIZE as u64);
+        queue_config.addr_cache.used_ring_host =
+            mem_space.get_host_address(queue_config.used_ring).unwrap();
+        queue_config.size = DEFAULT_VIRTQUEUE_SIZE;
+        queue_config.ready = true;
+        queue_config
+    }
+
+    // Put the buffer of descriptor `index` to the avail ring, as the guest driver does.
+    fn push_avail(
+        mem_space: &AddressSpace,
+        config: &QueueConfig,
+        index: u16,
+        addr: u64,
+        flags: u16,
+    ) {
+        let desc = SplitVringDesc {
+            addr: GuestAddress(addr),
+            len: CTRL_BUF_LEN,
+            flags,
+            next: 0,
+        };
+        mem_space
+            .write_object(
+                &desc,
+                GuestAddress(
+                    config.desc_table.0 + index as u64 * size_of::<SplitVringDesc>() as u64,
+                ),
+            )
+            .unwrap();
+        mem_space
+            .write_object::<u16>(
+                &index,
+                GuestAddress(config.avail_ring.0 + 4 + index as u64 * 2),
+            )
+            .unwrap();
+        mem_space
+            .write_object::<u16>(&(index + 1), GuestAddress(config.avail_ring.0 + 2))
+            .unwrap();
+    }
+
+    // Send a control message from the guest driver to the device.
+    fn send_ctrl(
+        handler: &mut SerialControlHandler,
+        config: &QueueConfig,
+        index: u16,
+        ctrl: VirtioConsoleControl,
+    ) {
+        let mem_space = handler.mem_space.clone();
+        let addr = 0x80000 + index as u64 * CTRL_BUF_LEN as u64;
+        mem_space.write_object(&ctrl, GuestAddress(addr)).unwrap();
+        push_avail(&mem_space, config, index, addr, 0);
+        handler.output_control();
+    }
+
+    // Receive the control messages sent from the device since the used index `start`.
+    fn recv_ctrl(
+        mem_space: &AddressSpace,
+        config: &QueueConfig,
+        start: u16,
+    ) -> Vec<(VirtioConsoleControl, Vec<u8>)> {
+        let used_idx = mem_space
+            .read_object::<u16>(GuestAddress(config.used_ring.0 + 2))
+            .unwrap();
+        let mut msgs = Vec::new();
+        for i in start..used_idx {
+            let elem = GuestAddress(config.used_ring.0 + 4 + i as u64 * 8);
+            let id = mem_space.read_object::<u32>(elem).unwrap();
+            let len = mem_space
+                .read_object::<u32>(GuestAddress(elem.0 + 4))
+                .unwrap() as usize;
+            let addr = GuestAddress(0x40000 + id as u64 * CTRL_BUF_LEN as u64);
+            let ctrl = mem_space.read_object::<VirtioConsoleControl>(addr).unwrap();
+            let ctrl_len = size_of::<VirtioConsoleControl>();
+            let mut extra = vec![0_u8; len - ctrl_len];
+            mem_space
+                .read(
+                    &mut extra.as_mut_slice(),
+                    GuestAddress(addr.0 + ctrl_len as u64),
+                    (len - ctrl_len) as u64,
+                )
+                .unwrap();
+            msgs.push((ctrl, extra));
+        }
+        msgs
+    }
+
+    fn serial_config(max_ports: u32) -> VirtioSerialInfo {
+        VirtioSerialInfo {
+            id: "serial".to_string(),
+            pci_bdf: None,
+            multifunction: false,
+            max_ports,
+        }
+    }
+
+    fn serial_port(id: &str, nr: u32, is_console: bool) -> Arc<Mutex<SerialPort>> {
+        let port_cfg = VirtioSerialPort {
+            id: id.to_string(),
+            chardev: ChardevConfig {
+                id: format!("chardev_{}", id),
+                backend: ChardevType::Stdio,
+            },
+            nr: Some(nr),
+            name: None,
+            is_console,
+        };
+        Arc::new(Mutex::new(SerialPort::new(port_cfg, nr)))
+    }
 
     #[test]
     fn test_set_driver_features() {
-        let chardev_cfg = ChardevConfig {
-            id: "chardev".to_string(),
-            backend: ChardevType::Stdio,
-        };
-        let mut console = Console::new(VirtioConsole {
-            id: "console".to_string(),
-            chardev: chardev_cfg.clone(),
-        });
-        let mut chardev = Chardev::new(chardev_cfg);
-        chardev.output = Some(Arc::new(Mutex::new(std::io::stdout())));
-        console.chardev = Arc::new(Mutex::new(chardev));
+        let mut console = Console::new(&serial_config(3));
 
         //If the device feature is 0, all driver features are not supported.
         console.state.device_features = 0;
@@ -490,17 +1093,7 @@ mod tests {
 
     #[test]
     fn test_read_config() {
-        let chardev_cfg = ChardevConfig {
-            id: "chardev".to_string(),
-            backend: ChardevType::Stdio,
-        };
-        let mut console = Console::new(VirtioConsole {
-            id: "console".to_string(),
-            chardev: chardev_cfg.clone(),
-        });
-        let mut chardev = Chardev::new(chardev_cfg);
-        chardev.output = Some(Arc::new(Mutex::new(std::io::stdout())));
-        console.chardev = Arc::new(Mutex::new(chardev));
+        let console = Console::new(&serial_config(3));
 
         //The offset of configuration that needs to be read exceeds the maximum
         let offset = size_of::<VirtioConsoleConfig>() as u64;
@@ -510,14 +1103,183 @@ mod tests {
         //Check the configuration that needs to be read
         let offset = 0_u64;
         let mut read_data: Vec<u8> = vec![0; 12];
-        let expect_data: Vec<u8> = vec![0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0];
+        let expect_data: Vec<u8> = vec![0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0];
         assert_eq!(console.read_config(offset, &mut read_data).is_ok(), true);
         assert_eq!(read_data, expect_data);
 
         let offset = 4_u64;
         let mut read_data: Vec<u8> = vec![0; 1];
-        let expect_data: Vec<u8> = vec![1];
+        let expect_data: Vec<u8> = vec![3];
         assert_eq!(console.read_config(offset, &mut read_data).is_ok(), true);
         assert_eq!(read_data, expect_data);
     }
+
+    #[test]
+    fn test_port_number_alloc() {
+        let console = Console::new(&serial_config(4));
+        assert_eq!(console.queue_num(), 10);
+        assert_eq!(port_queue_index(0), (0, 1));
+        assert_eq!(port_queue_index(1), (4, 5));
+        assert_eq!(port_queue_index(3), (8, 9));
+
+        // Port 0 is only allocated to console automatically.
+        assert_eq!(console.get_free_nr(None, true).unwrap(), 0);
+        assert_eq!(console.get_free_nr(None, false).unwrap(), 1);
+        assert!(console.get_free_nr(Some(4), false).is_err());
+
+        console
+            .ports
+            .lock()
+            .unwrap()
+            .push(serial_port("console0", 0, true));
+        console
+            .ports
+            .lock()
+            .unwrap()
+            .push(serial_port("port1", 1, false));
+        assert!(console.has_port("port1"));
+        assert!(!console.has_port("port2"));
+        assert!(console.get_free_nr(Some(1), false).is_err());
+        assert_eq!(console.get_free_nr(None, true).unwrap(), 2);
+        assert_eq!(console.get_free_nr(None, false).unwrap(), 2);
+        assert_eq!(console.get_free_nr(Some(3), false).unwrap(), 3);
+
+        console
+            .ports
+            .lock()
+            .unwrap()
+            .push(serial_port("port2", 2, false));
+        console
+            .ports
+            .lock()
+            .unwrap()
+            .push(serial_port("port3", 3, false));
+        assert!(console.get_free_nr(None, false).is_err());
+        assert!(console.find_port(3).is_some());
+        assert!(console.find_port(4).is_none());
+    }
+
+    #[test]
+    fn test_control_message_round_trip() {
+        let mem_space = address_space_init();
+        let interrupt_cb = Arc::new(Box::new(
+            move |_int_type: &VirtioInterruptType, _queue: Option<&Queue>, _needs_reset: bool| {
+                Ok(())
+            },
+        ) as VirtioInterrupt);
+        let input_config = queue_config_init(&mem_space, 0);
+        let output_config = queue_config_init(&mem_space, 0x20000);
+
+        let ports: SerialPorts = Arc::new(Mutex::new(Vec::new()));
+        let console_port = serial_port("console0", 0, true);
+        let serial_port = serial_port("port1", 1, false);
+        serial_port.lock().unwrap().name = Some("org.test.port1".to_string());
+        ports.lock().unwrap().push(console_port.clone());
+        ports.lock().unwrap().push(serial_port.clone());
+
+        let mut handler = SerialControlHandler {
+            input_queue: Arc::new(Mutex::new(Queue::new(input_config, 1).unwrap())),
+            output_queue: Arc::new(Mutex::new(Queue::new(output_config, 1).unwrap())),
+            output_queue_evt: Arc::new(EventFd::new(libc::EFD_NONBLOCK).unwrap()),
+            mem_space: mem_space.clone(),
+            interrupt_cb,
+            driver_features: 0_u64,
+            ports,
+        };
+        // The guest driver fills the control receiveq with buffers.
+        for i in 0..8 {
+            push_avail(
+                &mem_space,
+                &input_config,
+                i,
+                0x40000 + i as u64 * CTRL_BUF_LEN as u64,
+                VIRTQ_DESC_F_WRITE,
+            );
+        }
+        let ctrl = |id, event, value| VirtioConsoleControl { id, event, value };
+
+        // DEVICE_READY is answered with PORT_ADD of all ports.
+        send_ctrl(
+            &mut handler,
+            &output_config,
+            0,
+            ctrl(0, VIRTIO_CONSOLE_DEVICE_READY, 1),
+        );
+        let msgs = recv_ctrl(&mem_space, &input_config, 0);
+        assert_eq!(msgs.len(), 2);
+        for (i, (msg, extra)) in msgs.iter().enumerate() {
+            assert_eq!(
+                (msg.id, msg.event, msg.value),
+                (i as u32, VIRTIO_CONSOLE_PORT_ADD, 1)
+            );
+            assert!(extra.is_empty());
+        }
+
+        // PORT_READY of a named port is answered with PORT_NAME and PORT_OPEN.
+        send_ctrl(
+            &mut handler,
+            &output_config,
+            1,
+            ctrl(1, VIRTIO_CONSOLE_PORT_READY, 1),
+        );
+        let msgs = recv_ctrl(&mem_space, &input_config, 2);
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(
+            (msgs[0].0.id, msgs[0].0.event, msgs[0].0.value),
+            (1, VIRTIO_CONSOLE_PORT_NAME, 1)
+        );
+        assert_eq!(msgs[0].1, b"org.test.port1\0".to_vec());
+        assert_eq!(
+            (msgs[1].0.id, msgs[1].0.event, msgs[1].0.value),
+            (1, VIRTIO_CONSOLE_PORT_OPEN, 1)
+        );
+
+        // PORT_READY of a console port is answered with CONSOLE_PORT and PORT_OPEN.
+        send_ctrl(
+            &mut handler,
+            &output_config,
+            2,
+            ctrl(0, VIRTIO_CONSOLE_PORT_READY, 1),
+        );
+        let msgs = recv_ctrl(&mem_space, &input_config, 4);
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(
+            (msgs[0].0.id, msgs[0].0.event, msgs[0].0.value),
+            (0, VIRTIO_CONSOLE_CONSOLE_PORT, 1)
+        );
+        assert_eq!(
+            (msgs[1].0.id, msgs[1].0.event, msgs[1].0.value),
+            (0, VIRTIO_CONSOLE_PORT_OPEN, 1)
+        );
+
+        // PORT_OPEN from the guest opens and closes the port.
+        send_ctrl(
+            &mut handler,
+            &output_config,
+            3,
+            ctrl(1, VIRTIO_CONSOLE_PORT_OPEN, 1),
+        );
+        assert!(serial_port.lock().unwrap().guest_connected);
+        assert!(!console_port.lock().unwrap().guest_connected);
+        send_ctrl(
+            &mut handler,
+            &output_config,
+            4,
+            ctrl(1, VIRTIO_CONSOLE_PORT_OPEN, 0),
+        );
+        assert!(!serial_port.lock().unwrap().guest_connected);
+
+        // Control messages of unknown ports are ignored.
+        send_ctrl(
+            &mut handler,
+            &output_config,
+            5,
+            ctrl(5, VIRTIO_CONSOLE_PORT_READY, 1),
+        );
+        assert!(recv_ctrl(&mem_space, &input_config, 6).is_empty());
+        let used_idx = mem_space
+            .read_object::<u16>(GuestAddress(output_config.used_ring.0 + 2))
+            .unwrap();
+        assert_eq!(used_idx, 6);
+    }
 }
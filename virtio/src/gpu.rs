@@ -12,23 +12,23 @@
 
 use super::{
     Element, Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType, VIRTIO_F_RING_EVENT_IDX,
-    VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_VERSION_1, VIRTIO_GPU_CMD_GET_DISPLAY_INFO,
-    VIRTIO_GPU_CMD_GET_EDID, VIRTIO_GPU_CMD_MOVE_CURSOR, VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING,
-    VIRTIO_GPU_CMD_RESOURCE_CREATE_2D, VIRTIO_GPU_CMD_RESOURCE_DETACH_BACKING,
-    VIRTIO_GPU_CMD_RESOURCE_FLUSH, VIRTIO_GPU_CMD_RESOURCE_UNREF, VIRTIO_GPU_CMD_SET_SCANOUT,
-    VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D, VIRTIO_GPU_CMD_UPDATE_CURSOR, VIRTIO_GPU_FLAG_FENCE,
-    VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER, VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID,
-    VIRTIO_GPU_RESP_ERR_INVALID_SCANOUT_ID, VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY,
-    VIRTIO_GPU_RESP_ERR_UNSPEC, VIRTIO_GPU_RESP_OK_DISPLAY_INFO, VIRTIO_GPU_RESP_OK_EDID,
-    VIRTIO_GPU_RESP_OK_NODATA, VIRTIO_TYPE_GPU,
+    VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_RING_PACKED, VIRTIO_F_VERSION_1,
+    VIRTIO_GPU_CMD_GET_DISPLAY_INFO, VIRTIO_GPU_CMD_GET_EDID, VIRTIO_GPU_CMD_MOVE_CURSOR,
+    VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING, VIRTIO_GPU_CMD_RESOURCE_CREATE_2D,
+    VIRTIO_GPU_CMD_RESOURCE_DETACH_BACKING, VIRTIO_GPU_CMD_RESOURCE_FLUSH,
+    VIRTIO_GPU_CMD_RESOURCE_UNREF, VIRTIO_GPU_CMD_SET_SCANOUT, VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D,
+    VIRTIO_GPU_CMD_UPDATE_CURSOR, VIRTIO_GPU_FLAG_FENCE, VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER,
+    VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID, VIRTIO_GPU_RESP_ERR_INVALID_SCANOUT_ID,
+    VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY, VIRTIO_GPU_RESP_ERR_UNSPEC, VIRTIO_GPU_RESP_OK_DISPLAY_INFO,
+    VIRTIO_GPU_RESP_OK_EDID, VIRTIO_GPU_RESP_OK_NODATA, VIRTIO_TYPE_GPU,
 };
 use crate::{iov_discard_front, iov_to_buf, VirtioError, VIRTIO_GPU_F_EDID};
 use address_space::{AddressSpace, GuestAddress};
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use log::{error, warn};
 use machine_manager::config::{GpuDevConfig, DEFAULT_VIRTQUEUE_SIZE, VIRTIO_GPU_MAX_SCANOUTS};
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
-use migration::{DeviceStateDesc, FieldDesc, MigrationManager};
+use migration::{DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager, StateTransfer};
 use migration_derive::{ByteCode, Desc};
 use std::io::Write;
 use std::mem::size_of;
@@ -70,6 +70,8 @@ struct GpuResource {
     scanouts_bitmask: u32,
     host_mem: u64,
     pixman_image: *mut pixman_image_t,
+    /// Guest memory entries of the backing, which is mapped to `iov`.
+    backing: Vec<VirtioGpuMemEntry>,
 }
 
 impl Default for GpuResource {
@@ -83,6 +85,7 @@ impl Default for GpuResource {
             scanouts_bitmask: 0,
             host_mem: 0,
             pixman_image: ptr::null_mut(),
+            backing: Vec::new(),
         }
     }
 }
@@ -227,7 +230,7 @@ struct VirtioGpuResourceAttachBacking {
 impl ByteCode for VirtioGpuResourceAttachBacking {}
 
 #[repr(C)]
-#[derive(Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy)]
 struct VirtioGpuMemEntry {
     addr: u64,
     length: u32,
@@ -405,6 +408,17 @@ struct GpuIoHandler {
     max_hostmem: u64,
     /// Current usage of host mem.
     used_hostmem: u64,
+    /// Layout of resources and scanouts shared with the device for migration.
+    display_state: Arc<Mutex<GpuDisplayState>>,
+}
+
+/// Resources and scanouts layout of gpu which is needed to rebuild the display after migration.
+#[derive(Clone, Default)]
+struct GpuDisplayState {
+    enable_output_bitmask: u32,
+    resources: Vec<GpuResourceState>,
+    backings: Vec<VirtioGpuMemEntry>,
+    scanouts: [GpuScanoutState; VIRTIO_GPU_MAX_SCANOUTS],
 }
 
 fn create_surface(
@@ -791,6 +805,7 @@ impl GpuIoHandler {
         }
         self.used_hostmem -= res.host_mem;
         res.iov.clear();
+        res.backing.clear();
     }
 
     fn cmd_resource_unref(&mut self, req: &VirtioGpuRequest) -> Result<()> {
@@ -818,12 +833,17 @@ impl GpuIoHandler {
         let mut info_set_scanout = VirtioGpuSetScanout::default();
         self.get_request(req, &mut info_set_scanout)?;
 
+        let resp_head_type = self.set_scanout(&info_set_scanout);
+        self.response_nodata(resp_head_type, req)
+    }
+
+    fn set_scanout(&mut self, info_set_scanout: &VirtioGpuSetScanout) -> u32 {
         if info_set_scanout.scanout_id >= self.num_scanouts {
             error!(
                 "GuestError: The scanout id {} is out of range.",
                 info_set_scanout.scanout_id
             );
-            return self.response_nodata(VIRTIO_GPU_RESP_ERR_INVALID_SCANOUT_ID, req);
+            return VIRTIO_GPU_RESP_ERR_INVALID_SCANOUT_ID;
         }
 
         let scanout = &mut self.scanouts[info_set_scanout.scanout_id as usize];
@@ -838,7 +858,7 @@ impl GpuIoHandler {
                 res.scanouts_bitmask &= !(1 << info_set_scanout.scanout_id);
             }
             disable_scanout(scanout);
-            return self.response_nodata(VIRTIO_GPU_RESP_OK_NODATA, req);
+            return VIRTIO_GPU_RESP_OK_NODATA;
         }
 
         if let Some(res_index) = self
@@ -862,7 +882,7 @@ impl GpuIoHandler {
                     info_set_scanout.rect.x_coord,
                     info_set_scanout.rect.y_coord,
                 );
-                return self.response_nodata(VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER, req);
+                return VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER;
             }
 
             let pixman_format = unsafe { pixman_image_get_format(res.pixman_image) };
@@ -877,7 +897,7 @@ impl GpuIoHandler {
                 None => {
                     if create_surface(
                         scanout,
-                        info_set_scanout,
+                        *info_set_scanout,
                         res,
                         pixman_format,
                         pixman_stride,
@@ -887,7 +907,7 @@ impl GpuIoHandler {
                     .is_null()
                     {
                         error!("HostError: surface image create failed, check pixman libary.");
-                        return self.response_nodata(VIRTIO_GPU_RESP_ERR_UNSPEC, req);
+                        return VIRTIO_GPU_RESP_ERR_UNSPEC;
                     }
                 }
                 Some(sur) => {
@@ -897,7 +917,7 @@ impl GpuIoHandler {
                         || scanout.height != info_set_scanout.rect.height)
                         && create_surface(
                             scanout,
-                            info_set_scanout,
+                            *info_set_scanout,
                             res,
                             pixman_format,
                             pixman_stride,
@@ -907,7 +927,7 @@ impl GpuIoHandler {
                         .is_null()
                     {
                         error!("HostError: surface pixman image create failed, please check pixman libary.");
-                        return self.response_nodata(VIRTIO_GPU_RESP_ERR_UNSPEC, req);
+                        return VIRTIO_GPU_RESP_ERR_UNSPEC;
                     }
                 }
             }
@@ -930,13 +950,13 @@ impl GpuIoHandler {
             scanout.width = info_set_scanout.rect.width;
             scanout.height = info_set_scanout.rect.height;
 
-            self.response_nodata(VIRTIO_GPU_RESP_OK_NODATA, req)
+            VIRTIO_GPU_RESP_OK_NODATA
         } else {
             error!(
                 "GuestError: The resource_id {} in set_scanout {} request is not existed.",
                 info_set_scanout.resource_id, info_set_scanout.scanout_id
             );
-            self.response_nodata(VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID, req)
+            VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID
         }
     }
 
@@ -1252,6 +1272,7 @@ impl GpuIoHandler {
                     })
                 {
                     res.iov.clear();
+                    res.backing.clear();
                     error!("{:?}", e);
                     return self.response_nodata(VIRTIO_GPU_RESP_ERR_UNSPEC, req);
                 }
@@ -1262,8 +1283,10 @@ impl GpuIoHandler {
                         iov_len: entry.length as u64,
                     };
                     res.iov.push(iov_item);
+                    res.backing.push(entry);
                 } else {
                     res.iov.clear();
+                    res.backing.clear();
                     error!("GuestError: Map desc base {:?} failed.", entry.addr);
                     return self.response_nodata(VIRTIO_GPU_RESP_ERR_UNSPEC, req);
                 }
@@ -1296,6 +1319,7 @@ impl GpuIoHandler {
                 return self.response_nodata(VIRTIO_GPU_RESP_ERR_UNSPEC, req);
             }
             res.iov.clear();
+            res.backing.clear();
             self.response_nodata(VIRTIO_GPU_RESP_OK_NODATA, req)
         } else {
             error!(
@@ -1324,6 +1348,118 @@ impl GpuIoHandler {
                 self.response_nodata(VIRTIO_GPU_RESP_ERR_UNSPEC, req)?;
             }
         }
+        self.update_display_state();
+
+        Ok(())
+    }
+
+    fn update_display_state(&mut self) {
+        let mut display_state = self.display_state.lock().unwrap();
+        display_state.enable_output_bitmask = self.enable_output_bitmask;
+        display_state.resources.clear();
+        display_state.backings.clear();
+        for res in self.resources_list.iter() {
+            let backing_start = display_state.backings.len() as u32;
+            display_state.resources.push(GpuResourceState {
+                resource_id: res.resource_id,
+                width: res.width,
+                height: res.height,
+                format: res.format,
+                backing_start,
+                backing_num: res.backing.len() as u32,
+            });
+            display_state.backings.extend_from_slice(&res.backing);
+        }
+        for (index, scanout) in self.scanouts.iter().enumerate() {
+            display_state.scanouts[index] = GpuScanoutState {
+                resource_id: scanout.resource_id,
+                x: scanout.x,
+                y: scanout.y,
+                width: scanout.width,
+                height: scanout.height,
+            };
+        }
+    }
+
+    /// Rebuild resources and scanouts from the state saved on the source side.
+    fn restore_display_state(&mut self, state: &GpuState) -> Result<()> {
+        self.enable_output_bitmask = state.enable_output_bitmask;
+        for res_state in state.resources[..state.resources_num as usize].iter() {
+            let pixman_format = get_pixman_format(res_state.format)?;
+            let mut res = GpuResource {
+                resource_id: res_state.resource_id,
+                width: res_state.width,
+                height: res_state.height,
+                format: res_state.format,
+                host_mem: get_image_hostmem(pixman_format, res_state.width, res_state.height),
+                ..Default::default()
+            };
+            res.pixman_image = unsafe {
+                pixman_image_create_bits(
+                    pixman_format,
+                    res.width as i32,
+                    res.height as i32,
+                    ptr::null_mut(),
+                    0,
+                )
+            };
+            if res.pixman_image.is_null() {
+                bail!("Failed to create gpu resource {}", res.resource_id);
+            }
+            self.used_hostmem += res.host_mem;
+
+            let start = res_state.backing_start as usize;
+            let end = start + res_state.backing_num as usize;
+            if end > state.backings_num as usize {
+                bail!("Invalid backing of gpu resource {}", res.resource_id);
+            }
+            for entry in state.backings[start..end].iter() {
+                let iov_base = self
+                    .mem_space
+                    .get_host_address(GuestAddress(entry.addr))
+                    .with_context(|| format!("Failed to map gpu backing {:#x}", entry.addr))?;
+                res.iov.push(Iovec {
+                    iov_base,
+                    iov_len: entry.length as u64,
+                });
+                res.backing.push(*entry);
+            }
+            let has_backing = !res.iov.is_empty();
+            self.resources_list.push(res);
+
+            if has_backing {
+                let info_transfer = VirtioGpuTransferToHost2d {
+                    rect: VirtioGpuRect {
+                        width: res_state.width,
+                        height: res_state.height,
+                        ..Default::default()
+                    },
+                    resource_id: res_state.resource_id,
+                    ..Default::default()
+                };
+                self.cmd_transfer_to_host_2d_update_resource(&info_transfer);
+            }
+        }
+
+        for (index, scanout_state) in state.scanouts.iter().enumerate() {
+            if scanout_state.resource_id == 0 || index >= self.num_scanouts as usize {
+                continue;
+            }
+            let info_set_scanout = VirtioGpuSetScanout {
+                rect: VirtioGpuRect {
+                    x_coord: scanout_state.x,
+                    y_coord: scanout_state.y,
+                    width: scanout_state.width,
+                    height: scanout_state.height,
+                },
+                scanout_id: index as u32,
+                resource_id: scanout_state.resource_id,
+            };
+            if self.set_scanout(&info_set_scanout) != VIRTIO_GPU_RESP_OK_NODATA {
+                bail!("Failed to restore gpu scanout {}", index);
+            }
+        }
+        self.update_display_state();
 
         Ok(())
     }
@@ -1498,6 +1634,41 @@ pub struct GpuState {
     driver_features: u64,
     /// Config space of the GPU device.
     config_space: VirtioGpuConfig,
+    /// The bit mask of whether scanout is enabled or not.
+    enable_output_bitmask: u32,
+    /// Number of valid entries in `resources`.
+    resources_num: u32,
+    /// Number of valid entries in `backings`.
+    backings_num: u32,
+    /// 2D resources created by the guest.
+    resources: [GpuResourceState; 64],
+    /// Guest memory entries of all resources' backing.
+    backings: [VirtioGpuMemEntry; 16384],
+    /// Resource and rectangle of each scanout.
+    scanouts: [GpuScanoutState; 16],
+}
+
+/// State of a 2D resource, its backing is `backings[backing_start..backing_start + backing_num]`.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct GpuResourceState {
+    resource_id: u32,
+    width: u32,
+    height: u32,
+    format: u32,
+    backing_start: u32,
+    backing_num: u32,
+}
+
+/// State of a scanout, resource id 0 means the scanout is disabled.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct GpuScanoutState {
+    resource_id: u32,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
 }
 
 /// GPU device structure.
@@ -1511,6 +1682,8 @@ pub struct Gpu {
     interrupt_cb: Option<Arc<VirtioInterrupt>>,
     /// Eventfd for device deactivate.
     deactivate_evts: Vec<RawFd>,
+    /// Layout of resources and scanouts updated by the IO handler.
+    display_state: Arc<Mutex<GpuDisplayState>>,
 }
 
 impl Gpu {
@@ -1520,6 +1693,7 @@ impl Gpu {
             state: GpuState::default(),
             interrupt_cb: None,
             deactivate_evts: Vec::new(),
+            display_state: Arc::new(Mutex::new(GpuDisplayState::default())),
         }
     }
 
@@ -1543,6 +1717,7 @@ impl VirtioDevice for Gpu {
         self.state.device_features = 1u64 << VIRTIO_F_VERSION_1;
         self.state.device_features |= 1u64 << VIRTIO_F_RING_EVENT_IDX;
         self.state.device_features |= 1u64 << VIRTIO_F_RING_INDIRECT_DESC;
+        self.state.device_features |= 1u64 << VIRTIO_F_RING_PACKED;
         if self.cfg.edid {
             self.state.device_features |= 1 << VIRTIO_GPU_F_EDID;
         }
@@ -1670,9 +1845,18 @@ impl VirtioDevice for Gpu {
             scanouts,
             max_hostmem: self.cfg.max_hostmem,
             used_hostmem: 0,
+            display_state: self.display_state.clone(),
         };
         handler.req_states[0].width = self.cfg.xres;
         handler.req_states[0].height = self.cfg.yres;
+        if self.state.resources_num != 0 {
+            handler
+                .restore_display_state(&self.state)
+                .with_context(|| "Failed to restore gpu display")?;
+            self.state.resources_num = 0;
+            self.state.backings_num = 0;
+            self.state.scanouts = [GpuScanoutState::default(); VIRTIO_GPU_MAX_SCANOUTS];
+        }
 
         let notifiers = EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler)));
         register_event_helper(notifiers, None, &mut self.deactivate_evts)?;
@@ -1681,6 +1865,42 @@ impl VirtioDevice for Gpu {
     }
 
     fn deactivate(&mut self) -> Result<()> {
+        *self.display_state.lock().unwrap() = GpuDisplayState::default();
         unregister_event_helper(None, &mut self.deactivate_evts)
     }
 }
+
+impl StateTransfer for Gpu {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        let mut state = self.state;
+        let display_state = self.display_state.lock().unwrap();
+        if display_state.resources.len() > state.resources.len()
+            || display_state.backings.len() > state.backings.len()
+        {
+            bail!(
+                "Too many gpu resources {} or backing entries {} to save",
+                display_state.resources.len(),
+                display_state.backings.len()
+            );
+        }
+        state.enable_output_bitmask = display_state.enable_output_bitmask;
+        state.resources_num = display_state.resources.len() as u32;
+        state.resources[..display_state.resources.len()].copy_from_slice(&display_state.resources);
+        state.backings_num = display_state.backings.len() as u32;
+        state.backings[..display_state.backings.len()].copy_from_slice(&display_state.backings);
+        state.scanouts = display_state.scanouts;
+        Ok(state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        self.state = *GpuState::from_bytes(state)
+            .ok_or_else(|| anyhow!(migration::error::MigrationError::FromBytesError("GPU")))?;
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&GpuState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+impl MigrationHook for Gpu {}
@@ -41,7 +41,7 @@ mod virtqueue;
 pub use anyhow::Result;
 pub use balloon::*;
 pub use block::{Block, BlockState};
-pub use console::{Console, VirtioConsoleState};
+pub use console::{Console, SerialPort, VirtioConsoleState};
 pub use error::VirtioError;
 pub use error::*;
 #[cfg(not(target_env = "musl"))]
@@ -65,7 +65,12 @@ use std::sync::{Arc, Mutex};
 use address_space::AddressSpace;
 use anyhow::anyhow;
 use anyhow::bail;
-use machine_manager::config::ConfigCheck;
+use machine_manager::config::{BlockErrorPolicy, ConfigCheck};
+use machine_manager::event;
+use machine_manager::event_loop::EventLoop;
+use machine_manager::qmp::qmp_schema::{self as schema, BlockErrorAction, IoOperationType};
+use machine_manager::qmp::QmpChannel;
+use migration::{general::Lifecycle, MigrationManager};
 use util::aio::mem_to_buf;
 use util::num_ops::write_u32;
 use vmm_sys_util::eventfd::EventFd;
@@ -144,6 +149,8 @@ pub const VIRTIO_NET_F_MQ: u32 = 22;
 pub const VIRTIO_NET_F_CTRL_MAC_ADDR: u32 = 23;
 /// Configuration cols and rows are valid.
 pub const VIRTIO_CONSOLE_F_SIZE: u64 = 0;
+/// Device has support for multiple ports.
+pub const VIRTIO_CONSOLE_F_MULTIPORT: u64 = 1;
 /// Maximum size of any single segment is in size_max.
 pub const VIRTIO_BLK_F_SIZE_MAX: u32 = 1;
 /// Maximum number of segments in a request is in seg_max.
@@ -230,6 +237,12 @@ pub const VIRTIO_BLK_T_OUT: u32 = 1;
 pub const VIRTIO_BLK_T_FLUSH: u32 = 4;
 /// Device id
 pub const VIRTIO_BLK_T_GET_ID: u32 = 8;
+/// Discard command.
+pub const VIRTIO_BLK_T_DISCARD: u32 = 11;
+/// Write zeroes command.
+pub const VIRTIO_BLK_T_WRITE_ZEROES: u32 = 13;
+/// Write zeroes command may deallocate the sectors.
+pub const VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP: u32 = 1;
 /// Device id length
 pub const VIRTIO_BLK_ID_BYTES: u32 = 20;
 /// Success
@@ -461,6 +474,47 @@ pub fn report_virtio_error(
     broken.store(true, Ordering::SeqCst);
 }
 
+/// Handle a disk I/O request of the device `device` which failed with the
+/// negative errno `ret`, according to the error `policy` of the device.
+///
+/// The client is notified of the error. If the action is `stop`, the VM is
+/// paused and the caller should keep the request to retry it when the VM is
+/// resumed.
+pub fn handle_block_io_error(
+    device: &str,
+    operation: IoOperationType,
+    policy: BlockErrorPolicy,
+    ret: i64,
+) -> BlockErrorAction {
+    let errno = -ret as i32;
+    let action = policy.action(errno);
+    if QmpChannel::is_connected() {
+        let io_error = schema::BlockIoError {
+            device: device.to_string(),
+            operation,
+            action,
+            nospace: errno == libc::ENOSPC,
+            reason: std::io::Error::from_raw_os_error(errno).to_string(),
+        };
+        event!(BlockIoError; io_error);
+    }
+    if action == BlockErrorAction::stop {
+        // The request may be completed with the lock of the VM held, so pause
+        // the VM in the main loop.
+        let pause_vm = Box::new(|| {
+            if let Err(e) = MigrationManager::pause() {
+                error!("Failed to stop VM on block I/O error: {:?}", e);
+            }
+        });
+        if let Some(ctx) = EventLoop::get_ctx(None) {
+            ctx.delay_call(pause_vm, 0);
+        } else {
+            error!("Failed to get ctx in event loop context to stop VM on block I/O error");
+        }
+    }
+    action
+}
+
 /// Read iovec to buf and return the readed number of bytes.
 pub fn iov_to_buf(mem_space: &AddressSpace, iovec: &[ElemIovec], buf: &mut [u8]) -> Result<usize> {
     let mut start: usize = 0;
@@ -23,9 +23,9 @@ use std::{cmp, fs, mem};
 
 use super::{
     Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType, VirtioNetHdr, VirtioTrace,
-    VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_VERSION_1, VIRTIO_NET_CTRL_MAC,
-    VIRTIO_NET_CTRL_MAC_ADDR_SET, VIRTIO_NET_CTRL_MAC_TABLE_SET, VIRTIO_NET_CTRL_MQ,
-    VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN,
+    VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_RING_PACKED, VIRTIO_F_VERSION_1,
+    VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_ADDR_SET, VIRTIO_NET_CTRL_MAC_TABLE_SET,
+    VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN,
     VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, VIRTIO_NET_CTRL_RX, VIRTIO_NET_CTRL_RX_ALLMULTI,
     VIRTIO_NET_CTRL_RX_ALLUNI, VIRTIO_NET_CTRL_RX_NOBCAST, VIRTIO_NET_CTRL_RX_NOMULTI,
     VIRTIO_NET_CTRL_RX_NOUNI, VIRTIO_NET_CTRL_RX_PROMISC, VIRTIO_NET_CTRL_VLAN,
@@ -46,7 +46,9 @@ use log::{error, warn};
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
 use machine_manager::{
     config::{ConfigCheck, NetworkInterfaceConfig},
+    event,
     event_loop::EventLoop,
+    qmp::{qmp_schema as schema, QmpChannel},
 };
 use migration::{
     migration::Migratable, DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager,
@@ -58,7 +60,9 @@ use util::loop_context::gen_delete_notifiers;
 use util::loop_context::{
     read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
 };
+use util::net_backend::{create_socket_backend, NetBackend, SeqpacketBackend};
 use util::num_ops::{read_u32, str_to_usize};
+use util::slirp::SlirpBackend;
 use util::tap::{
     Tap, IFF_MULTI_QUEUE, TUN_F_CSUM, TUN_F_TSO4, TUN_F_TSO6, TUN_F_TSO_ECN, TUN_F_UFO,
 };
@@ -81,8 +85,17 @@ const NET_HDR_LENGTH: usize = mem::size_of::<VirtioNetHdr>();
 const VLAN_TAG_LENGTH: usize = 4;
 /// The offset of vlan tpid for 802.1Q tag.
 const VLAN_TPID_LENGTH: usize = 2;
-
-type SenderConfig = Option<Tap>;
+/// The features which need the virtio net header passed to the backend.
+const NET_OFFLOAD_FEATURES: u64 = 1 << VIRTIO_NET_F_CSUM
+    | 1 << VIRTIO_NET_F_GUEST_CSUM
+    | 1 << VIRTIO_NET_F_GUEST_TSO4
+    | 1 << VIRTIO_NET_F_GUEST_TSO6
+    | 1 << VIRTIO_NET_F_GUEST_UFO
+    | 1 << VIRTIO_NET_F_HOST_TSO4
+    | 1 << VIRTIO_NET_F_HOST_TSO6
+    | 1 << VIRTIO_NET_F_HOST_UFO;
+
+type SenderConfig = Option<Box<dyn NetBackend>>;
 
 /// The first default mac address.
 const FIRST_DEFAULT_MAC: [u8; MAC_ADDR_LEN] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
@@ -512,6 +525,8 @@ pub struct NetCtrlHandler {
     pub driver_features: u64,
     /// Device is broken or not.
     pub device_broken: Arc<AtomicBool>,
+    /// The id of the net device, used in the rx filter changed event.
+    pub device_id: String,
 }
 
 #[repr(C, packed)]
@@ -524,6 +539,18 @@ struct CtrlHdr {
 impl ByteCode for CtrlHdr {}
 
 impl NetCtrlHandler {
+    /// Notify the client that the guest changed the mac address, the mac
+    /// table, the vlan table or the rx mode.
+    fn notify_rx_filter_changed(&self) {
+        if QmpChannel::is_connected() {
+            let filter_event = schema::NicRxFilterChanged {
+                name: Some(self.device_id.clone()),
+                path: format!("/machine/peripheral/{}/virtio-backend", self.device_id),
+            };
+            event!(NicRxFilterChanged; filter_event);
+        }
+    }
+
     fn handle_ctrl(&mut self) -> Result<()> {
         let mut locked_queue = self.ctrl.queue.lock().unwrap();
         loop {
@@ -601,6 +628,15 @@ impl NetCtrlHandler {
                 }
             }
 
+            if ack == VIRTIO_NET_OK
+                && matches!(
+                    ctrl_hdr.class,
+                    VIRTIO_NET_CTRL_RX | VIRTIO_NET_CTRL_MAC | VIRTIO_NET_CTRL_VLAN
+                )
+            {
+                self.notify_rx_filter_changed();
+            }
+
             // Write result to the device writable iovec.
             let status = elem
                 .in_iovec
@@ -694,8 +730,8 @@ impl RxVirtio {
 struct NetIoHandler {
     rx: RxVirtio,
     tx: TxVirtio,
-    tap: Option<Tap>,
-    tap_fd: RawFd,
+    backend: Option<Box<dyn NetBackend>>,
+    backend_fd: RawFd,
     mem_space: Arc<AddressSpace>,
     interrupt_cb: Arc<VirtioInterrupt>,
     driver_features: u64,
@@ -708,38 +744,6 @@ struct NetIoHandler {
 }
 
 impl NetIoHandler {
-    fn read_from_tap(iovecs: &[libc::iovec], tap: &mut Tap) -> i32 {
-        // SAFETY: the arguments of readv has been checked and is correct.
-        let size = unsafe {
-            libc::readv(
-                tap.as_raw_fd() as libc::c_int,
-                iovecs.as_ptr() as *const libc::iovec,
-                iovecs.len() as libc::c_int,
-            )
-        } as i32;
-        if size < 0 {
-            let e = std::io::Error::last_os_error();
-            if e.kind() == std::io::ErrorKind::WouldBlock {
-                return size;
-            }
-
-            // If the backend tap device is removed, readv returns less than 0.
-            // At this time, the content in the tap needs to be cleaned up.
-            // Here, read is called to process, otherwise handle_rx may be triggered all the time.
-            let mut buf = [0; 1024];
-            match tap.read(&mut buf) {
-                Ok(cnt) => error!("Failed to call readv but tap read is ok: cnt {}", cnt),
-                Err(e) => {
-                    // When the backend tap device is abnormally removed, read return EBADFD.
-                    error!("Failed to read tap: {}", e);
-                }
-            }
-            error!("Failed to call readv for net handle_rx: {}", e);
-        }
-
-        size
-    }
-
     fn get_libc_iovecs(
         mem_space: &Arc<AddressSpace>,
         cache: &Option<RegionCache>,
@@ -768,7 +772,7 @@ impl NetIoHandler {
         }
 
         let mut rx_packets = 0;
-        while let Some(tap) = self.tap.as_mut() {
+        while let Some(backend) = self.backend.as_mut() {
             let elem = queue
                 .vring
                 .pop_avail(&self.mem_space, self.driver_features)
@@ -785,17 +789,17 @@ impl NetIoHandler {
                 &elem.in_iovec,
             );
 
-            if MigrationManager::is_active() {
+            if MigrationManager::is_active() || MigrationManager::is_tracking_snapshot() {
                 // FIXME: mark dirty page needs to be managed by `AddressSpace` crate.
                 for iov in iovecs.iter() {
-                    // Mark vmm dirty page manually if live migration is active.
+                    // Mark vmm dirty page manually if dirty page is being logged.
                     MigrationManager::mark_dirty_log(iov.iov_base as u64, iov.iov_len as u64);
                 }
             }
 
-            // Read the data from the tap device.
-            let size = NetIoHandler::read_from_tap(&iovecs, tap);
-            if size < (NET_HDR_LENGTH + ETHERNET_HDR_LENGTH + VLAN_TAG_LENGTH) as i32 {
+            // Read the data from the backend.
+            let size = backend.recv(&iovecs).unwrap_or(0);
+            if size < NET_HDR_LENGTH + ETHERNET_HDR_LENGTH + VLAN_TAG_LENGTH {
                 queue.vring.push_back();
                 break;
             }
@@ -858,30 +862,6 @@ impl NetIoHandler {
         Ok(())
     }
 
-    fn send_packets(&self, tap_fd: libc::c_int, iovecs: &[libc::iovec]) -> i8 {
-        loop {
-            // SAFETY: the arguments of writev has been checked and is correct.
-            let size = unsafe {
-                libc::writev(
-                    tap_fd,
-                    iovecs.as_ptr() as *const libc::iovec,
-                    iovecs.len() as libc::c_int,
-                )
-            };
-            if size < 0 {
-                let e = std::io::Error::last_os_error();
-                match e.kind() {
-                    ErrorKind::Interrupted => continue,
-                    ErrorKind::WouldBlock => return -1_i8,
-                    // Ignore other errors which can not be handled.
-                    _ => error!("Failed to call writev for net handle_tx: {}", e),
-                }
-            }
-            break;
-        }
-        0_i8
-    }
-
     fn handle_tx(&mut self) -> Result<()> {
         self.trace_request("Net".to_string(), "to tx".to_string());
         let mut queue = self.tx.queue.lock().unwrap();
@@ -905,17 +885,19 @@ impl NetIoHandler {
                 queue.vring.get_cache(),
                 &elem.out_iovec,
             );
-            let tap_fd = if let Some(tap) = self.tap.as_mut() {
-                tap.as_raw_fd() as libc::c_int
-            } else {
-                -1_i32
-            };
-            if tap_fd != -1 && self.send_packets(tap_fd, &iovecs) == -1 {
-                queue.vring.push_back();
-                self.tx.queue_evt.write(1).with_context(|| {
-                    "Failed to trigger tx queue event when writev blocked".to_string()
-                })?;
-                return Ok(());
+            if let Some(backend) = self.backend.as_mut() {
+                match backend.send(&iovecs) {
+                    Ok(_) => {}
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        queue.vring.push_back();
+                        self.tx.queue_evt.write(1).with_context(|| {
+                            "Failed to trigger tx queue event when send blocked".to_string()
+                        })?;
+                        return Ok(());
+                    }
+                    // Ignore other errors which can not be handled.
+                    Err(e) => error!("Failed to send packets for net handle_tx: {}", e),
+                }
             }
 
             queue
@@ -951,17 +933,17 @@ impl NetIoHandler {
 
     fn update_evt_handler(net_io: &Arc<Mutex<Self>>) -> Vec<EventNotifier> {
         let mut locked_net_io = net_io.lock().unwrap();
-        locked_net_io.tap = match locked_net_io.receiver.recv() {
-            Ok(tap) => tap,
+        locked_net_io.backend = match locked_net_io.receiver.recv() {
+            Ok(backend) => backend,
             Err(e) => {
-                error!("Failed to receive the tap {}", e);
+                error!("Failed to receive the net backend {}", e);
                 None
             }
         };
-        let old_tap_fd = locked_net_io.tap_fd;
-        locked_net_io.tap_fd = -1;
-        if let Some(tap) = locked_net_io.tap.as_ref() {
-            locked_net_io.tap_fd = tap.as_raw_fd();
+        let old_backend_fd = locked_net_io.backend_fd;
+        locked_net_io.backend_fd = -1;
+        if let Some(backend) = locked_net_io.backend.as_ref() {
+            locked_net_io.backend_fd = backend.as_raw_fd();
         }
 
         let mut notifiers_fds = vec![
@@ -969,8 +951,8 @@ impl NetIoHandler {
             locked_net_io.rx.queue_evt.as_raw_fd(),
             locked_net_io.tx.queue_evt.as_raw_fd(),
         ];
-        if old_tap_fd != -1 {
-            notifiers_fds.push(old_tap_fd);
+        if old_backend_fd != -1 {
+            notifiers_fds.push(old_backend_fd);
         }
         let mut notifiers = gen_delete_notifiers(&notifiers_fds);
         drop(locked_net_io);
@@ -1043,11 +1025,11 @@ impl EventNotifierHelper for NetIoHandler {
             if locked_net_io.device_broken.load(Ordering::SeqCst) {
                 return None;
             }
-            if let Some(tap) = locked_net_io.tap.as_ref() {
+            if let Some(backend) = locked_net_io.backend.as_ref() {
                 if !locked_net_io.is_listening {
                     let notifier = vec![EventNotifier::new(
                         NotifierOperation::Resume,
-                        tap.as_raw_fd(),
+                        backend.as_raw_fd(),
                         None,
                         EventSet::IN | EventSet::EDGE_TRIGGERED,
                         Vec::new(),
@@ -1092,9 +1074,9 @@ impl EventNotifierHelper for NetIoHandler {
             EventSet::IN,
         ));
 
-        // Register event notifier for tap.
+        // Register event notifier for backend.
         let cloned_net_io = net_io.clone();
-        if let Some(tap) = locked_net_io.tap.as_ref() {
+        if let Some(backend) = locked_net_io.backend.as_ref() {
             let handler: Rc<NotifierCallback> = Rc::new(move |_, _| {
                 let mut locked_net_io = cloned_net_io.lock().unwrap();
                 if locked_net_io.device_broken.load(Ordering::SeqCst) {
@@ -1102,7 +1084,7 @@ impl EventNotifierHelper for NetIoHandler {
                 }
 
                 if let Err(ref e) = locked_net_io.handle_rx() {
-                    error!("Failed to handle rx(tap event), {:?}", e);
+                    error!("Failed to handle rx(backend event), {:?}", e);
                     report_virtio_error(
                         locked_net_io.interrupt_cb.clone(),
                         locked_net_io.driver_features,
@@ -1111,11 +1093,11 @@ impl EventNotifierHelper for NetIoHandler {
                     return None;
                 }
 
-                if let Some(tap) = locked_net_io.tap.as_ref() {
+                if let Some(backend) = locked_net_io.backend.as_ref() {
                     if locked_net_io.rx.queue_full {
                         let notifier = vec![EventNotifier::new(
                             NotifierOperation::Park,
-                            tap.as_raw_fd(),
+                            backend.as_raw_fd(),
                             None,
                             EventSet::IN | EventSet::EDGE_TRIGGERED,
                             Vec::new(),
@@ -1127,9 +1109,9 @@ impl EventNotifierHelper for NetIoHandler {
                 }
                 None
             });
-            let tap_fd = tap.as_raw_fd();
+            let backend_fd = backend.as_raw_fd();
             notifiers.push(build_event_notifier(
-                tap_fd,
+                backend_fd,
                 Some(handler),
                 NotifierOperation::AddShared,
                 EventSet::IN | EventSet::EDGE_TRIGGERED,
@@ -1159,8 +1141,10 @@ pub struct VirtioNetState {
 pub struct Net {
     /// Configuration of the network device.
     net_cfg: NetworkInterfaceConfig,
-    /// Tap device opened.
-    taps: Option<Vec<Tap>>,
+    /// Backends of the queue pairs, such as the tap devices opened.
+    backends: Option<Vec<Box<dyn NetBackend>>>,
+    /// Userspace network backend connected with the socket backend.
+    slirp: Option<SlirpBackend>,
     /// The status of net device.
     state: Arc<Mutex<VirtioNetState>>,
     /// The send half of Rust's channel to send tap information.
@@ -1179,7 +1163,8 @@ impl Default for Net {
     fn default() -> Self {
         Self {
             net_cfg: Default::default(),
-            taps: None,
+            backends: None,
+            slirp: None,
             state: Arc::new(Mutex::new(VirtioNetState::default())),
             senders: None,
             update_evts: Vec::new(),
@@ -1194,7 +1179,8 @@ impl Net {
     pub fn new(net_cfg: NetworkInterfaceConfig) -> Self {
         Self {
             net_cfg,
-            taps: None,
+            backends: None,
+            slirp: None,
             state: Arc::new(Mutex::new(VirtioNetState::default())),
             senders: None,
             update_evts: Vec::new(),
@@ -1338,6 +1324,12 @@ pub fn create_tap(
     Ok(Some(taps))
 }
 
+fn tap_backends(taps: Vec<Tap>) -> Vec<Box<dyn NetBackend>> {
+    taps.into_iter()
+        .map(|tap| Box::new(tap) as Box<dyn NetBackend>)
+        .collect()
+}
+
 /// Get the tap offload flags from driver features.
 ///
 /// # Arguments
@@ -1392,7 +1384,8 @@ impl VirtioDevice for Net {
             | 1 << VIRTIO_NET_F_CTRL_MAC_ADDR
             | 1 << VIRTIO_NET_F_CTRL_VQ
             | 1 << VIRTIO_F_RING_INDIRECT_DESC
-            | 1 << VIRTIO_F_RING_EVENT_IDX;
+            | 1 << VIRTIO_F_RING_EVENT_IDX
+            | 1 << VIRTIO_F_RING_PACKED;
 
         let queue_pairs = self.net_cfg.queues / 2;
         if self.net_cfg.mq
@@ -1404,33 +1397,53 @@ impl VirtioDevice for Net {
         }
 
         if !self.net_cfg.host_dev_name.is_empty() {
-            self.taps = None;
-            self.taps = create_tap(None, Some(&self.net_cfg.host_dev_name), queue_pairs)
-                .with_context(|| "Failed to open tap with file path")?;
+            self.backends = None;
+            self.backends = create_tap(None, Some(&self.net_cfg.host_dev_name), queue_pairs)
+                .with_context(|| "Failed to open tap with file path")?
+                .map(tap_backends);
         } else if let Some(fds) = self.net_cfg.tap_fds.as_mut() {
             let mut created_fds = 0;
-            if let Some(taps) = &self.taps {
-                for (index, tap) in taps.iter().enumerate() {
-                    if fds.get(index).map_or(-1, |fd| *fd as RawFd) == tap.as_raw_fd() {
+            if let Some(backends) = &self.backends {
+                for (index, backend) in backends.iter().enumerate() {
+                    if fds.get(index).map_or(-1, |fd| *fd as RawFd) == backend.as_raw_fd() {
                         created_fds += 1;
                     }
                 }
             }
 
             if created_fds != fds.len() {
-                self.taps = create_tap(Some(fds), None, queue_pairs)
-                    .with_context(|| "Failed to open tap")?;
+                self.backends = create_tap(Some(fds), None, queue_pairs)
+                    .with_context(|| "Failed to open tap")?
+                    .map(tap_backends);
             }
+        } else if let Some(user_net) = self.net_cfg.user_net.as_ref() {
+            // Stop the old backend first to release the host forwarding ports.
+            self.slirp = None;
+            let (sock, slirp) = SlirpBackend::start(&self.net_cfg.id, user_net, NET_HDR_LENGTH)
+                .with_context(|| "Failed to start userspace network backend")?;
+            self.backends = Some(vec![Box::new(SeqpacketBackend::new(sock))]);
+            self.slirp = Some(slirp);
+        } else if let Some(socket) = self.net_cfg.socket.as_ref() {
+            self.backends = None;
+            self.backends = Some(vec![create_socket_backend(socket, NET_HDR_LENGTH)
+                .with_context(|| "Failed to create socket network backend")?]);
         } else {
-            self.taps = None;
+            self.backends = None;
+        }
+        if self.net_cfg.user_net.is_none() {
+            self.slirp = None;
         }
 
-        // Using the first tap to test if all the taps have ufo.
-        if let Some(tap) = self.taps.as_ref().map(|t| &t[0]) {
-            if !tap.has_ufo() {
+        // Using the first backend to test if all the backends have ufo.
+        if let Some(backend) = self.backends.as_ref().map(|b| &b[0]) {
+            if !backend.has_ufo() {
                 locked_state.device_features &=
                     !(1 << VIRTIO_NET_F_GUEST_UFO | 1 << VIRTIO_NET_F_HOST_UFO);
             }
+            // The offload needs the virtio net header passed to the backend.
+            if !backend.has_vnet_hdr() {
+                locked_state.device_features &= !NET_OFFLOAD_FEATURES;
+            }
         }
 
         if let Some(mac) = &self.net_cfg.mac {
@@ -1451,6 +1464,7 @@ impl VirtioDevice for Net {
     }
 
     fn unrealize(&mut self) -> Result<()> {
+        self.slirp = None;
         mark_mac_table(&self.state.lock().unwrap().config_space.mac, false);
         MigrationManager::unregister_device_instance(
             VirtioNetState::descriptor(),
@@ -1561,6 +1575,7 @@ impl VirtioDevice for Net {
                 interrupt_cb: interrupt_cb.clone(),
                 driver_features,
                 device_broken: self.broken.clone(),
+                device_id: self.net_cfg.id.clone(),
             };
 
             let notifiers =
@@ -1587,8 +1602,9 @@ impl VirtioDevice for Net {
             let (sender, receiver) = channel();
             senders.push(sender);
 
-            if let Some(tap) = self.taps.as_ref().map(|t| t[index].clone()) {
-                tap.set_offload(flags)
+            if let Some(backend) = self.backends.as_ref().map(|b| &b[index]) {
+                backend
+                    .set_offload(flags)
                     .with_context(|| "Failed to set tap offload")?;
             }
 
@@ -1596,8 +1612,8 @@ impl VirtioDevice for Net {
             let mut handler = NetIoHandler {
                 rx: RxVirtio::new(rx_queue, rx_queue_evt),
                 tx: TxVirtio::new(tx_queue, tx_queue_evt),
-                tap: self.taps.as_ref().map(|t| t[index].clone()),
-                tap_fd: -1,
+                backend: self.backends.as_ref().map(|b| b[index].clone()),
+                backend_fd: -1,
                 mem_space: mem_space.clone(),
                 interrupt_cb: interrupt_cb.clone(),
                 driver_features,
@@ -1608,8 +1624,8 @@ impl VirtioDevice for Net {
                 ctrl_info: ctrl_info.clone(),
                 queue_size: self.queue_size(),
             };
-            if let Some(tap) = &handler.tap {
-                handler.tap_fd = tap.as_raw_fd();
+            if let Some(backend) = &handler.backend {
+                handler.backend_fd = backend.as_raw_fd();
             }
 
             let notifiers = EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler)));
@@ -1638,9 +1654,10 @@ impl VirtioDevice for Net {
             // The features about offload is included in bits 0 to 31.
             let features = self.get_driver_features(0_u32);
             let flags = get_tap_offload_flags(features as u64);
-            if let Some(taps) = &self.taps {
-                for (_, tap) in taps.iter().enumerate() {
-                    tap.set_offload(flags)
+            if let Some(backends) = &self.backends {
+                for backend in backends.iter() {
+                    backend
+                        .set_offload(flags)
                         .with_context(|| "Failed to set tap offload")?;
                 }
             }
@@ -1652,14 +1669,14 @@ impl VirtioDevice for Net {
 
         if let Some(senders) = &self.senders {
             for (index, sender) in senders.iter().enumerate() {
-                match self.taps.take() {
-                    Some(taps) => {
-                        let tap = taps
+                match self.backends.take() {
+                    Some(backends) => {
+                        let backend = backends
                             .get(index)
                             .cloned()
-                            .with_context(|| format!("Failed to get index {} tap", index))?;
-                        sender.send(Some(tap)).with_context(|| {
-                            anyhow!(VirtioError::ChannelSend("tap fd".to_string()))
+                            .with_context(|| format!("Failed to get index {} backend", index))?;
+                        sender.send(Some(backend)).with_context(|| {
+                            anyhow!(VirtioError::ChannelSend("net backend".to_string()))
                         })?;
                     }
                     None => sender
@@ -1734,7 +1751,7 @@ mod tests {
         assert_eq!(net.state.lock().unwrap().device_features, 0);
         assert_eq!(net.state.lock().unwrap().driver_features, 0);
 
-        assert_eq!(net.taps.is_none(), true);
+        assert_eq!(net.backends.is_none(), true);
         assert_eq!(net.senders.is_none(), true);
         assert_eq!(net.net_cfg.mac.is_none(), true);
         assert_eq!(net.net_cfg.tap_fds.is_none(), true);
@@ -39,7 +39,7 @@ use vmm_sys_util::eventfd::EventFd;
 
 use super::{
     ElemIovec, Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType, VirtioTrace,
-    VIRTIO_F_VERSION_1, VIRTIO_TYPE_RNG,
+    VIRTIO_F_RING_PACKED, VIRTIO_F_VERSION_1, VIRTIO_TYPE_RNG,
 };
 use crate::error::VirtioError;
 use anyhow::{anyhow, bail, Context, Result};
@@ -264,7 +264,15 @@ impl VirtioDevice for Rng {
             .with_context(|| "Failed to open file of random number generator")?;
 
         self.random_file = Some(file);
-        self.state.device_features = 1 << VIRTIO_F_VERSION_1 as u64;
+        self.state.device_features =
+            1 << VIRTIO_F_VERSION_1 as u64 | 1 << VIRTIO_F_RING_PACKED as u64;
+        Ok(())
+    }
+
+    /// Unrealize virtio rng device.
+    fn unrealize(&mut self) -> Result<()> {
+        MigrationManager::unregister_device_instance(RngState::descriptor(), &self.rng_cfg.id);
+        self.random_file = None;
         Ok(())
     }
 
@@ -16,6 +16,8 @@ use std::io::Write;
 use std::sync::{Arc, Mutex, Weak};
 
 use anyhow::{anyhow, bail, Context, Result};
+use block_backend::stats::{block_acct_start, BlockAcctType};
+use block_backend::submit_rw_request;
 
 use crate::ScsiCntlr::{
     ScsiCntlr, ScsiCompleteCb, ScsiXferMode, VirtioScsiCmdReq, VirtioScsiCmdResp,
@@ -241,7 +243,7 @@ pub const SCSI_SENSE_DEVICE_INTERNAL_RESET: ScsiSense = scsisense!(UNIT_ATTENTIO
 pub const SCSI_SENSE_WRITE_PROTECTED: ScsiSense = scsisense!(DATA_PROTECT, 0x27, 0x00);
 pub const SCSI_SENSE_SPACE_ALLOC_FAILED: ScsiSense = scsisense!(DATA_PROTECT, 0x27, 0x07);
 
-#[derive(Default)]
+#[derive(Clone, Copy, Default)]
 pub struct ScsiSense {
     /// Sense key.
     pub key: u8,
@@ -406,39 +408,61 @@ impl ScsiBus {
         None
     }
 
+    /// Set REPORTED LUNS CHANGED unit attention condition to all luns of the target, after
+    /// a lun is attached to or detached from it.
+    pub fn report_luns_changed(bus: &Arc<Mutex<ScsiBus>>, target: u8) {
+        // Don't lock the devices with the bus locked, REPORT LUNS locks them the other way round.
+        let devices: Vec<Arc<Mutex<ScsiDevice>>> =
+            bus.lock().unwrap().devices.values().cloned().collect();
+        for device in devices {
+            let mut locked_dev = device.lock().unwrap();
+            if locked_dev.config.target == target {
+                locked_dev.unit_attention = Some(SCSI_SENSE_REPORTED_LUNS_CHANGED);
+            }
+        }
+    }
+
     pub fn scsi_bus_parse_req_cdb(
         &self,
         cdb: [u8; VIRTIO_SCSI_CDB_DEFAULT_SIZE],
         dev: Arc<Mutex<ScsiDevice>>,
     ) -> Option<ScsiCommand> {
-        let buf: [u8; SCSI_CMD_BUF_SIZE] = (cdb[0..SCSI_CMD_BUF_SIZE])
-            .try_into()
-            .expect("incorrect length");
-        let command = cdb[0];
-        let len = scsi_cdb_length(&cdb);
-        if len < 0 {
-            return None;
-        }
+        scsi_parse_cdb(cdb, dev)
+    }
+}
 
-        let xfer = scsi_cdb_xfer(&cdb, dev);
-        if xfer < 0 {
-            return None;
-        }
+/// Parse the Command Descriptor Block `cdb` which is sent to the scsi device `dev`.
+pub fn scsi_parse_cdb(
+    cdb: [u8; VIRTIO_SCSI_CDB_DEFAULT_SIZE],
+    dev: Arc<Mutex<ScsiDevice>>,
+) -> Option<ScsiCommand> {
+    let buf: [u8; SCSI_CMD_BUF_SIZE] = (cdb[0..SCSI_CMD_BUF_SIZE])
+        .try_into()
+        .expect("incorrect length");
+    let command = cdb[0];
+    let len = scsi_cdb_length(&cdb);
+    if len < 0 {
+        return None;
+    }
 
-        let lba = scsi_cdb_lba(&cdb);
-        if lba < 0 {
-            return None;
-        }
+    let xfer = scsi_cdb_xfer(&cdb, dev);
+    if xfer < 0 {
+        return None;
+    }
 
-        Some(ScsiCommand {
-            buf,
-            command,
-            len: len as u32,
-            xfer: xfer as u32,
-            lba: lba as u64,
-            mode: scsi_cdb_xfer_mode(&cdb),
-        })
+    let lba = scsi_cdb_lba(&cdb);
+    if lba < 0 {
+        return None;
     }
+
+    Some(ScsiCommand {
+        buf,
+        command,
+        len: len as u32,
+        xfer: xfer as u32,
+        lba: lba as u64,
+        mode: scsi_cdb_xfer_mode(&cdb),
+    })
 }
 
 pub fn create_scsi_bus(bus_name: &str, scsi_cntlr: &Arc<Mutex<ScsiCntlr>>) -> Result<()> {
@@ -461,7 +485,7 @@ pub struct ScsiCommand {
     /// Logical Block Address.
     pub lba: u64,
     /// Transfer direction.
-    mode: ScsiXferMode,
+    pub mode: ScsiXferMode,
 }
 
 #[derive(Clone)]
@@ -472,7 +496,7 @@ pub struct ScsiRequest {
     _resid: u32,
     pub opstype: u32,
     pub virtioscsireq: Arc<Mutex<VirtioScsiRequest<VirtioScsiCmdReq, VirtioScsiCmdResp>>>,
-    dev: Arc<Mutex<ScsiDevice>>,
+    pub dev: Arc<Mutex<ScsiDevice>>,
 }
 
 impl ScsiRequest {
@@ -546,6 +570,9 @@ impl ScsiRequest {
             _ => SCSI_CDROM_DEFAULT_BLOCK_SIZE_SHIFT,
         };
         aiocb.offset = (self.cmd.lba << offset) as usize;
+        let backend = dev_lock.block_backend.clone();
+        let stats = dev_lock.stats.clone();
+        drop(dev_lock);
 
         for iov in self.virtioscsireq.lock().unwrap().iovec.iter() {
             let iovec = Iovec {
@@ -557,6 +584,19 @@ impl ScsiRequest {
             aiocb.nbytes += iov.iov_len;
         }
 
+        let acct_type = if self.cmd.command == SYNCHRONIZE_CACHE {
+            Some(BlockAcctType::Flush)
+        } else {
+            match self.cmd.mode {
+                ScsiXferMode::ScsiXferFromDev => Some(BlockAcctType::Read),
+                ScsiXferMode::ScsiXferToDev => Some(BlockAcctType::Write),
+                _ => None,
+            }
+        };
+        if let Some(acct_type) = acct_type {
+            aiocb.iocompletecb.acct = Some(block_acct_start(&stats, acct_type, aiocb.nbytes, 1));
+        }
+
         if self.cmd.command == SYNCHRONIZE_CACHE {
             aiocb.opcode = OpCode::Fdsync;
             aio.submit_request(aiocb)
@@ -567,12 +607,12 @@ impl ScsiRequest {
         match self.cmd.mode {
             ScsiXferMode::ScsiXferFromDev => {
                 aiocb.opcode = OpCode::Preadv;
-                aio.submit_request(aiocb)
+                submit_rw_request(aio, &backend, aiocb)
                     .with_context(|| "Failed to process scsi request for reading")?;
             }
             ScsiXferMode::ScsiXferToDev => {
                 aiocb.opcode = OpCode::Pwritev;
-                aio.submit_request(aiocb)
+                submit_rw_request(aio, &backend, aiocb)
                     .with_context(|| "Failed to process block request for writing")?;
             }
             _ => {
@@ -582,112 +622,27 @@ impl ScsiRequest {
         Ok(0)
     }
 
+    /// Take the pending unit attention condition of the device, which is reported to the
+    /// initiator instead of executing the command.
+    pub fn take_unit_attention(&self) -> Option<ScsiSense> {
+        scsi_take_unit_attention(&self.cmd, &self.dev)
+    }
+
     pub fn emulate_execute(
         &self,
         iocompletecb: ScsiCompleteCb,
         req_lun_id: u16,
         found_lun_id: u16,
     ) -> Result<()> {
-        debug!("scsi command is {:#x}", self.cmd.command);
-        let mut not_supported_flag = false;
-        let mut sense = None;
-
-        // Requested lun id is not equal to found device id means it may be a target request.
-        // REPORT LUNS is also a target request command.
-        let result = if req_lun_id != found_lun_id || self.cmd.command == REPORT_LUNS {
-            match self.cmd.command {
-                REPORT_LUNS => scsi_command_emulate_report_luns(&self.cmd, &self.dev),
-                INQUIRY => scsi_command_emulate_target_inquiry(req_lun_id, &self.cmd),
-                REQUEST_SENSE => {
-                    if req_lun_id != 0 {
-                        sense = Some(SCSI_SENSE_LUN_NOT_SUPPORTED);
-                    }
-                    // Scsi Device does not realize sense buffer now, so just return.
-                    Ok(Vec::new())
-                }
-                TEST_UNIT_READY => Ok(Vec::new()),
-                _ => {
-                    not_supported_flag = true;
-                    sense = Some(SCSI_SENSE_INVALID_OPCODE);
-                    Err(anyhow!("Invalid emulation target scsi command"))
-                }
-            }
-        } else {
-            // It's not a target request.
-            match self.cmd.command {
-                REQUEST_SENSE => {
-                    sense = Some(SCSI_SENSE_NO_SENSE);
-                    Ok(Vec::new())
-                }
-                TEST_UNIT_READY => {
-                    let dev_lock = self.dev.lock().unwrap();
-                    if dev_lock.disk_image.is_none() {
-                        Err(anyhow!("No scsi backend!"))
-                    } else {
-                        Ok(Vec::new())
-                    }
-                }
-                INQUIRY => scsi_command_emulate_inquiry(&self.cmd, &self.dev),
-                READ_CAPACITY_10 => scsi_command_emulate_read_capacity_10(&self.cmd, &self.dev),
-                MODE_SENSE | MODE_SENSE_10 => scsi_command_emulate_mode_sense(&self.cmd, &self.dev),
-                SERVICE_ACTION_IN_16 => {
-                    scsi_command_emulate_service_action_in_16(&self.cmd, &self.dev)
-                }
-                READ_DISC_INFORMATION => {
-                    scsi_command_emulate_read_disc_information(&self.cmd, &self.dev)
-                }
-                GET_EVENT_STATUS_NOTIFICATION => {
-                    scsi_command_emulate_get_event_status_notification(&self.cmd, &self.dev)
-                }
-                READ_TOC => scsi_command_emulate_read_toc(&self.cmd, &self.dev),
-                GET_CONFIGURATION => scsi_command_emulate_get_configuration(&self.cmd, &self.dev),
-                _ => {
-                    not_supported_flag = true;
-                    Err(anyhow!("Emulation scsi command is not supported now!"))
-                }
-            }
-        };
-
-        match result {
-            Ok(outbuf) => {
-                self.cmd_complete(
-                    &iocompletecb.mem_space,
-                    VIRTIO_SCSI_S_OK,
-                    GOOD,
-                    sense,
-                    &outbuf,
-                )?;
-            }
-            Err(ref e) => {
-                if not_supported_flag {
-                    info!(
-                        "emulation scsi command {:#x} is no supported",
-                        self.cmd.command
-                    );
-                    self.cmd_complete(
-                        &iocompletecb.mem_space,
-                        VIRTIO_SCSI_S_OK,
-                        CHECK_CONDITION,
-                        Some(SCSI_SENSE_INVALID_OPCODE),
-                        &Vec::new(),
-                    )?;
-                } else {
-                    error!(
-                        "Error in processing scsi command {:#x}, err is {:?}",
-                        self.cmd.command, e
-                    );
-                    self.cmd_complete(
-                        &iocompletecb.mem_space,
-                        VIRTIO_SCSI_S_OK,
-                        CHECK_CONDITION,
-                        Some(SCSI_SENSE_INVALID_FIELD),
-                        &Vec::new(),
-                    )?;
-                }
-            }
-        }
-
-        Ok(())
+        let (status, sense, outbuf) =
+            scsi_command_emulate(&self.cmd, &self.dev, req_lun_id, found_lun_id);
+        self.cmd_complete(
+            &iocompletecb.mem_space,
+            VIRTIO_SCSI_S_OK,
+            status,
+            sense,
+            &outbuf,
+        )
     }
 
     fn cmd_complete(
@@ -730,6 +685,100 @@ impl ScsiRequest {
     }
 }
 
+/// Take the pending unit attention condition of `dev` for the scsi command `cmd`.
+/// INQUIRY, REPORT LUNS and REQUEST SENSE don't report it.
+pub fn scsi_take_unit_attention(
+    cmd: &ScsiCommand,
+    dev: &Arc<Mutex<ScsiDevice>>,
+) -> Option<ScsiSense> {
+    match cmd.command {
+        INQUIRY | REPORT_LUNS | REQUEST_SENSE => None,
+        _ => dev.lock().unwrap().unit_attention.take(),
+    }
+}
+
+/// Emulate the scsi command `cmd` which doesn't access the image file. Return the scsi
+/// status, the sense and the data to be sent to the initiator.
+pub fn scsi_command_emulate(
+    cmd: &ScsiCommand,
+    dev: &Arc<Mutex<ScsiDevice>>,
+    req_lun_id: u16,
+    found_lun_id: u16,
+) -> (u8, Option<ScsiSense>, Vec<u8>) {
+    debug!("scsi command is {:#x}", cmd.command);
+    let mut not_supported_flag = false;
+    let mut sense = None;
+
+    // Requested lun id is not equal to found device id means it may be a target request.
+    // REPORT LUNS is also a target request command.
+    let result = if req_lun_id != found_lun_id || cmd.command == REPORT_LUNS {
+        match cmd.command {
+            REPORT_LUNS => scsi_command_emulate_report_luns(cmd, dev),
+            INQUIRY => scsi_command_emulate_target_inquiry(req_lun_id, cmd),
+            REQUEST_SENSE => {
+                if req_lun_id != 0 {
+                    sense = Some(SCSI_SENSE_LUN_NOT_SUPPORTED);
+                }
+                // Scsi Device does not realize sense buffer now, so just return.
+                Ok(Vec::new())
+            }
+            TEST_UNIT_READY => Ok(Vec::new()),
+            _ => {
+                not_supported_flag = true;
+                sense = Some(SCSI_SENSE_INVALID_OPCODE);
+                Err(anyhow!("Invalid emulation target scsi command"))
+            }
+        }
+    } else {
+        // It's not a target request.
+        match cmd.command {
+            REQUEST_SENSE => {
+                let unit_attention = dev.lock().unwrap().unit_attention.take();
+                sense = Some(unit_attention.unwrap_or(SCSI_SENSE_NO_SENSE));
+                Ok(Vec::new())
+            }
+            TEST_UNIT_READY => {
+                let dev_lock = dev.lock().unwrap();
+                if dev_lock.disk_image.is_none() {
+                    Err(anyhow!("No scsi backend!"))
+                } else {
+                    Ok(Vec::new())
+                }
+            }
+            INQUIRY => scsi_command_emulate_inquiry(cmd, dev),
+            READ_CAPACITY_10 => scsi_command_emulate_read_capacity_10(cmd, dev),
+            MODE_SENSE | MODE_SENSE_10 => scsi_command_emulate_mode_sense(cmd, dev),
+            SERVICE_ACTION_IN_16 => scsi_command_emulate_service_action_in_16(cmd, dev),
+            READ_DISC_INFORMATION => scsi_command_emulate_read_disc_information(cmd, dev),
+            GET_EVENT_STATUS_NOTIFICATION => {
+                scsi_command_emulate_get_event_status_notification(cmd, dev)
+            }
+            READ_TOC => scsi_command_emulate_read_toc(cmd, dev),
+            GET_CONFIGURATION => scsi_command_emulate_get_configuration(cmd, dev),
+            _ => {
+                not_supported_flag = true;
+                Err(anyhow!("Emulation scsi command is not supported now!"))
+            }
+        }
+    };
+
+    match result {
+        Ok(outbuf) => (GOOD, sense, outbuf),
+        Err(ref e) => {
+            if not_supported_flag {
+                info!("emulation scsi command {:#x} is no supported", cmd.command);
+                (CHECK_CONDITION, Some(SCSI_SENSE_INVALID_OPCODE), Vec::new())
+            } else {
+                error!(
+                    "Error in processing scsi command {:#x}, err is {:?}",
+                    cmd.command, e
+                );
+                (CHECK_CONDITION, Some(SCSI_SENSE_INVALID_FIELD), Vec::new())
+            }
+        }
+    }
+}
+
 fn write_buf_mem(buf: &[u8], max: u64, hva: u64) -> Result<()> {
     let mut slice = unsafe {
         std::slice::from_raw_parts_mut(hva as *mut u8, cmp::min(buf.len(), max as usize))
@@ -746,7 +795,7 @@ pub const EMULATE_SCSI_OPS: u32 = 0;
 // Scsi Commands which will do something(eg: read and write) to the backend.
 pub const NON_EMULATE_SCSI_OPS: u32 = 1;
 
-fn scsi_operation_type(op: u8) -> u32 {
+pub fn scsi_operation_type(op: u8) -> u32 {
     match op {
         READ_6 | READ_10 | READ_12 | READ_16 | WRITE_6 | WRITE_10 | WRITE_12 | WRITE_16
         | WRITE_VERIFY_10 | WRITE_VERIFY_12 | WRITE_VERIFY_16 | SYNCHRONIZE_CACHE => {
@@ -812,8 +861,9 @@ fn scsi_cdb_xfer(cdb: &[u8; VIRTIO_SCSI_CDB_DEFAULT_SIZE], dev: Arc<Mutex<ScsiDe
         WRITE_6 | READ_6 => {
             // length 0 means 256 blocks.
             if xfer == 0 {
-                xfer = 256 * block_size;
+                xfer = 256;
             }
+            xfer *= block_size;
         }
         WRITE_10 | WRITE_12 | WRITE_16 | READ_10 | READ_12 | READ_16 => {
             xfer *= block_size;
@@ -1098,9 +1148,9 @@ fn scsi_command_emulate_inquiry(
     let dev_lock = dev.lock().unwrap();
 
     outbuf[0] = (dev_lock.scsi_type & 0x1f) as u8;
-    outbuf[1] = match dev_lock.state.features & SCSI_DISK_F_REMOVABLE {
-        1 => 0x80,
-        _ => 0,
+    outbuf[1] = match dev_lock.state.features & (1 << SCSI_DISK_F_REMOVABLE) {
+        0 => 0,
+        _ => 0x80,
     };
 
     let product_bytes = dev_lock.state.product.as_bytes();
@@ -1742,3 +1792,98 @@ fn cdrom_read_formatted_toc(nb_sectors: u32, msf: u8, track_number: u8) -> Resul
 
     Ok(outbuf)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ScsiCntlr::{
+        ScsiCntlr, VIRTIO_SCSI_EVT_RESET_REMOVED, VIRTIO_SCSI_EVT_RESET_RESCAN,
+    };
+    use machine_manager::config::{ScsiCntlrConfig, ScsiDevConfig};
+
+    fn attach_lun(bus: &Arc<Mutex<ScsiBus>>, target: u8, lun: u16) -> Arc<Mutex<ScsiDevice>> {
+        let config = ScsiDevConfig {
+            id: format!("scsi-disk-{}-{}", target, lun),
+            target,
+            lun,
+            ..Default::default()
+        };
+        let dev = Arc::new(Mutex::new(ScsiDevice::new(
+            config,
+            SCSI_TYPE_DISK,
+            Arc::new(Mutex::new(HashMap::new())),
+        )));
+        bus.lock()
+            .unwrap()
+            .devices
+            .insert((target, lun), dev.clone());
+        dev
+    }
+
+    fn scsi_cmd(opcode: u8, dev: &Arc<Mutex<ScsiDevice>>) -> ScsiCommand {
+        let mut cdb = [0_u8; VIRTIO_SCSI_CDB_DEFAULT_SIZE];
+        cdb[0] = opcode;
+        scsi_parse_cdb(cdb, dev.clone()).unwrap()
+    }
+
+    fn is_luns_changed(sense: Option<ScsiSense>) -> bool {
+        match sense {
+            Some(s) => {
+                (s.key, s.asc, s.ascq)
+                    == (
+                        SCSI_SENSE_REPORTED_LUNS_CHANGED.key,
+                        SCSI_SENSE_REPORTED_LUNS_CHANGED.asc,
+                        SCSI_SENSE_REPORTED_LUNS_CHANGED.ascq,
+                    )
+            }
+            None => false,
+        }
+    }
+
+    #[test]
+    fn test_reported_luns_changed() {
+        let cntlr = Arc::new(Mutex::new(ScsiCntlr::new(ScsiCntlrConfig::default())));
+        create_scsi_bus("scsi0.0", &cntlr).unwrap();
+        let bus = cntlr.lock().unwrap().bus.clone().unwrap();
+        let lun0 = attach_lun(&bus, 0, 0);
+        let lun1 = attach_lun(&bus, 0, 1);
+        let other = attach_lun(&bus, 1, 0);
+
+        // Hotplug sets the unit attention to all luns of the target.
+        let lun2 = attach_lun(&bus, 0, 2);
+        cntlr
+            .lock()
+            .unwrap()
+            .lun_changed(0, 2, VIRTIO_SCSI_EVT_RESET_RESCAN)
+            .unwrap();
+        for dev in [&lun0, &lun1, &lun2] {
+            assert!(is_luns_changed(dev.lock().unwrap().unit_attention));
+        }
+        assert!(other.lock().unwrap().unit_attention.is_none());
+
+        // INQUIRY doesn't report it, other commands report and clear it.
+        assert!(scsi_take_unit_attention(&scsi_cmd(INQUIRY, &lun0), &lun0).is_none());
+        let cmd = scsi_cmd(TEST_UNIT_READY, &lun0);
+        assert!(is_luns_changed(scsi_take_unit_attention(&cmd, &lun0)));
+        assert!(scsi_take_unit_attention(&cmd, &lun0).is_none());
+
+        // REQUEST SENSE reports and clears it.
+        let cmd = scsi_cmd(REQUEST_SENSE, &lun1);
+        assert!(scsi_take_unit_attention(&cmd, &lun1).is_none());
+        let (status, sense, _) = scsi_command_emulate(&cmd, &lun1, 1, 1);
+        assert_eq!(status, GOOD);
+        assert!(is_luns_changed(sense));
+        assert!(lun1.lock().unwrap().unit_attention.is_none());
+
+        // Unplug sets it to the remaining luns of the target.
+        bus.lock().unwrap().devices.remove(&(0, 2));
+        cntlr
+            .lock()
+            .unwrap()
+            .lun_changed(0, 2, VIRTIO_SCSI_EVT_RESET_REMOVED)
+            .unwrap();
+        assert!(is_luns_changed(lun0.lock().unwrap().unit_attention));
+        assert!(is_luns_changed(lun1.lock().unwrap().unit_attention));
+        assert!(other.lock().unwrap().unit_attention.is_none());
+    }
+}
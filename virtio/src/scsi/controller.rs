@@ -11,7 +11,7 @@
 // See the Mulan PSL v2 for more details.
 
 use std::cmp;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, VecDeque};
 use std::io::Write;
 use std::mem::size_of;
 use std::os::unix::io::{AsRawFd, RawFd};
@@ -22,9 +22,10 @@ use std::sync::{Arc, Mutex};
 use anyhow::{anyhow, bail, Context, Result};
 
 use super::super::{
-    report_virtio_error, Element, Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType,
-    VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_VERSION_1, VIRTIO_SCSI_F_CHANGE,
-    VIRTIO_SCSI_F_HOTPLUG, VIRTIO_TYPE_SCSI,
+    handle_block_io_error, report_virtio_error, virtio_has_feature, Element, Queue, VirtioDevice,
+    VirtioInterrupt, VirtioInterruptType, VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC,
+    VIRTIO_F_RING_PACKED, VIRTIO_F_VERSION_1, VIRTIO_SCSI_F_CHANGE, VIRTIO_SCSI_F_HOTPLUG,
+    VIRTIO_TYPE_SCSI,
 };
 use crate::ScsiBus::{
     virtio_scsi_get_lun, ScsiBus, ScsiRequest, ScsiSense, CHECK_CONDITION, EMULATE_SCSI_OPS, GOOD,
@@ -32,13 +33,18 @@ use crate::ScsiBus::{
 };
 use crate::VirtioError;
 use address_space::{AddressSpace, GuestAddress};
+use block_backend::stats::BlockAcctCookie;
 use log::{debug, error, info};
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
 use machine_manager::{
     config::{ScsiCntlrConfig, VIRTIO_SCSI_MAX_LUN, VIRTIO_SCSI_MAX_TARGET},
     event_loop::EventLoop,
+    machine::{register_vm_resume_notifier, unregister_vm_resume_notifier},
+    qmp::qmp_schema::{BlockErrorAction, IoOperationType},
 };
-use util::aio::{Aio, AioCb, AioEngine, Iovec, OpCode};
+use migration::{DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager, StateTransfer};
+use migration_derive::{ByteCode, Desc};
+use util::aio::{Aio, AioCb, AioEngine, Iovec, OpCode, WriteZeroesState};
 use util::byte_code::ByteCode;
 use util::loop_context::{
     read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
@@ -81,6 +87,19 @@ pub const VIRTIO_SCSI_T_TMF_LOGICAL_UNIT_RESET: u32 = 5;
 pub const VIRTIO_SCSI_T_TMF_QUERY_TASK: u32 = 6;
 pub const VIRTIO_SCSI_T_TMF_QUERY_TASK_SET: u32 = 7;
 
+/// Event types of the event virtqueue.
+pub const VIRTIO_SCSI_T_NO_EVENT: u32 = 0;
+pub const VIRTIO_SCSI_T_TRANSPORT_RESET: u32 = 1;
+pub const VIRTIO_SCSI_T_EVENTS_MISSED: u32 = 0x8000_0000;
+
+/// Reasons of the transport reset event.
+pub const VIRTIO_SCSI_EVT_RESET_HARD: u32 = 0;
+pub const VIRTIO_SCSI_EVT_RESET_RESCAN: u32 = 1;
+pub const VIRTIO_SCSI_EVT_RESET_REMOVED: u32 = 2;
+
+/// Max number of events waiting for the event buffers from guest.
+const SCSI_MAX_PENDING_EVENTS: usize = 64;
+
 /// Response codes.
 pub const VIRTIO_SCSI_S_OK: u8 = 0;
 pub const VIRTIO_SCSI_S_OVERRUN: u8 = 1;
@@ -124,7 +143,9 @@ struct VirtioScsiConfig {
 impl ByteCode for VirtioScsiConfig {}
 
 /// State of virtio scsi controller.
-#[derive(Clone, Copy, Default)]
+#[repr(C)]
+#[derive(Clone, Copy, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
 pub struct ScsiCntlrState {
     /// Bitmask of features supported by the backend.
     device_features: u64,
@@ -132,6 +153,16 @@ pub struct ScsiCntlrState {
     driver_features: u64,
     /// Config space of the virtio scsi controller.
     config_space: VirtioScsiConfig,
+    /// Number of valid entries in `inflight`.
+    inflight_num: u32,
+    /// Requests in flight when the state is saved, see `inflight_key`.
+    inflight: [u32; 1024],
+}
+
+/// Key of the in-flight request `desc_index` in virtqueue `queue_index`. `desc_index` is the head
+/// descriptor index for split vring and the buffer id for packed vring, see `Element::index`.
+fn inflight_key(queue_index: u16, desc_index: u16) -> u32 {
+    (queue_index as u32) << 16 | desc_index as u32
 }
 
 /// Virtio Scsi Controller device structure.
@@ -146,6 +177,12 @@ pub struct ScsiCntlr {
     deactivate_evts: Vec<RawFd>,
     /// Device is broken or not.
     broken: Arc<AtomicBool>,
+    /// Requests which are popped from cmd queues but not completed yet.
+    inflight: Arc<Mutex<BTreeSet<u32>>>,
+    /// Handler of the event queue, only exists when the device is activated.
+    event_handler: Option<Arc<Mutex<ScsiEventHandler>>>,
+    /// Eventfds to retry the failed requests of the cmd queues.
+    retry_evts: Vec<Arc<EventFd>>,
 }
 
 impl ScsiCntlr {
@@ -156,7 +193,35 @@ impl ScsiCntlr {
             bus: None,
             deactivate_evts: Vec::new(),
             broken: Arc::new(AtomicBool::new(false)),
+            inflight: Arc::new(Mutex::new(BTreeSet::new())),
+            event_handler: None,
+            retry_evts: Vec::new(),
+        }
+    }
+
+    /// Notify the guest that a lun is attached to or detached from the bus.
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - Target of the lun.
+    /// * `lun` - Lun number.
+    /// * `reason` - VIRTIO_SCSI_EVT_RESET_RESCAN or VIRTIO_SCSI_EVT_RESET_REMOVED.
+    pub fn lun_changed(&self, target: u8, lun: u16, reason: u32) -> Result<()> {
+        if let Some(bus) = &self.bus {
+            ScsiBus::report_luns_changed(bus, target);
+        }
+        if !virtio_has_feature(self.state.driver_features, VIRTIO_SCSI_F_HOTPLUG) {
+            return Ok(());
+        }
+        if let Some(handler) = &self.event_handler {
+            let event = VirtioScsiEvent {
+                event: VIRTIO_SCSI_T_TRANSPORT_RESET,
+                lun: [1, target, 0x40 | (lun >> 8) as u8, lun as u8, 0, 0, 0, 0],
+                reason,
+            };
+            handler.lock().unwrap().push_event(event)?;
         }
+        Ok(())
     }
 }
 
@@ -189,12 +254,14 @@ impl VirtioDevice for ScsiCntlr {
             | (1_u64 << VIRTIO_SCSI_F_HOTPLUG)
             | (1_u64 << VIRTIO_SCSI_F_CHANGE)
             | (1_u64 << VIRTIO_F_RING_EVENT_IDX)
-            | (1_u64 << VIRTIO_F_RING_INDIRECT_DESC);
+            | (1_u64 << VIRTIO_F_RING_INDIRECT_DESC)
+            | (1_u64 << VIRTIO_F_RING_PACKED);
 
         Ok(())
     }
 
     fn unrealize(&mut self) -> Result<()> {
+        MigrationManager::unregister_device_instance(ScsiCntlrState::descriptor(), &self.config.id);
         Ok(())
     }
 
@@ -295,37 +362,54 @@ impl VirtioDevice for ScsiCntlr {
 
         let event_queue = queues[1].clone();
         let event_queue_evt = queue_evts.remove(0);
-        let event_handler = ScsiEventHandler {
-            _queue: event_queue,
+        let event_handler = Arc::new(Mutex::new(ScsiEventHandler {
+            queue: event_queue,
             queue_evt: event_queue_evt,
-            _mem_space: mem_space.clone(),
-            _interrupt_cb: interrupt_cb.clone(),
-            _driver_features: self.state.driver_features,
+            mem_space: mem_space.clone(),
+            interrupt_cb: interrupt_cb.clone(),
+            driver_features: self.state.driver_features,
             device_broken: self.broken.clone(),
-        };
-        let notifiers =
-            EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(event_handler)));
+            pending: VecDeque::new(),
+            events_dropped: false,
+        }));
+        let notifiers = EventNotifierHelper::internal_notifiers(event_handler.clone());
         register_event_helper(
             notifiers,
             self.config.iothread.as_ref(),
             &mut self.deactivate_evts,
         )?;
+        self.event_handler = Some(event_handler);
+
+        // Requests in flight on the source side are submitted again after migration.
+        let restored: Vec<u32> = self.state.inflight[..self.state.inflight_num as usize].to_vec();
+        self.state.inflight_num = 0;
+        self.inflight.lock().unwrap().clear();
 
         let queues_num = queues.len();
-        for cmd_queue in queues.iter().take(queues_num).skip(2) {
+        for (index, cmd_queue) in queues.iter().enumerate().take(queues_num).skip(2) {
             if let Some(bus) = &self.bus {
+                let retry_evt = Arc::new(EventFd::new(libc::EFD_NONBLOCK)?);
                 let mut cmd_handler = ScsiCmdHandler {
                     aio: None,
                     scsibus: bus.clone(),
                     queue: cmd_queue.clone(),
+                    queue_index: index as u16,
                     queue_evt: queue_evts.remove(0),
                     mem_space: mem_space.clone(),
                     interrupt_cb: interrupt_cb.clone(),
                     driver_features: self.state.driver_features,
                     device_broken: self.broken.clone(),
+                    inflight: self.inflight.clone(),
+                    failed_reqs: Arc::new(Mutex::new(Vec::new())),
+                    retry_evt: retry_evt.clone(),
                 };
 
                 cmd_handler.aio = Some(cmd_handler.build_aio()?);
+                for key in restored.iter().filter(|key| *key >> 16 == index as u32) {
+                    cmd_handler
+                        .requeue_request(*key as u16)
+                        .with_context(|| "Failed to requeue in-flight scsi request")?;
+                }
 
                 let notifiers =
                     EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(cmd_handler)));
@@ -334,20 +418,66 @@ impl VirtioDevice for ScsiCntlr {
                     self.config.iothread.as_ref(),
                     &mut self.deactivate_evts,
                 )?;
+                self.retry_evts.push(retry_evt);
             } else {
                 bail!("Scsi controller has no bus!");
             }
         }
+        let retry_evts = self.retry_evts.clone();
+        register_vm_resume_notifier(
+            &self.config.id,
+            Arc::new(move || {
+                for retry_evt in retry_evts.iter() {
+                    if let Err(e) = retry_evt.write(1) {
+                        error!("Failed to write retry event of scsi: {:?}", e);
+                    }
+                }
+            }),
+        );
         self.broken.store(false, Ordering::SeqCst);
 
         Ok(())
     }
 
     fn deactivate(&mut self) -> Result<()> {
+        self.event_handler = None;
+        unregister_vm_resume_notifier(&self.config.id);
+        self.retry_evts.clear();
         unregister_event_helper(self.config.iothread.as_ref(), &mut self.deactivate_evts)
     }
 }
 
+impl StateTransfer for ScsiCntlr {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        let mut state = self.state;
+        let inflight = self.inflight.lock().unwrap();
+        if inflight.len() > state.inflight.len() {
+            bail!(
+                "Too many in-flight scsi requests {} to save, max is {}",
+                inflight.len(),
+                state.inflight.len()
+            );
+        }
+        for (index, key) in inflight.iter().enumerate() {
+            state.inflight[index] = *key;
+        }
+        state.inflight_num = inflight.len() as u32;
+        Ok(state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        self.state = *ScsiCntlrState::from_bytes(state)
+            .ok_or_else(|| anyhow!(migration::error::MigrationError::FromBytesError("SCSI")))?;
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&ScsiCntlrState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+impl MigrationHook for ScsiCntlr {}
+
 fn build_event_notifier(fd: RawFd, handler: Rc<NotifierCallback>) -> EventNotifier {
     EventNotifier::new(
         NotifierOperation::AddShared,
@@ -394,6 +524,17 @@ pub struct VirtioScsiCtrlAnResp {
 
 impl ByteCode for VirtioScsiCtrlAnResp {}
 
+/// Event reported to the guest through the event queue.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct VirtioScsiEvent {
+    pub event: u32,
+    pub lun: [u8; 8],
+    pub reason: u32,
+}
+
+impl ByteCode for VirtioScsiEvent {}
+
 #[repr(C, packed)]
 #[derive(Default, Clone, Copy)]
 pub struct VirtioScsiCmdReq {
@@ -472,6 +613,8 @@ pub struct VirtioScsiRequest<T: Clone + ByteCode, U: Clone + ByteCode> {
     resp_addr: GuestAddress,
     pub req: T,
     pub resp: U,
+    /// In-flight requests set of the controller and index of the virtqueue, only for cmd request.
+    inflight: Option<(Arc<Mutex<BTreeSet<u32>>>, u16)>,
 }
 
 /// T: request; U:response.
@@ -530,6 +673,7 @@ impl<T: Clone + ByteCode, U: Clone + ByteCode> VirtioScsiRequest<T, U> {
             resp_addr: in_iov_elem.addr,
             req: scsi_req,
             resp: scsi_resp,
+            inflight: None,
         };
 
         let mut out_len: u32 = 0;
@@ -582,7 +726,23 @@ impl<T: Clone + ByteCode, U: Clone + ByteCode> VirtioScsiRequest<T, U> {
         Ok(request)
     }
 
+    /// Record the request as in flight until it is completed.
+    fn track_inflight(&mut self, inflight: &Arc<Mutex<BTreeSet<u32>>>, queue_index: u16) {
+        inflight
+            .lock()
+            .unwrap()
+            .insert(inflight_key(queue_index, self.desc_index));
+        self.inflight = Some((inflight.clone(), queue_index));
+    }
+
     pub fn complete(&self, mem_space: &Arc<AddressSpace>) -> Result<()> {
+        if let Some((inflight, queue_index)) = &self.inflight {
+            inflight
+                .lock()
+                .unwrap()
+                .remove(&inflight_key(*queue_index, self.desc_index));
+        }
+
         if let Err(ref e) = mem_space.write_object(&self.resp, self.resp_addr) {
             bail!("Failed to write the scsi response {:?}", e);
         }
@@ -736,17 +896,21 @@ impl EventNotifierHelper for ScsiCtrlHandler {
 
 pub struct ScsiEventHandler {
     /// The Event virtqueue.
-    _queue: Arc<Mutex<Queue>>,
+    queue: Arc<Mutex<Queue>>,
     /// EventFd for the Event virtqueue.
     queue_evt: Arc<EventFd>,
     /// The address space to which the scsi HBA belongs.
-    _mem_space: Arc<AddressSpace>,
+    mem_space: Arc<AddressSpace>,
     /// The interrupt callback function.
-    _interrupt_cb: Arc<VirtioInterrupt>,
+    interrupt_cb: Arc<VirtioInterrupt>,
     /// Bit mask of features negotiated by the backend and the frontend.
-    _driver_features: u64,
+    driver_features: u64,
     /// Device is broken or not.
     device_broken: Arc<AtomicBool>,
+    /// Events waiting for the event buffers from guest.
+    pending: VecDeque<VirtioScsiEvent>,
+    /// Whether some events are dropped because too many events are pending.
+    events_dropped: bool,
 }
 
 impl EventNotifierHelper for ScsiEventHandler {
@@ -774,6 +938,76 @@ impl EventNotifierHelper for ScsiEventHandler {
 
 impl ScsiEventHandler {
     fn handle_event(&mut self) -> Result<()> {
+        let result = self.flush_events();
+        if result.is_err() {
+            report_virtio_error(
+                self.interrupt_cb.clone(),
+                self.driver_features,
+                &self.device_broken,
+            );
+        }
+
+        result
+    }
+
+    /// Report the event to guest, it is kept pending if no event buffer is available.
+    fn push_event(&mut self, event: VirtioScsiEvent) -> Result<()> {
+        if self.device_broken.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        if self.pending.len() >= SCSI_MAX_PENDING_EVENTS {
+            self.events_dropped = true;
+        } else {
+            self.pending.push_back(event);
+        }
+        self.handle_event()
+    }
+
+    fn flush_events(&mut self) -> Result<()> {
+        if !self.queue.lock().unwrap().is_enabled() {
+            return Ok(());
+        }
+
+        while let Some(mut event) = self.pending.front().copied() {
+            let mut queue = self.queue.lock().unwrap();
+            let elem = queue
+                .vring
+                .pop_avail(&self.mem_space, self.driver_features)?;
+            if elem.desc_num == 0 {
+                break;
+            }
+
+            let in_iov = match elem.in_iovec.first() {
+                Some(iov) if iov.len as usize >= size_of::<VirtioScsiEvent>() => iov,
+                _ => bail!("Invalid virtio scsi event buffer"),
+            };
+            if self.events_dropped {
+                event.event |= VIRTIO_SCSI_T_EVENTS_MISSED;
+                self.events_dropped = false;
+            }
+            self.mem_space
+                .write_object(&event, in_iov.addr)
+                .with_context(|| "Failed to write the scsi event")?;
+            queue
+                .vring
+                .add_used(
+                    &self.mem_space,
+                    elem.index,
+                    size_of::<VirtioScsiEvent>() as u32,
+                )
+                .with_context(|| {
+                    format!("Failed to add used ring(scsi event), index {}", elem.index)
+                })?;
+            if queue
+                .vring
+                .should_notify(&self.mem_space, self.driver_features)
+            {
+                (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue), false)
+                    .with_context(|| "Failed to trigger interrupt(scsi event)")?;
+            }
+            self.pending.pop_front();
+        }
+
         Ok(())
     }
 }
@@ -783,6 +1017,8 @@ pub struct ScsiCmdHandler {
     scsibus: Arc<Mutex<ScsiBus>>,
     /// The Cmd virtqueue.
     queue: Arc<Mutex<Queue>>,
+    /// Index of the Cmd virtqueue.
+    queue_index: u16,
     /// EventFd for the Cmd virtqueue.
     queue_evt: Arc<EventFd>,
     /// The address space to which the scsi HBA belongs.
@@ -795,6 +1031,12 @@ pub struct ScsiCmdHandler {
     aio: Option<Box<Aio<ScsiCompleteCb>>>,
     /// Device is broken or not.
     device_broken: Arc<AtomicBool>,
+    /// Requests which are popped from cmd queues but not completed yet.
+    inflight: Arc<Mutex<BTreeSet<u32>>>,
+    /// Requests stopped by the error policy, they are retried when the VM is resumed.
+    failed_reqs: Arc<Mutex<Vec<ScsiRequest>>>,
+    /// Eventfd to retry the failed requests after the VM is resumed.
+    retry_evt: Arc<EventFd>,
 }
 
 impl EventNotifierHelper for ScsiCmdHandler {
@@ -817,6 +1059,22 @@ impl EventNotifierHelper for ScsiCmdHandler {
         });
         notifiers.push(build_event_notifier(h_locked.queue_evt.as_raw_fd(), h));
 
+        // Register event notifier for retry_evt.
+        let h_clone = handler.clone();
+        let h: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            let mut h_lock = h_clone.lock().unwrap();
+            if h_lock.device_broken.load(Ordering::SeqCst) {
+                return None;
+            }
+            h_lock
+                .retry_failed_requests()
+                .unwrap_or_else(|e| error!("Failed to retry scsi requests, err is {}", e));
+
+            None
+        });
+        notifiers.push(build_event_notifier(h_locked.retry_evt.as_raw_fd(), h));
+
         // Register event notifier for aio.
         if let Some(ref aio) = h_locked.aio {
             let h_clone = handler.clone();
@@ -863,6 +1121,11 @@ impl ScsiCmdHandler {
         if !self.queue.lock().unwrap().is_enabled() {
             return Ok(());
         }
+        // Keep the order of the requests, the new ones are handled after the
+        // stopped ones are retried.
+        if !self.failed_reqs.lock().unwrap().is_empty() {
+            return Ok(());
+        }
 
         loop {
             let mut queue = self.queue.lock().unwrap();
@@ -874,104 +1137,191 @@ impl ScsiCmdHandler {
             }
             drop(queue);
 
-            let mut cmd = VirtioScsiRequest::<VirtioScsiCmdReq, VirtioScsiCmdResp>::new(
-                &self.mem_space,
-                self.queue.clone(),
-                self.interrupt_cb.clone(),
-                self.driver_features,
-                &elem,
-            )?;
+            self.handle_cmd_element(&elem)?;
+        }
 
-            let lun = cmd.req.lun;
-            let scsibus = self.scsibus.lock().unwrap();
-            let req_lun_id = virtio_scsi_get_lun(lun);
+        Ok(())
+    }
 
-            let scsidevice = if let Some(scsi_device) = scsibus.get_device(lun[1], req_lun_id) {
-                scsi_device
-            } else {
-                // No such target. Response VIRTIO_SCSI_S_BAD_TARGET to guest scsi drivers.
-                // It's not an error!
-                cmd.resp.response = VIRTIO_SCSI_S_BAD_TARGET;
-                cmd.complete(&self.mem_space)?;
-                debug!(
-                    "no such scsi device target {}, lun {}",
-                    lun[1],
-                    virtio_scsi_get_lun(lun)
-                );
-                continue;
-            };
-            drop(scsibus);
+    /// Submit the scsi request which was in flight when the device state was saved.
+    fn requeue_request(&mut self, desc_index: u16) -> Result<()> {
+        let elem = self
+            .queue
+            .lock()
+            .unwrap()
+            .vring
+            .get_inflight_element(&self.mem_space, desc_index)?;
+        self.handle_cmd_element(&elem)
+    }
 
-            let cmd_h = Arc::new(Mutex::new(cmd));
-            let scsi_req = if let Ok(req) =
-                ScsiRequest::new(cmd_h.clone(), self.scsibus.clone(), scsidevice.clone())
-            {
-                req
-            } else {
-                // Wrong scsi cdb. Response CHECK_CONDITION / SCSI_SENSE_INVALID_OPCODE to guest scsi drivers.
+    fn handle_cmd_element(&mut self, elem: &Element) -> Result<()> {
+        let mut cmd = VirtioScsiRequest::<VirtioScsiCmdReq, VirtioScsiCmdResp>::new(
+            &self.mem_space,
+            self.queue.clone(),
+            self.interrupt_cb.clone(),
+            self.driver_features,
+            elem,
+        )?;
+        cmd.track_inflight(&self.inflight, self.queue_index);
+
+        let lun = cmd.req.lun;
+        let scsibus = self.scsibus.lock().unwrap();
+        let req_lun_id = virtio_scsi_get_lun(lun);
+
+        let scsidevice = if let Some(scsi_device) = scsibus.get_device(lun[1], req_lun_id) {
+            scsi_device
+        } else {
+            // No such target. Response VIRTIO_SCSI_S_BAD_TARGET to guest scsi drivers.
+            // It's not an error!
+            cmd.resp.response = VIRTIO_SCSI_S_BAD_TARGET;
+            cmd.complete(&self.mem_space)?;
+            debug!(
+                "no such scsi device target {}, lun {}",
+                lun[1],
+                virtio_scsi_get_lun(lun)
+            );
+            return Ok(());
+        };
+        drop(scsibus);
+
+        let cmd_h = Arc::new(Mutex::new(cmd));
+        let scsi_req = if let Ok(req) =
+            ScsiRequest::new(cmd_h.clone(), self.scsibus.clone(), scsidevice.clone())
+        {
+            req
+        } else {
+            // Wrong scsi cdb. Response CHECK_CONDITION / SCSI_SENSE_INVALID_OPCODE to guest scsi drivers.
+            let mut cmd_lock = cmd_h.lock().unwrap();
+            cmd_lock.resp.set_scsi_sense(SCSI_SENSE_INVALID_OPCODE);
+            cmd_lock.resp.status = CHECK_CONDITION;
+            cmd_lock.complete(&self.mem_space)?;
+            drop(cmd_lock);
+
+            error!("Failed to create scsi request");
+            return Ok(());
+        };
+
+        // The pending unit attention condition of the lun is reported instead of executing
+        // the command, target requests are not affected.
+        if req_lun_id == scsidevice.lock().unwrap().config.lun {
+            if let Some(sense) = scsi_req.take_unit_attention() {
                 let mut cmd_lock = cmd_h.lock().unwrap();
-                cmd_lock.resp.set_scsi_sense(SCSI_SENSE_INVALID_OPCODE);
+                cmd_lock.resp.set_scsi_sense(sense);
                 cmd_lock.resp.status = CHECK_CONDITION;
                 cmd_lock.complete(&self.mem_space)?;
-                drop(cmd_lock);
+                return Ok(());
+            }
+        }
 
-                error!("Failed to create scsi request");
-                continue;
-            };
+        let scsi_device_lock = scsidevice.lock().unwrap();
+        if scsi_req.opstype == EMULATE_SCSI_OPS {
+            let lun = scsi_device_lock.config.lun;
+            drop(scsi_device_lock);
+            let scsicompletecb = ScsiCompleteCb::new(
+                self.mem_space.clone(),
+                Arc::new(Mutex::new(scsi_req.clone())),
+                self.failed_reqs.clone(),
+            );
+            // If found device's lun id is not equal to request lun id, this request is a target request.
+            scsi_req.emulate_execute(scsicompletecb, req_lun_id, lun)?;
+        } else {
+            drop(scsi_device_lock);
+            self.submit_request(scsi_req)?;
+        }
 
-            let scsi_device_lock = scsidevice.lock().unwrap();
-            if scsi_req.opstype == EMULATE_SCSI_OPS {
-                let lun = scsi_device_lock.config.lun;
-                drop(scsi_device_lock);
-                let scsicompletecb = ScsiCompleteCb::new(
-                    self.mem_space.clone(),
-                    Arc::new(Mutex::new(scsi_req.clone())),
-                );
-                // If found device's lun id is not equal to request lun id, this request is a target request.
-                scsi_req.emulate_execute(scsicompletecb, req_lun_id, lun)?;
-            } else {
-                let direct = scsi_device_lock.config.direct;
-                let disk_img = scsi_device_lock.disk_image.as_ref().unwrap().clone();
-                let req_align = scsi_device_lock.req_align;
-                let buf_align = scsi_device_lock.buf_align;
-                drop(scsi_device_lock);
-
-                let scsicompletecb = ScsiCompleteCb::new(
-                    self.mem_space.clone(),
-                    Arc::new(Mutex::new(scsi_req.clone())),
-                );
-                if let Some(ref mut aio) = self.aio {
-                    let aiocb = AioCb {
-                        direct,
-                        req_align,
-                        buf_align,
-                        file_fd: disk_img.as_raw_fd(),
-                        opcode: OpCode::Noop,
-                        iovec: Vec::new(),
-                        offset: 0,
-                        nbytes: 0,
-                        user_data: 0,
-                        iocompletecb: scsicompletecb,
-                    };
-                    scsi_req.execute(aio, aiocb)?;
-                    aio.flush_request()?;
-                }
-            }
+        Ok(())
+    }
+
+    fn submit_request(&mut self, scsi_req: ScsiRequest) -> Result<()> {
+        let scsi_device_lock = scsi_req.dev.lock().unwrap();
+        let direct = scsi_device_lock.config.direct;
+        let disk_img = scsi_device_lock.disk_image.as_ref().unwrap().clone();
+        let req_align = scsi_device_lock.req_align;
+        let buf_align = scsi_device_lock.buf_align;
+        drop(scsi_device_lock);
+
+        let scsicompletecb = ScsiCompleteCb::new(
+            self.mem_space.clone(),
+            Arc::new(Mutex::new(scsi_req.clone())),
+            self.failed_reqs.clone(),
+        );
+        if let Some(ref mut aio) = self.aio {
+            let aiocb = AioCb {
+                direct,
+                req_align,
+                buf_align,
+                discard: false,
+                write_zeroes: WriteZeroesState::Off,
+                file_fd: disk_img.as_raw_fd(),
+                opcode: OpCode::Noop,
+                iovec: Vec::new(),
+                offset: 0,
+                nbytes: 0,
+                user_data: 0,
+                iocompletecb: scsicompletecb,
+                combine_req: None,
+            };
+            scsi_req.execute(aio, aiocb)?;
+            aio.flush_request()?;
         }
 
         Ok(())
     }
 
+    /// Submit again the requests stopped by the error policy, then go on with
+    /// the requests in the cmd queue.
+    fn retry_failed_requests(&mut self) -> Result<()> {
+        let failed_reqs = std::mem::take(&mut *self.failed_reqs.lock().unwrap());
+        if failed_reqs.is_empty() {
+            return Ok(());
+        }
+        for scsi_req in failed_reqs {
+            self.submit_request(scsi_req)?;
+        }
+        self.handle_cmd()
+    }
+
     fn complete_func(aiocb: &AioCb<ScsiCompleteCb>, ret: i64) -> Result<()> {
         let complete_cb = &aiocb.iocompletecb;
         let request = &aiocb.iocompletecb.req.lock().unwrap();
+        let mut failed = ret < 0;
+        if failed {
+            let dev_lock = request.dev.lock().unwrap();
+            let (operation, policy) = match aiocb.opcode {
+                OpCode::Preadv => (IoOperationType::read, dev_lock.config.rerror),
+                _ => (IoOperationType::write, dev_lock.config.werror),
+            };
+            let action = handle_block_io_error(&dev_lock.config.id, operation, policy, ret);
+            drop(dev_lock);
+            match action {
+                BlockErrorAction::stop => {
+                    // The request is not completed, keep it to retry on resume. It stays
+                    // in the in-flight set as well, so it is submitted again after the
+                    // state is restored if the VM is migrated before resuming.
+                    if let Some(acct) = complete_cb.acct.as_ref() {
+                        acct.cancel();
+                    }
+                    complete_cb
+                        .failed_reqs
+                        .lock()
+                        .unwrap()
+                        .push((**request).clone());
+                    return Ok(());
+                }
+                BlockErrorAction::ignore => failed = false,
+                BlockErrorAction::report => {}
+            }
+        }
         let mut virtio_scsi_req = request.virtioscsireq.lock().unwrap();
 
-        virtio_scsi_req.resp.response = if ret < 0 {
+        virtio_scsi_req.resp.response = if failed {
             VIRTIO_SCSI_S_FAILURE
         } else {
             VIRTIO_SCSI_S_OK
         };
+        if let Some(acct) = complete_cb.acct.as_ref() {
+            acct.done(failed);
+        }
 
         virtio_scsi_req.resp.status = GOOD;
         virtio_scsi_req.resp.resid = 0;
@@ -991,10 +1341,23 @@ impl ScsiCmdHandler {
 pub struct ScsiCompleteCb {
     pub mem_space: Arc<AddressSpace>,
     req: Arc<Mutex<ScsiRequest>>,
+    /// Accounting of the request, None if the request is not accounted.
+    pub acct: Option<BlockAcctCookie>,
+    /// Requests stopped by the error policy.
+    failed_reqs: Arc<Mutex<Vec<ScsiRequest>>>,
 }
 
 impl ScsiCompleteCb {
-    fn new(mem_space: Arc<AddressSpace>, req: Arc<Mutex<ScsiRequest>>) -> Self {
-        ScsiCompleteCb { mem_space, req }
+    fn new(
+        mem_space: Arc<AddressSpace>,
+        req: Arc<Mutex<ScsiRequest>>,
+        failed_reqs: Arc<Mutex<Vec<ScsiRequest>>>,
+    ) -> Self {
+        ScsiCompleteCb {
+            mem_space,
+            req,
+            acct: None,
+            failed_reqs,
+        }
     }
 }
@@ -12,13 +12,20 @@
 
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{Seek, SeekFrom};
 use std::sync::{Arc, Mutex, Weak};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{bail, Result};
+use block_backend::stats::{
+    register_block_drive, unregister_block_drive, BlockAcctStats, BlockDriveInfo, BlockStatsRef,
+};
+use block_backend::{
+    create_block_backend, register_block_backend, unregister_block_backend, BlockBackend,
+    BlockProperty,
+};
 
-use crate::ScsiBus::ScsiBus;
+use crate::ScsiBus::{ScsiBus, ScsiSense};
 use machine_manager::config::{DriveFile, ScsiDevConfig, VmConfig};
+use util::aio::WriteZeroesState;
 
 /// SCSI DEVICE TYPES.
 pub const SCSI_TYPE_DISK: u32 = 0x00;
@@ -94,6 +101,8 @@ pub struct ScsiDevice {
     pub state: ScsiDevState,
     /// Image file opened.
     pub disk_image: Option<Arc<File>>,
+    /// The format driver of the image file.
+    pub block_backend: Option<BlockBackend>,
     /// The align requirement of request(offset/len).
     pub req_align: u32,
     /// The align requirement of buffer(iova_base).
@@ -108,6 +117,10 @@ pub struct ScsiDevice {
     pub parent_bus: Weak<Mutex<ScsiBus>>,
     /// Drive backend files.
     drive_files: Arc<Mutex<HashMap<String, DriveFile>>>,
+    /// IO statistics of the scsi device.
+    pub stats: BlockStatsRef,
+    /// Pending unit attention condition to be reported to the initiator.
+    pub unit_attention: Option<ScsiSense>,
 }
 
 impl ScsiDevice {
@@ -120,6 +133,7 @@ impl ScsiDevice {
             config,
             state: ScsiDevState::new(),
             disk_image: None,
+            block_backend: None,
             req_align: 1,
             buf_align: 1,
             disk_sectors: 0,
@@ -127,6 +141,8 @@ impl ScsiDevice {
             scsi_type,
             parent_bus: Weak::new(),
             drive_files,
+            stats: Arc::new(Mutex::new(BlockAcctStats::default())),
+            unit_attention: None,
         }
     }
 
@@ -154,17 +170,43 @@ impl ScsiDevice {
             self.disk_image = None;
 
             let drive_files = self.drive_files.lock().unwrap();
-            let mut file = VmConfig::fetch_drive_file(&drive_files, &self.config.path_on_host)?;
-            disk_size = file
-                .seek(SeekFrom::End(0))
-                .with_context(|| "Failed to seek the end for scsi device")?;
-            self.disk_image = Some(Arc::new(file));
-
+            let file = Arc::new(VmConfig::fetch_drive_file(
+                &drive_files,
+                &self.config.path_on_host,
+            )?);
             let alignments = VmConfig::fetch_drive_align(&drive_files, &self.config.path_on_host)?;
+            let prop = BlockProperty {
+                format: self.config.format,
+                read_only: self.config.read_only,
+                direct: self.config.direct,
+                req_align: alignments.0,
+                buf_align: alignments.1,
+                path: self.config.path_on_host.clone(),
+            };
+            let backend = create_block_backend(file.clone(), prop)?;
+            disk_size = backend.lock().unwrap().disk_size()?;
+            register_block_backend(&self.config.id, backend.clone());
+            register_block_drive(
+                BlockDriveInfo {
+                    id: self.config.id.clone(),
+                    file: self.config.path_on_host.clone(),
+                    format: self.config.format,
+                    read_only: self.config.read_only,
+                    direct: self.config.direct,
+                    aio: self.config.aio_type,
+                    iops: None,
+                    write_zeroes: WriteZeroesState::Off,
+                },
+                self.stats.clone(),
+            );
+
+            self.disk_image = Some(file);
+            self.block_backend = Some(backend);
             self.req_align = alignments.0;
             self.buf_align = alignments.1;
         } else {
             self.disk_image = None;
+            self.block_backend = None;
             self.req_align = 1;
             self.buf_align = 1;
         }
@@ -173,4 +215,13 @@ impl ScsiDevice {
 
         Ok(())
     }
+
+    /// Unregister the block backend of the device when it is detached from the bus. The
+    /// image is kept open until the requests in flight are completed.
+    pub fn unrealize(&mut self) {
+        if self.block_backend.is_some() {
+            unregister_block_backend(&self.config.id);
+            unregister_block_drive(&self.config.id);
+        }
+    }
 }
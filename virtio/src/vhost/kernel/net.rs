@@ -21,6 +21,7 @@ use address_space::AddressSpace;
 use anyhow::{anyhow, bail, Context, Result};
 use machine_manager::config::NetworkInterfaceConfig;
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
+use migration::MigrationManager;
 use util::byte_code::ByteCode;
 use util::loop_context::EventNotifierHelper;
 use util::num_ops::read_u32;
@@ -170,10 +171,16 @@ impl VirtioDevice for Net {
         locked_state.device_features = device_features;
         self.vhost_features = vhost_features;
 
+        MigrationManager::register_migration_blocker(
+            &self.net_cfg.id,
+            "vhost-net device is not migratable",
+        );
+
         Ok(())
     }
 
     fn unrealize(&mut self) -> Result<()> {
+        MigrationManager::unregister_migration_blocker(&self.net_cfg.id);
         Ok(())
     }
 
@@ -267,6 +274,7 @@ impl VirtioDevice for Net {
                 interrupt_cb: interrupt_cb.clone(),
                 driver_features,
                 device_broken: self.broken.clone(),
+                device_id: self.net_cfg.id.clone(),
             };
 
             let notifiers =
@@ -455,6 +463,8 @@ mod tests {
             mq: false,
             socket_path: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            user_net: None,
+            socket: None,
         };
         let conf = vec![net1];
         let confs = Some(conf);
@@ -477,6 +487,8 @@ mod tests {
             mq: false,
             socket_path: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            user_net: None,
+            socket: None,
         };
         let conf = vec![net1];
         let confs = Some(conf);
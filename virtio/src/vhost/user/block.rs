@@ -18,6 +18,7 @@ use std::sync::{Arc, Mutex};
 
 use address_space::AddressSpace;
 use machine_manager::config::BlkDevConfig;
+use migration::MigrationManager;
 use util::byte_code::ByteCode;
 use util::num_ops::read_u32;
 use vmm_sys_util::eventfd::EventFd;
@@ -173,6 +174,11 @@ impl VirtioDevice for Block {
         self.init_client()?;
         self.negotiate_features()?;
 
+        MigrationManager::register_migration_blocker(
+            &self.blk_cfg.id,
+            "vhost-user-blk device is not migratable",
+        );
+
         Ok(())
     }
 
@@ -290,6 +296,7 @@ impl VirtioDevice for Block {
         self.delete_event()?;
         self.call_events.clear();
         self.client = None;
+        MigrationManager::unregister_migration_blocker(&self.blk_cfg.id);
         Ok(())
     }
 
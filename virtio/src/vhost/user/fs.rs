@@ -30,6 +30,7 @@ use vmm_sys_util::{epoll::EventSet, eventfd::EventFd};
 use address_space::AddressSpace;
 use machine_manager::config::{FsConfig, MAX_TAG_LENGTH};
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
+use migration::MigrationManager;
 use util::byte_code::ByteCode;
 use util::loop_context::{
     read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
@@ -161,6 +162,16 @@ impl VirtioDevice for Fs {
             .with_context(|| "Failed to get features for virtio fs")?;
         self.client = Some(client);
 
+        MigrationManager::register_migration_blocker(
+            &self.fs_cfg.id,
+            "vhost-user-fs device is not migratable",
+        );
+
+        Ok(())
+    }
+
+    fn unrealize(&mut self) -> Result<()> {
+        MigrationManager::unregister_migration_blocker(&self.fs_cfg.id);
         Ok(())
     }
 
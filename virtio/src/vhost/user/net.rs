@@ -20,6 +20,7 @@ use std::sync::{Arc, Mutex};
 use address_space::AddressSpace;
 use machine_manager::config::NetworkInterfaceConfig;
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
+use migration::MigrationManager;
 use util::byte_code::ByteCode;
 use util::loop_context::EventNotifierHelper;
 use util::num_ops::read_u32;
@@ -159,6 +160,11 @@ impl VirtioDevice for Net {
                 build_device_config_space(&mut locked_state.config_space, mac);
         }
 
+        MigrationManager::register_migration_blocker(
+            &self.net_cfg.id,
+            "vhost-user-net device is not migratable",
+        );
+
         Ok(())
     }
 
@@ -253,6 +259,7 @@ impl VirtioDevice for Net {
                 interrupt_cb: interrupt_cb.clone(),
                 driver_features,
                 device_broken: self.broken.clone(),
+                device_id: self.net_cfg.id.clone(),
             };
 
             let notifiers =
@@ -310,6 +317,7 @@ impl VirtioDevice for Net {
         self.call_events.clear();
         self.client = None;
 
+        MigrationManager::unregister_migration_blocker(&self.net_cfg.id);
         Ok(())
     }
 
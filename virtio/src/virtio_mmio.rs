@@ -273,10 +273,14 @@ impl VirtioMmioCommonConfig {
                         .lock()
                         .unwrap()
                         .set_driver_features(self.acked_features_select, value);
-                    if self.acked_features_select == 1
-                        && virtio_has_feature(u64::from(value) << 32, VIRTIO_F_RING_PACKED)
-                    {
-                        self.queue_type = QUEUE_TYPE_PACKED_VRING;
+                    if self.acked_features_select == 1 {
+                        let features =
+                            u64::from(device.lock().unwrap().get_driver_features(1)) << 32;
+                        if virtio_has_feature(features, VIRTIO_F_RING_PACKED) {
+                            self.queue_type = QUEUE_TYPE_PACKED_VRING;
+                        } else {
+                            self.queue_type = QUEUE_TYPE_SPLIT_VRING;
+                        }
                     }
                 } else {
                     return Err(anyhow!(VirtioError::DevStatErr(self.device_status)));
@@ -656,8 +660,9 @@ impl StateTransfer for VirtioMmioDevice {
         let mut state = self.state.lock().unwrap();
 
         for (index, queue) in self.queues.iter().enumerate() {
-            state.config_space.queues_config[index] =
-                queue.lock().unwrap().vring.get_queue_config();
+            let locked_queue = queue.lock().unwrap();
+            locked_queue.vring.check_inflight()?;
+            state.config_space.queues_config[index] = locked_queue.vring.get_queue_config();
         }
         state.config_space.interrupt_status = self.interrupt_status.load(Ordering::Relaxed);
 
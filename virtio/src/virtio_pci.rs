@@ -1329,7 +1329,9 @@ impl StateTransfer for VirtioPciDevice {
         {
             let locked_queues = self.queues.lock().unwrap();
             for (index, queue) in locked_queues.iter().enumerate() {
-                state.queues_config[index] = queue.lock().unwrap().vring.get_queue_config();
+                let locked_queue = queue.lock().unwrap();
+                locked_queue.vring.check_inflight()?;
+                state.queues_config[index] = locked_queue.vring.get_queue_config();
                 state.queue_num += 1;
             }
         }
@@ -1338,7 +1340,7 @@ impl StateTransfer for VirtioPciDevice {
     }
 
     fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
-        let mut pci_state = *VirtioPciState::from_bytes(state).ok_or_else(|| {
+        let pci_state = *VirtioPciState::from_bytes(state).ok_or_else(|| {
             anyhow!(migration::error::MigrationError::FromBytesError(
                 "PCI_DEVICE"
             ),)
@@ -1369,24 +1371,12 @@ impl StateTransfer for VirtioPciDevice {
         self.device_activated
             .store(pci_state.activated, Ordering::Relaxed);
         self.dev_id.store(pci_state.dev_id, Ordering::Release);
+        // The queues are created in `resume`, as the queue type depends on the features
+        // negotiated with the driver, which are restored with the virtio device later.
         {
-            let queue_type = self.common_config.lock().unwrap().queue_type;
-            let mut locked_queues = self.queues.lock().unwrap();
-            let cloned_mem_space = self.sys_mem.clone();
-            for queue_state in pci_state.queues_config[0..pci_state.queue_num].iter_mut() {
-                queue_state.addr_cache.desc_table_host = cloned_mem_space
-                    .get_host_address(queue_state.desc_table)
-                    .unwrap_or(0);
-                queue_state.addr_cache.avail_ring_host = cloned_mem_space
-                    .get_host_address(queue_state.avail_ring)
-                    .unwrap_or(0);
-                queue_state.addr_cache.used_ring_host = cloned_mem_space
-                    .get_host_address(queue_state.used_ring)
-                    .unwrap_or(0);
-                locked_queues.push(Arc::new(Mutex::new(
-                    Queue::new(*queue_state, queue_type).unwrap(),
-                )))
-            }
+            let mut common_config = self.common_config.lock().unwrap();
+            common_config.queues_config[..pci_state.queue_num]
+                .copy_from_slice(&pci_state.queues_config[..pci_state.queue_num]);
         }
 
         Ok(())
@@ -1404,6 +1394,36 @@ impl StateTransfer for VirtioPciDevice {
 impl MigrationHook for VirtioPciDevice {
     fn resume(&mut self) -> migration::Result<()> {
         if self.device_activated.load(Ordering::Relaxed) {
+            // Create the queues with the configuration restored in `set_state_mut`.
+            {
+                let features = (self.device.lock().unwrap().get_driver_features(1) as u64) << 32;
+                let queue_type = if virtio_has_feature(features, VIRTIO_F_RING_PACKED) {
+                    QUEUE_TYPE_PACKED_VRING
+                } else {
+                    QUEUE_TYPE_SPLIT_VRING
+                };
+                let mut common_config = self.common_config.lock().unwrap();
+                common_config.queue_type = queue_type;
+                let mut locked_queues = self.queues.lock().unwrap();
+                locked_queues.clear();
+                for q_config in common_config.queues_config.iter_mut() {
+                    q_config.addr_cache.desc_table_host = self
+                        .sys_mem
+                        .get_host_address(q_config.desc_table)
+                        .unwrap_or(0);
+                    q_config.addr_cache.avail_ring_host = self
+                        .sys_mem
+                        .get_host_address(q_config.avail_ring)
+                        .unwrap_or(0);
+                    q_config.addr_cache.used_ring_host = self
+                        .sys_mem
+                        .get_host_address(q_config.used_ring)
+                        .unwrap_or(0);
+                    let queue = Queue::new(*q_config, queue_type)?;
+                    locked_queues.push(Arc::new(Mutex::new(queue)));
+                }
+            }
+
             // Reregister ioevents for notifies.
             let parent_bus = self.parent_bus.upgrade().unwrap();
             let locked_parent_bus = parent_bus.lock().unwrap();
@@ -1816,6 +1836,80 @@ mod tests {
         assert_eq!(virtio_pci.device_activated.load(Ordering::Relaxed), false);
     }
 
+    #[test]
+    fn test_state_restore_packed_queue() {
+        let sys_mem = AddressSpace::new(Region::init_container_region(u64::MAX)).unwrap();
+        let mem_size: u64 = 1024 * 1024;
+        let host_mmap = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, mem_size, None, false, false, false)
+                .unwrap(),
+        );
+        sys_mem
+            .root()
+            .add_subregion(
+                Region::init_ram_region(host_mmap.clone()),
+                host_mmap.start_address().raw_value(),
+            )
+            .unwrap();
+        let parent_bus = Arc::new(Mutex::new(PciBus::new(
+            String::from("test bus"),
+            #[cfg(target_arch = "x86_64")]
+            Region::init_container_region(1 << 16),
+            sys_mem.root().clone(),
+        )));
+        let packed_features = 1_u64 << VIRTIO_F_RING_PACKED;
+
+        // The source device has an activated packed queue.
+        let src_dev = Arc::new(Mutex::new(VirtioDeviceTest::new()));
+        src_dev.lock().unwrap().driver_features = packed_features;
+        let src_pci = VirtioPciDevice::new(
+            String::from("test device"),
+            0,
+            sys_mem.clone(),
+            src_dev,
+            Arc::downgrade(&parent_bus),
+            false,
+        );
+        let mut queue_config = QueueConfig::new(VIRTIO_DEVICE_QUEUE_SIZE);
+        queue_config.avail_ring = GuestAddress((VIRTIO_DEVICE_QUEUE_SIZE as u64) * 16);
+        queue_config.used_ring = GuestAddress(2 * 4096);
+        queue_config.ready = true;
+        let queue = Queue::new(queue_config, QUEUE_TYPE_PACKED_VRING).unwrap();
+        src_pci
+            .queues
+            .lock()
+            .unwrap()
+            .push(Arc::new(Mutex::new(queue)));
+        src_pci.device_activated.store(true, Ordering::Relaxed);
+        let state = src_pci.get_state_vec().unwrap();
+
+        // The transport is restored before the virtio device.
+        let dst_dev = Arc::new(Mutex::new(VirtioDeviceTest::new()));
+        let mut dst_pci = VirtioPciDevice::new(
+            String::from("test device"),
+            0,
+            sys_mem,
+            dst_dev.clone(),
+            Arc::downgrade(&parent_bus),
+            false,
+        );
+        dst_pci.assign_interrupt_cb();
+        dst_pci.set_state_mut(&state).unwrap();
+        assert!(dst_pci.queues.lock().unwrap().is_empty());
+        dst_dev.lock().unwrap().driver_features = packed_features;
+        dst_pci.resume().unwrap();
+
+        assert_eq!(
+            dst_pci.common_config.lock().unwrap().queue_type,
+            QUEUE_TYPE_PACKED_VRING
+        );
+        assert_eq!(
+            dst_pci.queues.lock().unwrap().len(),
+            VIRTIO_DEVICE_QUEUE_NUM
+        );
+        assert!(dst_dev.lock().unwrap().is_activated);
+    }
+
     #[test]
     fn test_multifunction() {
         let virtio_dev: Arc<Mutex<dyn VirtioDevice>> =
@@ -10,6 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+mod packed;
 mod split;
 
 use address_space::{AddressSpace, GuestAddress, RegionCache};
@@ -17,6 +18,7 @@ use anyhow::{bail, Result};
 use std::sync::Arc;
 use vmm_sys_util::eventfd::EventFd;
 
+pub use packed::*;
 pub use split::*;
 
 /// Split Virtqueue.
@@ -32,6 +34,8 @@ const VIRTQ_DESC_F_NEXT: u16 = 0x1;
 const VIRTQ_DESC_F_WRITE: u16 = 0x2;
 /// This means the buffer contains a list of buffer descriptors.
 const VIRTQ_DESC_F_INDIRECT: u16 = 0x4;
+/// Max total len of a descriptor chain.
+const DESC_CHAIN_MAX_TOTAL_LEN: u64 = 1u64 << 32;
 
 fn checked_offset_mem(
     mmio_space: &Arc<AddressSpace>,
@@ -116,6 +120,17 @@ pub trait VringOps {
     /// Rollback the entry which is pop from available queue by `pop_avail`.
     fn push_back(&mut self);
 
+    /// Assemble an IO request element with the descriptor chain whose head is `index`,
+    /// which has been popped from the available vring before, e.g. a request which
+    /// was in flight when the device state was saved.
+    ///
+    /// # Arguments
+    ///
+    /// * `sys_mem` - Address space to which the vring belongs.
+    /// * `index` - Index of the head descriptor in the virqueue descriptor table, or the
+    ///   buffer id for packed vring, the same as `Element::index`.
+    fn get_inflight_element(&mut self, sys_mem: &Arc<AddressSpace>, index: u16) -> Result<Element>;
+
     /// Fill the used vring after processing the IO request.
     ///
     /// # Arguments
@@ -153,13 +168,17 @@ pub trait VringOps {
     /// Get the configuration of the vring.
     fn get_queue_config(&self) -> QueueConfig;
 
+    /// Check whether the buffers in flight allow the vring to be saved with the
+    /// configuration got from `get_queue_config`.
+    fn check_inflight(&self) -> Result<()>;
+
     /// The number of descriptor chains in the available ring.
     fn avail_ring_len(&mut self, sys_mem: &Arc<AddressSpace>) -> Result<u16>;
 
     /// Get the avail index of the vring.
     fn get_avail_idx(&self, sys_mem: &Arc<AddressSpace>) -> Result<u16>;
 
-    /// Get the region cache information of the SplitVring.
+    /// Get the region cache information of the vring.
     fn get_cache(&self) -> &Option<RegionCache>;
 }
 
@@ -179,6 +198,7 @@ impl Queue {
     pub fn new(queue_config: QueueConfig, queue_type: u16) -> Result<Self> {
         let vring: Box<dyn VringOps + Send> = match queue_type {
             QUEUE_TYPE_SPLIT_VRING => Box::new(SplitVring::new(queue_config)),
+            QUEUE_TYPE_PACKED_VRING => Box::new(PackedVring::new(queue_config)),
             _ => {
                 bail!("Unsupported queue type {}", queue_type);
             }
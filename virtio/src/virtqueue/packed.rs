@@ -0,0 +1,1127 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::cmp::min;
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::num::Wrapping;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{fence, Ordering};
+use std::sync::Arc;
+
+use address_space::{AddressSpace, GuestAddress, RegionCache};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{error, warn};
+use util::byte_code::ByteCode;
+
+use super::{
+    checked_offset_mem, ElemIovec, Element, QueueConfig, VringOps, DESC_CHAIN_MAX_TOTAL_LEN,
+    VIRTQ_DESC_F_INDIRECT, VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE,
+};
+use crate::{virtio_has_feature, VirtioError, VIRTIO_F_RING_EVENT_IDX};
+
+/// The descriptor is made available by the driver.
+const VRING_PACKED_DESC_F_AVAIL: u16 = 1 << 7;
+/// The descriptor is used by the device.
+const VRING_PACKED_DESC_F_USED: u16 = 1 << 15;
+/// Enable events.
+const VRING_PACKED_EVENT_FLAG_ENABLE: u16 = 0x0;
+/// Disable events.
+const VRING_PACKED_EVENT_FLAG_DISABLE: u16 = 0x1;
+/// Enable events for a specific descriptor, only valid if VIRTIO_F_RING_EVENT_IDX is negotiated.
+const VRING_PACKED_EVENT_FLAG_DESC: u16 = 0x2;
+/// The bit of the wrap counter in the `off_wrap` field of the event suppression structure.
+const VRING_PACKED_EVENT_WRAP_SHIFT: u16 = 15;
+/// The bit of the saved `next_avail` and `next_used` in `QueueConfig`, which is set if the
+/// wrap counter is 0. The ring size is at most 32768, so the bit is never used by the index.
+const VRING_PACKED_SAVED_WRAP_FLIPPED: u16 = 1 << 15;
+/// The length of packed virtio descriptor.
+const PACKED_DESCRIPTOR_LEN: u64 = size_of::<PackedVringDesc>() as u64;
+/// The length of the event suppression structure.
+const EVENT_SUPPRESS_LEN: u64 = size_of::<PackedVringEvent>() as u64;
+/// The position of `len` in the packed virtio descriptor.
+const DESC_LEN_POSITION: u64 = 8;
+/// The position of `id` in the packed virtio descriptor.
+const DESC_ID_POSITION: u64 = 12;
+/// The position of `flags` in the packed virtio descriptor.
+const DESC_FLAGS_POSITION: u64 = 14;
+/// The position of `flags` in the event suppression structure.
+const EVENT_FLAGS_POSITION: u64 = 2;
+
+/// Descriptor of packed vring.
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+pub struct PackedVringDesc {
+    /// Buffer address (guest-physical).
+    pub addr: GuestAddress,
+    /// Buffer length.
+    pub len: u32,
+    /// Buffer ID.
+    pub id: u16,
+    /// The flags depending on descriptor type.
+    pub flags: u16,
+}
+
+impl ByteCode for PackedVringDesc {}
+
+impl PackedVringDesc {
+    /// Return true if the descriptor is made available by the driver in the
+    /// round of `wrap_counter`.
+    fn is_avail(&self, wrap_counter: bool) -> bool {
+        let avail = self.flags & VRING_PACKED_DESC_F_AVAIL != 0;
+        let used = self.flags & VRING_PACKED_DESC_F_USED != 0;
+        avail == wrap_counter && used != wrap_counter
+    }
+
+    /// Return true if this descriptor has next descriptor.
+    fn has_next(&self) -> bool {
+        self.flags & VIRTQ_DESC_F_NEXT != 0
+    }
+
+    /// Check whether this descriptor is write-only or read-only.
+    fn write_only(&self) -> bool {
+        self.flags & VIRTQ_DESC_F_WRITE != 0
+    }
+
+    /// Return true if this descriptor is a indirect descriptor.
+    fn is_indirect_desc(&self) -> bool {
+        self.flags & VIRTQ_DESC_F_INDIRECT != 0
+    }
+
+    /// Return true if the indirect descriptor is valid.
+    /// The len can be divided evenly by the size of descriptor and can not be zero.
+    fn is_valid_indirect_desc(&self) -> bool {
+        if self.len == 0
+            || u64::from(self.len) % PACKED_DESCRIPTOR_LEN != 0
+            || u64::from(self.len) / PACKED_DESCRIPTOR_LEN > u16::MAX as u64
+        {
+            error!("The indirect descriptor is invalid, len: {}", self.len);
+            return false;
+        }
+        if self.has_next() {
+            error!("INDIRECT and NEXT flag should not be used together");
+            return false;
+        }
+        true
+    }
+
+    /// Return true if the buffer of the descriptor is valid.
+    fn is_valid(&self, sys_mem: &Arc<AddressSpace>) -> bool {
+        if self.len == 0 {
+            error!("Zero sized buffers are not allowed");
+            return false;
+        }
+        if let Err(ref e) = checked_offset_mem(sys_mem, self.addr, u64::from(self.len)) {
+            error!("The memory of descriptor is invalid, {:?} ", e);
+            return false;
+        }
+        true
+    }
+}
+
+/// Event suppression structure of packed vring, the driver area and the device
+/// area of the queue are both in this format.
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct PackedVringEvent {
+    /// Descriptor ring change event offset(bit 0-14) and wrap counter(bit 15).
+    off_wrap: u16,
+    /// Descriptor ring change event flags.
+    flags: u16,
+}
+
+impl ByteCode for PackedVringEvent {}
+
+/// A buffer which is popped from the ring but not used yet.
+#[derive(Default, Clone, Copy)]
+struct InflightBuffer {
+    /// Position of the first descriptor of the buffer in the ring.
+    index: u16,
+    /// Wrap counter of the round in which the buffer is made available.
+    wrap_counter: bool,
+    /// Number of descriptors in the ring used by the buffer, 0 if it is not in flight.
+    desc_num: u16,
+}
+
+/// The element being parsed from the descriptor ring.
+#[derive(Default)]
+struct ParseState {
+    /// Number of the writable descriptors.
+    write_count: u32,
+    /// Total length of the buffers.
+    total_len: u64,
+}
+
+/// Packed vring.
+///
+/// The `desc_table` of the queue configuration is the descriptor ring, the `avail_ring`
+/// is the driver event suppression area and the `used_ring` is the device event
+/// suppression area.
+pub struct PackedVring {
+    /// Region cache information.
+    cache: Option<RegionCache>,
+    /// The configuration of virtqueue.
+    queue_config: QueueConfig,
+    /// The wrap counter of the next available descriptor.
+    avail_wrap_counter: bool,
+    /// The wrap counter of the next used descriptor.
+    used_wrap_counter: bool,
+    /// The buffers in flight, indexed by buffer id.
+    inflight: Vec<InflightBuffer>,
+    /// The next index and wrap counter before the last pop, and the buffer id popped.
+    last_pop: Option<(Wrapping<u16>, bool, u16)>,
+    /// The buffers which were in flight when the vring was saved, they are got again from
+    /// the ring by the first call of `get_inflight_element`.
+    restored: Option<HashMap<u16, Element>>,
+}
+
+impl Deref for PackedVring {
+    type Target = QueueConfig;
+    fn deref(&self) -> &Self::Target {
+        &self.queue_config
+    }
+}
+
+impl DerefMut for PackedVring {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.queue_config
+    }
+}
+
+impl PackedVring {
+    /// Create a packed vring.
+    ///
+    /// # Arguments
+    ///
+    /// * `queue_config` - Configuration of the vring.
+    pub fn new(mut queue_config: QueueConfig) -> Self {
+        let size = min(queue_config.size, queue_config.max_size);
+        // The wrap counters are kept in the indexes of the saved configuration.
+        let avail_wrap_counter = queue_config.next_avail.0 & VRING_PACKED_SAVED_WRAP_FLIPPED == 0;
+        let used_wrap_counter = queue_config.next_used.0 & VRING_PACKED_SAVED_WRAP_FLIPPED == 0;
+        queue_config.next_avail.0 &= !VRING_PACKED_SAVED_WRAP_FLIPPED;
+        queue_config.next_used.0 &= !VRING_PACKED_SAVED_WRAP_FLIPPED;
+        PackedVring {
+            cache: None,
+            queue_config,
+            avail_wrap_counter,
+            used_wrap_counter,
+            inflight: vec![InflightBuffer::default(); size as usize],
+            last_pop: None,
+            restored: None,
+        }
+    }
+
+    /// The actual size of the queue.
+    fn actual_size(&self) -> u16 {
+        min(self.size, self.max_size)
+    }
+
+    /// Get the position after `num` descriptors from `index` in the round of `wrap_counter`.
+    fn advance(&self, index: u16, wrap_counter: bool, num: u16) -> (u16, bool) {
+        let next = u32::from(index) + u32::from(num);
+        let size = u32::from(self.actual_size());
+        if next >= size {
+            ((next - size) as u16, !wrap_counter)
+        } else {
+            (next as u16, wrap_counter)
+        }
+    }
+
+    /// Get the number of descriptors from position `from` to position `to` of the ring,
+    /// each position is given with the wrap counter of its round.
+    fn distance(&self, from: (u16, bool), to: (u16, bool)) -> u32 {
+        let size = u32::from(self.actual_size());
+        let distance = (u32::from(to.0) + size - u32::from(from.0)) % size;
+        if distance == 0 && from.1 != to.1 {
+            size
+        } else {
+            distance
+        }
+    }
+
+    /// Read the descriptor at `index` of the descriptor ring.
+    fn read_desc(&self, sys_mem: &Arc<AddressSpace>, index: u16) -> Result<PackedVringDesc> {
+        if index >= self.actual_size() {
+            return Err(anyhow!(VirtioError::QueueIndex(index, self.actual_size())));
+        }
+        // The GPA of desc_table_host with desc ring length has been checked in
+        // is_invalid_memory which must not be overflowed.
+        let desc_addr = self.addr_cache.desc_table_host + u64::from(index) * PACKED_DESCRIPTOR_LEN;
+        sys_mem
+            .read_object_direct::<PackedVringDesc>(desc_addr)
+            .with_context(|| anyhow!(VirtioError::ReadObjectErr("a descriptor", desc_addr)))
+    }
+
+    /// Get the driver event suppression structure from guest memory.
+    fn get_driver_event(&self, sys_mem: &Arc<AddressSpace>) -> Result<PackedVringEvent> {
+        // Make sure the event read from sys_mem is new.
+        fence(Ordering::SeqCst);
+        sys_mem
+            .read_object_direct::<PackedVringEvent>(self.addr_cache.avail_ring_host)
+            .with_context(|| {
+                anyhow!(VirtioError::ReadObjectErr(
+                    "driver event",
+                    self.avail_ring.raw_value()
+                ))
+            })
+    }
+
+    /// Set the device event suppression structure to guest memory, `off_wrap` is
+    /// written before `flags`.
+    fn set_device_event(&self, sys_mem: &Arc<AddressSpace>, event: PackedVringEvent) -> Result<()> {
+        let event_addr = self.addr_cache.used_ring_host;
+        if event.flags == VRING_PACKED_EVENT_FLAG_DESC {
+            sys_mem
+                .write_object_direct(&event.off_wrap, event_addr)
+                .with_context(|| {
+                    format!(
+                        "Failed to set device event off_wrap, used_ring: 0x{:X}",
+                        self.used_ring.raw_value()
+                    )
+                })?;
+            // Make sure off_wrap is set before flags.
+            fence(Ordering::Release);
+        }
+        sys_mem
+            .write_object_direct(&event.flags, event_addr + EVENT_FLAGS_POSITION)
+            .with_context(|| {
+                format!(
+                    "Failed to set device event flags, used_ring: 0x{:X}",
+                    self.used_ring.raw_value()
+                )
+            })?;
+        // Make sure the data has been set.
+        fence(Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Return true if it's required to trigger interrupt for the used vring.
+    fn used_ring_need_event(&mut self, sys_mem: &Arc<AddressSpace>, features: u64) -> bool {
+        let event = match self.get_driver_event(sys_mem) {
+            Ok(event) => event,
+            Err(ref e) => {
+                warn!("Failed to get the driver event of packed vring {:?}", e);
+                return true;
+            }
+        };
+
+        let old = self.last_signal_used;
+        let new = self.next_used;
+        let valid = self.signal_used_valid;
+        self.signal_used_valid = true;
+        self.last_signal_used = new;
+
+        match event.flags {
+            VRING_PACKED_EVENT_FLAG_DISABLE => false,
+            VRING_PACKED_EVENT_FLAG_DESC
+                if virtio_has_feature(features, VIRTIO_F_RING_EVENT_IDX) =>
+            {
+                if !valid {
+                    return true;
+                }
+                let wrap = event.off_wrap >> VRING_PACKED_EVENT_WRAP_SHIFT != 0;
+                let mut off = Wrapping(event.off_wrap & !(1 << VRING_PACKED_EVENT_WRAP_SHIFT));
+                if wrap != self.used_wrap_counter {
+                    off -= Wrapping(self.actual_size());
+                }
+                (new - off - Wrapping(1)) < (new - old)
+            }
+            _ => true,
+        }
+    }
+
+    fn is_invalid_memory(&self, sys_mem: &Arc<AddressSpace>, actual_size: u64) -> bool {
+        if let Err(ref e) = checked_offset_mem(
+            sys_mem,
+            self.desc_table,
+            PACKED_DESCRIPTOR_LEN * actual_size,
+        ) {
+            error!(
+                "descriptor ring is out of bounds: start:0x{:X} size:{} {:?}",
+                self.desc_table.raw_value(),
+                PACKED_DESCRIPTOR_LEN * actual_size,
+                e
+            );
+            return true;
+        }
+        if let Err(ref e) = checked_offset_mem(sys_mem, self.avail_ring, EVENT_SUPPRESS_LEN) {
+            error!(
+                "driver area is out of bounds: start:0x{:X} {:?}",
+                self.avail_ring.raw_value(),
+                e
+            );
+            return true;
+        }
+        if let Err(ref e) = checked_offset_mem(sys_mem, self.used_ring, EVENT_SUPPRESS_LEN) {
+            error!(
+                "device area is out of bounds: start:0x{:X} {:?}",
+                self.used_ring.raw_value(),
+                e
+            );
+            return true;
+        }
+
+        if self.desc_table.0 & 0xf != 0 {
+            error!(
+                "descriptor ring: 0x{:X} is not aligned",
+                self.desc_table.raw_value()
+            );
+            true
+        } else if self.avail_ring.0 & 0x3 != 0 {
+            error!(
+                "driver area: 0x{:X} is not aligned",
+                self.avail_ring.raw_value()
+            );
+            true
+        } else if self.used_ring.0 & 0x3 != 0 {
+            error!(
+                "device area: 0x{:X} is not aligned",
+                self.used_ring.raw_value()
+            );
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Add the buffer of `desc` to `elem`.
+    fn push_iovec(
+        sys_mem: &Arc<AddressSpace>,
+        desc: &PackedVringDesc,
+        state: &mut ParseState,
+        elem: &mut Element,
+    ) -> Result<()> {
+        if !desc.is_valid(sys_mem) {
+            return Err(anyhow!(VirtioError::QueueDescInvalid));
+        }
+        let iovec = ElemIovec {
+            addr: desc.addr,
+            len: desc.len,
+        };
+        if desc.write_only() {
+            elem.in_iovec.push(iovec);
+            state.write_count += 1;
+        } else {
+            if state.write_count > 0 {
+                bail!("Invalid order of the descriptor elem");
+            }
+            elem.out_iovec.push(iovec);
+        }
+        elem.desc_num += 1;
+        state.total_len += u64::from(desc.len);
+        Ok(())
+    }
+
+    /// Add the buffers of the indirect descriptor table `desc` to `elem`. The descriptors
+    /// in the table are used sequentially.
+    fn push_indirect_iovecs(
+        &mut self,
+        sys_mem: &Arc<AddressSpace>,
+        desc: &PackedVringDesc,
+        state: &mut ParseState,
+        elem: &mut Element,
+    ) -> Result<()> {
+        if !desc.is_valid_indirect_desc() {
+            return Err(anyhow!(VirtioError::QueueDescInvalid));
+        }
+        let table_host = sys_mem
+            .get_host_address_from_cache(desc.addr, &self.cache)
+            .ok_or_else(|| anyhow!("Failed to get descriptor table entry host address"))?;
+        let desc_num = (u64::from(desc.len) / PACKED_DESCRIPTOR_LEN) as u16;
+        for i in 0..desc_num {
+            let desc_addr = table_host + u64::from(i) * PACKED_DESCRIPTOR_LEN;
+            let desc = sys_mem
+                .read_object_direct::<PackedVringDesc>(desc_addr)
+                .with_context(|| {
+                    anyhow!(VirtioError::ReadObjectErr(
+                        "an indirect descriptor",
+                        desc_addr
+                    ))
+                })?;
+            if desc.is_indirect_desc() {
+                bail!("Found two indirect descriptor elem in one request");
+            }
+            Self::push_iovec(sys_mem, &desc, state, elem)?;
+        }
+        Ok(())
+    }
+
+    /// Get element from the descriptor chain starting at position `start` of the ring made
+    /// available in the round of `wrap_counter`, and mark the buffer in flight. Return the
+    /// number of descriptors in the ring used by the buffer.
+    fn get_desc_chain(
+        &mut self,
+        sys_mem: &Arc<AddressSpace>,
+        start: u16,
+        wrap_counter: bool,
+        elem: &mut Element,
+    ) -> Result<u16> {
+        let size = self.actual_size();
+        let mut state = ParseState::default();
+        let mut index = start;
+        let mut ring_desc_num: u16 = 0;
+
+        let id = loop {
+            if ring_desc_num >= size {
+                bail!("The element desc number exceeds max allowed");
+            }
+            let desc = self.read_desc(sys_mem, index)?;
+            ring_desc_num += 1;
+
+            if desc.is_indirect_desc() {
+                if ring_desc_num != 1 {
+                    bail!("The indirect descriptor should be the only one in the chain");
+                }
+                self.push_indirect_iovecs(sys_mem, &desc, &mut state, elem)?;
+                break desc.id;
+            }
+            Self::push_iovec(sys_mem, &desc, &mut state, elem)?;
+            if !desc.has_next() {
+                break desc.id;
+            }
+            index = self.advance(index, true, 1).0;
+        };
+
+        if state.total_len > DESC_CHAIN_MAX_TOTAL_LEN {
+            bail!("Find a descriptor chain longer than 4GB in total");
+        }
+        if id >= size {
+            return Err(anyhow!(VirtioError::QueueIndex(id, size)));
+        }
+        if self.inflight[id as usize].desc_num != 0 {
+            bail!("The buffer id {} is already in use", id);
+        }
+        self.inflight[id as usize] = InflightBuffer {
+            index: start,
+            wrap_counter,
+            desc_num: ring_desc_num,
+        };
+        elem.index = id;
+
+        Ok(ring_desc_num)
+    }
+
+    /// Get element from the descriptor chain starting at the next available descriptor.
+    fn get_vring_element(&mut self, sys_mem: &Arc<AddressSpace>, elem: &mut Element) -> Result<()> {
+        let ring_desc_num =
+            self.get_desc_chain(sys_mem, self.next_avail.0, self.avail_wrap_counter, elem)?;
+
+        self.last_pop = Some((self.next_avail, self.avail_wrap_counter, elem.index));
+        let (next_avail, wrap_counter) =
+            self.advance(self.next_avail.0, self.avail_wrap_counter, ring_desc_num);
+        self.next_avail = Wrapping(next_avail);
+        self.avail_wrap_counter = wrap_counter;
+
+        Ok(())
+    }
+
+    /// Get all the buffers which were in flight when the vring was saved. See `check_inflight`,
+    /// they take up the ring from the next used descriptor to the next available descriptor.
+    /// They are got at once, as the used descriptors of the buffers which are submitted again
+    /// overwrite the ring from the next used descriptor.
+    fn restore_inflight(&mut self, sys_mem: &Arc<AddressSpace>) -> Result<HashMap<u16, Element>> {
+        let end = (self.next_avail.0, self.avail_wrap_counter);
+        let mut pos = (self.next_used.0, self.used_wrap_counter);
+        let mut left = self.distance(pos, end);
+        let mut restored = HashMap::new();
+        while left != 0 {
+            let mut elem = Element::new(0);
+            let desc_num = self
+                .get_desc_chain(sys_mem, pos.0, pos.1, &mut elem)
+                .with_context(|| {
+                    format!(
+                        "Failed to get in-flight buffer at {} of packed vring",
+                        pos.0
+                    )
+                })?;
+            if u32::from(desc_num) > left {
+                bail!(
+                    "In-flight buffer {} of packed vring exceeds the available ring",
+                    elem.index
+                );
+            }
+            left -= u32::from(desc_num);
+            pos = self.advance(pos.0, pos.1, desc_num);
+            restored.insert(elem.index, elem);
+        }
+        Ok(restored)
+    }
+}
+
+impl VringOps for PackedVring {
+    fn is_enabled(&self) -> bool {
+        self.ready
+    }
+
+    fn is_valid(&self, sys_mem: &Arc<AddressSpace>) -> bool {
+        let size = u64::from(self.actual_size());
+        if !self.ready {
+            error!("The configuration of vring is not ready\n");
+            false
+        } else if self.size > self.max_size || self.size == 0 {
+            error!(
+                "vring with invalid size:{} max size:{}",
+                self.size, self.max_size
+            );
+            false
+        } else {
+            !self.is_invalid_memory(sys_mem, size)
+        }
+    }
+
+    fn pop_avail(&mut self, sys_mem: &Arc<AddressSpace>, _features: u64) -> Result<Element> {
+        let mut element = Element::new(0);
+        let desc = self.read_desc(sys_mem, self.next_avail.0)?;
+        if !desc.is_avail(self.avail_wrap_counter) {
+            return Ok(element);
+        }
+
+        // Make sure descriptor read does not bypass the flags read.
+        fence(Ordering::Acquire);
+
+        self.get_vring_element(sys_mem, &mut element)
+            .with_context(|| {
+                format!(
+                    "Failed to get vring element, next avail {}, wrap counter {}",
+                    self.next_avail.0, self.avail_wrap_counter
+                )
+            })?;
+
+        Ok(element)
+    }
+
+    fn get_inflight_element(&mut self, sys_mem: &Arc<AddressSpace>, index: u16) -> Result<Element> {
+        if self.restored.is_none() {
+            self.restored = Some(self.restore_inflight(sys_mem)?);
+        }
+        self.restored
+            .as_mut()
+            .unwrap()
+            .remove(&index)
+            .with_context(|| format!("Buffer {} of packed vring is not in flight", index))
+    }
+
+    fn push_back(&mut self) {
+        if let Some((next_avail, wrap_counter, id)) = self.last_pop.take() {
+            self.next_avail = next_avail;
+            self.avail_wrap_counter = wrap_counter;
+            self.inflight[id as usize].desc_num = 0;
+        }
+    }
+
+    fn add_used(&mut self, sys_mem: &Arc<AddressSpace>, index: u16, len: u32) -> Result<()> {
+        if index >= self.actual_size() {
+            return Err(anyhow!(VirtioError::QueueIndex(index, self.actual_size())));
+        }
+        let desc_num = self.inflight[index as usize].desc_num;
+        if desc_num == 0 {
+            bail!("The buffer id {} is not in use", index);
+        }
+
+        let desc_addr =
+            self.addr_cache.desc_table_host + u64::from(self.next_used.0) * PACKED_DESCRIPTOR_LEN;
+        sys_mem
+            .write_object_direct(&len, desc_addr + DESC_LEN_POSITION)
+            .with_context(|| "Failed to write len of used descriptor")?;
+        sys_mem
+            .write_object_direct(&index, desc_addr + DESC_ID_POSITION)
+            .with_context(|| "Failed to write id of used descriptor")?;
+        // Make sure id and len are filled before updating flags.
+        fence(Ordering::Release);
+
+        let flags = if self.used_wrap_counter {
+            VRING_PACKED_DESC_F_AVAIL | VRING_PACKED_DESC_F_USED
+        } else {
+            0
+        };
+        sys_mem
+            .write_object_direct(&flags, desc_addr + DESC_FLAGS_POSITION)
+            .with_context(|| "Failed to write flags of used descriptor")?;
+        // Make sure used descriptor is exposed before notifying guest.
+        fence(Ordering::SeqCst);
+
+        self.inflight[index as usize].desc_num = 0;
+        self.last_pop = None;
+        let (next_used, wrap_counter) =
+            self.advance(self.next_used.0, self.used_wrap_counter, desc_num);
+        self.next_used = Wrapping(next_used);
+        if wrap_counter != self.used_wrap_counter {
+            self.used_wrap_counter = wrap_counter;
+            self.signal_used_valid = false;
+        }
+        Ok(())
+    }
+
+    fn should_notify(&mut self, sys_mem: &Arc<AddressSpace>, features: u64) -> bool {
+        self.used_ring_need_event(sys_mem, features)
+    }
+
+    fn suppress_queue_notify(
+        &mut self,
+        sys_mem: &Arc<AddressSpace>,
+        features: u64,
+        suppress: bool,
+    ) -> Result<()> {
+        let mut event = PackedVringEvent::default();
+        if suppress {
+            event.flags = VRING_PACKED_EVENT_FLAG_DISABLE;
+        } else if virtio_has_feature(features, VIRTIO_F_RING_EVENT_IDX) {
+            event.off_wrap = self.get_avail_idx(sys_mem)?;
+            event.flags = VRING_PACKED_EVENT_FLAG_DESC;
+        } else {
+            event.flags = VRING_PACKED_EVENT_FLAG_ENABLE;
+        }
+        self.set_device_event(sys_mem, event)
+    }
+
+    fn actual_size(&self) -> u16 {
+        self.actual_size()
+    }
+
+    fn get_queue_config(&self) -> QueueConfig {
+        let mut config = self.queue_config;
+        config.signal_used_valid = false;
+        if !self.avail_wrap_counter {
+            config.next_avail.0 |= VRING_PACKED_SAVED_WRAP_FLIPPED;
+        }
+        if !self.used_wrap_counter {
+            config.next_used.0 |= VRING_PACKED_SAVED_WRAP_FLIPPED;
+        }
+        config
+    }
+
+    fn check_inflight(&self) -> Result<()> {
+        // The buffers in flight are got again from the ring after restore, if they take up the
+        // ring from the next used descriptor to the next available descriptor. A buffer used
+        // out of order overwrites the ring entries of the earlier buffers still in flight with
+        // its used descriptor.
+        let next_avail = (self.next_avail.0, self.avail_wrap_counter);
+        let inflight_len = self.distance((self.next_used.0, self.used_wrap_counter), next_avail);
+        for (id, buffer) in self.inflight.iter().enumerate() {
+            if buffer.desc_num != 0
+                && self.distance((buffer.index, buffer.wrap_counter), next_avail) > inflight_len
+            {
+                bail!(
+                    "In-flight buffer {} of packed vring is overwritten by buffers used out of order",
+                    id
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The number of descriptor chains which are made available by the driver.
+    fn avail_ring_len(&mut self, sys_mem: &Arc<AddressSpace>) -> Result<u16> {
+        let mut index = self.next_avail.0;
+        let mut wrap_counter = self.avail_wrap_counter;
+        let mut len = 0;
+        for _ in 0..self.actual_size() {
+            let desc = self.read_desc(sys_mem, index)?;
+            if !desc.is_avail(wrap_counter) {
+                break;
+            }
+            if !desc.has_next() {
+                len += 1;
+            }
+            (index, wrap_counter) = self.advance(index, wrap_counter, 1);
+        }
+        Ok(len)
+    }
+
+    /// Get the next available index, with the wrap counter in bit 15.
+    fn get_avail_idx(&self, _sys_mem: &Arc<AddressSpace>) -> Result<u16> {
+        Ok(self.next_avail.0
+            | (u16::from(self.avail_wrap_counter) << VRING_PACKED_EVENT_WRAP_SHIFT))
+    }
+
+    fn get_cache(&self) -> &Option<RegionCache> {
+        &self.cache
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Queue, QUEUE_TYPE_PACKED_VRING};
+    use address_space::{AddressSpace, GuestAddress, HostMemMapping, Region};
+
+    const SYSTEM_SPACE_SIZE: u64 = (1024 * 1024) as u64;
+    const QUEUE_SIZE: u16 = 4;
+    const DESC_RING: u64 = 0x0;
+    const DRIVER_AREA: u64 = 0x1000;
+    const DEVICE_AREA: u64 = 0x2000;
+    const INDIRECT_TABLE: u64 = 0x3000;
+    const BUFFER: u64 = 0x10000;
+
+    fn address_space_init() -> Arc<AddressSpace> {
+        let root = Region::init_container_region(1 << 36);
+        let sys_space = AddressSpace::new(root).unwrap();
+        let host_mmap = Arc::new(
+            HostMemMapping::new(
+                GuestAddress(0),
+                None,
+                SYSTEM_SPACE_SIZE,
+                None,
+                false,
+                false,
+                false,
+            )
+            .unwrap(),
+        );
+        sys_space
+            .root()
+            .add_subregion(
+                Region::init_ram_region(host_mmap.clone()),
+                host_mmap.start_address().raw_value(),
+            )
+            .unwrap();
+        sys_space
+    }
+
+    fn create_vring(sys_space: &Arc<AddressSpace>) -> PackedVring {
+        let mut queue_config = QueueConfig::new(QUEUE_SIZE);
+        queue_config.desc_table = GuestAddress(DESC_RING);
+        queue_config.avail_ring = GuestAddress(DRIVER_AREA);
+        queue_config.used_ring = GuestAddress(DEVICE_AREA);
+        queue_config.addr_cache.desc_table_host =
+            sys_space.get_host_address(GuestAddress(DESC_RING)).unwrap();
+        queue_config.addr_cache.avail_ring_host = sys_space
+            .get_host_address(GuestAddress(DRIVER_AREA))
+            .unwrap();
+        queue_config.addr_cache.used_ring_host = sys_space
+            .get_host_address(GuestAddress(DEVICE_AREA))
+            .unwrap();
+        queue_config.ready = true;
+        PackedVring::new(queue_config)
+    }
+
+    fn avail_flags(wrap_counter: bool) -> u16 {
+        if wrap_counter {
+            VRING_PACKED_DESC_F_AVAIL
+        } else {
+            VRING_PACKED_DESC_F_USED
+        }
+    }
+
+    fn set_desc(
+        sys_space: &Arc<AddressSpace>,
+        index: u16,
+        addr: u64,
+        len: u32,
+        id: u16,
+        flags: u16,
+    ) {
+        let desc = PackedVringDesc {
+            addr: GuestAddress(addr),
+            len,
+            id,
+            flags,
+        };
+        sys_space
+            .write_object(
+                &desc,
+                GuestAddress(DESC_RING + u64::from(index) * PACKED_DESCRIPTOR_LEN),
+            )
+            .unwrap();
+    }
+
+    fn get_desc(sys_space: &Arc<AddressSpace>, index: u16) -> PackedVringDesc {
+        sys_space
+            .read_object::<PackedVringDesc>(GuestAddress(
+                DESC_RING + u64::from(index) * PACKED_DESCRIPTOR_LEN,
+            ))
+            .unwrap()
+    }
+
+    fn set_driver_event(sys_space: &Arc<AddressSpace>, off_wrap: u16, flags: u16) {
+        let event = PackedVringEvent { off_wrap, flags };
+        sys_space
+            .write_object(&event, GuestAddress(DRIVER_AREA))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_packed_queue_valid() {
+        let sys_space = address_space_init();
+        let vring = create_vring(&sys_space);
+        assert!(vring.is_valid(&sys_space));
+
+        // The size of packed vring is not required to be power of 2.
+        let mut queue_config = vring.get_queue_config();
+        queue_config.size = 3;
+        let queue = Queue::new(queue_config, QUEUE_TYPE_PACKED_VRING).unwrap();
+        assert!(queue.is_valid(&sys_space));
+
+        queue_config.size = QUEUE_SIZE + 1;
+        let queue = Queue::new(queue_config, QUEUE_TYPE_PACKED_VRING).unwrap();
+        assert!(!queue.is_valid(&sys_space));
+
+        queue_config.size = QUEUE_SIZE;
+        queue_config.avail_ring = GuestAddress(DRIVER_AREA + 2);
+        let queue = Queue::new(queue_config, QUEUE_TYPE_PACKED_VRING).unwrap();
+        assert!(!queue.is_valid(&sys_space));
+
+        queue_config.avail_ring = GuestAddress(DRIVER_AREA);
+        queue_config.desc_table = GuestAddress(SYSTEM_SPACE_SIZE - 16);
+        let queue = Queue::new(queue_config, QUEUE_TYPE_PACKED_VRING).unwrap();
+        assert!(!queue.is_valid(&sys_space));
+    }
+
+    #[test]
+    fn test_packed_pop_and_add_used() {
+        let sys_space = address_space_init();
+        let mut vring = create_vring(&sys_space);
+
+        // Nothing is available.
+        let elem = vring.pop_avail(&sys_space, 0).unwrap();
+        assert_eq!(elem.desc_num, 0);
+        assert_eq!(vring.avail_ring_len(&sys_space).unwrap(), 0);
+
+        // A chain of two descriptors with buffer id 1.
+        let flags = avail_flags(true);
+        set_desc(&sys_space, 0, BUFFER, 0x100, 1, flags | VIRTQ_DESC_F_NEXT);
+        set_desc(
+            &sys_space,
+            1,
+            BUFFER + 0x100,
+            0x200,
+            1,
+            flags | VIRTQ_DESC_F_WRITE,
+        );
+        assert_eq!(vring.avail_ring_len(&sys_space).unwrap(), 1);
+
+        let elem = vring.pop_avail(&sys_space, 0).unwrap();
+        assert_eq!(elem.index, 1);
+        assert_eq!(elem.desc_num, 2);
+        assert_eq!(elem.out_iovec.len(), 1);
+        assert_eq!(elem.in_iovec.len(), 1);
+        assert_eq!(elem.in_iovec[0].len, 0x200);
+        assert_eq!(vring.get_avail_idx(&sys_space).unwrap(), 2 | 1 << 15);
+
+        // Roll back and pop again.
+        vring.push_back();
+        assert_eq!(vring.get_avail_idx(&sys_space).unwrap(), 1 << 15);
+        let elem = vring.pop_avail(&sys_space, 0).unwrap();
+        assert_eq!(elem.index, 1);
+
+        // Buffer id which is not in use can not be used.
+        assert!(vring.add_used(&sys_space, 0, 0x200).is_err());
+        assert!(vring.add_used(&sys_space, QUEUE_SIZE, 0x200).is_err());
+
+        vring.add_used(&sys_space, 1, 0x200).unwrap();
+        let used = get_desc(&sys_space, 0);
+        assert_eq!(used.id, 1);
+        assert_eq!(used.len, 0x200);
+        assert_eq!(
+            used.flags,
+            VRING_PACKED_DESC_F_AVAIL | VRING_PACKED_DESC_F_USED
+        );
+        assert_eq!(vring.next_used.0, 2);
+        assert!(vring.add_used(&sys_space, 1, 0x200).is_err());
+    }
+
+    #[test]
+    fn test_packed_wrap_around() {
+        let sys_space = address_space_init();
+        let mut vring = create_vring(&sys_space);
+
+        // Use up the first round of the ring with buffers of one descriptor.
+        for i in 0..QUEUE_SIZE {
+            set_desc(&sys_space, i, BUFFER, 0x100, i, avail_flags(true));
+        }
+        assert_eq!(vring.avail_ring_len(&sys_space).unwrap(), QUEUE_SIZE);
+        for i in 0..QUEUE_SIZE {
+            let elem = vring.pop_avail(&sys_space, 0).unwrap();
+            assert_eq!(elem.index, i);
+        }
+        assert_eq!(vring.get_avail_idx(&sys_space).unwrap(), 0);
+        // The descriptors of the last round are not available in the new round.
+        assert_eq!(vring.pop_avail(&sys_space, 0).unwrap().desc_num, 0);
+
+        // Complete the buffers out of order.
+        for i in (0..QUEUE_SIZE).rev() {
+            vring.add_used(&sys_space, i, 0).unwrap();
+        }
+        assert_eq!(get_desc(&sys_space, 0).id, QUEUE_SIZE - 1);
+        assert!(!vring.used_wrap_counter);
+
+        // The second round of the ring.
+        set_desc(&sys_space, 0, BUFFER, 0x100, 2, avail_flags(false));
+        let elem = vring.pop_avail(&sys_space, 0).unwrap();
+        assert_eq!(elem.index, 2);
+        vring.add_used(&sys_space, 2, 0).unwrap();
+        // Used descriptors of the second round have both flags cleared.
+        assert_eq!(get_desc(&sys_space, 0).flags, 0);
+    }
+
+    #[test]
+    fn test_packed_queue_config_restore() {
+        let sys_space = address_space_init();
+        let mut vring = create_vring(&sys_space);
+
+        for i in 0..QUEUE_SIZE {
+            set_desc(&sys_space, i, BUFFER, 0x100, i, avail_flags(true));
+        }
+        for _ in 0..QUEUE_SIZE - 1 {
+            vring.pop_avail(&sys_space, 0).unwrap();
+        }
+        // The buffers in flight are got again from the ring after restore.
+        vring.add_used(&sys_space, 0, 0).unwrap();
+        vring.check_inflight().unwrap();
+        let mut restored = PackedVring::new(vring.get_queue_config());
+        assert_eq!(
+            restored.get_inflight_element(&sys_space, 2).unwrap().index,
+            2
+        );
+        let elem = restored.get_inflight_element(&sys_space, 1).unwrap();
+        assert_eq!(elem.index, 1);
+        assert_eq!(elem.out_iovec[0].len, 0x100);
+        assert!(restored.get_inflight_element(&sys_space, 0).is_err());
+        restored.add_used(&sys_space, 2, 0).unwrap();
+        restored.add_used(&sys_space, 1, 0).unwrap();
+        assert_eq!(restored.next_used.0, 3);
+
+        // The buffer used out of order overwrites the ring entry of buffer 1 in flight.
+        vring.add_used(&sys_space, 2, 0).unwrap();
+        assert!(vring.check_inflight().is_err());
+        vring.add_used(&sys_space, 1, 0).unwrap();
+        vring.check_inflight().unwrap();
+
+        // The wrap counters are restored from the saved indexes.
+        let elem = vring.pop_avail(&sys_space, 0).unwrap();
+        vring.add_used(&sys_space, elem.index, 0).unwrap();
+        assert!(!vring.avail_wrap_counter);
+        assert!(!vring.used_wrap_counter);
+        let config = vring.get_queue_config();
+        assert_eq!(config.next_avail.0, VRING_PACKED_SAVED_WRAP_FLIPPED);
+        let restored = PackedVring::new(config);
+        assert_eq!(restored.next_avail.0, 0);
+        assert_eq!(restored.next_used.0, 0);
+        assert!(!restored.avail_wrap_counter);
+        assert!(!restored.used_wrap_counter);
+
+        // The configuration of a new queue starts with wrap counters set.
+        let vring = create_vring(&sys_space);
+        assert!(vring.avail_wrap_counter);
+        assert!(vring.used_wrap_counter);
+    }
+
+    #[test]
+    fn test_packed_indirect_desc() {
+        let sys_space = address_space_init();
+        let mut vring = create_vring(&sys_space);
+
+        let table = [
+            PackedVringDesc {
+                addr: GuestAddress(BUFFER),
+                len: 0x10,
+                id: 0,
+                flags: 0,
+            },
+            PackedVringDesc {
+                addr: GuestAddress(BUFFER + 0x10),
+                len: 0x1000,
+                id: 0,
+                flags: VIRTQ_DESC_F_WRITE,
+            },
+            PackedVringDesc {
+                addr: GuestAddress(BUFFER + 0x1010),
+                len: 0x1,
+                id: 0,
+                flags: VIRTQ_DESC_F_WRITE,
+            },
+        ];
+        for (i, desc) in table.iter().enumerate() {
+            sys_space
+                .write_object(
+                    desc,
+                    GuestAddress(INDIRECT_TABLE + i as u64 * PACKED_DESCRIPTOR_LEN),
+                )
+                .unwrap();
+        }
+        set_desc(
+            &sys_space,
+            0,
+            INDIRECT_TABLE,
+            (PACKED_DESCRIPTOR_LEN * 3) as u32,
+            3,
+            avail_flags(true) | VIRTQ_DESC_F_INDIRECT,
+        );
+        let elem = vring.pop_avail(&sys_space, 0).unwrap();
+        assert_eq!(elem.index, 3);
+        assert_eq!(elem.desc_num, 3);
+        assert_eq!(elem.out_iovec.len(), 1);
+        assert_eq!(elem.in_iovec.len(), 2);
+        assert_eq!(Element::iovec_size(&elem.in_iovec), 0x1001);
+
+        // The indirect descriptor only takes one slot of the ring.
+        vring.add_used(&sys_space, 3, 0x1001).unwrap();
+        assert_eq!(vring.next_used.0, 1);
+
+        // INDIRECT and NEXT flag can not be used together.
+        set_desc(
+            &sys_space,
+            1,
+            INDIRECT_TABLE,
+            (PACKED_DESCRIPTOR_LEN * 3) as u32,
+            0,
+            avail_flags(true) | VIRTQ_DESC_F_INDIRECT | VIRTQ_DESC_F_NEXT,
+        );
+        assert!(vring.pop_avail(&sys_space, 0).is_err());
+    }
+
+    #[test]
+    fn test_packed_event_suppression() {
+        let sys_space = address_space_init();
+        let mut vring = create_vring(&sys_space);
+        let features = 1_u64 << VIRTIO_F_RING_EVENT_IDX;
+
+        // Device event suppression.
+        vring.suppress_queue_notify(&sys_space, 0, true).unwrap();
+        let event = sys_space
+            .read_object::<PackedVringEvent>(GuestAddress(DEVICE_AREA))
+            .unwrap();
+        assert_eq!(event.flags, VRING_PACKED_EVENT_FLAG_DISABLE);
+        vring.suppress_queue_notify(&sys_space, 0, false).unwrap();
+        let event = sys_space
+            .read_object::<PackedVringEvent>(GuestAddress(DEVICE_AREA))
+            .unwrap();
+        assert_eq!(event.flags, VRING_PACKED_EVENT_FLAG_ENABLE);
+        vring
+            .suppress_queue_notify(&sys_space, features, false)
+            .unwrap();
+        let event = sys_space
+            .read_object::<PackedVringEvent>(GuestAddress(DEVICE_AREA))
+            .unwrap();
+        assert_eq!(event.flags, VRING_PACKED_EVENT_FLAG_DESC);
+        assert_eq!(event.off_wrap, 1 << 15);
+
+        // Driver event suppression.
+        for i in 0..2 {
+            set_desc(&sys_space, i, BUFFER, 0x100, i, avail_flags(true));
+            vring.pop_avail(&sys_space, features).unwrap();
+        }
+        set_driver_event(&sys_space, 0, VRING_PACKED_EVENT_FLAG_DISABLE);
+        vring.add_used(&sys_space, 0, 0).unwrap();
+        assert!(!vring.should_notify(&sys_space, features));
+
+        set_driver_event(&sys_space, 0, VRING_PACKED_EVENT_FLAG_ENABLE);
+        assert!(vring.should_notify(&sys_space, features));
+
+        // Notify only when the used descriptor at offset 1 is written.
+        set_driver_event(&sys_space, 1 | 1 << 15, VRING_PACKED_EVENT_FLAG_DESC);
+        assert!(!vring.should_notify(&sys_space, features));
+        vring.add_used(&sys_space, 1, 0).unwrap();
+        assert!(vring.should_notify(&sys_space, features));
+    }
+}
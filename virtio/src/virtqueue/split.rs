@@ -23,8 +23,8 @@ use log::{error, warn};
 use util::byte_code::ByteCode;
 
 use super::{
-    checked_offset_mem, ElemIovec, Element, VringOps, INVALID_VECTOR_NUM, VIRTQ_DESC_F_INDIRECT,
-    VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE,
+    checked_offset_mem, ElemIovec, Element, VringOps, DESC_CHAIN_MAX_TOTAL_LEN, INVALID_VECTOR_NUM,
+    VIRTQ_DESC_F_INDIRECT, VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE,
 };
 use crate::{virtio_has_feature, VirtioError, VIRTIO_F_RING_EVENT_IDX};
 
@@ -33,8 +33,6 @@ const VRING_AVAIL_F_NO_INTERRUPT: u16 = 1;
 /// When guest produces a buffer, don't notify the host.
 const VRING_USED_F_NO_NOTIFY: u16 = 1;
 
-/// Max total len of a descriptor chain.
-const DESC_CHAIN_MAX_TOTAL_LEN: u64 = 1u64 << 32;
 /// The length of used element.
 const USEDELEM_LEN: u64 = size_of::<UsedElem>() as u64;
 /// The length of avail element.
@@ -80,13 +78,13 @@ pub struct QueueConfig {
     /// Interrupt vector index of the queue for msix
     pub vector: u16,
     /// The next index which can be popped in the available vring.
-    next_avail: Wrapping<u16>,
+    pub(super) next_avail: Wrapping<u16>,
     /// The next index which can be pushed in the used vring.
-    next_used: Wrapping<u16>,
+    pub(super) next_used: Wrapping<u16>,
     /// The index of last descriptor used which has triggered interrupt.
-    last_signal_used: Wrapping<u16>,
+    pub(super) last_signal_used: Wrapping<u16>,
     /// The last_signal_used is valid or not.
-    signal_used_valid: bool,
+    pub(super) signal_used_valid: bool,
 }
 
 impl QueueConfig {
@@ -762,6 +760,27 @@ impl VringOps for SplitVring {
         Ok(element)
     }
 
+    fn get_inflight_element(&mut self, sys_mem: &Arc<AddressSpace>, index: u16) -> Result<Element> {
+        let desc = SplitVringDesc::new(
+            sys_mem,
+            self.addr_cache.desc_table_host,
+            self.actual_size(),
+            index,
+            &mut self.cache,
+        )?;
+        let desc_info = DescInfo {
+            table_host: self.addr_cache.desc_table_host,
+            size: self.actual_size(),
+            index,
+            desc,
+        };
+        let mut element = Element::new(0);
+        SplitVringDesc::get_element(sys_mem, &desc_info, &mut self.cache, &mut element)
+            .with_context(|| format!("Failed to get in-flight element {}", index))?;
+
+        Ok(element)
+    }
+
     fn push_back(&mut self) {
         self.next_avail -= Wrapping(1);
     }
@@ -833,6 +852,11 @@ impl VringOps for SplitVring {
         config
     }
 
+    fn check_inflight(&self) -> Result<()> {
+        // The in flight buffers can be got again by `get_inflight_element`.
+        Ok(())
+    }
+
     /// The number of descriptor chains in the available ring.
     fn avail_ring_len(&mut self, sys_mem: &Arc<AddressSpace>) -> Result<u16> {
         let avail_idx = self.get_avail_idx(sys_mem).map(Wrapping)?;
@@ -1047,7 +1071,7 @@ mod tests {
         let queue = Queue::new(queue_config, 0);
         assert!(queue.is_err());
         let queue = Queue::new(queue_config, QUEUE_TYPE_PACKED_VRING);
-        assert!(queue.is_err());
+        assert!(queue.is_ok());
 
         // it is valid
         queue_config.desc_table = GuestAddress(0);
@@ -1359,6 +1383,16 @@ mod tests {
         assert_eq!(event_idx, 1);
         let avail_idx = vring.get_avail_idx(&sys_space).unwrap();
         assert_eq!(avail_idx, 1);
+
+        // the popped descriptor chain can be assembled again by its head index
+        let next_avail = vring.next_avail;
+        let inflight = vring.get_inflight_element(&sys_space, 0).unwrap();
+        assert_eq!(inflight.index, elem.index);
+        assert_eq!(inflight.desc_num, 3);
+        assert_eq!(inflight.out_iovec.len(), 1);
+        assert_eq!(inflight.in_iovec.len(), 2);
+        assert_eq!(vring.next_avail, next_avail);
+        assert!(vring.get_inflight_element(&sys_space, QUEUE_SIZE).is_err());
     }
 
     #[test]
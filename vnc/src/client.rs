@@ -13,6 +13,7 @@
 use crate::{
     auth::AuthState,
     console::DisplayMouse,
+    encoding::enc_zlib::ZlibStreams,
     pixman::{bytes_per_pixel, get_image_height, get_image_width, PixelFormat},
     round_up_div,
     server::VncServer,
@@ -53,9 +54,9 @@ const NUM_OF_COLORMAP: u16 = 256;
 // VNC encodings types.
 pub const ENCODING_RAW: i32 = 0;
 pub const ENCODING_HEXTILE: i32 = 5;
-const ENCODING_ZLIB: i32 = 6;
-const ENCODING_TIGHT: i32 = 7;
-const ENCODING_ZRLE: i32 = 16;
+pub const ENCODING_ZLIB: i32 = 6;
+pub const ENCODING_TIGHT: i32 = 7;
+pub const ENCODING_ZRLE: i32 = 16;
 const ENCODING_ZYWRLE: i32 = 17;
 const ENCODING_DESKTOPRESIZE: i32 = -223;
 pub const ENCODING_RICH_CURSOR: i32 = -239;
@@ -297,6 +298,8 @@ pub struct ClientState {
     pub conn_state: Arc<Mutex<ConnState>>,
     /// Identify the image update area.
     pub dirty_bitmap: Arc<Mutex<Bitmap<u64>>>,
+    /// Zlib streams of compressed encodings.
+    pub zlib_streams: Arc<Mutex<ZlibStreams>>,
 }
 
 impl ClientState {
@@ -313,6 +316,7 @@ impl ClientState {
                 MAX_WINDOW_HEIGHT as usize
                     * round_up_div(DIRTY_WIDTH_BITS as u64, u64::BITS as u64) as usize,
             ))),
+            zlib_streams: Arc::new(Mutex::new(ZlibStreams::default())),
         }
     }
 }
@@ -864,8 +868,8 @@ impl ClientIoHandler {
                     locked_dpm.enc = enc;
                 }
                 ENCODING_ZYWRLE => {
+                    // Zywrle is not supported, fall back to the other encodings.
                     locked_dpm.feature |= 1 << VncFeatures::VncFeatureZywrle as usize;
-                    locked_dpm.enc = enc;
                 }
                 ENCODING_DESKTOPRESIZE => {
                     locked_dpm.feature |= 1 << VncFeatures::VncFeatureResize as usize;
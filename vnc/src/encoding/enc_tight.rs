@@ -0,0 +1,403 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use crate::client::{DisplayMode, Rectangle, ENCODING_TIGHT};
+use crate::encoding::enc_zlib::{ZlibStream, TIGHT_STREAM_NUM};
+use crate::vnc::{framebuffer_upadate, get_rect_pixels, write_single_pixel};
+use anyhow::Result;
+use log::error;
+use std::{
+    cmp,
+    collections::{hash_map::Entry, HashMap},
+};
+use util::pixman::pixman_image_t;
+
+/// Max width of rectangle.
+const TIGHT_MAX_RECT_WIDTH: i32 = 2048;
+/// Max pixels of rectangle.
+const TIGHT_MAX_RECT_SIZE: i32 = 65536;
+/// Data shorter than it is sent without compression.
+const TIGHT_MIN_TO_COMPRESS: usize = 12;
+/// Max number of colors in palette.
+const TIGHT_MAX_PALETTE: usize = 256;
+/// Compression control of tight.
+const TIGHT_FILL: u8 = 0x80;
+const TIGHT_EXPLICIT_FILTER: u8 = 0x40;
+/// Filter type of basic compression.
+const TIGHT_FILTER_PALETTE: u8 = 0x01;
+const TIGHT_FILTER_GRADIENT: u8 = 0x02;
+/// Zlib stream used by each filter.
+const TIGHT_STREAM_COPY: usize = 0;
+const TIGHT_STREAM_MONO: usize = 1;
+const TIGHT_STREAM_PALETTE: usize = 2;
+const TIGHT_STREAM_GRADIENT: usize = 3;
+/// Max average prediction error of each color component to use gradient filter.
+const TIGHT_GRADIENT_THRESHOLD: usize = 16;
+
+/// Compress data by tight algorithm before sending.
+/// Rectangles are split up into subrectangles of no more than 2048 pixels
+/// wide and 65536 pixels in total, each of them is filled with one color or
+/// filtered by palette or gradient filter, then compressed by one of the four
+/// zlib streams of the client. Return the number of subrectangles.
+///
+/// # Arguments
+///
+/// * `image` - pointer to the data need to be send.
+/// * `rect` - dirty area of image.
+/// * `client_dpm` - Output mode information of client display.
+/// * `streams` - tight zlib streams of the client.
+/// * `buf` - send buffer.
+pub fn tight_send_framebuffer_update(
+    image: *mut pixman_image_t,
+    rect: &Rectangle,
+    client_dpm: &DisplayMode,
+    streams: &mut [ZlibStream; TIGHT_STREAM_NUM],
+    buf: &mut Vec<u8>,
+) -> i32 {
+    let start = buf.len();
+    let max_w = cmp::min(rect.w, TIGHT_MAX_RECT_WIDTH);
+    let max_h = cmp::max(TIGHT_MAX_RECT_SIZE / cmp::max(max_w, 1), 1);
+    let mut n_rects = 0;
+    for j in (0..rect.h).step_by(max_h as usize) {
+        for i in (0..rect.w).step_by(max_w as usize) {
+            let sub_rect = Rectangle::new(
+                rect.x + i,
+                rect.y + j,
+                cmp::min(max_w, rect.w - i),
+                cmp::min(max_h, rect.h - j),
+            );
+            framebuffer_upadate(
+                sub_rect.x,
+                sub_rect.y,
+                sub_rect.w,
+                sub_rect.h,
+                ENCODING_TIGHT,
+                buf,
+            );
+            let pixels = get_rect_pixels(image, &sub_rect);
+            if let Err(e) =
+                compress_each_rect(&pixels, sub_rect.w as usize, client_dpm, streams, buf)
+            {
+                error!("Tight encoding: {:?}", e);
+                buf.truncate(start);
+                return -1;
+            }
+            n_rects += 1;
+        }
+    }
+    n_rects
+}
+
+/// Compress the subrectangle by tight algorithm.
+///
+/// # Arguments
+///
+/// * `pixels` - pixels of the subrectangle.
+/// * `width` - width of the subrectangle.
+/// * `client_dpm` - Output mode information of client display.
+/// * `streams` - tight zlib streams of the client.
+/// * `buf` - send buffer.
+fn compress_each_rect(
+    pixels: &[u32],
+    width: usize,
+    client_dpm: &DisplayMode,
+    streams: &mut [ZlibStream; TIGHT_STREAM_NUM],
+    buf: &mut Vec<u8>,
+) -> Result<()> {
+    let rgb = is_rgb_tpixel(client_dpm);
+    let mut palette: Vec<u32> = Vec::new();
+    let mut index: HashMap<u32, u8> = HashMap::new();
+    for &color in pixels {
+        if let Entry::Vacant(e) = index.entry(color) {
+            if palette.len() == TIGHT_MAX_PALETTE {
+                palette.clear();
+                break;
+            }
+            e.insert(palette.len() as u8);
+            palette.push(color);
+        }
+    }
+
+    let mut data: Vec<u8> = Vec::new();
+    let stream_id = match palette.len() {
+        1 => {
+            buf.push(TIGHT_FILL);
+            write_tpixel(palette[0], client_dpm, rgb, buf);
+            return Ok(());
+        }
+        2 => {
+            for row in pixels.chunks(width) {
+                for bits in row.chunks(8) {
+                    let mut byte: u8 = 0;
+                    for (i, color) in bits.iter().enumerate() {
+                        byte |= index[color] << (7 - i);
+                    }
+                    data.push(byte);
+                }
+            }
+            TIGHT_STREAM_MONO
+        }
+        0 => {
+            if rgb && gradient_filter(pixels, width, &mut data) {
+                TIGHT_STREAM_GRADIENT
+            } else {
+                data.clear();
+                for color in pixels {
+                    write_tpixel(*color, client_dpm, rgb, &mut data);
+                }
+                TIGHT_STREAM_COPY
+            }
+        }
+        _ => {
+            for color in pixels {
+                data.push(index[color]);
+            }
+            TIGHT_STREAM_PALETTE
+        }
+    };
+
+    match stream_id {
+        TIGHT_STREAM_COPY => buf.push((stream_id as u8) << 4),
+        TIGHT_STREAM_GRADIENT => {
+            buf.push((stream_id as u8) << 4 | TIGHT_EXPLICIT_FILTER);
+            buf.push(TIGHT_FILTER_GRADIENT);
+        }
+        _ => {
+            buf.push((stream_id as u8) << 4 | TIGHT_EXPLICIT_FILTER);
+            buf.push(TIGHT_FILTER_PALETTE);
+            buf.push((palette.len() - 1) as u8);
+            for color in &palette {
+                write_tpixel(*color, client_dpm, rgb, buf);
+            }
+        }
+    }
+
+    if data.len() < TIGHT_MIN_TO_COMPRESS {
+        buf.append(&mut data);
+        return Ok(());
+    }
+    let mut zlib_buf: Vec<u8> = Vec::new();
+    streams[stream_id].compress(&data, &mut zlib_buf)?;
+    write_compact_length(zlib_buf.len(), buf);
+    buf.append(&mut zlib_buf);
+    Ok(())
+}
+
+/// TPIXEL is three bytes in order of red, green and blue if the client pixel
+/// is 32 bits with 8 bits of each color.
+fn is_rgb_tpixel(client_dpm: &DisplayMode) -> bool {
+    let pf = &client_dpm.pf;
+    pf.pixel_bits == 32
+        && pf.depth == 24
+        && pf.red.max == 0xff
+        && pf.green.max == 0xff
+        && pf.blue.max == 0xff
+}
+
+/// Write the pixel to client in TPIXEL format.
+fn write_tpixel(color: u32, client_dpm: &DisplayMode, rgb: bool, buf: &mut Vec<u8>) {
+    if rgb {
+        buf.push((color >> 16) as u8);
+        buf.push((color >> 8) as u8);
+        buf.push(color as u8);
+    } else {
+        write_single_pixel(color, client_dpm, buf);
+    }
+}
+
+/// Write the length of compressed data in one to three bytes, seven bits per
+/// byte, the highest bit indicates that there is another byte.
+fn write_compact_length(len: usize, buf: &mut Vec<u8>) {
+    if len < 0x80 {
+        buf.push(len as u8);
+    } else if len < 0x4000 {
+        buf.push((len & 0x7f) as u8 | 0x80);
+        buf.push((len >> 7) as u8);
+    } else {
+        buf.push((len & 0x7f) as u8 | 0x80);
+        buf.push(((len >> 7) & 0x7f) as u8 | 0x80);
+        buf.push((len >> 14) as u8);
+    }
+}
+
+/// Filter the pixels by gradient filter, each color component is sent as the
+/// difference from the value predicted by its left, upper and upper left
+/// neighbours. Return false if the image is not smooth enough to benefit from it.
+///
+/// # Arguments
+///
+/// * `pixels` - pixels of the subrectangle.
+/// * `width` - width of the subrectangle.
+/// * `data` - filtered data.
+fn gradient_filter(pixels: &[u32], width: usize, data: &mut Vec<u8>) -> bool {
+    let component = |color: u32, c: usize| -> i32 { ((color >> (16 - 8 * c)) & 0xff) as i32 };
+    let mut error: usize = 0;
+    for (i, &color) in pixels.iter().enumerate() {
+        let (x, y) = (i % width, i / width);
+        for c in 0..3 {
+            let left = if x > 0 {
+                component(pixels[i - 1], c)
+            } else {
+                0
+            };
+            let upper = if y > 0 {
+                component(pixels[i - width], c)
+            } else {
+                0
+            };
+            let upper_left = if x > 0 && y > 0 {
+                component(pixels[i - width - 1], c)
+            } else {
+                0
+            };
+            let predicted = (left + upper - upper_left).clamp(0, 0xff);
+            let diff = component(color, c) - predicted;
+            error += diff.unsigned_abs() as usize;
+            data.push(diff as u8);
+        }
+    }
+    error < pixels.len() * 3 * TIGHT_GRADIENT_THRESHOLD
+}
+
+#[cfg(test)]
+mod tests {
+    use super::tight_send_framebuffer_update;
+    use crate::{
+        client::{DisplayMode, Rectangle, ENCODING_TIGHT},
+        encoding::{
+            enc_zlib::ZlibStreams,
+            test_hextile_image_data::{
+                IMAGE_DATA_MULTI_PIXELS, IMAGE_DATA_SINGLE_PIXEL, IMAGE_DATA_TWO_PIXEL,
+            },
+            test_tight_image_data::{
+                IMAGE_DATA_GRADIENT, TARGET_DATA_TIGHT_GRADIENT, TARGET_DATA_TIGHT_MULTI_PIXELS,
+                TARGET_DATA_TIGHT_SINGLE_PIXEL, TARGET_DATA_TIGHT_TWO_PIXEL,
+            },
+        },
+        pixman::{create_pixman_image, PixelFormat},
+    };
+    use flate2::{Decompress, FlushDecompress};
+    use util::pixman::pixman_format_code_t;
+    fn color_init() -> PixelFormat {
+        let mut pf = PixelFormat::default();
+        pf.red.set_color_info(16, 255);
+        pf.green.set_color_info(8, 255);
+        pf.blue.set_color_info(0, 255);
+        pf.pixel_bits = 32;
+        pf.pixel_bytes = 4;
+        pf.depth = 24;
+        pf
+    }
+
+    fn tight_encode(image_data: &[u8], image_width: i32, image_height: i32) -> Vec<u8> {
+        let pf = color_init();
+        let client_dpm = DisplayMode::new(ENCODING_TIGHT, false, false, pf);
+        let mut streams = ZlibStreams::default();
+        let image = create_pixman_image(
+            pixman_format_code_t::PIXMAN_x8r8g8b8,
+            image_width,
+            image_height,
+            image_data.as_ptr() as *mut u32,
+            image_width * 4,
+        );
+        let mut buf: Vec<u8> = Vec::new();
+        let rect = Rectangle::new(0, 0, image_width, image_height);
+        assert_eq!(
+            tight_send_framebuffer_update(image, &rect, &client_dpm, &mut streams.tight, &mut buf),
+            1
+        );
+        buf
+    }
+
+    /// Replace the compact length and compressed data after `head_len` bytes
+    /// with the decompressed data.
+    fn tight_decompress(buf: &[u8], head_len: usize) -> Vec<u8> {
+        let mut pos = head_len;
+        let mut len: usize = 0;
+        for i in 0..3 {
+            let byte = buf[pos];
+            pos += 1;
+            if i == 2 {
+                len |= (byte as usize) << 14;
+                break;
+            }
+            len |= ((byte & 0x7f) as usize) << (7 * i);
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        assert_eq!(buf.len(), pos + len);
+
+        let mut out = buf[..head_len].to_vec();
+        out.reserve(65536);
+        let mut decoder = Decompress::new(true);
+        decoder
+            .decompress_vec(&buf[pos..], &mut out, FlushDecompress::Sync)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn test_tight_send_framebuffer_single_pixel() {
+        let buf = tight_encode(&IMAGE_DATA_SINGLE_PIXEL, 32, 32);
+        assert_eq!(buf, TARGET_DATA_TIGHT_SINGLE_PIXEL);
+    }
+
+    #[test]
+    fn test_tight_send_framebuffer_two_pixels() {
+        let buf = tight_encode(&IMAGE_DATA_TWO_PIXEL, 40, 40);
+        // Header, compression control, filter and palette of two colors.
+        assert_eq!(tight_decompress(&buf, 21), TARGET_DATA_TIGHT_TWO_PIXEL);
+    }
+
+    #[test]
+    fn test_tight_send_framebuffer_multi_pixels() {
+        let buf = tight_encode(&IMAGE_DATA_MULTI_PIXELS, 40, 40);
+        // Header, compression control, filter and palette of four colors.
+        assert_eq!(tight_decompress(&buf, 27), TARGET_DATA_TIGHT_MULTI_PIXELS);
+    }
+
+    #[test]
+    fn test_tight_send_framebuffer_gradient() {
+        let buf = tight_encode(&IMAGE_DATA_GRADIENT, 20, 20);
+        // Header, compression control and filter.
+        assert_eq!(tight_decompress(&buf, 14), TARGET_DATA_TIGHT_GRADIENT);
+    }
+
+    #[test]
+    fn test_tight_split_rect() {
+        let pf = color_init();
+        let client_dpm = DisplayMode::new(ENCODING_TIGHT, false, false, pf);
+        let mut streams = ZlibStreams::default();
+        let image_data = vec![0u32; 4096 * 32];
+        let image = create_pixman_image(
+            pixman_format_code_t::PIXMAN_x8r8g8b8,
+            4096,
+            32,
+            image_data.as_ptr() as *mut u32,
+            4096 * 4,
+        );
+        let mut buf: Vec<u8> = Vec::new();
+        let rect = Rectangle::new(0, 0, 4096, 32);
+        assert_eq!(
+            tight_send_framebuffer_update(image, &rect, &client_dpm, &mut streams.tight, &mut buf),
+            2
+        );
+        // Two subrectangles filled with one color.
+        assert_eq!(buf.len(), 2 * 16);
+        assert_eq!(buf[..8], [0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x20]);
+        assert_eq!(
+            buf[16..24],
+            [0x08, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x20]
+        );
+    }
+}
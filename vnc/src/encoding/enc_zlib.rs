@@ -0,0 +1,179 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use crate::client::{DisplayMode, Rectangle, ENCODING_ZLIB};
+use crate::pixman::{bytes_per_pixel, get_image_data, get_image_stride};
+use crate::vnc::{framebuffer_upadate, write_pixel};
+use anyhow::{anyhow, Result};
+use flate2::{Compress, Compression, FlushCompress};
+use log::error;
+use util::pixman::pixman_image_t;
+
+/// Number of zlib streams used by tight encoding.
+pub const TIGHT_STREAM_NUM: usize = 4;
+
+/// Deflate stream which lives as long as the client connection. The client
+/// keeps the matching inflate stream, so the dictionary built by the previous
+/// updates is reused by the following ones.
+pub struct ZlibStream {
+    compress: Compress,
+}
+
+impl Default for ZlibStream {
+    fn default() -> Self {
+        ZlibStream {
+            compress: Compress::new(Compression::default(), true),
+        }
+    }
+}
+
+impl ZlibStream {
+    /// Compress the data and append the output to `buf`. The stream is
+    /// synchronized so that the client can decode all the data sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - data to be compressed.
+    /// * `buf` - output buffer.
+    pub fn compress(&mut self, data: &[u8], buf: &mut Vec<u8>) -> Result<usize> {
+        let start = buf.len();
+        let mut consumed = 0;
+        loop {
+            buf.reserve(data.len() - consumed + 64);
+            let total_in = self.compress.total_in();
+            self.compress
+                .compress_vec(&data[consumed..], buf, FlushCompress::Sync)
+                .map_err(|e| anyhow!("Failed to compress data: {:?}", e))?;
+            consumed += (self.compress.total_in() - total_in) as usize;
+            // The output has been flushed completely if there is space left.
+            if consumed == data.len() && buf.len() < buf.capacity() {
+                break;
+            }
+        }
+        Ok(buf.len() - start)
+    }
+}
+
+/// Persistent zlib streams of one vnc client.
+#[derive(Default)]
+pub struct ZlibStreams {
+    /// Stream of zlib encoding.
+    pub zlib: ZlibStream,
+    /// Stream of zrle encoding.
+    pub zrle: ZlibStream,
+    /// Streams of tight encoding.
+    pub tight: [ZlibStream; TIGHT_STREAM_NUM],
+}
+
+/// Send the raw pixel data compressed by the zlib stream.
+///
+/// # Arguments
+///
+/// * `image` - pointer to the data need to be send.
+/// * `rect` - dirty area of image.
+/// * `client_dpm` - Output mode information of client display.
+/// * `stream` - zlib stream of the client.
+/// * `buf` - send buffer.
+pub fn zlib_send_framebuffer_update(
+    image: *mut pixman_image_t,
+    rect: &Rectangle,
+    client_dpm: &DisplayMode,
+    stream: &mut ZlibStream,
+    buf: &mut Vec<u8>,
+) -> i32 {
+    let stride = get_image_stride(image);
+    let mut data_ptr = get_image_data(image) as *mut u8;
+    data_ptr = (data_ptr as usize
+        + (rect.y * stride) as usize
+        + rect.x as usize * bytes_per_pixel()) as *mut u8;
+    let copy_bytes = rect.w as usize * bytes_per_pixel();
+
+    let mut raw_buf: Vec<u8> = Vec::new();
+    for _i in 0..rect.h {
+        write_pixel(data_ptr, copy_bytes, client_dpm, &mut raw_buf);
+        data_ptr = (data_ptr as usize + stride as usize) as *mut u8;
+    }
+
+    let mut zlib_buf: Vec<u8> = Vec::new();
+    if let Err(e) = stream.compress(&raw_buf, &mut zlib_buf) {
+        error!("Zlib encoding: {:?}", e);
+        return -1;
+    }
+
+    framebuffer_upadate(rect.x, rect.y, rect.w, rect.h, ENCODING_ZLIB, buf);
+    buf.append(&mut (zlib_buf.len() as u32).to_be_bytes().to_vec());
+    buf.append(&mut zlib_buf);
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{zlib_send_framebuffer_update, ZlibStream};
+    use crate::{
+        client::{DisplayMode, Rectangle, ENCODING_ZLIB},
+        encoding::test_hextile_image_data::{IMAGE_DATA_MULTI_PIXELS, IMAGE_DATA_TWO_PIXEL},
+        pixman::{create_pixman_image, PixelFormat},
+    };
+    use flate2::{Decompress, FlushDecompress};
+    use util::pixman::pixman_format_code_t;
+    fn color_init() -> PixelFormat {
+        let mut pf = PixelFormat::default();
+        pf.red.set_color_info(16, 255);
+        pf.green.set_color_info(8, 255);
+        pf.blue.set_color_info(0, 255);
+        pf.pixel_bits = 32;
+        pf.pixel_bytes = 4;
+        pf.depth = 24;
+        pf
+    }
+
+    fn decompress(decoder: &mut Decompress, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(65536);
+        decoder
+            .decompress_vec(data, &mut out, FlushDecompress::Sync)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn test_zlib_send_framebuffer_update() {
+        let pf = color_init();
+        let client_dpm = DisplayMode::new(ENCODING_ZLIB, false, false, pf);
+        let mut stream = ZlibStream::default();
+        let mut decoder = Decompress::new(true);
+        let image_width: i32 = 40;
+        let image_height: i32 = 40;
+        let image_stride: i32 = 160;
+
+        for image_data in [IMAGE_DATA_TWO_PIXEL, IMAGE_DATA_MULTI_PIXELS] {
+            let image = create_pixman_image(
+                pixman_format_code_t::PIXMAN_x8r8g8b8,
+                image_width,
+                image_height,
+                image_data.as_ptr() as *mut u32,
+                image_stride,
+            );
+            let mut buf: Vec<u8> = Vec::new();
+            let rect = Rectangle::new(0, 0, image_width, image_height);
+            assert_eq!(
+                zlib_send_framebuffer_update(image, &rect, &client_dpm, &mut stream, &mut buf),
+                1
+            );
+            assert_eq!(buf[..8], [0, 0, 0, 0, 0, 40, 0, 40]);
+            assert_eq!(buf[8..12], ENCODING_ZLIB.to_be_bytes());
+            let len = u32::from_be_bytes([buf[12], buf[13], buf[14], buf[15]]) as usize;
+            assert_eq!(buf.len(), 16 + len);
+            // The second update is decoded by the same inflate stream.
+            assert_eq!(decompress(&mut decoder, &buf[16..]), image_data.to_vec());
+        }
+    }
+}
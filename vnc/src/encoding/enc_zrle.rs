@@ -0,0 +1,353 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use crate::client::{DisplayMode, Rectangle, ENCODING_ZRLE};
+use crate::encoding::enc_zlib::ZlibStream;
+use crate::round_up_div;
+use crate::vnc::{framebuffer_upadate, get_rect_pixels, write_single_pixel};
+use log::error;
+use std::{cmp, collections::HashMap, ops::Range};
+use util::pixman::pixman_image_t;
+
+/// Size of tile.
+const ZRLE_TILE_SIZE: i32 = 64;
+/// SubEncoding type of zrle.
+const ZRLE_RAW: u8 = 0;
+const ZRLE_SOLID: u8 = 1;
+const ZRLE_PLAIN_RLE: u8 = 128;
+const ZRLE_PALETTE_RLE: u8 = 128;
+/// Max number of colors in palette.
+const ZRLE_MAX_PACKED_PALETTE: usize = 16;
+const ZRLE_MAX_RLE_PALETTE: usize = 127;
+
+/// Compress data by zrle algorithm before sending.
+/// Rectangles are split up into 64 * 64 tiles, and all the tiles are
+/// compressed by the zlib stream of the client.
+///
+/// # Arguments
+///
+/// * `image` - pointer to the data need to be send.
+/// * `rect` - dirty area of image.
+/// * `client_dpm` - Output mode information of client display.
+/// * `stream` - zlib stream of the client.
+/// * `buf` - send buffer.
+pub fn zrle_send_framebuffer_update(
+    image: *mut pixman_image_t,
+    rect: &Rectangle,
+    client_dpm: &DisplayMode,
+    stream: &mut ZlibStream,
+    buf: &mut Vec<u8>,
+) -> i32 {
+    let cpixel = cpixel_range(client_dpm);
+    let mut raw_buf: Vec<u8> = Vec::new();
+    for j in (0..rect.h).step_by(ZRLE_TILE_SIZE as usize) {
+        for i in (0..rect.w).step_by(ZRLE_TILE_SIZE as usize) {
+            let sub_rect = Rectangle::new(
+                rect.x + i,
+                rect.y + j,
+                cmp::min(ZRLE_TILE_SIZE, rect.w - i),
+                cmp::min(ZRLE_TILE_SIZE, rect.h - j),
+            );
+            let pixels = get_rect_pixels(image, &sub_rect);
+            compress_each_tile(
+                &pixels,
+                sub_rect.w as usize,
+                client_dpm,
+                &cpixel,
+                &mut raw_buf,
+            );
+        }
+    }
+
+    let mut zlib_buf: Vec<u8> = Vec::new();
+    if let Err(e) = stream.compress(&raw_buf, &mut zlib_buf) {
+        error!("Zrle encoding: {:?}", e);
+        return -1;
+    }
+
+    framebuffer_upadate(rect.x, rect.y, rect.w, rect.h, ENCODING_ZRLE, buf);
+    buf.append(&mut (zlib_buf.len() as u32).to_be_bytes().to_vec());
+    buf.append(&mut zlib_buf);
+    1
+}
+
+/// Bytes of the client pixel which are sent as CPIXEL. It is only three bytes
+/// if the pixel is 32 bits and all the color bits fit in three bytes.
+///
+/// # Arguments
+///
+/// * `client_dpm` - Output mode information of client display.
+fn cpixel_range(client_dpm: &DisplayMode) -> Range<usize> {
+    let pf = &client_dpm.pf;
+    if pf.pixel_bits != 32 || pf.depth > 24 {
+        return 0..pf.pixel_bytes as usize;
+    }
+
+    let colors = [&pf.red, &pf.green, &pf.blue];
+    let fits_ls = colors.iter().all(|c| c.shift as u32 + c.bits as u32 <= 24);
+    let fits_ms = colors.iter().all(|c| c.shift >= 8);
+    // The pixel is sent in big endian only if it is converted.
+    let big_endian = client_dpm.convert && client_dpm.client_be;
+    match (fits_ls, fits_ms, big_endian) {
+        (true, _, false) | (false, true, true) => 0..3,
+        (true, _, true) | (false, true, false) => 1..4,
+        _ => 0..4,
+    }
+}
+
+/// Write the pixel to client in CPIXEL format.
+fn write_cpixel(color: u32, client_dpm: &DisplayMode, cpixel: &Range<usize>, buf: &mut Vec<u8>) {
+    let mut pixel: Vec<u8> = Vec::with_capacity(4);
+    write_single_pixel(color, client_dpm, &mut pixel);
+    buf.extend_from_slice(&pixel[cpixel.clone()]);
+}
+
+/// Write the run length which is encoded as the sum of bytes plus one.
+fn write_run_length(len: usize, buf: &mut Vec<u8>) {
+    let mut remain = len - 1;
+    while remain >= 255 {
+        buf.push(255);
+        remain -= 255;
+    }
+    buf.push(remain as u8);
+}
+
+/// Compress each tile by zrle algorithm, the subencoding which produces the
+/// smallest data is chosen.
+///
+/// # Arguments
+///
+/// * `pixels` - pixels of the tile.
+/// * `width` - width of the tile.
+/// * `client_dpm` - Output mode information of client display.
+/// * `cpixel` - bytes of client pixel sent as CPIXEL.
+/// * `buf` - send buffer.
+fn compress_each_tile(
+    pixels: &[u32],
+    width: usize,
+    client_dpm: &DisplayMode,
+    cpixel: &Range<usize>,
+    buf: &mut Vec<u8>,
+) {
+    let height = pixels.len() / width;
+    let cpixel_bytes = cpixel.len();
+
+    // Colors in order of appearance, and runs which can span rows.
+    let mut palette: Vec<u32> = Vec::new();
+    let mut index: HashMap<u32, u8> = HashMap::new();
+    let mut runs: Vec<(u32, usize)> = Vec::new();
+    for &color in pixels {
+        if palette.len() <= ZRLE_MAX_RLE_PALETTE && !index.contains_key(&color) {
+            index.insert(color, palette.len() as u8);
+            palette.push(color);
+        }
+        match runs.last_mut() {
+            Some((last, len)) if *last == color => *len += 1,
+            _ => runs.push((color, 1)),
+        }
+    }
+
+    if palette.len() == 1 {
+        buf.push(ZRLE_SOLID);
+        write_cpixel(palette[0], client_dpm, cpixel, buf);
+        return;
+    }
+
+    let run_length_bytes: usize = runs.iter().map(|(_, len)| (len - 1) / 255 + 1).sum();
+    let raw_size = pixels.len() * cpixel_bytes;
+    let plain_rle_size = runs.len() * cpixel_bytes + run_length_bytes;
+    let mut best_size = cmp::min(raw_size, plain_rle_size);
+
+    let mut packed_size = usize::MAX;
+    let mut palette_rle_size = usize::MAX;
+    if palette.len() <= ZRLE_MAX_RLE_PALETTE {
+        let palette_size = palette.len() * cpixel_bytes;
+        palette_rle_size = palette_size
+            + runs
+                .iter()
+                .map(|(_, len)| {
+                    if *len == 1 {
+                        1
+                    } else {
+                        1 + (len - 1) / 255 + 1
+                    }
+                })
+                .sum::<usize>();
+        if palette.len() <= ZRLE_MAX_PACKED_PALETTE {
+            let bits = packed_bits(palette.len());
+            packed_size = palette_size + height * round_up_div((width * bits) as u64, 8) as usize;
+        }
+        best_size = cmp::min(best_size, cmp::min(palette_rle_size, packed_size));
+    }
+
+    if best_size == packed_size {
+        buf.push(palette.len() as u8);
+        for color in &palette {
+            write_cpixel(*color, client_dpm, cpixel, buf);
+        }
+        let bits = packed_bits(palette.len());
+        for row in pixels.chunks(width) {
+            let mut byte: u8 = 0;
+            let mut n_bits = 0;
+            for color in row {
+                byte = (byte << bits) | index[color];
+                n_bits += bits;
+                if n_bits == 8 {
+                    buf.push(byte);
+                    byte = 0;
+                    n_bits = 0;
+                }
+            }
+            if n_bits > 0 {
+                buf.push(byte << (8 - n_bits));
+            }
+        }
+    } else if best_size == palette_rle_size {
+        buf.push(ZRLE_PALETTE_RLE + palette.len() as u8);
+        for color in &palette {
+            write_cpixel(*color, client_dpm, cpixel, buf);
+        }
+        for (color, len) in &runs {
+            if *len == 1 {
+                buf.push(index[color]);
+            } else {
+                buf.push(index[color] | 0x80);
+                write_run_length(*len, buf);
+            }
+        }
+    } else if best_size == plain_rle_size {
+        buf.push(ZRLE_PLAIN_RLE);
+        for (color, len) in &runs {
+            write_cpixel(*color, client_dpm, cpixel, buf);
+            write_run_length(*len, buf);
+        }
+    } else {
+        buf.push(ZRLE_RAW);
+        for color in pixels {
+            write_cpixel(*color, client_dpm, cpixel, buf);
+        }
+    }
+}
+
+/// Bits of each palette index in packed palette subencoding.
+fn packed_bits(n_colors: usize) -> usize {
+    match n_colors {
+        2 => 1,
+        3..=4 => 2,
+        _ => 4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::zrle_send_framebuffer_update;
+    use crate::{
+        client::{DisplayMode, Rectangle, ENCODING_ZRLE},
+        encoding::{
+            enc_zlib::ZlibStream,
+            test_hextile_image_data::{
+                IMAGE_DATA_MULTI_PIXELS, IMAGE_DATA_SINGLE_PIXEL, IMAGE_DATA_TWO_PIXEL,
+            },
+            test_zrle_image_data::{
+                TARGET_DATA_ZRLE_MULTI_PIXELS, TARGET_DATA_ZRLE_SINGLE_PIXEL,
+                TARGET_DATA_ZRLE_TWO_PIXEL,
+            },
+        },
+        pixman::{create_pixman_image, PixelFormat},
+    };
+    use flate2::{Decompress, FlushDecompress};
+    use util::pixman::pixman_format_code_t;
+    fn color_init() -> PixelFormat {
+        let mut pf = PixelFormat::default();
+        pf.red.set_color_info(16, 255);
+        pf.green.set_color_info(8, 255);
+        pf.blue.set_color_info(0, 255);
+        pf.pixel_bits = 32;
+        pf.pixel_bytes = 4;
+        pf.depth = 24;
+        pf
+    }
+
+    /// Encode the image and return the zrle data decompressed by the decoder.
+    fn zrle_encode(
+        image_data: &[u8],
+        image_width: i32,
+        image_height: i32,
+        stream: &mut ZlibStream,
+        decoder: &mut Decompress,
+    ) -> Vec<u8> {
+        let pf = color_init();
+        let client_dpm = DisplayMode::new(ENCODING_ZRLE, false, false, pf);
+        let image = create_pixman_image(
+            pixman_format_code_t::PIXMAN_x8r8g8b8,
+            image_width,
+            image_height,
+            image_data.as_ptr() as *mut u32,
+            image_width * 4,
+        );
+        let mut buf: Vec<u8> = Vec::new();
+        let rect = Rectangle::new(0, 0, image_width, image_height);
+        assert_eq!(
+            zrle_send_framebuffer_update(image, &rect, &client_dpm, stream, &mut buf),
+            1
+        );
+        assert_eq!(buf[8..12], ENCODING_ZRLE.to_be_bytes());
+        let len = u32::from_be_bytes([buf[12], buf[13], buf[14], buf[15]]) as usize;
+        assert_eq!(buf.len(), 16 + len);
+
+        let mut out = Vec::with_capacity(65536);
+        decoder
+            .decompress_vec(&buf[16..], &mut out, FlushDecompress::Sync)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn test_zrle_send_framebuffer_single_pixel() {
+        let mut stream = ZlibStream::default();
+        let mut decoder = Decompress::new(true);
+        let data = zrle_encode(&IMAGE_DATA_SINGLE_PIXEL, 32, 32, &mut stream, &mut decoder);
+        assert_eq!(data, TARGET_DATA_ZRLE_SINGLE_PIXEL);
+    }
+
+    #[test]
+    fn test_zrle_send_framebuffer_two_pixels() {
+        let mut stream = ZlibStream::default();
+        let mut decoder = Decompress::new(true);
+        let data = zrle_encode(&IMAGE_DATA_TWO_PIXEL, 40, 40, &mut stream, &mut decoder);
+        assert_eq!(data, TARGET_DATA_ZRLE_TWO_PIXEL);
+    }
+
+    #[test]
+    fn test_zrle_send_framebuffer_multi_pixels() {
+        let mut stream = ZlibStream::default();
+        let mut decoder = Decompress::new(true);
+        let data = zrle_encode(&IMAGE_DATA_MULTI_PIXELS, 40, 40, &mut stream, &mut decoder);
+        assert_eq!(data, TARGET_DATA_ZRLE_MULTI_PIXELS);
+    }
+
+    #[test]
+    fn test_zrle_persistent_stream() {
+        // All the updates of a client are decoded by the same inflate stream.
+        let mut stream = ZlibStream::default();
+        let mut decoder = Decompress::new(true);
+        let images: [(&[u8], i32, &[u8]); 3] = [
+            (&IMAGE_DATA_TWO_PIXEL, 40, &TARGET_DATA_ZRLE_TWO_PIXEL),
+            (&IMAGE_DATA_SINGLE_PIXEL, 32, &TARGET_DATA_ZRLE_SINGLE_PIXEL),
+            (&IMAGE_DATA_TWO_PIXEL, 40, &TARGET_DATA_ZRLE_TWO_PIXEL),
+        ];
+        for (image_data, size, target_data) in images {
+            let data = zrle_encode(image_data, size, size, &mut stream, &mut decoder);
+            assert_eq!(data, target_data);
+        }
+    }
+}
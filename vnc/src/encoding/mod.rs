@@ -11,5 +11,12 @@
 // See the Mulan PSL v2 for more details.
 
 pub mod enc_hextile;
+pub mod enc_tight;
+pub mod enc_zlib;
+pub mod enc_zrle;
 #[cfg(test)]
 mod test_hextile_image_data;
+#[cfg(test)]
+mod test_tight_image_data;
+#[cfg(test)]
+mod test_zrle_image_data;
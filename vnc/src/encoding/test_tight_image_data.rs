@@ -0,0 +1,340 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+/// Image data: Color of each pixel changes smoothly, it contains more than
+/// 256 different pixels.
+/// Width of image = 20
+/// Height of image = 20
+/// Stride of image = 80
+/// Total length is 1600 Byte.
+pub const IMAGE_DATA_GRADIENT: [u8; 1600] = [
+    0x00, 0x00, 0x00, 0x00, 0x06, 0x00, 0x0c, 0x00, 0x0c, 0x00, 0x18, 0x00, 0x12, 0x00, 0x24, 0x00,
+    0x18, 0x00, 0x30, 0x00, 0x1e, 0x00, 0x3c, 0x00, 0x24, 0x00, 0x48, 0x00, 0x2a, 0x00, 0x54, 0x00,
+    0x30, 0x00, 0x60, 0x00, 0x36, 0x00, 0x6c, 0x00, 0x3c, 0x00, 0x78, 0x00, 0x42, 0x00, 0x84, 0x00,
+    0x48, 0x00, 0x90, 0x00, 0x4e, 0x00, 0x9c, 0x00, 0x54, 0x00, 0xa8, 0x00, 0x5a, 0x00, 0xb4, 0x00,
+    0x60, 0x00, 0xc0, 0x00, 0x66, 0x00, 0xcc, 0x00, 0x6c, 0x00, 0xd8, 0x00, 0x72, 0x00, 0xe4, 0x00,
+    0x06, 0x0c, 0x00, 0x00, 0x0c, 0x0c, 0x0c, 0x00, 0x12, 0x0c, 0x18, 0x00, 0x18, 0x0c, 0x24, 0x00,
+    0x1e, 0x0c, 0x30, 0x00, 0x24, 0x0c, 0x3c, 0x00, 0x2a, 0x0c, 0x48, 0x00, 0x30, 0x0c, 0x54, 0x00,
+    0x36, 0x0c, 0x60, 0x00, 0x3c, 0x0c, 0x6c, 0x00, 0x42, 0x0c, 0x78, 0x00, 0x48, 0x0c, 0x84, 0x00,
+    0x4e, 0x0c, 0x90, 0x00, 0x54, 0x0c, 0x9c, 0x00, 0x5a, 0x0c, 0xa8, 0x00, 0x60, 0x0c, 0xb4, 0x00,
+    0x66, 0x0c, 0xc0, 0x00, 0x6c, 0x0c, 0xcc, 0x00, 0x72, 0x0c, 0xd8, 0x00, 0x78, 0x0c, 0xe4, 0x00,
+    0x0c, 0x18, 0x00, 0x00, 0x12, 0x18, 0x0c, 0x00, 0x18, 0x18, 0x18, 0x00, 0x1e, 0x18, 0x24, 0x00,
+    0x24, 0x18, 0x30, 0x00, 0x2a, 0x18, 0x3c, 0x00, 0x30, 0x18, 0x48, 0x00, 0x36, 0x18, 0x54, 0x00,
+    0x3c, 0x18, 0x60, 0x00, 0x42, 0x18, 0x6c, 0x00, 0x48, 0x18, 0x78, 0x00, 0x4e, 0x18, 0x84, 0x00,
+    0x54, 0x18, 0x90, 0x00, 0x5a, 0x18, 0x9c, 0x00, 0x60, 0x18, 0xa8, 0x00, 0x66, 0x18, 0xb4, 0x00,
+    0x6c, 0x18, 0xc0, 0x00, 0x72, 0x18, 0xcc, 0x00, 0x78, 0x18, 0xd8, 0x00, 0x7e, 0x18, 0xe4, 0x00,
+    0x12, 0x24, 0x00, 0x00, 0x18, 0x24, 0x0c, 0x00, 0x1e, 0x24, 0x18, 0x00, 0x24, 0x24, 0x24, 0x00,
+    0x2a, 0x24, 0x30, 0x00, 0x30, 0x24, 0x3c, 0x00, 0x36, 0x24, 0x48, 0x00, 0x3c, 0x24, 0x54, 0x00,
+    0x42, 0x24, 0x60, 0x00, 0x48, 0x24, 0x6c, 0x00, 0x4e, 0x24, 0x78, 0x00, 0x54, 0x24, 0x84, 0x00,
+    0x5a, 0x24, 0x90, 0x00, 0x60, 0x24, 0x9c, 0x00, 0x66, 0x24, 0xa8, 0x00, 0x6c, 0x24, 0xb4, 0x00,
+    0x72, 0x24, 0xc0, 0x00, 0x78, 0x24, 0xcc, 0x00, 0x7e, 0x24, 0xd8, 0x00, 0x84, 0x24, 0xe4, 0x00,
+    0x18, 0x30, 0x00, 0x00, 0x1e, 0x30, 0x0c, 0x00, 0x24, 0x30, 0x18, 0x00, 0x2a, 0x30, 0x24, 0x00,
+    0x30, 0x30, 0x30, 0x00, 0x36, 0x30, 0x3c, 0x00, 0x3c, 0x30, 0x48, 0x00, 0x42, 0x30, 0x54, 0x00,
+    0x48, 0x30, 0x60, 0x00, 0x4e, 0x30, 0x6c, 0x00, 0x54, 0x30, 0x78, 0x00, 0x5a, 0x30, 0x84, 0x00,
+    0x60, 0x30, 0x90, 0x00, 0x66, 0x30, 0x9c, 0x00, 0x6c, 0x30, 0xa8, 0x00, 0x72, 0x30, 0xb4, 0x00,
+    0x78, 0x30, 0xc0, 0x00, 0x7e, 0x30, 0xcc, 0x00, 0x84, 0x30, 0xd8, 0x00, 0x8a, 0x30, 0xe4, 0x00,
+    0x1e, 0x3c, 0x00, 0x00, 0x24, 0x3c, 0x0c, 0x00, 0x2a, 0x3c, 0x18, 0x00, 0x30, 0x3c, 0x24, 0x00,
+    0x36, 0x3c, 0x30, 0x00, 0x3c, 0x3c, 0x3c, 0x00, 0x42, 0x3c, 0x48, 0x00, 0x48, 0x3c, 0x54, 0x00,
+    0x4e, 0x3c, 0x60, 0x00, 0x54, 0x3c, 0x6c, 0x00, 0x5a, 0x3c, 0x78, 0x00, 0x60, 0x3c, 0x84, 0x00,
+    0x66, 0x3c, 0x90, 0x00, 0x6c, 0x3c, 0x9c, 0x00, 0x72, 0x3c, 0xa8, 0x00, 0x78, 0x3c, 0xb4, 0x00,
+    0x7e, 0x3c, 0xc0, 0x00, 0x84, 0x3c, 0xcc, 0x00, 0x8a, 0x3c, 0xd8, 0x00, 0x90, 0x3c, 0xe4, 0x00,
+    0x24, 0x48, 0x00, 0x00, 0x2a, 0x48, 0x0c, 0x00, 0x30, 0x48, 0x18, 0x00, 0x36, 0x48, 0x24, 0x00,
+    0x3c, 0x48, 0x30, 0x00, 0x42, 0x48, 0x3c, 0x00, 0x48, 0x48, 0x48, 0x00, 0x4e, 0x48, 0x54, 0x00,
+    0x54, 0x48, 0x60, 0x00, 0x5a, 0x48, 0x6c, 0x00, 0x60, 0x48, 0x78, 0x00, 0x66, 0x48, 0x84, 0x00,
+    0x6c, 0x48, 0x90, 0x00, 0x72, 0x48, 0x9c, 0x00, 0x78, 0x48, 0xa8, 0x00, 0x7e, 0x48, 0xb4, 0x00,
+    0x84, 0x48, 0xc0, 0x00, 0x8a, 0x48, 0xcc, 0x00, 0x90, 0x48, 0xd8, 0x00, 0x96, 0x48, 0xe4, 0x00,
+    0x2a, 0x54, 0x00, 0x00, 0x30, 0x54, 0x0c, 0x00, 0x36, 0x54, 0x18, 0x00, 0x3c, 0x54, 0x24, 0x00,
+    0x42, 0x54, 0x30, 0x00, 0x48, 0x54, 0x3c, 0x00, 0x4e, 0x54, 0x48, 0x00, 0x54, 0x54, 0x54, 0x00,
+    0x5a, 0x54, 0x60, 0x00, 0x60, 0x54, 0x6c, 0x00, 0x66, 0x54, 0x78, 0x00, 0x6c, 0x54, 0x84, 0x00,
+    0x72, 0x54, 0x90, 0x00, 0x78, 0x54, 0x9c, 0x00, 0x7e, 0x54, 0xa8, 0x00, 0x84, 0x54, 0xb4, 0x00,
+    0x8a, 0x54, 0xc0, 0x00, 0x90, 0x54, 0xcc, 0x00, 0x96, 0x54, 0xd8, 0x00, 0x9c, 0x54, 0xe4, 0x00,
+    0x30, 0x60, 0x00, 0x00, 0x36, 0x60, 0x0c, 0x00, 0x3c, 0x60, 0x18, 0x00, 0x42, 0x60, 0x24, 0x00,
+    0x48, 0x60, 0x30, 0x00, 0x4e, 0x60, 0x3c, 0x00, 0x54, 0x60, 0x48, 0x00, 0x5a, 0x60, 0x54, 0x00,
+    0x60, 0x60, 0x60, 0x00, 0x66, 0x60, 0x6c, 0x00, 0x6c, 0x60, 0x78, 0x00, 0x72, 0x60, 0x84, 0x00,
+    0x78, 0x60, 0x90, 0x00, 0x7e, 0x60, 0x9c, 0x00, 0x84, 0x60, 0xa8, 0x00, 0x8a, 0x60, 0xb4, 0x00,
+    0x90, 0x60, 0xc0, 0x00, 0x96, 0x60, 0xcc, 0x00, 0x9c, 0x60, 0xd8, 0x00, 0xa2, 0x60, 0xe4, 0x00,
+    0x36, 0x6c, 0x00, 0x00, 0x3c, 0x6c, 0x0c, 0x00, 0x42, 0x6c, 0x18, 0x00, 0x48, 0x6c, 0x24, 0x00,
+    0x4e, 0x6c, 0x30, 0x00, 0x54, 0x6c, 0x3c, 0x00, 0x5a, 0x6c, 0x48, 0x00, 0x60, 0x6c, 0x54, 0x00,
+    0x66, 0x6c, 0x60, 0x00, 0x6c, 0x6c, 0x6c, 0x00, 0x72, 0x6c, 0x78, 0x00, 0x78, 0x6c, 0x84, 0x00,
+    0x7e, 0x6c, 0x90, 0x00, 0x84, 0x6c, 0x9c, 0x00, 0x8a, 0x6c, 0xa8, 0x00, 0x90, 0x6c, 0xb4, 0x00,
+    0x96, 0x6c, 0xc0, 0x00, 0x9c, 0x6c, 0xcc, 0x00, 0xa2, 0x6c, 0xd8, 0x00, 0xa8, 0x6c, 0xe4, 0x00,
+    0x3c, 0x78, 0x00, 0x00, 0x42, 0x78, 0x0c, 0x00, 0x48, 0x78, 0x18, 0x00, 0x4e, 0x78, 0x24, 0x00,
+    0x54, 0x78, 0x30, 0x00, 0x5a, 0x78, 0x3c, 0x00, 0x60, 0x78, 0x48, 0x00, 0x66, 0x78, 0x54, 0x00,
+    0x6c, 0x78, 0x60, 0x00, 0x72, 0x78, 0x6c, 0x00, 0x78, 0x78, 0x78, 0x00, 0x7e, 0x78, 0x84, 0x00,
+    0x84, 0x78, 0x90, 0x00, 0x8a, 0x78, 0x9c, 0x00, 0x90, 0x78, 0xa8, 0x00, 0x96, 0x78, 0xb4, 0x00,
+    0x9c, 0x78, 0xc0, 0x00, 0xa2, 0x78, 0xcc, 0x00, 0xa8, 0x78, 0xd8, 0x00, 0xae, 0x78, 0xe4, 0x00,
+    0x42, 0x84, 0x00, 0x00, 0x48, 0x84, 0x0c, 0x00, 0x4e, 0x84, 0x18, 0x00, 0x54, 0x84, 0x24, 0x00,
+    0x5a, 0x84, 0x30, 0x00, 0x60, 0x84, 0x3c, 0x00, 0x66, 0x84, 0x48, 0x00, 0x6c, 0x84, 0x54, 0x00,
+    0x72, 0x84, 0x60, 0x00, 0x78, 0x84, 0x6c, 0x00, 0x7e, 0x84, 0x78, 0x00, 0x84, 0x84, 0x84, 0x00,
+    0x8a, 0x84, 0x90, 0x00, 0x90, 0x84, 0x9c, 0x00, 0x96, 0x84, 0xa8, 0x00, 0x9c, 0x84, 0xb4, 0x00,
+    0xa2, 0x84, 0xc0, 0x00, 0xa8, 0x84, 0xcc, 0x00, 0xae, 0x84, 0xd8, 0x00, 0xb4, 0x84, 0xe4, 0x00,
+    0x48, 0x90, 0x00, 0x00, 0x4e, 0x90, 0x0c, 0x00, 0x54, 0x90, 0x18, 0x00, 0x5a, 0x90, 0x24, 0x00,
+    0x60, 0x90, 0x30, 0x00, 0x66, 0x90, 0x3c, 0x00, 0x6c, 0x90, 0x48, 0x00, 0x72, 0x90, 0x54, 0x00,
+    0x78, 0x90, 0x60, 0x00, 0x7e, 0x90, 0x6c, 0x00, 0x84, 0x90, 0x78, 0x00, 0x8a, 0x90, 0x84, 0x00,
+    0x90, 0x90, 0x90, 0x00, 0x96, 0x90, 0x9c, 0x00, 0x9c, 0x90, 0xa8, 0x00, 0xa2, 0x90, 0xb4, 0x00,
+    0xa8, 0x90, 0xc0, 0x00, 0xae, 0x90, 0xcc, 0x00, 0xb4, 0x90, 0xd8, 0x00, 0xba, 0x90, 0xe4, 0x00,
+    0x4e, 0x9c, 0x00, 0x00, 0x54, 0x9c, 0x0c, 0x00, 0x5a, 0x9c, 0x18, 0x00, 0x60, 0x9c, 0x24, 0x00,
+    0x66, 0x9c, 0x30, 0x00, 0x6c, 0x9c, 0x3c, 0x00, 0x72, 0x9c, 0x48, 0x00, 0x78, 0x9c, 0x54, 0x00,
+    0x7e, 0x9c, 0x60, 0x00, 0x84, 0x9c, 0x6c, 0x00, 0x8a, 0x9c, 0x78, 0x00, 0x90, 0x9c, 0x84, 0x00,
+    0x96, 0x9c, 0x90, 0x00, 0x9c, 0x9c, 0x9c, 0x00, 0xa2, 0x9c, 0xa8, 0x00, 0xa8, 0x9c, 0xb4, 0x00,
+    0xae, 0x9c, 0xc0, 0x00, 0xb4, 0x9c, 0xcc, 0x00, 0xba, 0x9c, 0xd8, 0x00, 0xc0, 0x9c, 0xe4, 0x00,
+    0x54, 0xa8, 0x00, 0x00, 0x5a, 0xa8, 0x0c, 0x00, 0x60, 0xa8, 0x18, 0x00, 0x66, 0xa8, 0x24, 0x00,
+    0x6c, 0xa8, 0x30, 0x00, 0x72, 0xa8, 0x3c, 0x00, 0x78, 0xa8, 0x48, 0x00, 0x7e, 0xa8, 0x54, 0x00,
+    0x84, 0xa8, 0x60, 0x00, 0x8a, 0xa8, 0x6c, 0x00, 0x90, 0xa8, 0x78, 0x00, 0x96, 0xa8, 0x84, 0x00,
+    0x9c, 0xa8, 0x90, 0x00, 0xa2, 0xa8, 0x9c, 0x00, 0xa8, 0xa8, 0xa8, 0x00, 0xae, 0xa8, 0xb4, 0x00,
+    0xb4, 0xa8, 0xc0, 0x00, 0xba, 0xa8, 0xcc, 0x00, 0xc0, 0xa8, 0xd8, 0x00, 0xc6, 0xa8, 0xe4, 0x00,
+    0x5a, 0xb4, 0x00, 0x00, 0x60, 0xb4, 0x0c, 0x00, 0x66, 0xb4, 0x18, 0x00, 0x6c, 0xb4, 0x24, 0x00,
+    0x72, 0xb4, 0x30, 0x00, 0x78, 0xb4, 0x3c, 0x00, 0x7e, 0xb4, 0x48, 0x00, 0x84, 0xb4, 0x54, 0x00,
+    0x8a, 0xb4, 0x60, 0x00, 0x90, 0xb4, 0x6c, 0x00, 0x96, 0xb4, 0x78, 0x00, 0x9c, 0xb4, 0x84, 0x00,
+    0xa2, 0xb4, 0x90, 0x00, 0xa8, 0xb4, 0x9c, 0x00, 0xae, 0xb4, 0xa8, 0x00, 0xb4, 0xb4, 0xb4, 0x00,
+    0xba, 0xb4, 0xc0, 0x00, 0xc0, 0xb4, 0xcc, 0x00, 0xc6, 0xb4, 0xd8, 0x00, 0xcc, 0xb4, 0xe4, 0x00,
+    0x60, 0xc0, 0x00, 0x00, 0x66, 0xc0, 0x0c, 0x00, 0x6c, 0xc0, 0x18, 0x00, 0x72, 0xc0, 0x24, 0x00,
+    0x78, 0xc0, 0x30, 0x00, 0x7e, 0xc0, 0x3c, 0x00, 0x84, 0xc0, 0x48, 0x00, 0x8a, 0xc0, 0x54, 0x00,
+    0x90, 0xc0, 0x60, 0x00, 0x96, 0xc0, 0x6c, 0x00, 0x9c, 0xc0, 0x78, 0x00, 0xa2, 0xc0, 0x84, 0x00,
+    0xa8, 0xc0, 0x90, 0x00, 0xae, 0xc0, 0x9c, 0x00, 0xb4, 0xc0, 0xa8, 0x00, 0xba, 0xc0, 0xb4, 0x00,
+    0xc0, 0xc0, 0xc0, 0x00, 0xc6, 0xc0, 0xcc, 0x00, 0xcc, 0xc0, 0xd8, 0x00, 0xd2, 0xc0, 0xe4, 0x00,
+    0x66, 0xcc, 0x00, 0x00, 0x6c, 0xcc, 0x0c, 0x00, 0x72, 0xcc, 0x18, 0x00, 0x78, 0xcc, 0x24, 0x00,
+    0x7e, 0xcc, 0x30, 0x00, 0x84, 0xcc, 0x3c, 0x00, 0x8a, 0xcc, 0x48, 0x00, 0x90, 0xcc, 0x54, 0x00,
+    0x96, 0xcc, 0x60, 0x00, 0x9c, 0xcc, 0x6c, 0x00, 0xa2, 0xcc, 0x78, 0x00, 0xa8, 0xcc, 0x84, 0x00,
+    0xae, 0xcc, 0x90, 0x00, 0xb4, 0xcc, 0x9c, 0x00, 0xba, 0xcc, 0xa8, 0x00, 0xc0, 0xcc, 0xb4, 0x00,
+    0xc6, 0xcc, 0xc0, 0x00, 0xcc, 0xcc, 0xcc, 0x00, 0xd2, 0xcc, 0xd8, 0x00, 0xd8, 0xcc, 0xe4, 0x00,
+    0x6c, 0xd8, 0x00, 0x00, 0x72, 0xd8, 0x0c, 0x00, 0x78, 0xd8, 0x18, 0x00, 0x7e, 0xd8, 0x24, 0x00,
+    0x84, 0xd8, 0x30, 0x00, 0x8a, 0xd8, 0x3c, 0x00, 0x90, 0xd8, 0x48, 0x00, 0x96, 0xd8, 0x54, 0x00,
+    0x9c, 0xd8, 0x60, 0x00, 0xa2, 0xd8, 0x6c, 0x00, 0xa8, 0xd8, 0x78, 0x00, 0xae, 0xd8, 0x84, 0x00,
+    0xb4, 0xd8, 0x90, 0x00, 0xba, 0xd8, 0x9c, 0x00, 0xc0, 0xd8, 0xa8, 0x00, 0xc6, 0xd8, 0xb4, 0x00,
+    0xcc, 0xd8, 0xc0, 0x00, 0xd2, 0xd8, 0xcc, 0x00, 0xd8, 0xd8, 0xd8, 0x00, 0xde, 0xd8, 0xe4, 0x00,
+    0x72, 0xe4, 0x00, 0x00, 0x78, 0xe4, 0x0c, 0x00, 0x7e, 0xe4, 0x18, 0x00, 0x84, 0xe4, 0x24, 0x00,
+    0x8a, 0xe4, 0x30, 0x00, 0x90, 0xe4, 0x3c, 0x00, 0x96, 0xe4, 0x48, 0x00, 0x9c, 0xe4, 0x54, 0x00,
+    0xa2, 0xe4, 0x60, 0x00, 0xa8, 0xe4, 0x6c, 0x00, 0xae, 0xe4, 0x78, 0x00, 0xb4, 0xe4, 0x84, 0x00,
+    0xba, 0xe4, 0x90, 0x00, 0xc0, 0xe4, 0x9c, 0x00, 0xc6, 0xe4, 0xa8, 0x00, 0xcc, 0xe4, 0xb4, 0x00,
+    0xd2, 0xe4, 0xc0, 0x00, 0xd8, 0xe4, 0xcc, 0x00, 0xde, 0xe4, 0xd8, 0x00, 0xe4, 0xe4, 0xe4, 0x00,
+];
+
+/// The data stream obtained after the IMAGE_DATA_SINGLE_PIXEL is compressed using the Tight
+/// algorithm.
+/// Total length is equal to 16.
+pub const TARGET_DATA_TIGHT_SINGLE_PIXEL: [u8; 16] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x20, 0x00, 0x00, 0x00, 0x07, 0x80, 0x00, 0x00, 0x00,
+];
+
+/// The data stream obtained after the IMAGE_DATA_TWO_PIXEL is compressed using the Tight
+/// algorithm.
+/// The compressed data following the compression control is replaced by its uncompressed data.
+/// Total length is equal to 221.
+pub const TARGET_DATA_TIGHT_TWO_PIXEL: [u8; 221] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x28, 0x00, 0x28, 0x00, 0x00, 0x00, 0x07, 0x50, 0x01, 0x01, 0x00,
+    0x00, 0x00, 0xaa, 0xaa, 0xaa, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff,
+    0x00, 0x00, 0xe0, 0x00, 0xdb, 0x00, 0x00, 0x60, 0x00, 0x99, 0x00, 0x00, 0x60, 0x00, 0x18, 0x78,
+    0x7c, 0x66, 0x7c, 0x18, 0x0c, 0xc6, 0x6c, 0xc6, 0x18, 0x7c, 0x60, 0x78, 0x60, 0x18, 0xcc, 0x38,
+    0x78, 0x38, 0x18, 0xcc, 0x0c, 0x6c, 0x0c, 0x18, 0xcc, 0xc6, 0x66, 0xc6, 0x3c, 0x76, 0x7c, 0xe6,
+    0x7c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x3c, 0x00, 0x00, 0x0c, 0x00, 0x66, 0x00, 0x00, 0x18, 0xc2, 0xc2, 0x00, 0x00, 0x30, 0xc6, 0xc0,
+    0xdc, 0xcc, 0x30, 0x0c, 0xc0, 0x66, 0xcc, 0x30, 0x18, 0xc0, 0x66, 0xcc, 0x30, 0x30, 0xc0, 0x66,
+    0xcc, 0x30, 0x60, 0xc2, 0x66, 0xcc, 0x30, 0xc6, 0x66, 0x66, 0xcc, 0x18, 0x86, 0x3c, 0x7c, 0x76,
+    0x0c, 0x00, 0x00, 0x60, 0x00, 0x00, 0x00, 0x00, 0x60, 0x00, 0x00, 0x00, 0x00, 0xf0, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc3,
+    0x18, 0xfc, 0x00, 0xc3, 0xe7, 0x18, 0x66, 0x00, 0xe7, 0xff, 0x00, 0x66, 0x00, 0xff, 0xff, 0x38,
+    0x66, 0x00, 0xff, 0xdb, 0x18, 0x7c, 0x00, 0xdb, 0xc3, 0x18, 0x66, 0x00, 0xc3,
+];
+
+/// The data stream obtained after the IMAGE_DATA_MULTI_PIXELS is compressed using the Tight
+/// algorithm.
+/// The compressed data following the compression control is replaced by its uncompressed data.
+/// Total length is equal to 1627.
+pub const TARGET_DATA_TIGHT_MULTI_PIXELS: [u8; 1627] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x28, 0x00, 0x28, 0x00, 0x00, 0x00, 0x07, 0x60, 0x01, 0x03, 0x7b,
+    0x72, 0x8a, 0x7b, 0x71, 0x8a, 0x7a, 0x71, 0x8a, 0x7a, 0x70, 0x8a, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03,
+    0x03, 0x03, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03,
+    0x03, 0x03, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03,
+    0x03, 0x03, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x00, 0x00, 0x01, 0x01, 0x01,
+    0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03,
+    0x03, 0x03, 0x03, 0x00, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x00, 0x00, 0x01, 0x01, 0x01,
+    0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03,
+    0x03, 0x03, 0x03, 0x00, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x00, 0x00, 0x01, 0x01, 0x01,
+    0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03,
+    0x03, 0x03, 0x03, 0x00, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x00, 0x00, 0x01, 0x01, 0x01,
+    0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03,
+    0x03, 0x03, 0x03, 0x00, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x00, 0x00, 0x01, 0x01, 0x01,
+    0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03,
+    0x03, 0x03, 0x03, 0x00, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x00, 0x00, 0x01, 0x01, 0x01,
+    0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03,
+    0x03, 0x03, 0x03, 0x00, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x00, 0x00, 0x01, 0x01, 0x01,
+    0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03,
+    0x03, 0x03, 0x03, 0x00, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x00, 0x00, 0x01, 0x01, 0x01,
+    0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03,
+    0x03, 0x03, 0x03, 0x00, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x00, 0x00, 0x01, 0x01, 0x01,
+    0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03,
+    0x03, 0x03, 0x03, 0x00, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x00, 0x00, 0x01, 0x01, 0x01,
+    0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03,
+    0x03, 0x03, 0x03, 0x00, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+    0x01, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x00, 0x00, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03,
+    0x03, 0x03, 0x03, 0x00, 0x00, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x00, 0x00, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03,
+    0x03, 0x03, 0x03, 0x00, 0x00, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x00, 0x00, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03,
+    0x03, 0x03, 0x03, 0x00, 0x00, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x00, 0x00, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03,
+    0x03, 0x03, 0x03, 0x00, 0x00, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x00, 0x00, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03,
+    0x03, 0x03, 0x03, 0x00, 0x00, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x00, 0x00, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03,
+    0x03, 0x03, 0x03, 0x00, 0x00, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03, 0x00, 0x00, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03,
+    0x03, 0x03, 0x03, 0x00, 0x00, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x03, 0x03, 0x03, 0x03, 0x03, 0x03,
+];
+
+/// The data stream obtained after the IMAGE_DATA_GRADIENT is compressed using the Tight
+/// algorithm.
+/// The compressed data following the compression control is replaced by its uncompressed data.
+/// Total length is equal to 1214.
+pub const TARGET_DATA_TIGHT_GRADIENT: [u8; 1214] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x14, 0x00, 0x14, 0x00, 0x00, 0x00, 0x07, 0x70, 0x02, 0x00, 0x00,
+    0x00, 0x0c, 0x00, 0x06, 0x0c, 0x00, 0x06, 0x0c, 0x00, 0x06, 0x0c, 0x00, 0x06, 0x0c, 0x00, 0x06,
+    0x0c, 0x00, 0x06, 0x0c, 0x00, 0x06, 0x0c, 0x00, 0x06, 0x0c, 0x00, 0x06, 0x0c, 0x00, 0x06, 0x0c,
+    0x00, 0x06, 0x0c, 0x00, 0x06, 0x0c, 0x00, 0x06, 0x0c, 0x00, 0x06, 0x0c, 0x00, 0x06, 0x0c, 0x00,
+    0x06, 0x0c, 0x00, 0x06, 0x0c, 0x00, 0x06, 0x0c, 0x00, 0x06, 0x00, 0x0c, 0x06, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x0c, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0c,
+    0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x06, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x0c, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0c,
+    0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x06, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x0c, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0c,
+    0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x06, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x0c, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0c,
+    0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x06, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x0c, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
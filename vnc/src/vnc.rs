@@ -14,7 +14,7 @@ use crate::{
     client::{
         desktop_resize, display_cursor_define, get_rects, set_color_depth, vnc_flush,
         vnc_update_output_throttle, vnc_write, DisplayMode, RectInfo, Rectangle, ServerMsg,
-        ENCODING_HEXTILE, ENCODING_RAW,
+        ENCODING_HEXTILE, ENCODING_RAW, ENCODING_TIGHT, ENCODING_ZLIB, ENCODING_ZRLE,
     },
     console::{
         graphic_hardware_update, register_display, DisplayChangeListener,
@@ -22,7 +22,12 @@ use crate::{
         DISPLAY_UPDATE_INTERVAL_DEFAULT, DISPLAY_UPDATE_INTERVAL_INC, DISPLAY_UPDATE_INTERVAL_MAX,
     },
     data::keycode::KEYSYM2KEYCODE,
-    encoding::enc_hextile::hextile_send_framebuffer_update,
+    encoding::{
+        enc_hextile::hextile_send_framebuffer_update,
+        enc_tight::tight_send_framebuffer_update,
+        enc_zlib::{zlib_send_framebuffer_update, ZlibStreams},
+        enc_zrle::zrle_send_framebuffer_update,
+    },
     input::KeyBoardState,
     pixman::{
         bytes_per_pixel, create_pixman_image, get_image_data, get_image_height, get_image_stride,
@@ -340,8 +345,14 @@ fn start_vnc_thread() -> Result<()> {
                 let width = dpm.client_width;
                 let height = dpm.client_height;
                 if check_rect(rect, width, height) {
-                    let n =
-                        send_framebuffer_update(locked_surface.server_image, rect, &dpm, &mut buf);
+                    let mut locked_streams = rect_info.client.zlib_streams.lock().unwrap();
+                    let n = send_framebuffer_update(
+                        locked_surface.server_image,
+                        rect,
+                        &dpm,
+                        &mut locked_streams,
+                        &mut buf,
+                    );
                     if n >= 0 {
                         num_rects += n;
                     }
@@ -549,6 +560,46 @@ pub fn write_pixel(
     }
 }
 
+/// Get the pixels of the rectangle area in image, row by row.
+///
+/// # Arguments
+///
+/// * `image` - pointer to the image.
+/// * `rect` - area of image.
+pub fn get_rect_pixels(image: *mut pixman_image_t, rect: &Rectangle) -> Vec<u32> {
+    let stride = get_image_stride(image);
+    let mut data_ptr = get_image_data(image) as *mut u8;
+    data_ptr = (data_ptr as usize
+        + (rect.y * stride) as usize
+        + rect.x as usize * bytes_per_pixel()) as *mut u8;
+
+    let mut pixels = Vec::with_capacity((rect.w * rect.h) as usize);
+    for _i in 0..rect.h {
+        let ptr = data_ptr as *mut u32;
+        for j in 0..rect.w as usize {
+            // SAFETY: it can be ensure the raw pointer will not exceed the range.
+            pixels.push(unsafe { ptr.add(j).read_unaligned() });
+        }
+        data_ptr = (data_ptr as usize + stride as usize) as *mut u8;
+    }
+    pixels
+}
+
+/// Write one pixel to client.
+///
+/// # Arguments
+///
+/// * `color` - the pixel value of image.
+/// * `client_dpm` - Output mod of client display.
+/// * `buf` - send buffer.
+pub fn write_single_pixel(color: u32, client_dpm: &DisplayMode, buf: &mut Vec<u8>) {
+    if client_dpm.convert {
+        convert_pixel(client_dpm, buf, color);
+    } else {
+        buf.extend_from_slice(&color.to_ne_bytes());
+    }
+}
+
 /// Convert the sent information to a format supported  
 /// by the client depend on byte arrangement
 ///
@@ -633,23 +684,38 @@ pub fn raw_send_framebuffer_update(
 /// * `image` = pointer to the data need to be send.
 /// * `rect` - dirty area of image.
 /// * `client_dpm` - Output mod information of client display.
+/// * `streams` - zlib streams of client.
 /// * `buf` - send buffer.
 fn send_framebuffer_update(
     image: *mut pixman_image_t,
     rect: &Rectangle,
     client_dpm: &DisplayMode,
+    streams: &mut ZlibStreams,
     buf: &mut Vec<u8>,
 ) -> i32 {
-    match client_dpm.enc {
+    let n = match client_dpm.enc {
         ENCODING_HEXTILE => {
             framebuffer_upadate(rect.x, rect.y, rect.w, rect.h, ENCODING_HEXTILE, buf);
             hextile_send_framebuffer_update(image, rect, client_dpm, buf)
         }
-        _ => {
-            framebuffer_upadate(rect.x, rect.y, rect.w, rect.h, ENCODING_RAW, buf);
-            raw_send_framebuffer_update(image, rect, client_dpm, buf)
+        ENCODING_ZLIB => {
+            zlib_send_framebuffer_update(image, rect, client_dpm, &mut streams.zlib, buf)
+        }
+        ENCODING_ZRLE => {
+            zrle_send_framebuffer_update(image, rect, client_dpm, &mut streams.zrle, buf)
         }
+        ENCODING_TIGHT => {
+            tight_send_framebuffer_update(image, rect, client_dpm, &mut streams.tight, buf)
+        }
+        _ => -1,
+    };
+    if n >= 0 {
+        return n;
     }
+
+    // Send raw data if the encoding is not supported or failed.
+    framebuffer_upadate(rect.x, rect.y, rect.w, rect.h, ENCODING_RAW, buf);
+    raw_send_framebuffer_update(image, rect, client_dpm, buf)
 }
 
 /// Initialize a default image